@@ -1,5 +1,4 @@
 use mesher::prelude::*;
-use mesher_basic::TCP;
 
 fn main() {
   let mut args = std::env::args().skip(1);
@@ -7,7 +6,8 @@ fn main() {
   let (pkey, key) = encrypt::gen_keypair();
   println!(
     "Key to send to is: {}",
-    pkey[..]
+    pkey
+      .as_bytes()
       .iter()
       .fold(String::with_capacity(64), |a, i| a + &format!("{:02X}", i))
   );
@@ -15,7 +15,7 @@ fn main() {
   println!("Listening for data on {}", sock);
 
   let mut m = Mesher::unsigned(vec![key]);
-  m.add_transport::<TCP>("tcp").expect("Failed to add required transport");
+  mesher_basic::register_defaults(&mut m).expect("Failed to add transports");
   m.listen_on(&format!("tcp:{}", sock))
     .expect("Failed to add listener for messages");
 
@@ -1,7 +1,6 @@
 use std::io::{stdin, Read};
 
 use mesher::prelude::*;
-use mesher_basic::TCP;
 
 fn get_pkey(s: &str) -> Result<encrypt::PublicKey, &'static str> {
   if s.len() != 64 {
@@ -28,7 +27,7 @@ fn main() {
     .read_to_end(&mut data)
     .expect("Failed to read from STDIN");
 
-  if !data.ends_with(&['\n' as u8]) {
+  if !data.ends_with(b"\n") {
     println!();
   }
   println!("Sending {} bytes...", data.len());
@@ -36,7 +35,7 @@ fn main() {
   let (self_pk, self_sk) = encrypt::gen_keypair();
 
   let mut m = Mesher::unsigned(vec![self_sk]);
-  m.add_transport::<TCP>("tcp").expect("Failed to add TCP transport");
+  mesher_basic::register_defaults(&mut m).expect("Failed to add transports");
 
   let mut packet = Packet::unsigned();
   packet.add_hop(format!("tcp:{}", sock), &self_pk);
@@ -0,0 +1,602 @@
+//! Using the mesh as a proxy, for plain HTTP requests: nodes send requests to an [`Exit`](struct.Exit.html), which makes
+//! them and sends back the responses along the reply path.
+//!
+//! The server only sees the exit, and the exit only sees the node before it in the route, plus the key the response is
+//! encrypted for, so clients that want to stay anonymous should use a route through a few other nodes, and a key that's
+//! only used for this.
+//! Only `http` URLs work, since there's nothing here to speak TLS with; anything sent through an exit can be read by it,
+//! and by anyone between it and the server.
+//! Applications that need more than that, or don't speak HTTP, can tunnel whole TCP connections through an exit with
+//! a [SOCKS5 frontend](../socks/index.html) instead.
+//!
+//! Requests and responses are [RPCs](../../mesher/rpc/index.html) for the method `http`, so an exit answers them with
+//! the same [`Rpc`](../../mesher/rpc/struct.Rpc.html) as any other methods it serves:
+//!
+//! ```no_run
+//! # use mesher::prelude::*;
+//! use mesher::rpc::Rpc;
+//! use mesher_basic::exit::{self, Exit, HttpRequest};
+//! use std::time::Duration;
+//!
+//! let (exit_pk, exit_sk) = encrypt::gen_keypair();
+//! let (client_pk, client_sk) = encrypt::gen_keypair();
+//! // on the exit
+//! let mut exit_mesher = Mesher::unsigned(vec![exit_sk]);
+//! exit_mesher.add_transport::<mesher_basic::TCP>("tcp").expect("Failed to add transport");
+//! exit_mesher.listen_on("tcp:0.0.0.0:18540").expect("Failed to listen");
+//! let mut exit_rpc = Rpc::new();
+//! Exit::new().serve(&mut exit_rpc);
+//! // ...then pass everything exit_mesher receives to exit_rpc.handle
+//!
+//! // on the client
+//! let mut client = Mesher::unsigned(vec![client_sk]);
+//! client.add_transport::<mesher_basic::TCP>("tcp").expect("Failed to add transport");
+//! client.listen_on("tcp:0.0.0.0:18541").expect("Failed to listen");
+//! let route = mesher::Route::new()
+//!   .then(Path::parse("tcp:client.example.com:18541").unwrap(), client_pk)
+//!   .then(Path::parse("tcp:exit.example.com:18540").unwrap(), exit_pk);
+//! let mut rpc = Rpc::new();
+//! let response = exit::fetch(&mut rpc, &mut client, &route, &HttpRequest::get("http://example.com/"), Duration::from_secs(30))
+//!   .expect("Failed to fetch");
+//! println!("{}", String::from_utf8_lossy(&response.body));
+//! ```
+
+use crate::socks;
+
+use mesher::{addr, prelude::*, rpc::Rpc, Route};
+
+use std::{
+  convert::TryFrom,
+  io::{prelude::*, BufReader},
+  net::{SocketAddr, TcpStream, ToSocketAddrs},
+  time::Duration,
+};
+
+/// The RPC method exits answer requests for.
+pub const METHOD: &str = "http";
+
+/// Starts the answer to a request that the exit made.
+const FETCHED: u8 = 0;
+/// Starts the answer to a request that the exit couldn't make, followed by why not.
+const FAILED: u8 = 1;
+
+/// The most a response's status lines and headers, along with a chunked body's size lines and trailers, can add up to.
+const MAX_HEAD: usize = 64 * 1024;
+/// The most headers a response can have, or trailers.
+const MAX_HEADERS: usize = 128;
+
+/// Headers which are about one connection, rather than the request or response, so they're never passed along.
+const HOP_BY_HOP: &[&str] = &[
+  "connection",
+  "keep-alive",
+  "proxy-connection",
+  "transfer-encoding",
+  "te",
+  "trailer",
+  "upgrade",
+];
+
+pub(crate) fn failure(message: impl Into<String>) -> fail::MesherFail {
+  fail::MesherFail::ExitFailure(message.into())
+}
+
+/// What to tell whoever asked about why the exit couldn't do something.
+pub(crate) fn reason(e: fail::MesherFail) -> String {
+  match e {
+    fail::MesherFail::ExitFailure(why) => why,
+    other => format!("{:?}", other),
+  }
+}
+
+/// Writes how long something is as a `u16`, or fails if it's too long for that.
+fn put_u16_len(b: &mut Vec<u8>, len: usize, what: &str) -> fail::Result<()> {
+  let len = u16::try_from(len).map_err(|_| failure(format!("{} is too long: {}, past {}", what, len, u16::MAX)))?;
+  b.extend_from_slice(&len.to_be_bytes());
+  Ok(())
+}
+
+pub(crate) fn put_str(b: &mut Vec<u8>, s: &str) -> fail::Result<()> {
+  put_u16_len(b, s.len(), "a string")?;
+  b.extend_from_slice(s.as_bytes());
+  Ok(())
+}
+
+pub(crate) fn put_bytes(b: &mut Vec<u8>, bytes: &[u8]) -> fail::Result<()> {
+  let len = u32::try_from(bytes.len())
+    .map_err(|_| failure(format!("a body is too long: {}, past {}", bytes.len(), u32::MAX)))?;
+  b.extend_from_slice(&len.to_be_bytes());
+  b.extend_from_slice(bytes);
+  Ok(())
+}
+
+fn put_headers(b: &mut Vec<u8>, headers: &[(String, String)]) -> fail::Result<()> {
+  put_u16_len(b, headers.len(), "a list of headers")?;
+  for (name, value) in headers {
+    put_str(b, name)?;
+    put_str(b, value)?;
+  }
+  Ok(())
+}
+
+pub(crate) fn take<'a>(from: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+  if from.len() < len {
+    return None;
+  }
+  let (taken, rest) = from.split_at(len);
+  *from = rest;
+  Some(taken)
+}
+
+pub(crate) fn take_u16(from: &mut &[u8]) -> Option<u16> {
+  let b = take(from, 2)?;
+  Some(u16::from_be_bytes([b[0], b[1]]))
+}
+
+pub(crate) fn take_str(from: &mut &[u8]) -> Option<String> {
+  let len = take_u16(from)? as usize;
+  String::from_utf8(take(from, len)?.to_vec()).ok()
+}
+
+pub(crate) fn take_bytes(from: &mut &[u8]) -> Option<Vec<u8>> {
+  let b = take(from, 4)?;
+  let len = u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize;
+  Some(take(from, len)?.to_vec())
+}
+
+fn take_headers(from: &mut &[u8]) -> Option<Vec<(String, String)>> {
+  (0..take_u16(from)?)
+    .map(|_| Some((take_str(from)?, take_str(from)?)))
+    .collect()
+}
+
+/// The value of the first header with the given name, ignoring case.
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+  headers
+    .iter()
+    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+    .map(|(_, v)| v.as_str())
+}
+
+/// An HTTP request, for an exit to make.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+  pub method: String,
+  /// Where to send it, e.g. `http://example.com/index.html`.
+  pub url: String,
+  /// Any headers besides `Host`, `Content-Length`, and the ones about the connection, which the exit sets itself.
+  pub headers: Vec<(String, String)>,
+  pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+  /// A request with the given method, for `url`, with no headers or body.
+  pub fn new(method: &str, url: &str) -> HttpRequest {
+    HttpRequest {
+      method: method.to_owned(),
+      url: url.to_owned(),
+      headers: vec![],
+      body: vec![],
+    }
+  }
+
+  /// A `GET` request for `url`.
+  pub fn get(url: &str) -> HttpRequest {
+    HttpRequest::new("GET", url)
+  }
+
+  /// Adds a header.
+  pub fn header(mut self, name: &str, value: &str) -> HttpRequest {
+    self.headers.push((name.to_owned(), value.to_owned()));
+    self
+  }
+
+  /// Sets the body.
+  pub fn body(mut self, body: Vec<u8>) -> HttpRequest {
+    self.body = body;
+    self
+  }
+
+  /// Encodes the method and URL, prefixed by their big-endian `u16` lengths, then a big-endian `u16` count of headers,
+  /// each name and value prefixed the same way, then the body, prefixed by its big-endian `u32` length.
+  ///
+  /// Fails if anything's too long for its length to fit.
+  fn encode(&self) -> fail::Result<Vec<u8>> {
+    let mut b = vec![];
+    put_str(&mut b, &self.method)?;
+    put_str(&mut b, &self.url)?;
+    put_headers(&mut b, &self.headers)?;
+    put_bytes(&mut b, &self.body)?;
+    Ok(b)
+  }
+
+  fn decode(mut from: &[u8]) -> Option<HttpRequest> {
+    let request = HttpRequest {
+      method: take_str(&mut from)?,
+      url: take_str(&mut from)?,
+      headers: take_headers(&mut from)?,
+      body: take_bytes(&mut from)?,
+    };
+    Some(request).filter(|_| from.is_empty())
+  }
+}
+
+/// The response to an [`HttpRequest`](struct.HttpRequest.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+  pub status: u16,
+  /// The headers the server sent, besides the ones about the connection, which the exit leaves out.
+  pub headers: Vec<(String, String)>,
+  /// The body, already un-chunked if it was sent in chunks.
+  pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+  /// The value of the first header with the given name, ignoring case.
+  pub fn header(&self, name: &str) -> Option<&str> {
+    find_header(&self.headers, name)
+  }
+
+  /// Encodes the status as a big-endian `u16`, then the headers and body like a request's, failing the same way.
+  fn encode(&self) -> fail::Result<Vec<u8>> {
+    let mut b = self.status.to_be_bytes().to_vec();
+    put_headers(&mut b, &self.headers)?;
+    put_bytes(&mut b, &self.body)?;
+    Ok(b)
+  }
+
+  fn decode(mut from: &[u8]) -> Option<HttpResponse> {
+    let response = HttpResponse {
+      status: take_u16(&mut from)?,
+      headers: take_headers(&mut from)?,
+      body: take_bytes(&mut from)?,
+    };
+    Some(response).filter(|_| from.is_empty())
+  }
+}
+
+/// Whether a string's safe to put in a request line or header, i.e. can't end it early.
+fn is_clean(s: &str) -> bool {
+  !s.contains(['\r', '\n', '\0'])
+}
+
+/// Splits an `http://host[:port]/path` URL into the host, port, and path, including any query.
+fn split_url(url: &str) -> fail::Result<(String, u16, String)> {
+  let rest = match url.split_once("://") {
+    Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+    Some((scheme, _)) => return Err(failure(format!("only http URLs are supported, not {}", scheme))),
+    None => return Err(failure(format!("{} isn't a URL", url))),
+  };
+  let (authority, path) = match rest.find(['/', '?', '#']) {
+    Some(at) => (&rest[..at], &rest[at..]),
+    None => (rest, "/"),
+  };
+  // the fragment's only for the client
+  let path = path.split('#').next().unwrap_or("/");
+  let path = if path.starts_with('/') {
+    path.to_owned()
+  } else {
+    format!("/{}", path)
+  };
+  if authority.contains('@') {
+    return Err(failure("URLs with credentials in them aren't supported"));
+  }
+  let (host, port) = match authority.rsplit_once(':') {
+    Some((host, port)) if !port.contains(']') => {
+      let port = port
+        .parse()
+        .map_err(|_| failure(format!("{} isn't a valid port", port)))?;
+      (host, port)
+    }
+    _ => (authority, 80),
+  };
+  let host = host.trim_start_matches('[').trim_end_matches(']');
+  if host.is_empty() || !is_clean(host) || !is_clean(&path) || path.contains(' ') {
+    return Err(failure(format!("{} isn't a valid URL", url)));
+  }
+  Ok((host.to_owned(), port, path))
+}
+
+/// Reads a response's body, which is everything until the connection closes, up to `max` bytes.
+fn read_to_close(conn: &mut impl Read, max: usize) -> fail::Result<Vec<u8>> {
+  let mut body = vec![];
+  conn
+    .take(max as u64 + 1)
+    .read_to_end(&mut body)
+    .map_err(|e| failure(format!("couldn't read the response: {}", e)))?;
+  if body.len() > max {
+    return Err(failure(format!("the response is bigger than {} bytes", max)));
+  }
+  Ok(body)
+}
+
+/// Reads a line of a response's head, or of the framing around a chunked body, taking its length out of `budget`.
+fn read_line(conn: &mut impl BufRead, budget: &mut usize) -> fail::Result<String> {
+  let mut line = String::new();
+  let len = conn
+    .by_ref()
+    .take(*budget as u64 + 1)
+    .read_line(&mut line)
+    .map_err(|e| failure(format!("couldn't read the response: {}", e)))?;
+  if len > *budget {
+    return Err(failure(format!(
+      "the response's headers are bigger than {} bytes",
+      MAX_HEAD
+    )));
+  }
+  *budget -= len;
+  Ok(line)
+}
+
+/// Reads a body sent with `Transfer-Encoding: chunked`, up to `max` bytes, with the framing coming out of `budget`.
+fn read_chunked(conn: &mut impl BufRead, max: usize, budget: &mut usize) -> fail::Result<Vec<u8>> {
+  let malformed = || failure("the server sent malformed chunks");
+  let mut body = vec![];
+  loop {
+    let line = read_line(conn, budget)?;
+    let size = line.split(';').next().map(str::trim).ok_or_else(malformed)?;
+    let size = usize::from_str_radix(size, 16).map_err(|_| malformed())?;
+    if size == 0 {
+      // trailers, which are dropped like any other hop-by-hop header
+      for _ in 0..=MAX_HEADERS {
+        if read_line(conn, budget)?.trim().is_empty() {
+          return Ok(body);
+        }
+      }
+      return Err(failure(format!("the response has more than {} trailers", MAX_HEADERS)));
+    }
+    // the body's never bigger than max, so this can't overflow, however big the chunk claims to be
+    if size > max - body.len() {
+      return Err(failure(format!("the response is bigger than {} bytes", max)));
+    }
+    let start = body.len();
+    body.resize(start + size, 0);
+    conn.read_exact(&mut body[start..]).map_err(|_| malformed())?;
+    read_line(conn, budget)?;
+  }
+}
+
+/// Reads a response's status line and headers, taking their length out of `budget`.
+fn read_head(conn: &mut impl BufRead, budget: &mut usize) -> fail::Result<(u16, Vec<(String, String)>)> {
+  let malformed = || failure("the server sent a malformed response");
+  let line = read_line(conn, budget)?;
+  let status = match line.split_whitespace().collect::<Vec<_>>()[..] {
+    [version, status, ..] if version.starts_with("HTTP/") => status.parse().map_err(|_| malformed())?,
+    _ => return Err(malformed()),
+  };
+  let mut headers = vec![];
+  loop {
+    let line = read_line(conn, budget)?;
+    if line.is_empty() {
+      return Err(malformed());
+    }
+    let line = line.trim_end();
+    if line.is_empty() {
+      return Ok((status, headers));
+    }
+    if headers.len() >= MAX_HEADERS {
+      return Err(failure(format!("the response has more than {} headers", MAX_HEADERS)));
+    }
+    let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+    headers.push((name.trim().to_owned(), value.trim().to_owned()));
+  }
+}
+
+/// Makes HTTP requests for the mesh.
+///
+/// By default, it only connects to addresses on the public internet, so nobody can use it to reach the exit's own
+/// machine or network; [`allow_private`](#method.allow_private) turns that off.
+/// It also gives up on servers that take more than 30 seconds to connect to or to send anything, on responses bigger
+/// than 1 MiB, since the whole thing has to fit in a message, and on responses with more than 64 KiB of headers.
+#[derive(Debug, Clone)]
+pub struct Exit {
+  allow_private: bool,
+  ports: Option<Vec<u16>>,
+  max_response: usize,
+  timeout: Duration,
+}
+
+impl Default for Exit {
+  fn default() -> Exit {
+    Exit::new()
+  }
+}
+
+impl Exit {
+  pub fn new() -> Exit {
+    Exit {
+      allow_private: false,
+      ports: None,
+      max_response: 1024 * 1024,
+      timeout: Duration::from_secs(30),
+    }
+  }
+
+  /// Lets requests go to loopback, private, link-local, and other addresses that aren't on the public internet.
+  pub fn allow_private(mut self, allow: bool) -> Exit {
+    self.allow_private = allow;
+    self
+  }
+
+  /// Only lets requests go to the given ports, rather than any.
+  pub fn ports(mut self, ports: Vec<u16>) -> Exit {
+    self.ports = Some(ports);
+    self
+  }
+
+  /// Sets the biggest response body the exit will send back.
+  pub fn max_response_size(mut self, max: usize) -> Exit {
+    self.max_response = max;
+    self
+  }
+
+  /// Sets how long the exit waits to connect, and for each read or write after that.
+  pub fn timeout(mut self, timeout: Duration) -> Exit {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Connects to the first of the host's addresses that's allowed and answers, with reads and writes timing out like
+  /// connecting does.
+  pub(crate) fn connect(&self, host: &str, port: u16) -> fail::Result<TcpStream> {
+    if let Some(ports) = &self.ports {
+      if !ports.contains(&port) {
+        return Err(failure(format!("this exit doesn't connect to port {}", port)));
+      }
+    }
+    let addrs: Vec<SocketAddr> = (host, port)
+      .to_socket_addrs()
+      .map_err(|e| failure(format!("couldn't resolve {}: {}", host, e)))?
+      .filter(|a| self.allow_private || addr::is_public(a.ip()))
+      .collect();
+    if addrs.is_empty() {
+      return Err(failure(format!("{} isn't on the public internet", host)));
+    }
+    let mut last_err = None;
+    for addr in addrs {
+      let connected = TcpStream::connect_timeout(&addr, self.timeout).and_then(|conn| {
+        conn.set_read_timeout(Some(self.timeout))?;
+        conn.set_write_timeout(Some(self.timeout))?;
+        Ok(conn)
+      });
+      match connected {
+        Ok(conn) => return Ok(conn),
+        Err(e) => last_err = Some(e),
+      }
+    }
+    let e = last_err.expect("There was at least one address");
+    Err(failure(format!("couldn't connect to {}: {}", host, e)))
+  }
+
+  /// Makes a request, right here, the same way it would for the mesh.
+  pub fn fetch(&self, request: &HttpRequest) -> fail::Result<HttpResponse> {
+    let (host, port, path) = split_url(&request.url)?;
+    let method = &request.method;
+    if method.is_empty() || !method.bytes().all(|b| b.is_ascii_alphabetic()) {
+      return Err(failure(format!("{:?} isn't an HTTP method", method)));
+    }
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, path);
+    let host_header = match (host.contains(':'), port) {
+      (true, 80) => format!("[{}]", host),
+      (true, _) => format!("[{}]:{}", host, port),
+      (false, 80) => host.clone(),
+      (false, _) => format!("{}:{}", host, port),
+    };
+    head.push_str(&format!("Host: {}\r\nConnection: close\r\n", host_header));
+    for (name, value) in &request.headers {
+      let lower = name.to_ascii_lowercase();
+      if lower == "host" || lower == "content-length" || HOP_BY_HOP.contains(&lower.as_str()) {
+        continue;
+      }
+      if name.is_empty() || name.contains([':', ' ']) || !is_clean(name) || !is_clean(value) {
+        return Err(failure(format!("{:?} isn't a valid header", name)));
+      }
+      head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !request.body.is_empty() || matches!(method.as_str(), "POST" | "PUT" | "PATCH") {
+      head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+    }
+    head.push_str("\r\n");
+
+    let conn = self.connect(&host, port)?;
+    let io_err = |e: std::io::Error| failure(format!("couldn't talk to {}: {}", host, e));
+    let mut writer = conn.try_clone().map_err(io_err)?;
+    writer.write_all(head.as_bytes()).map_err(io_err)?;
+    writer.write_all(&request.body).map_err(io_err)?;
+    writer.flush().map_err(io_err)?;
+
+    let mut conn = BufReader::new(conn);
+    // shared by every response, so the server can't send informational ones forever either
+    let mut budget = MAX_HEAD;
+    let (status, headers) = loop {
+      let (status, headers) = read_head(&mut conn, &mut budget)?;
+      // informational responses come before the real one
+      if !(100..200).contains(&status) || status == 101 {
+        break (status, headers);
+      }
+    };
+    let no_body = method.eq_ignore_ascii_case("HEAD") || status == 204 || status == 304 || status < 200;
+    let chunked =
+      find_header(&headers, "transfer-encoding").is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+    let body = if no_body {
+      vec![]
+    } else if chunked {
+      read_chunked(&mut conn, self.max_response, &mut budget)?
+    } else if let Some(len) = find_header(&headers, "content-length") {
+      let len: usize = len
+        .parse()
+        .map_err(|_| failure("the server sent an invalid Content-Length"))?;
+      if len > self.max_response {
+        return Err(failure(format!(
+          "the response is bigger than {} bytes",
+          self.max_response
+        )));
+      }
+      let mut body = vec![0; len];
+      conn
+        .read_exact(&mut body)
+        .map_err(|e| failure(format!("couldn't read the response: {}", e)))?;
+      body
+    } else {
+      read_to_close(&mut conn, self.max_response)?
+    };
+    let headers = headers
+      .into_iter()
+      .filter(|(name, _)| !HOP_BY_HOP.contains(&name.to_ascii_lowercase().as_str()))
+      .collect();
+    debug_event!(url = %request.url, status, bytes = body.len(), "exit fetched");
+    Ok(HttpResponse { status, headers, body })
+  }
+
+  /// Answers requests for the [`http`](constant.METHOD.html) method that `rpc` handles, by making them, and tunnels
+  /// [streams](../socks/index.html) for the [`stream`](../socks/constant.METHOD.html) method, under the same rules.
+  ///
+  /// Requests are made while the `Rpc` is [handling](../../mesher/rpc/struct.Rpc.html#method.handle) them, so nothing
+  /// else is handled until they're done.
+  pub fn serve(self, rpc: &mut Rpc) {
+    let mut streams = socks::Streams::new(self.clone());
+    rpc.register(socks::METHOD, move |body| streams.handle(body));
+    rpc.register(METHOD, move |body| {
+      let result = HttpRequest::decode(body)
+        .ok_or_else(|| failure("malformed request"))
+        .and_then(|request| self.fetch(&request))
+        .and_then(|response| response.encode());
+      match result {
+        Ok(response) => {
+          let mut answer = vec![FETCHED];
+          answer.extend(response);
+          answer
+        }
+        Err(e) => {
+          let why = reason(e);
+          debug_event!(reason = %why, "exit couldn't fetch");
+          let mut answer = vec![FAILED];
+          answer.extend_from_slice(why.as_bytes());
+          answer
+        }
+      }
+    });
+  }
+}
+
+/// Sends a request to the exit at the end of `route`, and waits up to `timeout` for the response, like
+/// [`Rpc::call`](../../mesher/rpc/struct.Rpc.html#method.call).
+///
+/// The route has to start with a path the mesher's listening on, since the response comes back along it, reversed.
+/// Fails with [`ExitFailure`](../../mesher/fail/enum.MesherFail.html#variant.ExitFailure) if the exit couldn't make
+/// the request, e.g. because the URL isn't allowed, or if the request has a header longer than 64 KiB; a response with an error status is still a response, though.
+pub fn fetch(
+  rpc: &mut Rpc,
+  mesher: &mut Mesher,
+  route: &Route,
+  request: &HttpRequest,
+  timeout: Duration,
+) -> fail::Result<HttpResponse> {
+  let answer = rpc.call(mesher, route, METHOD, &request.encode()?, timeout)?;
+  match answer.split_first() {
+    Some((&FETCHED, response)) => {
+      HttpResponse::decode(response).ok_or_else(|| failure("the exit sent a malformed response"))
+    }
+    Some((&FAILED, why)) => Err(failure(String::from_utf8_lossy(why))),
+    _ => Err(failure("the exit sent a malformed response")),
+  }
+}
@@ -0,0 +1,234 @@
+//! What the [Tor](struct.Tor.html) and [I2P](struct.I2P.html) transports have in common: both talk to a local router
+//! over a line-based control protocol, and have it forward connections to their hidden address on to a local TCP
+//! listener.
+
+use mesher::prelude::*;
+
+use std::{
+  collections::HashMap,
+  fs,
+  io::{prelude::*, BufReader},
+  net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+  time::Duration,
+};
+
+use crate::TCP;
+
+use mesher::ListenStatus;
+
+/// How long to wait for the router to answer a command, or a connection through it to be made, unless the path's
+/// options say otherwise; building circuits or tunnels can take a while.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A connection to a router's control port, which speaks in lines.
+pub(crate) struct Control {
+  reader: BufReader<TcpStream>,
+}
+
+impl Control {
+  /// Connects to the router at `addr`, e.g. `127.0.0.1:9051`, giving up on anything that takes longer than `timeout`.
+  pub(crate) fn connect(what: &str, addr: &str, timeout: Duration) -> Result<Control, String> {
+    let addr: SocketAddr = addr
+      .parse()
+      .map_err(|_| format!("not a valid {} address: {}", what, addr))?;
+    let stream = TcpStream::connect_timeout(&addr, timeout)
+      .map_err(|e| format!("couldn't connect to {} at {}: {}", what, addr, e))?;
+    stream
+      .set_read_timeout(Some(timeout))
+      .and_then(|_| stream.set_write_timeout(Some(timeout)))
+      .map_err(|e| format!("couldn't set timeouts: {}", e))?;
+    Ok(Control {
+      reader: BufReader::new(stream),
+    })
+  }
+
+  /// Sends a line, adding the line ending.
+  pub(crate) fn send(&mut self, line: &str) -> Result<(), String> {
+    let stream = self.reader.get_mut();
+    stream
+      .write_all(format!("{}\r\n", line).as_bytes())
+      .map_err(|e| format!("couldn't send command: {}", e))
+  }
+
+  /// Reads a line, without its line ending.
+  pub(crate) fn line(&mut self) -> Result<String, String> {
+    let mut line = String::new();
+    match self.reader.read_line(&mut line) {
+      Ok(0) => Err("the router hung up".to_owned()),
+      Ok(_) => Ok(line.trim_end_matches(['\r', '\n']).to_owned()),
+      Err(e) => Err(format!("couldn't read reply: {}", e)),
+    }
+  }
+
+  /// Stops timing out reads, once the connection's only being kept open to keep something alive.
+  pub(crate) fn keep_alive(self) -> TcpStream {
+    let stream = self.reader.into_inner();
+    let _ = stream.set_read_timeout(None);
+    stream
+  }
+
+  /// Hands over the connection for sending data through, once the router's connected it somewhere.
+  ///
+  /// Fails if the router already sent more than was asked for, since it'd be lost.
+  pub(crate) fn into_stream(self) -> Result<TcpStream, String> {
+    if !self.reader.buffer().is_empty() {
+      return Err("the router sent more than expected".to_owned());
+    }
+    Ok(self.reader.into_inner())
+  }
+}
+
+/// Finds a free port on the loopback address, for the router to forward connections to.
+///
+/// Another process could take it before it's bound again; listening would then fail, and can be retried.
+pub(crate) fn free_port() -> std::io::Result<u16> {
+  Ok(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?.port())
+}
+
+/// Reads a saved hidden address and the private key that goes with it, written by [`save_key`](fn.save_key.html).
+pub(crate) fn load_key(file: &str) -> Result<Option<(String, String)>, String> {
+  let text = match fs::read_to_string(file) {
+    Ok(text) => text,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(format!("couldn't read {}: {}", file, e)),
+  };
+  let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+  match (lines.next(), lines.next()) {
+    // both are spliced into control commands, so anything that could end one or start another is refused
+    (Some(address), Some(key)) if [address, key].iter().all(|l| l.chars().all(|c| c.is_ascii_graphic())) => {
+      Ok(Some((address.to_owned(), key.to_owned())))
+    }
+    (Some(_), Some(_)) => Err(format!(
+      "{} has spaces or control characters in its address or key",
+      file
+    )),
+    _ => Err(format!("{} should have an address and a key, on their own lines", file)),
+  }
+}
+
+/// Saves a hidden address and its private key, so listening with the same file gets the same address next time.
+///
+/// The file's only readable by its owner, and is never overwritten, since anyone with the key can take over the
+/// address.
+pub(crate) fn save_key(file: &str, address: &str, key: &str) -> Result<(), String> {
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  options
+    .open(file)
+    .and_then(|mut out| out.write_all(format!("{}\n{}\n", address, key).as_bytes()))
+    .map_err(|e| format!("couldn't save key to {}: {}", file, e))
+}
+
+/// The address saved in a key file, if there's one.
+pub(crate) fn saved_address(file: &str) -> Option<String> {
+  load_key(file).ok().flatten().map(|(address, _)| address)
+}
+
+/// A hidden address being listened on: the local path the router forwards it to, and the connections that keep it up.
+struct Forward {
+  path: Path,
+  local: Path,
+  // the router takes the address down once these close
+  _alive: Vec<TcpStream>,
+}
+
+/// Listens on the local ends of hidden addresses through a [TCP](struct.TCP.html) transport, and reports what it
+/// receives as having arrived on the hidden paths.
+pub(crate) struct Forwarded {
+  inner: TCP,
+  forwards: HashMap<String, Forward>,
+}
+
+impl Forwarded {
+  pub(crate) fn new() -> fail::Result<Forwarded> {
+    Ok(Forwarded {
+      inner: TCP::new("tcp")?,
+      forwards: HashMap::new(),
+    })
+  }
+
+  pub(crate) fn is_listening(&self, path: &Path) -> bool {
+    self.forwards.contains_key(path.location())
+  }
+
+  /// Starts listening on the given loopback port, before the router's told to forward to it.
+  pub(crate) fn listen_locally(&mut self, port: u16) -> fail::Result<Path> {
+    let local = Path::parse(&format!("tcp:127.0.0.1:{}", port))?;
+    self.inner.listen(&local)?;
+    Ok(local)
+  }
+
+  /// Stops listening locally, if the router couldn't be set up to forward there.
+  pub(crate) fn abandon(&mut self, local: &Path) {
+    let _ = self.inner.unlisten(local);
+  }
+
+  /// Records that the router's forwarding `path` to `local`, for as long as the connections in `alive` stay open.
+  pub(crate) fn add(&mut self, path: &Path, local: Path, alive: Vec<TcpStream>) {
+    let forward = Forward {
+      path: path.clone(),
+      local,
+      _alive: alive,
+    };
+    self.forwards.insert(path.location().to_owned(), forward);
+  }
+
+  pub(crate) fn remove(&mut self, path: &Path) -> fail::Result<()> {
+    let forward = self
+      .forwards
+      .remove(path.location())
+      .ok_or_else(|| fail::MesherFail::NotListening(path.to_string()))?;
+    self.inner.unlisten(&forward.local)
+  }
+
+  /// Packets received on any local end, as though they'd arrived on the hidden path, since the local one means nothing
+  /// to anyone else.
+  pub(crate) fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let received = self.inner.receive_up_to(max)?;
+    Ok(
+      received
+        .into_iter()
+        .map(|(source, packet)| {
+          let hidden = source
+            .listen_path()
+            .and_then(|local| self.forwards.values().find(|f| f.local.location() == local.location()));
+          match hidden {
+            Some(f) => (Source::listening_on(f.path.clone()), packet),
+            None => (Source::unknown(), packet),
+          }
+        })
+        .collect(),
+    )
+  }
+
+  pub(crate) fn status(&self) -> Vec<(Path, ListenStatus)> {
+    let local = self.inner.status();
+    self
+      .forwards
+      .values()
+      .map(|f| {
+        let status = local
+          .iter()
+          .find(|(p, _)| p.location() == f.local.location())
+          .map(|(_, s)| s.clone())
+          .unwrap_or_else(|| ListenStatus::Failed("not listening locally".to_owned()));
+        (f.path.clone(), status)
+      })
+      .collect()
+  }
+
+  pub(crate) fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.inner.set_max_packet_size(max);
+  }
+
+  pub(crate) fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.inner.set_max_buffered(max);
+  }
+
+  pub(crate) fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    self.forwards.clear();
+    self.inner.close(timeout)
+  }
+}
@@ -0,0 +1,334 @@
+use mesher::prelude::*;
+use sha2::Digest;
+
+use std::{
+  collections::HashMap,
+  io::prelude::*,
+  net::TcpStream,
+  sync::atomic::{AtomicU64, Ordering},
+  time::Duration,
+};
+
+use crate::hidden::{free_port, load_key, save_key, saved_address, Control, Forwarded, DEFAULT_TIMEOUT};
+
+use mesher::ListenStatus;
+
+/// Where the I2P router's SAM bridge usually is.
+const DEFAULT_SAM: &str = "127.0.0.1:7656";
+
+/// Numbers the sessions made for sending, so each has its own ID.
+static NEXT_SENDER: AtomicU64 = AtomicU64::new(0);
+
+/// Finds `key=value` in a SAM reply, returning the value.
+fn field<'r>(reply: &'r str, key: &str) -> Option<&'r str> {
+  reply
+    .split(' ')
+    .find_map(|part| part.strip_prefix(key).and_then(|rest| rest.strip_prefix('=')))
+}
+
+/// Reads a reply, failing unless it's the one expected, e.g. `HELLO REPLY`, and says it worked.
+fn expect_ok(control: &mut Control, expected: &str) -> Result<String, String> {
+  let reply = control.line()?;
+  if !reply.starts_with(expected) || field(&reply, "RESULT") != Some("OK") {
+    return Err(format!("the SAM bridge said {:?}", reply));
+  }
+  Ok(reply)
+}
+
+/// Connects to the SAM bridge and says hello, which every connection has to before anything else.
+fn hello(sam: &str, timeout: Duration) -> Result<Control, String> {
+  let mut control = Control::connect("the SAM bridge", sam, timeout)?;
+  control.send("HELLO VERSION MIN=3.1 MAX=3.3")?;
+  expect_ok(&mut control, "HELLO REPLY")?;
+  Ok(control)
+}
+
+/// Decodes I2P's base64, which uses `-` and `~` in place of `+` and `/`.
+fn from_i2p_base64(text: &str) -> Option<Vec<u8>> {
+  let digit = |c: u8| match c {
+    b'A'..=b'Z' => Some(c - b'A'),
+    b'a'..=b'z' => Some(c - b'a' + 26),
+    b'0'..=b'9' => Some(c - b'0' + 52),
+    b'-' => Some(62),
+    b'~' => Some(63),
+    _ => None,
+  };
+  let mut out = vec![];
+  let (mut acc, mut bits) = (0u32, 0);
+  for c in text.trim_end_matches('=').bytes() {
+    acc = (acc << 6) | digit(c)? as u32;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      out.push((acc >> bits) as u8);
+    }
+  }
+  Some(out)
+}
+
+/// Encodes bytes as lowercase base32 without padding, as in `.b32.i2p` addresses.
+fn to_base32(bytes: &[u8]) -> String {
+  const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+  let mut out = String::new();
+  let (mut acc, mut bits) = (0u32, 0);
+  for &b in bytes {
+    acc = (acc << 8) | b as u32;
+    bits += 8;
+    while bits >= 5 {
+      bits -= 5;
+      out.push(ALPHABET[((acc >> bits) & 31) as usize] as char);
+    }
+  }
+  if bits > 0 {
+    out.push(ALPHABET[((acc << (5 - bits)) & 31) as usize] as char);
+  }
+  out
+}
+
+/// The short `.b32.i2p` address of a destination, given in base64: the base32 of its SHA-256 hash.
+fn b32_address(destination: &str) -> Option<String> {
+  let bytes = from_i2p_base64(destination)?;
+  let hash = sha2::Sha256::digest(&bytes);
+  Some(format!("{}.b32.i2p", to_base32(&hash)))
+}
+
+/// Creates a streaming session called `id`, with the given private key, or a new one if there's none, returning the
+/// connection, which has to stay open for the session to last, the session's private key, and its address.
+fn create_session(
+  sam: &str,
+  timeout: Duration,
+  id: &str,
+  key: Option<&str>,
+) -> Result<(Control, String, String), String> {
+  let mut control = hello(sam, timeout)?;
+  control.send(&format!(
+    "SESSION CREATE STYLE=STREAM ID={} DESTINATION={} SIGNATURE_TYPE=EdDSA_SHA512_Ed25519",
+    id,
+    key.unwrap_or("TRANSIENT")
+  ))?;
+  let reply = expect_ok(&mut control, "SESSION STATUS")?;
+  let private = field(&reply, "DESTINATION")
+    .ok_or("the SAM bridge didn't send the session's key")?
+    .to_owned();
+  control.send("NAMING LOOKUP NAME=ME")?;
+  let reply = expect_ok(&mut control, "NAMING REPLY")?;
+  let address = field(&reply, "VALUE")
+    .and_then(b32_address)
+    .ok_or("the SAM bridge didn't say what the session's destination is")?;
+  Ok((control, private, address))
+}
+
+/// Has the router set up a destination for the session `id` which forwards to `local_port` on the loopback address,
+/// returning the connections which have to stay open to keep it up, and its address.
+///
+/// With a key file, the destination uses the key in it, or a new one which is saved there, so it keeps its address.
+fn add_destination(
+  sam: &str,
+  timeout: Duration,
+  id: &str,
+  key_file: Option<&str>,
+  local_port: u16,
+) -> Result<(Vec<TcpStream>, String), String> {
+  let saved = match key_file {
+    Some(file) => load_key(file)?,
+    None => None,
+  };
+  let (session, private, address) = create_session(sam, timeout, id, saved.as_ref().map(|(_, key)| key.as_str()))?;
+  if let (Some(file), None) = (key_file, &saved) {
+    save_key(file, &address, &private)?;
+  }
+  let mut forward = hello(sam, timeout)?;
+  forward.send(&format!(
+    "STREAM FORWARD ID={} PORT={} HOST=127.0.0.1 SILENT=true",
+    id, local_port
+  ))?;
+  expect_ok(&mut forward, "STREAM STATUS")?;
+  Ok((vec![session.keep_alive(), forward.keep_alive()], address))
+}
+
+/// Sends packets to I2P destinations, and listens as one, so neither end learns where the other is.
+///
+/// This needs an I2P router running nearby, e.g. the Java router or i2pd, with its SAM bridge turned on.
+///
+/// Paths to send to look like `i2p:ADDRESS`, where the address is a `.b32.i2p` one, a full base64 destination, or a name
+/// the router knows, e.g. `i2p:abc...xyz.b32.i2p`.
+/// Packets are sent one stream per packet, like [TCP](struct.TCP.html)'s connections, from a session made the first
+/// time something's sent, with a new address that's only used for sending.
+/// The SAM bridge is at `127.0.0.1:7656`, unless the `sam` option says otherwise, for both sending and listening.
+///
+/// Paths to listen on look like `i2p:NAME`, where the name identifies the session to the router, so it has to be unique
+/// among everything using that router.
+/// Listening creates a destination, which the router forwards streams from to a TCP listener on the loopback address;
+/// that's on a free port, or the one in the `local` option.
+/// The destination lasts until listening stops, and gets a new address every time, unless the `key` option names a file
+/// to keep its key in, e.g. `i2p:relay?key=relay.i2p`, which is created if it doesn't exist;
+/// [`I2P::address`](#method.address) reads the address back out of it, to give to whoever should send here.
+///
+/// Everything waits up to 60 seconds for the router, since building tunnels can be slow; the `timeout` option changes
+/// that, e.g. `i2p:relay?timeout=2m`.
+/// Packets received are reported as arriving on the path being listened on, with no remote address, since there isn't
+/// one to know.
+pub struct I2P {
+  scheme: String,
+  forwarded: Forwarded,
+  /// The session packets are sent from on each SAM bridge, by its address, and the connection keeping it alive.
+  senders: HashMap<String, (String, TcpStream)>,
+  limit: crate::SizeLimit,
+}
+
+impl I2P {
+  /// The `.b32.i2p` address saved in a key file, as made by listening with the `key` option, if there's one there.
+  pub fn address(key_file: &str) -> Option<String> {
+    saved_address(key_file)
+  }
+
+  /// The ID of the session to send from on the given bridge, creating it if there isn't one yet.
+  fn sender(&mut self, sam: &str, timeout: Duration) -> Result<String, String> {
+    if let Some((id, _)) = self.senders.get(sam) {
+      return Ok(id.clone());
+    }
+    let id = format!(
+      "mesher-send-{}-{}",
+      std::process::id(),
+      NEXT_SENDER.fetch_add(1, Ordering::Relaxed)
+    );
+    let (session, _, _) = create_session(sam, timeout, &id, None)?;
+    self.senders.insert(sam.to_owned(), (id.clone(), session.keep_alive()));
+    Ok(id)
+  }
+
+  /// Opens a stream to the destination, through the session for sending.
+  fn connect(&mut self, sam: &str, destination: &str, timeout: Duration) -> Result<TcpStream, String> {
+    let id = self.sender(sam, timeout)?;
+    let mut stream = hello(sam, timeout)?;
+    stream.send(&format!(
+      "STREAM CONNECT ID={} DESTINATION={} SILENT=false",
+      id, destination
+    ))?;
+    match expect_ok(&mut stream, "STREAM STATUS") {
+      Ok(_) => stream.into_stream(),
+      Err(e) => {
+        // the router might've forgotten the session, e.g. after restarting, so make a new one next time
+        if e.contains("INVALID_ID") {
+          self.senders.remove(sam);
+        }
+        Err(e)
+      }
+    }
+  }
+}
+
+impl Transport for I2P {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(I2P {
+      scheme: scheme.to_owned(),
+      forwarded: Forwarded::new()?,
+      senders: HashMap::new(),
+      limit: crate::SizeLimit::new(),
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "I2P"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    self.limit.check(blob.len())?;
+    let destination = path.location();
+    if destination.is_empty() || destination.contains(' ') {
+      return Err(fail::MesherFail::InvalidURL(format!(
+        "I2P paths to send to look like i2p:ADDRESS, not {}",
+        path
+      )));
+    }
+    let options = path.options();
+    let timeout = options.duration("timeout")?.unwrap_or(DEFAULT_TIMEOUT);
+    let sam = options.get("sam").unwrap_or(DEFAULT_SAM);
+    let mut conn = self
+      .connect(sam, destination, timeout)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("couldn't reach {}: {}", path, e)))?;
+    conn
+      .write_all(blob)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))?;
+    debug_event!(path = %path, bytes = blob.len(), "I2P sent packet");
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    if self.forwarded.is_listening(path) {
+      return Ok(());
+    }
+    let id = path.location();
+    if id.is_empty() || id.contains(|c: char| c.is_whitespace() || c == '=') {
+      return Err(fail::MesherFail::InvalidURL(format!(
+        "I2P paths to listen on look like i2p:NAME, not {}",
+        path
+      )));
+    }
+    let options = path.options();
+    let timeout = options.duration("timeout")?.unwrap_or(DEFAULT_TIMEOUT);
+    let local_port = match options.parse_value("local")? {
+      Some(port) => port,
+      None => {
+        free_port().map_err(|e| fail::MesherFail::ListenFailure(format!("couldn't find a free local port: {}", e)))?
+      }
+    };
+    let local = self.forwarded.listen_locally(local_port)?;
+    let added = add_destination(
+      options.get("sam").unwrap_or(DEFAULT_SAM),
+      timeout,
+      id,
+      options.get("key"),
+      local_port,
+    );
+    match added {
+      Ok((alive, _address)) => {
+        debug_event!(path = %path, address = %_address, "I2P listening as destination");
+        self.forwarded.add(path, local, alive);
+        Ok(())
+      }
+      Err(e) => {
+        self.forwarded.abandon(&local);
+        Err(fail::MesherFail::ListenFailure(format!(
+          "couldn't set up an I2P destination for {}: {}",
+          path, e
+        )))
+      }
+    }
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    // closing the session's connection takes the destination down
+    self.forwarded.remove(path)
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.receive_up_to(usize::MAX)
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.forwarded.receive_up_to(max)
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self.forwarded.status()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.limit.set(max);
+    self.forwarded.set_max_packet_size(max);
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.forwarded.set_max_buffered(max);
+  }
+
+  fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    self.senders.clear();
+    self.forwarded.close(timeout)
+  }
+}
@@ -1,4 +1,406 @@
 extern crate mesher;
 
+/// Emits a `tracing` debug event, if the `tracing` feature is on, and does nothing otherwise.
+macro_rules! debug_event {
+  ($($arg:tt)*) => {
+    #[cfg(feature = "tracing")]
+    tracing::debug!($($arg)*);
+  };
+}
+
+use mesher::prelude::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+use mesher::ListenStatus;
+use std::sync::{
+  atomic::{AtomicUsize, Ordering},
+  Arc,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+  collections::HashMap,
+  net::{SocketAddr, ToSocketAddrs},
+  sync::{
+    mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    Mutex,
+  },
+  thread::{sleep, Builder, JoinHandle},
+  time::{Duration, Instant},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod exit;
+#[cfg(not(target_arch = "wasm32"))]
+mod hidden;
+#[cfg(not(target_arch = "wasm32"))]
+mod i2p;
+#[cfg(not(target_arch = "wasm32"))]
+pub use i2p::I2P;
+#[cfg(windows)]
+mod pipe;
+#[cfg(windows)]
+pub use pipe::NamedPipe;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod nat;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod socks;
+#[cfg(not(target_arch = "wasm32"))]
+mod stdio;
+#[cfg(not(target_arch = "wasm32"))]
+pub use stdio::Stdio;
+#[cfg(not(target_arch = "wasm32"))]
 mod tcp;
+#[cfg(not(target_arch = "wasm32"))]
 pub use tcp::TCP;
+#[cfg(not(target_arch = "wasm32"))]
+mod tor;
+#[cfg(not(target_arch = "wasm32"))]
+pub use tor::Tor;
+#[cfg(not(target_arch = "wasm32"))]
+mod udp;
+#[cfg(not(target_arch = "wasm32"))]
+pub use udp::UDP;
+#[cfg(target_arch = "wasm32")]
+mod ws;
+#[cfg(target_arch = "wasm32")]
+pub use ws::WebSocket;
+
+/// Adds every transport this crate has to the mesher, under its usual scheme, so quick starts don't have to add them one
+/// by one: [`TCP`](struct.TCP.html) for `tcp`, [`UDP`](struct.UDP.html) for `udp`, and the
+/// [in-memory transport](../mesher/debug_transports/struct.InMemory.html) for `inmem`.
+/// In the browser, [`WebSocket`](struct.WebSocket.html) is added for both `ws` and `wss` in place of TCP and UDP.
+///
+/// Fails with [`MesherFail::AlreadyRegistered`](../mesher/fail/enum.MesherFail.html#variant.AlreadyRegistered), without
+/// adding any of them, if one of those schemes already has a transport, and otherwise like
+/// [`Mesher::add_transport`](../mesher/struct.Mesher.html#method.add_transport).
+pub fn register_defaults(mesher: &mut Mesher) -> fail::Result<()> {
+  #[cfg(not(target_arch = "wasm32"))]
+  const SCHEMES: &[&str] = &["tcp", "udp", "inmem"];
+  #[cfg(target_arch = "wasm32")]
+  const SCHEMES: &[&str] = &["ws", "wss", "inmem"];
+  if let Some(taken) = mesher.transports().into_iter().find(|s| SCHEMES.contains(s)) {
+    return Err(fail::MesherFail::AlreadyRegistered(taken.to_owned()));
+  }
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    mesher.add_transport::<TCP>("tcp")?;
+    mesher.add_transport::<UDP>("udp")?;
+  }
+  #[cfg(target_arch = "wasm32")]
+  mesher.add_transport_for_schemes::<WebSocket>(&["ws", "wss"])?;
+  mesher.add_transport::<mesher::debug_transports::InMemory>("inmem")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn socket_addr(path: &Path) -> fail::Result<SocketAddr> {
+  let get_path_fail = || fail::MesherFail::InvalidURL(format!("not a valid socket address format: {}", path));
+  path
+    .location()
+    .to_socket_addrs()
+    .map_err(|_| get_path_fail())?
+    .next()
+    .ok_or_else(get_path_fail)
+}
+
+/// Every address the path's location resolves to, in order, without duplicates.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn socket_addrs(path: &Path) -> fail::Result<Vec<SocketAddr>> {
+  let mut addrs: Vec<SocketAddr> = vec![];
+  let resolved = path
+    .location()
+    .to_socket_addrs()
+    .map_err(|_| fail::MesherFail::InvalidURL(format!("not a valid socket address format: {}", path)))?;
+  for addr in resolved {
+    if !addrs.contains(&addr) {
+      addrs.push(addr);
+    }
+  }
+  if addrs.is_empty() {
+    return Err(fail::MesherFail::InvalidURL(format!("no addresses found for {}", path)));
+  }
+  Ok(addrs)
+}
+
+/// How long listener threads wait for new data before checking whether they've been told to stop, unless the path's
+/// `poll` option says otherwise.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The poll interval a path being listened on asks for with its `poll` option, or
+/// [`POLL_INTERVAL`](constant.POLL_INTERVAL.html) if it doesn't.
+///
+/// Fails with [`MesherFail::InvalidURL`](../mesher/fail/enum.MesherFail.html#variant.InvalidURL) if it's 0, since
+/// listeners would never wait at all.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn poll_interval(path: &Path) -> fail::Result<Duration> {
+  match path.options().duration("poll")? {
+    Some(poll) if poll.is_zero() => Err(fail::MesherFail::InvalidURL(format!(
+      "the poll interval can't be 0 in {}",
+      path
+    ))),
+    poll => Ok(poll.unwrap_or(POLL_INTERVAL)),
+  }
+}
+
+/// Waits up to `wait`, usually the [poll interval](fn.poll_interval.html), to see if a listener thread should stop.
+/// It should stop if it's told to explicitly, or if its transport has been dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn wait_for_stop(stop: &Receiver<()>, wait: Duration) -> bool {
+  !matches!(stop.recv_timeout(wait), Err(RecvTimeoutError::Timeout))
+}
+
+/// How long to wait between attempts to bring a failed listener back up: doubling after every failed attempt, from
+/// 100ms up to 30 seconds, and starting over once it's back.
+pub(crate) struct Backoff(core::time::Duration);
+
+impl Backoff {
+  const FIRST: core::time::Duration = core::time::Duration::from_millis(100);
+  const MAX: core::time::Duration = core::time::Duration::from_secs(30);
+
+  pub(crate) fn new() -> Backoff {
+    Backoff(Backoff::FIRST)
+  }
+
+  /// How long to wait before the next attempt.
+  pub(crate) fn next(&mut self) -> core::time::Duration {
+    let wait = self.0;
+    self.0 = (wait * 2).min(Backoff::MAX);
+    wait
+  }
+
+  /// Starts over, after an attempt succeeded.
+  pub(crate) fn reset(&mut self) {
+    self.0 = Backoff::FIRST;
+  }
+}
+
+/// A listener thread's latest status, shared with the thread so it can update it.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub(crate) struct StatusCell(Arc<Mutex<ListenStatus>>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StatusCell {
+  pub(crate) fn set(&self, status: ListenStatus) {
+    *self.0.lock().expect("poisoned lock?") = status;
+  }
+}
+
+/// The thread listening on one path, which is told to stop when this is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct Listener {
+  path: Path,
+  // sending on it, or dropping it, tells the listener thread to stop
+  stop: Sender<()>,
+  status: StatusCell,
+  thread: JoinHandle<()>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Listener {
+  /// Starts a thread with the given name, running `code`, which should return once
+  /// [`wait_for_stop`](fn.wait_for_stop.html) says to, and keep the status up to date.
+  pub(crate) fn spawn(
+    path: &Path,
+    name: String,
+    code: impl FnOnce(Receiver<()>, StatusCell) + Send + 'static,
+  ) -> fail::Result<Listener> {
+    let (stop_tx, stop_rx) = channel();
+    let status = StatusCell(Arc::new(Mutex::new(ListenStatus::Listening)));
+    let thread_status = status.clone();
+    let thread = Builder::new()
+      .name(name.clone())
+      .spawn(move || code(stop_rx, thread_status))
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to start {}: {:?}", name, e)))?;
+    Ok(Listener {
+      path: path.clone(),
+      stop: stop_tx,
+      status,
+      thread,
+    })
+  }
+
+  /// Whether the thread has stopped without being told to, e.g. because it panicked.
+  ///
+  /// Listeners are only told to stop when they're dropped or [stopped](fn.stop_all.html), so any that are still around
+  /// and aren't running have died.
+  pub(crate) fn is_dead(&self) -> bool {
+    self.thread.is_finished()
+  }
+
+  /// Tells the thread to stop, without dropping the listener, and waits up to `timeout` for it to, returning whether it
+  /// did.
+  pub(crate) fn halt(&self, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    // if the thread's already stopped, there's nobody to tell
+    let _ = self.stop.send(());
+    while !self.thread.is_finished() {
+      if Instant::now() >= deadline {
+        return false;
+      }
+      sleep(Duration::from_millis(5));
+    }
+    true
+  }
+
+  /// The path being listened on.
+  pub(crate) fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// The path being listened on, and how it's going.
+  pub(crate) fn status(&self) -> (Path, ListenStatus) {
+    // the thread only returns by itself if it's told to stop, which only happens once this is dropped or stopped
+    let status = if self.thread.is_finished() {
+      ListenStatus::Failed("listener thread stopped".to_owned())
+    } else {
+      self.status.0.lock().expect("poisoned lock?").clone()
+    };
+    (self.path.clone(), status)
+  }
+}
+
+/// Keeps track of restarting listeners whose threads died, so one that keeps dying isn't restarted as fast as the mesher
+/// receives.
+///
+/// Each try waits longer than the last, the same as listeners rebinding, and that doesn't start over until the path's
+/// [forgotten](#method.forget).
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub(crate) struct Restarts(HashMap<String, (Backoff, Instant)>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Restarts {
+  /// Whether it's time to try restarting the listener on the given location.
+  pub(crate) fn due(&mut self, location: &str) -> bool {
+    let now = Instant::now();
+    let (backoff, next_try) = self
+      .0
+      .entry(location.to_owned())
+      .or_insert_with(|| (Backoff::new(), now));
+    if now < *next_try {
+      return false;
+    }
+    // one that's been fine for a while since it last died starts over
+    if now >= *next_try + Backoff::MAX {
+      backoff.reset();
+    }
+    *next_try = now + backoff.next();
+    true
+  }
+
+  /// Starts over for the given location, once it's not being listened on.
+  pub(crate) fn forget(&mut self, location: &str) {
+    self.0.remove(location);
+  }
+}
+
+/// Tells every listener's thread to stop, then waits up to `timeout` for all of them to.
+///
+/// Fails with [`MesherFail::TimedOut`](../mesher/fail/enum.MesherFail.html#variant.TimedOut), naming the paths whose
+/// threads are still going, if any don't stop in time; they're left to stop on their own.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn stop_all(listeners: impl IntoIterator<Item = Listener>, timeout: Duration) -> fail::Result<()> {
+  let deadline = Instant::now() + timeout;
+  let mut running: Vec<_> = listeners.into_iter().collect();
+  for listener in &running {
+    // if the thread's already stopped, there's nobody to tell
+    let _ = listener.stop.send(());
+  }
+  loop {
+    running.retain(|l| !l.thread.is_finished());
+    if running.is_empty() {
+      return Ok(());
+    }
+    if Instant::now() >= deadline {
+      let paths: Vec<_> = running.iter().map(|l| l.path.to_string()).collect();
+      return Err(fail::MesherFail::TimedOut(format!(
+        "listener threads for {} didn't stop in time",
+        paths.join(", ")
+      )));
+    }
+    sleep(Duration::from_millis(5));
+  }
+}
+
+/// Hands packets from a transport's listener threads to the transport, holding no more than it's been told to.
+///
+/// Each listener thread has a clone, and the transport keeps the receiving end of the channel, as well as its own
+/// clone to take packets out with and to set the limit through.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub(crate) struct Inbox {
+  sender: Sender<(Source, Vec<u8>)>,
+  held: Arc<AtomicUsize>,
+  max: Arc<AtomicUsize>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Inbox {
+  /// Creates an inbox with no limit, until the mesher sets one, and the channel's receiving end.
+  pub(crate) fn new() -> (Inbox, Receiver<(Source, Vec<u8>)>) {
+    let (sender, receiver) = channel();
+    let inbox = Inbox {
+      sender,
+      held: Arc::new(AtomicUsize::new(0)),
+      max: Arc::new(AtomicUsize::new(usize::MAX)),
+    };
+    (inbox, receiver)
+  }
+
+  pub(crate) fn set_max(&self, max: Option<usize>) {
+    self.max.store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+  }
+
+  /// Passes a packet on to the transport, unless it's already holding as many as it's allowed, in which case the packet
+  /// is dropped.
+  ///
+  /// Returns false if the transport's gone, i.e. the listener should stop.
+  pub(crate) fn push(&self, source: Source, packet: Vec<u8>) -> bool {
+    if self.held.fetch_add(1, Ordering::Relaxed) >= self.max.load(Ordering::Relaxed) {
+      self.held.fetch_sub(1, Ordering::Relaxed);
+      debug_event!(
+        bytes = packet.len(),
+        "dropped packet, since the transport's holding as many as it can"
+      );
+      return true;
+    }
+    self.sender.send((source, packet)).is_ok()
+  }
+
+  /// Takes up to `max` of the packets waiting in `receiver`, oldest first.
+  pub(crate) fn take(&self, receiver: &Receiver<(Source, Vec<u8>)>, max: usize) -> Vec<(Source, Vec<u8>)> {
+    let taken: Vec<_> = receiver.try_iter().take(max).collect();
+    self.held.fetch_sub(taken.len(), Ordering::Relaxed);
+    taken
+  }
+}
+
+/// The largest packet a transport will send or accept, shared with its listener threads so changes reach them.
+#[derive(Clone)]
+pub(crate) struct SizeLimit(Arc<AtomicUsize>);
+
+impl SizeLimit {
+  /// Creates a limit that lets everything through, until the mesher sets one.
+  pub(crate) fn new() -> SizeLimit {
+    SizeLimit(Arc::new(AtomicUsize::new(usize::MAX)))
+  }
+
+  pub(crate) fn set(&self, max: Option<usize>) {
+    self.0.store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+  }
+
+  pub(crate) fn get(&self) -> usize {
+    self.0.load(Ordering::Relaxed)
+  }
+
+  /// Fails with [`MesherFail::PacketTooLarge`](../mesher/fail/enum.MesherFail.html#variant.PacketTooLarge) if a packet
+  /// of `len` bytes is over the limit.
+  pub(crate) fn check(&self, len: usize) -> fail::Result<()> {
+    let max = self.get();
+    if len > max {
+      return Err(fail::MesherFail::PacketTooLarge(len, max));
+    }
+    Ok(())
+  }
+}
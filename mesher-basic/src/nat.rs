@@ -0,0 +1,769 @@
+//! Getting packets through the NAT in front of a home network, so nodes behind one can be sent to without anyone setting
+//! up port forwarding by hand.
+//!
+//! There are two ways:
+//!
+//! - [Port mapping](fn.map_port.html) asks the router to forward a port, with NAT-PMP or UPnP, whichever it speaks.
+//!   [TCP](../struct.TCP.html) and [UDP](../struct.UDP.html) listeners do it themselves with the `map` option, e.g.
+//!   `udp:0.0.0.0:18540?map`, and keep the mapping up for as long as they listen.
+//! - Hole punching, for routers that won't map ports, has the node behind the NAT keep in touch with a
+//!   [rendezvous server](struct.Rendezvous.html) somewhere public, which tells it and whoever wants to send to it where
+//!   the other is, so they can each send the other a datagram to open up their NATs.
+//!   It only works over [UDP](../struct.UDP.html), and only through NATs that keep a socket's external port the same
+//!   whoever it sends to, which most home routers do.
+
+use mesher::prelude::*;
+
+use std::{
+  collections::HashMap,
+  convert::TryFrom,
+  net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket},
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Which protocol's port to map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+  Tcp,
+  Udp,
+}
+
+impl Protocol {
+  fn upnp(self) -> igd::PortMappingProtocol {
+    match self {
+      Protocol::Tcp => igd::PortMappingProtocol::TCP,
+      Protocol::Udp => igd::PortMappingProtocol::UDP,
+    }
+  }
+}
+
+/// Which router to ask for a port mapping, and how.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Router {
+  /// Try NAT-PMP with the default gateway, if it can be found, then UPnP with whichever device answers discovery.
+  Discover,
+  /// NAT-PMP, with the router at this address, usually port 5351.
+  NatPmp(SocketAddr),
+  /// UPnP, with the device that answers discovery searches at this address, usually port 1900, for when multicast
+  /// discovery doesn't work.
+  Upnp(SocketAddr),
+}
+
+/// How long mappings are asked for; they're renewed halfway through.
+const LIFETIME: Duration = Duration::from_secs(3600);
+
+/// How long to wait for the router when making a mapping, unless the path's options say otherwise, and when renewing
+/// one.
+pub(crate) const MAP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait before trying to renew a mapping again, after renewing failed.
+const RETRY_RENEW: Duration = Duration::from_secs(30);
+
+const NAT_PMP_PORT: u16 = 5351;
+
+fn failure(message: impl Into<String>) -> fail::MesherFail {
+  fail::MesherFail::SetupFailure(message.into())
+}
+
+/// How a mapping was made, so it can be renewed and removed the same way.
+#[derive(Debug, Clone)]
+enum Method {
+  NatPmp(SocketAddr),
+  Upnp(Upnp),
+}
+
+/// A port the router's forwarding here, which it stops forwarding once this is dropped.
+///
+/// Mappings don't last forever: call [`renew`](#method.renew) before half of the [lifetime](#method.lifetime) is up, or
+/// use [`keep_up`](#method.keep_up), which does it when it's time.
+#[derive(Debug)]
+pub struct Mapping {
+  protocol: Protocol,
+  internal_port: u16,
+  external: SocketAddr,
+  lifetime: Duration,
+  method: Method,
+  renew_at: Instant,
+}
+
+impl Mapping {
+  pub fn protocol(&self) -> Protocol {
+    self.protocol
+  }
+
+  /// The port on this machine that's being forwarded to.
+  pub fn internal_port(&self) -> u16 {
+    self.internal_port
+  }
+
+  /// The router's public address and the port it's forwarding, which is what others should send to.
+  pub fn external(&self) -> SocketAddr {
+    self.external
+  }
+
+  /// How long the router said the mapping would last, counting from when it was made or last renewed, where zero means
+  /// until it's removed.
+  pub fn lifetime(&self) -> Duration {
+    self.lifetime
+  }
+
+  /// Asks the router for the mapping again, restarting its lifetime.
+  pub fn renew(&mut self, timeout: Duration) -> fail::Result<()> {
+    let renewed = request(&self.method, self.protocol, self.internal_port, LIFETIME, timeout)?;
+    *self = renewed;
+    Ok(())
+  }
+
+  /// Renews the mapping if it's time to, returning whether it's still up.
+  ///
+  /// If renewing fails, it's tried again later, rather than every time this is called.
+  pub fn keep_up(&mut self, timeout: Duration) -> bool {
+    if Instant::now() < self.renew_at {
+      return true;
+    }
+    match self.renew(timeout) {
+      Ok(()) => true,
+      Err(_e) => {
+        debug_event!(port = self.internal_port, error = ?_e, "failed to renew port mapping");
+        self.renew_at = Instant::now() + RETRY_RENEW;
+        false
+      }
+    }
+  }
+}
+
+impl Drop for Mapping {
+  fn drop(&mut self) {
+    // the router forgets it eventually anyway, so this doesn't have to work
+    let timeout = Duration::from_secs(1);
+    let _ = match &self.method {
+      Method::NatPmp(router) => {
+        nat_pmp_map(*router, self.protocol, self.internal_port, Duration::ZERO, timeout).map(|_| ())
+      }
+      Method::Upnp(upnp) => upnp.delete(self.protocol, self.external.port(), timeout),
+    };
+  }
+}
+
+/// Asks the router to forward the given port, on its public address, to this machine.
+///
+/// Fails with [`SetupFailure`](../../mesher/fail/enum.MesherFail.html#variant.SetupFailure) if there's no router that
+/// will, e.g. because it doesn't speak NAT-PMP or UPnP, or has them turned off.
+/// Discovering routers waits up to `timeout` for each way of asking.
+pub fn map_port(protocol: Protocol, port: u16, router: &Router, timeout: Duration) -> fail::Result<Mapping> {
+  let method = match router {
+    Router::NatPmp(addr) => Method::NatPmp(*addr),
+    Router::Upnp(device) => Method::Upnp(Upnp::search(*device, timeout)?),
+    Router::Discover => {
+      if let Some(gateway) = default_gateway() {
+        let method = Method::NatPmp(SocketAddr::new(gateway.into(), NAT_PMP_PORT));
+        match request(&method, protocol, port, LIFETIME, timeout) {
+          Ok(mapping) => return Ok(mapping),
+          Err(_e) => {
+            debug_event!(gateway = %gateway, error = ?_e, "NAT-PMP didn't work, trying UPnP");
+          }
+        }
+      }
+      Method::Upnp(Upnp::search(SSDP, timeout)?)
+    }
+  };
+  request(&method, protocol, port, LIFETIME, timeout)
+}
+
+fn request(
+  method: &Method,
+  protocol: Protocol,
+  port: u16,
+  lifetime: Duration,
+  timeout: Duration,
+) -> fail::Result<Mapping> {
+  let (external, lifetime) = match method {
+    Method::NatPmp(router) => {
+      let (external_port, granted) = nat_pmp_map(*router, protocol, port, lifetime, timeout)?;
+      let ip = nat_pmp_address(*router, timeout)?;
+      (SocketAddr::new(ip.into(), external_port), granted)
+    }
+    Method::Upnp(upnp) => {
+      let granted = upnp.add(protocol, port, lifetime, timeout)?;
+      (SocketAddr::new(upnp.external_ip(timeout)?, port), granted)
+    }
+  };
+  debug_event!(port, external = %external, "mapped port");
+  // a lifetime of zero means the router keeps it until it's removed
+  let renew_in = if lifetime.is_zero() { LIFETIME } else { lifetime / 2 };
+  Ok(Mapping {
+    protocol,
+    internal_port: port,
+    external,
+    lifetime,
+    method: method.clone(),
+    renew_at: Instant::now() + renew_in,
+  })
+}
+
+/// The default IPv4 gateway, if it can be found, which it can only be on Linux, from the routing table.
+fn default_gateway() -> Option<Ipv4Addr> {
+  let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+  routes.lines().skip(1).find_map(|line| {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    if fields.get(1) != Some(&"00000000") {
+      return None;
+    }
+    let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+    // the table's in the machine's byte order, which is little-endian everywhere this is likely to run
+    Some(Ipv4Addr::from(gateway.swap_bytes())).filter(|ip| !ip.is_unspecified())
+  })
+}
+
+/// Sends a NAT-PMP request and waits for the reply to it, resending with the waits doubling, from 250ms, until `timeout`
+/// is up.
+fn nat_pmp(router: SocketAddr, request: &[u8], reply_len: usize, timeout: Duration) -> fail::Result<Vec<u8>> {
+  let sock = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+    .and_then(|s| s.connect(router).map(|_| s))
+    .map_err(|e| failure(format!("couldn't reach the router at {}: {}", router, e)))?;
+  let deadline = Instant::now() + timeout;
+  let mut wait = Duration::from_millis(250);
+  let mut buf = [0; 16];
+  loop {
+    let now = Instant::now();
+    if now >= deadline {
+      return Err(failure(format!("no NAT-PMP reply from {}", router)));
+    }
+    sock
+      .send(request)
+      .map_err(|e| failure(format!("couldn't send to the router at {}: {}", router, e)))?;
+    let until = (now + wait).min(deadline);
+    while let Some(left) = until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+      sock.set_read_timeout(Some(left)).expect("timeout isn't zero");
+      match sock.recv(&mut buf) {
+        // replies have the request's opcode plus 128
+        Ok(len) if len >= reply_len && buf[0] == 0 && buf[1] == request[1] + 128 => {
+          let result = u16::from_be_bytes([buf[2], buf[3]]);
+          if result != 0 {
+            return Err(failure(format!("the router refused, with NAT-PMP result {}", result)));
+          }
+          return Ok(buf[..reply_len].to_vec());
+        }
+        Ok(_) => continue,
+        // an earlier request bounced, so nothing's listening there
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+          return Err(failure(format!("nothing's answering NAT-PMP at {}", router)));
+        }
+        Err(_) => break,
+      }
+    }
+    wait *= 2;
+  }
+}
+
+/// The router's public address.
+fn nat_pmp_address(router: SocketAddr, timeout: Duration) -> fail::Result<Ipv4Addr> {
+  let reply = nat_pmp(router, &[0, 0], 12, timeout)?;
+  Ok(Ipv4Addr::new(reply[8], reply[9], reply[10], reply[11]))
+}
+
+/// Maps a port, or unmaps it with a lifetime of zero, returning the external port and how long it lasts.
+fn nat_pmp_map(
+  router: SocketAddr,
+  protocol: Protocol,
+  port: u16,
+  lifetime: Duration,
+  timeout: Duration,
+) -> fail::Result<(u16, Duration)> {
+  let op = match protocol {
+    Protocol::Udp => 1,
+    Protocol::Tcp => 2,
+  };
+  let mut request = vec![0, op, 0, 0];
+  request.extend_from_slice(&port.to_be_bytes());
+  // asking for the same port outside; unmapping has to ask for 0
+  let suggested = if lifetime.is_zero() { 0 } else { port };
+  request.extend_from_slice(&suggested.to_be_bytes());
+  request.extend_from_slice(&(lifetime.as_secs() as u32).to_be_bytes());
+  let reply = nat_pmp(router, &request, 16, timeout)?;
+  let external = u16::from_be_bytes([reply[10], reply[11]]);
+  let granted = u32::from_be_bytes([reply[12], reply[13], reply[14], reply[15]]);
+  Ok((external, Duration::from_secs(granted.into())))
+}
+
+/// Where UPnP devices listen for discovery searches.
+const SSDP: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900));
+
+/// Calls the router on another thread, giving up after `timeout`, since igd's own timeouts are 30 seconds long and
+/// listeners renew their mappings between packets.
+fn within<T: Send + 'static>(
+  timeout: Duration,
+  what: &str,
+  call: impl FnOnce() -> T + Send + 'static,
+) -> fail::Result<T> {
+  let (tx, rx) = std::sync::mpsc::sync_channel(1);
+  std::thread::spawn(move || {
+    let _ = tx.send(call());
+  });
+  rx.recv_timeout(timeout)
+    .map_err(|_| failure(format!("the router didn't answer in time to {}", what)))
+}
+
+/// A UPnP internet gateway device, found and talked to with [`igd`](https://crates.io/crates/igd).
+///
+/// igd only speaks UPnP, which is why NAT-PMP is still done by hand.
+#[derive(Debug, Clone)]
+struct Upnp {
+  gateway: igd::Gateway,
+  /// This machine's address on the router's network, which mappings forward to.
+  local_ip: Ipv4Addr,
+}
+
+impl Upnp {
+  /// Finds the gateway that answers a search sent to `at`, either the multicast discovery address or a particular
+  /// device's, waiting up to `timeout`.
+  fn search(at: SocketAddr, timeout: Duration) -> fail::Result<Upnp> {
+    let options = igd::SearchOptions {
+      broadcast_address: at,
+      timeout: Some(timeout),
+      ..Default::default()
+    };
+    let gateway = within(timeout, "search for UPnP gateways", move || {
+      igd::search_gateway(options)
+    })?
+    .map_err(|e| failure(format!("no UPnP gateway answered: {}", e)))?;
+    // whichever address the OS would use to reach the router is the one it can forward to
+    let local_ip = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+      .and_then(|s| s.connect(gateway.addr).and_then(|_| s.local_addr()))
+      .map_err(|e| failure(format!("couldn't find this machine's address: {}", e)))?
+      .ip();
+    match local_ip {
+      IpAddr::V4(local_ip) => Ok(Upnp { gateway, local_ip }),
+      IpAddr::V6(_) => Err(failure("UPnP gateways can only forward to IPv4 addresses")),
+    }
+  }
+
+  /// Maps a port, returning how long it lasts.
+  fn add(&self, protocol: Protocol, port: u16, lifetime: Duration, timeout: Duration) -> fail::Result<Duration> {
+    let gateway = self.gateway.clone();
+    let local = SocketAddrV4::new(self.local_ip, port);
+    within(timeout, "map a port", move || {
+      let add = |lease: Duration| {
+        let lease = u32::try_from(lease.as_secs()).unwrap_or(u32::MAX);
+        gateway.add_port(protocol.upnp(), port, local, lease, "mesher")
+      };
+      match add(lifetime) {
+        // some routers only do permanent mappings, which have a lease of 0
+        Err(igd::AddPortError::OnlyPermanentLeasesSupported) => add(Duration::ZERO).map(|_| Duration::ZERO),
+        other => other.map(|_| lifetime),
+      }
+    })?
+    .map_err(|e| failure(format!("the router refused to map port {}: {}", port, e)))
+  }
+
+  fn delete(&self, protocol: Protocol, port: u16, timeout: Duration) -> fail::Result<()> {
+    let gateway = self.gateway.clone();
+    within(timeout, "unmap a port", move || {
+      gateway.remove_port(protocol.upnp(), port)
+    })?
+    .map_err(|e| failure(format!("the router refused to unmap port {}: {}", port, e)))
+  }
+
+  fn external_ip(&self, timeout: Duration) -> fail::Result<IpAddr> {
+    let gateway = self.gateway.clone();
+    within(timeout, "say what its public address is", move || {
+      gateway.get_external_ip()
+    })?
+    .map(IpAddr::V4)
+    .map_err(|e| failure(format!("the router didn't say what its public address is: {}", e)))
+  }
+}
+
+/// Makes the port mapping a listening path asks for with its `map` option, if it does and it's an address that could
+/// be behind a NAT.
+///
+/// The `gateway` option says which router to use NAT-PMP with, e.g. `gateway=192.168.1.1`, and `igd` which to use UPnP
+/// with, e.g. `igd=192.168.1.1`; otherwise, one's [discovered](enum.Router.html#variant.Discover).
+/// Each way of asking waits up to the `map_timeout` option, or 2 seconds.
+/// Only IPv4 addresses are mapped, since IPv6 ones aren't usually behind a NAT.
+pub(crate) fn map_for(path: &Path, protocol: Protocol, addr: SocketAddr) -> fail::Result<Option<Mapping>> {
+  let options = path.options();
+  if !options.has("map") || !addr.is_ipv4() || addr.ip().is_loopback() {
+    return Ok(None);
+  }
+  let router = match (options.get("gateway"), options.get("igd")) {
+    (Some(gateway), _) => {
+      let with_port = if gateway.contains(':') {
+        gateway.to_owned()
+      } else {
+        format!("{}:{}", gateway, NAT_PMP_PORT)
+      };
+      let addr = with_port
+        .parse()
+        .map_err(|_| fail::MesherFail::InvalidURL(format!("not a valid gateway address: {}", gateway)))?;
+      Router::NatPmp(addr)
+    }
+    (None, Some(device)) => {
+      let with_port = if device.contains(':') {
+        device.to_owned()
+      } else {
+        format!("{}:{}", device, SSDP.port())
+      };
+      let addr = with_port
+        .parse()
+        .map_err(|_| fail::MesherFail::InvalidURL(format!("not a valid UPnP device address: {}", device)))?;
+      Router::Upnp(addr)
+    }
+    (None, None) => Router::Discover,
+  };
+  let timeout = options.duration("map_timeout")?.unwrap_or(MAP_TIMEOUT);
+  let mapping = map_port(protocol, addr.port(), &router, timeout)
+    .map_err(|e| fail::MesherFail::ListenFailure(format!("couldn't map {} on the router: {:?}", path, e)))?;
+  Ok(Some(mapping))
+}
+
+/// Starts every rendezvous message, so they can't be mistaken for packets, which never start with 0xFE.
+pub(crate) const MAGIC: &[u8] = b"\xFEmesher-rv";
+
+/// The kinds of rendezvous message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+  /// From a listener to the server, with its name, signed: "I'm here".
+  Register = 1,
+  /// From a sender to the server, with a name: "where's this?"
+  Lookup = 2,
+  /// From the server, with an address: to a sender, where the listener is, and to the listener, where the sender is.
+  Peer = 3,
+  /// From the server to a sender, with the name it looked up: "nobody by that name".
+  Unknown = 4,
+  /// Between a sender and listener, with nothing: "I can hear you".
+  Punch = 5,
+}
+
+/// Builds a rendezvous message.
+pub(crate) fn message(kind: Kind, payload: &str) -> Vec<u8> {
+  let mut m = MAGIC.to_vec();
+  m.push(kind as u8);
+  m.extend_from_slice(payload.as_bytes());
+  m
+}
+
+/// Reads a rendezvous message, if the datagram is one.
+pub(crate) fn parse(datagram: &[u8]) -> Option<(Kind, &str)> {
+  let rest = datagram.strip_prefix(MAGIC)?;
+  let (&kind, payload) = rest.split_first()?;
+  let kind = match kind {
+    1 => Kind::Register,
+    2 => Kind::Lookup,
+    3 => Kind::Peer,
+    4 => Kind::Unknown,
+    5 => Kind::Punch,
+    _ => return None,
+  };
+  Some((kind, std::str::from_utf8(payload).ok()?))
+}
+
+/// How often listeners register with the rendezvous server, which also keeps their NAT's mapping for it open.
+pub(crate) const REGISTER_EVERY: Duration = Duration::from_secs(20);
+
+/// How long the server remembers a listener for after it last registered.
+const FORGET_AFTER: Duration = Duration::from_secs(60);
+
+/// How often the server clears out listeners it's forgotten.
+const SWEEP_EVERY: Duration = Duration::from_secs(1);
+
+/// How many names the server keeps at once; past that, new ones are turned away until old ones are forgotten.
+const MAX_NAMES: usize = 4096;
+
+/// How long an Ed25519 signature is.
+const SIGNATURE_BYTES: usize = 64;
+
+/// What a listener signs to register: the name, and when, so old registrations can't be sent again.
+fn registration_body(name: &str, time: u64) -> Vec<u8> {
+  format!("mesher-rv register {} {}", time, name).into_bytes()
+}
+
+/// Builds a registration's payload: the name, the key it's bound to, when it was sent, in milliseconds since the Unix
+/// epoch, and a signature over the name and time.
+fn sign_registration(name: &str, time: u64, skey: &sign::SecretKey) -> String {
+  let signed = default_backend().sign(&registration_body(name, time), skey);
+  format!(
+    "{} {} {} {}",
+    name,
+    skey.public_key(),
+    time,
+    write_key(&signed[..SIGNATURE_BYTES])
+  )
+}
+
+/// Reads a registration's name, key, and time, if it's well-formed and its signature checks out.
+fn verify_registration(payload: &str) -> Option<(&str, sign::PublicKey, u64)> {
+  // the name comes first, so it can have spaces in it
+  let mut fields = payload.rsplitn(4, ' ');
+  let mut signed = parse_key(fields.next()?).filter(|s| s.len() == SIGNATURE_BYTES)?;
+  let time = fields.next()?.parse().ok()?;
+  let key: sign::PublicKey = fields.next()?.parse().ok()?;
+  let name = fields.next().filter(|n| !n.is_empty())?;
+  signed.extend(registration_body(name, time));
+  default_backend().verify(&signed, &key)?;
+  Some((name, key, time))
+}
+
+/// A listener the server knows about.
+struct Listener {
+  /// The key it first registered with, which it has to sign with to keep the name.
+  key: sign::PublicKey,
+  addr: SocketAddr,
+  /// The time in its latest registration, which later ones have to be after.
+  time: u64,
+  seen: Instant,
+}
+
+/// Introduces UDP listeners behind NATs to the nodes that want to send to them, so they can punch holes through.
+///
+/// It has to be somewhere both can reach, i.e. not behind a NAT itself.
+/// Listeners register with it under a name, with the `rendezvous` and `name` options, e.g.
+/// `udp:0.0.0.0:18540?rendezvous=rv.example.com:18600&name=alice`, and senders send to it with the name of the node
+/// they want in the `punch` option, e.g. `udp:rv.example.com:18600?punch=alice`.
+/// It never sees packets, just the addresses of the nodes it introduces.
+///
+/// Each name belongs to the key the listener first registered it with, and only registrations signed with that key can
+/// move it, until the listener's gone a minute without registering and the name's forgotten.
+/// Listeners make a new key each time they start listening, so one that restarts gets its name back once the old
+/// registration's forgotten.
+/// At most 4096 names are kept at once.
+///
+/// `mesher rendezvous ADDRESS`, from `mesher-node`, runs one.
+pub struct Rendezvous {
+  socket: UdpSocket,
+  listeners: HashMap<String, Listener>,
+  swept: Instant,
+}
+
+impl Rendezvous {
+  /// Binds the server to the given address, e.g. `0.0.0.0:18600`.
+  pub fn bind(addr: impl ToSocketAddrs) -> fail::Result<Rendezvous> {
+    let socket = UdpSocket::bind(addr)
+      .map_err(|e| fail::MesherFail::ListenFailure(format!("couldn't bind rendezvous server: {}", e)))?;
+    Ok(Rendezvous {
+      socket,
+      listeners: HashMap::new(),
+      swept: Instant::now(),
+    })
+  }
+
+  /// The address the server's bound to.
+  pub fn local_addr(&self) -> fail::Result<SocketAddr> {
+    self
+      .socket
+      .local_addr()
+      .map_err(|e| fail::MesherFail::ListenFailure(format!("couldn't get the server's address: {}", e)))
+  }
+
+  /// Handles messages as they arrive, forever, unless receiving fails.
+  pub fn run(&mut self) -> fail::Result<()> {
+    let mut buf = [0; 512];
+    loop {
+      let (len, from) = self
+        .socket
+        .recv_from(&mut buf)
+        .map_err(|e| fail::MesherFail::ReceiveFailure(format!("rendezvous server couldn't receive: {}", e)))?;
+      self.handle(&buf[..len], from);
+    }
+  }
+
+  fn handle(&mut self, datagram: &[u8], from: SocketAddr) {
+    let now = Instant::now();
+    if now.duration_since(self.swept) >= SWEEP_EVERY {
+      self
+        .listeners
+        .retain(|_, listener| now.duration_since(listener.seen) < FORGET_AFTER);
+      self.swept = now;
+    }
+    // sending's best-effort, since either side will ask again if it doesn't hear back
+    match parse(datagram) {
+      Some((Kind::Register, payload)) => self.register(payload, from, now),
+      Some((Kind::Lookup, name)) => match self
+        .listeners
+        .get(name)
+        .filter(|l| now.duration_since(l.seen) < FORGET_AFTER)
+      {
+        Some(listener) => {
+          debug_event!(name, from = %from, "rendezvous introduction");
+          let _ = self
+            .socket
+            .send_to(&message(Kind::Peer, &listener.addr.to_string()), from);
+          let _ = self
+            .socket
+            .send_to(&message(Kind::Peer, &from.to_string()), listener.addr);
+        }
+        None => {
+          let _ = self.socket.send_to(&message(Kind::Unknown, name), from);
+        }
+      },
+      _ => (),
+    }
+  }
+
+  /// Takes a registration, if it's signed by the key the name belongs to, or the name's free.
+  fn register(&mut self, payload: &str, from: SocketAddr, now: Instant) {
+    let (name, key, time) = match verify_registration(payload) {
+      Some(registration) => registration,
+      None => {
+        debug_event!(from = %from, "rendezvous registration with a bad signature");
+        return;
+      }
+    };
+    match self.listeners.get_mut(name) {
+      Some(listener) if now.duration_since(listener.seen) < FORGET_AFTER => {
+        if listener.key != key || time <= listener.time {
+          debug_event!(name, from = %from, "rendezvous registration for someone else's name, or replayed");
+          return;
+        }
+        debug_event!(name, from = %from, "rendezvous registration");
+        listener.addr = from;
+        listener.time = time;
+        listener.seen = now;
+      }
+      forgotten => {
+        if forgotten.is_none() && self.listeners.len() >= MAX_NAMES {
+          debug_event!(name, from = %from, "rendezvous server full");
+          return;
+        }
+        debug_event!(name, from = %from, "rendezvous registration");
+        self.listeners.insert(
+          name.to_owned(),
+          Listener {
+            key,
+            addr: from,
+            time,
+            seen: now,
+          },
+        );
+      }
+    }
+  }
+}
+
+/// Sends a packet to the listener registered as `name` with the rendezvous server at `server`, punching through its
+/// NAT first, and giving up after `timeout`.
+pub(crate) fn punch_and_send(server: SocketAddr, name: &str, blob: &[u8], timeout: Duration) -> fail::Result<()> {
+  let send_err =
+    |e: std::io::Error| fail::MesherFail::SendFailure(format!("couldn't punch through to {}: {}", name, e));
+  let sock = UdpSocket::bind(match server {
+    SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+    SocketAddr::V6(_) => SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 0)),
+  })
+  .map_err(send_err)?;
+  let resend = Duration::from_millis(100);
+  sock.set_read_timeout(Some(resend)).map_err(send_err)?;
+  let deadline = Instant::now() + timeout;
+  let mut buf = [0; 512];
+  let mut peer = None;
+  while Instant::now() < deadline {
+    // asking again, or punching again, every time nothing useful comes back in time
+    match peer {
+      None => sock.send_to(&message(Kind::Lookup, name), server),
+      Some(peer) => sock.send_to(&message(Kind::Punch, ""), peer),
+    }
+    .map_err(send_err)?;
+    let (len, from) = match sock.recv_from(&mut buf) {
+      Ok(got) => got,
+      Err(_) => continue,
+    };
+    match parse(&buf[..len]) {
+      Some((Kind::Peer, addr)) if from == server => {
+        peer = Some(addr.parse().map_err(|_| {
+          fail::MesherFail::SendFailure(format!("the rendezvous server sent a bad address for {}", name))
+        })?);
+      }
+      Some((Kind::Unknown, _)) if from == server => {
+        return Err(fail::MesherFail::SendFailure(format!(
+          "nobody's registered as {} with the rendezvous server at {}",
+          name, server
+        )));
+      }
+      Some((Kind::Punch, _)) if Some(from) == peer => {
+        sock.send_to(blob, from).map_err(send_err)?;
+        debug_event!(name, peer = %from, bytes = blob.len(), "UDP punched through and sent packet");
+        return Ok(());
+      }
+      _ => (),
+    }
+  }
+  Err(fail::MesherFail::TimedOut(format!(
+    "couldn't punch through to {} in time",
+    name
+  )))
+}
+
+/// What a listener registered with a rendezvous server has to keep track of.
+pub(crate) struct Registration {
+  server: SocketAddr,
+  name: String,
+  /// The key the name's bound to, made fresh for each listener.
+  key: sign::SecretKey,
+  /// The time in the latest registration, so each one's later than the last even if the clock goes backwards.
+  time: u64,
+  next: Instant,
+  /// Senders the server's introduced recently, who'll be punching through, so only they get answered.
+  expected: HashMap<SocketAddr, Instant>,
+}
+
+impl Registration {
+  /// Reads the `rendezvous` and `name` options from a listening path, if it has them.
+  pub(crate) fn for_path(path: &Path) -> fail::Result<Option<Registration>> {
+    let options = path.options();
+    let (server, name) = match (options.get("rendezvous"), options.get("name")) {
+      (None, None) => return Ok(None),
+      (Some(server), Some(name)) if !name.is_empty() => (server, name),
+      _ => {
+        return Err(fail::MesherFail::InvalidURL(format!(
+          "registering with a rendezvous server needs both the rendezvous and name options, in {}",
+          path
+        )))
+      }
+    };
+    let server = server
+      .to_socket_addrs()
+      .ok()
+      .and_then(|mut a| a.next())
+      .ok_or_else(|| fail::MesherFail::InvalidURL(format!("couldn't resolve rendezvous server {}", server)))?;
+    Ok(Some(Registration {
+      server,
+      name: name.to_owned(),
+      key: sign::gen_keypair().1,
+      time: 0,
+      next: Instant::now(),
+      expected: HashMap::new(),
+    }))
+  }
+
+  /// Registers again, if it's time to.
+  pub(crate) fn keep_up(&mut self, sock: &UdpSocket) {
+    let now = Instant::now();
+    if now < self.next {
+      return;
+    }
+    let clock = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |t| u64::try_from(t.as_millis()).unwrap_or(u64::MAX));
+    self.time = clock.max(self.time + 1);
+    // if it's lost, the next one will do
+    let registration = sign_registration(&self.name, self.time, &self.key);
+    let _ = sock.send_to(&message(Kind::Register, &registration), self.server);
+    self.next = now + REGISTER_EVERY;
+    self.expected.retain(|_, until| *until > now);
+  }
+
+  /// Deals with a rendezvous message the listener received, punching back through to senders the server introduces.
+  pub(crate) fn handle(&mut self, sock: &UdpSocket, kind: Kind, payload: &str, from: SocketAddr) {
+    match kind {
+      Kind::Peer if from == self.server => {
+        if let Ok(sender) = payload.parse() {
+          self.expected.insert(sender, Instant::now() + Duration::from_secs(10));
+          let _ = sock.send_to(&message(Kind::Punch, ""), sender);
+        }
+      }
+      Kind::Punch if self.expected.contains_key(&from) => {
+        let _ = sock.send_to(&message(Kind::Punch, ""), from);
+      }
+      _ => (),
+    }
+  }
+}
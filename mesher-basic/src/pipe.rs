@@ -0,0 +1,370 @@
+use mesher::prelude::*;
+
+use std::{
+  collections::HashMap,
+  ffi::c_void,
+  fs::{File, OpenOptions},
+  io::{prelude::*, ErrorKind},
+  os::windows::{
+    ffi::OsStrExt,
+    io::{AsRawHandle, FromRawHandle, RawHandle},
+  },
+  ptr,
+  sync::mpsc::Receiver,
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+use crate::{poll_interval, stop_all, wait_for_stop, Backoff, Inbox, Listener, Restarts, SizeLimit, StatusCell};
+
+use mesher::ListenStatus;
+
+/// How long sending waits for the pipe to take a connection, and then to write the packet, unless the path's options say
+/// otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The bits of the Win32 named pipe API that std doesn't cover, which is everything on the listening side.
+const PIPE_ACCESS_INBOUND: u32 = 0x0000_0001;
+const FILE_FLAG_FIRST_PIPE_INSTANCE: u32 = 0x0008_0000;
+const PIPE_WAIT: u32 = 0x0000_0000;
+const PIPE_NOWAIT: u32 = 0x0000_0001;
+const PIPE_REJECT_REMOTE_CLIENTS: u32 = 0x0000_0008;
+const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+const ERROR_FILE_NOT_FOUND: i32 = 2;
+const ERROR_NO_DATA: i32 = 232;
+const ERROR_PIPE_BUSY: i32 = 231;
+const ERROR_PIPE_CONNECTED: i32 = 535;
+const ERROR_PIPE_LISTENING: i32 = 536;
+
+#[link(name = "kernel32")]
+extern "system" {
+  fn CreateNamedPipeW(
+    name: *const u16,
+    open_mode: u32,
+    pipe_mode: u32,
+    max_instances: u32,
+    out_buffer_size: u32,
+    in_buffer_size: u32,
+    default_timeout: u32,
+    security_attributes: *mut c_void,
+  ) -> RawHandle;
+  fn ConnectNamedPipe(pipe: RawHandle, overlapped: *mut c_void) -> i32;
+  fn SetNamedPipeHandleState(
+    pipe: RawHandle,
+    mode: *mut u32,
+    max_collection_count: *mut u32,
+    collect_data_timeout: *mut u32,
+  ) -> i32;
+}
+
+/// The pipe's full name, e.g. `\\.\pipe\mesher` for `pipe:mesher`.
+///
+/// Fails with [`MesherFail::InvalidURL`](../mesher/fail/enum.MesherFail.html#variant.InvalidURL) if the path doesn't
+/// name a pipe, since the name can't be empty or have backslashes in it.
+fn pipe_name(path: &Path) -> fail::Result<String> {
+  let name = path.location();
+  if name.is_empty() || name.contains('\\') {
+    return Err(fail::MesherFail::InvalidURL(format!("not a valid pipe name: {}", path)));
+  }
+  Ok(format!(r"\\.\pipe\{}", name))
+}
+
+/// One instance of a pipe being listened on, which takes one connection before it's closed and replaced.
+struct Instance(File);
+
+impl Instance {
+  /// Creates a new instance of the pipe, which doesn't wait for connections, so the listener can check whether it should
+  /// stop in between.
+  ///
+  /// The first instance is created with `first` set, so it fails if some other process already has the pipe, the same
+  /// way binding a port does.
+  fn create(name: &str, first: bool) -> std::io::Result<Instance> {
+    let wide: Vec<u16> = std::ffi::OsStr::new(name).encode_wide().chain(Some(0)).collect();
+    let open_mode = PIPE_ACCESS_INBOUND | if first { FILE_FLAG_FIRST_PIPE_INSTANCE } else { 0 };
+    // SAFETY: the name is NUL-terminated, and null security attributes just mean the defaults
+    let handle = unsafe {
+      CreateNamedPipeW(
+        wide.as_ptr(),
+        open_mode,
+        PIPE_NOWAIT | PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_UNLIMITED_INSTANCES,
+        0,
+        64 * 1024,
+        0,
+        ptr::null_mut(),
+      )
+    };
+    // INVALID_HANDLE_VALUE
+    if handle as isize == -1 {
+      return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the handle was just created, and nothing else owns it
+    Ok(Instance(unsafe { File::from_raw_handle(handle) }))
+  }
+
+  /// Checks for a client without waiting, returning whether there is one.
+  ///
+  /// One that's already written its packet and hung up still counts, since what it wrote can still be read.
+  fn connected(&self) -> std::io::Result<bool> {
+    // SAFETY: the handle's open for as long as self is, and null means it's not overlapped
+    if unsafe { ConnectNamedPipe(self.0.as_raw_handle(), ptr::null_mut()) } != 0 {
+      return Ok(false);
+    }
+    let e = std::io::Error::last_os_error();
+    match e.raw_os_error() {
+      Some(ERROR_PIPE_LISTENING) => Ok(false),
+      Some(ERROR_PIPE_CONNECTED) | Some(ERROR_NO_DATA) => Ok(true),
+      _ => Err(e),
+    }
+  }
+
+  /// Makes reads wait for data, once a client's connected, rather than failing when there isn't any yet.
+  fn into_blocking(self) -> std::io::Result<File> {
+    let mut mode = PIPE_WAIT;
+    // SAFETY: the handle's open, and null means leave the other settings alone
+    let ok = unsafe { SetNamedPipeHandleState(self.0.as_raw_handle(), &mut mode, ptr::null_mut(), ptr::null_mut()) };
+    if ok == 0 {
+      return Err(std::io::Error::last_os_error());
+    }
+    Ok(self.0)
+  }
+}
+
+fn listen(on: &Path, name: String, poll: Duration, inbox: Inbox, limit: SizeLimit) -> fail::Result<Listener> {
+  let instance = Instance::create(&name, true)
+    .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to create pipe {}: {:?}", name, e)))?;
+  debug_event!(pipe = %name, "named pipe listening");
+
+  let source_path = on.clone();
+  let thread_name = format!("named pipe {} listener", name);
+  let mut instance = Some(instance);
+  let mut backoff = Backoff::new();
+  let thread_code = move |stop, status: StatusCell| loop {
+    let current = match instance.take() {
+      Some(i) => i,
+      None => {
+        if wait_for_stop(&stop, backoff.next()) {
+          return;
+        }
+        if let Ok(i) = Instance::create(&name, false) {
+          status.set(ListenStatus::Listening);
+          backoff.reset();
+          instance = Some(i);
+        }
+        continue;
+      }
+    };
+    match current.connected() {
+      Ok(true) => (),
+      Ok(false) => {
+        instance = Some(current);
+        if wait_for_stop(&stop, poll) {
+          return;
+        }
+        continue;
+      }
+      Err(_e) => {
+        debug_event!(pipe = %name, error = %_e, "named pipe failed, recreating it");
+        status.set(ListenStatus::Reconnecting);
+        continue;
+      }
+    }
+    // the next instance is made before this one's closed, so senders don't find the pipe missing in between
+    let next = Instance::create(&name, false);
+    let max = limit.get();
+    let mut bytes = vec![];
+    // reading one byte past the limit is enough to tell the packet's too big, without buffering the rest of it
+    let read = current
+      .into_blocking()
+      .and_then(|conn| conn.take(max as u64 + 1).read_to_end(&mut bytes));
+    match next {
+      Ok(i) => instance = Some(i),
+      Err(_) => status.set(ListenStatus::Reconnecting),
+    }
+    if read.is_err() || bytes.is_empty() {
+      continue;
+    }
+    if bytes.len() > max {
+      debug_event!(pipe = %name, max, "named pipe dropped oversized packet");
+      continue;
+    }
+    debug_event!(pipe = %name, bytes = bytes.len(), "named pipe received packet");
+    if !inbox.push(Source::listening_on(source_path.clone()), bytes) {
+      return;
+    }
+  };
+
+  Listener::spawn(on, thread_name, thread_code)
+}
+
+/// Opens the pipe to write a packet, waiting up to `timeout` for it to exist and have an instance free.
+fn connect(name: &str, timeout: Option<Duration>) -> fail::Result<File> {
+  let deadline = timeout.map(|t| Instant::now() + t);
+  loop {
+    match OpenOptions::new().write(true).open(name) {
+      Ok(f) => return Ok(f),
+      // the listener's between instances, or there's a sender ahead of this one
+      Err(e)
+        if matches!(e.raw_os_error(), Some(ERROR_PIPE_BUSY) | Some(ERROR_FILE_NOT_FOUND))
+          && deadline.is_none_or(|d| Instant::now() < d) =>
+      {
+        sleep(Duration::from_millis(5));
+      }
+      Err(e) if e.kind() == ErrorKind::NotFound => {
+        return Err(fail::MesherFail::SendFailure(format!(
+          "Nobody's listening on pipe {}",
+          name
+        )))
+      }
+      Err(e) => {
+        return Err(fail::MesherFail::SendFailure(format!(
+          "Failed to open pipe {}: {:?}",
+          name, e
+        )))
+      }
+    }
+  }
+}
+
+/// Sends and receives packets over Windows named pipes, one connection per packet, for other processes on the same
+/// machine, e.g. a local daemon's clients.
+///
+/// Paths look like `pipe:name`, for the pipe `\\.\pipe\name`.
+/// Only local processes can connect; remote clients are rejected, so this is never reachable over the network.
+/// Listening fails if another process already has a pipe by that name.
+/// Listeners check for new connections every 50ms, so that's how long a packet can wait before it's read; the `poll`
+/// option changes that, e.g. `pipe:mesher?poll=5ms`.
+///
+/// Sending waits up to 10 seconds for the pipe to be free, since the listener takes one connection at a time, and fails
+/// straight away with [`SendFailure`](../../mesher/fail/enum.MesherFail.html#variant.SendFailure) if it finds nobody
+/// listening after that.
+/// The `timeout` option changes how long it waits, e.g. `pipe:mesher?timeout=2s`; a timeout of `0` waits forever.
+///
+/// If a listener's thread dies, e.g. by panicking, it's restarted the next time the mesher receives, with a growing wait
+/// between attempts if it keeps dying; if restarting fails, receiving fails with
+/// [`TransportDead`](../../mesher/fail/enum.MesherFail.html#variant.TransportDead), and it's tried again later.
+///
+/// Only available on Windows.
+pub struct NamedPipe {
+  inbox: Inbox,
+  receiver: Receiver<(Source, Vec<u8>)>,
+  scheme: String,
+  listeners: HashMap<String, Listener>,
+  restarts: Restarts,
+  limit: SizeLimit,
+}
+
+impl NamedPipe {
+  /// Restarts the listeners whose threads have died, if it's time to try again.
+  fn restart_dead(&mut self) -> fail::Result<()> {
+    let dead: Vec<Path> = self
+      .listeners
+      .values()
+      .filter(|l| l.is_dead())
+      .map(|l| l.path().clone())
+      .collect();
+    let mut result = Ok(());
+    for path in dead {
+      if !self.restarts.due(path.location()) {
+        continue;
+      }
+      debug_event!(path = %path, "named pipe listener thread died, restarting it");
+      // the dead thread's instance went with it, so the name's free to take again
+      let old = self.listeners.remove(path.location()).expect("dead listener vanished");
+      if let Err(e) = self.listen(&path) {
+        // kept around so it's still reported, and tried again later
+        self.listeners.insert(path.location().to_owned(), old);
+        result = Err(fail::MesherFail::TransportDead(format!(
+          "listener on {} died, and restarting it failed: {:?}",
+          path, e
+        )));
+      }
+    }
+    result
+  }
+}
+
+impl Transport for NamedPipe {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    let (inbox, receiver) = Inbox::new();
+    Ok(NamedPipe {
+      scheme: scheme.to_string(),
+      inbox,
+      receiver,
+      listeners: HashMap::new(),
+      restarts: Restarts::default(),
+      limit: SizeLimit::new(),
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "named pipe"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    self.limit.check(blob.len())?;
+    let timeout = path.options().duration("timeout")?.unwrap_or(DEFAULT_TIMEOUT);
+    let mut out = connect(&pipe_name(path)?, Some(timeout).filter(|t| !t.is_zero()))?;
+    out
+      .write_all(blob)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))?;
+    debug_event!(path = %path, bytes = blob.len(), "named pipe sent packet");
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    if self.listeners.contains_key(path.location()) {
+      return Ok(());
+    }
+    let listener = listen(
+      path,
+      pipe_name(path)?,
+      poll_interval(path)?,
+      self.inbox.clone(),
+      self.limit.clone(),
+    )?;
+    self.listeners.insert(path.location().to_owned(), listener);
+    Ok(())
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    self.restarts.forget(path.location());
+    // dropping the listener tells its thread to stop
+    self
+      .listeners
+      .remove(path.location())
+      .map(|_| ())
+      .ok_or_else(|| fail::MesherFail::NotListening(path.to_string()))
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.receive_up_to(usize::MAX)
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    // anything the dead thread received is still waiting in the inbox for next time
+    self.restart_dead()?;
+    Ok(self.inbox.take(&self.receiver, max))
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self.listeners.values().map(Listener::status).collect()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.limit.set(max);
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.inbox.set_max(max);
+  }
+
+  fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    self.restarts = Restarts::default();
+    stop_all(self.listeners.drain().map(|(_, listener)| listener), timeout)
+  }
+}
@@ -0,0 +1,818 @@
+//! Tunnelling TCP connections through the mesh, so applications that speak SOCKS5 can use it without knowing it's there.
+//!
+//! A [`Socks`](struct.Socks.html) frontend listens for SOCKS5 clients locally, and for each connection they ask for, has
+//! an [exit](../exit/struct.Exit.html) at the end of a route open it and pass data back and forth.
+//! Data goes in chunks of up to 16 KiB, each in an [RPC](../../mesher/rpc/index.html) numbered in order, whose response
+//! brings back whatever the exit's read from the server since the last one.
+//! Each stream only has one request out at a time, and neither end reads more than 256 KiB ahead of what the other's
+//! taken, so a slow reader slows the other end down rather than filling up memory.
+//! Each end writes to its connections from a thread per stream, so one that's stopped reading doesn't hold up the
+//! others; if a server falls that far behind, the exit answers without taking the data, and the frontend sends it again
+//! later.
+//! When neither side has anything to send, the frontend asks for more less and less often, down to once a second.
+//! Requests that go unanswered are sent again with the same number, so the exit can tell it's seen them before and give
+//! the same answer, and streams are given up on after three tries.
+//!
+//! Only `CONNECT`, without authentication, is supported, since the frontend's meant to listen on loopback for the
+//! applications on the same machine; anyone who can reach it can use the exit as if they were that machine.
+//! The same caveats apply as for [plain HTTP](../exit/index.html): the exit sees everything that isn't encrypted
+//! end-to-end, e.g. with TLS, and learns the key its responses are encrypted for, so that should be one just for this.
+//!
+//! ```no_run
+//! # use mesher::prelude::*;
+//! use mesher_basic::socks::Socks;
+//!
+//! # let exit_pk = encrypt::gen_keypair().0;
+//! let (own_pk, own_sk) = encrypt::gen_keypair();
+//! let mut mesher = Mesher::unsigned(vec![own_sk]);
+//! mesher.add_transport::<mesher_basic::TCP>("tcp").expect("Failed to add transport");
+//! mesher.listen_on("tcp:0.0.0.0:18541").expect("Failed to listen");
+//! let route = mesher::Route::new()
+//!   .then(Path::parse("tcp:client.example.com:18541").unwrap(), own_pk)
+//!   .then(Path::parse("tcp:exit.example.com:18540").unwrap(), exit_pk);
+//! // then e.g. `curl --socks5-hostname localhost:1080 http://example.com/`
+//! let mut socks = Socks::bind("127.0.0.1:1080", route).expect("Failed to bind");
+//! socks.run(&mut mesher).expect("Failed to tunnel");
+//! ```
+
+use crate::exit::{failure, put_bytes, put_str, reason, take, take_bytes, take_str, take_u16, Exit};
+
+use mesher::{
+  prelude::*,
+  rpc::{RequestId, Rpc},
+  Route,
+};
+
+use std::{
+  collections::{HashMap, VecDeque},
+  io::{prelude::*, ErrorKind},
+  net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+  sync::{
+    mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError},
+    Arc, Mutex,
+  },
+  thread::{sleep, Builder},
+  time::{Duration, Instant},
+};
+
+/// The RPC method exits answer stream requests for.
+pub const METHOD: &str = "stream";
+
+/// The most data sent either way in one request or response.
+const CHUNK: usize = 16 * 1024;
+/// The most data read from either end of a stream before the other end's taken it.
+const WINDOW: usize = 256 * 1024;
+/// The most streams an exit, or a frontend, has open at once.
+const MAX_STREAMS: usize = 256;
+/// How long an exit keeps a stream it hasn't heard about, before assuming the frontend's gone.
+const FORGET_AFTER: Duration = Duration::from_secs(120);
+/// How long a frontend waits for each response before asking again, unless it's told otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many times a frontend sends a request before giving up on its stream.
+const TRIES: u32 = 3;
+/// How long a stream with nothing going either way waits to ask the exit for more, at first.
+const FIRST_IDLE: Duration = Duration::from_millis(20);
+/// How long a stream with nothing going either way waits to ask the exit for more, at most.
+const MAX_IDLE: Duration = Duration::from_secs(1);
+/// How long threads reading from connections wait for data before checking whether their stream's gone.
+const READ_POLL: Duration = Duration::from_millis(100);
+/// How long SOCKS clients have to say where they want to connect.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// what a request asks the exit to do
+const OPEN: u8 = 0;
+const DATA: u8 = 1;
+const CLOSE: u8 = 2;
+
+/// Marks data as the last its sender will send.
+const FIN: u8 = 1;
+/// Marks a response as not having taken the request's data, since the server's behind, so it has to be sent again.
+const HELD: u8 = 2;
+
+// how a response starts
+const OK: u8 = 0;
+const FAILED: u8 = 1;
+
+// SOCKS5's constants
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN: u8 = 3;
+const IPV6: u8 = 4;
+const SUCCEEDED: u8 = 0;
+const GENERAL_FAILURE: u8 = 1;
+const HOST_UNREACHABLE: u8 = 4;
+const COMMAND_NOT_SUPPORTED: u8 = 7;
+const ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// Identifies a stream to the exit, chosen at random by the frontend so other frontends can't guess it.
+type StreamId = [u8; 16];
+
+/// What a frontend asks an exit to do with a stream.
+#[derive(Debug, PartialEq, Eq)]
+enum Request {
+  Open { host: String, port: u16 },
+  Data { fin: bool, data: Vec<u8> },
+  Close,
+}
+
+/// Encodes a request as the stream's ID, the big-endian `u32` sequence number, and the kind of request, followed by:
+/// for opening, the host, prefixed by its big-endian `u16` length, and the port; for data, the flags, then the data,
+/// prefixed by its big-endian `u32` length; and for closing, nothing.
+fn encode_request(id: &StreamId, seq: u32, request: &Request) -> fail::Result<Vec<u8>> {
+  let mut b = id.to_vec();
+  b.extend_from_slice(&seq.to_be_bytes());
+  match request {
+    Request::Open { host, port } => {
+      b.push(OPEN);
+      put_str(&mut b, host)?;
+      b.extend_from_slice(&port.to_be_bytes());
+    }
+    Request::Data { fin, data } => {
+      b.push(DATA);
+      b.push(if *fin { FIN } else { 0 });
+      put_bytes(&mut b, data)?;
+    }
+    Request::Close => b.push(CLOSE),
+  }
+  Ok(b)
+}
+
+fn decode_request(mut from: &[u8]) -> Option<(StreamId, u32, Request)> {
+  let mut id = StreamId::default();
+  id.copy_from_slice(take(&mut from, 16)?);
+  let seq = take(&mut from, 4)?;
+  let seq = u32::from_be_bytes([seq[0], seq[1], seq[2], seq[3]]);
+  let request = match take(&mut from, 1)?[0] {
+    OPEN => Request::Open {
+      host: take_str(&mut from)?,
+      port: take_u16(&mut from)?,
+    },
+    DATA => Request::Data {
+      fin: take(&mut from, 1)?[0] & FIN != 0,
+      data: take_bytes(&mut from).filter(|data| data.len() <= CHUNK)?,
+    },
+    CLOSE => Request::Close,
+    _ => return None,
+  };
+  Some((id, seq, request)).filter(|_| from.is_empty())
+}
+
+/// Encodes a response with data, as `OK`, the flags, and the data, prefixed by its big-endian `u32` length.
+fn encode_data(flags: u8, data: &[u8]) -> fail::Result<Vec<u8>> {
+  let mut b = vec![OK, flags];
+  put_bytes(&mut b, data)?;
+  Ok(b)
+}
+
+/// Encodes a response saying the request failed, as `FAILED`, then why.
+fn encode_failure(why: &str) -> Vec<u8> {
+  let mut b = vec![FAILED];
+  b.extend_from_slice(why.as_bytes());
+  b
+}
+
+/// Decodes a response into its flags and data, or why the request failed.
+fn decode_response(from: &[u8]) -> fail::Result<(u8, Vec<u8>)> {
+  match from.split_first() {
+    Some((&OK, mut rest)) => {
+      let flags = take(&mut rest, 1);
+      match (flags, take_bytes(&mut rest)) {
+        (Some(flags), Some(data)) if rest.is_empty() && data.len() <= CHUNK => Ok((flags[0], data)),
+        _ => Err(failure("the exit sent a malformed response")),
+      }
+    }
+    Some((&FAILED, why)) => Err(failure(String::from_utf8_lossy(why))),
+    _ => Err(failure("the exit sent a malformed response")),
+  }
+}
+
+/// Data read from one end of a stream that's waiting to be sent to the other, shared with the thread reading it.
+#[derive(Default)]
+struct Buffered {
+  data: VecDeque<u8>,
+  closed: bool,
+}
+
+type Incoming = Arc<Mutex<Buffered>>;
+
+/// Starts a thread reading from `conn` into a buffer, which waits whenever there's a window's worth in it, and stops
+/// once the connection closes or the buffer's dropped.
+fn read_into_buffer(mut conn: TcpStream, name: String) -> Incoming {
+  let incoming = Incoming::default();
+  let shared = incoming.clone();
+  let started = Builder::new().name(name).spawn(move || {
+    let mut buf = vec![0; CHUNK];
+    // without a timeout, the thread wouldn't notice the buffer being dropped until the connection closes
+    let _ = conn.set_read_timeout(Some(READ_POLL));
+    while Arc::strong_count(&shared) > 1 {
+      if shared.lock().expect("poisoned lock?").data.len() >= WINDOW {
+        sleep(Duration::from_millis(5));
+        continue;
+      }
+      let waited = [ErrorKind::WouldBlock, ErrorKind::TimedOut, ErrorKind::Interrupted];
+      match conn.read(&mut buf) {
+        Ok(0) => break,
+        Ok(len) => shared.lock().expect("poisoned lock?").data.extend(&buf[..len]),
+        Err(e) if waited.contains(&e.kind()) => continue,
+        Err(_) => break,
+      }
+    }
+    shared.lock().expect("poisoned lock?").closed = true;
+  });
+  if started.is_err() {
+    incoming.lock().expect("poisoned lock?").closed = true;
+  }
+  incoming
+}
+
+/// Takes up to a chunk of what's been read, and whether that's the last of it, i.e. the connection's closed and there's
+/// nothing left.
+fn drain(incoming: &Incoming) -> (Vec<u8>, bool) {
+  let mut buffered = incoming.lock().expect("poisoned lock?");
+  let len = buffered.data.len().min(CHUNK);
+  let data = buffered.data.drain(..len).collect();
+  (data, buffered.closed && buffered.data.is_empty())
+}
+
+/// One stream an exit's tunnelling, to the server the frontend asked for.
+struct Tunnel {
+  conn: TcpStream,
+  incoming: Incoming,
+  /// Hands what the frontend sent to the thread writing it to the server, until the frontend's sent the last of it.
+  outgoing: Option<SyncSender<Vec<u8>>>,
+  /// The sequence number of the next request.
+  next: u32,
+  /// The answer to the last request, in case it's sent again.
+  last: Vec<u8>,
+  seen: Instant,
+}
+
+impl Tunnel {
+  /// Queues what the frontend sent to be written to the server, and answers with whatever's been read from it since
+  /// last time.
+  ///
+  /// If the server's a window behind, the data isn't taken, and the answer says so, rather than waiting for it.
+  fn pass_on(&mut self, fin: bool, data: Vec<u8>) -> fail::Result<Vec<u8>> {
+    let mut flags = 0;
+    if !data.is_empty() {
+      let outgoing = self
+        .outgoing
+        .as_ref()
+        .ok_or_else(|| failure("the frontend already sent the last of its data"))?;
+      match outgoing.try_send(data) {
+        Ok(()) => (),
+        Err(TrySendError::Full(_)) => flags |= HELD,
+        Err(TrySendError::Disconnected(_)) => return Err(failure("couldn't write to the server")),
+      }
+    }
+    if fin && flags & HELD == 0 {
+      // the writing thread finishes what it has, then shuts down the server's side
+      self.outgoing = None;
+    }
+    let (data, closed) = drain(&self.incoming);
+    if closed {
+      flags |= FIN;
+    }
+    encode_data(flags, &data)
+  }
+}
+
+impl Drop for Tunnel {
+  fn drop(&mut self) {
+    let _ = self.conn.shutdown(Shutdown::Both);
+  }
+}
+
+/// The streams an exit's tunnelling for frontends, following the same rules as it does for HTTP requests.
+pub(crate) struct Streams {
+  exit: Exit,
+  tunnels: HashMap<StreamId, Tunnel>,
+}
+
+impl Streams {
+  pub(crate) fn new(exit: Exit) -> Streams {
+    Streams {
+      exit,
+      tunnels: HashMap::new(),
+    }
+  }
+
+  /// Answers a request for one of the streams.
+  pub(crate) fn handle(&mut self, body: &[u8]) -> Vec<u8> {
+    self.answer(body).unwrap_or_else(|e| encode_failure(&reason(e)))
+  }
+
+  fn answer(&mut self, body: &[u8]) -> fail::Result<Vec<u8>> {
+    let now = Instant::now();
+    self
+      .tunnels
+      .retain(|_, tunnel| now.duration_since(tunnel.seen) < FORGET_AFTER);
+    let (id, seq, request) = decode_request(body).ok_or_else(|| failure("malformed request"))?;
+    let tunnel = match (self.tunnels.get_mut(&id), request) {
+      (None, Request::Open { host, port }) if seq == 0 => return self.open(id, &host, port),
+      (None, _) => return Err(failure("the stream isn't open")),
+      (Some(tunnel), request) => {
+        tunnel.seen = now;
+        // the frontend didn't get the answer, so it's asking again
+        if seq.wrapping_add(1) == tunnel.next {
+          return Ok(tunnel.last.clone());
+        }
+        if seq != tunnel.next {
+          return Err(failure("request out of order"));
+        }
+        match request {
+          Request::Open { .. } => return Err(failure("the stream's already open")),
+          Request::Close => {
+            self.tunnels.remove(&id);
+            debug_event!("exit closed stream");
+            return encode_data(FIN, &[]);
+          }
+          Request::Data { fin, data } => match tunnel.pass_on(fin, data) {
+            Ok(answer) => {
+              tunnel.next = seq.wrapping_add(1);
+              tunnel.last = answer;
+              tunnel
+            }
+            Err(e) => {
+              self.tunnels.remove(&id);
+              debug_event!(reason = ?e, "exit dropped stream");
+              return Err(e);
+            }
+          },
+        }
+      }
+    };
+    Ok(tunnel.last.clone())
+  }
+
+  /// Opens a new stream to `host` on `port`, if the exit allows it.
+  fn open(&mut self, id: StreamId, host: &str, port: u16) -> fail::Result<Vec<u8>> {
+    if self.tunnels.len() >= MAX_STREAMS {
+      return Err(failure("the exit has too many streams open"));
+    }
+    let conn = self.exit.connect(host, port)?;
+    let (reader, writer) = conn
+      .try_clone()
+      .and_then(|reader| Ok((reader, conn.try_clone()?)))
+      .map_err(|e| failure(format!("couldn't tunnel to {}: {}", host, e)))?;
+    let outgoing = write_from_channel(writer, format!("exit stream to {}:{}", host, port))
+      .ok_or_else(|| failure(format!("couldn't start writing to {}", host)))?;
+    debug_event!(host, port, "exit opened stream");
+    let incoming = read_into_buffer(reader, format!("exit stream from {}:{}", host, port));
+    let answer = encode_data(0, &[])?;
+    let tunnel = Tunnel {
+      conn,
+      incoming,
+      outgoing: Some(outgoing),
+      next: 1,
+      last: answer.clone(),
+      seen: Instant::now(),
+    };
+    self.tunnels.insert(id, tunnel);
+    Ok(answer)
+  }
+}
+
+/// Answers a SOCKS5 request with the given reply code, and no particular bound address.
+fn reply(conn: &mut TcpStream, code: u8) -> bool {
+  conn
+    .write_all(&[SOCKS_VERSION, code, 0, IPV4, 0, 0, 0, 0, 0, 0])
+    .is_ok()
+}
+
+/// Reads a SOCKS5 client's greeting and request, answering the greeting, and returns the host and port it wants to
+/// connect to, or `None` if it wants something else, after telling it so if SOCKS5 has a way to.
+fn handshake(conn: &mut TcpStream) -> Option<(String, u16)> {
+  let mut head = [0; 2];
+  conn.read_exact(&mut head).ok()?;
+  if head[0] != SOCKS_VERSION {
+    return None;
+  }
+  let mut methods = vec![0; head[1] as usize];
+  conn.read_exact(&mut methods).ok()?;
+  if !methods.contains(&NO_AUTH) {
+    let _ = conn.write_all(&[SOCKS_VERSION, NO_ACCEPTABLE_METHODS]);
+    return None;
+  }
+  conn.write_all(&[SOCKS_VERSION, NO_AUTH]).ok()?;
+
+  let mut request = [0; 4];
+  conn.read_exact(&mut request).ok()?;
+  if request[0] != SOCKS_VERSION {
+    return None;
+  }
+  if request[1] != CONNECT {
+    reply(conn, COMMAND_NOT_SUPPORTED);
+    return None;
+  }
+  let host = match request[3] {
+    IPV4 => {
+      let mut ip = [0; 4];
+      conn.read_exact(&mut ip).ok()?;
+      Ipv4Addr::from(ip).to_string()
+    }
+    DOMAIN => {
+      let mut len = [0];
+      conn.read_exact(&mut len).ok()?;
+      let mut name = vec![0; len[0] as usize];
+      conn.read_exact(&mut name).ok()?;
+      String::from_utf8(name).ok()?
+    }
+    IPV6 => {
+      let mut ip = [0; 16];
+      conn.read_exact(&mut ip).ok()?;
+      Ipv6Addr::from(ip).to_string()
+    }
+    _ => {
+      reply(conn, ADDRESS_NOT_SUPPORTED);
+      return None;
+    }
+  };
+  let mut port = [0; 2];
+  conn.read_exact(&mut port).ok()?;
+  Some((host, u16::from_be_bytes(port)))
+}
+
+/// Starts a thread writing everything sent to the returned channel to `conn`, which shuts down the connection's writing
+/// side once the channel's dropped, and drops its end if writing fails.
+fn write_from_channel(mut conn: TcpStream, name: String) -> Option<SyncSender<Vec<u8>>> {
+  let (sender, receiver) = sync_channel::<Vec<u8>>(WINDOW / CHUNK);
+  Builder::new()
+    .name(name)
+    .spawn(move || {
+      for data in receiver {
+        if conn.write_all(&data).is_err() {
+          return;
+        }
+      }
+      let _ = conn.shutdown(Shutdown::Write);
+    })
+    .ok()?;
+  Some(sender)
+}
+
+/// What a frontend needs to send requests to the exit.
+struct Link<'a> {
+  mesher: &'a mut Mesher,
+  rpc: &'a mut Rpc,
+  route: &'a Route,
+  timeout: Duration,
+}
+
+impl Link<'_> {
+  fn request(&mut self, body: &[u8]) -> fail::Result<RequestId> {
+    self.rpc.request(self.mesher, self.route, METHOD, body, self.timeout)
+  }
+}
+
+/// A request that's been sent for a stream, and hasn't been answered yet.
+struct InFlight {
+  id: RequestId,
+  body: Vec<u8>,
+  tries: u32,
+}
+
+/// One connection from a SOCKS client, being tunnelled through the exit.
+struct Stream {
+  id: StreamId,
+  /// The sequence number of the next request.
+  seq: u32,
+  conn: TcpStream,
+  /// What's been read from the client, once the stream's open.
+  incoming: Option<Incoming>,
+  /// Hands what the exit sent to the thread writing it to the client, once the stream's open.
+  outgoing: Option<SyncSender<Vec<u8>>>,
+  /// What the exit sent that's waiting for room in `outgoing`.
+  held: Option<Vec<u8>>,
+  in_flight: Option<InFlight>,
+  /// When to ask the exit for more, if there's nothing to send it.
+  next_ask: Instant,
+  idle: Duration,
+  sent_fin: bool,
+  got_fin: bool,
+  /// Whether the exit's server is behind, so data isn't sent again until `next_ask`.
+  exit_behind: bool,
+  closing: bool,
+}
+
+impl Stream {
+  /// Sends the stream's next request, returning whether it was sent.
+  fn send(&mut self, link: &mut Link, request: Request) -> bool {
+    let sent = encode_request(&self.id, self.seq, &request).and_then(|body| Ok((link.request(&body)?, body)));
+    match sent {
+      Ok((id, body)) => {
+        self.in_flight = Some(InFlight { id, body, tries: 1 });
+        true
+      }
+      Err(_e) => {
+        debug_event!(reason = ?_e, "couldn't send SOCKS stream request");
+        false
+      }
+    }
+  }
+
+  /// Passes data from the exit on to the client, holding it if the client's behind, and returns false if the client's
+  /// stopped reading.
+  fn hand_over(&mut self, data: Vec<u8>) -> bool {
+    let outgoing = match &self.outgoing {
+      Some(outgoing) => outgoing,
+      None => return false,
+    };
+    match outgoing.try_send(data) {
+      Ok(()) => true,
+      Err(TrySendError::Full(data)) => {
+        self.held = Some(data);
+        true
+      }
+      Err(TrySendError::Disconnected(_)) => false,
+    }
+  }
+
+  /// Puts data the exit didn't take back in front of what's been read from the client, to be sent again.
+  fn unsend(&mut self, body: &[u8]) {
+    if let (Some(incoming), Some((_, _, Request::Data { fin, data }))) = (&self.incoming, decode_request(body)) {
+      let mut buffered = incoming.lock().expect("poisoned lock?");
+      for byte in data.into_iter().rev() {
+        buffered.data.push_front(byte);
+      }
+      self.sent_fin &= !fin;
+    }
+  }
+
+  /// Gives up on the stream, telling the exit to close it if it's open there, and returns whether there's still a
+  /// response to wait for.
+  fn abort(&mut self, link: &mut Link) -> bool {
+    let _ = self.conn.shutdown(Shutdown::Both);
+    if self.incoming.is_none() || self.closing {
+      return false;
+    }
+    self.closing = true;
+    self.send(link, Request::Close)
+  }
+
+  /// Moves the stream along as far as it can go without waiting, and returns false once it's done with.
+  fn step(&mut self, link: &mut Link) -> bool {
+    if let Some(held) = self.held.take() {
+      if !self.hand_over(held) {
+        return self.abort(link);
+      }
+    }
+
+    if let Some(in_flight) = &mut self.in_flight {
+      let answer = match link.rpc.response(in_flight.id) {
+        None => return true,
+        Some(Err(fail::MesherFail::TimedOut(_))) if in_flight.tries < TRIES && !self.closing => {
+          match link.request(&in_flight.body) {
+            Ok(id) => {
+              in_flight.id = id;
+              in_flight.tries += 1;
+              return true;
+            }
+            Err(e) => Err(e),
+          }
+        }
+        Some(answer) => answer,
+      };
+      let sent = std::mem::take(&mut in_flight.body);
+      self.in_flight = None;
+      self.seq = self.seq.wrapping_add(1);
+      if self.closing {
+        return false;
+      }
+      let (flags, data) = match answer.and_then(|answer| decode_response(&answer)) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+          debug_event!(reason = ?e, "SOCKS stream failed");
+          if self.incoming.is_none() {
+            let code = match e {
+              fail::MesherFail::TimedOut(_) => HOST_UNREACHABLE,
+              _ => GENERAL_FAILURE,
+            };
+            reply(&mut self.conn, code);
+          }
+          let _ = self.conn.shutdown(Shutdown::Both);
+          return false;
+        }
+      };
+
+      if self.incoming.is_none() {
+        let reader = self.conn.try_clone().ok();
+        let writer = self.conn.try_clone().ok();
+        let (reader, writer) = match (reader, writer) {
+          (Some(reader), Some(writer)) if reply(&mut self.conn, SUCCEEDED) => (reader, writer),
+          _ => {
+            // the exit opened it, so it has to close it too
+            self.incoming = Some(Incoming::default());
+            return self.abort(link);
+          }
+        };
+        let name = self.conn.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        self.incoming = Some(read_into_buffer(reader, format!("SOCKS stream from {}", name)));
+        self.outgoing = write_from_channel(writer, format!("SOCKS stream to {}", name));
+      }
+      let now = Instant::now();
+      if data.is_empty() {
+        self.next_ask = now + self.idle;
+        self.idle = (self.idle * 2).min(MAX_IDLE);
+      } else {
+        self.next_ask = now;
+        self.idle = FIRST_IDLE;
+        if !self.hand_over(data) {
+          return self.abort(link);
+        }
+      }
+      if flags & HELD != 0 {
+        // give the server a moment to catch up before sending it the same data again
+        self.unsend(&sent);
+        self.next_ask = now + self.idle;
+        self.idle = (self.idle * 2).min(MAX_IDLE);
+        self.exit_behind = true;
+      }
+      self.got_fin |= flags & FIN != 0;
+    }
+
+    let incoming = match &self.incoming {
+      Some(incoming) => incoming,
+      // still waiting for the exit to open it
+      None => return true,
+    };
+    // the exit's waiting on the client, so there's no point asking it for more
+    if self.held.is_some() {
+      return true;
+    }
+    if self.got_fin {
+      // the writing thread finishes what it has, then shuts down the client's side
+      self.outgoing = None;
+    }
+    if self.got_fin && self.sent_fin {
+      self.closing = true;
+      return self.send(link, Request::Close);
+    }
+    if self.exit_behind && Instant::now() < self.next_ask {
+      return true;
+    }
+    let (data, closed) = drain(incoming);
+    let fin = closed && !self.sent_fin;
+    if data.is_empty() && !fin && (self.got_fin || Instant::now() < self.next_ask) {
+      return true;
+    }
+    if !data.is_empty() && !self.exit_behind {
+      // an answer's probably on its way, so start checking for it often again
+      self.idle = FIRST_IDLE;
+    }
+    self.exit_behind = false;
+    self.sent_fin |= fin;
+    self.send(link, Request::Data { fin, data })
+  }
+}
+
+/// A local SOCKS5 server, which tunnels the connections its clients ask for through the mesh, to an
+/// [exit](../exit/struct.Exit.html).
+///
+/// It needs to be [polled](#method.poll) regularly to make progress, with the [`Rpc`](../../mesher/rpc/struct.Rpc.html)
+/// that the mesher's messages are handed to, or [run](#method.run) with a mesher of its own.
+pub struct Socks {
+  listener: TcpListener,
+  route: Route,
+  timeout: Duration,
+  asked_tx: Sender<(TcpStream, String, u16)>,
+  asked: Receiver<(TcpStream, String, u16)>,
+  streams: Vec<Stream>,
+}
+
+impl Socks {
+  /// Listens for SOCKS5 clients on `addr`, to tunnel their connections to the exit at the end of `route`.
+  ///
+  /// The route has to start with a path the mesher it's polled with is listening on, since the exit's responses come
+  /// back along it, reversed.
+  pub fn bind(addr: impl ToSocketAddrs, route: Route) -> fail::Result<Socks> {
+    let listener = TcpListener::bind(addr)
+      .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+      .map_err(|e| fail::MesherFail::ListenFailure(format!("couldn't bind SOCKS frontend: {}", e)))?;
+    let (asked_tx, asked) = channel();
+    Ok(Socks {
+      listener,
+      route,
+      timeout: DEFAULT_TIMEOUT,
+      asked_tx,
+      asked,
+      streams: vec![],
+    })
+  }
+
+  /// The address the frontend's listening on.
+  pub fn local_addr(&self) -> fail::Result<SocketAddr> {
+    self
+      .listener
+      .local_addr()
+      .map_err(|e| fail::MesherFail::ListenFailure(format!("couldn't get the frontend's address: {}", e)))
+  }
+
+  /// Sets how long to wait for each response from the exit before asking again.
+  pub fn timeout(mut self, timeout: Duration) -> Socks {
+    self.timeout = timeout;
+    self
+  }
+
+  /// How many streams are open, including ones the exit hasn't opened yet.
+  pub fn streams(&self) -> usize {
+    self.streams.len()
+  }
+
+  /// Accepts new clients, and moves every stream along as far as it can go without waiting.
+  ///
+  /// The exit's responses only come in through `rpc`, so every message the mesher receives should be
+  /// [handed to it](../../mesher/rpc/struct.Rpc.html#method.handle) between polls.
+  /// Fails if the listener can't accept clients anymore; problems with individual streams, including sending their
+  /// requests, just close them.
+  pub fn poll(&mut self, mesher: &mut Mesher, rpc: &mut Rpc) -> fail::Result<()> {
+    loop {
+      match self.listener.accept() {
+        Ok((conn, from)) => self.greet(conn, from),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+        Err(e) => {
+          return Err(fail::MesherFail::ListenFailure(format!(
+            "SOCKS frontend couldn't accept: {}",
+            e
+          )))
+        }
+      }
+    }
+
+    let mut link = Link {
+      mesher,
+      rpc,
+      route: &self.route,
+      timeout: self.timeout,
+    };
+    for (mut conn, host, port) in self.asked.try_iter() {
+      if self.streams.len() >= MAX_STREAMS {
+        reply(&mut conn, GENERAL_FAILURE);
+        continue;
+      }
+      debug_event!(host = %host, port, "opening SOCKS stream");
+      let mut stream = Stream {
+        id: rand::random(),
+        seq: 0,
+        conn,
+        incoming: None,
+        outgoing: None,
+        held: None,
+        in_flight: None,
+        next_ask: Instant::now(),
+        idle: FIRST_IDLE,
+        sent_fin: false,
+        got_fin: false,
+        exit_behind: false,
+        closing: false,
+      };
+      if stream.send(&mut link, Request::Open { host, port }) {
+        self.streams.push(stream);
+      } else {
+        reply(&mut stream.conn, GENERAL_FAILURE);
+      }
+    }
+    self.streams.retain_mut(|stream| stream.step(&mut link));
+    Ok(())
+  }
+
+  /// Has a thread find out where a new client wants to connect, so slow ones don't hold up the rest.
+  fn greet(&self, mut conn: TcpStream, from: SocketAddr) {
+    let asked = self.asked_tx.clone();
+    // if the thread can't start, the client's dropped, and can try again
+    let _ = Builder::new()
+      .name(format!("SOCKS handshake with {}", from))
+      .spawn(move || {
+        let ready = conn
+          .set_nonblocking(false)
+          .and_then(|_| conn.set_read_timeout(Some(HANDSHAKE_TIMEOUT)));
+        if ready.is_err() {
+          return;
+        }
+        if let Some((host, port)) = handshake(&mut conn) {
+          if conn.set_read_timeout(None).is_ok() {
+            let _ = asked.send((conn, host, port));
+          }
+        }
+      });
+  }
+
+  /// Tunnels connections forever, receiving with `mesher` and handling responses with an `Rpc` of its own, unless
+  /// receiving or accepting clients fails.
+  ///
+  /// Messages that aren't responses are dropped, and requests are answered as not having a handler.
+  pub fn run(&mut self, mesher: &mut Mesher) -> fail::Result<()> {
+    let mut rpc = Rpc::new();
+    loop {
+      for message in mesher.receive_wait(Duration::from_millis(10))? {
+        if !rpc.handle(mesher, &message)? {
+          debug_event!("SOCKS frontend dropped a message that wasn't a response");
+        }
+      }
+      self.poll(mesher, &mut rpc)?;
+    }
+  }
+}
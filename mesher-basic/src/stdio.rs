@@ -0,0 +1,333 @@
+use mesher::prelude::*;
+
+use std::{
+  collections::BTreeMap,
+  io::{self, prelude::*},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc::Receiver,
+    Arc, Mutex,
+  },
+  thread::Builder,
+  time::Duration,
+};
+
+use crate::{Inbox, SizeLimit};
+
+use mesher::ListenStatus;
+
+/// The stream that's always there: the process's own stdin and stdout.
+const STD_STREAM: &str = "-";
+
+/// Every stream that's been attached, by name, shared by every `Stdio` transport in the process.
+static STREAMS: Mutex<BTreeMap<String, Stream>> = Mutex::new(BTreeMap::new());
+
+/// Gives each transport its own ID, so one can't stop another's listening.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Whichever transport's listening on a stream, which the reading thread hands packets to.
+struct Subscriber {
+  transport: u64,
+  path: Path,
+  inbox: Inbox,
+  limit: SizeLimit,
+}
+
+/// What a stream's reading thread shares with the transports.
+#[derive(Default)]
+struct Shared {
+  subscriber: Mutex<Option<Subscriber>>,
+  /// Why the stream stopped being readable, once it has.
+  ended: Mutex<Option<String>>,
+}
+
+/// An attached pair of streams.
+struct Stream {
+  /// Taken by the reading thread, which is started the first time the stream's listened on.
+  reader: Option<Box<dyn Read + Send>>,
+  writer: Arc<Mutex<Box<dyn Write + Send>>>,
+  shared: Arc<Shared>,
+}
+
+impl Stream {
+  fn new(reader: Box<dyn Read + Send>, writer: Box<dyn Write + Send>) -> Stream {
+    Stream {
+      reader: Some(reader),
+      writer: Arc::new(Mutex::new(writer)),
+      shared: Arc::default(),
+    }
+  }
+}
+
+/// Runs `code` on the stream with the given name, creating the [standard one](constant.STD_STREAM.html) the first time
+/// it's used.
+fn with_stream<T>(name: &str, code: impl FnOnce(&mut Stream) -> fail::Result<T>) -> fail::Result<T> {
+  let mut streams = STREAMS.lock().expect("poisoned lock?");
+  if name == STD_STREAM && !streams.contains_key(name) {
+    streams.insert(
+      name.to_owned(),
+      Stream::new(Box::new(io::stdin()), Box::new(io::stdout())),
+    );
+  }
+  match streams.get_mut(name) {
+    Some(stream) => code(stream),
+    None => Err(fail::MesherFail::InvalidURL(format!("no stream attached as {}", name))),
+  }
+}
+
+/// Reads packets from the stream until it ends, handing them to whichever transport's listening at the time.
+///
+/// Packets that arrive while nobody's listening, or that are over the listener's limit, are skipped, without losing
+/// track of where the next one starts.
+fn read_packets(mut reader: Box<dyn Read + Send>, shared: Arc<Shared>) {
+  let ended = loop {
+    let mut len = [0; 4];
+    if let Err(e) = reader.read_exact(&mut len) {
+      break match e.kind() {
+        io::ErrorKind::UnexpectedEof => "stream ended".to_owned(),
+        _ => format!("couldn't read stream: {:?}", e),
+      };
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    let wanted = {
+      let subscriber = shared.subscriber.lock().expect("poisoned lock?");
+      subscriber.as_ref().is_some_and(|s| len <= s.limit.get())
+    };
+    if !wanted {
+      debug_event!(bytes = len, "stdio skipped packet nobody could take");
+      match io::copy(&mut (&mut reader).take(len as u64), &mut io::sink()) {
+        Ok(skipped) if skipped == len as u64 => continue,
+        _ => break "stream ended partway through a packet".to_owned(),
+      }
+    }
+    let mut bytes = vec![0; len];
+    if reader.read_exact(&mut bytes).is_err() {
+      break "stream ended partway through a packet".to_owned();
+    }
+    let mut subscriber = shared.subscriber.lock().expect("poisoned lock?");
+    // the listener might've gone away while the packet was being read
+    if let Some(s) = subscriber.as_ref() {
+      debug_event!(path = %s.path, bytes = bytes.len(), "stdio received packet");
+      if !s.inbox.push(Source::listening_on(s.path.clone()), bytes) {
+        *subscriber = None;
+      }
+    }
+  };
+  debug_event!(reason = %ended, "stdio stream stopped");
+  *shared.ended.lock().expect("poisoned lock?") = Some(ended);
+}
+
+/// Sends and receives packets over a pair of byte streams, e.g. stdin and stdout, so meshers can be chained through
+/// anything that carries bytes both ways, like SSH (`ssh host mesherd --stdio CONFIG`) or a container's standard
+/// streams.
+///
+/// Paths look like `stdio:name`, where `stdio:-` is the process's own stdin and stdout, and other names are streams
+/// [attached](#method.attach) by the program, e.g. a child process's pipes.
+/// Sending writes the packet to the stream's writer, and listening reads from its reader; each packet is a 4-byte
+/// big-endian length followed by that many bytes, the same as in [TCP](struct.TCP.html) batches.
+/// Packets that arrive while nothing's listening on the stream are dropped, as are ones over the
+/// [size limit](../mesher/struct.Mesher.html#method.set_max_packet_size).
+///
+/// Streams are shared by every transport in the process, but only one can listen on each at a time; the others fail
+/// with [`ListenFailure`](../mesher/fail/enum.MesherFail.html#variant.ListenFailure).
+/// Stopping listening, or dropping the transport, frees the stream up for another one, e.g. a replacement mesher after
+/// reloading its config.
+/// Each stream is read by its own thread, which keeps going until the stream ends, after which its status is
+/// [`Failed`](../mesher/enum.ListenStatus.html#variant.Failed).
+///
+/// Nothing else should write to stdout while `stdio:-` is in use, e.g. logging, since it would be read as packets on the
+/// other end.
+pub struct Stdio {
+  id: u64,
+  scheme: String,
+  inbox: Inbox,
+  receiver: Receiver<(Source, Vec<u8>)>,
+  listening: Vec<Path>,
+  limit: SizeLimit,
+}
+
+impl Stdio {
+  /// Attaches a pair of streams under the given name, so paths like `stdio:name` read packets from `reader` and write
+  /// them to `writer`.
+  ///
+  /// Fails with [`SetupFailure`](../mesher/fail/enum.MesherFail.html#variant.SetupFailure) if the name's already in use,
+  /// including `-`, which is always stdin and stdout.
+  pub fn attach(
+    name: &str,
+    reader: impl Read + Send + 'static,
+    writer: impl Write + Send + 'static,
+  ) -> fail::Result<()> {
+    let mut streams = STREAMS.lock().expect("poisoned lock?");
+    if name == STD_STREAM || streams.contains_key(name) {
+      return Err(fail::MesherFail::SetupFailure(format!(
+        "a stream is already attached as {}",
+        name
+      )));
+    }
+    streams.insert(name.to_owned(), Stream::new(Box::new(reader), Box::new(writer)));
+    Ok(())
+  }
+
+  /// Detaches the streams with the given name, returning whether there were any.
+  ///
+  /// Anything listening on them stops getting packets, and sending to them fails from then on.
+  /// The reader isn't dropped until its thread finishes, which is when it next returns from reading, if it's being read.
+  pub fn detach(name: &str) -> bool {
+    match STREAMS.lock().expect("poisoned lock?").remove(name) {
+      Some(stream) => {
+        *stream.shared.subscriber.lock().expect("poisoned lock?") = None;
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Stops listening on the given stream, if this transport was the one listening on it.
+  fn unsubscribe(&self, name: &str) {
+    if let Ok(shared) = with_stream(name, |stream| Ok(stream.shared.clone())) {
+      let mut subscriber = shared.subscriber.lock().expect("poisoned lock?");
+      if subscriber.as_ref().is_some_and(|s| s.transport == self.id) {
+        *subscriber = None;
+      }
+    }
+  }
+}
+
+impl Transport for Stdio {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    let (inbox, receiver) = Inbox::new();
+    Ok(Stdio {
+      id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+      scheme: scheme.to_owned(),
+      inbox,
+      receiver,
+      listening: vec![],
+      limit: SizeLimit::new(),
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "stdio"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    self.limit.check(blob.len())?;
+    // lengths are 4 bytes, so anything bigger can't be sent even if the limit allows it
+    if blob.len() > u32::MAX as usize {
+      return Err(fail::MesherFail::PacketTooLarge(blob.len(), u32::MAX as usize));
+    }
+    let writer = with_stream(path.location(), |stream| Ok(stream.writer.clone()))?;
+    let mut writer = writer.lock().expect("poisoned lock?");
+    writer
+      .write_all(&(blob.len() as u32).to_be_bytes())
+      .and_then(|_| writer.write_all(blob))
+      .and_then(|_| writer.flush())
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to write to {}: {:?}", path, e)))?;
+    debug_event!(path = %path, bytes = blob.len(), "stdio sent packet");
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    if self.listening.iter().any(|p| p.location() == path.location()) {
+      return Ok(());
+    }
+    let subscriber = Subscriber {
+      transport: self.id,
+      path: path.clone(),
+      inbox: self.inbox.clone(),
+      limit: self.limit.clone(),
+    };
+    with_stream(path.location(), |stream| {
+      {
+        let mut current = stream.shared.subscriber.lock().expect("poisoned lock?");
+        if current.is_some() {
+          return Err(fail::MesherFail::ListenFailure(format!(
+            "something else is already listening on {}",
+            path
+          )));
+        }
+        *current = Some(subscriber);
+      }
+      if let Some(reader) = stream.reader.take() {
+        let shared = stream.shared.clone();
+        let started = Builder::new()
+          .name(format!("stdio {} reader", path.location()))
+          .spawn(move || read_packets(reader, shared));
+        if let Err(e) = started {
+          *stream.shared.subscriber.lock().expect("poisoned lock?") = None;
+          return Err(fail::MesherFail::ListenFailure(format!(
+            "Failed to start reading {}: {:?}",
+            path, e
+          )));
+        }
+      }
+      Ok(())
+    })?;
+    self.listening.push(path.clone());
+    Ok(())
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    let before = self.listening.len();
+    self.listening.retain(|p| p.location() != path.location());
+    if self.listening.len() == before {
+      return Err(fail::MesherFail::NotListening(path.to_string()));
+    }
+    self.unsubscribe(path.location());
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.receive_up_to(usize::MAX)
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    Ok(self.inbox.take(&self.receiver, max))
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self
+      .listening
+      .iter()
+      .map(|path| {
+        let ended = with_stream(path.location(), |stream| {
+          Ok(stream.shared.ended.lock().expect("poisoned lock?").clone())
+        });
+        let status = match ended {
+          Ok(None) => ListenStatus::Listening,
+          Ok(Some(why)) => ListenStatus::Failed(why),
+          Err(_) => ListenStatus::Failed("stream detached".to_owned()),
+        };
+        (path.clone(), status)
+      })
+      .collect()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.limit.set(max);
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.inbox.set_max(max);
+  }
+
+  fn close(&mut self, _timeout: Duration) -> fail::Result<()> {
+    // the reading threads carry on, for whatever listens next
+    for path in self.listening.drain(..).collect::<Vec<_>>() {
+      self.unsubscribe(path.location());
+    }
+    Ok(())
+  }
+}
+
+impl Drop for Stdio {
+  fn drop(&mut self) {
+    for path in &self.listening {
+      self.unsubscribe(path.location());
+    }
+  }
+}
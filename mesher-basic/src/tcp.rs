@@ -1,83 +1,439 @@
 use mesher::prelude::*;
 
 use std::{
-  io::prelude::*,
-  net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
-  sync::mpsc::{channel, Receiver, Sender},
-  thread::Builder,
+  collections::HashMap,
+  io::{prelude::*, BufWriter, ErrorKind},
+  net::{SocketAddr, TcpListener, TcpStream},
+  sync::mpsc::Receiver,
+  thread::sleep,
+  time::Duration,
 };
 
-fn socket_addr_from_string(scheme: &str, path: String) -> fail::Result<SocketAddr> {
-  let (_, path) = path.split_at(scheme.len() + 1);
-  let get_path_fail = || fail::MesherFail::InvalidURL(format!("not a valid socket address format: {}", path));
-  path
-    .to_socket_addrs()
-    .map_err(|_| get_path_fail())?
-    .next()
-    .ok_or_else(get_path_fail)
+use crate::{
+  nat, poll_interval, socket_addrs, stop_all, wait_for_stop, Backoff, Inbox, Listener, Restarts, SizeLimit, StatusCell,
+  POLL_INTERVAL,
+};
+
+use mesher::ListenStatus;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+/// How long sending waits to connect, and then to write the packet, unless the path's options say otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Starts a connection carrying a batch of packets, rather than just one.
+///
+/// It's never the first byte of a packet, since no packet version is 255, so listeners can tell the two apart.
+/// After it, each packet is sent as a 4-byte big-endian length followed by that many bytes.
+const BATCH_MARKER: u8 = 0xFF;
+
+/// Reads a batch's packets, after its marker, and passes them along until the connection ends.
+///
+/// Returns false if the transport's gone, i.e. the listener should stop.
+fn read_batch(conn: &mut impl Read, source: &Source, inbox: &Inbox, max: usize) -> bool {
+  loop {
+    let mut len = [0; 4];
+    if conn.read_exact(&mut len).is_err() {
+      return true;
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max {
+      // the rest of the connection can't be trusted to line up with the frames, so give up on it
+      debug_event!(max, "TCP dropped oversized packet in batch");
+      return true;
+    }
+    let mut bytes = vec![0; len];
+    if conn.read_exact(&mut bytes).is_err() {
+      return true;
+    }
+    debug_event!(bytes = bytes.len(), "TCP received batched packet");
+    if !inbox.push(source.clone(), bytes) {
+      return false;
+    }
+  }
 }
 
-fn listen(scheme: &str, addr: SocketAddr, sender: Sender<Vec<u8>>) -> fail::Result<()> {
-  let tcp_listen = TcpListener::bind(addr)
-    .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to bind listener: {:?}", e)))?;
-
-  let thread_code = move || {
-    for conn in tcp_listen.incoming() {
-      let mut conn = match conn {
-        Ok(c) => c,
-        Err(_) => continue,
-      };
-      let mut bytes = vec![];
-      if conn.read_to_end(&mut bytes).is_err() {
+/// Binds a nonblocking listener, which, if `dual_stack` is set and it's on an IPv6 address, accepts IPv4 connections
+/// too.
+fn bind(addr: SocketAddr, dual_stack: bool) -> std::io::Result<TcpListener> {
+  let listener = if dual_stack && addr.is_ipv6() {
+    let sock = Socket::new(Domain::ipv6(), Type::stream(), Some(Protocol::tcp()))?;
+    sock.set_only_v6(false)?;
+    // the same as std does, so rebinding doesn't have to wait out old connections
+    #[cfg(unix)]
+    sock.set_reuse_address(true)?;
+    sock.bind(&SockAddr::from(addr))?;
+    sock.listen(128)?;
+    sock.into_tcp_listener()
+  } else {
+    TcpListener::bind(addr)?
+  };
+  listener.set_nonblocking(true)?;
+  Ok(listener)
+}
+
+/// Whether an error accepting a connection only affects that connection, rather than the whole listener.
+fn is_transient(e: &std::io::Error) -> bool {
+  matches!(
+    e.kind(),
+    ErrorKind::WouldBlock | ErrorKind::Interrupted | ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset
+  )
+}
+
+fn listen(
+  scheme: &str,
+  on: &Path,
+  addr: SocketAddr,
+  dual_stack: bool,
+  poll: Duration,
+  inbox: Inbox,
+  limit: SizeLimit,
+) -> fail::Result<Listener> {
+  let tcp_listen = bind(addr, dual_stack)
+    .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to bind listener on {}: {:?}", addr, e)))?;
+  debug_event!(scheme, addr = %addr, "TCP listening");
+  let bound = tcp_listen.local_addr().unwrap_or(addr);
+  // dropped along with the thread, which takes the mapping down
+  let mut mapping = nat::map_for(on, nat::Protocol::Tcp, bound)?;
+
+  let source_path = on.clone();
+  let mut tcp_listen = Some(tcp_listen);
+  let mut backoff = Backoff::new();
+  let thread_code = move |stop, status: StatusCell| loop {
+    if let Some(mapping) = &mut mapping {
+      mapping.keep_up(nat::MAP_TIMEOUT);
+    }
+    let listener = match &tcp_listen {
+      Some(l) => l,
+      None => {
+        if wait_for_stop(&stop, backoff.next()) {
+          return;
+        }
+        if let Ok(l) = bind(addr, dual_stack) {
+          debug_event!(addr = %addr, "TCP listener rebound");
+          status.set(ListenStatus::Listening);
+          backoff.reset();
+          tcp_listen = Some(l);
+        }
+        continue;
+      }
+    };
+    let (conn, from) = match listener.accept() {
+      Ok(accepted) => accepted,
+      Err(e) if is_transient(&e) => {
+        // accepting doesn't block, so this is how long a new connection can wait to be noticed
+        if e.kind() == ErrorKind::WouldBlock && wait_for_stop(&stop, poll) {
+          return;
+        }
         continue;
       }
-      if sender.send(bytes).is_err() {
+      Err(_e) => {
+        // whatever went wrong, e.g. the address going away, starting over with a new listener might fix it
+        debug_event!(addr = %addr, error = %_e, "TCP listener failed, rebinding");
+        status.set(ListenStatus::Reconnecting);
+        tcp_listen = None;
+        continue;
+      }
+    };
+    // on some platforms, accepted connections inherit the listener's nonblocking-ness
+    let mut conn = conn;
+    if conn.set_nonblocking(false).is_err() {
+      continue;
+    }
+    let max = limit.get();
+    let mut first = [0];
+    match conn.read(&mut first) {
+      Ok(1) => (),
+      _ => continue,
+    }
+    let source = Source::listening_on(source_path.clone()).from_remote(from.to_string());
+    if first[0] == BATCH_MARKER {
+      if !read_batch(&mut conn, &source, &inbox, max) {
         return;
       }
+      continue;
+    }
+    // reading one byte past the limit is enough to tell the packet's too big, without buffering the rest of it
+    let mut bytes = first.to_vec();
+    if conn.take(max as u64).read_to_end(&mut bytes).is_err() {
+      continue;
+    }
+    if bytes.len() > max {
+      debug_event!(addr = %addr, max, "TCP dropped oversized packet");
+      continue;
+    }
+    debug_event!(addr = %addr, from = %from, bytes = bytes.len(), "TCP received packet");
+    if !inbox.push(source, bytes) {
+      return;
     }
   };
 
-  Builder::new()
-    .name(format!("TCP {}:{} listener", scheme, addr))
-    .spawn(thread_code)
-    .map_err(|e| fail::MesherFail::SetupFailure(format!("Faield to start TCP {}: listener: {:?}", scheme, e)))?;
+  Listener::spawn(on, format!("TCP {}:{} listener", scheme, addr), thread_code)
+}
+
+/// Tries each address in turn until one connects.
+fn connect_any(addrs: &[SocketAddr], timeout: Option<Duration>) -> std::io::Result<TcpStream> {
+  let mut last_err = None;
+  for addr in addrs {
+    let attempt = match timeout {
+      Some(timeout) => TcpStream::connect_timeout(addr, timeout),
+      None => TcpStream::connect(addr),
+    };
+    match attempt {
+      Ok(s) => return Ok(s),
+      Err(e) => last_err = Some(e),
+    }
+  }
+  Err(last_err.unwrap_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no addresses to connect to")))
+}
 
-  Ok(())
+fn connect(path: &Path, retries: u32, timeout: Option<Duration>) -> fail::Result<TcpStream> {
+  let mut tries_left = retries;
+  loop {
+    // resolved again for every attempt, so a peer whose address changes, e.g. with dynamic DNS, is found at the new one
+    let addrs = socket_addrs(path)?;
+    match connect_any(&addrs, timeout) {
+      Ok(s) => return Ok(s),
+      Err(_) if tries_left > 0 => {
+        tries_left -= 1;
+        sleep(Duration::from_millis(100));
+      }
+      Err(e) => {
+        return Err(fail::MesherFail::SendFailure(format!(
+          "Failed to establish TCP connection: {:?}",
+          e
+        )))
+      }
+    }
+  }
 }
 
+/// Whichever of the two statuses is more worrying.
+fn worse(a: ListenStatus, b: ListenStatus) -> ListenStatus {
+  match (&a, &b) {
+    (ListenStatus::Failed(_), _) => a,
+    (_, ListenStatus::Failed(_)) => b,
+    (ListenStatus::Reconnecting, _) => a,
+    _ => b,
+  }
+}
+
+/// Connects to send along the path, with the retries and timeouts its options ask for.
+fn open(path: &Path) -> fail::Result<TcpStream> {
+  let options = path.options();
+  let retries = options.parse_value("retries")?.unwrap_or(0);
+  let timeout = options.duration("timeout")?.unwrap_or(DEFAULT_TIMEOUT);
+  let nonzero = |t: Duration| Some(t).filter(|t| !t.is_zero());
+  let connect_timeout = nonzero(options.duration("connect_timeout")?.unwrap_or(timeout));
+  let write_timeout = nonzero(options.duration("write_timeout")?.unwrap_or(timeout));
+  let out = connect(path, retries, connect_timeout)?;
+  out
+    .set_write_timeout(write_timeout)
+    .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to set write timeout: {:?}", e)))?;
+  Ok(out)
+}
+
+/// Sends and receives packets over TCP, one connection per packet.
+///
+/// Paths look like `tcp:host:port`.
+/// Listening binds every address the host resolves to, e.g. both `127.0.0.1` and `[::1]` for `localhost` on most
+/// machines, and fails if any of them can't be bound.
+/// With the `dual_stack` option, listening on an IPv6 address accepts IPv4 connections too, so `tcp:[::]:18540?dual_stack`
+/// listens on every address, of both kinds; without it, it's up to the OS.
+/// Listeners check for new connections every 50ms, so that's how long a packet can wait before it's read; the `poll`
+/// option changes that, e.g. `tcp:[::1]:18540?poll=5ms`, trading latency for how often the listener thread wakes up.
+/// Sending resolves the host again every time, and tries each of its addresses in turn until one connects.
+/// Nothing's cached here, so how fresh the addresses are is up to the system's resolver, which generally follows the
+/// records' TTLs.
+/// When sending, the `retries` option sets how many more times to try connecting if the first attempt fails, e.g. `tcp:[::1]:18540?retries=3`.
+///
+/// Sending also gives up if connecting, or writing the packet once connected, takes longer than 10 seconds, so a hung
+/// destination can't hold up the mesher forever.
+/// The `connect_timeout` and `write_timeout` options change those, and `timeout` changes both, e.g.
+/// `tcp:[::1]:18540?timeout=2s`; a timeout of `0` waits forever.
+/// Each address tried gets the whole connect timeout.
+///
+/// Batches of packets are sent over one connection if the path has the `batch` option, e.g.
+/// `tcp:[::1]:18540?batch`, and one connection per packet otherwise.
+/// Only use it for destinations running a version of this transport which understands batches.
+/// The timeouts apply to the batch as a whole, and if any packet in it is too big, none of them are sent.
+///
+/// With the `map` option, e.g. `tcp:0.0.0.0:18540?map`, listening asks the router to forward the port with NAT-PMP or
+/// UPnP, so nodes behind a NAT can be sent to, and keeps the mapping up until it stops; see [`UDP`](struct.UDP.html)
+/// for the options that go with it.
+///
+/// If a listener's thread dies, e.g. by panicking, it's restarted the next time the mesher receives, with a growing wait
+/// between attempts if it keeps dying; if restarting fails, receiving fails with
+/// [`TransportDead`](../../mesher/fail/enum.MesherFail.html#variant.TransportDead), and it's tried again later.
 pub struct TCP {
-  sender: Sender<Vec<u8>>,
-  receiver: Receiver<Vec<u8>>,
+  inbox: Inbox,
+  receiver: Receiver<(Source, Vec<u8>)>,
   scheme: String,
+  listeners: HashMap<String, Vec<Listener>>,
+  restarts: Restarts,
+  limit: SizeLimit,
+}
+
+impl TCP {
+  /// Restarts the listeners on any path where one of the threads has died, if it's time to try again.
+  fn restart_dead(&mut self) -> fail::Result<()> {
+    let dead: Vec<Path> = self
+      .listeners
+      .values()
+      .filter(|listeners| listeners.iter().any(Listener::is_dead))
+      .map(|listeners| listeners[0].path().clone())
+      .collect();
+    let mut result = Ok(());
+    for path in dead {
+      if !self.restarts.due(path.location()) {
+        continue;
+      }
+      debug_event!(path = %path, "TCP listener thread died, restarting it");
+      let old = self.listeners.remove(path.location()).expect("dead listener vanished");
+      // the other addresses' threads are still holding their sockets
+      for listener in &old {
+        listener.halt(POLL_INTERVAL * 4);
+      }
+      if let Err(e) = self.listen(&path) {
+        // kept around so it's still reported, and tried again later
+        self.listeners.insert(path.location().to_owned(), old);
+        result = Err(fail::MesherFail::TransportDead(format!(
+          "listener on {} died, and restarting it failed: {:?}",
+          path, e
+        )));
+      }
+    }
+    result
+  }
 }
 
 impl Transport for TCP {
   fn new(scheme: &str) -> fail::Result<Self> {
-    let (sender, receiver) = channel();
+    let (inbox, receiver) = Inbox::new();
     Ok(TCP {
       scheme: scheme.to_string(),
-      sender,
+      inbox,
       receiver,
+      listeners: HashMap::new(),
+      restarts: Restarts::default(),
+      limit: SizeLimit::new(),
     })
   }
 
-  fn send(&mut self, path: String, blob: Vec<u8>) -> fail::Result<()> {
-    let sock = socket_addr_from_string(&self.scheme, path)?;
-    let mut out = TcpStream::connect(sock)
-      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to establish TCP connection: {:?}", e)))?;
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "TCP"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    self.limit.check(blob.len())?;
+    let mut out = open(path)?;
     out
-      .write_all(&blob)
+      .write_all(blob)
       .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))?;
+    debug_event!(path = %path, bytes = blob.len(), "TCP sent packet");
+    Ok(())
+  }
+
+  fn send_batch(&mut self, path: &Path, blobs: &[&[u8]]) -> fail::Result<()> {
+    if !path.options().has("batch") {
+      let mut result = Ok(());
+      for blob in blobs {
+        if let Err(e) = self.send(path, blob) {
+          result = result.and(Err(e));
+        }
+      }
+      return result;
+    }
+    for blob in blobs {
+      self.limit.check(blob.len())?;
+      // frame lengths are 4 bytes, so anything bigger can't be batched even if the limit allows it
+      if blob.len() > u32::MAX as usize {
+        return Err(fail::MesherFail::PacketTooLarge(blob.len(), u32::MAX as usize));
+      }
+    }
+    let send_err = |e| fail::MesherFail::SendFailure(format!("Failed to send batch: {:?}", e));
+    let mut out = BufWriter::new(open(path)?);
+    out.write_all(&[BATCH_MARKER]).map_err(send_err)?;
+    for blob in blobs {
+      out.write_all(&(blob.len() as u32).to_be_bytes()).map_err(send_err)?;
+      out.write_all(blob).map_err(send_err)?;
+    }
+    out.flush().map_err(send_err)?;
+    debug_event!(path = %path, packets = blobs.len(), "TCP sent batch");
     Ok(())
   }
 
-  fn listen(&mut self, path: String) -> fail::Result<()> {
-    let sock = socket_addr_from_string(&self.scheme, path)?;
-    listen(&self.scheme, sock, self.sender.clone())?;
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    if self.listeners.contains_key(path.location()) {
+      return Ok(());
+    }
+    let dual_stack = path.options().has("dual_stack");
+    let poll = poll_interval(path)?;
+    // if any address fails, the listeners already started are dropped, which stops them
+    let listeners = socket_addrs(path)?
+      .into_iter()
+      .map(|addr| {
+        listen(
+          &self.scheme,
+          path,
+          addr,
+          dual_stack,
+          poll,
+          self.inbox.clone(),
+          self.limit.clone(),
+        )
+      })
+      .collect::<fail::Result<_>>()?;
+    self.listeners.insert(path.location().to_owned(), listeners);
     Ok(())
   }
 
-  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
-    Ok(self.receiver.try_iter().collect())
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    self.restarts.forget(path.location());
+    // dropping the listener tells its thread to stop
+    self
+      .listeners
+      .remove(path.location())
+      .map(|_| ())
+      .ok_or_else(|| fail::MesherFail::NotListening(path.to_string()))
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.receive_up_to(usize::MAX)
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    // anything the dead thread received is still waiting in the inbox for next time
+    self.restart_dead()?;
+    Ok(self.inbox.take(&self.receiver, max))
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self
+      .listeners
+      .values()
+      .filter_map(|listeners| {
+        // a path's only as healthy as its least healthy address
+        let mut statuses = listeners.iter().map(Listener::status);
+        let first = statuses.next()?;
+        Some(statuses.fold(first, |(path, a), (_, b)| (path, worse(a, b))))
+      })
+      .collect()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.limit.set(max);
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.inbox.set_max(max);
+  }
+
+  fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    self.restarts = Restarts::default();
+    stop_all(self.listeners.drain().flat_map(|(_, listeners)| listeners), timeout)
   }
 }
@@ -0,0 +1,375 @@
+use mesher::prelude::*;
+
+use std::{
+  fs,
+  io::prelude::*,
+  net::{SocketAddr, TcpStream},
+  time::Duration,
+};
+
+use crate::hidden::{free_port, load_key, save_key, saved_address, Control, Forwarded, DEFAULT_TIMEOUT};
+
+use mesher::ListenStatus;
+
+/// Where Tor's SOCKS proxy and control port usually are.
+const DEFAULT_PROXY: &str = "127.0.0.1:9050";
+const DEFAULT_CONTROL: &str = "127.0.0.1:9051";
+
+/// Sends a command to Tor's control port and returns the lines of its reply, without their status codes, failing if it
+/// wasn't a success.
+fn command(control: &mut Control, line: &str) -> Result<Vec<String>, String> {
+  control.send(line)?;
+  let mut reply = vec![];
+  loop {
+    let line = control.line()?;
+    if line.len() < 4 {
+      return Err(format!("Tor sent a malformed reply: {:?}", line));
+    }
+    let (code, rest) = line.split_at(3);
+    if code != "250" {
+      return Err(format!("Tor refused: {}", line));
+    }
+    reply.push(rest[1..].to_owned());
+    // the last line of a reply has a space after the code, and the others a dash
+    if rest.starts_with(' ') {
+      return Ok(reply);
+    }
+  }
+}
+
+/// Finds `key=value` in a reply, returning the value.
+fn value<'r>(reply: &'r [String], key: &str) -> Option<&'r str> {
+  reply
+    .iter()
+    .find_map(|line| line.strip_prefix(key).and_then(|rest| rest.strip_prefix('=')))
+}
+
+/// Quotes a string for the control protocol.
+fn quoted(text: &str) -> String {
+  format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Reads a quoted string at the start of `text`, e.g. a cookie file's path.
+fn unquoted(text: &str) -> Option<String> {
+  let mut chars = text.strip_prefix('"')?.chars();
+  let mut out = String::new();
+  loop {
+    match chars.next()? {
+      '"' => return Some(out),
+      '\\' => out.push(chars.next()?),
+      c => out.push(c),
+    }
+  }
+}
+
+/// Logs in to the control port, with the password if there is one, or however else Tor says it can be done.
+///
+/// Cookie authentication reads the cookie from the file Tor names, so it only works for a Tor on the same machine, run
+/// by a user whose files this process can read.
+fn authenticate(control: &mut Control, password: Option<&str>) -> Result<(), String> {
+  let line = match password {
+    Some(password) => format!("AUTHENTICATE {}", quoted(password)),
+    None => {
+      let info = command(control, "PROTOCOLINFO 1")?;
+      let auth = info
+        .iter()
+        .find_map(|l| l.strip_prefix("AUTH "))
+        .ok_or("Tor didn't say how to log in")?;
+      let methods = auth
+        .strip_prefix("METHODS=")
+        .and_then(|m| m.split(' ').next())
+        .unwrap_or("");
+      let methods: Vec<_> = methods.split(',').collect();
+      if methods.contains(&"NULL") {
+        "AUTHENTICATE".to_owned()
+      } else if methods.contains(&"COOKIE") {
+        let file = auth
+          .find("COOKIEFILE=")
+          .and_then(|at| unquoted(&auth[at + "COOKIEFILE=".len()..]))
+          .ok_or("Tor didn't say where its cookie is")?;
+        let cookie = fs::read(&file).map_err(|e| format!("couldn't read Tor's cookie from {}: {}", file, e))?;
+        let hex: String = cookie.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("AUTHENTICATE {}", hex)
+      } else {
+        return Err("Tor needs a password to log in; give it with the password_env option".to_owned());
+      }
+    }
+  };
+  command(control, &line).map(|_| ())
+}
+
+/// Has Tor set up an onion service forwarding `port` to `local_port` on the loopback address, returning the control
+/// connection, which has to stay open for the service to stay up, and the service's address.
+///
+/// With a key file, the service uses the key in it, or a new one which is saved there, so it keeps its address.
+fn add_onion(
+  control_addr: &str,
+  timeout: Duration,
+  password: Option<&str>,
+  key_file: Option<&str>,
+  port: u16,
+  local_port: u16,
+) -> Result<(TcpStream, String), String> {
+  let mut control = Control::connect("Tor's control port", control_addr, timeout)?;
+  authenticate(&mut control, password)?;
+  let saved = match key_file {
+    Some(file) => load_key(file)?,
+    None => None,
+  };
+  let key = saved.as_ref().map_or("NEW:ED25519-V3", |(_, key)| key.as_str());
+  // there's nowhere to keep a new key without a file, so Tor might as well not send it
+  let flags = if key_file.is_none() { " Flags=DiscardPK" } else { "" };
+  let reply = command(
+    &mut control,
+    &format!("ADD_ONION {}{} Port={},127.0.0.1:{}", key, flags, port, local_port),
+  )?;
+  let id = value(&reply, "ServiceID").ok_or("Tor didn't say what the onion address is")?;
+  let address = format!("{}.onion", id);
+  if let (Some(file), None) = (key_file, &saved) {
+    let key = value(&reply, "PrivateKey").ok_or("Tor didn't send the new service's key")?;
+    save_key(file, &address, key)?;
+  }
+  Ok((control.keep_alive(), address))
+}
+
+/// What a SOCKS5 reply code means.
+fn socks_error(code: u8) -> &'static str {
+  match code {
+    1 => "general failure",
+    2 => "not allowed",
+    3 => "network unreachable",
+    4 => "host unreachable",
+    5 => "connection refused",
+    6 => "timed out",
+    7 => "command not supported",
+    8 => "address type not supported",
+    0xF0 => "onion service descriptor not found",
+    0xF1 => "onion service descriptor invalid",
+    0xF2 => "onion service introduction failed",
+    0xF3 => "onion service rendezvous failed",
+    0xF4 => "onion service needs client authorization",
+    0xF5 => "onion service client authorization is wrong",
+    0xF6 => "not a valid onion address",
+    0xF7 => "onion service introduction timed out",
+    _ => "unknown error",
+  }
+}
+
+/// Connects to `host:port` through Tor's SOCKS proxy, leaving the name for Tor to resolve, which it has to for onion
+/// addresses.
+fn socks_connect(proxy: &str, host: &str, port: u16, timeout: Duration) -> Result<TcpStream, String> {
+  let addr: SocketAddr = proxy
+    .parse()
+    .map_err(|_| format!("not a valid proxy address: {}", proxy))?;
+  let mut conn =
+    TcpStream::connect_timeout(&addr, timeout).map_err(|e| format!("couldn't connect to Tor at {}: {}", addr, e))?;
+  conn
+    .set_read_timeout(Some(timeout))
+    .and_then(|_| conn.set_write_timeout(Some(timeout)))
+    .map_err(|e| format!("couldn't set timeouts: {}", e))?;
+  let io_err = |e: std::io::Error| format!("couldn't talk to Tor: {}", e);
+  if host.len() > 255 {
+    return Err(format!("{} is too long a name", host));
+  }
+
+  // version 5, offering only "no authentication"
+  conn.write_all(&[5, 1, 0]).map_err(io_err)?;
+  let mut chosen = [0; 2];
+  conn.read_exact(&mut chosen).map_err(io_err)?;
+  if chosen != [5, 0] {
+    return Err("Tor's proxy wants authentication".to_owned());
+  }
+
+  let mut request = vec![5, 1, 0, 3, host.len() as u8];
+  request.extend_from_slice(host.as_bytes());
+  request.extend_from_slice(&port.to_be_bytes());
+  conn.write_all(&request).map_err(io_err)?;
+  let mut reply = [0; 4];
+  conn.read_exact(&mut reply).map_err(io_err)?;
+  if reply[1] != 0 {
+    return Err(format!("Tor couldn't connect to {}: {}", host, socks_error(reply[1])));
+  }
+  // the address Tor bound, which is meaningless here, but has to be read past
+  let bound = match reply[3] {
+    1 => 4,
+    4 => 16,
+    3 => {
+      let mut len = [0];
+      conn.read_exact(&mut len).map_err(io_err)?;
+      len[0] as usize
+    }
+    _ => return Err("Tor sent a malformed reply".to_owned()),
+  };
+  let mut skipped = vec![0; bound + 2];
+  conn.read_exact(&mut skipped).map_err(io_err)?;
+  Ok(conn)
+}
+
+/// Splits a path to send to into its host and port.
+fn host_port(path: &Path) -> fail::Result<(&str, u16)> {
+  let invalid = || {
+    fail::MesherFail::InvalidURL(format!(
+      "Tor paths to send to look like tor:HOST.onion:PORT, not {}",
+      path
+    ))
+  };
+  let (host, port) = path.location().rsplit_once(':').ok_or_else(invalid)?;
+  let port = port.parse().map_err(|_| invalid())?;
+  if host.is_empty() {
+    return Err(invalid());
+  }
+  Ok((host, port))
+}
+
+/// Sends packets to Tor onion services, and listens as one, so neither end learns where the other is.
+///
+/// This needs a Tor running nearby, with its SOCKS proxy and control port turned on, e.g. with `ControlPort 9051` and
+/// `CookieAuthentication 1` in its torrc.
+///
+/// Paths to send to look like `tor:HOST.onion:PORT`, and packets are sent through Tor's SOCKS proxy, one connection per
+/// packet, like [TCP](struct.TCP.html).
+/// The proxy's at `127.0.0.1:9050` unless the `proxy` option says otherwise, e.g.
+/// `tor:abc...xyz.onion:18540?proxy=127.0.0.1:9150` for the Tor Browser's.
+/// Any other host works too, through an exit node, but that only hides the sender.
+///
+/// Paths to listen on look like `tor:PORT`, for the port other nodes send to.
+/// Listening has Tor set up an onion service, through its control port at `127.0.0.1:9051`, or wherever the `control`
+/// option says, which forwards to a TCP listener on the loopback address.
+/// That's on a free port, or the one in the `local` option.
+/// Logging in to the control port uses Tor's cookie if it has one, or a password, if the `password_env` option names
+/// the environment variable it's in, e.g. `tor:18540?password_env=TOR_PASSWORD`.
+/// Paths are logged, and reported as where packets came from, so the password itself can't be given in one.
+/// The service lasts until listening stops, and gets a new address every time, unless the `key` option names a file to
+/// keep its key in, e.g. `tor:18540?key=node.onion`, which is created if it doesn't exist;
+/// [`Tor::address`](#method.address) reads the address back out of it, to give to whoever should send here.
+///
+/// Everything waits up to 60 seconds for Tor, since building circuits can be slow; the `timeout` option changes that,
+/// e.g. `tor:18540?timeout=2m`.
+/// Packets received are reported as arriving on the path being listened on, with no remote address, since there isn't
+/// one to know.
+pub struct Tor {
+  scheme: String,
+  forwarded: Forwarded,
+  limit: crate::SizeLimit,
+}
+
+impl Tor {
+  /// The onion address saved in a key file, as made by listening with the `key` option, if there's one there.
+  pub fn address(key_file: &str) -> Option<String> {
+    saved_address(key_file)
+  }
+}
+
+impl Transport for Tor {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Tor {
+      scheme: scheme.to_owned(),
+      forwarded: Forwarded::new()?,
+      limit: crate::SizeLimit::new(),
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "Tor"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    self.limit.check(blob.len())?;
+    let (host, port) = host_port(path)?;
+    let options = path.options();
+    let timeout = options.duration("timeout")?.unwrap_or(DEFAULT_TIMEOUT);
+    let proxy = options.get("proxy").unwrap_or(DEFAULT_PROXY);
+    let mut conn = socks_connect(proxy, host, port, timeout).map_err(fail::MesherFail::SendFailure)?;
+    conn
+      .write_all(blob)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))?;
+    debug_event!(path = %path, bytes = blob.len(), "Tor sent packet");
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    if self.forwarded.is_listening(path) {
+      return Ok(());
+    }
+    let port = path
+      .location()
+      .parse()
+      .map_err(|_| fail::MesherFail::InvalidURL(format!("Tor paths to listen on look like tor:PORT, not {}", path)))?;
+    let options = path.options();
+    let timeout = options.duration("timeout")?.unwrap_or(DEFAULT_TIMEOUT);
+    let local_port = match options.parse_value("local")? {
+      Some(port) => port,
+      None => {
+        free_port().map_err(|e| fail::MesherFail::ListenFailure(format!("couldn't find a free local port: {}", e)))?
+      }
+    };
+    if options.has("password") {
+      return Err(fail::MesherFail::InvalidURL(
+        "Tor passwords can't go in paths; put it in an environment variable and name that with password_env".to_owned(),
+      ));
+    }
+    let password = match options.get("password_env") {
+      Some(var) => Some(std::env::var(var).map_err(|_| {
+        fail::MesherFail::ListenFailure(format!("the Tor password should be in ${}, which isn't set", var))
+      })?),
+      None => None,
+    };
+    let local = self.forwarded.listen_locally(local_port)?;
+    let added = add_onion(
+      options.get("control").unwrap_or(DEFAULT_CONTROL),
+      timeout,
+      password.as_deref(),
+      options.get("key"),
+      port,
+      local_port,
+    );
+    match added {
+      Ok((control, _address)) => {
+        debug_event!(path = %path, address = %_address, "Tor listening as onion service");
+        self.forwarded.add(path, local, vec![control]);
+        Ok(())
+      }
+      Err(e) => {
+        self.forwarded.abandon(&local);
+        Err(fail::MesherFail::ListenFailure(format!(
+          "couldn't set up an onion service for {}: {}",
+          path, e
+        )))
+      }
+    }
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    // closing the control connection takes the service down
+    self.forwarded.remove(path)
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.receive_up_to(usize::MAX)
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.forwarded.receive_up_to(max)
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self.forwarded.status()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.limit.set(max);
+    self.forwarded.set_max_packet_size(max);
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.forwarded.set_max_buffered(max);
+  }
+
+  fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    self.forwarded.close(timeout)
+  }
+}
@@ -0,0 +1,341 @@
+use mesher::prelude::*;
+
+use std::{
+  collections::HashMap,
+  io::ErrorKind,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+  sync::mpsc::Receiver,
+  time::Duration,
+};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::{
+  nat, poll_interval, socket_addr, stop_all, wait_for_stop, Backoff, Inbox, Listener, Restarts, SizeLimit, StatusCell,
+};
+
+use mesher::ListenStatus;
+
+/// The largest payload that fits in a single UDP datagram.
+const MAX_DATAGRAM: usize = 65507;
+
+/// How long sending waits to punch through to a listener behind a NAT, unless the path's options say otherwise.
+const DEFAULT_PUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn make_socket(addr: &SocketAddr) -> std::io::Result<Socket> {
+  let domain = match addr {
+    SocketAddr::V4(_) => Domain::ipv4(),
+    SocketAddr::V6(_) => Domain::ipv6(),
+  };
+  Socket::new(domain, Type::dgram(), Some(Protocol::udp()))
+}
+
+fn bind_listener(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+  let sock = make_socket(&addr)?;
+  if !addr.ip().is_multicast() {
+    sock.bind(&SockAddr::from(addr))?;
+    return Ok(sock.into_udp_socket());
+  }
+
+  // Several meshers on the same machine should all be able to sit on the same group.
+  sock.set_reuse_address(true)?;
+  #[cfg(unix)]
+  sock.set_reuse_port(true)?;
+  match addr.ip() {
+    IpAddr::V4(group) => {
+      sock.bind(&SockAddr::from(SocketAddr::new(
+        Ipv4Addr::UNSPECIFIED.into(),
+        addr.port(),
+      )))?;
+      sock.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+    }
+    IpAddr::V6(group) => {
+      sock.bind(&SockAddr::from(SocketAddr::new(
+        Ipv6Addr::UNSPECIFIED.into(),
+        addr.port(),
+      )))?;
+      sock.join_multicast_v6(&group, 0)?;
+    }
+  }
+  Ok(sock.into_udp_socket())
+}
+
+fn bind_sender(dest: &SocketAddr, ttl: u32) -> std::io::Result<UdpSocket> {
+  let sock = make_socket(dest)?;
+  let local: SocketAddr = match dest {
+    SocketAddr::V4(_) => {
+      sock.set_multicast_ttl_v4(ttl)?;
+      (Ipv4Addr::UNSPECIFIED, 0).into()
+    }
+    SocketAddr::V6(_) => {
+      sock.set_multicast_hops_v6(ttl)?;
+      (Ipv6Addr::UNSPECIFIED, 0).into()
+    }
+  };
+  sock.bind(&SockAddr::from(local))?;
+  Ok(sock.into_udp_socket())
+}
+
+/// Binds a listener that gives up on receiving every `poll`, to check whether it should stop.
+fn bind_polling(addr: SocketAddr, poll: Duration) -> std::io::Result<UdpSocket> {
+  let sock = bind_listener(addr)?;
+  sock.set_read_timeout(Some(poll))?;
+  Ok(sock)
+}
+
+/// Whether an error receiving is just a timeout, or an echo of an earlier send failing, rather than the socket breaking.
+fn is_transient(e: &std::io::Error) -> bool {
+  matches!(
+    e.kind(),
+    ErrorKind::WouldBlock
+      | ErrorKind::TimedOut
+      | ErrorKind::Interrupted
+      | ErrorKind::ConnectionReset
+      | ErrorKind::ConnectionRefused
+  )
+}
+
+fn listen(
+  scheme: &str,
+  on: &Path,
+  addr: SocketAddr,
+  poll: Duration,
+  inbox: Inbox,
+  limit: SizeLimit,
+) -> fail::Result<Listener> {
+  let mut registration = nat::Registration::for_path(on)?;
+  let udp_listen = bind_polling(addr, poll)
+    .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to bind listener: {:?}", e)))?;
+  debug_event!(scheme, addr = %addr, "UDP listening");
+  let bound = udp_listen.local_addr().unwrap_or(addr);
+  // dropped along with the thread, which takes the mapping down
+  let mut mapping = nat::map_for(on, nat::Protocol::Udp, bound)?;
+
+  let source_path = on.clone();
+  let thread_code = move |stop, status: StatusCell| {
+    let mut buf = vec![0; MAX_DATAGRAM];
+    let mut udp_listen = Some(udp_listen);
+    let mut backoff = Backoff::new();
+    loop {
+      if let Some(mapping) = &mut mapping {
+        mapping.keep_up(nat::MAP_TIMEOUT);
+      }
+      if let (Some(registration), Some(listener)) = (&mut registration, &udp_listen) {
+        registration.keep_up(listener);
+      }
+      let listener = match &udp_listen {
+        Some(l) => l,
+        None => {
+          if wait_for_stop(&stop, backoff.next()) {
+            return;
+          }
+          if let Ok(l) = bind_polling(addr, poll) {
+            debug_event!(addr = %addr, "UDP listener rebound");
+            status.set(ListenStatus::Listening);
+            backoff.reset();
+            udp_listen = Some(l);
+          }
+          continue;
+        }
+      };
+      let (len, from) = match listener.recv_from(&mut buf) {
+        Ok(got) => got,
+        Err(e) if is_transient(&e) => {
+          // receiving already waited out the poll interval if it timed out, and packets arriving now shouldn't wait
+          // another one
+          let timed_out = matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut);
+          if wait_for_stop(&stop, if timed_out { Duration::ZERO } else { poll }) {
+            return;
+          }
+          continue;
+        }
+        Err(_e) => {
+          // whatever went wrong, e.g. the address going away, starting over with a new socket might fix it
+          debug_event!(addr = %addr, error = %_e, "UDP listener failed, rebinding");
+          status.set(ListenStatus::Reconnecting);
+          udp_listen = None;
+          continue;
+        }
+      };
+      if let Some((kind, payload)) = nat::parse(&buf[..len]) {
+        if let Some(registration) = &mut registration {
+          registration.handle(listener, kind, payload, from);
+        }
+        continue;
+      }
+      if len > limit.get() {
+        debug_event!(addr = %addr, bytes = len, "UDP dropped oversized packet");
+        continue;
+      }
+      debug_event!(addr = %addr, from = %from, bytes = len, "UDP received packet");
+      let source = Source::listening_on(source_path.clone()).from_remote(from.to_string());
+      if !inbox.push(source, buf[..len].to_vec()) {
+        return;
+      }
+    }
+  };
+
+  Listener::spawn(on, format!("UDP {}:{} listener", scheme, addr), thread_code)
+}
+
+/// Sends and receives packets as single UDP datagrams.
+///
+/// Paths look like `udp:host:port`, the same as for [`TCP`](struct.TCP.html).
+/// If the address is a multicast group (e.g. `udp:239.255.77.77:18540`), sending will deliver the packet to every mesher on the LAN listening on that group, and listening will join the group.
+/// Since every chunk is encrypted for a specific key anyway, this gives zero-configuration LAN meshes: just send to the group and let whichever nodes can decrypt it pick it up.
+///
+/// When sending, the `ttl` option sets how many hops multicast packets can travel, e.g. `udp:239.255.77.77:18540?ttl=4`.
+/// It defaults to 1, i.e. only the local network.
+///
+/// Packets are read as soon as they arrive, but listeners only check whether they've been told to stop every 50ms, so
+/// stopping can take that long; the `poll` option changes it, e.g. `udp:[::1]:18540?poll=10ms`.
+///
+/// Packets larger than a single datagram (65507 bytes) can't be sent.
+///
+/// Listeners behind a NAT can get through it two ways (see [`nat`](nat/index.html)):
+///
+/// - The `map` option asks the router to forward the port, e.g. `udp:0.0.0.0:18540?map`, with NAT-PMP or UPnP, and
+///   keeps the mapping up until the listener stops.
+///   The `gateway` option picks the router to ask with NAT-PMP, e.g. `gateway=192.168.1.1`, and `igd` the one to ask
+///   with UPnP, e.g. `igd=192.168.1.1`, for when discovering one doesn't work.
+/// - The `rendezvous` and `name` options register the listener with a
+///   [rendezvous server](nat/struct.Rendezvous.html), e.g.
+///   `udp:0.0.0.0:18540?rendezvous=rv.example.com:18600&name=alice`, and senders reach it by sending to the server with
+///   its name in the `punch` option, e.g. `udp:rv.example.com:18600?punch=alice`, which punches a hole through both NATs
+///   and sends the packet through it.
+///   Punching gives up after the `timeout` option, or 5 seconds, with
+///   [`TimedOut`](../../mesher/fail/enum.MesherFail.html#variant.TimedOut).
+///
+/// If a listener's thread dies, e.g. by panicking, it's restarted the next time the mesher receives, with a growing wait
+/// between attempts if it keeps dying; if restarting fails, receiving fails with
+/// [`TransportDead`](../../mesher/fail/enum.MesherFail.html#variant.TransportDead), and it's tried again later.
+pub struct UDP {
+  inbox: Inbox,
+  receiver: Receiver<(Source, Vec<u8>)>,
+  scheme: String,
+  listeners: HashMap<String, Listener>,
+  restarts: Restarts,
+  limit: SizeLimit,
+}
+
+impl UDP {
+  /// Restarts any listeners whose threads have died, if it's time to try again.
+  fn restart_dead(&mut self) -> fail::Result<()> {
+    let dead: Vec<Path> = self
+      .listeners
+      .values()
+      .filter(|listener| listener.is_dead())
+      .map(|listener| listener.path().clone())
+      .collect();
+    let mut result = Ok(());
+    for path in dead {
+      if !self.restarts.due(path.location()) {
+        continue;
+      }
+      debug_event!(path = %path, "UDP listener thread died, restarting it");
+      let old = self.listeners.remove(path.location()).expect("dead listener vanished");
+      if let Err(e) = self.listen(&path) {
+        // kept around so it's still reported, and tried again later
+        self.listeners.insert(path.location().to_owned(), old);
+        result = Err(fail::MesherFail::TransportDead(format!(
+          "listener on {} died, and restarting it failed: {:?}",
+          path, e
+        )));
+      }
+    }
+    result
+  }
+}
+
+impl Transport for UDP {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    let (inbox, receiver) = Inbox::new();
+    Ok(UDP {
+      scheme: scheme.to_string(),
+      inbox,
+      receiver,
+      listeners: HashMap::new(),
+      restarts: Restarts::default(),
+      limit: SizeLimit::new(),
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "UDP"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    self.limit.check(blob.len())?;
+    if blob.len() > MAX_DATAGRAM {
+      return Err(fail::MesherFail::SendFailure(format!(
+        "{} bytes won't fit in a single datagram",
+        blob.len()
+      )));
+    }
+    let sock = socket_addr(path)?;
+    if let Some(name) = path.options().get("punch") {
+      let timeout = path.options().duration("timeout")?.unwrap_or(DEFAULT_PUNCH_TIMEOUT);
+      return nat::punch_and_send(sock, name, blob, timeout);
+    }
+    let ttl = path.options().parse_value("ttl")?.unwrap_or(1);
+    let out = bind_sender(&sock, ttl)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to bind UDP socket: {:?}", e)))?;
+    out
+      .send_to(blob, sock)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))?;
+    debug_event!(path = %path, bytes = blob.len(), "UDP sent packet");
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    if self.listeners.contains_key(path.location()) {
+      return Ok(());
+    }
+    let sock = socket_addr(path)?;
+    let poll = poll_interval(path)?;
+    let listener = listen(&self.scheme, path, sock, poll, self.inbox.clone(), self.limit.clone())?;
+    self.listeners.insert(path.location().to_owned(), listener);
+    Ok(())
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    self.restarts.forget(path.location());
+    // dropping the listener tells its thread to stop
+    self
+      .listeners
+      .remove(path.location())
+      .map(|_| ())
+      .ok_or_else(|| fail::MesherFail::NotListening(path.to_string()))
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.receive_up_to(usize::MAX)
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    // anything the dead thread received is still waiting in the inbox for next time
+    self.restart_dead()?;
+    Ok(self.inbox.take(&self.receiver, max))
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self.listeners.values().map(Listener::status).collect()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.limit.set(max);
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.inbox.set_max(max);
+  }
+
+  fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    self.restarts = Restarts::default();
+    stop_all(self.listeners.drain().map(|(_, listener)| listener), timeout)
+  }
+}
@@ -0,0 +1,266 @@
+use mesher::prelude::*;
+
+use std::{
+  cell::{Cell, RefCell},
+  collections::HashMap,
+  rc::Rc,
+};
+
+use js_sys::{ArrayBuffer, Uint8Array};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{BinaryType, MessageEvent, WebSocket as Socket};
+
+use crate::{Backoff, SizeLimit};
+
+use mesher::{ListenStatus, Priority};
+
+/// Packets received on any connection, waiting for the mesher to ask for them.
+type Inbox = Rc<RefCell<Vec<(Source, Vec<u8>)>>>;
+
+/// Packets waiting for a connection to open, with their priorities, most urgent first.
+type Outbox = Rc<RefCell<Vec<(Priority, Vec<u8>)>>>;
+
+/// One open (or opening) WebSocket, and the callbacks keeping it going.
+struct Connection {
+  path: Path,
+  socket: Socket,
+  /// Packets sent before the socket finished opening, which are sent as soon as it does.
+  pending: Outbox,
+  /// Whether packets the server sends over this connection should be received, or ignored.
+  listening: Rc<Cell<bool>>,
+  // the callbacks are only called as long as these are alive
+  _onopen: Closure<dyn FnMut()>,
+  _onmessage: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl Connection {
+  fn open(path: &Path, inbox: Inbox, limit: SizeLimit, max_buffered: Rc<Cell<usize>>) -> fail::Result<Connection> {
+    let socket = Socket::new(path.as_str())
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to open WebSocket: {:?}", e)))?;
+    socket.set_binary_type(BinaryType::Arraybuffer);
+
+    let pending: Outbox = Rc::default();
+    let onopen = {
+      let socket = socket.clone();
+      let pending = pending.clone();
+      Closure::<dyn FnMut()>::new(move || {
+        for (_, blob) in pending.borrow_mut().drain(..) {
+          // there's nowhere to report this to, so it's just lost, like any other packet the network drops
+          let _ = socket.send_with_u8_array(&blob);
+        }
+      })
+    };
+    socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+
+    let listening = Rc::new(Cell::new(false));
+    let onmessage = {
+      let listening = listening.clone();
+      let path = path.clone();
+      Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        if !listening.get() {
+          return;
+        }
+        // text messages aren't packets, so they're ignored
+        let buffer = match event.data().dyn_into::<ArrayBuffer>() {
+          Ok(b) => b,
+          Err(_) => return,
+        };
+        // the browser's already read the whole thing, so all that's left to do is not pass it on
+        if buffer.byte_length() as usize > limit.get() || inbox.borrow().len() >= max_buffered.get() {
+          return;
+        }
+        let source = Source::listening_on(path.clone()).from_remote(event.origin());
+        inbox.borrow_mut().push((source, Uint8Array::new(&buffer).to_vec()));
+      })
+    };
+    socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+
+    Ok(Connection {
+      path: path.clone(),
+      socket,
+      pending,
+      listening,
+      _onopen: onopen,
+      _onmessage: onmessage,
+    })
+  }
+
+  /// Whether the socket has closed, or is closing, so a new one has to be opened.
+  fn is_closed(&self) -> bool {
+    let state = self.socket.ready_state();
+    state == Socket::CLOSING || state == Socket::CLOSED
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    self.socket.set_onopen(None);
+    self.socket.set_onmessage(None);
+    let _ = self.socket.close();
+  }
+}
+
+/// Sends and receives packets over WebSockets, from inside a browser.
+///
+/// Paths are WebSocket URLs, e.g. `wss://relay.example/mesher`, so this should be
+/// [added for](../mesher/struct.Mesher.html#method.add_transport_for_schemes) both the `ws` and `wss` schemes, if both
+/// are used.
+/// Each packet is one binary message.
+///
+/// Browsers can't accept connections, so listening on a path opens a connection to it, and packets the server sends
+/// back over that connection are received.
+/// If the server closes it, it's reopened when the mesher receives, waiting longer after each failed attempt, from 100ms
+/// up to 30 seconds; until it's open again, its [status](../mesher/trait.Transport.html#method.status) is `Reconnecting`.
+/// Sending reuses the connection to the path, if there is one, and opens it otherwise; packets sent before it's open are
+/// held until it is, then sent most [urgent](../mesher/enum.Priority.html) first.
+///
+/// Only available when building for `wasm32`.
+pub struct WebSocket {
+  scheme: String,
+  connections: HashMap<String, Connection>,
+  /// For listening connections which dropped, how long to wait between reconnecting, and when to try next, in
+  /// milliseconds since the epoch, since wasm has no `Instant`.
+  reconnects: HashMap<String, (Backoff, f64)>,
+  inbox: Inbox,
+  limit: SizeLimit,
+  max_buffered: Rc<Cell<usize>>,
+}
+
+// SAFETY: Transports have to be Send, but JS objects can't be, since they belong to the thread that made them.
+// Without the atomics target feature, though, wasm has no other threads for them to be sent to.
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Send for WebSocket {}
+
+impl WebSocket {
+  /// Gets the connection to the path, opening a new one if there isn't one yet or the old one closed.
+  fn connection(&mut self, path: &Path) -> fail::Result<&mut Connection> {
+    if self.connections.get(path.as_str()).is_some_and(Connection::is_closed) {
+      self.connections.remove(path.as_str());
+    }
+    if !self.connections.contains_key(path.as_str()) {
+      let conn = Connection::open(path, self.inbox.clone(), self.limit.clone(), self.max_buffered.clone())?;
+      debug_event!(path = %path, "WebSocket connecting");
+      self.connections.insert(path.as_str().to_owned(), conn);
+    }
+    Ok(self.connections.get_mut(path.as_str()).expect("Just inserted"))
+  }
+}
+
+impl Transport for WebSocket {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(WebSocket {
+      scheme: scheme.to_owned(),
+      connections: HashMap::new(),
+      reconnects: HashMap::new(),
+      inbox: Rc::default(),
+      limit: SizeLimit::new(),
+      max_buffered: Rc::new(Cell::new(usize::MAX)),
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "WebSocket"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    self.send_with_priority(path, blob, Priority::Normal)
+  }
+
+  fn send_with_priority(&mut self, path: &Path, blob: &[u8], priority: Priority) -> fail::Result<()> {
+    self.limit.check(blob.len())?;
+    let conn = self.connection(path)?;
+    if conn.socket.ready_state() == Socket::CONNECTING {
+      let mut pending = conn.pending.borrow_mut();
+      // behind everything at least as urgent, so packets with the same priority keep their order
+      let at = pending.iter().position(|(p, _)| *p > priority).unwrap_or(pending.len());
+      pending.insert(at, (priority, blob.to_vec()));
+      return Ok(());
+    }
+    conn
+      .socket
+      .send_with_u8_array(blob)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))?;
+    debug_event!(path = %path, bytes = blob.len(), "WebSocket sent packet");
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    let conn = self.connection(path)?;
+    conn.listening.set(true);
+    Ok(())
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    let conn = self
+      .connections
+      .get(path.as_str())
+      .filter(|c| c.listening.get())
+      .ok_or_else(|| fail::MesherFail::NotListening(path.to_string()))?;
+    // the connection's kept open, in case it's sent along again
+    conn.listening.set(false);
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    self.receive_up_to(usize::MAX)
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    // if the server hung up on a connection being listened on, reconnect, so it keeps listening
+    let connections = &self.connections;
+    self.reconnects.retain(|path, _| {
+      connections
+        .get(path)
+        .is_some_and(|c| c.socket.ready_state() != Socket::OPEN)
+    });
+    let dropped: Vec<_> = self
+      .connections
+      .values()
+      .filter(|c| c.listening.get() && c.is_closed())
+      .map(|c| c.path.clone())
+      .collect();
+    let now = js_sys::Date::now();
+    for path in dropped {
+      let (backoff, next_try) = self
+        .reconnects
+        .entry(path.as_str().to_owned())
+        .or_insert_with(|| (Backoff::new(), now));
+      if now < *next_try {
+        continue;
+      }
+      *next_try = now + backoff.next().as_millis() as f64;
+      debug_event!(path = %path, "WebSocket reconnecting");
+      self.listen(&path)?;
+    }
+    let mut inbox = self.inbox.borrow_mut();
+    let count = max.min(inbox.len());
+    Ok(inbox.drain(..count).collect())
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self
+      .connections
+      .values()
+      .filter(|c| c.listening.get())
+      .map(|c| {
+        let status = match c.socket.ready_state() {
+          Socket::OPEN => ListenStatus::Listening,
+          _ => ListenStatus::Reconnecting,
+        };
+        (c.path.clone(), status)
+      })
+      .collect()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.limit.set(max);
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.max_buffered.set(max.unwrap_or(usize::MAX));
+  }
+}
@@ -0,0 +1,228 @@
+use mesher::{prelude::*, rpc::Rpc, Route};
+use mesher_basic::exit::{self, Exit, HttpRequest};
+
+use std::{
+  io::prelude::*,
+  net::{Ipv4Addr, SocketAddr, TcpListener},
+  sync::mpsc::{channel, Receiver},
+  thread::spawn,
+  time::Duration,
+};
+
+/// An HTTP server which answers every request with `response`, passing on each request it gets.
+fn fake_server(response: &'static [u8]) -> (SocketAddr, Receiver<String>) {
+  let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  let addr = listener.local_addr().expect("no address");
+  let (tx, rx) = channel();
+  spawn(move || {
+    for conn in listener.incoming() {
+      let mut conn = conn.expect("failed to accept");
+      let mut request = vec![];
+      let mut buf = [0; 1024];
+      while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        match conn.read(&mut buf) {
+          Ok(0) | Err(_) => break,
+          Ok(len) => request.extend_from_slice(&buf[..len]),
+        }
+      }
+      let head = String::from_utf8_lossy(&request).into_owned();
+      let length = head
+        .lines()
+        .find_map(|l| l.strip_prefix("Content-Length: "))
+        .map_or(0, |l| l.trim().parse().unwrap());
+      let body_start = request.windows(4).position(|w| w == b"\r\n\r\n").map_or(0, |p| p + 4);
+      while request.len() < body_start + length {
+        match conn.read(&mut buf) {
+          Ok(0) | Err(_) => break,
+          Ok(len) => request.extend_from_slice(&buf[..len]),
+        }
+      }
+      let _ = conn.write_all(response);
+      if tx.send(String::from_utf8_lossy(&request).into_owned()).is_err() {
+        return;
+      }
+    }
+  });
+  (addr, rx)
+}
+
+/// An HTTP server which answers every request with `start`, then `repeated` over and over, until the other end hangs up.
+fn endless_server(start: &'static [u8], repeated: &'static [u8]) -> SocketAddr {
+  let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  let addr = listener.local_addr().expect("no address");
+  spawn(move || {
+    for conn in listener.incoming() {
+      let mut conn = conn.expect("failed to accept");
+      spawn(move || {
+        let mut buf = [0; 1024];
+        let _ = conn.read(&mut buf);
+        let _ = conn.write_all(start);
+        while conn.write_all(repeated).is_ok() {}
+      });
+    }
+  });
+  addr
+}
+
+#[test]
+fn fetches_directly() {
+  let (server, requests) = fake_server(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Test: yes\r\n\r\nhello");
+  let exit = Exit::new().allow_private(true);
+
+  let request = HttpRequest::new("POST", &format!("http://{}/path?q=1#frag", server))
+    .header("Accept", "text/plain")
+    .body(b"data".to_vec());
+  let response = exit.fetch(&request).expect("failed to fetch");
+  assert_eq!(200, response.status);
+  assert_eq!(Some("yes"), response.header("x-test"));
+  assert_eq!(b"hello", &response.body[..]);
+
+  let request = requests.recv_timeout(Duration::from_secs(1)).expect("no request");
+  assert!(request.starts_with("POST /path?q=1 HTTP/1.1\r\n"));
+  assert!(request.contains(&format!("\r\nHost: {}\r\n", server)));
+  assert!(request.contains("\r\nAccept: text/plain\r\n"));
+  assert!(request.contains("\r\nContent-Length: 4\r\n"));
+  assert!(request.ends_with("\r\n\r\ndata"));
+}
+
+#[test]
+fn reads_chunked_responses() {
+  let (server, _requests) =
+    fake_server(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+  let response = Exit::new()
+    .allow_private(true)
+    .fetch(&HttpRequest::get(&format!("http://{}/", server)))
+    .expect("failed to fetch");
+  assert_eq!(b"hello world", &response.body[..]);
+  assert_eq!(None, response.header("transfer-encoding"));
+}
+
+#[test]
+fn refuses_what_it_should() {
+  let (server, _requests) = fake_server(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\ntoo long");
+  let refused = |exit: &Exit, request: &HttpRequest| match exit.fetch(request) {
+    Err(fail::MesherFail::ExitFailure(_)) => (),
+    other => panic!("should have been refused: {:?}", other),
+  };
+  let url = format!("http://{}/", server);
+
+  refused(&Exit::new(), &HttpRequest::get(&url));
+  refused(
+    &Exit::new().allow_private(true).ports(vec![80]),
+    &HttpRequest::get(&url),
+  );
+  refused(
+    &Exit::new().allow_private(true).max_response_size(4),
+    &HttpRequest::get(&url),
+  );
+  refused(
+    &Exit::new().allow_private(true),
+    &HttpRequest::get("https://example.com/"),
+  );
+  refused(
+    &Exit::new().allow_private(true),
+    &HttpRequest::get(&url).header("X-Evil", "a\r\nHost: elsewhere"),
+  );
+  refused(&Exit::new().allow_private(true), &HttpRequest::new("GET /evil", &url));
+
+  // a chunk so big that adding its size to the body's would overflow
+  let (huge, _requests) =
+    fake_server(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\nffffffffffffffff\r\n");
+  refused(
+    &Exit::new().allow_private(true),
+    &HttpRequest::get(&format!("http://{}/", huge)),
+  );
+
+  // heads that never end, which the body's size limit doesn't cover
+  let endless = [
+    endless_server(b"HTTP/1.1 200 OK\r\nX-Long: ", b"aaaaaaaa"),
+    endless_server(b"HTTP/1.1 200 OK\r\n", b"X-Many: a\r\n"),
+    endless_server(b"", b"HTTP/1.1 100 Continue\r\n\r\n"),
+    endless_server(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1;", b"aaaaaaaa"),
+    endless_server(
+      b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n",
+      b"X-Trailer: a\r\n",
+    ),
+  ];
+  for server in endless {
+    refused(
+      &Exit::new().allow_private(true),
+      &HttpRequest::get(&format!("http://{}/", server)),
+    );
+  }
+}
+
+#[test]
+fn fetches_over_the_mesh() {
+  let (server, _requests) = fake_server(b"HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\nnope");
+
+  let (exit_pk, exit_sk) = encrypt::gen_keypair();
+  let mut exit_mesher = Mesher::unsigned(vec![exit_sk]);
+  exit_mesher
+    .add_transport::<mesher_basic::TCP>("tcp")
+    .expect("failed to add transport");
+  exit_mesher.listen_on("tcp:localhost:18690").expect("failed to listen");
+  let (client_pk, client_sk) = encrypt::gen_keypair();
+  let mut client = Mesher::unsigned(vec![client_sk]);
+  client
+    .add_transport::<mesher_basic::TCP>("tcp")
+    .expect("failed to add transport");
+  client.listen_on("tcp:localhost:18691").expect("failed to listen");
+
+  spawn(move || {
+    let mut rpc = Rpc::new();
+    Exit::new()
+      .allow_private(true)
+      .ports(vec![server.port()])
+      .serve(&mut rpc);
+    loop {
+      for message in exit_mesher
+        .receive_wait(Duration::from_millis(5))
+        .expect("failed to receive")
+      {
+        rpc.handle(&mut exit_mesher, &message).expect("failed to answer");
+      }
+    }
+  });
+
+  let route = Route::new()
+    .then(Path::parse("tcp:localhost:18691").unwrap(), client_pk)
+    .then(Path::parse("tcp:localhost:18690").unwrap(), exit_pk);
+  let mut rpc = Rpc::new();
+  let url = format!("http://{}/missing", server);
+  let response = exit::fetch(
+    &mut rpc,
+    &mut client,
+    &route,
+    &HttpRequest::get(&url),
+    Duration::from_secs(5),
+  )
+  .expect("failed to fetch");
+  assert_eq!(404, response.status);
+  assert_eq!(b"nope", &response.body[..]);
+
+  let url = "http://localhost:1/";
+  match exit::fetch(
+    &mut rpc,
+    &mut client,
+    &route,
+    &HttpRequest::get(url),
+    Duration::from_secs(5),
+  ) {
+    Err(fail::MesherFail::ExitFailure(why)) => assert!(why.contains("port 1"), "wrong reason: {}", why),
+    other => panic!("should have been refused: {:?}", other),
+  }
+
+  // too long to encode, rather than cut short
+  let long = "x".repeat(usize::from(u16::MAX) + 1);
+  match exit::fetch(
+    &mut rpc,
+    &mut client,
+    &route,
+    &HttpRequest::get(url).header("X-Long", &long),
+    Duration::from_secs(5),
+  ) {
+    Err(fail::MesherFail::ExitFailure(why)) => assert!(why.contains("too long"), "wrong reason: {}", why),
+    other => panic!("should have been refused: {:?}", other),
+  }
+}
@@ -0,0 +1,161 @@
+//! Runs the I2P transport against a stand-in for a router's SAM bridge, which passes streams straight on to the local
+//! port of whichever destination was set up last, like the router would.
+
+use mesher::prelude::*;
+use mesher_basic::I2P;
+
+use std::{
+  env, fs,
+  io::{self, prelude::*, BufRead, BufReader},
+  net::{TcpListener, TcpStream},
+  sync::{Arc, Mutex},
+  thread::{sleep, spawn},
+  time::Duration,
+};
+
+/// A destination whose public half is three zero bytes, in I2P's base64, and the address that makes.
+const PUBLIC: &str = "AAAA";
+const ADDRESS: &str = "ocpibseeq6rechq64tp3t4rkqykjfuqmi5srkdampffl24hycr6a.b32.i2p";
+
+/// What the fake bridge has been asked to do.
+#[derive(Default)]
+struct Bridge {
+  commands: Vec<String>,
+  forward_to: Option<u16>,
+}
+
+fn handle(conn: TcpStream, bridge: Arc<Mutex<Bridge>>) {
+  let mut out = conn.try_clone().unwrap();
+  let mut reader = BufReader::new(conn);
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+      return;
+    }
+    let line = line.trim_end().to_owned();
+    bridge.lock().unwrap().commands.push(line.clone());
+    let reply = if line.starts_with("HELLO VERSION") {
+      "HELLO REPLY RESULT=OK VERSION=3.1".to_owned()
+    } else if line.starts_with("SESSION CREATE") {
+      let key = line.split(' ').find_map(|f| f.strip_prefix("DESTINATION=")).unwrap();
+      let private = if key == "TRANSIENT" { "bmV3a2V5" } else { key };
+      format!("SESSION STATUS RESULT=OK DESTINATION={}", private)
+    } else if line == "NAMING LOOKUP NAME=ME" {
+      format!("NAMING REPLY RESULT=OK NAME=ME VALUE={}", PUBLIC)
+    } else if line.starts_with("STREAM FORWARD") {
+      let port = line.split(' ').find_map(|f| f.strip_prefix("PORT=")).unwrap();
+      bridge.lock().unwrap().forward_to = Some(port.parse().unwrap());
+      "STREAM STATUS RESULT=OK".to_owned()
+    } else if line.starts_with("STREAM CONNECT") {
+      let port = bridge.lock().unwrap().forward_to;
+      match port.filter(|_| line.contains(&format!("DESTINATION={} ", ADDRESS))) {
+        Some(port) => {
+          out.write_all(b"STREAM STATUS RESULT=OK\n").unwrap();
+          let mut local = TcpStream::connect(("127.0.0.1", port)).unwrap();
+          io::copy(&mut reader, &mut local).unwrap();
+          return;
+        }
+        None => "STREAM STATUS RESULT=CANT_REACH_PEER".to_owned(),
+      }
+    } else {
+      "ERROR RESULT=I2P_ERROR".to_owned()
+    };
+    out.write_all(format!("{}\n", reply).as_bytes()).unwrap();
+  }
+}
+
+/// Starts a fake SAM bridge, returning its address and what it's been asked to do.
+fn fake_bridge() -> (String, Arc<Mutex<Bridge>>) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr = listener.local_addr().unwrap().to_string();
+  let bridge = Arc::new(Mutex::new(Bridge::default()));
+  let shared = bridge.clone();
+  spawn(move || {
+    for conn in listener.incoming() {
+      let shared = shared.clone();
+      spawn(move || handle(conn.unwrap(), shared));
+    }
+  });
+  (addr, bridge)
+}
+
+fn make_mesher() -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::unsigned(vec![sk]);
+  m.add_transport::<I2P>("i2p").expect("Failed to add transport");
+  (m, pk)
+}
+
+#[test]
+fn through_destination() {
+  let key_file = env::temp_dir().join("mesher-i2p-test.key");
+  let _ = fs::remove_file(&key_file);
+  let (sam, bridge) = fake_bridge();
+  let (mut m_dest, k_dest) = make_mesher();
+  let listen = format!("i2p:dest-test?sam={}&key={}", sam, key_file.display());
+  m_dest.listen_on(&listen).expect("Failed to listen");
+  assert_eq!(Some(ADDRESS.to_owned()), I2P::address(key_file.to_str().unwrap()));
+  assert_eq!(
+    format!("{}\nbmV3a2V5\n", ADDRESS),
+    fs::read_to_string(&key_file).unwrap()
+  );
+
+  let (mut m_source, k_source) = make_mesher();
+  let mut packet = Packet::unsigned();
+  packet.add_hop(format!("i2p:{}?sam={}", ADDRESS, sam), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(200));
+
+  let received = m_dest.receive().expect("Failed to receive");
+  assert_eq!(1, received.len());
+  assert_eq!(&[1, 2, 3], received[0].contents());
+  assert_eq!(
+    listen,
+    received[0].source().and_then(|s| s.listen_path()).unwrap().to_string()
+  );
+  // the sender got its own session, rather than sending from the listener's
+  let commands = &bridge.lock().unwrap().commands;
+  assert_eq!(2, commands.iter().filter(|c| c.starts_with("SESSION CREATE")).count());
+  assert!(commands
+    .iter()
+    .any(|c| c.starts_with("SESSION CREATE STYLE=STREAM ID=dest-test ")));
+}
+
+#[test]
+fn saved_key_reused() {
+  let key_file = env::temp_dir().join("mesher-i2p-reuse.key");
+  fs::write(&key_file, format!("{}\nc2F2ZWQ=\n", ADDRESS)).unwrap();
+  let (sam, bridge) = fake_bridge();
+  let (mut m, _) = make_mesher();
+  m.listen_on(&format!("i2p:reuse-test?sam={}&key={}", sam, key_file.display()))
+    .expect("Failed to listen");
+  let commands = &bridge.lock().unwrap().commands;
+  assert!(commands
+    .iter()
+    .any(|c| c.contains("ID=reuse-test DESTINATION=c2F2ZWQ= ")));
+}
+
+#[test]
+fn unreachable_destination() {
+  let (sam, _) = fake_bridge();
+  let (mut m, k) = make_mesher();
+  let mut packet = Packet::unsigned();
+  packet.add_hop(format!("i2p:nowhere.b32.i2p?sam={}", sam), &k);
+  packet.add_message(&[1], &k);
+  match m.launch(packet) {
+    Err(fail::MesherFail::SendFailure(e)) => assert!(e.contains("CANT_REACH_PEER"), "{}", e),
+    other => panic!("unexpected result: {:?}", other),
+  }
+}
+
+#[test]
+fn no_bridge() {
+  let (mut m, _) = make_mesher();
+  // nothing listens on port 1
+  assert!(matches!(
+    m.listen_on("i2p:nobody?sam=127.0.0.1:1&timeout=1s"),
+    Err(fail::MesherFail::ListenFailure(_))
+  ));
+}
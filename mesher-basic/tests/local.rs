@@ -91,3 +91,240 @@ fn two_hops() {
     .collect::<Vec<_>>();
   assert_eq!(vec![vec![1, 2, 3]], received);
 }
+
+#[test]
+fn retries_until_listening() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (k_dest, sk_dest) = encrypt::gen_keypair();
+
+  let late_dest = std::thread::spawn(move || {
+    sleep(Duration::from_millis(250));
+    let mut m_dest = Mesher::unsigned(vec![sk_dest]);
+    m_dest.add_transport::<TCP>("tcp").expect("Failed to add transport");
+    m_dest.listen_on("tcp:localhost:18570").expect("Failed to listen");
+    sleep(Duration::from_millis(250));
+    m_dest
+      .receive()
+      .expect("failed to receive")
+      .into_iter()
+      .map(|m| m.into_contents())
+      .collect::<Vec<_>>()
+  });
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("tcp:localhost:18570?retries=20".to_owned(), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  let received = late_dest.join().expect("Receiving thread panicked");
+  assert_eq!(vec![vec![1, 2, 3]], received);
+}
+
+#[test]
+fn stop_listening_frees_port() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest, k_dest) = make_mesher(None);
+  let handle = m_dest.listen_on("tcp:localhost:18580").expect("Failed to listen");
+  handle.stop(&mut m_dest).expect("Failed to stop listening");
+  sleep(Duration::from_millis(100));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("tcp:localhost:18580".to_owned(), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  assert!(m_source.launch(packet).is_err());
+
+  // and since the old listener is gone, the port can be listened on again
+  m_dest.listen_on("tcp:localhost:18580").expect("Failed to relisten");
+}
+
+#[test]
+fn oversized_packets_refused() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest, k_dest) = make_mesher(None);
+  m_dest.set_max_packet_size(Some(1024));
+  m_dest.listen_on("tcp:localhost:18590").expect("Failed to listen");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("tcp:localhost:18590".to_owned(), &k_source);
+  packet.add_message(&[0; 2048], &k_dest);
+  m_source.set_max_packet_size(Some(1024));
+  match m_source.launch(packet.clone()) {
+    Err(fail::MesherFail::PacketTooLarge(_, 1024)) => (),
+    other => panic!("expected packet to be too large, got {:?}", other),
+  }
+
+  // the listener stops reading as soon as it's over the limit
+  m_source.set_max_packet_size(None);
+  m_source.launch(packet).expect("Failed to send");
+  sleep(Duration::from_millis(100));
+  assert!(m_dest.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn hung_destination_times_out() {
+  // never accepted or read from, so writes stop once the socket buffers fill
+  let _hung = std::net::TcpListener::bind("localhost:18600").expect("Failed to bind");
+  let mut tcp = TCP::new("tcp").expect("Failed to create transport");
+  let path = Path::parse("tcp:localhost:18600?write_timeout=200ms").expect("Failed to parse path");
+
+  let start = std::time::Instant::now();
+  match tcp.send(&path, &vec![0; 64 * 1024 * 1024]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("expected send to time out, got {:?}", other),
+  }
+  assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn batches_share_a_connection() {
+  let mut t = TCP::new("tcp").expect("Failed to create transport");
+  t.listen(&Path::parse("tcp:localhost:18610").expect("Failed to parse path"))
+    .expect("Failed to listen");
+  let batch = Path::parse("tcp:localhost:18610?batch").expect("Failed to parse path");
+  t.send_batch(&batch, &[&[1], &[], &[2, 3]])
+    .expect("Failed to send batch");
+  // listeners still take unbatched packets too
+  let single = Path::parse("tcp:localhost:18610").expect("Failed to parse path");
+  t.send(&single, &[4]).expect("Failed to send");
+
+  sleep(Duration::from_millis(100));
+
+  let (sources, received): (Vec<_>, Vec<_>) = t.receive().expect("Failed to receive").into_iter().unzip();
+  assert_eq!(vec![vec![1], vec![], vec![2, 3], vec![4]], received);
+  assert!(sources
+    .iter()
+    .all(|s| s.listen_path().map(Path::as_str) == Some("tcp:localhost:18610")));
+}
+
+#[test]
+fn sources_recorded_and_filtered() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest, k_dest) = make_mesher(None);
+  m_dest.listen_on("tcp:127.0.0.1:18620").expect("Failed to listen");
+  m_dest.set_forward_policy(Some(mesher::ForwardPolicy::new().deny_from("127.0.0.1:*")));
+  let denied = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+  let denied_log = denied.clone();
+  m_dest.on_failure(move |f| denied_log.lock().unwrap().push(f));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("tcp:127.0.0.1:18620".to_owned(), &k_source);
+  packet.add_hop("tcp:127.0.0.1:18621".to_owned(), &k_dest);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(100));
+
+  let received = m_dest.receive().expect("Failed to receive");
+  assert_eq!(1, received.len());
+  let source = received[0].source().expect("Message should have a source");
+  assert_eq!(Some("tcp:127.0.0.1:18620"), source.listen_path().map(Path::as_str));
+  assert!(source
+    .remote()
+    .expect("TCP should know the remote")
+    .starts_with("127.0.0.1:"));
+  let denied = denied.lock().unwrap();
+  assert_eq!(1, denied.len());
+  assert!(matches!(denied[0], fail::MesherFail::ForwardDenied(_)));
+}
+
+#[test]
+fn health_reported() {
+  let (mut m, _) = make_mesher(Some(18630));
+  assert_eq!(
+    vec![(
+      Path::parse("tcp:localhost:18630").expect("Failed to parse path"),
+      mesher::ListenStatus::Listening
+    )],
+    m.health()
+  );
+  m.stop_listening("tcp:localhost:18630")
+    .expect("Failed to stop listening");
+  assert!(m.health().is_empty());
+}
+
+#[test]
+fn dual_stack_takes_ipv4() {
+  let mut t = TCP::new("tcp").expect("Failed to create transport");
+  t.listen(&Path::parse("tcp:[::]:18640?dual_stack").expect("Failed to parse path"))
+    .expect("Failed to listen");
+  t.send(&Path::parse("tcp:127.0.0.1:18640").expect("Failed to parse path"), &[1])
+    .expect("Failed to send over IPv4");
+  t.send(&Path::parse("tcp:[::1]:18640").expect("Failed to parse path"), &[2])
+    .expect("Failed to send over IPv6");
+
+  sleep(Duration::from_millis(100));
+
+  let mut received: Vec<_> = t
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(|(_, p)| p)
+    .collect();
+  received.sort();
+  assert_eq!(vec![vec![1], vec![2]], received);
+}
+
+#[test]
+fn poll_interval_configurable() {
+  let mut t = TCP::new("tcp").expect("Failed to create transport");
+  match t.listen(&Path::parse("tcp:localhost:18670?poll=0").expect("Failed to parse path")) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    other => panic!("Expected InvalidURL, got {:?}", other),
+  }
+  t.listen(&Path::parse("tcp:localhost:18670?poll=1ms").expect("Failed to parse path"))
+    .expect("Failed to listen");
+  t.send(&Path::parse("tcp:localhost:18670").expect("Failed to parse path"), &[1])
+    .expect("Failed to send");
+
+  // well under the default interval
+  sleep(Duration::from_millis(20));
+
+  let received: Vec<_> = t
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(|(_, p)| p)
+    .collect();
+  assert_eq!(vec![vec![1]], received);
+}
+
+#[test]
+fn every_address_bound() {
+  let mut t = TCP::new("tcp").expect("Failed to create transport");
+  // whatever localhost resolves to here, all of it should be taken
+  t.listen(&Path::parse("tcp:localhost:18650").expect("Failed to parse path"))
+    .expect("Failed to listen");
+  for addr in std::net::ToSocketAddrs::to_socket_addrs("localhost:18650").expect("Failed to resolve") {
+    assert!(
+      std::net::TcpListener::bind(addr).is_err(),
+      "{} should already be bound",
+      addr
+    );
+  }
+}
+
+#[test]
+fn closing_frees_port() {
+  let (m_dest, _) = make_mesher(Some(18660));
+  m_dest.close(Duration::from_secs(2)).expect("Failed to close");
+
+  // the listener's thread is gone once closing returns, so the port can be taken straight away
+  let (mut m_again, _) = make_mesher(None);
+  m_again.listen_on("tcp:localhost:18660").expect("Failed to relisten");
+}
+
+#[test]
+fn defaults_registered() {
+  let mut m = Mesher::unsigned(vec![]);
+  mesher_basic::register_defaults(&mut m).expect("Failed to register defaults");
+  assert_eq!(vec!["inmem", "tcp", "udp"], m.transports());
+
+  let mut taken = Mesher::unsigned(vec![]);
+  taken.add_transport::<TCP>("udp").expect("Failed to add transport");
+  match mesher_basic::register_defaults(&mut taken) {
+    Err(fail::MesherFail::AlreadyRegistered(s)) => assert_eq!("udp", s),
+    _ => panic!("Taken scheme should be rejected"),
+  }
+  // nothing else was added
+  assert_eq!(vec!["udp"], taken.transports());
+}
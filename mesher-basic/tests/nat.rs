@@ -0,0 +1,329 @@
+use mesher::prelude::*;
+use mesher_basic::{
+  nat::{self, Protocol, Rendezvous, Router},
+  UDP,
+};
+
+use std::{
+  io::prelude::*,
+  net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+  sync::mpsc::{channel, Receiver},
+  thread::{sleep, spawn},
+  time::Duration,
+};
+
+/// A NAT-PMP router which maps whatever it's asked to, at 203.0.113.7, passing on each mapping request it gets.
+fn fake_nat_pmp() -> (SocketAddr, Receiver<Vec<u8>>) {
+  let sock = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  let addr = sock.local_addr().expect("no address");
+  let (tx, rx) = channel();
+  spawn(move || {
+    let mut buf = [0; 16];
+    while let Ok((len, from)) = sock.recv_from(&mut buf) {
+      let reply = match (len, buf[1]) {
+        (2, 0) => vec![0, 128, 0, 0, 0, 0, 0, 1, 203, 0, 113, 7],
+        (12, op) => {
+          let mut reply = vec![0, 128 + op, 0, 0, 0, 0, 0, 1];
+          // internal port, external port, lifetime, straight from the request
+          reply.extend_from_slice(&buf[4..12]);
+          if tx.send(buf[..len].to_vec()).is_err() {
+            return;
+          }
+          reply
+        }
+        _ => continue,
+      };
+      let _ = sock.send_to(&reply, from);
+    }
+  });
+  (addr, rx)
+}
+
+#[test]
+fn maps_with_nat_pmp() {
+  let (router, requests) = fake_nat_pmp();
+
+  let mapping =
+    nat::map_port(Protocol::Tcp, 18680, &Router::NatPmp(router), Duration::from_secs(2)).expect("failed to map");
+  assert_eq!("203.0.113.7:18680".parse::<SocketAddr>().unwrap(), mapping.external());
+  assert_eq!(Duration::from_secs(3600), mapping.lifetime());
+  let request = requests.recv_timeout(Duration::from_secs(1)).expect("no request");
+  assert_eq!(&[0, 2, 0, 0, 0x48, 0xf8, 0x48, 0xf8, 0, 0, 0x0e, 0x10], &request[..]);
+
+  drop(mapping);
+  let request = requests.recv_timeout(Duration::from_secs(1)).expect("not unmapped");
+  assert_eq!(&[0, 2, 0, 0, 0x48, 0xf8, 0, 0, 0, 0, 0, 0], &request[..]);
+}
+
+/// Reads an HTTP request's head and body.
+fn read_request(conn: &mut impl Read) -> String {
+  let mut request = vec![];
+  let mut byte = [0];
+  while !request.ends_with(b"\r\n\r\n") && conn.read(&mut byte).unwrap_or(0) == 1 {
+    request.push(byte[0]);
+  }
+  let head = String::from_utf8_lossy(&request).into_owned();
+  let len = head
+    .lines()
+    .find_map(|l| {
+      let (name, value) = l.split_once(':')?;
+      Some(value.trim()).filter(|_| name.eq_ignore_ascii_case("content-length"))
+    })
+    .and_then(|l| l.parse().ok())
+    .unwrap_or(0);
+  let mut body = vec![0; len];
+  let _ = conn.read_exact(&mut body);
+  head + &String::from_utf8_lossy(&body)
+}
+
+/// A UPnP gateway which only makes permanent mappings, passing on each action it's asked to do, and returning the
+/// address it answers searches at.
+fn fake_upnp() -> (SocketAddr, Receiver<String>) {
+  let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  let location = format!("http://{}/desc.xml", server.local_addr().expect("no address"));
+  let ssdp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  let addr = ssdp.local_addr().expect("no address");
+  spawn(move || {
+    let mut buf = [0; 1024];
+    while let Ok((_, from)) = ssdp.recv_from(&mut buf) {
+      let answer = format!(
+        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nLOCATION: {}\r\n\r\n",
+        location
+      );
+      let _ = ssdp.send_to(answer.as_bytes(), from);
+    }
+  });
+  let (tx, rx) = channel();
+  spawn(move || {
+    for conn in server.incoming() {
+      let mut conn = match conn {
+        Ok(conn) => conn,
+        Err(_) => return,
+      };
+      let request = read_request(&mut conn);
+      let (status, body) = if request.starts_with("GET /desc.xml") {
+        (
+          "200 OK",
+          "<root><device><serviceList><service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1\
+           </serviceType><SCPDURL>/l3f.xml</SCPDURL><controlURL>/l3f</controlURL></service><service>\
+           <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType><SCPDURL>/scpd.xml</SCPDURL>\
+           <controlURL>/ctl</controlURL></service></serviceList></device></root>"
+            .to_owned(),
+        )
+      } else if request.starts_with("GET /scpd.xml") {
+        let action = |name: &str, args: &[&str]| {
+          let args: String = args
+            .iter()
+            .map(|a| format!("<argument><name>{}</name><direction>in</direction></argument>", a))
+            .collect();
+          format!(
+            "<action><name>{}</name><argumentList>{}</argumentList></action>",
+            name, args
+          )
+        };
+        let actions = [
+          action(
+            "AddPortMapping",
+            &[
+              "NewRemoteHost",
+              "NewExternalPort",
+              "NewProtocol",
+              "NewInternalPort",
+              "NewInternalClient",
+              "NewEnabled",
+              "NewPortMappingDescription",
+              "NewLeaseDuration",
+            ],
+          ),
+          action(
+            "DeletePortMapping",
+            &["NewRemoteHost", "NewExternalPort", "NewProtocol"],
+          ),
+          action("GetExternalIPAddress", &[]),
+        ];
+        (
+          "200 OK",
+          format!("<scpd><actionList>{}</actionList></scpd>", actions.concat()),
+        )
+      } else if request.contains("<NewLeaseDuration>3600<") {
+        (
+          "500 Internal Server Error",
+          "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body><s:Fault><detail><UPnPError><errorCode>725</errorCode>\
+           <errorDescription>OnlyPermanentLeasesSupported</errorDescription></UPnPError></detail></s:Fault></s:Body>\
+           </s:Envelope>"
+            .to_owned(),
+        )
+      } else if request.contains("#GetExternalIPAddress") {
+        (
+          "200 OK",
+          "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body>\
+           <u:GetExternalIPAddressResponse xmlns:u=\"x\">\
+           <NewExternalIPAddress>198.51.100.9</NewExternalIPAddress></u:GetExternalIPAddressResponse></s:Body>\
+           </s:Envelope>"
+            .to_owned(),
+        )
+      } else {
+        let action = ["AddPortMapping", "DeletePortMapping"]
+          .iter()
+          .find(|a| request.contains(&format!("#{}", a)))
+          .unwrap_or(&"Unknown");
+        (
+          "200 OK",
+          format!(
+            "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body>\
+             <u:{}Response xmlns:u=\"x\"/></s:Body></s:Envelope>",
+            action
+          ),
+        )
+      };
+      if request.starts_with("POST /ctl") && tx.send(request).is_err() {
+        return;
+      }
+      let response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body);
+      let _ = conn.write_all(response.as_bytes());
+    }
+  });
+  (addr, rx)
+}
+
+#[test]
+fn maps_with_upnp() {
+  let (device, requests) = fake_upnp();
+
+  let mapping =
+    nat::map_port(Protocol::Udp, 18681, &Router::Upnp(device), Duration::from_secs(2)).expect("failed to map");
+  assert_eq!("198.51.100.9:18681".parse::<SocketAddr>().unwrap(), mapping.external());
+  // the router only does permanent mappings
+  assert_eq!(Duration::ZERO, mapping.lifetime());
+  let tried = requests.recv_timeout(Duration::from_secs(1)).expect("no request");
+  assert!(tried.contains("#AddPortMapping") && tried.contains("<NewLeaseDuration>3600<"));
+  let added = requests.recv_timeout(Duration::from_secs(1)).expect("no retry");
+  assert!(added.contains("<NewProtocol>UDP<") && added.contains("<NewInternalClient>127.0.0.1<"));
+  assert!(added.contains("<NewLeaseDuration>0<"));
+
+  drop(mapping);
+  let deleted = requests
+    .iter()
+    .find(|r| r.contains("#DeletePortMapping"))
+    .expect("not unmapped");
+  assert!(deleted.contains("<NewExternalPort>18681<"));
+}
+
+#[test]
+fn listener_keeps_mapping() {
+  let (router, requests) = fake_nat_pmp();
+  let path = format!("udp:0.0.0.0:18682?map&gateway={}", router);
+
+  let mut m = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+  m.add_transport::<UDP>("udp").expect("Failed to add transport");
+  m.listen_on(&path).expect("Failed to listen");
+  let request = requests.recv_timeout(Duration::from_secs(1)).expect("not mapped");
+  assert_eq!([0, 1, 0, 0, 0x48, 0xfa], request[..6]);
+
+  m.stop_listening(&path).expect("Failed to stop listening");
+  let request = requests.recv_timeout(Duration::from_secs(1)).expect("not unmapped");
+  assert_eq!([0, 1, 0, 0, 0x48, 0xfa, 0, 0], request[..8]);
+}
+
+#[test]
+fn unmappable_listener_fails() {
+  // nothing's there to answer
+  let path = "udp:0.0.0.0:18683?map&gateway=127.0.0.1:18684&map_timeout=300ms";
+
+  let mut m = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+  m.add_transport::<UDP>("udp").expect("Failed to add transport");
+  match m.listen_on(path) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("expected ListenFailure, got {:?}", other),
+  }
+}
+
+fn rendezvous() -> SocketAddr {
+  let mut server = Rendezvous::bind("127.0.0.1:0").expect("failed to bind");
+  let addr = server.local_addr().expect("no address");
+  spawn(move || server.run());
+  addr
+}
+
+#[test]
+fn punches_through() {
+  let server = rendezvous();
+  let (k_source, s_source) = encrypt::gen_keypair();
+  let (k_dest, s_dest) = encrypt::gen_keypair();
+  let mut m_source = Mesher::unsigned(vec![s_source]);
+  m_source.add_transport::<UDP>("udp").expect("Failed to add transport");
+  let mut m_dest = Mesher::unsigned(vec![s_dest]);
+  m_dest.add_transport::<UDP>("udp").expect("Failed to add transport");
+  m_dest
+    .listen_on(&format!("udp:127.0.0.1:18685?rendezvous={}&name=alice", server))
+    .expect("Failed to listen");
+  // give it time to register
+  sleep(Duration::from_millis(100));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop(format!("udp:{}?punch=alice", server), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(100));
+  let received: Vec<_> = m_dest
+    .receive()
+    .expect("failed to receive")
+    .into_iter()
+    .map(|m| m.into_contents())
+    .collect();
+  assert_eq!(vec![vec![1, 2, 3]], received);
+}
+
+#[test]
+fn punching_to_nobody_fails() {
+  let server = rendezvous();
+  let mut udp = <UDP as Transport>::new("udp").expect("Failed to create transport");
+  let path = Path::parse(&format!("udp:{}?punch=nobody", server)).expect("bad path");
+  match udp.send(&path, &[1, 2, 3]) {
+    Err(fail::MesherFail::SendFailure(e)) => assert!(e.contains("nobody")),
+    other => panic!("expected SendFailure, got {:?}", other),
+  }
+}
+
+#[test]
+fn names_stay_with_their_key() {
+  let server = rendezvous();
+  // stands in between a listener and the server, to get a registration to send again
+  let relay = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  relay
+    .set_read_timeout(Some(Duration::from_secs(2)))
+    .expect("failed to set timeout");
+  let relay_addr = relay.local_addr().expect("no address");
+  let mut m_first = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+  m_first.add_transport::<UDP>("udp").expect("Failed to add transport");
+  m_first
+    .listen_on(&format!("udp:127.0.0.1:18686?rendezvous={}&name=bob", relay_addr))
+    .expect("Failed to listen");
+  let mut buf = [0; 512];
+  let (len, _) = relay.recv_from(&mut buf).expect("not registered");
+  let registration = buf[..len].to_vec();
+  relay.send_to(&registration, server).expect("failed to send");
+
+  let other = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  // sent again, from somewhere else
+  other.send_to(&registration, server).expect("failed to send");
+  // not signed at all
+  other.send_to(b"\xFEmesher-rv\x01bob", server).expect("failed to send");
+  // signed, but with a different key
+  let mut m_second = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+  m_second.add_transport::<UDP>("udp").expect("Failed to add transport");
+  m_second
+    .listen_on(&format!("udp:127.0.0.1:18687?rendezvous={}&name=bob", server))
+    .expect("Failed to listen");
+  sleep(Duration::from_millis(100));
+
+  other
+    .set_read_timeout(Some(Duration::from_secs(2)))
+    .expect("failed to set timeout");
+  other.send_to(b"\xFEmesher-rv\x02bob", server).expect("failed to send");
+  let (len, _) = other.recv_from(&mut buf).expect("no answer");
+  let mut expected = b"\xFEmesher-rv\x03".to_vec();
+  expected.extend_from_slice(relay_addr.to_string().as_bytes());
+  assert_eq!(expected, buf[..len].to_vec());
+}
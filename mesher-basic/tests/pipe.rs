@@ -0,0 +1,80 @@
+#![cfg(windows)]
+
+use mesher::prelude::*;
+use mesher_basic::NamedPipe;
+
+use std::{thread::sleep, time::Duration};
+
+fn make_mesher(listen: Option<&str>) -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::unsigned(vec![sk]);
+  m.add_transport::<NamedPipe>("pipe").expect("Failed to add transport");
+  if let Some(path) = listen {
+    m.listen_on(path).expect("Failed to listen");
+  }
+  (m, pk)
+}
+
+fn contents(m: &mut Mesher) -> Vec<Vec<u8>> {
+  m.receive()
+    .expect("failed to receive")
+    .into_iter()
+    .map(|m| m.into_contents())
+    .collect()
+}
+
+#[test]
+fn direct() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest, k_dest) = make_mesher(Some("pipe:mesher-test-direct"));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("pipe:mesher-test-direct".to_owned(), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(100));
+
+  assert_eq!(vec![vec![1, 2, 3]], contents(&mut m_dest));
+}
+
+#[test]
+fn several_senders() {
+  let (mut m_dest, k_dest) = make_mesher(Some("pipe:mesher-test-several"));
+  for i in 0..3 {
+    let (mut m_source, k_source) = make_mesher(None);
+    let mut packet = Packet::unsigned();
+    packet.add_hop("pipe:mesher-test-several".to_owned(), &k_source);
+    packet.add_message(&[i], &k_dest);
+    m_source.launch(packet).expect("Failed to send");
+  }
+
+  sleep(Duration::from_millis(200));
+
+  let mut received = contents(&mut m_dest);
+  received.sort();
+  assert_eq!(vec![vec![0], vec![1], vec![2]], received);
+}
+
+#[test]
+fn name_already_taken() {
+  let (_first, _) = make_mesher(Some("pipe:mesher-test-taken"));
+  let (_, sk) = encrypt::gen_keypair();
+  let mut second = Mesher::unsigned(vec![sk]);
+  second
+    .add_transport::<NamedPipe>("pipe")
+    .expect("Failed to add transport");
+  assert!(matches!(
+    second.listen_on("pipe:mesher-test-taken"),
+    Err(fail::MesherFail::ListenFailure(_))
+  ));
+}
+
+#[test]
+fn nobody_listening() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let mut packet = Packet::unsigned();
+  packet.add_hop("pipe:mesher-test-nobody?timeout=50ms".to_owned(), &k_source);
+  packet.add_message(&[1], &k_source);
+  assert!(m_source.launch(packet).is_err());
+}
@@ -0,0 +1,239 @@
+use mesher::{prelude::*, rpc::Rpc, Route};
+use mesher_basic::{exit::Exit, socks::Socks, TCP};
+
+use std::{
+  io::prelude::*,
+  net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+  thread::{sleep, spawn},
+  time::Duration,
+};
+
+/// A server which sends back everything it's sent, until the other end stops sending.
+fn echo_server() -> SocketAddr {
+  let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  let addr = listener.local_addr().expect("no address");
+  spawn(move || {
+    for conn in listener.incoming() {
+      let mut conn = conn.expect("failed to accept");
+      spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok(len @ 1..) = conn.read(&mut buf) {
+          if conn.write_all(&buf[..len]).is_err() {
+            return;
+          }
+        }
+      });
+    }
+  });
+  addr
+}
+
+fn tcp_mesher(port: u16) -> (Mesher, encrypt::PublicKey) {
+  let (pkey, skey) = encrypt::gen_keypair();
+  let mut mesher = Mesher::unsigned(vec![skey]);
+  mesher.add_transport::<TCP>("tcp").expect("failed to add transport");
+  mesher
+    .listen_on(&format!("tcp:localhost:{}", port))
+    .expect("failed to listen");
+  (mesher, pkey)
+}
+
+/// A server which takes connections, then never reads from them.
+fn stalled_server() -> SocketAddr {
+  let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to bind");
+  let addr = listener.local_addr().expect("no address");
+  // the connections are kept until the listener stops, which it never does
+  spawn(move || listener.incoming().for_each(std::mem::forget));
+  addr
+}
+
+/// Starts an exit on `exit_port`, returning its key.
+fn serve_exit(exit: Exit, exit_port: u16) -> encrypt::PublicKey {
+  let (mut exit_mesher, exit_pk) = tcp_mesher(exit_port);
+  spawn(move || {
+    let mut rpc = Rpc::new();
+    exit.serve(&mut rpc);
+    loop {
+      for message in exit_mesher
+        .receive_wait(Duration::from_millis(5))
+        .expect("failed to receive")
+      {
+        rpc.handle(&mut exit_mesher, &message).expect("failed to answer");
+      }
+    }
+  });
+  exit_pk
+}
+
+/// A route from a mesher listening on `client_port` to the exit on `exit_port`.
+fn route(client_port: u16, client_pk: encrypt::PublicKey, exit_port: u16, exit_pk: encrypt::PublicKey) -> Route {
+  Route::new()
+    .then(
+      Path::parse(&format!("tcp:localhost:{}", client_port)).unwrap(),
+      client_pk,
+    )
+    .then(Path::parse(&format!("tcp:localhost:{}", exit_port)).unwrap(), exit_pk)
+}
+
+/// Starts an exit on `exit_port` and a SOCKS frontend using it, with its mesher on `client_port`, returning the
+/// frontend's address.
+fn tunnel(exit: Exit, client_port: u16, exit_port: u16) -> SocketAddr {
+  let exit_pk = serve_exit(exit, exit_port);
+  let (mut client, client_pk) = tcp_mesher(client_port);
+  let route = route(client_port, client_pk, exit_port, exit_pk);
+  let mut socks = Socks::bind((Ipv4Addr::LOCALHOST, 0), route)
+    .expect("failed to bind")
+    .timeout(Duration::from_secs(2));
+  let addr = socks.local_addr().expect("no address");
+  spawn(move || socks.run(&mut client).expect("failed to tunnel"));
+  addr
+}
+
+/// Asks the SOCKS frontend to connect to `target`, returning the connection and the reply code.
+fn connect(socks: SocketAddr, target: SocketAddr) -> (TcpStream, u8) {
+  let mut conn = TcpStream::connect(socks).expect("failed to connect");
+  conn
+    .set_read_timeout(Some(Duration::from_secs(10)))
+    .expect("failed to set timeout");
+  conn.write_all(&[5, 1, 0]).expect("failed to greet");
+  let mut greeting = [0; 2];
+  conn.read_exact(&mut greeting).expect("no greeting");
+  assert_eq!([5, 0], greeting);
+
+  let mut request = vec![5, 1, 0, 3, 9];
+  request.extend_from_slice(b"localhost");
+  request.extend_from_slice(&target.port().to_be_bytes());
+  conn.write_all(&request).expect("failed to request");
+  let mut reply = [0; 10];
+  conn.read_exact(&mut reply).expect("no reply");
+  assert_eq!((5, 0, 1), (reply[0], reply[2], reply[3]));
+  (conn, reply[1])
+}
+
+#[test]
+fn tunnels_streams() {
+  let echo = echo_server();
+  let socks = tunnel(Exit::new().allow_private(true), 18692, 18693);
+  let (mut conn, code) = connect(socks, echo);
+  assert_eq!(0, code);
+
+  // bigger than a window, so it has to wait for the other end to catch up
+  let sent: Vec<u8> = (0..400_000u32).map(|i| (i % 251) as u8).collect();
+  let mut writer = conn.try_clone().expect("failed to clone");
+  let to_send = sent.clone();
+  let writing = spawn(move || {
+    writer.write_all(&to_send).expect("failed to send");
+    writer.shutdown(std::net::Shutdown::Write).expect("failed to shut down");
+  });
+  let mut received = vec![];
+  conn.read_to_end(&mut received).expect("failed to receive");
+  writing.join().expect("writing failed");
+  assert_eq!(sent.len(), received.len());
+  assert!(sent == received);
+}
+
+#[test]
+fn tunnels_several_streams_at_once() {
+  let echo = echo_server();
+  let socks = tunnel(Exit::new().allow_private(true), 18694, 18695);
+  let mut conns: Vec<_> = (0..4).map(|_| connect(socks, echo).0).collect();
+  for (i, conn) in conns.iter_mut().enumerate() {
+    conn
+      .write_all(format!("stream {}", i).as_bytes())
+      .expect("failed to send");
+  }
+  for (i, conn) in conns.iter_mut().enumerate() {
+    let expected = format!("stream {}", i);
+    let mut buf = vec![0; expected.len()];
+    conn.read_exact(&mut buf).expect("failed to receive");
+    assert_eq!(expected.as_bytes(), &buf[..]);
+  }
+}
+
+#[test]
+fn refuses_what_the_exit_does() {
+  let echo = echo_server();
+  let socks = tunnel(Exit::new(), 18696, 18697);
+  let (mut conn, code) = connect(socks, echo);
+  assert_eq!(1, code);
+  let mut rest = vec![];
+  assert_eq!(0, conn.read_to_end(&mut rest).expect("failed to read"));
+}
+
+#[test]
+fn refuses_what_socks_does() {
+  let socks = tunnel(Exit::new(), 18698, 18699);
+
+  let mut conn = TcpStream::connect(socks).expect("failed to connect");
+  conn.write_all(&[5, 1, 2]).expect("failed to greet");
+  let mut greeting = [0; 2];
+  conn.read_exact(&mut greeting).expect("no greeting");
+  assert_eq!([5, 0xFF], greeting);
+
+  let mut conn = TcpStream::connect(socks).expect("failed to connect");
+  // BIND, rather than CONNECT
+  conn
+    .write_all(&[5, 1, 0, 5, 2, 0, 1, 127, 0, 0, 1, 0, 80])
+    .expect("failed to request");
+  let mut reply = [0; 12];
+  conn.read_exact(&mut reply).expect("no reply");
+  assert_eq!([5, 0, 5, 7], reply[..4]);
+}
+
+#[test]
+fn stalled_servers_dont_hold_up_other_streams() {
+  let echo = echo_server();
+  let stalled = stalled_server();
+  let socks = tunnel(Exit::new().allow_private(true), 18700, 18701);
+
+  let (mut stuck, code) = connect(socks, stalled);
+  assert_eq!(0, code);
+  spawn(move || {
+    let chunk = vec![0; 64 * 1024];
+    while stuck.write_all(&chunk).is_ok() {}
+  });
+  // a single stream's slow enough that it takes a while to fill the buffers between the exit and the stalled server
+  sleep(Duration::from_secs(20));
+
+  let (mut conn, code) = connect(socks, echo);
+  assert_eq!(0, code);
+  for _ in 0..3 {
+    conn.write_all(b"still moving").expect("failed to send");
+    let mut buf = [0; 12];
+    conn.read_exact(&mut buf).expect("failed to receive");
+    assert_eq!(b"still moving", &buf);
+  }
+}
+
+#[test]
+fn refuses_chunks_that_are_too_big() {
+  let echo = echo_server();
+  let exit_pk = serve_exit(Exit::new().allow_private(true), 18703);
+  let (mut client, client_pk) = tcp_mesher(18702);
+  let route = route(18702, client_pk, 18703, exit_pk);
+  let mut rpc = Rpc::new();
+  // requests are the stream's ID, the sequence number, then what's asked for, as the frontend sends them
+  let mut call = |seq: u32, request: &[u8]| {
+    let mut body = vec![7; 16];
+    body.extend_from_slice(&seq.to_be_bytes());
+    body.extend_from_slice(request);
+    rpc
+      .call(&mut client, &route, "stream", &body, Duration::from_secs(5))
+      .expect("no response")
+  };
+
+  let mut open = vec![0, 0, 9];
+  open.extend_from_slice(b"localhost");
+  open.extend_from_slice(&echo.port().to_be_bytes());
+  assert_eq!(vec![0, 0, 0, 0, 0, 0], call(0, &open));
+
+  // data, with no flags, then its length
+  let data = |len: usize| {
+    let mut data = vec![1, 0];
+    data.extend_from_slice(&(len as u32).to_be_bytes());
+    data.extend(vec![b'a'; len]);
+    data
+  };
+  assert_eq!(1, call(1, &data(16 * 1024 + 1))[0]);
+  assert_eq!(0, call(1, &data(16 * 1024))[0]);
+}
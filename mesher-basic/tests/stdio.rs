@@ -0,0 +1,153 @@
+use mesher::{metrics::Counters, prelude::*, ListenStatus};
+use mesher_basic::Stdio;
+
+use std::{
+  io::{self, pipe, Cursor},
+  sync::Arc,
+  thread::sleep,
+  time::Duration,
+};
+
+fn make_mesher(listen: Option<&str>) -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::unsigned(vec![sk]);
+  m.add_transport::<Stdio>("stdio").expect("Failed to add transport");
+  if let Some(path) = listen {
+    m.listen_on(path).expect("Failed to listen");
+  }
+  (m, pk)
+}
+
+fn contents(m: &mut Mesher) -> Vec<Vec<u8>> {
+  m.receive()
+    .expect("failed to receive")
+    .into_iter()
+    .map(|m| m.into_contents())
+    .collect()
+}
+
+/// Attaches two streams which are each other's other end, like two ends of an SSH connection.
+fn attach_pair(a: &str, b: &str) {
+  let (a_reader, b_writer) = pipe().expect("Failed to make pipe");
+  let (b_reader, a_writer) = pipe().expect("Failed to make pipe");
+  Stdio::attach(a, a_reader, a_writer).expect("Failed to attach");
+  Stdio::attach(b, b_reader, b_writer).expect("Failed to attach");
+}
+
+/// Frames packets the way the transport expects them.
+fn framed(packets: &[&[u8]]) -> Vec<u8> {
+  let mut bytes = vec![];
+  for packet in packets {
+    bytes.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(packet);
+  }
+  bytes
+}
+
+#[test]
+fn both_ways() {
+  attach_pair("both-a", "both-b");
+  let (mut m_a, k_a) = make_mesher(Some("stdio:both-a"));
+  let (mut m_b, k_b) = make_mesher(Some("stdio:both-b"));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("stdio:both-a".to_owned(), &k_a);
+  packet.add_message(&[1, 2, 3], &k_b);
+  m_a.launch(packet).expect("Failed to send");
+  let mut packet = Packet::unsigned();
+  packet.add_hop("stdio:both-b".to_owned(), &k_b);
+  packet.add_message(&[4, 5], &k_a);
+  m_b.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(100));
+
+  assert_eq!(vec![vec![1, 2, 3]], contents(&mut m_b));
+  assert_eq!(vec![vec![4, 5]], contents(&mut m_a));
+}
+
+#[test]
+fn oversized_skipped() {
+  let (reader, writer) = pipe().expect("Failed to make pipe");
+  Stdio::attach("oversized-in", reader, io::sink()).expect("Failed to attach");
+  Stdio::attach("oversized-out", io::empty(), writer).expect("Failed to attach");
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest, k_dest) = make_mesher(None);
+  m_dest.set_max_packet_size(Some(500));
+  m_dest.listen_on("stdio:oversized-in").expect("Failed to listen");
+
+  for contents in [&[0; 1000][..], &[1]] {
+    let mut packet = Packet::unsigned();
+    packet.add_hop("stdio:oversized-out".to_owned(), &k_source);
+    packet.add_message(contents, &k_dest);
+    m_source.launch(packet).expect("Failed to send");
+  }
+
+  sleep(Duration::from_millis(100));
+
+  // the big one's skipped without losing track of where the small one starts
+  assert_eq!(vec![vec![1]], contents(&mut m_dest));
+}
+
+#[test]
+fn framing() {
+  Stdio::attach("framed", Cursor::new(framed(&[&[1, 2], &[3]])), io::sink()).expect("Failed to attach");
+  let (mut m, _) = make_mesher(None);
+  let counters = Arc::new(Counters::new());
+  m.set_metrics(Some(counters.clone()));
+  m.listen_on("stdio:framed").expect("Failed to listen");
+
+  sleep(Duration::from_millis(100));
+
+  // neither is a real packet, but they're each read whole and passed on, then dropped by the mesher
+  assert!(contents(&mut m).is_empty());
+  assert_eq!(2, counters.packets_received());
+}
+
+#[test]
+fn ended_stream_reported() {
+  Stdio::attach("ended", Cursor::new(vec![]), io::sink()).expect("Failed to attach");
+  let (mut m, _) = make_mesher(Some("stdio:ended"));
+
+  sleep(Duration::from_millis(100));
+
+  assert!(contents(&mut m).is_empty());
+  match &m.health()[..] {
+    [(_, ListenStatus::Failed(_))] => (),
+    other => panic!("unexpected health: {:?}", other),
+  }
+}
+
+#[test]
+fn one_listener_per_stream() {
+  Stdio::attach("shared", Cursor::new(vec![]), io::sink()).expect("Failed to attach");
+  let (mut first, _) = make_mesher(Some("stdio:shared"));
+  let (mut second, _) = make_mesher(None);
+  assert!(matches!(
+    second.listen_on("stdio:shared"),
+    Err(fail::MesherFail::ListenFailure(_))
+  ));
+  // once the first stops, the stream's free again, e.g. for a mesher that replaces it
+  first.stop_listening("stdio:shared").expect("Failed to stop listening");
+  second.listen_on("stdio:shared").expect("Failed to listen");
+}
+
+#[test]
+fn names_checked() {
+  let (mut m, k) = make_mesher(None);
+  Stdio::attach("taken", io::empty(), io::sink()).expect("Failed to attach");
+  assert!(matches!(
+    Stdio::attach("taken", io::empty(), io::sink()),
+    Err(fail::MesherFail::SetupFailure(_))
+  ));
+  assert!(matches!(
+    Stdio::attach("-", io::empty(), io::sink()),
+    Err(fail::MesherFail::SetupFailure(_))
+  ));
+  assert!(Stdio::detach("taken"));
+  assert!(!Stdio::detach("taken"));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("stdio:never-attached".to_owned(), &k);
+  packet.add_message(&[1], &k);
+  assert!(m.launch(packet).is_err());
+}
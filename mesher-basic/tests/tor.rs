@@ -0,0 +1,184 @@
+//! Runs the Tor transport against stand-ins for Tor's control port and SOCKS proxy, which forward straight to the
+//! listener's local port, like Tor would through an onion service.
+
+use mesher::prelude::*;
+use mesher_basic::Tor;
+
+use std::{
+  env, fs,
+  io::{self, prelude::*, BufReader},
+  net::{TcpListener, TcpStream},
+  sync::mpsc::{channel, Receiver},
+  thread::{sleep, spawn},
+  time::Duration,
+};
+
+/// Pretends to be Tor's control port, accepting one connection, which logs in with the cookie and adds an onion
+/// service with the ID `abcdef`.
+///
+/// Sends back the command that added the service, and the local port it forwards to.
+fn fake_control(cookie: &[u8], refuse: bool) -> (String, Receiver<(String, u16)>) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr = listener.local_addr().unwrap().to_string();
+  let cookie_file = env::temp_dir().join(format!("mesher-tor-cookie-{}", listener.local_addr().unwrap().port()));
+  fs::write(&cookie_file, cookie).unwrap();
+  let cookie_hex: String = cookie.iter().map(|b| format!("{:02x}", b)).collect();
+  let (tx, rx) = channel();
+  spawn(move || {
+    let (conn, _) = listener.accept().unwrap();
+    let mut out = conn.try_clone().unwrap();
+    for line in BufReader::new(conn).lines() {
+      let line = line.unwrap();
+      let reply = if line == "PROTOCOLINFO 1" {
+        format!(
+          "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"{}\"\r\n250 OK\r\n",
+          cookie_file.display()
+        )
+      } else if line == format!("AUTHENTICATE {}", cookie_hex) {
+        "250 OK\r\n".to_owned()
+      } else if line.starts_with("ADD_ONION ") && !refuse {
+        let local = line.rsplit(':').next().unwrap().parse().unwrap();
+        tx.send((line.clone(), local)).unwrap();
+        "250-ServiceID=abcdef\r\n250-PrivateKey=ED25519-V3:c2VjcmV0\r\n250 OK\r\n".to_owned()
+      } else {
+        "512 Unrecognized command\r\n".to_owned()
+      };
+      out.write_all(reply.as_bytes()).unwrap();
+    }
+  });
+  (addr, rx)
+}
+
+/// Pretends to be Tor's SOCKS proxy for one connection to `abcdef.onion:18900`, passing it on to `local_port`, or
+/// failing with `error` if it's given.
+fn fake_proxy(local_port: Receiver<u16>, error: Option<u8>) -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr = listener.local_addr().unwrap().to_string();
+  spawn(move || {
+    let (mut conn, _) = listener.accept().unwrap();
+    let mut greeting = [0; 3];
+    conn.read_exact(&mut greeting).unwrap();
+    assert_eq!([5, 1, 0], greeting);
+    conn.write_all(&[5, 0]).unwrap();
+    let mut request = [0; 5];
+    conn.read_exact(&mut request).unwrap();
+    assert_eq!([5, 1, 0, 3], request[..4]);
+    let mut host = vec![0; request[4] as usize + 2];
+    conn.read_exact(&mut host).unwrap();
+    assert_eq!(b"abcdef.onion\x49\xd4", &host[..]);
+    conn
+      .write_all(&[5, error.unwrap_or(0), 0, 1, 0, 0, 0, 0, 0, 0])
+      .unwrap();
+    if error.is_none() {
+      let mut local = TcpStream::connect(("127.0.0.1", local_port.recv().unwrap())).unwrap();
+      io::copy(&mut conn, &mut local).unwrap();
+    }
+  });
+  addr
+}
+
+fn make_mesher() -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::unsigned(vec![sk]);
+  m.add_transport::<Tor>("tor").expect("Failed to add transport");
+  (m, pk)
+}
+
+#[test]
+fn through_onion_service() {
+  let key_file = env::temp_dir().join("mesher-tor-test.key");
+  let _ = fs::remove_file(&key_file);
+  let (control, added) = fake_control(b"cookie", false);
+  let (mut m_dest, k_dest) = make_mesher();
+  let listen = format!("tor:18900?control={}&key={}", control, key_file.display());
+  m_dest.listen_on(&listen).expect("Failed to listen");
+
+  let (command, local) = added.recv().unwrap();
+  assert!(command.starts_with("ADD_ONION NEW:ED25519-V3 Port=18900,127.0.0.1:"));
+  assert_eq!(
+    Some("abcdef.onion".to_owned()),
+    Tor::address(key_file.to_str().unwrap())
+  );
+  assert_eq!(
+    "abcdef.onion\nED25519-V3:c2VjcmV0\n",
+    fs::read_to_string(&key_file).unwrap()
+  );
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    assert_eq!(0o600, fs::metadata(&key_file).unwrap().permissions().mode() & 0o777);
+  }
+
+  let (port_tx, port_rx) = channel();
+  port_tx.send(local).unwrap();
+  let proxy = fake_proxy(port_rx, None);
+  let (mut m_source, k_source) = make_mesher();
+  let mut packet = Packet::unsigned();
+  packet.add_hop(format!("tor:abcdef.onion:18900?proxy={}", proxy), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(200));
+
+  let received = m_dest.receive().expect("Failed to receive");
+  assert_eq!(1, received.len());
+  assert_eq!(&[1, 2, 3], received[0].contents());
+  assert_eq!(
+    listen,
+    received[0].source().and_then(|s| s.listen_path()).unwrap().to_string()
+  );
+}
+
+#[test]
+fn saved_key_reused() {
+  let key_file = env::temp_dir().join("mesher-tor-reuse.key");
+  fs::write(&key_file, "abcdef.onion\nED25519-V3:c2VjcmV0\n").unwrap();
+  let (control, added) = fake_control(b"another cookie", false);
+  let (mut m, _) = make_mesher();
+  m.listen_on(&format!("tor:18901?control={}&key={}", control, key_file.display()))
+    .expect("Failed to listen");
+  let (command, _) = added.recv().unwrap();
+  assert!(command.starts_with("ADD_ONION ED25519-V3:c2VjcmV0 Port=18901,"));
+}
+
+#[test]
+fn key_files_and_passwords_stay_out_of_commands() {
+  let key_file = env::temp_dir().join("mesher-tor-injected.key");
+  fs::write(&key_file, "abcdef.onion\nED25519-V3:c2VjcmV0 Flags=Detach\n").unwrap();
+  let (control, added) = fake_control(b"cookie", false);
+  let (mut m, _) = make_mesher();
+  assert!(matches!(
+    m.listen_on(&format!("tor:18903?control={}&key={}", control, key_file.display())),
+    Err(fail::MesherFail::ListenFailure(_))
+  ));
+  assert!(added.try_recv().is_err());
+
+  assert!(matches!(
+    m.listen_on(&format!("tor:18904?control={}&password=hunter2", control)),
+    Err(fail::MesherFail::InvalidURL(e)) if !e.contains("hunter2")
+  ));
+}
+
+#[test]
+fn refused_service() {
+  let (control, _) = fake_control(b"cookie", true);
+  let (mut m, _) = make_mesher();
+  assert!(matches!(
+    m.listen_on(&format!("tor:18902?control={}", control)),
+    Err(fail::MesherFail::ListenFailure(_))
+  ));
+}
+
+#[test]
+fn unreachable_onion() {
+  let (_, port_rx) = channel();
+  let proxy = fake_proxy(port_rx, Some(0xF0));
+  let (mut m, k) = make_mesher();
+  let mut packet = Packet::unsigned();
+  packet.add_hop(format!("tor:abcdef.onion:18900?proxy={}", proxy), &k);
+  packet.add_message(&[1], &k);
+  match m.launch(packet) {
+    Err(fail::MesherFail::SendFailure(e)) => assert!(e.contains("descriptor not found"), "{}", e),
+    other => panic!("unexpected result: {:?}", other),
+  }
+}
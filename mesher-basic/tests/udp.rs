@@ -0,0 +1,106 @@
+use mesher::prelude::*;
+use mesher_basic::UDP;
+
+use std::{thread::sleep, time::Duration};
+
+fn make_mesher(listen: Option<&str>) -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::unsigned(vec![sk]);
+  m.add_transport::<UDP>("udp").expect("Failed to add transport");
+  if let Some(path) = listen {
+    m.listen_on(path).expect("Failed to listen");
+  }
+  (m, pk)
+}
+
+fn contents(m: &mut Mesher) -> Vec<Vec<u8>> {
+  m.receive()
+    .expect("failed to receive")
+    .into_iter()
+    .map(|m| m.into_contents())
+    .collect()
+}
+
+#[test]
+fn direct() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest, k_dest) = make_mesher(Some("udp:127.0.0.1:18640"));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("udp:127.0.0.1:18640".to_owned(), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(100));
+
+  assert_eq!(vec![vec![1, 2, 3]], contents(&mut m_dest));
+}
+
+#[test]
+fn multicast_reaches_all_listeners() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest1, k_dest1) = make_mesher(Some("udp:239.255.77.77:18641"));
+  let (mut m_dest2, k_dest2) = make_mesher(Some("udp:239.255.77.77:18641"));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("udp:239.255.77.77:18641".to_owned(), &k_source);
+  packet.add_message(&[1], &k_dest1);
+  packet.add_message(&[2], &k_dest2);
+  m_source.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(100));
+
+  assert_eq!(vec![vec![1]], contents(&mut m_dest1));
+  assert_eq!(vec![vec![2]], contents(&mut m_dest2));
+}
+
+#[test]
+fn oversized_fails() {
+  let (mut m_source, k_source) = make_mesher(None);
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("udp:127.0.0.1:18642".to_owned(), &k_source);
+  packet.add_message(&[0; 70000], &k_source);
+  match m_source.launch(packet) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("expected send failure, got {:?}", other),
+  }
+}
+
+#[test]
+fn stop_listening() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest, k_dest) = make_mesher(Some("udp:127.0.0.1:18643"));
+  m_dest
+    .stop_listening("udp:127.0.0.1:18643")
+    .expect("Failed to stop listening");
+  sleep(Duration::from_millis(100));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("udp:127.0.0.1:18643".to_owned(), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+  sleep(Duration::from_millis(100));
+
+  assert!(contents(&mut m_dest).is_empty());
+}
+
+#[test]
+fn buffer_limited() {
+  let (mut m_source, k_source) = make_mesher(None);
+  let (mut m_dest, k_dest) = make_mesher(Some("udp:127.0.0.1:18644"));
+  m_dest.set_max_buffered(Some(2));
+
+  for i in 0..5 {
+    let mut packet = Packet::unsigned();
+    packet.add_hop("udp:127.0.0.1:18644".to_owned(), &k_source);
+    packet.add_message(&[i], &k_dest);
+    m_source.launch(packet).expect("Failed to send");
+  }
+  sleep(Duration::from_millis(100));
+
+  let (received, more) = m_dest.receive_limited(1).expect("Failed to receive");
+  assert_eq!(1, received.len());
+  assert!(more);
+  assert_eq!(1, contents(&mut m_dest).len());
+}
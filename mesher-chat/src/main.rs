@@ -0,0 +1,257 @@
+//! A small chat program, showing the whole stack: exchanging keys, routing through relays, and replying.
+//!
+//! ```text
+//! cargo run -p mesher-chat -- NICK PATH
+//! ```
+//!
+//! It listens on PATH, e.g. `tcp:[::1]:18540`, and prints its address, `KEY@PATH`, for others to add it by.
+//! Every running chat also relays packets for the others, so three of them are enough to try out routes.
+//!
+//! Commands, typed in while it's running:
+//!
+//! ```text
+//! /add NAME [RELAY_KEY@PATH ...] KEY@PATH   adds a contact, reached through the given relays, in order
+//! /msg NAME TEXT                           sends TEXT to a contact, along with a way to reply
+//! /reply TEXT                              replies to the last message, without needing to know who sent it
+//! /contacts                                lists everyone added so far, and the route to each
+//! /quit                                    stops
+//! ```
+
+use mesher::{prelude::*, Route};
+
+use std::{
+  collections::BTreeMap,
+  env,
+  io::{stdin, stdout, BufRead, Write},
+  process::exit,
+  sync::mpsc::{channel, Receiver},
+  thread::{sleep, spawn},
+  time::Duration,
+};
+
+/// How long to wait between checks for new messages and commands.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn parse_pkey(s: &str) -> Result<encrypt::PublicKey, String> {
+  s.parse().map_err(|_| {
+    format!(
+      "{:?} isn't a public key; keys are 72 hex characters, or 64 without the checksum",
+      s
+    )
+  })
+}
+
+/// Reads an address, as printed when a chat starts.
+fn parse_address(s: &str) -> Result<(Path, encrypt::PublicKey), String> {
+  let at = s
+    .find('@')
+    .ok_or_else(|| format!("{:?} isn't an address, like KEY@PATH", s))?;
+  let path =
+    Path::parse(&s[at + 1..]).map_err(|_| format!("{:?} isn't a valid path, like tcp:host:port", &s[at + 1..]))?;
+  Ok((path, parse_pkey(&s[..at])?))
+}
+
+/// What's sent in every message: who it's from, so it can be shown, and the key to reply to, then the text.
+fn encode(pkey: &encrypt::PublicKey, nick: &str, text: &str) -> Vec<u8> {
+  format!("{} {}\n{}", pkey, nick, text).into_bytes()
+}
+
+/// Reads a message back into the key to reply to, the sender's nickname, and the text.
+fn decode(contents: &[u8]) -> Option<(encrypt::PublicKey, &str, &str)> {
+  let contents = std::str::from_utf8(contents).ok()?;
+  let (header, text) = contents.split_once('\n')?;
+  let (key, nick) = header.split_once(' ')?;
+  Some((parse_pkey(key).ok()?, nick, text))
+}
+
+struct Chat {
+  nick: String,
+  pkey: encrypt::PublicKey,
+  path: Path,
+  mesher: Mesher,
+  contacts: BTreeMap<String, Route>,
+  /// The last message received, and the key its sender said to reply to.
+  last: Option<(Message, encrypt::PublicKey)>,
+}
+
+impl Chat {
+  fn new(nick: &str, path: &str) -> Result<Chat, String> {
+    let (pkey, skey) = encrypt::gen_keypair();
+    let mut mesher = Mesher::unsigned(vec![skey]);
+    mesher_basic::register_defaults(&mut mesher).map_err(|e| format!("couldn't set up transports: {:?}", e))?;
+    mesher
+      .listen_on(path)
+      .map_err(|e| format!("couldn't listen on {}: {:?}", path, e))?;
+    mesher.on_failure(|f| eprintln!("warning: {:?}", f));
+    Ok(Chat {
+      nick: nick.to_owned(),
+      pkey,
+      path: Path::parse(path).expect("Path was just listened on"),
+      mesher,
+      contacts: BTreeMap::new(),
+      last: None,
+    })
+  }
+
+  /// Runs a command, returning whether to keep going.
+  fn command(&mut self, line: &str) -> Result<bool, String> {
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    match command {
+      "/add" => self.add(rest)?,
+      "/msg" => {
+        let (name, text) = rest
+          .split_once(' ')
+          .ok_or("/msg needs a contact and something to say")?;
+        self.send(name, text)?;
+      }
+      "/reply" => self.reply(rest)?,
+      "/contacts" => {
+        for (name, route) in &self.contacts {
+          let hops: Vec<_> = route.nodes().skip(1).map(|(path, _)| path.to_string()).collect();
+          println!("{}: {}", name, hops.join(" -> "));
+        }
+      }
+      "/quit" => return Ok(false),
+      _ => {
+        return Err(format!(
+          "unknown command {:?}; try /add, /msg, /reply, /contacts, or /quit",
+          command
+        ))
+      }
+    }
+    Ok(true)
+  }
+
+  fn add(&mut self, args: &str) -> Result<(), String> {
+    let mut args = args.split_whitespace();
+    let name = args.next().ok_or("/add needs a name and an address")?;
+    // the route starts here, so replies can be routed back
+    let mut route = Route::new().then(self.path.clone(), self.pkey);
+    for address in args {
+      let (path, pkey) = parse_address(address)?;
+      route = route.then(path, pkey);
+    }
+    if route.len() < 2 {
+      return Err("/add needs an address, like KEY@PATH, after the name".to_owned());
+    }
+    let (_, to) = route.nodes().last().expect("Just checked the route's length");
+    println!(
+      "added {}, {} hop(s) away, with fingerprint {}",
+      name,
+      route.len() - 1,
+      to.fingerprint()
+    );
+    self.contacts.insert(name.to_owned(), route);
+    Ok(())
+  }
+
+  fn send(&mut self, name: &str, text: &str) -> Result<(), String> {
+    let route = self
+      .contacts
+      .get(name)
+      .ok_or_else(|| format!("no contact called {}", name))?;
+    let (_, to) = route.nodes().last().expect("Routes have at least two nodes");
+    let to = *to;
+    let mut packet = Packet::unsigned();
+    packet.via_route(route);
+    // the reply comes back the way the message went, through the same relays
+    let mut reply = packet.add_reply_path().expect("A new packet has room for a reply path");
+    reply.via_route(&route.reversed());
+    reply.use_for_message(&encode(&self.pkey, &self.nick, text), &to);
+    self
+      .mesher
+      .launch(packet)
+      .map_err(|e| format!("couldn't send: {:?}", e))
+  }
+
+  fn reply(&mut self, text: &str) -> Result<(), String> {
+    let (message, to) = self.last.as_ref().ok_or("nothing to reply to yet")?;
+    let mut packet = Packet::unsigned();
+    packet
+      .reply_to(message)
+      .map_err(|_| "the last message didn't come with a way to reply".to_owned())?;
+    packet.add_message(&encode(&self.pkey, &self.nick, text), to);
+    self
+      .mesher
+      .launch(packet)
+      .map_err(|e| format!("couldn't reply: {:?}", e))
+  }
+
+  fn receive(&mut self) -> Result<(), String> {
+    for message in self
+      .mesher
+      .receive()
+      .map_err(|e| format!("couldn't receive: {:?}", e))?
+    {
+      match decode(message.contents()) {
+        Some((from, nick, text)) => {
+          println!("<{}> {}", nick, text);
+          self.last = Some((message, from));
+        }
+        None => println!("(got a message that isn't from a chat)"),
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Reads lines from stdin on another thread, so the chat can keep receiving while waiting for them.
+fn read_lines() -> Receiver<String> {
+  let (tx, rx) = channel();
+  spawn(move || {
+    for line in stdin().lock().lines() {
+      let sent = line.map(|line| tx.send(line).is_ok());
+      if !matches!(sent, Ok(true)) {
+        return;
+      }
+    }
+  });
+  rx
+}
+
+fn run(nick: &str, path: &str) -> Result<(), String> {
+  let mut chat = Chat::new(nick, path)?;
+  println!("You're {}; others can add you with:", nick);
+  println!("  /add {} {}@{}", nick, chat.pkey, chat.path);
+  println!(
+    "Your key's fingerprint is {}, for them to check.",
+    chat.pkey.fingerprint()
+  );
+  let lines = read_lines();
+  loop {
+    for line in lines.try_iter() {
+      let line = line.trim();
+      if line.is_empty() {
+        continue;
+      }
+      let result = if line.starts_with('/') {
+        chat.command(line)
+      } else {
+        Err("commands start with /, e.g. /msg NAME TEXT".to_owned())
+      };
+      match result {
+        Ok(true) => (),
+        Ok(false) => return Ok(()),
+        Err(e) => eprintln!("{}", e),
+      }
+    }
+    chat.receive()?;
+    let _ = stdout().flush();
+    sleep(POLL_INTERVAL);
+  }
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let (nick, path) = match &args[..] {
+    [nick, path] => (nick, path),
+    _ => {
+      eprintln!("Usage: mesher-chat NICK PATH, e.g. mesher-chat alice tcp:[::1]:18540");
+      exit(2);
+    }
+  };
+  if let Err(e) = run(nick, path) {
+    eprintln!("mesher-chat: {}", e);
+    exit(1);
+  }
+}
@@ -0,0 +1,12 @@
+//! Generates a fresh set of packet test vectors, in the format
+//! [`mesher::testvectors`](https://docs.rs/mesher/*/mesher/testvectors/index.html) reads, and writes them to stdout.
+//!
+//! Usage: `mesher-testvectors > new-vectors.txt`.
+//! Every run makes new keys and packets, so to cover something new, add the vectors that test it to
+//! `mesher/testvectors/packets.txt` rather than replacing the whole file.
+
+use mesher::testvectors;
+
+fn main() {
+  print!("{}", testvectors::to_file(&testvectors::generate()));
+}
@@ -0,0 +1,715 @@
+//! A command-line tool for sending and receiving messages over the standard transports.
+//!
+//! ```text
+//! mesher keygen FILE
+//!     Generates a keypair, writes the secret key to FILE in hex, and prints the public key, and its fingerprint to
+//!     stderr. FILE mustn't exist yet, and on Unix, only its owner can read it.
+//! mesher send --to KEY|NAME [--via [KEY@]PATH ...] [--sign FILE] [--out FILE] [--contacts FILE] < data
+//!     Sends stdin as one message to KEY, or the contact called NAME, through each --via in order.
+//!     Every --via but the last needs the key of the node listening there; the last defaults to the recipient.
+//!     Without any --via, the contact's saved route is used.
+//!     With --out, the packet is written to FILE instead of being sent.
+//! mesher recv --key FILE --listen PATH [--listen PATH ...] [--count N]
+//!     Listens on each PATH and writes every message received to stdout, stopping after N if --count is given.
+//! mesher inspect PACKET [--key FILE ...]
+//!     Describes the packet in the file PACKET: how many chunks it has, and what's in the ones the keys can open.
+//! mesher ping --listen PATH --via KEY@PATH [--via KEY@PATH ...] [--count N] [--timeout SECS]
+//!     Sends N probes (4 by default) through each --via in order, and says how long the last node took to answer each.
+//!     Answers come back the same way, to PATH, so it has to be reachable from there.
+//! mesher trace --listen PATH --via KEY@PATH [--via KEY@PATH ...] [--count N] [--timeout SECS]
+//!     Like ping, but every node answers, to show how far along the route probes get.
+//! mesher qr (--key FILE | --route FILE) [--png FILE]
+//!     Shows the public key for the secret key in FILE, or the route in FILE, as a QR code, for another device to scan.
+//!     With --png, the code is written to FILE as an image instead.
+//! mesher scan [TEXT]
+//!     Reads a key or route from TEXT, or stdin without it, e.g. as scanned from a QR code, and describes it.
+//! mesher contacts add NAME KEY [--via [KEY@]PATH ...] [--contacts FILE]
+//!     Saves KEY as the contact called NAME, reached through each --via in order, like send's.
+//! mesher contacts remove NAME [--contacts FILE]
+//! mesher contacts list [--contacts FILE]
+//! mesher rendezvous ADDRESS
+//!     Runs a rendezvous server on ADDRESS, e.g. 0.0.0.0:18600, which introduces UDP listeners behind NATs to the nodes
+//!     sending to them, so they can punch through.
+//! mesher exit --key FILE --listen PATH [--listen PATH ...] [--port N ...]
+//!     Runs an exit node, which makes the HTTP requests it's sent and sends back the responses, only to the given ports
+//!     if any --port is given, and never to private addresses.
+//! mesher fetch URL --listen PATH --via KEY@PATH [--via KEY@PATH ...] [--method M] [--header 'NAME: VALUE' ...]
+//!              [--data FILE] [--timeout SECS]
+//!     Has the exit node at the last --via make the HTTP request, and writes the response's body to stdout, and its
+//!     status to stderr. The response comes back the same way, to PATH, like ping's answers.
+//! mesher socks ADDRESS --listen PATH --via KEY@PATH [--via KEY@PATH ...] [--timeout SECS]
+//!     Runs a SOCKS5 server on ADDRESS, e.g. 127.0.0.1:1080, which tunnels its clients' connections to the exit node at
+//!     the last --via. Responses come back to PATH, like fetch's.
+//! ```
+//!
+//! Contacts are kept in FILE, or $MESHER_CONTACTS, or `.mesher-contacts` in the home directory.
+//!
+//! Keys are given in hex, as printed by `keygen`, with a checksum that catches typos; key files can hold either hex or
+//! the raw bytes.
+//! Paths can use the `tcp` and `udp` transports.
+
+use mesher::{
+  contacts::{Contact, Contacts},
+  crypto::{parse_key, write_key},
+  ping::Probe,
+  prelude::*,
+  qr::Exchange,
+  rpc::Rpc,
+  Route,
+};
+use mesher_basic::{
+  exit::{self as http_exit, Exit, HttpRequest},
+  nat::Rendezvous,
+  socks::Socks,
+  TCP, UDP,
+};
+
+use std::{
+  env, fs,
+  io::{stdin, stdout, ErrorKind, Read, Write},
+  path::PathBuf,
+  process::exit,
+  thread::sleep,
+  time::Duration,
+};
+
+const USAGE: &str = "Usage:
+  mesher keygen FILE
+  mesher send --to KEY|NAME [--via [KEY@]PATH ...] [--sign FILE] [--out FILE] [--contacts FILE] < data
+  mesher recv --key FILE --listen PATH [--listen PATH ...] [--count N]
+  mesher inspect PACKET [--key FILE ...]
+  mesher ping --listen PATH --via KEY@PATH [--via KEY@PATH ...] [--count N] [--timeout SECS]
+  mesher trace --listen PATH --via KEY@PATH [--via KEY@PATH ...] [--count N] [--timeout SECS]
+  mesher qr (--key FILE | --route FILE) [--png FILE]
+  mesher scan [TEXT]
+  mesher contacts add NAME KEY [--via [KEY@]PATH ...] [--contacts FILE]
+  mesher contacts remove NAME [--contacts FILE]
+  mesher contacts list [--contacts FILE]
+  mesher rendezvous ADDRESS
+  mesher exit --key FILE --listen PATH [--listen PATH ...] [--port N ...]
+  mesher fetch URL --listen PATH --via KEY@PATH [--via KEY@PATH ...] [--method M] [--header 'NAME: VALUE' ...]
+               [--data FILE] [--timeout SECS]
+  mesher socks ADDRESS --listen PATH --via KEY@PATH [--via KEY@PATH ...] [--timeout SECS]";
+
+/// How long each wait for new messages lasts, before checking whether enough have come in.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Reads a key file holding `len` bytes, either raw or in hex.
+fn read_key(file: &str, len: usize) -> Result<Vec<u8>, String> {
+  let bytes = fs::read(file).map_err(|e| format!("couldn't read key file {}: {}", file, e))?;
+  if bytes.len() == len {
+    return Ok(bytes);
+  }
+  match std::str::from_utf8(&bytes).ok().and_then(parse_key) {
+    Some(bytes) if bytes.len() == len => Ok(bytes),
+    _ => Err(format!("{} should hold a {}-byte key, either raw or in hex", file, len)),
+  }
+}
+
+fn parse_pkey(s: &str) -> Result<encrypt::PublicKey, String> {
+  s.parse().map_err(|_| {
+    format!(
+      "{:?} isn't a public key; keys are 72 hex characters, or 64 without the checksum",
+      s
+    )
+  })
+}
+
+/// Pulls out the values of every `--name VALUE` flag, in order, and fails on anything else.
+fn parse_flags(args: &[String], known: &[&str]) -> Result<Vec<(String, String)>, String> {
+  let mut flags = vec![];
+  let mut args = args.iter();
+  while let Some(flag) = args.next() {
+    let name = flag
+      .strip_prefix("--")
+      .filter(|n| known.contains(n))
+      .ok_or_else(|| format!("unexpected argument {:?}", flag))?;
+    let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+    flags.push((name.to_owned(), value.clone()));
+  }
+  Ok(flags)
+}
+
+fn values<'a>(flags: &'a [(String, String)], name: &str) -> Vec<&'a str> {
+  flags
+    .iter()
+    .filter(|(n, _)| n == name)
+    .map(|(_, v)| v.as_str())
+    .collect()
+}
+
+fn single<'a>(flags: &'a [(String, String)], name: &str) -> Result<Option<&'a str>, String> {
+  match values(flags, name)[..] {
+    [] => Ok(None),
+    [v] => Ok(Some(v)),
+    _ => Err(format!("--{} can only be given once", name)),
+  }
+}
+
+fn make_mesher(keys: Vec<encrypt::SecretKey>) -> Result<Mesher, String> {
+  Mesher::builder()
+    .own_keys(keys)
+    .transport::<TCP>("tcp")
+    .transport::<UDP>("udp")
+    .build()
+    .map_err(|e| format!("couldn't set up transports: {:?}", e))
+}
+
+/// The path routes from `send` start at, which is never used, since it just launches the packet.
+fn sender_path() -> Path {
+  Path::parse("local:sender").expect("Path is valid")
+}
+
+/// Builds the route from the sender through every `--via`, ending at the recipient.
+fn route(sender: encrypt::PublicKey, to: encrypt::PublicKey, vias: &[&str]) -> Result<Route, String> {
+  route_from(sender_path(), sender, Some(to), vias)
+}
+
+/// Opens the address book in `--contacts`, or `$MESHER_CONTACTS`, or `.mesher-contacts` in the home directory.
+fn open_contacts(flags: &[(String, String)]) -> Result<Contacts, String> {
+  let file = match single(flags, "contacts")? {
+    Some(file) => PathBuf::from(file),
+    None => env::var_os("MESHER_CONTACTS")
+      .map(PathBuf::from)
+      .or_else(|| {
+        env::var_os("HOME")
+          .or_else(|| env::var_os("USERPROFILE"))
+          .map(|home| PathBuf::from(home).join(".mesher-contacts"))
+      })
+      .ok_or("couldn't find a home directory to keep contacts in; give --contacts FILE or set $MESHER_CONTACTS")?,
+  };
+  Contacts::open(&file).map_err(|e| format!("couldn't open contacts: {:?}", e))
+}
+
+/// Builds the route from the sender, at the given path, through every `--via`.
+/// The last `--via` can leave out the key if `to` is given, and uses it instead.
+fn route_from(
+  own_path: Path,
+  sender: encrypt::PublicKey,
+  to: Option<encrypt::PublicKey>,
+  vias: &[&str],
+) -> Result<Route, String> {
+  if vias.is_empty() {
+    return Err("at least one --via is needed, to say where to send the packet".to_owned());
+  }
+  let mut route = Route::new().then(own_path, sender);
+  for (i, via) in vias.iter().enumerate() {
+    let (path, pkey) = match (via.find('@'), to) {
+      (Some(at), _) => (&via[at + 1..], parse_pkey(&via[..at])?),
+      (None, Some(to)) if i == vias.len() - 1 => (*via, to),
+      (None, None) => {
+        return Err(format!(
+          "--via {} needs the key of the node listening there, as KEY@PATH",
+          via
+        ))
+      }
+      (None, Some(_)) => {
+        return Err(format!(
+          "--via {} needs the key of the node listening there, as KEY@PATH, since it's not the last",
+          via
+        ))
+      }
+    };
+    let path = Path::parse(path).map_err(|_| format!("{:?} isn't a valid path, like tcp:host:port", path))?;
+    route = route.then(path, pkey);
+  }
+  Ok(route)
+}
+
+fn keygen(args: &[String]) -> Result<(), String> {
+  let file = match args {
+    [file] => file,
+    _ => return Err("keygen takes exactly one argument, the file to write the secret key to".to_owned()),
+  };
+  let mut options = fs::OpenOptions::new();
+  // never overwrite a key that's already there, in case it's still in use
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  let mut out = options.open(file).map_err(|e| match e.kind() {
+    ErrorKind::AlreadyExists => format!("{} already exists, so it wasn't overwritten", file),
+    _ => format!("couldn't create {}: {}", file, e),
+  })?;
+  let (pkey, skey) = encrypt::gen_keypair();
+  out
+    .write_all((write_key(skey.as_bytes()) + "\n").as_bytes())
+    .map_err(|e| format!("couldn't write {}: {}", file, e))?;
+  println!("{}", pkey);
+  eprintln!("fingerprint: {}", pkey.fingerprint());
+  Ok(())
+}
+
+fn send(args: &[String]) -> Result<(), String> {
+  let flags = parse_flags(args, &["to", "via", "sign", "out", "contacts"])?;
+  let to = single(&flags, "to")?.ok_or("--to is required")?;
+  let vias = values(&flags, "via");
+  let (own_pkey, own_skey) = encrypt::gen_keypair();
+  let (to, route) = match parse_pkey(to) {
+    Ok(key) => (key, route(own_pkey, key, &vias)?),
+    Err(_) => {
+      let contacts = open_contacts(&flags)?;
+      let contact = contacts
+        .get(to)
+        .ok_or_else(|| format!("{:?} isn't a public key or a contact", to))?;
+      let route = match vias[..] {
+        [] => contact
+          .route_from(sender_path(), own_pkey)
+          .ok_or_else(|| format!("there's no saved route to {}, so give one with --via", to))?,
+        _ => route(own_pkey, contact.key(), &vias)?,
+      };
+      (contact.key(), route)
+    }
+  };
+
+  let mut data = vec![];
+  stdin()
+    .read_to_end(&mut data)
+    .map_err(|e| format!("couldn't read stdin: {}", e))?;
+  let mut packet = match single(&flags, "sign")? {
+    Some(file) => Packet::signed(sign::SecretKey::from_slice(&read_key(file, 64)?).expect("Length was just checked")),
+    None => Packet::unsigned(),
+  };
+  packet.via_route(&route);
+  packet.add_message(&data, &to);
+
+  if let Some(file) = single(&flags, "out")? {
+    let bytes = packet
+      .into_bytes()
+      .map_err(|e| format!("couldn't build packet: {:?}", e))?;
+    return fs::write(file, bytes).map_err(|e| format!("couldn't write {}: {}", file, e));
+  }
+  let mut mesher = make_mesher(vec![own_skey])?;
+  mesher.launch(packet).map_err(|e| format!("couldn't send: {:?}", e))
+}
+
+fn recv(args: &[String]) -> Result<(), String> {
+  let flags = parse_flags(args, &["key", "listen", "count"])?;
+  let key = read_key(single(&flags, "key")?.ok_or("--key is required")?, 32)?;
+  let count = match single(&flags, "count")? {
+    Some(c) => Some(
+      c.parse::<usize>()
+        .map_err(|_| format!("--count {} isn't a number", c))?,
+    ),
+    None => None,
+  };
+  let listens = values(&flags, "listen");
+  if listens.is_empty() {
+    return Err("at least one --listen is needed, to say where to receive packets".to_owned());
+  }
+
+  let mut mesher = make_mesher(vec![
+    encrypt::SecretKey::from_slice(&key).expect("Length was just checked")
+  ])?;
+  for path in listens {
+    mesher
+      .listen_on(path)
+      .map_err(|e| format!("couldn't listen on {}: {:?}", path, e))?;
+  }
+  mesher.on_failure(|f| eprintln!("warning: {:?}", f));
+  let mut received = 0;
+  while count.is_none_or(|c| received < c) {
+    for message in mesher
+      .receive_wait(POLL_INTERVAL)
+      .map_err(|e| format!("couldn't receive: {:?}", e))?
+    {
+      let mut out = stdout();
+      out
+        .write_all(message.contents())
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("couldn't write to stdout: {}", e))?;
+      received += 1;
+    }
+  }
+  Ok(())
+}
+
+/// Sends probes along the route given by the flags, for `ping` or `trace`, and prints how long each node took to answer.
+fn probe(args: &[String], trace: bool) -> Result<(), String> {
+  let flags = parse_flags(args, &["listen", "via", "count", "timeout"])?;
+  let listen = single(&flags, "listen")?.ok_or("--listen is required, so answers have somewhere to come back to")?;
+  let number = |name: &str, default: u64| match single(&flags, name)? {
+    Some(n) => n.parse::<u64>().map_err(|_| format!("--{} {} isn't a number", name, n)),
+    None => Ok(default),
+  };
+  let count = number("count", if trace { 1 } else { 4 })?;
+  let timeout = Duration::from_secs(number("timeout", 5)?);
+
+  let (own_pkey, own_skey) = encrypt::gen_keypair();
+  let own_path = Path::parse(listen).map_err(|_| format!("{:?} isn't a valid path, like tcp:host:port", listen))?;
+  let route = route_from(own_path, own_pkey, None, &values(&flags, "via"))?;
+  let mut mesher = make_mesher(vec![own_skey])?;
+  mesher
+    .listen_on(listen)
+    .map_err(|e| format!("couldn't listen on {}: {:?}", listen, e))?;
+  mesher.on_failure(|f| eprintln!("warning: {:?}", f));
+
+  let mut answered = 0;
+  for i in 0..count {
+    if i > 0 {
+      sleep(Duration::from_secs(1));
+    }
+    let mut probe = if trace {
+      Probe::trace(&mut mesher, &route)
+    } else {
+      Probe::ping(&mut mesher, &route)
+    }
+    .map_err(|e| format!("couldn't send probe: {:?}", e))?;
+    probe
+      .wait(&mut mesher, timeout)
+      .map_err(|e| format!("couldn't receive: {:?}", e))?;
+    for (hop, node) in probe.hops().iter().enumerate() {
+      let time = match node.round_trip() {
+        Some(rtt) => format!("{:.1} ms", rtt.as_secs_f64() * 1000.0),
+        None if node.failed() => "unreachable from the hop before".to_owned(),
+        None => format!("no answer within {}s", timeout.as_secs()),
+      };
+      if trace {
+        println!("{:>2}  {}  {}", hop + 1, node.path(), time);
+      } else {
+        println!("{}: {}", node.path(), time);
+      }
+    }
+    if probe.round_trip().is_some() {
+      answered += 1;
+    }
+    if trace && i + 1 < count {
+      println!();
+    }
+  }
+  match answered {
+    0 => Err("no probes made it to the end of the route and back".to_owned()),
+    _ => Ok(()),
+  }
+}
+
+fn inspect(args: &[String]) -> Result<(), String> {
+  let (file, flags) = match args.split_first() {
+    Some((file, rest)) if !file.starts_with("--") => (file, parse_flags(rest, &["key"])?),
+    _ => return Err("inspect needs the file holding the packet to look at".to_owned()),
+  };
+  let keys = values(&flags, "key")
+    .into_iter()
+    .map(|k| read_key(k, 32).map(|k| encrypt::SecretKey::from_slice(&k).expect("Length was just checked")))
+    .collect::<Result<Vec<_>, _>>()?;
+  let packet = fs::read(file).map_err(|e| format!("couldn't read {}: {}", file, e))?;
+  let dump = Packet::debug_decode(&packet, &keys).map_err(|e| format!("{} isn't a valid packet: {:?}", file, e))?;
+  print!("{}", dump);
+  Ok(())
+}
+
+fn qr(args: &[String]) -> Result<(), String> {
+  let flags = parse_flags(args, &["key", "route", "png"])?;
+  let exchange = match (single(&flags, "key")?, single(&flags, "route")?) {
+    (Some(file), None) => Exchange::Key(
+      encrypt::SecretKey::from_slice(&read_key(file, 32)?)
+        .expect("Length was just checked")
+        .public_key(),
+    ),
+    (None, Some(file)) => {
+      let text = fs::read_to_string(file).map_err(|e| format!("couldn't read {}: {}", file, e))?;
+      Exchange::Route(
+        text
+          .parse()
+          .map_err(|e| format!("{} isn't a valid route: {:?}", file, e))?,
+      )
+    }
+    _ => return Err("qr needs exactly one of --key or --route".to_owned()),
+  };
+  let code = exchange
+    .to_qr()
+    .ok_or("that's too much to fit in a QR code; try a shorter route")?;
+  match single(&flags, "png")? {
+    Some(file) => fs::write(file, code.to_png(8)).map_err(|e| format!("couldn't write {}: {}", file, e)),
+    None => {
+      print!("{}", code.to_terminal());
+      println!("{}", exchange);
+      Ok(())
+    }
+  }
+}
+
+fn scan(args: &[String]) -> Result<(), String> {
+  let text = match args {
+    [] => {
+      let mut text = String::new();
+      stdin()
+        .read_to_string(&mut text)
+        .map_err(|e| format!("couldn't read stdin: {}", e))?;
+      text
+    }
+    [text] => text.clone(),
+    _ => return Err("scan takes at most one argument, the scanned text".to_owned()),
+  };
+  let exchange: Exchange = text
+    .parse()
+    .map_err(|e| format!("that isn't a key or route: {:?}", e))?;
+  match exchange {
+    Exchange::Key(pkey) => println!("encryption key {}\nfingerprint {}", pkey, pkey.fingerprint()),
+    Exchange::SignKey(pkey) => println!("signing key {}\nfingerprint {}", pkey, pkey.fingerprint()),
+    Exchange::Route(route) => {
+      println!("route with {} node(s):", route.len());
+      for (path, pkey) in route.nodes() {
+        println!("  {} (fingerprint {})", path, pkey.fingerprint());
+      }
+    }
+  }
+  Ok(())
+}
+
+fn contacts(args: &[String]) -> Result<(), String> {
+  let (command, rest) = args
+    .split_first()
+    .ok_or("contacts needs a command: add, remove, or list")?;
+  let positional = rest.iter().take_while(|a| !a.starts_with("--")).count();
+  let (positional, flags) = rest.split_at(positional);
+  match (command.as_str(), positional) {
+    ("add", [name, key]) => {
+      let flags = parse_flags(flags, &["via", "contacts"])?;
+      let key = parse_pkey(key)?;
+      let vias = values(&flags, "via");
+      let mut contact = Contact::new(key);
+      if !vias.is_empty() {
+        // the sender's left off, since it's whoever's sending at the time
+        let full = route_from(sender_path(), key, Some(key), &vias)?;
+        let relays = full
+          .nodes()
+          .skip(1)
+          .fold(Route::new(), |route, (path, pkey)| route.then(path.clone(), *pkey));
+        contact = contact.via(relays);
+      }
+      open_contacts(&flags)?
+        .add(name, contact)
+        .map_err(|e| format!("couldn't add {}: {:?}", name, e))
+    }
+    ("remove", [name]) => {
+      let flags = parse_flags(flags, &["contacts"])?;
+      match open_contacts(&flags)?.remove(name) {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(format!("there's no contact called {}", name)),
+        Err(e) => Err(format!("couldn't remove {}: {:?}", name, e)),
+      }
+    }
+    ("list", []) => {
+      let flags = parse_flags(flags, &["contacts"])?;
+      for (name, contact) in open_contacts(&flags)?.iter() {
+        println!(
+          "{} {} (fingerprint {})",
+          name,
+          contact.key(),
+          contact.key().fingerprint()
+        );
+        for (path, pkey) in contact.route().nodes() {
+          println!("  {}@{}", pkey, path);
+        }
+      }
+      Ok(())
+    }
+    _ => Err("contacts takes add NAME KEY, remove NAME, or list".to_owned()),
+  }
+}
+
+fn rendezvous(args: &[String]) -> Result<(), String> {
+  let addr = match args {
+    [addr] => addr,
+    _ => return Err("rendezvous takes exactly one argument, the address to listen on".to_owned()),
+  };
+  let mut server = Rendezvous::bind(addr.as_str()).map_err(|e| format!("{:?}", e))?;
+  eprintln!(
+    "rendezvous server listening on {}",
+    server.local_addr().map_err(|e| format!("{:?}", e))?
+  );
+  server.run().map_err(|e| format!("{:?}", e))
+}
+
+fn exit_node(args: &[String]) -> Result<(), String> {
+  let flags = parse_flags(args, &["key", "listen", "port"])?;
+  let key = read_key(single(&flags, "key")?.ok_or("--key is required")?, 32)?;
+  let ports = values(&flags, "port")
+    .into_iter()
+    .map(|p| p.parse::<u16>().map_err(|_| format!("--port {} isn't a port", p)))
+    .collect::<Result<Vec<_>, _>>()?;
+  let listens = values(&flags, "listen");
+  if listens.is_empty() {
+    return Err("at least one --listen is needed, to say where to receive requests".to_owned());
+  }
+
+  let mut mesher = make_mesher(vec![
+    encrypt::SecretKey::from_slice(&key).expect("Length was just checked")
+  ])?;
+  for path in listens {
+    mesher
+      .listen_on(path)
+      .map_err(|e| format!("couldn't listen on {}: {:?}", path, e))?;
+  }
+  mesher.on_failure(|f| eprintln!("warning: {:?}", f));
+  let mut rpc = Rpc::new();
+  let exit_node = Exit::new();
+  if ports.is_empty() {
+    exit_node
+  } else {
+    exit_node.ports(ports)
+  }
+  .serve(&mut rpc);
+  loop {
+    for message in mesher
+      .receive_wait(POLL_INTERVAL)
+      .map_err(|e| format!("couldn't receive: {:?}", e))?
+    {
+      if let Err(e) = rpc.handle(&mut mesher, &message) {
+        eprintln!("warning: couldn't answer a request: {:?}", e);
+      }
+    }
+  }
+}
+
+fn fetch(args: &[String]) -> Result<(), String> {
+  let (url, flags) = match args.split_first() {
+    Some((url, rest)) if !url.starts_with("--") => (
+      url,
+      parse_flags(rest, &["listen", "via", "method", "header", "data", "timeout"])?,
+    ),
+    _ => return Err("fetch needs the URL to request".to_owned()),
+  };
+  let listen =
+    single(&flags, "listen")?.ok_or("--listen is required, so the response has somewhere to come back to")?;
+  let timeout = match single(&flags, "timeout")? {
+    Some(t) => Duration::from_secs(t.parse().map_err(|_| format!("--timeout {} isn't a number", t))?),
+    None => Duration::from_secs(30),
+  };
+  let mut request = HttpRequest::new(single(&flags, "method")?.unwrap_or("GET"), url);
+  for header in values(&flags, "header") {
+    let (name, value) = header
+      .split_once(':')
+      .ok_or_else(|| format!("--header {:?} should look like 'NAME: VALUE'", header))?;
+    request = request.header(name.trim(), value.trim());
+  }
+  if let Some(file) = single(&flags, "data")? {
+    request = request.body(fs::read(file).map_err(|e| format!("couldn't read {}: {}", file, e))?);
+  }
+
+  // a fresh key, so the exit can't tell which requests came from the same place
+  let (own_pkey, own_skey) = encrypt::gen_keypair();
+  let own_path = Path::parse(listen).map_err(|_| format!("{:?} isn't a valid path, like tcp:host:port", listen))?;
+  let route = route_from(own_path, own_pkey, None, &values(&flags, "via"))?;
+  let mut mesher = make_mesher(vec![own_skey])?;
+  mesher
+    .listen_on(listen)
+    .map_err(|e| format!("couldn't listen on {}: {:?}", listen, e))?;
+  mesher.on_failure(|f| eprintln!("warning: {:?}", f));
+
+  let response = match http_exit::fetch(&mut Rpc::new(), &mut mesher, &route, &request, timeout) {
+    Ok(response) => response,
+    Err(fail::MesherFail::ExitFailure(why)) => return Err(format!("the exit couldn't fetch {}: {}", url, why)),
+    Err(e) => return Err(format!("couldn't fetch {}: {:?}", url, e)),
+  };
+  eprintln!("{}", response.status);
+  let mut out = stdout();
+  out
+    .write_all(&response.body)
+    .and_then(|_| out.flush())
+    .map_err(|e| format!("couldn't write to stdout: {}", e))
+}
+
+fn socks(args: &[String]) -> Result<(), String> {
+  let (addr, flags) = match args.split_first() {
+    Some((addr, rest)) if !addr.starts_with("--") => (addr, parse_flags(rest, &["listen", "via", "timeout"])?),
+    _ => return Err("socks needs the address to listen for SOCKS clients on".to_owned()),
+  };
+  let listen = single(&flags, "listen")?.ok_or("--listen is required, so responses have somewhere to come back to")?;
+
+  // a fresh key, like fetch's
+  let (own_pkey, own_skey) = encrypt::gen_keypair();
+  let own_path = Path::parse(listen).map_err(|_| format!("{:?} isn't a valid path, like tcp:host:port", listen))?;
+  let route = route_from(own_path, own_pkey, None, &values(&flags, "via"))?;
+  let mut server = Socks::bind(addr.as_str(), route).map_err(|e| format!("{:?}", e))?;
+  if let Some(t) = single(&flags, "timeout")? {
+    server = server.timeout(Duration::from_secs(
+      t.parse().map_err(|_| format!("--timeout {} isn't a number", t))?,
+    ));
+  }
+  let mut mesher = make_mesher(vec![own_skey])?;
+  mesher
+    .listen_on(listen)
+    .map_err(|e| format!("couldn't listen on {}: {:?}", listen, e))?;
+  mesher.on_failure(|f| eprintln!("warning: {:?}", f));
+  eprintln!(
+    "SOCKS server listening on {}",
+    server.local_addr().map_err(|e| format!("{:?}", e))?
+  );
+  server.run(&mut mesher).map_err(|e| format!("{:?}", e))
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let result = match args.first().map(String::as_str) {
+    Some("keygen") => keygen(&args[1..]),
+    Some("send") => send(&args[1..]),
+    Some("recv") => recv(&args[1..]),
+    Some("inspect") => inspect(&args[1..]),
+    Some("ping") => probe(&args[1..], false),
+    Some("trace") => probe(&args[1..], true),
+    Some("qr") => qr(&args[1..]),
+    Some("scan") => scan(&args[1..]),
+    Some("contacts") => contacts(&args[1..]),
+    Some("rendezvous") => rendezvous(&args[1..]),
+    Some("exit") => exit_node(&args[1..]),
+    Some("fetch") => fetch(&args[1..]),
+    Some("socks") => socks(&args[1..]),
+    _ => {
+      eprintln!("{}", USAGE);
+      exit(2);
+    }
+  };
+  if let Err(e) = result {
+    eprintln!("mesher: {}", e);
+    exit(1);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn vias_make_route() {
+    let (sender, _) = encrypt::gen_keypair();
+    let (relay, _) = encrypt::gen_keypair();
+    let (to, _) = encrypt::gen_keypair();
+    let relay_via = format!("{}@tcp:[::1]:1", relay);
+    let route = route(sender, to, &[&relay_via, "udp:[::1]:2"]).expect("Failed to build route");
+    let nodes: Vec<_> = route.nodes().map(|(p, k)| (p.as_str().to_owned(), *k)).collect();
+    assert_eq!(
+      vec![
+        ("local:sender".to_owned(), sender),
+        ("tcp:[::1]:1".to_owned(), relay),
+        ("udp:[::1]:2".to_owned(), to)
+      ],
+      nodes
+    );
+
+    assert!(super::route(sender, to, &[]).is_err());
+    assert!(super::route(sender, to, &["tcp:[::1]:1", "udp:[::1]:2"]).is_err());
+
+    // probes need every node's key, since every node might be asked to answer
+    let own_path = Path::parse("tcp:[::1]:3").expect("Path is valid");
+    assert!(route_from(own_path.clone(), sender, None, &["udp:[::1]:2"]).is_err());
+    let route = route_from(own_path, sender, None, &[&relay_via]).expect("Failed to build route");
+    assert_eq!(
+      Some((&Path::parse("tcp:[::1]:1").unwrap(), &relay)),
+      route.nodes().last()
+    );
+  }
+
+  #[test]
+  fn flags_parsed() {
+    let args: Vec<_> = ["--to", "a", "--via", "b", "--via", "c"]
+      .iter()
+      .map(|s| s.to_string())
+      .collect();
+    let flags = parse_flags(&args, &["to", "via"]).expect("Failed to parse");
+    assert_eq!(Ok(Some("a")), single(&flags, "to"));
+    assert_eq!(vec!["b", "c"], values(&flags, "via"));
+    assert!(parse_flags(&args, &["to"]).is_err());
+    assert!(parse_flags(&args[..1], &["to"]).is_err());
+  }
+}
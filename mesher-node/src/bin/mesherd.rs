@@ -0,0 +1,159 @@
+//! A standalone relay: reads a [config file](https://docs.rs/mesher/*/mesher/config/index.html), sets up the mesher it
+//! describes, and relays packets until it's killed.
+//!
+//! Usage: `mesherd [--stdio] CONFIG`.
+//! Logging is controlled with `RUST_LOG`, e.g. `RUST_LOG=debug mesherd relay.toml`, and defaults to `info`.
+//! With `--stdio`, it also listens on its own stdin, and can be sent to through its stdout, as `stdio:-`, so relays can
+//! be chained through SSH, e.g. with `ssh host mesherd --stdio relay.toml` run by the
+//! [stdio transport](https://docs.rs/mesher-basic/*/mesher_basic/struct.Stdio.html) on this end; logs go to stderr
+//! instead.
+//! Packet counts are logged every minute.
+//! On Unix, `SIGHUP` reloads the config, replacing the old mesher with a new one.
+//! Configs can use the `tcp`, `udp`, `stdio`, `tor`, and `i2p` transport kinds, and on Windows `pipe`, for named pipes.
+
+use mesher::{config::Config, metrics::Counters, prelude::*};
+
+use std::{
+  env,
+  process::exit,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+/// How long to wait for new packets before checking whether to reload or log the counts.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often to log the packet counts.
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many more times to try loading the config when reloading, in case the old listeners haven't stopped yet.
+const RELOAD_RETRIES: u32 = 10;
+
+/// How long to wait for the old mesher's listeners to stop when reloading.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Set by the `SIGHUP` handler, and cleared once the config's been reloaded.
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn watch_for_reload() {
+  // only touches an atomic, so it's safe to run in a signal handler
+  let registered = unsafe { signal_hook_registry::register(libc::SIGHUP, || RELOAD.store(true, Ordering::SeqCst)) };
+  if let Err(e) = registered {
+    tracing::warn!("couldn't watch for SIGHUP, so the config can't be reloaded: {}", e);
+  }
+}
+
+#[cfg(not(unix))]
+fn watch_for_reload() {}
+
+/// Loads the config and builds the mesher it describes, with the standard transports available, and listening on stdin
+/// too if `stdio` is set.
+fn load(path: &str, stdio: bool, counters: &Arc<Counters>) -> fail::Result<Mesher> {
+  let config = Config::load(path)?
+    .transport_kind::<mesher_basic::TCP>("tcp")
+    .transport_kind::<mesher_basic::UDP>("udp")
+    .transport_kind::<mesher_basic::Stdio>("stdio")
+    .transport_kind::<mesher_basic::Tor>("tor")
+    .transport_kind::<mesher_basic::I2P>("i2p");
+  #[cfg(windows)]
+  let config = config.transport_kind::<mesher_basic::NamedPipe>("pipe");
+  let mut mesher = config.build()?;
+  if stdio {
+    if !mesher.transports().contains(&"stdio") {
+      mesher.add_transport::<mesher_basic::Stdio>("stdio")?;
+    }
+    mesher.listen_on("stdio:-")?;
+  }
+  mesher.set_metrics(Some(counters.clone()));
+  mesher.on_failure(|f| tracing::warn!("{:?}", f));
+  Ok(mesher)
+}
+
+fn log_stats(counters: &Counters) {
+  tracing::info!(
+    received = counters.packets_received(),
+    sent = counters.packets_sent(),
+    forwarded = counters.packets_forwarded(),
+    undecryptable = counters.packets_undecryptable(),
+    "packet counts"
+  );
+}
+
+fn main() {
+  let mut args: Vec<String> = env::args().skip(1).collect();
+  let stdio = args.first().is_some_and(|a| a == "--stdio");
+  if stdio {
+    args.remove(0);
+  }
+  let path = match &args[..] {
+    [p] => p.clone(),
+    _ => {
+      eprintln!("Usage: mesherd [--stdio] CONFIG");
+      exit(2);
+    }
+  };
+  let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+    .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+  if stdio {
+    // stdout's carrying packets
+    tracing_subscriber::fmt()
+      .with_env_filter(filter)
+      .with_writer(std::io::stderr)
+      .init();
+  } else {
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+  }
+
+  let counters = Arc::new(Counters::new());
+  let mut mesher = match load(&path, stdio, &counters) {
+    Ok(m) => m,
+    Err(e) => {
+      eprintln!("Failed to start from {}: {:?}", path, e);
+      exit(1);
+    }
+  };
+  watch_for_reload();
+  tracing::info!(config = %path, transports = ?mesher.transports(), "relaying");
+
+  let mut last_stats = Instant::now();
+  loop {
+    if RELOAD.swap(false, Ordering::SeqCst) {
+      // the old mesher has to go first, so its listeners free up their addresses for the new one
+      if let Err(e) = mesher.close(CLOSE_TIMEOUT) {
+        tracing::warn!("failed to close the old mesher cleanly: {:?}", e);
+      }
+      let mut loaded = load(&path, stdio, &counters);
+      for _ in 0..RELOAD_RETRIES {
+        if loaded.is_ok() {
+          break;
+        }
+        sleep(POLL_INTERVAL * 2);
+        loaded = load(&path, stdio, &counters);
+      }
+      mesher = match loaded {
+        Ok(m) => {
+          tracing::info!(config = %path, "reloaded config");
+          m
+        }
+        Err(e) => {
+          tracing::error!(config = %path, "failed to reload config: {:?}", e);
+          exit(1);
+        }
+      };
+    }
+    match mesher.receive_wait(POLL_INTERVAL) {
+      Ok(messages) if !messages.is_empty() => tracing::info!(count = messages.len(), "received messages for this node"),
+      Ok(_) => (),
+      Err(e) => tracing::warn!("failed to receive: {:?}", e),
+    }
+    if last_stats.elapsed() >= STATS_INTERVAL {
+      log_stats(&counters);
+      last_stats = Instant::now();
+    }
+  }
+}
@@ -0,0 +1,106 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use mesher::{bench, debug_transports::InMemory, prelude::*};
+
+/// Builds a packet with one message for `to` and `others` more chunks for random other keys.
+fn packet_for(to: &encrypt::PublicKey, others: usize, hints: bool) -> Packet {
+  let mut packet = Packet::unsigned();
+  packet.use_key_hints(hints);
+  for _ in 0..others {
+    packet.add_message(&[0; 64], &encrypt::gen_keypair().0);
+  }
+  packet.add_message(&[1; 64], to);
+  packet
+}
+
+fn build(c: &mut Criterion) {
+  let mut group = c.benchmark_group("build");
+  let (pk, _) = encrypt::gen_keypair();
+  for chunks in [1, 8, 32] {
+    group.bench_with_input(BenchmarkId::new("unsigned", chunks), &chunks, |b, &chunks| {
+      b.iter(|| {
+        let mut packet = Packet::unsigned();
+        for _ in 0..chunks {
+          packet.add_message(&[0; 64], &pk);
+        }
+        packet.into_bytes()
+      })
+    });
+    let (_, ssk) = sign::gen_keypair();
+    group.bench_with_input(BenchmarkId::new("signed", chunks), &chunks, |b, &chunks| {
+      b.iter(|| {
+        let mut packet = Packet::signed(ssk.clone_secret());
+        for _ in 0..chunks {
+          packet.add_message(&[0; 64], &pk);
+        }
+        packet.into_bytes()
+      })
+    });
+  }
+  group.finish();
+}
+
+fn serialize(c: &mut Criterion) {
+  let (pk, _) = encrypt::gen_keypair();
+  let packet = packet_for(&pk, 31, false);
+  c.bench_function("serialize/32 chunks", |b| {
+    b.iter_batched(|| packet.clone(), Packet::into_bytes, BatchSize::SmallInput)
+  });
+}
+
+fn trial_decrypt(c: &mut Criterion) {
+  let mut group = c.benchmark_group("trial_decrypt");
+  for hints in [false, true] {
+    let name = if hints { "hinted" } else { "plain" };
+    for key_count in [1, 4, 16] {
+      let keys: Vec<_> = (0..key_count).map(|_| encrypt::gen_keypair()).collect();
+      let packet = packet_for(&keys[0].0, 15, hints)
+        .into_bytes()
+        .expect("Failed to serialize");
+      let skeys: Vec<_> = keys.into_iter().map(|(_, sk)| sk).collect();
+      group.bench_with_input(BenchmarkId::new(name, key_count), &skeys, |b, skeys| {
+        b.iter(|| bench::open_packet(&packet, skeys))
+      });
+    }
+  }
+  let (pk, sk) = encrypt::gen_keypair();
+  let (spk, ssk) = sign::gen_keypair();
+  let mut signed = Packet::signed(ssk);
+  for _ in 0..15 {
+    signed.add_message(&[0; 64], &encrypt::gen_keypair().0);
+  }
+  signed.add_message(&[1; 64], &pk);
+  let signed = signed.into_bytes().expect("Failed to serialize");
+  group.bench_function("signed/1", |b| {
+    b.iter(|| bench::open_signed_packet(&signed, std::slice::from_ref(&sk), &[spk]))
+  });
+  group.finish();
+}
+
+fn inmem_throughput(c: &mut Criterion) {
+  const PACKETS: u64 = 64;
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut mesher = Mesher::builder()
+    .own_key(sk)
+    .transport::<InMemory>("inmem")
+    .listen_on("inmem:bench-throughput")
+    .build()
+    .expect("Failed to build mesher");
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:bench-throughput".to_owned(), &pk);
+  packet.add_message(&[0; 256], &pk);
+
+  let mut group = c.benchmark_group("inmem");
+  group.throughput(Throughput::Elements(PACKETS));
+  group.bench_function("launch and receive", |b| {
+    b.iter(|| {
+      for _ in 0..PACKETS {
+        mesher.launch(packet.clone()).expect("Failed to launch");
+      }
+      mesher.receive().expect("Failed to receive")
+    })
+  });
+  group.finish();
+}
+
+criterion_group!(benches, build, serialize, trial_decrypt, inmem_throughput);
+criterion_main!(benches);
@@ -0,0 +1,133 @@
+//! Tells which IP addresses are on the public internet, for anything that shouldn't be used to reach the rest, like
+//! [forward policies](../struct.ForwardPolicy.html#method.deny_local_addresses) and exits.
+
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+
+/// Whether an address is on the public internet, rather than e.g. this machine or its network.
+///
+/// IPv6 addresses with an IPv4 address inside, which reach it through some translation or tunnel, are only as public as
+/// the IPv4 address is.
+pub fn is_public(ip: IpAddr) -> bool {
+  match ip {
+    IpAddr::V4(ip) => {
+      let [a, b, c, _] = ip.octets();
+      !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // shared address space, i.e. carrier-grade NAT
+        || a == 100 && (64..128).contains(&b)
+        // IETF protocol assignments
+        || a == 192 && b == 0 && c == 0
+        // benchmarking
+        || a == 198 && (18..20).contains(&b)
+        // reserved, along with the broadcast address
+        || a >= 240
+        || a == 0)
+    }
+    IpAddr::V6(ip) => {
+      let embedded = |high: u16, low: u16| is_public(Ipv4Addr::from(u32::from(high) << 16 | u32::from(low)).into());
+      if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public(v4.into());
+      }
+      match ip.segments() {
+        // NAT64
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => embedded(high, low),
+        // 6to4
+        [0x2002, high, low, ..] => embedded(high, low),
+        // IPv4-compatible, which also covers the loopback and unspecified addresses
+        [0, 0, 0, 0, 0, 0, high, low] => embedded(high, low),
+        [first, second, ..] => {
+          !(ip.is_multicast()
+            // local-use NAT64, which is translated somewhere private
+            || first == 0x64 && second == 0xff9b
+            // unique local
+            || first & 0xfe00 == 0xfc00
+            // link-local
+            || first & 0xffc0 == 0xfe80
+            // site-local, which is deprecated but still private where it's used
+            || first & 0xffc0 == 0xfec0)
+        }
+      }
+    }
+  }
+}
+
+/// Every address a location like `host:port`, `[::1]`, or `localhost` refers to, resolving it if it's a name.
+///
+/// Names are resolved the same way the OS resolves them to connect, so unusual ways of writing an address, like
+/// `127.1` or `2130706433`, come out as the address they mean.
+/// Locations that can't be resolved, e.g. because they aren't IP-based at all, refer to none.
+pub(crate) fn resolve(location: &str) -> Vec<IpAddr> {
+  let addrs = location.to_socket_addrs().or_else(|_| {
+    let host = location.trim_start_matches('[').trim_end_matches(']');
+    (host, 0).to_socket_addrs()
+  });
+  match addrs {
+    Ok(addrs) => addrs.map(|a| a.ip()).collect(),
+    Err(_) => vec![],
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn public_addresses_told_apart() {
+    for ip in &[
+      "1.1.1.1",
+      "8.8.8.8",
+      "2606:4700::1111",
+      "::ffff:8.8.8.8",
+      "64:ff9b::808:808",
+      "2002:808:808::1",
+      "::8.8.8.8",
+    ] {
+      assert!(is_public(ip.parse().unwrap()), "{} should be public", ip);
+    }
+    for ip in &[
+      "127.0.0.1",
+      "10.1.2.3",
+      "172.16.0.1",
+      "192.168.1.1",
+      "169.254.0.1",
+      "100.64.0.1",
+      "224.0.0.1",
+      "255.255.255.255",
+      "0.0.0.0",
+      "::1",
+      "::",
+      "fd00::1",
+      "fe80::1",
+      "ff02::1",
+      "::ffff:10.0.0.1",
+      "198.18.0.1",
+      "198.19.255.255",
+      "192.0.0.8",
+      "240.0.0.1",
+      "64:ff9b::7f00:1",
+      "64:ff9b:1::808:808",
+      "2002:a00:1::1",
+      "2002:7f00:1::",
+      "::127.0.0.1",
+      "::10.0.0.1",
+      "fec0::1",
+    ] {
+      assert!(!is_public(ip.parse().unwrap()), "{} shouldn't be public", ip);
+    }
+  }
+
+  #[test]
+  fn odd_addresses_resolved() {
+    let localhost = IpAddr::from([127, 0, 0, 1]);
+    assert_eq!(vec![localhost], resolve("127.1:22"));
+    assert_eq!(vec![localhost], resolve("2130706433:22"));
+    assert_eq!(vec![localhost], resolve("0x7f.1:22"));
+    assert_eq!(vec![IpAddr::from([0u16, 0, 0, 0, 0, 0, 0, 1])], resolve("[::1]"));
+    assert!(resolve("localhost:22").iter().all(|ip| ip.is_loopback()));
+    assert!(resolve("not a location").is_empty());
+  }
+}
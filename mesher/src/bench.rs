@@ -0,0 +1,34 @@
+//! Internal functions exposed for the benchmarks in `benches/`, with the `bench` feature.
+//!
+//! None of this is part of the public API, and it can change in any release.
+
+use crate::prelude::*;
+
+/// Parses a packet and decrypts every chunk it can with the given keys, like an unsigned mesher receiving it.
+///
+/// Returns how many chunks were opened.
+pub fn open_packet(packet: &[u8], keys: &[encrypt::SecretKey]) -> fail::Result<usize> {
+  let crypto = crate::crypto::default_backend();
+  Packet::deserialize(packet, keys, &[], crypto.as_ref()).map(|chunks| chunks.len())
+}
+
+/// Parses a packet and decrypts every chunk it can with the given keys, like a signed mesher receiving it.
+///
+/// Returns how many chunks were opened.
+pub fn open_signed_packet(
+  packet: &[u8],
+  keys: &[encrypt::SecretKey],
+  sender_keys: &[sign::PublicKey],
+) -> fail::Result<usize> {
+  let crypto = crate::crypto::default_backend();
+  let mut failures = vec![];
+  Packet::deserialize_signed(
+    packet,
+    &crate::packet::OwnKeys::new(keys, &[]),
+    sender_keys,
+    &[],
+    crypto.as_ref(),
+    &mut failures,
+  )
+  .map(|chunks| chunks.len())
+}
@@ -0,0 +1,513 @@
+//! Contains helpers for stitching together meshes that use different transports, e.g. a LoRa mesh and a TCP one.
+//!
+//! A [`Bridge`](struct.Bridge.html) is a gateway which doesn't need any keys: it receives packets on one transport and
+//! launches them, unopened, along another, according to the links it's given.
+//! Since it never opens them, the packets it relays don't have to be routed through it; the nodes on either side just
+//! send to the path it's listening on.
+//!
+//! Links between transports with very different packet sizes need the packets split up on the way across.
+//! [`Framed`](struct.Framed.html) wraps the transport with the smaller packets, splitting everything sent through it
+//! into frames that fit, and reassembling them on the other end.
+//! Every node on that side of the bridge, not just the bridge itself, has to use it, with the same
+//! [frame size](fn.set_frame_size.html).
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::{
+//!   bridge::{set_frame_size, Bridge, Framed},
+//!   debug_transports::InMemory,
+//! };
+//!
+//! // pretend the `radio` scheme only carries 64 bytes at a time
+//! set_frame_size("radio", 64);
+//!
+//! let mut bridge = Bridge::new();
+//! bridge.add_transport::<InMemory>("inmem").expect("Failed to add transport");
+//! bridge.add_transport::<Framed<InMemory>>("radio").expect("Failed to add transport");
+//! bridge.link("inmem:bridge-doc-gateway", "radio:bridge-doc-broadcast").expect("Failed to link");
+//!
+//! let (receiver_pk, receiver_sk) = encrypt::gen_keypair();
+//! let mut receiver = Mesher::unsigned(vec![receiver_sk]);
+//! receiver.add_transport::<Framed<InMemory>>("radio").expect("Failed to add transport");
+//! receiver.listen_on("radio:bridge-doc-broadcast").expect("Failed to listen");
+//!
+//! let (sender_pk, sender_sk) = encrypt::gen_keypair();
+//! let mut sender = Mesher::unsigned(vec![sender_sk]);
+//! sender.add_transport::<InMemory>("inmem").expect("Failed to add transport");
+//! let mut packet = Packet::unsigned();
+//! packet.add_hop("inmem:bridge-doc-gateway".to_owned(), &sender_pk);
+//! packet.add_message(&[7; 500], &receiver_pk);
+//! sender.launch(packet).expect("Failed to launch");
+//!
+//! assert_eq!(1, bridge.relay().expect("Failed to relay"));
+//! let received = receiver.receive().expect("Failed to receive");
+//! assert_eq!(vec![7; 500], received[0].contents());
+//! ```
+
+use crate::{prelude::*, transport::check_supported, ListenStatus, Mesher};
+
+use std::{
+  collections::{BTreeMap, HashMap, VecDeque},
+  sync::Mutex,
+  time::Duration,
+};
+
+/// Marks a frame written by [`Framed`](struct.Framed.html).
+const MAGIC: &[u8; 2] = b"MF";
+/// The magic, the packet's ID, the frame's index, and how many frames the packet was split into.
+const HEADER_LEN: usize = 2 + 4 + 2 + 2;
+/// The most packets reassembled at once; when another starts arriving, the one that started first is dropped.
+const MAX_PARTIAL: usize = 64;
+
+lazy_static! {
+  static ref FRAME_SIZES: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+}
+
+/// Sets the biggest frame, in bytes, that [`Framed`](struct.Framed.html) transports for the given scheme will send,
+/// including the 10-byte header on each.
+///
+/// It's read when the transport's created, so it has to be set before the transport's added to a mesher or bridge.
+/// If it's never set, frames are at most [`DEFAULT_FRAME_SIZE`](constant.DEFAULT_FRAME_SIZE.html) bytes.
+///
+/// # Panics
+///
+/// If `size` isn't big enough to fit the header and at least one byte of the packet.
+pub fn set_frame_size(scheme: &str, size: usize) {
+  assert!(
+    size > HEADER_LEN,
+    "frames must be bigger than their {}-byte header",
+    HEADER_LEN
+  );
+  FRAME_SIZES
+    .lock()
+    .expect("poisoned lock?")
+    .insert(scheme.to_owned(), size);
+}
+
+/// How big frames are, if [`set_frame_size`](fn.set_frame_size.html) isn't called: small enough for a LoRa packet.
+pub const DEFAULT_FRAME_SIZE: usize = 200;
+
+/// The frames received so far of one packet.
+struct Partial {
+  frames: Vec<Option<Vec<u8>>>,
+  missing: usize,
+}
+
+/// Identifies the packet a frame belongs to: where it came from, and the ID the sender gave it.
+type PartialKey = (Option<Path>, Option<String>, u32);
+
+/// Wraps a transport which can only carry small packets, splitting bigger ones into frames on the way out and putting
+/// them back together on the way in.
+///
+/// Add it to a mesher or [bridge](struct.Bridge.html) in place of the transport it wraps, after setting the frame size
+/// with [`set_frame_size`](fn.set_frame_size.html).
+/// Each frame carries a 10-byte header, which is how the other end puts them back together, so everything receiving
+/// what it sends has to use it too.
+///
+/// A packet is only received once every one of its frames has arrived, from the same source; if any are lost, so is
+/// the whole packet.
+/// Frames can arrive in any order, and a few packets can be put back together at once, but if too many are started
+/// without being finished, the oldest are dropped.
+/// Anything received that isn't a frame is dropped, too.
+pub struct Framed<T: Transport> {
+  inner: T,
+  frame_size: usize,
+  max_packet_size: Option<usize>,
+  next_id: u32,
+  partial: HashMap<PartialKey, Partial>,
+  started: VecDeque<PartialKey>,
+}
+
+impl<T: Transport> Framed<T> {
+  /// Reassembles every frame, returning the packets that were finished.
+  fn reassemble_all(&mut self, frames: Vec<(Source, Vec<u8>)>) -> Vec<(Source, Vec<u8>)> {
+    frames
+      .into_iter()
+      .filter_map(|(source, frame)| self.reassemble(&source, &frame).map(|packet| (source, packet)))
+      .collect()
+  }
+
+  /// Puts a frame with the others from its packet, returning the whole packet if it was the last one missing.
+  fn reassemble(&mut self, source: &Source, frame: &[u8]) -> Option<Vec<u8>> {
+    if frame.len() < HEADER_LEN || &frame[..2] != MAGIC {
+      return None;
+    }
+    let id = u32::from_be_bytes([frame[2], frame[3], frame[4], frame[5]]);
+    let index = u16::from_be_bytes([frame[6], frame[7]]) as usize;
+    let count = u16::from_be_bytes([frame[8], frame[9]]) as usize;
+    if index >= count {
+      return None;
+    }
+    let body = &frame[HEADER_LEN..];
+    if count == 1 {
+      return Some(body.to_vec());
+    }
+    let max_body = self.frame_size - HEADER_LEN;
+    if let Some(max) = self.max_packet_size {
+      if (count - 1) * max_body >= max {
+        return None;
+      }
+    }
+
+    let key = (source.listen_path().cloned(), source.remote().map(str::to_owned), id);
+    if !self.partial.contains_key(&key) {
+      if self.started.len() >= MAX_PARTIAL {
+        if let Some(oldest) = self.started.pop_front() {
+          self.partial.remove(&oldest);
+        }
+      }
+      self.started.push_back(key.clone());
+      self.partial.insert(
+        key.clone(),
+        Partial {
+          frames: vec![None; count],
+          missing: count,
+        },
+      );
+    }
+    let partial = self.partial.get_mut(&key).expect("just inserted");
+    if partial.frames.len() != count {
+      return None;
+    }
+    if partial.frames[index].is_none() {
+      partial.frames[index] = Some(body.to_vec());
+      partial.missing -= 1;
+    }
+    if partial.missing > 0 {
+      return None;
+    }
+    let partial = self.partial.remove(&key).expect("just checked");
+    self.started.retain(|k| k != &key);
+    Some(partial.frames.into_iter().flatten().flatten().collect())
+  }
+}
+
+impl<T: Transport> Transport for Framed<T> {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    let frame_size = FRAME_SIZES
+      .lock()
+      .expect("poisoned lock?")
+      .get(scheme)
+      .copied()
+      .unwrap_or(DEFAULT_FRAME_SIZE);
+    let mut inner = T::new(scheme)?;
+    inner.set_max_packet_size(Some(frame_size));
+    Ok(Framed {
+      inner,
+      frame_size,
+      max_packet_size: None,
+      next_id: rand::random(),
+      partial: HashMap::new(),
+      started: VecDeque::new(),
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    self.inner.scheme()
+  }
+
+  fn supported_schemes() -> Option<&'static [&'static str]> {
+    T::supported_schemes()
+  }
+
+  fn name(&self) -> &str {
+    self.inner.name()
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    let max_body = self.frame_size - HEADER_LEN;
+    let count = blob.len().max(1).div_ceil(max_body);
+    if count > u16::MAX as usize {
+      return Err(fail::MesherFail::PacketTooLarge(
+        blob.len(),
+        max_body * u16::MAX as usize,
+      ));
+    }
+    let id = self.next_id;
+    self.next_id = self.next_id.wrapping_add(1);
+    let frames: Vec<_> = (0..count)
+      .map(|index| {
+        let body = &blob[(index * max_body).min(blob.len())..((index + 1) * max_body).min(blob.len())];
+        let mut frame = Vec::with_capacity(HEADER_LEN + body.len());
+        frame.extend_from_slice(MAGIC);
+        frame.extend_from_slice(&id.to_be_bytes());
+        frame.extend_from_slice(&(index as u16).to_be_bytes());
+        frame.extend_from_slice(&(count as u16).to_be_bytes());
+        frame.extend_from_slice(body);
+        frame
+      })
+      .collect();
+    let frames: Vec<_> = frames.iter().map(Vec::as_slice).collect();
+    self.inner.send_batch(path, &frames)
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    self.inner.listen(path)
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    self.inner.unlisten(path)
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let frames = self.inner.receive()?;
+    Ok(self.reassemble_all(frames))
+  }
+
+  /// Receives at most `max` frames, so it can return fewer packets than that, but never more.
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let frames = self.inner.receive_up_to(max)?;
+    Ok(self.reassemble_all(frames))
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self.inner.status()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.max_packet_size = max;
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.inner.set_max_buffered(max)
+  }
+
+  fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    self.inner.close(timeout)
+  }
+}
+
+/// Relays packets between transports, without opening them.
+///
+/// Give it the transports on each side with [`add_transport`](#method.add_transport), then say where packets go with
+/// [`link`](#method.link), once for each direction they should flow in.
+/// Nothing happens on its own: call [`relay`](#method.relay) regularly, e.g. in a loop on its own thread, to pass on
+/// everything that's arrived since the last call.
+///
+/// Packets are relayed whole, so a bridge between transports with different packet sizes should wrap the one with
+/// smaller packets in [`Framed`](struct.Framed.html).
+/// A bridge has the same [maximum packet size](#method.set_max_packet_size) as a mesher, so it won't carry anything
+/// the meshers on either side would refuse anyway.
+pub struct Bridge {
+  transports: BTreeMap<String, Box<dyn Transport>>,
+  links: HashMap<Path, Vec<Path>>,
+  max_packet_size: Option<usize>,
+}
+
+impl Default for Bridge {
+  fn default() -> Self {
+    Bridge::new()
+  }
+}
+
+impl Bridge {
+  /// Creates a bridge with no transports, which won't relay anything yet.
+  pub fn new() -> Bridge {
+    Bridge {
+      transports: BTreeMap::new(),
+      links: HashMap::new(),
+      max_packet_size: Some(Mesher::DEFAULT_MAX_PACKET_SIZE),
+    }
+  }
+
+  /// Adds a transport for the given scheme, as with [`Mesher::add_transport`](../struct.Mesher.html#method.add_transport).
+  pub fn add_transport<T: Transport>(&mut self, scheme: &str) -> fail::Result<()> {
+    if self.transports.contains_key(scheme) {
+      return Err(fail::MesherFail::AlreadyRegistered(scheme.to_owned()));
+    }
+    check_supported::<T>(scheme)?;
+    let mut transport = T::new(scheme)?;
+    transport.set_max_packet_size(self.max_packet_size);
+    debug_event!(scheme, transport = transport.name(), "added transport to bridge");
+    self.transports.insert(scheme.to_owned(), Box::new(transport));
+    Ok(())
+  }
+
+  /// Has the bridge listen on `from`, if it isn't already, and launch everything received there along `to`.
+  ///
+  /// Linking one path to several sends everything received on it along each of them, and several paths can be linked
+  /// to the same one.
+  /// The transports for both paths' schemes have to have been added already.
+  pub fn link(&mut self, from: &str, to: &str) -> fail::Result<()> {
+    let from = Path::parse(from)?;
+    let to = Path::parse(to)?;
+    if !self.transports.contains_key(to.scheme()) {
+      return Err(fail::MesherFail::UnregisteredScheme(to.scheme().to_owned()));
+    }
+    if !self.links.contains_key(&from) {
+      self
+        .transports
+        .get_mut(from.scheme())
+        .ok_or_else(|| fail::MesherFail::UnregisteredScheme(from.scheme().to_owned()))?
+        .listen(&from)?;
+    }
+    let targets = self.links.entry(from).or_default();
+    if !targets.contains(&to) {
+      targets.push(to);
+    }
+    Ok(())
+  }
+
+  /// Stops relaying packets received on `from` along `to`, and stops listening on `from` if nothing else is linked to
+  /// it, returning whether they were linked.
+  pub fn unlink(&mut self, from: &str, to: &str) -> fail::Result<bool> {
+    let from = Path::parse(from)?;
+    let to = Path::parse(to)?;
+    let targets = match self.links.get_mut(&from) {
+      Some(targets) => targets,
+      None => return Ok(false),
+    };
+    let before = targets.len();
+    targets.retain(|t| t != &to);
+    let unlinked = targets.len() != before;
+    if targets.is_empty() {
+      self.links.remove(&from);
+      if let Some(transport) = self.transports.get_mut(from.scheme()) {
+        transport.unlisten(&from)?;
+      }
+    }
+    Ok(unlinked)
+  }
+
+  /// Limits how big packets can be, as with [`Mesher::set_max_packet_size`](../struct.Mesher.html#method.set_max_packet_size).
+  ///
+  /// It starts out at the same default as a mesher's.
+  pub fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.max_packet_size = max;
+    for transport in self.transports.values_mut() {
+      transport.set_max_packet_size(max);
+    }
+  }
+
+  /// Receives everything that's arrived on every transport, in order of their schemes, and launches each packet along every path linked to the
+  /// one it arrived on, returning how many packets were received.
+  ///
+  /// If a transport can't tell which path a packet arrived on, it's sent along every link from that transport's
+  /// scheme.
+  /// Packets are never sent back along the path they arrived on.
+  /// As with [`Transport::send_batch`](../trait.Transport.html#method.send_batch), every packet is tried even if one
+  /// fails, and the first failure is returned.
+  pub fn relay(&mut self) -> fail::Result<usize> {
+    let mut received = vec![];
+    let mut result = Ok(());
+    for (scheme, transport) in &mut self.transports {
+      match transport.receive() {
+        Ok(packets) => received.extend(packets.into_iter().map(|(source, blob)| (scheme.clone(), source, blob))),
+        Err(e) => result = result.and(Err(e)),
+      }
+    }
+
+    let count = received.len();
+    for (scheme, source, blob) in received {
+      if let Some(max) = self.max_packet_size {
+        if blob.len() > max {
+          result = result.and(Err(fail::MesherFail::PacketTooLarge(blob.len(), max)));
+          continue;
+        }
+      }
+      let targets: Vec<Path> = match source.listen_path() {
+        Some(listen) => self.links.get(listen).cloned().unwrap_or_default(),
+        None => self
+          .links
+          .iter()
+          .filter(|(from, _)| from.scheme() == scheme)
+          .flat_map(|(_, to)| to.iter().cloned())
+          .collect(),
+      };
+      for target in targets {
+        if Some(&target) == source.listen_path() {
+          continue;
+        }
+        let sent = match self.transports.get_mut(target.scheme()) {
+          Some(transport) => transport.send(&target, &blob),
+          None => Err(fail::MesherFail::UnregisteredScheme(target.scheme().to_owned())),
+        };
+        debug_event!(from = %scheme, to = %target, size = blob.len(), "relayed packet");
+        result = result.and(sent);
+      }
+    }
+    result.map(|_| count)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::MockTransport;
+
+  fn path(p: &str) -> Path {
+    Path::parse(p).unwrap()
+  }
+
+  #[test]
+  fn framed_packets_are_reassembled_in_any_order() {
+    set_frame_size("framed-test", 32);
+    let mock = MockTransport::control("framed-test");
+    let mut sender = Framed::<MockTransport>::new("framed-test").unwrap();
+    let mut receiver = Framed::<MockTransport>::new("framed-test").unwrap();
+    let out = path("framed-test:out");
+
+    let big: Vec<u8> = (0..=255).collect();
+    sender.send(&out, &big).unwrap();
+    sender.send(&out, &[1, 2, 3]).unwrap();
+    sender.send(&out, &[]).unwrap();
+    let mut frames = mock.take_sent();
+    // 256 bytes at 22 bytes per frame, then the two small ones
+    assert_eq!(12 + 1 + 1, frames.len());
+    assert!(frames.iter().all(|(p, f)| p == &out && f.len() <= 32));
+
+    frames.reverse();
+    for (_, frame) in frames {
+      mock.inject_from(Source::listening_on(out.clone()), frame);
+    }
+    mock.inject(b"not a frame".to_vec());
+    let received: Vec<_> = receiver.receive().unwrap().into_iter().map(|(_, p)| p).collect();
+    assert_eq!(vec![vec![], vec![1, 2, 3], big], received);
+  }
+
+  #[test]
+  fn framed_packets_need_every_frame() {
+    set_frame_size("framed-lossy-test", 16);
+    let mock = MockTransport::control("framed-lossy-test");
+    let mut sender = Framed::<MockTransport>::new("framed-lossy-test").unwrap();
+    let mut receiver = Framed::<MockTransport>::new("framed-lossy-test").unwrap();
+    sender.send(&path("framed-lossy-test:out"), &[9; 40]).unwrap();
+    let frames = mock.take_sent();
+    assert_eq!(7, frames.len());
+    for (_, frame) in frames.into_iter().skip(1) {
+      mock.inject(frame);
+    }
+    assert!(receiver.receive().unwrap().is_empty());
+  }
+
+  #[test]
+  fn bridges_follow_links() {
+    let left = MockTransport::control("bridge-test-left");
+    let right = MockTransport::control("bridge-test-right");
+    let mut bridge = Bridge::new();
+    bridge.add_transport::<MockTransport>("bridge-test-left").unwrap();
+    assert!(matches!(
+      bridge.link("bridge-test-left:in", "bridge-test-right:out"),
+      Err(fail::MesherFail::UnregisteredScheme(_))
+    ));
+    bridge.add_transport::<MockTransport>("bridge-test-right").unwrap();
+    bridge.link("bridge-test-left:in", "bridge-test-right:out").unwrap();
+    bridge.link("bridge-test-right:in", "bridge-test-left:out").unwrap();
+    assert_eq!(vec![path("bridge-test-left:in")], left.listening());
+
+    left.inject_from(Source::listening_on(path("bridge-test-left:in")), vec![1]);
+    left.inject_from(Source::listening_on(path("bridge-test-left:elsewhere")), vec![2]);
+    right.inject(vec![3]);
+    assert_eq!(3, bridge.relay().unwrap());
+    assert_eq!(vec![(path("bridge-test-right:out"), vec![1])], right.take_sent());
+    assert_eq!(vec![(path("bridge-test-left:out"), vec![3])], left.take_sent());
+
+    right.fail_sends(1);
+    left.inject_from(Source::listening_on(path("bridge-test-left:in")), vec![4]);
+    assert!(matches!(bridge.relay(), Err(fail::MesherFail::SendFailure(_))));
+
+    assert!(bridge.unlink("bridge-test-left:in", "bridge-test-right:out").unwrap());
+    assert!(!bridge.unlink("bridge-test-left:in", "bridge-test-right:out").unwrap());
+    assert!(left.listening().is_empty());
+  }
+}
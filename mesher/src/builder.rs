@@ -0,0 +1,424 @@
+//! Contains the builder for configuring a Mesher in one go.
+
+use crate::{
+  crypto::Crypto, discovery::Discovery, keystore::KeyStore, metrics::Metrics, prelude::*, ForwardPolicy, InboundQueue,
+  Mailbox, MixPolicy, OutboundQueue, Quota, RateLimit, TamperPolicy,
+};
+
+use std::{sync::Arc, time::Duration};
+
+pub(crate) type AddTransport = fn(&mut Mesher, &[&str]) -> fail::Result<()>;
+
+/// Builds a [`Mesher`](struct.Mesher.html) with everything it needs, all at once.
+///
+/// The transports aren't actually created, and paths aren't listened on, until [`build`](#method.build) is called.
+/// They're created and listened on in the order they were added to the builder.
+///
+/// ```
+/// # use mesher::prelude::*;
+/// let (_, sk) = encrypt::gen_keypair();
+/// let _mesher = Mesher::builder()
+///   .own_key(sk)
+///   .transport::<mesher::debug_transports::InMemory>("inmem")
+///   .listen_on("inmem:builder-doc")
+///   .build()
+///   .expect("Failed to build mesher");
+/// ```
+#[derive(Default)]
+pub struct MesherBuilder {
+  own_skeys: Vec<encrypt::SecretKey>,
+  keystore: Option<Arc<dyn KeyStore>>,
+  named_skeys: Vec<String>,
+  named_signing_key: Option<String>,
+  sender_pkeys: Vec<sign::PublicKey>,
+  trust_roots: Vec<sign::PublicKey>,
+  transports: Vec<(Vec<String>, AddTransport)>,
+  listens: Vec<String>,
+  crypto: Option<Arc<dyn Crypto>>,
+  tamper_policy: TamperPolicy,
+  dedup_window: Option<Duration>,
+  discovery: Option<Discovery>,
+  mix_policy: Option<MixPolicy>,
+  max_hold: Option<Duration>,
+  clock_skew: Option<Duration>,
+  rate_limit: Option<RateLimit>,
+  receive_limit: Option<usize>,
+  max_buffered: Option<usize>,
+  inbound_queue: Option<InboundQueue>,
+  metrics: Option<Arc<dyn Metrics>>,
+  forward_policy: Option<ForwardPolicy>,
+  loop_window: Option<Duration>,
+  queue: Option<OutboundQueue>,
+  mailbox: Option<Mailbox>,
+  signing_key: Option<sign::SecretKey>,
+  max_packet_size: Option<Option<usize>>,
+  quotas: Vec<(String, Quota)>,
+  key_hints: bool,
+}
+
+impl MesherBuilder {
+  /// Creates a builder for an unsigned mesher with no keys, transports, or listeners.
+  pub fn new() -> MesherBuilder {
+    MesherBuilder::default()
+  }
+
+  /// Adds a secret key the mesher will use to decrypt incoming packets.
+  pub fn own_key(mut self, skey: encrypt::SecretKey) -> MesherBuilder {
+    self.own_skeys.push(skey);
+    self
+  }
+
+  /// Adds several secret keys the mesher will use to decrypt incoming packets.
+  pub fn own_keys(mut self, skeys: impl IntoIterator<Item = encrypt::SecretKey>) -> MesherBuilder {
+    self.own_skeys.extend(skeys);
+    self
+  }
+
+  /// Sets the [keystore](keystore/index.html) keys named with [`own_key_named`](#method.own_key_named) and
+  /// [`signing_key_named`](#method.signing_key_named) are loaded from.
+  pub fn keystore(mut self, store: Arc<dyn KeyStore>) -> MesherBuilder {
+    self.keystore = Some(store);
+    self
+  }
+
+  /// Adds a secret key the mesher will use to decrypt incoming packets, loaded from the
+  /// [keystore](#method.keystore) when the mesher's built.
+  pub fn own_key_named(mut self, name: &str) -> MesherBuilder {
+    self.named_skeys.push(name.to_owned());
+    self
+  }
+
+  /// Adds a key which incoming packets can be signed with.
+  ///
+  /// If any sender keys are added, the mesher will be signed, as with [`Mesher::signed`](struct.Mesher.html#method.signed).
+  /// Otherwise, it'll be unsigned.
+  pub fn sender_key(mut self, pkey: sign::PublicKey) -> MesherBuilder {
+    self.sender_pkeys.push(pkey);
+    self
+  }
+
+  /// Adds several keys which incoming packets can be signed with.
+  ///
+  /// See [`sender_key`](#method.sender_key) for more details.
+  pub fn sender_keys(mut self, pkeys: impl IntoIterator<Item = sign::PublicKey>) -> MesherBuilder {
+    self.sender_pkeys.extend(pkeys);
+    self
+  }
+
+  /// Adds a trust root, so packets signed by any key it's endorsed are accepted, as with
+  /// [`Mesher::add_trust_root`](struct.Mesher.html#method.add_trust_root).
+  ///
+  /// Like sender keys, adding any trust roots makes the mesher signed.
+  pub fn trust_root(mut self, pkey: sign::PublicKey) -> MesherBuilder {
+    self.trust_roots.push(pkey);
+    self
+  }
+
+  /// Sets the crypto backend the mesher uses, as with [`Mesher::set_crypto`](struct.Mesher.html#method.set_crypto).
+  ///
+  /// If this isn't called, the [default backend](crypto/fn.default_backend.html) is used.
+  pub fn crypto(mut self, crypto: Arc<dyn Crypto>) -> MesherBuilder {
+    self.crypto = Some(crypto);
+    self
+  }
+
+  /// Sets what happens to packets with tampered chunks, as with [`Mesher::set_tamper_policy`](struct.Mesher.html#method.set_tamper_policy).
+  pub fn tamper_policy(mut self, policy: TamperPolicy) -> MesherBuilder {
+    self.tamper_policy = policy;
+    self
+  }
+
+  /// Sets how long to remember message IDs for deduplication, as with [`Mesher::set_dedup_window`](struct.Mesher.html#method.set_dedup_window).
+  pub fn dedup_window(mut self, window: Duration) -> MesherBuilder {
+    self.dedup_window = Some(window);
+    self
+  }
+
+  /// Announces the mesher to its peers, as with [`Mesher::set_discovery`](struct.Mesher.html#method.set_discovery).
+  pub fn discovery(mut self, discovery: Discovery) -> MesherBuilder {
+    self.discovery = Some(discovery);
+    self
+  }
+
+  /// Holds forwarded packets to mix them up, as with [`Mesher::set_mix_policy`](struct.Mesher.html#method.set_mix_policy).
+  pub fn mix_policy(mut self, policy: MixPolicy) -> MesherBuilder {
+    self.mix_policy = Some(policy);
+    self
+  }
+
+  /// Holds relayed packets when their senders ask, for up to `max`, as with
+  /// [`Mesher::set_max_hold`](struct.Mesher.html#method.set_max_hold).
+  pub fn max_hold(mut self, max: Duration) -> MesherBuilder {
+    self.max_hold = Some(max);
+    self
+  }
+
+  /// Allows for clocks being off by up to `skew` when checking whether packets have expired, as with
+  /// [`Mesher::set_clock_skew`](struct.Mesher.html#method.set_clock_skew).
+  ///
+  /// If this isn't called, the [default](struct.Mesher.html#associatedconstant.DEFAULT_CLOCK_SKEW) is used.
+  pub fn clock_skew(mut self, skew: Duration) -> MesherBuilder {
+    self.clock_skew = Some(skew);
+    self
+  }
+
+  /// Limits how many packets are processed from each transport, as with [`Mesher::set_rate_limit`](struct.Mesher.html#method.set_rate_limit).
+  pub fn rate_limit(mut self, limit: RateLimit) -> MesherBuilder {
+    self.rate_limit = Some(limit);
+    self
+  }
+
+  /// Limits how many packets are processed from each transport per receive, as with
+  /// [`Mesher::set_receive_limit`](struct.Mesher.html#method.set_receive_limit).
+  pub fn receive_limit(mut self, limit: usize) -> MesherBuilder {
+    self.receive_limit = Some(limit);
+    self
+  }
+
+  /// Tells the given metrics about everything the mesher does, as with [`Mesher::set_metrics`](struct.Mesher.html#method.set_metrics).
+  pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> MesherBuilder {
+    self.metrics = Some(metrics);
+    self
+  }
+
+  /// Only forwards received packets the given policy allows, as with [`Mesher::set_forward_policy`](struct.Mesher.html#method.set_forward_policy).
+  pub fn forward_policy(mut self, policy: ForwardPolicy) -> MesherBuilder {
+    self.forward_policy = Some(policy);
+    self
+  }
+
+  /// Drops packets that are forwarded along the same path twice within `window`, as with [`Mesher::set_loop_window`](struct.Mesher.html#method.set_loop_window).
+  pub fn loop_window(mut self, window: Duration) -> MesherBuilder {
+    self.loop_window = Some(window);
+    self
+  }
+
+  /// Queues forwarded packets that fail to send, as with [`Mesher::set_outbound_queue`](struct.Mesher.html#method.set_outbound_queue).
+  pub fn outbound_queue(mut self, queue: OutboundQueue) -> MesherBuilder {
+    self.queue = Some(queue);
+    self
+  }
+
+  /// Signs packets the mesher builds itself with the given key, as with [`Mesher::set_signing_key`](struct.Mesher.html#method.set_signing_key).
+  pub fn signing_key(mut self, skey: sign::SecretKey) -> MesherBuilder {
+    self.signing_key = Some(skey);
+    self
+  }
+
+  /// Sets the key the mesher signs packets it builds itself with, loaded from the [keystore](#method.keystore) when
+  /// the mesher's built.
+  ///
+  /// This replaces any key given with [`signing_key`](#method.signing_key).
+  pub fn signing_key_named(mut self, name: &str) -> MesherBuilder {
+    self.named_signing_key = Some(name.to_owned());
+    self
+  }
+
+  /// Adds key hints to packets the mesher builds itself, as with [`Mesher::set_key_hints`](struct.Mesher.html#method.set_key_hints).
+  pub fn key_hints(mut self, on: bool) -> MesherBuilder {
+    self.key_hints = on;
+    self
+  }
+
+  /// Holds packets for offline recipients, as with [`Mesher::set_mailbox`](struct.Mesher.html#method.set_mailbox).
+  pub fn mailbox(mut self, mailbox: Mailbox) -> MesherBuilder {
+    self.mailbox = Some(mailbox);
+    self
+  }
+
+  /// Limits how big packets can be, as with [`Mesher::set_max_packet_size`](struct.Mesher.html#method.set_max_packet_size).
+  ///
+  /// If this isn't called, the [default](struct.Mesher.html#associatedconstant.DEFAULT_MAX_PACKET_SIZE) is used.
+  pub fn max_packet_size(mut self, max: Option<usize>) -> MesherBuilder {
+    self.max_packet_size = Some(max);
+    self
+  }
+
+  /// Limits how many packets each transport holds on to between receives, as with
+  /// [`Mesher::set_max_buffered`](struct.Mesher.html#method.set_max_buffered).
+  pub fn max_buffered(mut self, max: usize) -> MesherBuilder {
+    self.max_buffered = Some(max);
+    self
+  }
+
+  /// Limits how many received packets are held for later, as with
+  /// [`Mesher::set_inbound_queue`](struct.Mesher.html#method.set_inbound_queue).
+  pub fn inbound_queue(mut self, queue: InboundQueue) -> MesherBuilder {
+    self.inbound_queue = Some(queue);
+    self
+  }
+
+  /// Caps how much can be sent through the given scheme's transport, as with [`Mesher::set_quota`](struct.Mesher.html#method.set_quota).
+  pub fn quota(mut self, scheme: &str, quota: Quota) -> MesherBuilder {
+    self.quotas.push((scheme.to_owned(), quota));
+    self
+  }
+
+  /// Adds a transport for the given scheme, as with [`Mesher::add_transport`](struct.Mesher.html#method.add_transport).
+  pub fn transport<T: Transport>(self, scheme: &str) -> MesherBuilder {
+    self.transport_with(&[scheme], Mesher::add_transport_for_schemes::<T>)
+  }
+
+  /// Adds one transport for several schemes, as with
+  /// [`Mesher::add_transport_for_schemes`](struct.Mesher.html#method.add_transport_for_schemes).
+  pub fn transport_for_schemes<T: Transport>(self, schemes: &[&str]) -> MesherBuilder {
+    self.transport_with(schemes, Mesher::add_transport_for_schemes::<T>)
+  }
+
+  /// Adds a transport for the given schemes, created by the given function rather than a type parameter.
+  pub(crate) fn transport_with(mut self, schemes: &[&str], add: AddTransport) -> MesherBuilder {
+    self
+      .transports
+      .push((schemes.iter().map(|s| (*s).to_owned()).collect(), add));
+    self
+  }
+
+  /// Listens on the given path, as with [`Mesher::listen_on`](struct.Mesher.html#method.listen_on).
+  ///
+  /// The path's scheme has to be one added through [`transport`](#method.transport).
+  pub fn listen_on(mut self, path: &str) -> MesherBuilder {
+    self.listens.push(path.to_owned());
+    self
+  }
+
+  /// Creates the mesher, then sets up all of its transports and listeners.
+  ///
+  /// Fails with the first error any transport or listener setup returns, or
+  /// [`MesherFail::KeystoreFailure`](fail/enum.MesherFail.html#variant.KeystoreFailure) if a named key can't be loaded.
+  pub fn build(mut self) -> fail::Result<Mesher> {
+    if !self.named_skeys.is_empty() || self.named_signing_key.is_some() {
+      let store = self.keystore.as_ref().ok_or_else(|| {
+        fail::MesherFail::KeystoreFailure("keys were named, but no keystore was given to load them from".to_owned())
+      })?;
+      for name in &self.named_skeys {
+        self.own_skeys.push(store.encrypt_key(name)?);
+      }
+      if let Some(name) = &self.named_signing_key {
+        self.signing_key = Some(store.sign_key(name)?);
+      }
+    }
+    let mut mesher = if self.sender_pkeys.is_empty() {
+      Mesher::unsigned(self.own_skeys)
+    } else {
+      Mesher::signed(self.own_skeys, self.sender_pkeys)?
+    };
+    for root in self.trust_roots {
+      mesher.add_trust_root(root);
+    }
+    if let Some(crypto) = self.crypto {
+      mesher.set_crypto(crypto);
+    }
+    mesher.set_tamper_policy(self.tamper_policy);
+    mesher.set_dedup_window(self.dedup_window);
+    mesher.set_discovery(self.discovery);
+    mesher.set_mix_policy(self.mix_policy);
+    mesher.set_max_hold(self.max_hold);
+    if let Some(skew) = self.clock_skew {
+      mesher.set_clock_skew(skew);
+    }
+    mesher.set_rate_limit(self.rate_limit);
+    mesher.set_receive_limit(self.receive_limit);
+    mesher.set_max_buffered(self.max_buffered);
+    mesher.set_inbound_queue(self.inbound_queue);
+    mesher.set_metrics(self.metrics);
+    mesher.set_forward_policy(self.forward_policy);
+    mesher.set_loop_window(self.loop_window);
+    mesher.set_outbound_queue(self.queue);
+    mesher.set_mailbox(self.mailbox);
+    mesher.set_signing_key(self.signing_key);
+    mesher.set_key_hints(self.key_hints);
+    if let Some(max) = self.max_packet_size {
+      mesher.set_max_packet_size(max);
+    }
+    for (schemes, add) in self.transports {
+      let schemes: Vec<_> = schemes.iter().map(String::as_str).collect();
+      add(&mut mesher, &schemes)?;
+    }
+    // after the transports, so quotas set by any of their schemes are shared
+    for (scheme, quota) in self.quotas {
+      mesher.set_quota(&scheme, Some(quota));
+    }
+    for path in self.listens {
+      mesher.listen_on(&path)?;
+    }
+    Ok(mesher)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::debug_transports::InMemory;
+
+  #[test]
+  fn builds_working_mesher() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut mesher = MesherBuilder::new()
+      .own_key(sk)
+      .transport::<InMemory>("inmem")
+      .listen_on("inmem:builder-works")
+      .build()
+      .expect("Failed to build");
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("inmem:builder-works".to_owned(), &pk);
+    packet.add_message(&[1], &pk);
+    mesher.launch(packet).expect("Failed to launch");
+
+    let received: Vec<_> = mesher
+      .receive()
+      .expect("Failed to receive")
+      .into_iter()
+      .map(Message::into_contents)
+      .collect();
+    assert_eq!(vec![vec![1]], received);
+  }
+
+  #[test]
+  fn sender_keys_make_signed() {
+    let (spk, ssk) = sign::gen_keypair();
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut mesher = MesherBuilder::new()
+      .own_key(sk)
+      .sender_key(spk)
+      .transport::<InMemory>("inmem")
+      .listen_on("inmem:builder-signed")
+      .build()
+      .expect("Failed to build");
+
+    let mut unsigned = Packet::unsigned();
+    unsigned.add_hop("inmem:builder-signed".to_owned(), &pk);
+    unsigned.add_message(&[1], &pk);
+    let mut signed = Packet::signed(ssk);
+    signed.add_hop("inmem:builder-signed".to_owned(), &pk);
+    signed.add_message(&[2], &pk);
+
+    mesher.launch(signed).expect("Failed to launch signed");
+    // the unsigned packet's hop won't even be read, since it's not signed
+    mesher.launch(unsigned).expect("Failed to launch unsigned");
+
+    let received: Vec<_> = mesher
+      .receive()
+      .expect("Failed to receive")
+      .into_iter()
+      .map(Message::into_contents)
+      .collect();
+    assert_eq!(vec![vec![2]], received);
+  }
+
+  #[test]
+  fn unregistered_listen_fails() {
+    let (_, sk) = encrypt::gen_keypair();
+    let built = MesherBuilder::new().own_key(sk).listen_on("nope:foo").build();
+    match built {
+      Err(fail::MesherFail::UnregisteredScheme(s)) => assert_eq!("nope", s),
+      _ => panic!("expected UnregisteredScheme"),
+    }
+  }
+
+  #[test]
+  fn named_keys_need_keystore() {
+    let built = MesherBuilder::new().own_key_named("node").build();
+    assert!(matches!(built, Err(fail::MesherFail::KeystoreFailure(_))));
+  }
+}
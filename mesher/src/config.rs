@@ -0,0 +1,723 @@
+//! Contains the configuration file format for setting up a mesher without writing code for it.
+//!
+//! Config files can be TOML or JSON, picked by their extension, and look like this:
+//!
+//! ```toml
+//! # secret keys, as files holding either the raw bytes or hex; relative paths are relative to the config file
+//! keys = ["node.key"]
+//! # the mesher's own signing key, for packets it builds itself; secret keys can also be loaded from the keystore
+//! signing_key = "keystore:node.sign"
+//! # if any are given, the mesher is signed and only accepts packets signed by these, as files or hex
+//! sender_keys = ["alice.pub"]
+//! # likewise, but accepting any key one of these has endorsed
+//! trust_roots = ["root.pub"]
+//! tamper_policy = "drop-packet"     # or "skip"
+//! dedup_window_secs = 60
+//! loop_window_secs = 60
+//! clock_skew_secs = 300             # how far off clocks can be when checking whether packets have expired
+//! max_packet_bytes = 1048576        # 0 for no limit
+//! receive_limit = 64                # most packets handled from each transport per receive, 0 for no limit
+//! max_buffered = 4096               # most packets each transport holds between receives, 0 for no limit
+//! key_hints = true                  # on packets the mesher builds itself
+//!
+//! [[transports]]
+//! scheme = "tcp"
+//! aliases = ["tcp6"]                # optional, other schemes the same transport handles
+//! kind = "tcp"                      # optional, defaults to the scheme
+//! listen = ["tcp:0.0.0.0:18540"]
+//! quota = { bytes = 10485760, per_secs = 86400 }   # optional cap on how much can be sent
+//!
+//! [rate_limit]
+//! per_second = 100
+//! burst = 200
+//!
+//! [inbound]
+//! capacity = 1024                   # most packets held from each transport
+//! overflow = "drop-oldest"          # or "drop-newest" (the default), or "block"
+//!
+//! [mix]
+//! batch_size = 8
+//! max_delay_ms = 500
+//!
+//! [forward]
+//! only_schemes = ["tcp"]
+//! deny = ["tcp:*:22"]
+//! deny_local_addresses = true
+//! rate_limit = { per_second = 50, burst = 100 }
+//!
+//! [queue]
+//! dir = "queue"
+//! retry_secs = 30
+//! max_attempts = 10
+//!
+//! [mailbox]
+//! recipients = ["<hex signing public key>"]
+//! max_packets = 256
+//!
+//! [discovery]
+//! paths = ["tcp:203.0.113.7:18540"]
+//! interval_secs = 300
+//!
+//! [keystore]
+//! kind = "os"                       # the OS's credential store (which needs the os-keychain feature), or "file"
+//!                                   # for a passphrase-encrypted file (which needs the crypto-sodium feature)
+//! service = "mesher"                # for "os", optional, defaults to "mesher"
+//! file = "node.keys"                # for "file"
+//! passphrase_env = "MESHER_PASSPHRASE"   # for "file", the environment variable holding the passphrase
+//! ```
+//!
+//! Every section and key is optional, though a mesher without keys or transports can't do much.
+//! Transport kinds have to be [registered](struct.Config.html#method.transport_kind) before the config is built; only
+//! `inmem`, the [in-memory debug transport](../debug_transports/struct.InMemory.html), is known by default.
+//! Keys named `keystore:<name>` are loaded from the [keystore](../keystore/index.html) as soon as the config is checked,
+//! so a wrong passphrase is caught then, rather than when the mesher's built.
+
+#[cfg(feature = "os-keychain")]
+use crate::keystore::OsKeychain;
+#[cfg(feature = "crypto-sodium")]
+use crate::keystore::PassphraseFile;
+use crate::{
+  builder::AddTransport, crypto::parse_public_key, discovery::Discovery, keystore::KeyStore, prelude::*, ForwardPolicy,
+  InboundQueue, Mailbox, MesherBuilder, MixPolicy, OutboundQueue, Overflow, Quota, RateLimit, TamperPolicy,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::{
+  collections::BTreeMap,
+  fs,
+  path::{Path as FsPath, PathBuf},
+  time::Duration,
+};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+  #[serde(default)]
+  keys: Vec<String>,
+  signing_key: Option<String>,
+  #[serde(default)]
+  sender_keys: Vec<String>,
+  #[serde(default)]
+  trust_roots: Vec<String>,
+  tamper_policy: Option<String>,
+  dedup_window_secs: Option<u64>,
+  loop_window_secs: Option<u64>,
+  clock_skew_secs: Option<u64>,
+  max_packet_bytes: Option<usize>,
+  receive_limit: Option<usize>,
+  max_buffered: Option<usize>,
+  #[serde(default)]
+  key_hints: bool,
+  #[serde(default)]
+  transports: Vec<RawTransport>,
+  rate_limit: Option<RawRateLimit>,
+  inbound: Option<RawInbound>,
+  mix: Option<RawMix>,
+  forward: Option<RawForward>,
+  queue: Option<RawQueue>,
+  mailbox: Option<RawMailbox>,
+  discovery: Option<RawDiscovery>,
+  keystore: Option<RawKeystore>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawTransport {
+  scheme: String,
+  #[serde(default)]
+  aliases: Vec<String>,
+  kind: Option<String>,
+  #[serde(default)]
+  listen: Vec<String>,
+  quota: Option<RawQuota>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawQuota {
+  bytes: u64,
+  per_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawRateLimit {
+  per_second: u32,
+  burst: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawInbound {
+  capacity: usize,
+  overflow: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawMix {
+  batch_size: usize,
+  max_delay_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawForward {
+  only_schemes: Option<Vec<String>>,
+  #[serde(default)]
+  deny: Vec<String>,
+  #[serde(default)]
+  deny_local_addresses: bool,
+  rate_limit: Option<RawRateLimit>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawQueue {
+  dir: String,
+  retry_secs: Option<u64>,
+  max_attempts: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawMailbox {
+  recipients: Vec<String>,
+  max_packets: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawDiscovery {
+  paths: Vec<String>,
+  interval_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawKeystore {
+  kind: String,
+  service: Option<String>,
+  file: Option<String>,
+  passphrase_env: Option<String>,
+}
+
+/// What secret keys loaded from a keystore start with, in place of a file or hex.
+const KEYSTORE_PREFIX: &str = "keystore:";
+
+fn invalid(msg: String) -> fail::MesherFail {
+  fail::MesherFail::InvalidConfig(msg)
+}
+
+/// A parsed config file, ready to be turned into a [`Mesher`](../struct.Mesher.html).
+///
+/// Nothing is checked beyond the file's structure until [`builder`](#method.builder) or [`build`](#method.build) is
+/// called, so transport kinds can be registered first.
+pub struct Config {
+  raw: RawConfig,
+  base: PathBuf,
+  kinds: BTreeMap<String, AddTransport>,
+}
+
+impl Config {
+  fn new(raw: RawConfig, base: PathBuf) -> Config {
+    let mut kinds = BTreeMap::new();
+    kinds.insert(
+      "inmem".to_owned(),
+      Mesher::add_transport_for_schemes::<crate::debug_transports::InMemory> as AddTransport,
+    );
+    Config { raw, base, kinds }
+  }
+
+  /// Loads a config file, as TOML if its extension is `.toml` or JSON if it's `.json`.
+  ///
+  /// Relative paths in the file are taken relative to the directory it's in.
+  pub fn load(path: impl AsRef<FsPath>) -> fail::Result<Config> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| invalid(format!("couldn't read {}: {}", path.display(), e)))?;
+    let base = path.parent().map(FsPath::to_owned).unwrap_or_default();
+    match path.extension().and_then(|e| e.to_str()) {
+      Some("toml") => Config::from_toml(&text, base),
+      Some("json") => Config::from_json(&text, base),
+      _ => Err(invalid(format!(
+        "don't know the format of {}; config files should end in .toml or .json",
+        path.display()
+      ))),
+    }
+  }
+
+  /// Parses a TOML config, with relative paths taken relative to `base`.
+  pub fn from_toml(text: &str, base: impl Into<PathBuf>) -> fail::Result<Config> {
+    let raw = toml::from_str(text).map_err(|e| invalid(format!("invalid TOML config: {}", e)))?;
+    Ok(Config::new(raw, base.into()))
+  }
+
+  /// Parses a JSON config, with relative paths taken relative to `base`.
+  pub fn from_json(text: &str, base: impl Into<PathBuf>) -> fail::Result<Config> {
+    let raw = serde_json::from_str(text).map_err(|e| invalid(format!("invalid JSON config: {}", e)))?;
+    Ok(Config::new(raw, base.into()))
+  }
+
+  /// Lets transports in the config use the given kind, e.g. `.transport_kind::<mesher_basic::TCP>("tcp")`.
+  pub fn transport_kind<T: Transport>(mut self, kind: &str) -> Config {
+    self
+      .kinds
+      .insert(kind.to_owned(), Mesher::add_transport_for_schemes::<T>);
+    self
+  }
+
+  /// Reads a key, given either as a file holding its raw bytes or hex, or as hex directly.
+  ///
+  /// The hex can have a public key's checksum after it, so keys can be pasted in the way they're printed.
+  fn key_bytes(&self, what: &str, key: &str, len: usize) -> fail::Result<Vec<u8>> {
+    if let Some(bytes) = parse_public_key(key, len) {
+      return Ok(bytes);
+    }
+    let file = self.base.join(key);
+    let bytes = fs::read(&file).map_err(|e| invalid(format!("couldn't read {} {}: {}", what, file.display(), e)))?;
+    if bytes.len() == len {
+      return Ok(bytes);
+    }
+    match std::str::from_utf8(&bytes)
+      .ok()
+      .and_then(|text| parse_public_key(text, len))
+    {
+      Some(bytes) => Ok(bytes),
+      None => Err(invalid(format!(
+        "{} {} should hold a {}-byte key, either raw or in hex",
+        what,
+        file.display(),
+        len
+      ))),
+    }
+  }
+
+  /// Opens the keystore the config describes, if it has one.
+  fn keystore(&self) -> fail::Result<Option<Box<dyn KeyStore>>> {
+    let raw = match &self.raw.keystore {
+      Some(raw) => raw,
+      None => return Ok(None),
+    };
+    match raw.kind.as_str() {
+      #[cfg(feature = "os-keychain")]
+      "os" => Ok(Some(Box::new(OsKeychain::new(
+        raw.service.as_deref().unwrap_or("mesher"),
+      )))),
+      #[cfg(not(feature = "os-keychain"))]
+      "os" => Err(invalid("OS keystores need mesher's os-keychain feature".to_owned())),
+      #[cfg(feature = "crypto-sodium")]
+      "file" => {
+        let file = raw
+          .file
+          .as_ref()
+          .ok_or_else(|| invalid("file keystores need a file to keep keys in".to_owned()))?;
+        let var = raw
+          .passphrase_env
+          .as_ref()
+          .ok_or_else(|| invalid("file keystores need a passphrase_env to read the passphrase from".to_owned()))?;
+        let passphrase = std::env::var(var).map_err(|_| {
+          invalid(format!(
+            "the keystore's passphrase should be in ${}, which isn't set",
+            var
+          ))
+        })?;
+        Ok(Some(Box::new(PassphraseFile::new(self.base.join(file), &passphrase))))
+      }
+      #[cfg(not(feature = "crypto-sodium"))]
+      "file" => Err(invalid("file keystores need mesher's crypto-sodium feature".to_owned())),
+      other => Err(invalid(format!(
+        "unknown keystore kind {:?}; it should be \"os\" or \"file\"",
+        other
+      ))),
+    }
+  }
+
+  /// Loads a secret key from the keystore, if it's named like `keystore:<name>`.
+  fn stored_key<K>(
+    &self,
+    what: &str,
+    key: &str,
+    store: Option<&dyn KeyStore>,
+    load: fn(&dyn KeyStore, &str) -> fail::Result<K>,
+  ) -> fail::Result<Option<K>> {
+    let name = match key.strip_prefix(KEYSTORE_PREFIX) {
+      Some(name) => name,
+      None => return Ok(None),
+    };
+    let store = store.ok_or_else(|| {
+      invalid(format!(
+        "{} {:?} is in the keystore, but there's no [keystore] section",
+        what, key
+      ))
+    })?;
+    match load(store, name) {
+      Ok(key) => Ok(Some(key)),
+      Err(fail::MesherFail::KeystoreFailure(msg)) => Err(invalid(format!(
+        "couldn't load {} {:?} from the keystore: {}",
+        what, name, msg
+      ))),
+      Err(e) => Err(e),
+    }
+  }
+
+  fn parse_path(&self, what: &str, path: &str) -> fail::Result<Path> {
+    Path::parse(path).map_err(|_| {
+      invalid(format!(
+        "{} {:?} isn't a valid path; paths look like scheme:location",
+        what, path
+      ))
+    })
+  }
+
+  /// Checks the whole config and sets up a builder from it, so more can be added, e.g. metrics, before building.
+  ///
+  /// Fails with [`MesherFail::InvalidConfig`](../fail/enum.MesherFail.html#variant.InvalidConfig) describing the first
+  /// problem found, e.g. a key file that doesn't exist or a transport of a kind that hasn't been registered.
+  pub fn builder(&self) -> fail::Result<MesherBuilder> {
+    let raw = &self.raw;
+    let mut builder = MesherBuilder::new();
+    let store = self.keystore()?;
+    for key in &raw.keys {
+      let skey = match self.stored_key("key", key, store.as_deref(), |s, n| s.encrypt_key(n))? {
+        Some(skey) => skey,
+        None => encrypt::SecretKey::from_slice(&self.key_bytes("key", key, 32)?).expect("Length was just checked"),
+      };
+      builder = builder.own_key(skey);
+    }
+    if let Some(key) = &raw.signing_key {
+      let skey = match self.stored_key("signing key", key, store.as_deref(), |s, n| s.sign_key(n))? {
+        Some(skey) => skey,
+        None => sign::SecretKey::from_slice(&self.key_bytes("signing key", key, 64)?).expect("Length was just checked"),
+      };
+      builder = builder.signing_key(skey);
+    }
+    for key in &raw.sender_keys {
+      let bytes = self.key_bytes("sender key", key, 32)?;
+      builder = builder.sender_key(sign::PublicKey::from_slice(&bytes).expect("Length was just checked"));
+    }
+    for key in &raw.trust_roots {
+      let bytes = self.key_bytes("trust root", key, 32)?;
+      builder = builder.trust_root(sign::PublicKey::from_slice(&bytes).expect("Length was just checked"));
+    }
+    match raw.tamper_policy.as_deref() {
+      None => (),
+      Some("skip") => builder = builder.tamper_policy(TamperPolicy::Skip),
+      Some("drop-packet") => builder = builder.tamper_policy(TamperPolicy::DropPacket),
+      Some(other) => {
+        return Err(invalid(format!(
+          "unknown tamper_policy {:?}; it should be \"skip\" or \"drop-packet\"",
+          other
+        )))
+      }
+    }
+    if let Some(secs) = raw.dedup_window_secs {
+      builder = builder.dedup_window(Duration::from_secs(secs));
+    }
+    if let Some(secs) = raw.loop_window_secs {
+      builder = builder.loop_window(Duration::from_secs(secs));
+    }
+    if let Some(secs) = raw.clock_skew_secs {
+      builder = builder.clock_skew(Duration::from_secs(secs));
+    }
+    builder = builder.key_hints(raw.key_hints);
+    if let Some(max) = raw.max_packet_bytes {
+      builder = builder.max_packet_size(Some(max).filter(|&m| m > 0));
+    }
+    if let Some(limit) = raw.receive_limit.filter(|&l| l > 0) {
+      builder = builder.receive_limit(limit);
+    }
+    if let Some(max) = raw.max_buffered.filter(|&m| m > 0) {
+      builder = builder.max_buffered(max);
+    }
+
+    let mut schemes = vec![];
+    for (i, transport) in raw.transports.iter().enumerate() {
+      let own: Vec<_> = std::iter::once(&transport.scheme)
+        .chain(&transport.aliases)
+        .map(String::as_str)
+        .collect();
+      for scheme in &own {
+        if schemes.contains(scheme) {
+          return Err(invalid(format!(
+            "transport {} uses scheme {:?}, which an earlier transport already uses",
+            i + 1,
+            scheme
+          )));
+        }
+        schemes.push(scheme);
+      }
+      let kind = transport.kind.as_ref().unwrap_or(&transport.scheme);
+      let add = self.kinds.get(kind).ok_or_else(|| {
+        let known: Vec<_> = self.kinds.keys().map(String::as_str).collect();
+        invalid(format!(
+          "transport {} ({}) has unknown kind {:?}; known kinds are: {}",
+          i + 1,
+          transport.scheme,
+          kind,
+          known.join(", ")
+        ))
+      })?;
+      builder = builder.transport_with(&own, *add);
+      if let Some(quota) = &transport.quota {
+        builder = builder.quota(
+          &transport.scheme,
+          Quota::new(quota.bytes, Duration::from_secs(quota.per_secs)),
+        );
+      }
+    }
+    for transport in &raw.transports {
+      for listen in &transport.listen {
+        let path = self.parse_path("listen path", listen)?;
+        if path.scheme() != transport.scheme && !transport.aliases.iter().any(|a| a == path.scheme()) {
+          return Err(invalid(format!(
+            "listen path {:?} is under the {:?} transport, but its scheme is {:?}",
+            listen,
+            transport.scheme,
+            path.scheme()
+          )));
+        }
+        builder = builder.listen_on(listen);
+      }
+    }
+
+    if let Some(limit) = &raw.rate_limit {
+      builder = builder.rate_limit(RateLimit::new(limit.per_second, limit.burst));
+    }
+    if let Some(inbound) = &raw.inbound {
+      let overflow = match inbound.overflow.as_deref() {
+        None | Some("drop-newest") => Overflow::DropNewest,
+        Some("drop-oldest") => Overflow::DropOldest,
+        Some("block") => Overflow::Block,
+        Some(other) => {
+          return Err(invalid(format!(
+            "unknown inbound overflow {:?}; it should be \"drop-oldest\", \"drop-newest\", or \"block\"",
+            other
+          )))
+        }
+      };
+      builder = builder.inbound_queue(InboundQueue::new(inbound.capacity).overflow(overflow));
+    }
+    if let Some(mix) = &raw.mix {
+      builder = builder.mix_policy(MixPolicy::new(mix.batch_size, Duration::from_millis(mix.max_delay_ms)));
+    }
+    if let Some(forward) = &raw.forward {
+      let mut policy = ForwardPolicy::new();
+      if let Some(only) = &forward.only_schemes {
+        policy = policy.only_schemes(only.iter().map(String::as_str));
+      }
+      for pattern in &forward.deny {
+        policy = policy.deny(pattern);
+      }
+      if forward.deny_local_addresses {
+        policy = policy.deny_local_addresses();
+      }
+      if let Some(limit) = &forward.rate_limit {
+        policy = policy.rate_limit(limit.per_second, limit.burst);
+      }
+      builder = builder.forward_policy(policy);
+    }
+    if let Some(queue) = &raw.queue {
+      let mut opened = OutboundQueue::open(self.base.join(&queue.dir))
+        .map_err(|e| invalid(format!("couldn't open queue directory {}: {:?}", queue.dir, e)))?;
+      if let Some(secs) = queue.retry_secs {
+        opened = opened.retry_every(Duration::from_secs(secs));
+      }
+      if let Some(attempts) = queue.max_attempts {
+        opened = opened.max_attempts(attempts);
+      }
+      builder = builder.outbound_queue(opened);
+    }
+    if let Some(mailbox) = &raw.mailbox {
+      let mut built = Mailbox::new();
+      for recipient in &mailbox.recipients {
+        let key: sign::PublicKey = recipient.parse().map_err(|_| {
+          invalid(format!(
+            "mailbox recipient {:?} should be a signing public key in hex (72 characters, or 64 without the checksum)",
+            recipient
+          ))
+        })?;
+        built = built.register(key);
+      }
+      if let Some(max) = mailbox.max_packets {
+        built = built.max_packets(max);
+      }
+      builder = builder.mailbox(built);
+    }
+    if let Some(discovery) = &raw.discovery {
+      let paths = discovery
+        .paths
+        .iter()
+        .map(|p| self.parse_path("discovery path", p))
+        .collect::<fail::Result<_>>()?;
+      builder = builder.discovery(Discovery::new(paths, Duration::from_secs(discovery.interval_secs)));
+    }
+    Ok(builder)
+  }
+
+  /// Checks the whole config and builds a mesher from it, as with [`builder`](#method.builder).
+  pub fn build(&self) -> fail::Result<Mesher> {
+    self.builder()?.build()
+  }
+}
+
+/// Writes out the config as it was read, e.g. so an application can save one it's changed.
+///
+/// Registered transport kinds, and the directory relative paths were relative to, aren't included.
+impl Serialize for Config {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    self.raw.serialize(s)
+  }
+}
+
+/// Reads a config in any format serde supports, not just TOML or JSON.
+///
+/// Since there's no file for them to be relative to, relative paths in it are taken relative to the working directory.
+impl<'de> Deserialize<'de> for Config {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Config, D::Error> {
+    RawConfig::deserialize(d).map(|raw| Config::new(raw, PathBuf::new()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn error(config: &str) -> String {
+    match Config::from_toml(config, ".").and_then(|c| c.builder()) {
+      Err(fail::MesherFail::InvalidConfig(msg)) => msg,
+      Err(e) => panic!("Wrong error: {:?}", e),
+      Ok(_) => panic!("Config was accepted"),
+    }
+  }
+
+  #[test]
+  fn full_config_builds() {
+    let (_, sk) = encrypt::gen_keypair();
+    let (recipient, _) = sign::gen_keypair();
+    let (root, _) = sign::gen_keypair();
+    let hex = |b: &[u8]| b.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    let config = format!(
+      r#"
+        keys = ["{}"]
+        trust_roots = ["{}"]
+        tamper_policy = "drop-packet"
+        dedup_window_secs = 60
+
+        [[transports]]
+        scheme = "inmem"
+        aliases = ["inmem-too"]
+        listen = ["inmem:config-full", "inmem-too:config-full-alias"]
+        quota = {{ bytes = 1000, per_secs = 60 }}
+
+        [rate_limit]
+        per_second = 10
+        burst = 20
+
+        [forward]
+        deny = ["inmem:internal-*"]
+        deny_local_addresses = true
+
+        [mailbox]
+        recipients = ["{}"]
+      "#,
+      hex(sk.as_bytes()),
+      root,
+      recipient,
+    );
+    let mesher = Config::from_toml(&config, ".")
+      .expect("Failed to parse")
+      .build()
+      .expect("Failed to build");
+    assert_eq!(vec!["inmem", "inmem-too"], mesher.transports());
+    assert!(mesher.mailbox().is_some());
+    assert_eq!(Some(1000), mesher.quota_remaining("inmem-too"));
+    assert_eq!(&[root], mesher.trust_roots());
+
+    let json = format!(
+      r#"{{ "keys": ["{}"], "transports": [{{ "scheme": "inmem", "listen": ["inmem:config-json"] }}] }}"#,
+      hex(sk.as_bytes())
+    );
+    Config::from_json(&json, ".")
+      .expect("Failed to parse")
+      .build()
+      .expect("Failed to build");
+  }
+
+  #[test]
+  fn serializes_as_read() {
+    let config = "dedup_window_secs = 60\n\n[[transports]]\nscheme = \"inmem\"\nlisten = [\"inmem:config-serde\"]\n";
+    let parsed = Config::from_toml(config, ".").expect("Failed to parse");
+    let written = toml::to_string(&parsed).expect("Failed to serialize");
+    let reread: Config = toml::from_str(&written).expect("Failed to deserialize");
+    let json = serde_json::to_value(&reread).expect("Failed to serialize JSON");
+    assert_eq!(Some(60), json["dedup_window_secs"].as_u64());
+    assert_eq!("inmem:config-serde", json["transports"][0]["listen"][0]);
+    reread.build().expect("Failed to build");
+  }
+
+  #[test]
+  fn keys_load_from_keystore() {
+    let dir = std::env::temp_dir();
+    let file = format!("mesher-config-keystore-{:x}", rand::random::<u32>());
+    let store = PassphraseFile::new(dir.join(&file), "hunter2");
+    let (pk, sk) = encrypt::gen_keypair();
+    let (_, ssk) = sign::gen_keypair();
+    store.save("node", sk.as_bytes()).expect("Failed to save");
+    store.save("node.sign", ssk.as_bytes()).expect("Failed to save");
+    std::env::set_var("MESHER_CONFIG_TEST_PASSPHRASE", "hunter2");
+
+    let config = format!(
+      r#"
+        keys = ["keystore:node"]
+        signing_key = "keystore:node.sign"
+
+        [keystore]
+        kind = "file"
+        file = "{}"
+        passphrase_env = "MESHER_CONFIG_TEST_PASSPHRASE"
+
+        [[transports]]
+        scheme = "inmem"
+        listen = ["inmem:config-keystore"]
+      "#,
+      file
+    );
+    let mut mesher = Config::from_toml(&config, &dir)
+      .expect("Failed to parse")
+      .build()
+      .expect("Failed to build");
+    let mut packet = Packet::unsigned();
+    packet.add_hop("inmem:config-keystore".to_owned(), &pk);
+    packet.add_message(&[1], &pk);
+    mesher.launch(packet).expect("Failed to launch");
+    assert_eq!(1, mesher.receive().expect("Failed to receive").len());
+
+    let missing = config.replace("keystore:node\"]", "keystore:nobody\"]");
+    let msg = match Config::from_toml(&missing, &dir).and_then(|c| c.builder()) {
+      Err(fail::MesherFail::InvalidConfig(msg)) => msg,
+      _ => panic!("Missing key was accepted"),
+    };
+    assert!(msg.contains("couldn't load key \"nobody\" from the keystore"));
+    fs::remove_file(dir.join(&file)).expect("Failed to clean up");
+  }
+
+  #[test]
+  fn mistakes_explained() {
+    assert!(error("kyes = []").contains("unknown field `kyes`"));
+    assert!(error("keys = [\"/nonexistent/node.key\"]").contains("couldn't read key /nonexistent/node.key"));
+    assert!(error("[[transports]]\nscheme = \"quic\"").contains("unknown kind \"quic\"; known kinds are: inmem"));
+    assert!(error("[[transports]]\nscheme = \"inmem\"\nlisten = [\"tcp:[::1]:1\"]").contains("its scheme is \"tcp\""));
+    assert!(
+      error("[[transports]]\nscheme = \"inmem\"\n[[transports]]\nscheme = \"other\"\naliases = [\"inmem\"]")
+        .contains("transport 2 uses scheme \"inmem\", which an earlier transport already uses")
+    );
+    assert!(error("tamper_policy = \"ignore\"").contains("\"skip\" or \"drop-packet\""));
+    assert!(error("[mailbox]\nrecipients = [\"abc\"]").contains("signing public key in hex"));
+    assert!(error("keys = [\"keystore:node\"]").contains("there's no [keystore] section"));
+    assert!(error("[keystore]\nkind = \"file\"").contains("need a file"));
+    assert!(
+      error("[inbound]\ncapacity = 8\noverflow = \"spill\"").contains("\"drop-oldest\", \"drop-newest\", or \"block\"")
+    );
+  }
+}
@@ -0,0 +1,339 @@
+//! Contains the address book, which maps names people can remember to the keys and routes of the nodes they stand for.
+//!
+//! Each [`Contact`](struct.Contact.html) has the node's encryption key and, optionally, the route to reach it by: the
+//! relays to go through, if any, ending at the node itself.
+//! [`Contacts`](struct.Contacts.html) keeps them in a file, which is rewritten every time one's added or removed.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::contacts::{Contact, Contacts};
+//! use mesher::Route;
+//!
+//! # let file = std::env::temp_dir().join(format!("mesher-contacts-doc-{}", std::process::id()));
+//! let (alice, _) = encrypt::gen_keypair();
+//! let mut contacts = Contacts::open(&file).expect("Failed to open contacts");
+//! let route = Route::new().then(Path::parse("tcp:[::1]:18540").unwrap(), alice);
+//! contacts.add("alice", Contact::new(alice).via(route)).expect("Failed to add contact");
+//!
+//! let (me, _) = encrypt::gen_keypair();
+//! let mut packet = Packet::unsigned();
+//! let alice = contacts.get("alice").expect("Alice was just added");
+//! packet.via_route(&alice.route_from(Path::parse("tcp:[::1]:18541").unwrap(), me).expect("Alice has a route"));
+//! packet.add_message(b"hi", &alice.key());
+//! # std::fs::remove_file(&file).unwrap();
+//! ```
+//!
+//! Names are up to 64 ASCII letters, digits, `.`, `_`, and `-`, like [keystore](../keystore/index.html) names, so they
+//! can be typed on the command line without any quoting.
+//!
+//! The file is text, so it can be edited by hand: each contact is its name and key on one line, then the nodes in its
+//! route, indented, in the [same form routes are written](../struct.Route.html) in, e.g.:
+//!
+//! ```text
+//! alice 6c1ffa...
+//!   0d4e21...@tcp:[::1]:18541
+//!   6c1ffa...@tcp:[::1]:18540
+//! bob 93be07...
+//! ```
+//!
+//! Blank lines, and lines starting with `#`, are ignored.
+
+use crate::{prelude::*, Route};
+
+use std::{
+  collections::BTreeMap,
+  fs,
+  io::ErrorKind,
+  path::{Path as FsPath, PathBuf},
+};
+
+fn contacts_fail(msg: String) -> fail::MesherFail {
+  fail::MesherFail::ContactsFailure(msg)
+}
+
+/// Checks a contact's name can be passed around without quoting.
+fn check_name(name: &str) -> fail::Result<()> {
+  let ok = |c: char| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-';
+  if name.is_empty() || name.len() > 64 || !name.chars().all(ok) {
+    return Err(contacts_fail(format!(
+      "{:?} isn't a valid contact name; names are up to 64 letters, digits, '.', '_', or '-'",
+      name
+    )));
+  }
+  Ok(())
+}
+
+/// A node someone wants to send to: its encryption key, and maybe how to reach it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+  key: encrypt::PublicKey,
+  route: Route,
+}
+
+impl Contact {
+  /// Creates a contact for the node with the given key, without a route to it.
+  pub fn new(key: encrypt::PublicKey) -> Contact {
+    Contact {
+      key,
+      route: Route::new(),
+    }
+  }
+
+  /// Reaches the contact through `route`, which starts at the first relay to send through, or the contact itself to
+  /// send to it directly, and ends at the contact.
+  ///
+  /// The route's checked when the contact's [added](struct.Contacts.html#method.add).
+  pub fn via(mut self, route: Route) -> Contact {
+    self.route = route;
+    self
+  }
+
+  /// The contact's encryption key, which messages to it should be encrypted for.
+  pub fn key(&self) -> encrypt::PublicKey {
+    self.key
+  }
+
+  /// The route to reach the contact by, which is empty if it hasn't been given one.
+  pub fn route(&self) -> &Route {
+    &self.route
+  }
+
+  fn route_ends_at_key(&self) -> bool {
+    self.route.nodes().last().is_none_or(|(_, last)| *last == self.key)
+  }
+
+  /// The whole route from a sender, listening on `own_path` with `own_key`, to the contact, ready to be
+  /// [applied to a packet](../struct.Packet.html#method.via_route), or `None` if the contact doesn't have a route.
+  pub fn route_from(&self, own_path: Path, own_key: encrypt::PublicKey) -> Option<Route> {
+    if self.route.is_empty() {
+      return None;
+    }
+    let start = Route::new().then(own_path, own_key);
+    Some(
+      self
+        .route
+        .nodes()
+        .fold(start, |route, (path, pkey)| route.then(path.clone(), *pkey)),
+    )
+  }
+}
+
+/// An address book, kept in a file.
+///
+/// Nothing stops two processes changing the same file at once, in which case one's changes will be lost.
+#[derive(Debug)]
+pub struct Contacts {
+  file: PathBuf,
+  contacts: BTreeMap<String, Contact>,
+}
+
+impl Contacts {
+  /// Opens the contacts in `file`, which doesn't have to exist until a contact's added.
+  ///
+  /// Fails with [`MesherFail::ContactsFailure`](../fail/enum.MesherFail.html#variant.ContactsFailure) if the file
+  /// can't be read, or isn't a valid contacts file.
+  pub fn open(file: impl AsRef<FsPath>) -> fail::Result<Contacts> {
+    let file = file.as_ref().to_owned();
+    let text = match fs::read_to_string(&file) {
+      Ok(text) => text,
+      Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+      Err(e) => return Err(contacts_fail(format!("couldn't read {}: {}", file.display(), e))),
+    };
+    let contacts = parse(&text).map_err(|msg| contacts_fail(format!("{}: {}", file.display(), msg)))?;
+    Ok(Contacts { file, contacts })
+  }
+
+  /// The contact with the given name, if there is one.
+  pub fn get(&self, name: &str) -> Option<&Contact> {
+    self.contacts.get(name)
+  }
+
+  /// The name of the contact with the given key, if there is one, e.g. to show who a message came from.
+  pub fn name_of(&self, key: &encrypt::PublicKey) -> Option<&str> {
+    self
+      .contacts
+      .iter()
+      .find(|(_, c)| c.key == *key)
+      .map(|(name, _)| name.as_str())
+  }
+
+  /// Every contact, in order of their names.
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &Contact)> {
+    self.contacts.iter().map(|(name, c)| (name.as_str(), c))
+  }
+
+  /// How many contacts there are.
+  pub fn len(&self) -> usize {
+    self.contacts.len()
+  }
+
+  /// Whether there are no contacts.
+  pub fn is_empty(&self) -> bool {
+    self.contacts.is_empty()
+  }
+
+  /// Adds a contact under `name`, replacing whatever was there, and saves the file.
+  ///
+  /// Fails if the name isn't valid, the contact's route doesn't end at its key, or the file can't be written.
+  pub fn add(&mut self, name: &str, contact: Contact) -> fail::Result<()> {
+    check_name(name)?;
+    if !contact.route_ends_at_key() {
+      return Err(contacts_fail(format!(
+        "the route to {} should end at its key, {}",
+        name, contact.key
+      )));
+    }
+    let old = self.contacts.insert(name.to_owned(), contact);
+    self.save().inspect_err(|_| {
+      // put things back how they were, so what's in memory matches the file
+      match old {
+        Some(old) => self.contacts.insert(name.to_owned(), old),
+        None => self.contacts.remove(name),
+      };
+    })
+  }
+
+  /// Removes the contact with the given name, if there is one, and saves the file.
+  pub fn remove(&mut self, name: &str) -> fail::Result<Option<Contact>> {
+    let removed = match self.contacts.remove(name) {
+      Some(removed) => removed,
+      None => return Ok(None),
+    };
+    match self.save() {
+      Ok(()) => Ok(Some(removed)),
+      Err(e) => {
+        self.contacts.insert(name.to_owned(), removed);
+        Err(e)
+      }
+    }
+  }
+
+  /// Writes every contact to the file, under another name first, so a crash partway through doesn't lose any.
+  fn save(&self) -> fail::Result<()> {
+    let write_fail = |e: std::io::Error| contacts_fail(format!("couldn't write {}: {}", self.file.display(), e));
+    if let Some(dir) = self.file.parent().filter(|d| !d.as_os_str().is_empty()) {
+      fs::create_dir_all(dir).map_err(write_fail)?;
+    }
+    let mut tmp = self.file.clone().into_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, encode(&self.contacts)).map_err(write_fail)?;
+    fs::rename(&tmp, &self.file).map_err(write_fail)
+  }
+}
+
+/// Writes contacts in the format described in the [module docs](index.html).
+fn encode(contacts: &BTreeMap<String, Contact>) -> String {
+  let mut text = String::new();
+  for (name, contact) in contacts {
+    text.push_str(&format!("{} {}\n", name, contact.key));
+    for line in contact.route.to_string().lines() {
+      text.push_str(&format!("  {}\n", line));
+    }
+  }
+  text
+}
+
+/// Reads contacts written by [`encode`](fn.encode.html), or says what's wrong with them.
+fn parse(text: &str) -> Result<BTreeMap<String, Contact>, String> {
+  let mut contacts = BTreeMap::new();
+  let mut current: Option<(String, Contact)> = None;
+  for (i, line) in text.lines().enumerate() {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+      continue;
+    }
+    let bad = |what: &str| format!("line {}: {}", i + 1, what);
+    if line.starts_with(char::is_whitespace) {
+      let (_, contact) = current.as_mut().ok_or_else(|| bad("route node before any contact"))?;
+      let node: Route = trimmed.parse().map_err(|_| bad("invalid route node"))?;
+      contact.route = node.nodes().fold(contact.route.clone(), |route, (path, pkey)| {
+        route.then(path.clone(), *pkey)
+      });
+      continue;
+    }
+    contacts.extend(current.take());
+    let (name, key) = trimmed
+      .split_once(char::is_whitespace)
+      .ok_or_else(|| bad("expected a name and a key"))?;
+    check_name(name).map_err(|_| bad("invalid contact name"))?;
+    let key = key.parse().map_err(|_| bad("invalid key"))?;
+    current = Some((name.to_owned(), Contact::new(key)));
+  }
+  contacts.extend(current);
+  match contacts.iter().find(|(_, c)| !c.route_ends_at_key()) {
+    Some((name, _)) => Err(format!("the route to {} doesn't end at its key", name)),
+    None => Ok(contacts),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::prelude::*;
+
+  fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mesher-contacts-{}-{:x}", name, thread_rng().gen::<u32>()))
+  }
+
+  #[test]
+  fn contacts_round_trip() {
+    let file = temp_file("round-trip");
+    let (alice, _) = encrypt::gen_keypair();
+    let (bob, _) = encrypt::gen_keypair();
+    let (relay, _) = encrypt::gen_keypair();
+    let route = Route::new()
+      .then(Path::parse("tcp:[::1]:18541").unwrap(), relay)
+      .then(Path::parse("tcp:[::1]:18540").unwrap(), alice);
+
+    let mut contacts = Contacts::open(&file).expect("Failed to open");
+    assert!(contacts.is_empty());
+    contacts
+      .add("alice", Contact::new(alice).via(route.clone()))
+      .expect("Failed to add");
+    contacts.add("bob", Contact::new(bob)).expect("Failed to add");
+
+    let reopened = Contacts::open(&file).expect("Failed to reopen");
+    assert_eq!(2, reopened.len());
+    assert_eq!(&route, reopened.get("alice").unwrap().route());
+    assert!(reopened.get("bob").unwrap().route().is_empty());
+    assert_eq!(Some("bob"), reopened.name_of(&bob));
+    assert_eq!(
+      vec!["alice", "bob"],
+      reopened.iter().map(|(n, _)| n).collect::<Vec<_>>()
+    );
+
+    contacts.remove("bob").expect("Failed to remove");
+    assert_eq!(None, Contacts::open(&file).unwrap().get("bob"));
+    fs::remove_file(&file).unwrap();
+  }
+
+  #[test]
+  fn bad_contacts_rejected() {
+    let file = temp_file("bad");
+    let (alice, _) = encrypt::gen_keypair();
+    let (other, _) = encrypt::gen_keypair();
+    let mut contacts = Contacts::open(&file).expect("Failed to open");
+    assert!(contacts.add("no spaces", Contact::new(alice)).is_err());
+    let elsewhere = Route::new().then(Path::parse("tcp:[::1]:18540").unwrap(), other);
+    assert!(contacts.add("alice", Contact::new(alice).via(elsewhere)).is_err());
+    assert!(contacts.is_empty());
+    assert!(!file.exists());
+
+    assert!(parse("  abcd@tcp:[::1]:1\n").is_err());
+    assert!(parse("alice nope\n").is_err());
+    assert!(parse(&format!("alice {}\n  {}@tcp:[::1]:1\n", alice, other)).is_err());
+    assert_eq!(Ok(BTreeMap::new()), parse("# nobody yet\n\n"));
+  }
+
+  #[test]
+  fn full_routes() {
+    let (alice, _) = encrypt::gen_keypair();
+    let (me, _) = encrypt::gen_keypair();
+    let own = Path::parse("tcp:[::1]:1").unwrap();
+    assert_eq!(None, Contact::new(alice).route_from(own.clone(), me));
+    let direct = Route::new().then(Path::parse("tcp:[::1]:2").unwrap(), alice);
+    let full = Contact::new(alice).via(direct).route_from(own, me).unwrap();
+    assert_eq!(2, full.len());
+    assert_eq!(Some(&me), full.nodes().next().map(|(_, k)| k));
+  }
+}
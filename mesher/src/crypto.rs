@@ -1,17 +1,894 @@
+//! Contains the key types used throughout mesher, and the backends which do the actual crypto with them.
+//!
+//! The keys themselves are just bytes, in the standard formats for X25519 (encryption) and Ed25519 (signing), though secret keys can also be [held by a device](hardware/index.html).
+//! The secret ones are wiped from memory when they're dropped, compared in constant time, and left out of `Debug` output.
+//! All of the work done with them is done by a [`Crypto`](trait.Crypto.html) backend.
+//! By default, that's [`Sodium`](struct.Sodium.html), but you can swap in your own, e.g. to use a different library.
+//!
+//! `Sodium` needs libsodium, which can't be built everywhere, so it's behind the `crypto-sodium` feature (on by
+//! default).
+//! Without it, nothing here links against libsodium, and the backend has to be passed in everywhere one's used, e.g.
+//! with [`Packet::unsigned_using`](../struct.Packet.html#method.unsigned_using) and
+//! [`MesherBuilder::crypto`](../struct.MesherBuilder.html#method.crypto); anything that falls back on the
+//! [default backend](fn.default_backend.html) panics when it's used.
+//!
+//! Public keys are written, e.g. in configs and on the command line, as lowercase hex followed by a 4-byte checksum, and
+//! can be parsed back from it with [`str::parse`](https://doc.rust-lang.org/std/primitive.str.html#method.parse), which
+//! rejects mistyped keys rather than reading them as different ones.
+//! Plain hex without the checksum, as written before it was added, is still accepted, but isn't checked.
+//! To check a key by eye, compare its [fingerprint](struct.Fingerprint.html) instead.
+//! [`write_key`](fn.write_key.html) and [`parse_key`](fn.parse_key.html) write and read any key's bytes as plain hex,
+//! e.g. for secret key files.
+
+#[cfg(feature = "crypto-sodium")]
 extern crate sodiumoxide;
 
+use crate::alloc_prelude::*;
+
+use alloc::sync::Arc;
+use core::fmt;
+
+pub mod hardware;
+#[cfg(feature = "pq-hybrid")]
+pub mod hybrid;
+
+/// The operations mesher needs to build and read packets.
+///
+/// Implementing this lets you swap out the library doing the crypto, e.g. for a pure-Rust one, or one that delegates to an HSM.
+/// Meshers and packets can be told to use a specific backend through [`MesherBuilder::crypto`](../struct.MesherBuilder.html#method.crypto) and [`Packet::unsigned_using`](../struct.Packet.html#method.unsigned_using) (or [`signed_using`](../struct.Packet.html#method.signed_using)).
+///
+/// Backends need to be compatible with each other, so that nodes using different ones can still talk:
+///
+/// - Sealing is done like libsodium's [sealed boxes](https://libsodium.gitbook.io/doc/public-key_cryptography/sealed_boxes).
+/// - Signing is done with Ed25519, with the signature prepended to the data, like libsodium's [combined mode](https://libsodium.gitbook.io/doc/public-key_cryptography/public-key_signatures#combined-mode).
+/// - Key hints are HMAC-SHA256, keyed with the public key's bytes.
+/// - Group chunks are libsodium [secret boxes](https://libsodium.gitbook.io/doc/secret-key_cryptography/secretbox), with the nonce prepended.
+///
+/// Secret keys can be [held by a device](hardware/index.html), in which case their raw bytes aren't available, and
+/// backends should pass opening and signing with them to the device, like `Sodium` does.
+pub trait Crypto: Send + Sync {
+  /// Generates a new, random keypair for encryption.
+  fn gen_encrypt_keypair(&self) -> (encrypt::PublicKey, encrypt::SecretKey);
+
+  /// Gets the public key corresponding to an encryption secret key.
+  fn encrypt_public_key(&self, skey: &encrypt::SecretKey) -> encrypt::PublicKey;
+
+  /// Encrypts some data so only the holder of the matching secret key can read it.
+  fn seal(&self, data: &[u8], pkey: &encrypt::PublicKey) -> Vec<u8>;
+
+  /// Decrypts data encrypted by [`seal`](#tymethod.seal), or returns `None` if it wasn't sealed for this key.
+  fn open(&self, data: &[u8], skey: &encrypt::SecretKey) -> Option<Vec<u8>>;
+
+  /// Generates a new, random keypair for signing.
+  fn gen_sign_keypair(&self) -> (sign::PublicKey, sign::SecretKey);
+
+  /// Gets the public key corresponding to a signing secret key.
+  fn sign_public_key(&self, skey: &sign::SecretKey) -> sign::PublicKey;
+
+  /// Signs some data, returning the signature and the data together.
+  fn sign(&self, data: &[u8], skey: &sign::SecretKey) -> Vec<u8>;
+
+  /// Checks data signed by [`sign`](#tymethod.sign), returning the original data if it was signed by this key, or `None` if it wasn't.
+  fn verify(&self, signed: &[u8], pkey: &sign::PublicKey) -> Option<Vec<u8>>;
+
+  /// Works out the [key hint](../struct.Packet.html#method.use_key_hints) for a chunk with the given nonce, sealed for
+  /// the given key.
+  /// Only the start of it is actually used.
+  ///
+  /// With the `crypto-sodium` feature, this uses libsodium by default, since it doesn't involve any secret keys.
+  #[cfg(feature = "crypto-sodium")]
+  fn key_hint(&self, nonce: &[u8], pkey: &encrypt::PublicKey) -> [u8; 32] {
+    use sodiumoxide::crypto::auth::hmacsha256;
+    hmacsha256::authenticate(nonce, &hmacsha256::Key(pkey.0)).0
+  }
+  /// Works out the [key hint](../struct.Packet.html#method.use_key_hints) for a chunk with the given nonce, sealed for
+  /// the given key.
+  /// Only the start of it is actually used.
+  ///
+  /// With the `crypto-sodium` feature, this uses libsodium by default, since it doesn't involve any secret keys.
+  #[cfg(not(feature = "crypto-sodium"))]
+  fn key_hint(&self, nonce: &[u8], pkey: &encrypt::PublicKey) -> [u8; 32];
+
+  /// Encrypts some data so anyone holding the [group key](group/struct.GroupKey.html) can read it.
+  ///
+  /// With the `crypto-sodium` feature, this uses libsodium by default, like [`key_hint`](#method.key_hint).
+  #[cfg(feature = "crypto-sodium")]
+  fn group_seal(&self, data: &[u8], key: &group::GroupKey) -> Vec<u8> {
+    use sodiumoxide::crypto::secretbox;
+    let nonce = secretbox::gen_nonce();
+    let mut sealed = nonce.0.to_vec();
+    sealed.extend(secretbox::seal(data, &nonce, &secretbox::Key(key.key)));
+    sealed
+  }
+  /// Encrypts some data so anyone holding the [group key](group/struct.GroupKey.html) can read it.
+  ///
+  /// With the `crypto-sodium` feature, this uses libsodium by default, like [`key_hint`](#method.key_hint).
+  #[cfg(not(feature = "crypto-sodium"))]
+  fn group_seal(&self, data: &[u8], key: &group::GroupKey) -> Vec<u8>;
+
+  /// Decrypts data encrypted by [`group_seal`](#method.group_seal), or returns `None` if it wasn't sealed with this key.
+  #[cfg(feature = "crypto-sodium")]
+  fn group_open(&self, data: &[u8], key: &group::GroupKey) -> Option<Vec<u8>> {
+    use sodiumoxide::crypto::secretbox;
+    if data.len() < secretbox::NONCEBYTES {
+      return None;
+    }
+    let (nonce, sealed) = data.split_at(secretbox::NONCEBYTES);
+    secretbox::open(sealed, &secretbox::Nonce::from_slice(nonce)?, &secretbox::Key(key.key)).ok()
+  }
+  /// Decrypts data encrypted by [`group_seal`](#method.group_seal), or returns `None` if it wasn't sealed with this key.
+  #[cfg(not(feature = "crypto-sodium"))]
+  fn group_open(&self, data: &[u8], key: &group::GroupKey) -> Option<Vec<u8>>;
+}
+
+/// The default crypto backend, using [`sodiumoxide`](https://crates.io/crates/sodiumoxide) (i.e. libsodium).
+///
+/// Only available with the `crypto-sodium` feature.
+#[cfg(feature = "crypto-sodium")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sodium;
+
+#[cfg(feature = "crypto-sodium")]
+impl Crypto for Sodium {
+  fn gen_encrypt_keypair(&self) -> (encrypt::PublicKey, encrypt::SecretKey) {
+    let (pk, sk) = sodiumoxide::crypto::box_::gen_keypair();
+    (encrypt::PublicKey(pk.0), encrypt::SecretKey(sk.0, None))
+  }
+
+  fn encrypt_public_key(&self, skey: &encrypt::SecretKey) -> encrypt::PublicKey {
+    if let Some(held) = &skey.1 {
+      return encrypt::PublicKey(held.public);
+    }
+    let sk = sodiumoxide::crypto::box_::SecretKey(skey.0);
+    encrypt::PublicKey(sk.public_key().0)
+  }
+
+  fn seal(&self, data: &[u8], pkey: &encrypt::PublicKey) -> Vec<u8> {
+    sodiumoxide::crypto::sealedbox::seal(data, &sodiumoxide::crypto::box_::PublicKey(pkey.0))
+  }
+
+  fn open(&self, data: &[u8], skey: &encrypt::SecretKey) -> Option<Vec<u8>> {
+    if let Some(held) = &skey.1 {
+      return held.open(data);
+    }
+    let sk = sodiumoxide::crypto::box_::SecretKey(skey.0);
+    sodiumoxide::crypto::sealedbox::open(data, &sk.public_key(), &sk).ok()
+  }
+
+  fn gen_sign_keypair(&self) -> (sign::PublicKey, sign::SecretKey) {
+    let (pk, sk) = sodiumoxide::crypto::sign::gen_keypair();
+    (sign::PublicKey(pk.0), sign::SecretKey(sk.0, None))
+  }
+
+  fn sign_public_key(&self, skey: &sign::SecretKey) -> sign::PublicKey {
+    if let Some(held) = &skey.1 {
+      return sign::PublicKey(held.public);
+    }
+    let sk = sodiumoxide::crypto::sign::SecretKey(skey.0);
+    sign::PublicKey(sk.public_key().0)
+  }
+
+  fn sign(&self, data: &[u8], skey: &sign::SecretKey) -> Vec<u8> {
+    if let Some(held) = &skey.1 {
+      return held.sign(data);
+    }
+    sodiumoxide::crypto::sign::sign(data, &sodiumoxide::crypto::sign::SecretKey(skey.0))
+  }
+
+  fn verify(&self, signed: &[u8], pkey: &sign::PublicKey) -> Option<Vec<u8>> {
+    sodiumoxide::crypto::sign::verify(signed, &sodiumoxide::crypto::sign::PublicKey(pkey.0)).ok()
+  }
+}
+
+/// The backend used when one isn't explicitly given, i.e. [`Sodium`](struct.Sodium.html).
+#[cfg(feature = "crypto-sodium")]
+pub fn default_backend() -> Arc<dyn Crypto> {
+  Arc::new(Sodium)
+}
+
+/// The backend used when one isn't explicitly given, which, without the `crypto-sodium` feature, panics as soon as it's
+/// used, since there's nothing to fall back on.
+#[cfg(not(feature = "crypto-sodium"))]
+pub fn default_backend() -> Arc<dyn Crypto> {
+  Arc::new(NoBackend)
+}
+
+/// Stands in for the default backend without the `crypto-sodium` feature, so code that never uses it still builds.
+#[cfg(not(feature = "crypto-sodium"))]
+struct NoBackend;
+
+#[cfg(not(feature = "crypto-sodium"))]
+impl NoBackend {
+  fn missing(&self) -> ! {
+    panic!("mesher was built without the crypto-sodium feature, so a crypto backend has to be passed in explicitly")
+  }
+}
+
+#[cfg(not(feature = "crypto-sodium"))]
+impl Crypto for NoBackend {
+  fn gen_encrypt_keypair(&self) -> (encrypt::PublicKey, encrypt::SecretKey) {
+    self.missing()
+  }
+  fn encrypt_public_key(&self, _: &encrypt::SecretKey) -> encrypt::PublicKey {
+    self.missing()
+  }
+  fn seal(&self, _: &[u8], _: &encrypt::PublicKey) -> Vec<u8> {
+    self.missing()
+  }
+  fn open(&self, _: &[u8], _: &encrypt::SecretKey) -> Option<Vec<u8>> {
+    self.missing()
+  }
+  fn gen_sign_keypair(&self) -> (sign::PublicKey, sign::SecretKey) {
+    self.missing()
+  }
+  fn sign_public_key(&self, _: &sign::SecretKey) -> sign::PublicKey {
+    self.missing()
+  }
+  fn sign(&self, _: &[u8], _: &sign::SecretKey) -> Vec<u8> {
+    self.missing()
+  }
+  fn verify(&self, _: &[u8], _: &sign::PublicKey) -> Option<Vec<u8>> {
+    self.missing()
+  }
+  fn key_hint(&self, _: &[u8], _: &encrypt::PublicKey) -> [u8; 32] {
+    self.missing()
+  }
+  fn group_seal(&self, _: &[u8], _: &group::GroupKey) -> Vec<u8> {
+    self.missing()
+  }
+  fn group_open(&self, _: &[u8], _: &group::GroupKey) -> Option<Vec<u8>> {
+    self.missing()
+  }
+}
+
+/// A short hash of a public key, for people to compare when checking they've got the right one.
+///
+/// It's the first 8 bytes of the SHA-256 hash of the key's bytes, displayed as four groups of four hex digits, e.g.
+/// `3f2a:91c0:5d7e:0b44`.
+/// That's plenty to catch typos and mix-ups, but not to stop someone deliberately searching for a key with the same
+/// fingerprint, so don't use it in place of the whole key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub [u8; 8]);
+
+impl Fingerprint {
+  /// Fingerprints a key's bytes.
+  ///
+  /// It's hashed with the [`sha2`](https://crates.io/crates/sha2) crate, rather than a backend, so fingerprints work
+  /// without `crypto-sodium`, and always come out the same.
+  fn of(key: &[u8]) -> Fingerprint {
+    use sha2::Digest;
+    let mut fingerprint = [0; 8];
+    fingerprint.copy_from_slice(&sha2::Sha256::digest(key)[..8]);
+    Fingerprint(fingerprint)
+  }
+}
+
+impl fmt::Display for Fingerprint {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (i, pair) in self.0.chunks(2).enumerate() {
+      if i > 0 {
+        f.write_str(":")?;
+      }
+      write!(f, "{:02x}{:02x}", pair[0], pair[1])?;
+    }
+    Ok(())
+  }
+}
+
+/// Writes a key's bytes, public or secret, as lowercase hex, the way configs and key files hold them.
+///
+/// Wipe the string once it's written out, if the key's secret.
+pub fn write_key(key: &[u8]) -> String {
+  let mut hex = String::with_capacity(key.len() * 2);
+  for b in key {
+    hex.push(char::from_digit((b >> 4) as u32, 16).expect("a nibble is a hex digit"));
+    hex.push(char::from_digit((b & 0xf) as u32, 16).expect("a nibble is a hex digit"));
+  }
+  hex
+}
+
+/// Reads hex written by [`write_key`](fn.write_key.html), in either case, ignoring surrounding whitespace, or returns
+/// `None` if it isn't hex.
+///
+/// Any number of bytes is accepted, so check the length is the one expected.
+pub fn parse_key(text: &str) -> Option<Vec<u8>> {
+  let hex = text.trim().as_bytes();
+  if !hex.len().is_multiple_of(2) || !hex.iter().all(u8::is_ascii_hexdigit) {
+    return None;
+  }
+  let digit = |d: u8| (d as char).to_digit(16).expect("checked it's a hex digit") as u8;
+  Some(hex.chunks(2).map(|pair| digit(pair[0]) << 4 | digit(pair[1])).collect())
+}
+
+/// How many bytes of checksum follow a public key written as text.
+const CHECKSUM_BYTES: usize = 4;
+
+/// Writes a public key's bytes as hex, followed by its checksum: the first few bytes of its
+/// [fingerprint](struct.Fingerprint.html).
+fn write_public_key(key: &[u8]) -> String {
+  write_key(&[key, &Fingerprint::of(key).0[..CHECKSUM_BYTES]].concat())
+}
+
+/// Reads a `len`-byte public key written by [`write_public_key`](fn.write_public_key.html), or as plain hex, or returns
+/// `None` if it isn't one or its checksum doesn't match.
+pub(crate) fn parse_public_key(text: &str, len: usize) -> Option<Vec<u8>> {
+  let mut key = parse_key(text)?;
+  if key.len() == len + CHECKSUM_BYTES {
+    let checksum = key.split_off(len);
+    if checksum[..] != Fingerprint::of(&key).0[..CHECKSUM_BYTES] {
+      return None;
+    }
+  }
+  Some(key).filter(|key| key.len() == len)
+}
+
+/// Adds `fingerprint`, `Display`, and `FromStr` to a public key type.
+macro_rules! public_key_text {
+  () => {
+    impl PublicKey {
+      /// The key's [fingerprint](../struct.Fingerprint.html), hashed with `sha2` whichever backend's in use.
+      pub fn fingerprint(&self) -> super::Fingerprint {
+        super::Fingerprint::of(&self.0)
+      }
+    }
+
+    /// Writes the key as 72 lowercase hex digits: 64 for the key, then 8 for its checksum.
+    impl core::fmt::Display for PublicKey {
+      fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(&super::write_public_key(&self.0))
+      }
+    }
+
+    /// Reads a key written by `Display`, or as 64 hex digits with no checksum, ignoring surrounding whitespace.
+    ///
+    /// Fails if the checksum's there but doesn't match, i.e. the key was mistyped.
+    impl core::str::FromStr for PublicKey {
+      type Err = crate::fail::MesherFail;
+
+      fn from_str(s: &str) -> crate::fail::Result<PublicKey> {
+        super::parse_public_key(s, core::mem::size_of::<PublicKey>())
+          .and_then(|key| core::convert::TryInto::try_into(key).ok())
+          .map(PublicKey)
+          .ok_or_else(|| crate::fail::MesherFail::InvalidKey(s.into()))
+      }
+    }
+  };
+}
+
+/// Compares two byte strings in time that only depends on their length, so comparing secrets doesn't leak where they
+/// first differ.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  let diff = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+  core::hint::black_box(diff) == 0
+}
+
+/// Overwrites the bytes with zeroes, in a way the compiler can't optimize out just because they're about to be freed.
+pub(crate) fn wipe(bytes: &mut [u8]) {
+  for byte in bytes.iter_mut() {
+    // SAFETY: `byte` comes from a `&mut`, so it's valid, aligned, and not aliased
+    unsafe { core::ptr::write_volatile(byte, 0) };
+  }
+  core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
 pub mod encrypt {
-  pub use sodiumoxide::crypto::box_::{gen_keypair, PublicKey, SecretKey};
+  //! Keys for encrypting chunks for specific nodes.
 
-  pub(crate) use sodiumoxide::crypto::sealedbox::seal;
+  use super::hardware::{Device, Held, KeyHandle};
+  use alloc::sync::Arc;
 
-  pub(crate) fn open(c: &[u8], key: &SecretKey) -> Result<Vec<u8>, ()> {
-    sodiumoxide::crypto::sealedbox::open(c, &key.public_key(), key)
+  /// The public half of an X25519 keypair, which chunks are encrypted for.
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+  pub struct PublicKey(pub(crate) [u8; 32]);
+
+  impl PublicKey {
+    /// Creates a key from its raw bytes, if there are the right number of them.
+    pub fn from_slice(bytes: &[u8]) -> Option<PublicKey> {
+      let mut key = [0; 32];
+      if bytes.len() != key.len() {
+        return None;
+      }
+      key.copy_from_slice(bytes);
+      Some(PublicKey(key))
+    }
+
+    /// The raw bytes of the key.
+    pub fn as_bytes(&self) -> &[u8] {
+      &self.0
+    }
+  }
+
+  public_key_text!();
+
+  /// The secret half of an X25519 keypair, which chunks are decrypted with.
+  ///
+  /// It isn't `Clone`, so that every copy of it is made on purpose, with [`clone_secret`](#method.clone_secret).
+  /// It can also refer to a key [held by a device](#method.held), rather than holding the key itself.
+  pub struct SecretKey(pub(crate) [u8; 32], pub(crate) Option<Held>);
+
+  impl PartialEq for SecretKey {
+    fn eq(&self, other: &SecretKey) -> bool {
+      match (&self.1, &other.1) {
+        (None, None) => super::ct_eq(&self.0, &other.0),
+        (Some(held), Some(other)) => held.same(other),
+        _ => false,
+      }
+    }
+  }
+
+  impl Eq for SecretKey {}
+
+  impl Drop for SecretKey {
+    fn drop(&mut self) {
+      super::wipe(&mut self.0);
+    }
+  }
+
+  impl core::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+      match &self.1 {
+        Some(held) => write!(f, "SecretKey(<held as {:?}>)", held.handle),
+        None => f.write_str("SecretKey(<redacted>)"),
+      }
+    }
+  }
+
+  impl SecretKey {
+    /// Creates a key from its raw bytes, if there are the right number of them.
+    pub fn from_slice(bytes: &[u8]) -> Option<SecretKey> {
+      let mut key = [0; 32];
+      if bytes.len() != key.len() {
+        return None;
+      }
+      key.copy_from_slice(bytes);
+      Some(SecretKey(key, None))
+    }
+
+    /// Refers to a key held by a device, which does everything that needs the secret half, given its public half.
+    pub fn held(device: Arc<dyn Device>, handle: KeyHandle, public: PublicKey) -> SecretKey {
+      SecretKey(
+        [0; 32],
+        Some(Held {
+          device,
+          handle,
+          public: public.0,
+        }),
+      )
+    }
+
+    /// The handle of the key on its device, if it's [held by one](#method.held).
+    pub fn handle(&self) -> Option<KeyHandle> {
+      self.1.as_ref().map(|held| held.handle)
+    }
+
+    /// The raw bytes of the key, or nothing if it's [held by a device](#method.held).
+    pub fn as_bytes(&self) -> &[u8] {
+      match self.1 {
+        Some(_) => &[],
+        None => &self.0,
+      }
+    }
+
+    /// Makes a copy of the key.
+    ///
+    /// Each copy is wiped separately when it's dropped, so keep as few around as possible.
+    /// Copies of a held key refer to the same key on the same device.
+    pub fn clone_secret(&self) -> SecretKey {
+      SecretKey(self.0, self.1.clone())
+    }
+
+    /// Gets the matching public key, using the default backend.
+    pub fn public_key(&self) -> PublicKey {
+      match &self.1 {
+        Some(held) => PublicKey(held.public),
+        None => super::default_backend().encrypt_public_key(self),
+      }
+    }
+  }
+
+  /// Generates a new, random keypair, using the default backend.
+  pub fn gen_keypair() -> (PublicKey, SecretKey) {
+    super::default_backend().gen_encrypt_keypair()
   }
 }
 
 pub mod sign {
-  pub use sodiumoxide::crypto::sign::{gen_keypair, PublicKey, SecretKey};
+  //! Keys for signing chunks, so receivers can tell who sent them.
+
+  use super::hardware::{Device, Held, KeyHandle};
+  use alloc::sync::Arc;
+
+  /// The public half of an Ed25519 keypair, which signatures are checked against.
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+  pub struct PublicKey(pub(crate) [u8; 32]);
+
+  impl PublicKey {
+    /// Creates a key from its raw bytes, if there are the right number of them.
+    pub fn from_slice(bytes: &[u8]) -> Option<PublicKey> {
+      let mut key = [0; 32];
+      if bytes.len() != key.len() {
+        return None;
+      }
+      key.copy_from_slice(bytes);
+      Some(PublicKey(key))
+    }
+
+    /// The raw bytes of the key.
+    pub fn as_bytes(&self) -> &[u8] {
+      &self.0
+    }
+  }
+
+  public_key_text!();
+
+  /// The secret half of an Ed25519 keypair, which chunks are signed with.
+  ///
+  /// Stored in libsodium's format: the 32-byte seed followed by the 32-byte public key.
+  /// It isn't `Clone`, so that every copy of it is made on purpose, with [`clone_secret`](#method.clone_secret).
+  /// It can also refer to a key [held by a device](#method.held), rather than holding the key itself.
+  pub struct SecretKey(pub(crate) [u8; 64], pub(crate) Option<Held>);
+
+  impl PartialEq for SecretKey {
+    fn eq(&self, other: &SecretKey) -> bool {
+      match (&self.1, &other.1) {
+        (None, None) => super::ct_eq(&self.0, &other.0),
+        (Some(held), Some(other)) => held.same(other),
+        _ => false,
+      }
+    }
+  }
+
+  impl Eq for SecretKey {}
+
+  impl Drop for SecretKey {
+    fn drop(&mut self) {
+      super::wipe(&mut self.0);
+    }
+  }
+
+  impl core::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+      match &self.1 {
+        Some(held) => write!(f, "SecretKey(<held as {:?}>)", held.handle),
+        None => f.write_str("SecretKey(<redacted>)"),
+      }
+    }
+  }
+
+  impl SecretKey {
+    /// Creates a key from its raw bytes, if there are the right number of them.
+    pub fn from_slice(bytes: &[u8]) -> Option<SecretKey> {
+      let mut key = [0; 64];
+      if bytes.len() != key.len() {
+        return None;
+      }
+      key.copy_from_slice(bytes);
+      Some(SecretKey(key, None))
+    }
+
+    /// Refers to a key held by a device, which does everything that needs the secret half, given its public half.
+    pub fn held(device: Arc<dyn Device>, handle: KeyHandle, public: PublicKey) -> SecretKey {
+      SecretKey(
+        [0; 64],
+        Some(Held {
+          device,
+          handle,
+          public: public.0,
+        }),
+      )
+    }
+
+    /// The handle of the key on its device, if it's [held by one](#method.held).
+    pub fn handle(&self) -> Option<KeyHandle> {
+      self.1.as_ref().map(|held| held.handle)
+    }
+
+    /// The raw bytes of the key, or nothing if it's [held by a device](#method.held).
+    pub fn as_bytes(&self) -> &[u8] {
+      match self.1 {
+        Some(_) => &[],
+        None => &self.0,
+      }
+    }
+
+    /// Makes a copy of the key.
+    ///
+    /// Each copy is wiped separately when it's dropped, so keep as few around as possible.
+    /// Copies of a held key refer to the same key on the same device.
+    pub fn clone_secret(&self) -> SecretKey {
+      SecretKey(self.0, self.1.clone())
+    }
+
+    /// Gets the matching public key, using the default backend.
+    pub fn public_key(&self) -> PublicKey {
+      match &self.1 {
+        Some(held) => PublicKey(held.public),
+        None => super::default_backend().sign_public_key(self),
+      }
+    }
+  }
+
+  /// Generates a new, random keypair, using the default backend.
+  pub fn gen_keypair() -> (PublicKey, SecretKey) {
+    super::default_backend().gen_sign_keypair()
+  }
+}
 
-  pub(crate) use sodiumoxide::crypto::sign::{sign, verify};
+pub mod group {
+  //! Keys shared by every member of a group, so one chunk can be read by all of them.
+
+  use crate::alloc_prelude::*;
+  use rand::{rngs::OsRng, RngCore};
+
+  /// Identifies a group, so its members know which key to open its chunks with.
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct GroupId(pub u64);
+
+  /// The symmetric key shared by a group's members, along with the group's ID and its epoch, i.e. how many times the
+  /// group's been re-keyed.
+  ///
+  /// Like secret keys, the key itself is wiped when it's dropped and compared in constant time.
+  #[derive(Clone)]
+  pub struct GroupKey {
+    pub(crate) id: GroupId,
+    pub(crate) epoch: u32,
+    pub(crate) key: [u8; 32],
+  }
+
+  impl PartialEq for GroupKey {
+    fn eq(&self, other: &GroupKey) -> bool {
+      self.id == other.id && self.epoch == other.epoch && super::ct_eq(&self.key, &other.key)
+    }
+  }
+
+  impl Eq for GroupKey {}
+
+  impl Drop for GroupKey {
+    fn drop(&mut self) {
+      super::wipe(&mut self.key);
+    }
+  }
+
+  /// Leaves out the key itself, so it isn't logged by accident.
+  impl core::fmt::Debug for GroupKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+      f.debug_struct("GroupKey")
+        .field("id", &self.id)
+        .field("epoch", &self.epoch)
+        .finish_non_exhaustive()
+    }
+  }
+
+  impl GroupKey {
+    /// Generates the first key for a new group, with a random ID, using the OS's RNG.
+    pub fn generate() -> GroupKey {
+      GroupKey {
+        id: GroupId(OsRng.next_u64()),
+        epoch: 0,
+        key: GroupKey::random_key(),
+      }
+    }
+
+    fn random_key() -> [u8; 32] {
+      let mut key = [0; 32];
+      OsRng.fill_bytes(&mut key);
+      key
+    }
+
+    /// Generates the group's next key, in the next epoch, e.g. so a removed member can't read what's sent with it.
+    pub fn next(&self) -> GroupKey {
+      GroupKey {
+        id: self.id,
+        epoch: self.epoch + 1,
+        key: GroupKey::random_key(),
+      }
+    }
+
+    /// The group this key is for.
+    pub fn id(&self) -> GroupId {
+      self.id
+    }
+
+    /// Which of the group's keys this is, counting up from 0 every time it's re-keyed.
+    pub fn epoch(&self) -> u32 {
+      self.epoch
+    }
+
+    /// Encodes the ID and epoch as big-endian integers, then the key.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+      let mut b = Vec::with_capacity(44);
+      b.extend_from_slice(&self.id.0.to_be_bytes());
+      b.extend_from_slice(&self.epoch.to_be_bytes());
+      b.extend_from_slice(&self.key);
+      b
+    }
+
+    /// Decodes a key written by [`encode`](#method.encode), if it's well-formed.
+    pub(crate) fn decode(from: &[u8]) -> Option<GroupKey> {
+      if from.len() != 44 {
+        return None;
+      }
+      let mut id = [0; 8];
+      id.copy_from_slice(&from[..8]);
+      let mut epoch = [0; 4];
+      epoch.copy_from_slice(&from[8..12]);
+      let mut key = [0; 32];
+      key.copy_from_slice(&from[12..]);
+      Some(GroupKey {
+        id: GroupId(u64::from_be_bytes(id)),
+        epoch: u32::from_be_bytes(epoch),
+        key,
+      })
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sodium_seal_roundtrip() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let (_, other_sk) = encrypt::gen_keypair();
+    let sealed = Sodium.seal(&[1, 2, 3], &pk);
+    assert_eq!(Some(vec![1, 2, 3]), Sodium.open(&sealed, &sk));
+    assert_eq!(None, Sodium.open(&sealed, &other_sk));
+    assert_eq!(pk, sk.public_key());
+  }
+
+  #[test]
+  fn sodium_sign_roundtrip() {
+    let (pk, sk) = sign::gen_keypair();
+    let (other_pk, _) = sign::gen_keypair();
+    let signed = Sodium.sign(&[1, 2, 3], &sk);
+    assert_eq!(Some(vec![1, 2, 3]), Sodium.verify(&signed, &pk));
+    assert_eq!(None, Sodium.verify(&signed, &other_pk));
+    assert_eq!(pk, sk.public_key());
+  }
+
+  #[test]
+  fn secret_keys_are_redacted_and_wiped() {
+    let (_, sk) = sign::gen_keypair();
+    let copy = sk.clone_secret();
+    assert_eq!(sk, copy);
+    assert_eq!("SecretKey(<redacted>)", format!("{:?}", copy));
+    let (_, other) = sign::gen_keypair();
+    assert_ne!(sk, other);
+    assert!(!ct_eq(&[1, 2], &[1, 2, 3]));
+
+    let mut bytes = [7; 64];
+    wipe(&mut bytes);
+    assert_eq!([0; 64], bytes);
+  }
+
+  /// Wraps Sodium, counting how many times each backend operation is used.
+  #[derive(Default)]
+  struct Counting {
+    seals: std::sync::atomic::AtomicUsize,
+    opens: std::sync::atomic::AtomicUsize,
+  }
+
+  impl Crypto for Counting {
+    fn gen_encrypt_keypair(&self) -> (encrypt::PublicKey, encrypt::SecretKey) {
+      Sodium.gen_encrypt_keypair()
+    }
+    fn encrypt_public_key(&self, skey: &encrypt::SecretKey) -> encrypt::PublicKey {
+      Sodium.encrypt_public_key(skey)
+    }
+    fn seal(&self, data: &[u8], pkey: &encrypt::PublicKey) -> Vec<u8> {
+      self.seals.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      Sodium.seal(data, pkey)
+    }
+    fn open(&self, data: &[u8], skey: &encrypt::SecretKey) -> Option<Vec<u8>> {
+      self.opens.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      Sodium.open(data, skey)
+    }
+    fn gen_sign_keypair(&self) -> (sign::PublicKey, sign::SecretKey) {
+      Sodium.gen_sign_keypair()
+    }
+    fn sign_public_key(&self, skey: &sign::SecretKey) -> sign::PublicKey {
+      Sodium.sign_public_key(skey)
+    }
+    fn sign(&self, data: &[u8], skey: &sign::SecretKey) -> Vec<u8> {
+      Sodium.sign(data, skey)
+    }
+    fn verify(&self, signed: &[u8], pkey: &sign::PublicKey) -> Option<Vec<u8>> {
+      Sodium.verify(signed, pkey)
+    }
+  }
+
+  #[test]
+  fn custom_backend_used() {
+    use crate::prelude::*;
+    use std::sync::atomic::Ordering;
+
+    let counting = Arc::new(Counting::default());
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut mesher = Mesher::builder()
+      .own_key(sk)
+      .crypto(counting.clone())
+      .transport::<crate::debug_transports::InMemory>("inmem")
+      .listen_on("inmem:custom-backend")
+      .build()
+      .expect("Failed to build mesher");
+
+    let mut packet = Packet::unsigned_using(counting.clone());
+    packet.add_hop("inmem:custom-backend".to_owned(), &encrypt::gen_keypair().0);
+    packet.add_message(&[1], &pk);
+    assert_eq!(2, counting.seals.load(Ordering::SeqCst));
+
+    let mut t = crate::debug_transports::InMemory::new("inmem").expect("Failed to create transport");
+    t.send(
+      &Path::parse("inmem:custom-backend").expect("Failed to parse path"),
+      &packet.serialize().expect("Failed to serialize"),
+    )
+    .expect("Failed to send");
+    let received = mesher.receive().expect("Failed to receive");
+    assert_eq!(1, received.len());
+    assert_eq!(2, counting.opens.load(Ordering::SeqCst));
+  }
+
+  #[test]
+  fn keys_from_slices() {
+    let (pk, sk) = encrypt::gen_keypair();
+    assert_eq!(Some(pk), encrypt::PublicKey::from_slice(pk.as_bytes()));
+    assert!(encrypt::SecretKey::from_slice(sk.as_bytes()) == Some(sk));
+    assert_eq!(None, encrypt::PublicKey::from_slice(&[0; 31]));
+    let (pk, sk) = sign::gen_keypair();
+    assert_eq!(Some(pk), sign::PublicKey::from_slice(pk.as_bytes()));
+    assert!(sign::SecretKey::from_slice(sk.as_bytes()) == Some(sk));
+    assert!(sign::SecretKey::from_slice(&[0; 32]).is_none());
+  }
+
+  #[test]
+  fn public_keys_as_text() {
+    let (pk, _) = encrypt::gen_keypair();
+    let text = pk.to_string();
+    assert_eq!(72, text.len());
+    assert_eq!(pk, text.parse().unwrap());
+    assert_eq!(pk, format!(" {}\n", text).parse().unwrap());
+    // keys written before the checksum was added still parse
+    assert_eq!(pk, text[..64].parse().unwrap());
+    // the checksum is the start of the fingerprint
+    let zero = encrypt::PublicKey([0; 32]);
+    assert_eq!(format!("{}66687aad", "00".repeat(32)), zero.to_string());
+    // changing any digit, of the key or the checksum, is caught
+    for i in 0..text.len() {
+      let mut typo = text.clone().into_bytes();
+      typo[i] = if typo[i] == b'0' { b'1' } else { b'0' };
+      let typo = String::from_utf8(typo).unwrap();
+      assert!(
+        typo.parse::<encrypt::PublicKey>().is_err(),
+        "{} should be rejected",
+        typo
+      );
+    }
+    assert!(format!("{}00", text).parse::<encrypt::PublicKey>().is_err());
+    let (spk, _) = sign::gen_keypair();
+    assert_eq!(spk, spk.to_string().parse().unwrap());
+    assert!(matches!(
+      "abcd".parse::<encrypt::PublicKey>(),
+      Err(crate::fail::MesherFail::InvalidKey(_))
+    ));
+    assert!("zz".repeat(32).parse::<sign::PublicKey>().is_err());
+    // from_str_radix takes a leading sign, so this would read as 0x01
+    assert!(format!("+1{}", "00".repeat(31)).parse::<encrypt::PublicKey>().is_err());
+    assert_eq!(None, parse_key("+1"));
+    assert_eq!(Some(vec![0xab, 0xcd]), parse_key(" AbCd\n"));
+    assert_eq!("00ff10", write_key(&[0, 0xff, 0x10]));
+  }
+
+  #[test]
+  fn fingerprints() {
+    // the first 8 bytes of the SHA-256 hash of 32 zero bytes
+    let zero = encrypt::PublicKey([0; 32]);
+    assert_eq!("6668:7aad:f862:bd77", zero.fingerprint().to_string());
+    assert_eq!(zero.fingerprint(), sign::PublicKey([0; 32]).fingerprint());
+    assert_ne!(zero.fingerprint(), encrypt::gen_keypair().0.fingerprint());
+  }
 }
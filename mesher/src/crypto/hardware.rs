@@ -0,0 +1,96 @@
+//! Support for secret keys held by a device, e.g. a PKCS#11 token, a TPM, or a YubiKey, which never hands out the key
+//! itself.
+//!
+//! Mesher doesn't drive any devices on its own.
+//! Instead, implement [`Device`](trait.Device.html) on top of whatever library talks to yours, then make keys from its
+//! handles with [`encrypt::SecretKey::held`](../encrypt/struct.SecretKey.html#method.held) and
+//! [`sign::SecretKey::held`](../sign/struct.SecretKey.html#method.held).
+//! Those can be used anywhere a raw key can, e.g. as a mesher's own keys or its signing key, and the default backend
+//! passes everything that needs the secret half to the device.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::crypto::hardware::{Device, KeyHandle};
+//! use std::sync::Arc;
+//!
+//! struct Token; // e.g. wrapping a PKCS#11 session
+//!
+//! impl Device for Token {
+//!   fn open(&self, handle: KeyHandle, sealed: &[u8]) -> Option<Vec<u8>> {
+//!     // derive the shared secret on the token, then open the sealed box with it
+//! #   let _ = (handle, sealed);
+//!     None
+//!   }
+//!
+//!   fn sign(&self, handle: KeyHandle, data: &[u8]) -> Option<[u8; 64]> {
+//!     // have the token make an Ed25519 signature
+//! #   let _ = (handle, data);
+//!     None
+//!   }
+//! }
+//!
+//! // the public key is read off the device once, e.g. when the key's generated on it
+//! # let (public, _) = encrypt::gen_keypair();
+//! let skey = encrypt::SecretKey::held(Arc::new(Token), KeyHandle(3), public);
+//! assert_eq!(public, skey.public_key());
+//! let _mesher = Mesher::unsigned(vec![skey]);
+//! ```
+//!
+//! [Sessions](../../session/index.html) do key exchanges themselves, so they need a raw identity key.
+
+use crate::alloc_prelude::*;
+
+use alloc::sync::Arc;
+
+/// Identifies a key on a device, e.g. a PKCS#11 object handle, a TPM persistent handle, or a YubiKey PIV slot.
+///
+/// What the number means is up to the [`Device`](trait.Device.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyHandle(pub u64);
+
+/// Something that holds secret keys and uses them on mesher's behalf.
+///
+/// Both methods return `None` if the device can't do what it's asked, e.g. because it's been unplugged or the handle
+/// doesn't refer to a key of the right type.
+pub trait Device: Send + Sync {
+  /// Opens data sealed for the X25519 key `handle` refers to, the same way libsodium's
+  /// [sealed boxes](https://libsodium.gitbook.io/doc/public-key_cryptography/sealed_boxes) are opened.
+  ///
+  /// Most devices can only do the key exchange itself, in which case the rest of the opening is done in software.
+  /// Opening fails for most chunks, since they're meant for other nodes, so this should be cheap when it does.
+  fn open(&self, handle: KeyHandle, sealed: &[u8]) -> Option<Vec<u8>>;
+
+  /// Makes an Ed25519 signature of `data` with the key `handle` refers to.
+  fn sign(&self, handle: KeyHandle, data: &[u8]) -> Option<[u8; 64]>;
+}
+
+/// Where a held key lives, and its public half, since the device won't hand over the secret half to work it out.
+#[derive(Clone)]
+pub(crate) struct Held {
+  pub(crate) device: Arc<dyn Device>,
+  pub(crate) handle: KeyHandle,
+  pub(crate) public: [u8; 32],
+}
+
+// only the built-in backend knows to hand held keys to their device
+#[cfg_attr(not(feature = "crypto-sodium"), allow(dead_code))]
+impl Held {
+  /// Opens a sealed box on the device.
+  pub(crate) fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+    self.device.open(self.handle, sealed)
+  }
+
+  /// Signs on the device, in libsodium's combined mode.
+  ///
+  /// If the device fails, the signature is all zeroes, which nobody will accept, so the chunk is treated like it was
+  /// tampered with.
+  pub(crate) fn sign(&self, data: &[u8]) -> Vec<u8> {
+    let signature = self.device.sign(self.handle, data).unwrap_or([0; 64]);
+    [&signature[..], data].concat()
+  }
+
+  /// Whether two held keys are the same key, on the same device.
+  pub(crate) fn same(&self, other: &Held) -> bool {
+    Arc::ptr_eq(&self.device, &other.device) && self.handle == other.handle && self.public == other.public
+  }
+}
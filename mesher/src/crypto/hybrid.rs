@@ -0,0 +1,234 @@
+//! Keys for the hybrid X25519 + ML-KEM-768 suite, for chunks that have to stay secret even if one of the two is broken,
+//! e.g. X25519 by a future quantum computer.
+//!
+//! A hybrid chunk is suite byte `1`, then an ordinary X25519 sealed box, made with the packet's backend, holding:
+//!
+//! - the ML-KEM-768 ciphertext, 1088 bytes, encapsulated to the recipient's ML-KEM key, then
+//! - the instruction, encrypted with ChaCha20-Poly1305 (all-zero nonce, no associated data), keyed with the ML-KEM
+//!   shared secret.
+//!
+//! Reading one takes both halves of the recipient's [`SecretKey`](struct.SecretKey.html), so an attacker has to break
+//! both X25519 and ML-KEM.
+//! Each chunk gets a fresh shared secret, which is why the nonce can be fixed.
+//!
+//! ML-KEM and the AEAD come from [`aws-lc-rs`](https://crates.io/crates/aws-lc-rs), not the
+//! [`Crypto`](../trait.Crypto.html) backend, so this is behind the `pq-hybrid` feature.
+//! Nodes without it skip hybrid chunks like any others they can't read.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::crypto::hybrid;
+//!
+//! let (pkey, skey) = hybrid::gen_keypair();
+//! let mut mesher = Mesher::unsigned(vec![]);
+//! mesher.add_hybrid_key(skey);
+//!
+//! let mut packet = Packet::unsigned();
+//! packet.add_hybrid_message(b"hello", &pkey);
+//! ```
+
+use super::{encrypt, Crypto};
+use crate::alloc_prelude::*;
+
+use aws_lc_rs::{
+  aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+  kem::{Ciphertext, DecapsulationKey, EncapsulationKey, ML_KEM_768},
+};
+
+/// How long an ML-KEM-768 encapsulation key is.
+const KEM_PUBLIC_BYTES: usize = 1184;
+
+/// How long an ML-KEM-768 decapsulation key is.
+const KEM_SECRET_BYTES: usize = 2400;
+
+/// How long an ML-KEM-768 ciphertext is.
+const KEM_CIPHERTEXT_BYTES: usize = 1088;
+
+/// The public half of a hybrid keypair: an X25519 key and an ML-KEM-768 encapsulation key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+  x25519: encrypt::PublicKey,
+  kem: Vec<u8>,
+}
+
+impl PublicKey {
+  /// Creates a key from the X25519 key's 32 bytes followed by the ML-KEM key's 1184, if that's what they are.
+  pub fn from_slice(bytes: &[u8]) -> Option<PublicKey> {
+    if bytes.len() != 32 + KEM_PUBLIC_BYTES {
+      return None;
+    }
+    let (x25519, kem) = bytes.split_at(32);
+    EncapsulationKey::new(&ML_KEM_768, kem).ok()?;
+    Some(PublicKey {
+      x25519: encrypt::PublicKey::from_slice(x25519)?,
+      kem: kem.to_vec(),
+    })
+  }
+
+  /// The bytes [`from_slice`](#method.from_slice) reads.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = self.x25519.as_bytes().to_vec();
+    bytes.extend_from_slice(&self.kem);
+    bytes
+  }
+
+  /// The X25519 half of the key.
+  pub fn x25519(&self) -> &encrypt::PublicKey {
+    &self.x25519
+  }
+}
+
+/// The secret half of a hybrid keypair: an X25519 key and an ML-KEM-768 decapsulation key.
+///
+/// Like the other secret keys, it isn't `Clone` and it's left out of `Debug` output; aws-lc wipes the ML-KEM key when
+/// it's dropped.
+pub struct SecretKey {
+  x25519: encrypt::SecretKey,
+  kem: DecapsulationKey,
+  public: PublicKey,
+}
+
+impl core::fmt::Debug for SecretKey {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    f.write_str("hybrid::SecretKey(<redacted>)")
+  }
+}
+
+impl SecretKey {
+  /// Creates a key from the bytes written by [`to_bytes`](#method.to_bytes), if they're well-formed.
+  pub fn from_slice(bytes: &[u8]) -> Option<SecretKey> {
+    if bytes.len() != 32 + KEM_SECRET_BYTES + 32 + KEM_PUBLIC_BYTES {
+      return None;
+    }
+    let (x25519, rest) = bytes.split_at(32);
+    let (kem, public) = rest.split_at(KEM_SECRET_BYTES);
+    Some(SecretKey {
+      x25519: encrypt::SecretKey::from_slice(x25519)?,
+      kem: DecapsulationKey::new(&ML_KEM_768, kem).ok()?,
+      public: PublicKey::from_slice(public)?,
+    })
+  }
+
+  /// The X25519 secret key, then the ML-KEM decapsulation key, then the [public key](#method.public_key), since
+  /// aws-lc can't work it out again from the decapsulation key.
+  ///
+  /// Wipe the bytes once they've been stored somewhere safe.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = self.x25519.as_bytes().to_vec();
+    bytes.extend_from_slice(
+      self
+        .kem
+        .key_bytes()
+        .expect("an ML-KEM key can always be serialized")
+        .as_ref(),
+    );
+    bytes.extend(self.public.to_bytes());
+    bytes
+  }
+
+  /// The matching public key.
+  pub fn public_key(&self) -> &PublicKey {
+    &self.public
+  }
+}
+
+/// Generates a new, random keypair, with the X25519 half from the default backend.
+pub fn gen_keypair() -> (PublicKey, SecretKey) {
+  gen_keypair_using(super::default_backend().as_ref())
+}
+
+/// Generates a new, random keypair, with the X25519 half from `crypto`.
+pub fn gen_keypair_using(crypto: &dyn Crypto) -> (PublicKey, SecretKey) {
+  let (x25519_pkey, x25519) = crypto.gen_encrypt_keypair();
+  let kem = DecapsulationKey::generate(&ML_KEM_768).expect("ML-KEM key generation failed");
+  let public = PublicKey {
+    x25519: x25519_pkey,
+    kem: kem
+      .encapsulation_key()
+      .and_then(|k| k.key_bytes().map(|b| b.as_ref().to_vec()))
+      .expect("an ML-KEM key always has an encapsulation key"),
+  };
+  (public.clone(), SecretKey { x25519, kem, public })
+}
+
+/// The AEAD key for a chunk, from its ML-KEM shared secret.
+fn aead_key(secret: &[u8]) -> LessSafeKey {
+  LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, secret).expect("ML-KEM secrets are 32 bytes"))
+}
+
+/// Encrypts some data for the holder of the matching secret key, without the suite byte.
+pub(crate) fn seal(data: &[u8], pkey: &PublicKey, crypto: &dyn Crypto) -> Vec<u8> {
+  let kem = EncapsulationKey::new(&ML_KEM_768, &pkey.kem).expect("public keys are checked when they're made");
+  let (ciphertext, secret) = kem.encapsulate().expect("ML-KEM encapsulation failed");
+  let mut inner = ciphertext.as_ref().to_vec();
+  let mut sealed = data.to_vec();
+  aead_key(secret.as_ref())
+    .seal_in_place_append_tag(Nonce::assume_unique_for_key([0; NONCE_LEN]), Aad::empty(), &mut sealed)
+    .expect("ChaCha20-Poly1305 sealing failed");
+  inner.extend(sealed);
+  crypto.seal(&inner, &pkey.x25519)
+}
+
+/// Decrypts data encrypted by [`seal`](fn.seal.html), without the suite byte, or returns `None` if it wasn't sealed
+/// for this key.
+pub(crate) fn open(sealed: &[u8], skey: &SecretKey, crypto: &dyn Crypto) -> Option<Vec<u8>> {
+  let mut data = crypto.open(sealed, &skey.x25519)?;
+  if data.len() < KEM_CIPHERTEXT_BYTES {
+    return None;
+  }
+  let secret = skey
+    .kem
+    .decapsulate(Ciphertext::from(&data[..KEM_CIPHERTEXT_BYTES]))
+    .ok()?;
+  data.drain(..KEM_CIPHERTEXT_BYTES);
+  let len = aead_key(secret.as_ref())
+    .open_in_place(Nonce::assume_unique_for_key([0; NONCE_LEN]), Aad::empty(), &mut data)
+    .ok()?
+    .len();
+  data.truncate(len);
+  Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crypto::Sodium;
+
+  #[test]
+  fn round_trips() {
+    let (pkey, skey) = gen_keypair_using(&Sodium);
+    let sealed = seal(b"hello", &pkey, &Sodium);
+    assert_eq!(Some(b"hello".to_vec()), open(&sealed, &skey, &Sodium));
+
+    let (_, other) = gen_keypair_using(&Sodium);
+    assert_eq!(None, open(&sealed, &other, &Sodium));
+  }
+
+  #[test]
+  fn needs_both_halves() {
+    let (pkey, skey) = gen_keypair_using(&Sodium);
+    let (_, other) = gen_keypair_using(&Sodium);
+    // the right X25519 key, but the wrong ML-KEM one
+    let mixed = SecretKey {
+      x25519: skey.x25519.clone_secret(),
+      kem: other.kem,
+      public: other.public,
+    };
+    assert_eq!(None, open(&seal(b"hello", &pkey, &Sodium), &mixed, &Sodium));
+  }
+
+  #[test]
+  fn keys_survive_serialization() {
+    let (pkey, skey) = gen_keypair_using(&Sodium);
+    assert_eq!(Some(&pkey), PublicKey::from_slice(&pkey.to_bytes()).as_ref());
+    let restored = SecretKey::from_slice(&skey.to_bytes()).expect("failed to restore key");
+    assert_eq!(&pkey, restored.public_key());
+    assert_eq!(
+      Some(b"hi".to_vec()),
+      open(&seal(b"hi", &pkey, &Sodium), &restored, &Sodium)
+    );
+
+    assert!(PublicKey::from_slice(&pkey.to_bytes()[1..]).is_none());
+    assert!(SecretKey::from_slice(&[0; 10]).is_none());
+  }
+}
@@ -1,6 +1,6 @@
 use std::{collections::HashMap, sync::Mutex};
 
-use crate::prelude::*;
+use crate::{prelude::*, ListenStatus};
 
 lazy_static! {
   static ref PACKETS: Mutex<HashMap<String, Vec<Vec<u8>>>> = Mutex::new(HashMap::new());
@@ -12,6 +12,7 @@ lazy_static! {
 /// Except in extremely rare circumstances (e.g. out-of-memory, huge numbers of messages, threads dying unexpectedly), this mesher cannot fail.
 ///
 /// Always use unique paths, even across tests, as the storage used is global.
+/// Packets are stored by the path's location, so `inmem:foo` and `other:foo` refer to the same place.
 /// This is intentional, as it allows for tests which run multiple threads to simulate multiple meshers operating independently.
 ///
 /// You should never use this struct directly. Instead, use it through [`Mesher`](../struct.Mesher.html), like any other transport:
@@ -22,91 +23,151 @@ lazy_static! {
 /// some_mesher.add_transport::<mesher::debug_transports::InMemory>("inmem")
 ///   .expect("Failed to add InMemory transport");
 /// ```
-#[allow(dead_code)]
 pub struct InMemory {
-  listening: Vec<String>,
+  scheme: String,
+  listening: Vec<Path>,
 }
 
 impl Transport for InMemory {
-  fn new(_scheme: &str) -> fail::Result<Self> {
-    Ok(InMemory { listening: vec![] })
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(InMemory {
+      scheme: scheme.to_owned(),
+      listening: vec![],
+    })
   }
 
-  fn send(&mut self, path: String, blob: Vec<u8>) -> fail::Result<()> {
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "InMemory"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
     let mut packets = PACKETS.lock().expect("poisoned lock?");
-    match packets.get_mut(&path) {
-      Some(v) => v.push(blob),
+    match packets.get_mut(path.location()) {
+      Some(v) => v.push(blob.to_vec()),
       None => {
-        packets.insert(path, vec![blob]);
+        packets.insert(path.location().to_owned(), vec![blob.to_vec()]);
       }
     };
     Ok(())
   }
 
-  fn listen(&mut self, path: String) -> fail::Result<()> {
-    self.listening.push(path);
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    self.listening.push(path.clone());
     Ok(())
   }
 
-  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    let before = self.listening.len();
+    self.listening.retain(|p| p.location() != path.location());
+    if self.listening.len() == before {
+      return Err(fail::MesherFail::NotListening(path.to_string()));
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
     let mut packets = PACKETS.lock().expect("poisoned lock?");
     Ok(
       self
         .listening
         .iter()
         .flat_map(|path| {
-          packets
-            .insert(path.clone(), vec![])
-            .unwrap_or_else(|| vec![])
+          let arrived = packets.insert(path.location().to_owned(), vec![]).unwrap_or_default();
+          arrived
             .into_iter()
+            .map(move |p| (Source::listening_on(path.clone()), p))
         })
         .collect(),
     )
   }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self
+      .listening
+      .iter()
+      .map(|p| (p.clone(), ListenStatus::Listening))
+      .collect()
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  fn path(p: &str) -> Path {
+    Path::parse(p).expect("Failed to parse path")
+  }
+
+  fn received(t: &mut InMemory) -> Vec<Vec<u8>> {
+    t.receive()
+      .expect("Failed to receive")
+      .into_iter()
+      .map(|(_, p)| p)
+      .collect()
+  }
+
+  #[test]
+  fn identifies_itself() {
+    let t = InMemory::new("mock").expect("Failed to create");
+    assert_eq!("mock", t.scheme());
+    assert_eq!("InMemory", t.name());
+  }
+
   #[test]
   fn send_and_receive() {
     let mut t = InMemory::new("inmem").expect("Failed to create");
 
-    t.listen("inmem:1".to_owned()).expect("Failed to listen");
-    t.send("inmem:1".to_owned(), vec![1, 2, 3, 4]).expect("Failed to send");
-    let received = t.receive().expect("Failed to receive");
-    assert_eq!(received, vec![vec![1, 2, 3, 4]]);
+    t.listen(&path("inmem:1")).expect("Failed to listen");
+    t.send(&path("inmem:1"), &[1, 2, 3, 4]).expect("Failed to send");
+    assert_eq!(
+      t.receive().expect("Failed to receive"),
+      vec![(Source::listening_on(path("inmem:1")), vec![1, 2, 3, 4])]
+    );
   }
 
   #[test]
   fn send_2_and_receive() {
     let mut t = InMemory::new("inmem").expect("Failed to create");
 
-    t.listen("inmem:2".to_owned()).expect("Failed to listen");
-    t.send("inmem:2".to_owned(), vec![1, 2, 3, 4]).expect("Failed to send");
-    t.send("inmem:2".to_owned(), vec![5, 6, 7, 8]).expect("Failed to send");
-    let received = t.receive().expect("Failed to receive");
-    assert_eq!(received, vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]]);
+    t.listen(&path("inmem:2")).expect("Failed to listen");
+    t.send(&path("inmem:2"), &[1, 2, 3, 4]).expect("Failed to send");
+    t.send(&path("inmem:2"), &[5, 6, 7, 8]).expect("Failed to send");
+    assert_eq!(received(&mut t), vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]]);
   }
 
   #[test]
   fn send_and_receive_out_of_order() {
     let mut t = InMemory::new("inmem").expect("Failed to create");
 
-    t.send("inmem:3".to_owned(), vec![9, 10, 11, 12])
-      .expect("Failed to send");
-    t.listen("inmem:3".to_owned()).expect("Failed to listen");
-    let received = t.receive().expect("Failed to receive");
-    assert_eq!(received, vec![vec![9, 10, 11, 12]]);
+    t.send(&path("inmem:3"), &[9, 10, 11, 12]).expect("Failed to send");
+    t.listen(&path("inmem:3")).expect("Failed to listen");
+    assert_eq!(received(&mut t), vec![vec![9, 10, 11, 12]]);
+  }
+
+  #[test]
+  fn unlisten_stops_receiving() {
+    let mut t = InMemory::new("inmem").expect("Failed to create");
+
+    t.listen(&path("inmem:5")).expect("Failed to listen");
+    t.unlisten(&path("inmem:5")).expect("Failed to unlisten");
+    t.send(&path("inmem:5"), &[1, 2, 3, 4]).expect("Failed to send");
+    assert_eq!(received(&mut t), Vec::<Vec<u8>>::new());
+
+    match t.unlisten(&path("inmem:5")) {
+      Err(fail::MesherFail::NotListening(_)) => (),
+      _ => panic!("Unlistening twice should fail"),
+    }
   }
 
   #[test]
   fn receive_blank() {
     let mut t = InMemory::new("inmem").expect("Failed to create");
 
-    t.listen("inmem:4".to_owned()).expect("Failed to listen");
-    let received = t.receive().expect("Failed to receive");
-    assert_eq!(received, Vec::<Vec<u8>>::new());
+    t.listen(&path("inmem:4")).expect("Failed to listen");
+    assert_eq!(received(&mut t), Vec::<Vec<u8>>::new());
   }
 }
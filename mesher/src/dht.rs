@@ -0,0 +1,1033 @@
+//! Contains an optional Kademlia-style distributed hash table, where nodes publish the paths they can be reached on
+//! under their key's [fingerprint](../crypto/struct.Fingerprint.html), so others can find them without a central
+//! directory.
+//!
+//! Each mesher using it has a [`Dht`](struct.Dht.html), which keeps a routing table of other nodes in it, organized by
+//! how far their fingerprints are from its own, XORed.
+//! [Finding](struct.Dht.html#method.find) a key asks the closest nodes it knows about, which answer with the closest
+//! nodes they know about, and so on, getting closer each round until one of them has the record.
+//! [Publishing](struct.Dht.html#method.publish) does the same, then stores the record with the closest nodes found, which
+//! is where lookups will end up.
+//! It only takes one node that's already in the table to [bootstrap](struct.Dht.html#method.bootstrap) from.
+//!
+//! Requests and responses travel as [custom chunks](../trait.CustomChunk.html) of kinds `0xFF03` and `0xFF04`, which
+//! the `Dht` handles itself, so they never show up as messages.
+//! It can't send anything while it's handling them, though, so [`poll`](struct.Dht.html#method.poll) has to be called
+//! after every [`receive`](../struct.Mesher.html#method.receive), to send answers and move lookups along.
+//!
+//! Like [announcements](../discovery/index.html), nothing in it is proof of anything: any node can claim any key, and
+//! publish any paths for it.
+//! Nodes storing a record keep the paths it was first stored with until it expires, though, only adding new ones after
+//! them, and limit how often each key can store, so claiming someone else's key can't take their record over.
+//! Packets are still encrypted for the key they're for, so the worst a lying node can do is send them somewhere they
+//! can't be read, and the record's key is checked against the fingerprint that was looked up.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::dht::Dht;
+//!
+//! fn node(name: &str) -> (Mesher, Dht, encrypt::PublicKey, Path) {
+//!   let (pk, sk) = encrypt::gen_keypair();
+//!   let mut mesher = Mesher::unsigned(vec![sk]);
+//!   mesher.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//!   let path = Path::parse(&format!("inmem:{}", name)).unwrap();
+//!   mesher.listen_on(path.as_str()).expect("Failed to listen");
+//!   let dht = Dht::new(&mut mesher, path.clone()).expect("Failed to join DHT");
+//!   (mesher, dht, pk, path)
+//! }
+//! let (mut a, a_dht, a_pk, a_path) = node("dht-doc-a");
+//! let (mut b, b_dht, _, _) = node("dht-doc-b");
+//! let (mut c, c_dht, c_pk, c_path) = node("dht-doc-c");
+//! // b and c only know about a
+//! b_dht.add_contact(a_pk, a_path.clone());
+//! c_dht.add_contact(a_pk, a_path);
+//!
+//! // c asks a who's closest to it, then stores its record with whoever that turns out to be, i.e. a
+//! let published = c_dht.publish(&mut c, vec![c_path.clone()]).expect("Failed to publish");
+//! a.receive().expect("Failed to receive");
+//! a_dht.poll(&mut a).expect("Failed to poll");
+//! c.receive().expect("Failed to receive");
+//! c_dht.poll(&mut c).expect("Failed to poll");
+//! assert_eq!(a_pk, *c_dht.result(published).expect("Should be done")[0].pkey());
+//! a.receive().expect("Failed to receive");
+//!
+//! // b asks a for c's record, and a has it
+//! let found = b_dht.find(&mut b, c_pk.fingerprint()).expect("Failed to look up");
+//! a.receive().expect("Failed to receive");
+//! a_dht.poll(&mut a).expect("Failed to poll");
+//! b.receive().expect("Failed to receive");
+//! b_dht.poll(&mut b).expect("Failed to poll");
+//! let found = b_dht.result(found).expect("Should be done");
+//! assert_eq!((&c_pk, &[c_path][..]), (found[0].pkey(), found[0].paths()));
+//! ```
+
+use crate::{
+  discovery::{decode_announcement, encode_announcement, Peer},
+  packet::{take, take_u32},
+  prelude::*,
+  ratelimit::TokenBucket,
+  CustomChunk, Priority, RateLimit,
+};
+
+use lru::LruCache;
+use std::{
+  collections::HashMap,
+  num::NonZeroUsize,
+  sync::{Arc, Mutex, MutexGuard},
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+/// How many nodes each bucket of the routing table holds, and how many a record's stored with.
+const K: usize = 8;
+/// How many requests each lookup has waiting at once.
+const ALPHA: usize = 3;
+/// The most candidates a lookup keeps; past this, the furthest are dropped, since they'd never be asked anyway.
+const MAX_CANDIDATES: usize = 4 * K;
+/// The most records a node stores for others; past this, the one heard from longest ago makes room for a new one.
+const MAX_RECORDS: usize = 4096;
+/// The most paths a stored record keeps.
+const MAX_PATHS: usize = 8;
+/// How many stores each node can make at once, before it's limited to one a second.
+const STORE_BURST: u32 = 4;
+/// How long [`Dht::wait`](struct.Dht.html#method.wait) waits between receives.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Where a fingerprint is in the DHT's keyspace.
+fn id(pkey: &encrypt::PublicKey) -> u64 {
+  u64::from_be_bytes(pkey.fingerprint().0)
+}
+
+/// Identifies one lookup, so its result can be collected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LookupId(pub u64);
+
+/// What a request asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Query {
+  /// The closest nodes to a point in the keyspace.
+  FindNode(u64),
+  /// The records for a fingerprint, as well as the closest nodes to it.
+  FindValue(u64),
+  /// To store the requester's own record, with these paths.
+  Store(Vec<Path>),
+}
+
+/// A request, from a node that's in the DHT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Request {
+  id: u64,
+  /// The requester's key, and the path it's reached on in the DHT.
+  from: (encrypt::PublicKey, Path),
+  query: Query,
+}
+
+const FIND_NODE: u8 = 0;
+const FIND_VALUE: u8 = 1;
+const STORE: u8 = 2;
+
+fn put_path(b: &mut Vec<u8>, path: &Path) {
+  b.extend_from_slice(&(path.as_str().len() as u16).to_be_bytes());
+  b.extend_from_slice(path.as_str().as_bytes());
+}
+
+fn take_path(from: &mut &[u8]) -> Option<Path> {
+  let len = take(from, 2)?;
+  let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+  Path::parse(std::str::from_utf8(take(from, len)?).ok()?).ok()
+}
+
+fn take_u64(from: &mut &[u8]) -> Option<u64> {
+  let mut bytes = [0; 8];
+  bytes.copy_from_slice(take(from, 8)?);
+  Some(u64::from_be_bytes(bytes))
+}
+
+impl CustomChunk for Request {
+  const KIND: u16 = 0xFF03;
+
+  /// Encodes the request's ID as a big-endian `u64`, the requester's key, and its path prefixed by its big-endian `u16`
+  /// length, then what's asked for: a byte saying which, then either the point in the keyspace, as a big-endian `u64`,
+  /// or the record to store, in the same format as [announcements](../discovery/index.html).
+  fn encode(&self) -> Vec<u8> {
+    let mut b = self.id.to_be_bytes().to_vec();
+    b.extend_from_slice(self.from.0.as_bytes());
+    put_path(&mut b, &self.from.1);
+    match &self.query {
+      Query::FindNode(target) => {
+        b.push(FIND_NODE);
+        b.extend_from_slice(&target.to_be_bytes());
+      }
+      Query::FindValue(target) => {
+        b.push(FIND_VALUE);
+        b.extend_from_slice(&target.to_be_bytes());
+      }
+      Query::Store(paths) => {
+        b.push(STORE);
+        b.extend_from_slice(&encode_announcement(std::iter::once((&self.from.0, &paths[..]))));
+      }
+    }
+    b
+  }
+
+  fn decode(mut from: &[u8]) -> Option<Request> {
+    let id = take_u64(&mut from)?;
+    let pkey = encrypt::PublicKey::from_slice(take(&mut from, 32)?)?;
+    let path = take_path(&mut from)?;
+    let query = match take(&mut from, 1)?[0] {
+      FIND_NODE => Query::FindNode(take_u64(&mut from)?),
+      FIND_VALUE => Query::FindValue(take_u64(&mut from)?),
+      STORE => {
+        // nodes can only store records for themselves
+        let mut record = decode_announcement(from)?;
+        from = &[];
+        match record.pop() {
+          Some((key, paths)) if record.is_empty() && key == pkey => Query::Store(paths),
+          _ => return None,
+        }
+      }
+      _ => return None,
+    };
+    if !from.is_empty() {
+      return None;
+    }
+    Some(Request {
+      id,
+      from: (pkey, path),
+      query,
+    })
+  }
+}
+
+/// The answer to a `FindNode` or `FindValue` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Response {
+  /// The request's ID.
+  id: u64,
+  /// The responder's key and path.
+  from: (encrypt::PublicKey, Path),
+  /// The closest nodes the responder knows about, each with the one path it's reached on in the DHT.
+  nodes: Vec<(encrypt::PublicKey, Vec<Path>)>,
+  /// The records the responder has for the fingerprint, if it was asked for them.
+  records: Vec<(encrypt::PublicKey, Vec<Path>)>,
+}
+
+impl CustomChunk for Response {
+  const KIND: u16 = 0xFF04;
+
+  /// Encodes the request's ID, the responder's key and path like a [request](struct.Request.html)'s, then the nodes,
+  /// prefixed by their big-endian `u32` length, and the records, both in the same format as
+  /// [announcements](../discovery/index.html).
+  fn encode(&self) -> Vec<u8> {
+    let mut b = self.id.to_be_bytes().to_vec();
+    b.extend_from_slice(self.from.0.as_bytes());
+    put_path(&mut b, &self.from.1);
+    let nodes = encode_announcement(self.nodes.iter().map(|(k, p)| (k, &p[..])));
+    b.extend_from_slice(&(nodes.len() as u32).to_be_bytes());
+    b.extend_from_slice(&nodes);
+    b.extend_from_slice(&encode_announcement(self.records.iter().map(|(k, p)| (k, &p[..]))));
+    b
+  }
+
+  fn decode(mut from: &[u8]) -> Option<Response> {
+    let id = take_u64(&mut from)?;
+    let pkey = encrypt::PublicKey::from_slice(take(&mut from, 32)?)?;
+    let path = take_path(&mut from)?;
+    let len = take_u32(&mut from)?;
+    let nodes = decode_announcement(take(&mut from, len)?)?;
+    let records = decode_announcement(from)?;
+    Some(Response {
+      id,
+      from: (pkey, path),
+      nodes,
+      records,
+    })
+  }
+}
+
+/// A node in the routing table.
+#[derive(Debug, Clone)]
+struct Contact {
+  pkey: encrypt::PublicKey,
+  path: Path,
+  last_heard: Instant,
+}
+
+/// How far along asking a node in a lookup is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Asked {
+  Not,
+  Waiting(Instant),
+  Answered,
+  Failed,
+}
+
+/// A node a lookup could ask, or has.
+struct Candidate {
+  pkey: encrypt::PublicKey,
+  path: Path,
+  distance: u64,
+  asked: Asked,
+}
+
+/// What a lookup does once it's found the closest nodes.
+enum Goal {
+  Nodes,
+  Value,
+  Store(Vec<Path>),
+}
+
+/// A lookup that's still going, or finished and waiting to be collected.
+struct Lookup {
+  target: u64,
+  goal: Goal,
+  /// Closest first.
+  candidates: Vec<Candidate>,
+  found: HashMap<encrypt::PublicKey, Peer>,
+  done: bool,
+}
+
+impl Lookup {
+  /// Adds a node the lookup could ask, keeping only the closest [`MAX_CANDIDATES`](constant.MAX_CANDIDATES.html).
+  fn add_candidate(&mut self, own: &encrypt::PublicKey, pkey: encrypt::PublicKey, path: Path) {
+    if pkey == *own || self.candidates.iter().any(|c| c.pkey == pkey) {
+      return;
+    }
+    let distance = id(&pkey) ^ self.target;
+    let at = self.candidates.partition_point(|c| c.distance <= distance);
+    self.candidates.insert(
+      at,
+      Candidate {
+        pkey,
+        path,
+        distance,
+        asked: Asked::Not,
+      },
+    );
+    self.candidates.truncate(MAX_CANDIDATES);
+  }
+}
+
+/// Something to send at the next poll.
+enum Outgoing {
+  Request(LookupId, Request),
+  Response(Response),
+}
+
+/// Everything shared between a `Dht` and the chunk handlers it registers on its mesher.
+struct State {
+  own: (encrypt::PublicKey, Path),
+  expire_after: Duration,
+  /// Bucket `i` holds the nodes whose distance from this one has its highest set bit at `i`, least recently heard from
+  /// first.
+  buckets: Vec<Vec<Contact>>,
+  records: HashMap<encrypt::PublicKey, Peer>,
+  /// How many more stores each node can make, for the most recent [`MAX_RECORDS`](constant.MAX_RECORDS.html) to make
+  /// any.
+  stores: LruCache<encrypt::PublicKey, TokenBucket>,
+  lookups: HashMap<LookupId, Lookup>,
+  /// Requests sent and not answered yet, with the lookup they're for.
+  waiting: HashMap<u64, (LookupId, encrypt::PublicKey)>,
+  outgoing: Vec<(encrypt::PublicKey, Path, Outgoing)>,
+}
+
+impl State {
+  fn bucket(&self, pkey: &encrypt::PublicKey) -> Option<usize> {
+    let distance = id(pkey) ^ id(&self.own.0);
+    match distance {
+      0 => None,
+      _ => Some(63 - distance.leading_zeros() as usize),
+    }
+  }
+
+  /// Adds a node to the routing table, or notes that it's been heard from.
+  ///
+  /// If its bucket's full, it replaces the node that was heard from longest ago, if that was more than `stale` ago, and
+  /// is dropped otherwise, so nodes that have been around longer are preferred.
+  fn heard_from(&mut self, pkey: encrypt::PublicKey, path: Path, stale: Duration) {
+    let bucket = match self.bucket(&pkey) {
+      Some(b) if pkey != self.own.0 => &mut self.buckets[b],
+      _ => return,
+    };
+    bucket.retain(|c| c.pkey != pkey);
+    if bucket.len() >= K {
+      if bucket[0].last_heard.elapsed() < stale {
+        return;
+      }
+      bucket.remove(0);
+    }
+    bucket.push(Contact {
+      pkey,
+      path,
+      last_heard: Instant::now(),
+    });
+  }
+
+  fn forget(&mut self, pkey: &encrypt::PublicKey) {
+    if let Some(b) = self.bucket(pkey) {
+      self.buckets[b].retain(|c| c.pkey != *pkey);
+    }
+  }
+
+  /// The `K` nodes in the routing table closest to `target`, closest first, not counting `except`.
+  fn closest(&self, target: u64, except: &encrypt::PublicKey) -> Vec<(encrypt::PublicKey, Path)> {
+    let mut all: Vec<_> = self.buckets.iter().flatten().filter(|c| c.pkey != *except).collect();
+    all.sort_by_key(|c| id(&c.pkey) ^ target);
+    all.into_iter().take(K).map(|c| (c.pkey, c.path.clone())).collect()
+  }
+
+  fn records_for(&self, target: u64) -> Vec<(encrypt::PublicKey, Vec<Path>)> {
+    self
+      .records
+      .values()
+      .filter(|r| id(&r.pkey) == target)
+      .map(|r| (r.pkey, r.paths.clone()))
+      .collect()
+  }
+
+  /// Stores a node's record, for others to find, unless it's storing too often.
+  ///
+  /// A record that's already stored keeps the paths it has, in the same order, and new ones are only added after them,
+  /// so a node claiming someone else's key can't take their record over.
+  fn store(&mut self, pkey: encrypt::PublicKey, mut paths: Vec<Path>) {
+    let limit = RateLimit::new(1, STORE_BURST);
+    if pkey == self.own.0
+      || !self
+        .stores
+        .get_or_insert_mut(pkey, || TokenBucket::new(&limit))
+        .take(&limit)
+    {
+      return;
+    }
+    let now = Instant::now();
+    if let Some(record) = self.records.get_mut(&pkey) {
+      for path in paths {
+        if record.paths.len() >= MAX_PATHS {
+          break;
+        }
+        if !record.paths.contains(&path) {
+          record.paths.push(path);
+        }
+      }
+      record.last_heard = now;
+      return;
+    }
+    if self.records.len() >= MAX_RECORDS {
+      let own = self.own.0;
+      let oldest = self
+        .records
+        .values()
+        .filter(|r| r.pkey != own)
+        .min_by_key(|r| r.last_heard)
+        .map(|r| r.pkey);
+      if let Some(oldest) = oldest {
+        self.records.remove(&oldest);
+      }
+    }
+    paths.truncate(MAX_PATHS);
+    let record = Peer {
+      pkey,
+      paths,
+      last_heard: now,
+    };
+    self.records.insert(pkey, record);
+  }
+
+  fn handle_request(&mut self, request: Request) {
+    let (pkey, path) = request.from;
+    self.heard_from(pkey, path.clone(), self.expire_after);
+    let (target, records) = match request.query {
+      Query::FindNode(target) => (target, vec![]),
+      Query::FindValue(target) => (target, self.records_for(target)),
+      Query::Store(paths) => {
+        self.store(pkey, paths);
+        return;
+      }
+    };
+    let response = Response {
+      id: request.id,
+      from: self.own.clone(),
+      nodes: self
+        .closest(target, &pkey)
+        .into_iter()
+        .map(|(k, p)| (k, vec![p]))
+        .collect(),
+      records,
+    };
+    self.outgoing.push((pkey, path, Outgoing::Response(response)));
+  }
+
+  fn handle_response(&mut self, response: Response) {
+    let (pkey, path) = response.from;
+    // anything else is an answer to a request that's timed out, or was never sent
+    let lookup_id = match self.waiting.get(&response.id) {
+      Some((lookup, asked)) if *asked == pkey => *lookup,
+      _ => return,
+    };
+    self.waiting.remove(&response.id);
+    // nobody honest knows more than K closest nodes, so this one's trying to flood the lookup
+    if response.nodes.len() > K {
+      self.send_failed(lookup_id, response.id, &pkey);
+      return;
+    }
+    self.heard_from(pkey, path, self.expire_after);
+    let own = self.own.0;
+    let lookup = match self.lookups.get_mut(&lookup_id) {
+      Some(lookup) => lookup,
+      None => return,
+    };
+    if let Some(c) = lookup.candidates.iter_mut().find(|c| c.pkey == pkey) {
+      c.asked = Asked::Answered;
+    }
+    for (node, mut paths) in response.nodes {
+      if let Some(path) = paths.pop() {
+        lookup.add_candidate(&own, node, path);
+      }
+    }
+    if let Goal::Value = lookup.goal {
+      let now = Instant::now();
+      for (key, paths) in response.records {
+        if id(&key) == lookup.target && !paths.is_empty() {
+          lookup.found.insert(
+            key,
+            Peer {
+              pkey: key,
+              paths,
+              last_heard: now,
+            },
+          );
+        }
+      }
+    }
+  }
+
+  /// Moves a lookup along: times out requests that haven't been answered, and sends new ones, or finishes it if there's
+  /// nobody closer left to ask.
+  fn advance(&mut self, lookup_id: LookupId, timeout: Duration) {
+    let own = self.own.clone();
+    let lookup = match self.lookups.get_mut(&lookup_id) {
+      Some(lookup) if !lookup.done => lookup,
+      _ => return,
+    };
+    let now = Instant::now();
+    let mut timed_out = vec![];
+    for c in &mut lookup.candidates {
+      if let Asked::Waiting(deadline) = c.asked {
+        if deadline <= now {
+          c.asked = Asked::Failed;
+          timed_out.push(c.pkey);
+        }
+      }
+    }
+    let found_value = matches!(lookup.goal, Goal::Value) && !lookup.found.is_empty();
+    let mut waiting = 0;
+    let mut to_ask = vec![];
+    let mut closest_answered = vec![];
+    let mut finished = true;
+    for c in lookup
+      .candidates
+      .iter_mut()
+      .filter(|c| c.asked != Asked::Failed)
+      .take(K)
+    {
+      match c.asked {
+        Asked::Answered => closest_answered.push((c.pkey, c.path.clone())),
+        Asked::Waiting(_) => {
+          waiting += 1;
+          finished = false;
+        }
+        Asked::Not => {
+          finished = false;
+          if waiting + to_ask.len() < ALPHA {
+            c.asked = Asked::Waiting(now + timeout);
+            to_ask.push((c.pkey, c.path.clone()));
+          }
+        }
+        Asked::Failed => unreachable!("Was just filtered out"),
+      }
+    }
+    let query = match &lookup.goal {
+      Goal::Value => Query::FindValue(lookup.target),
+      _ => Query::FindNode(lookup.target),
+    };
+    if found_value || finished {
+      lookup.done = true;
+      to_ask.clear();
+      if let Goal::Store(paths) = &lookup.goal {
+        let now = Instant::now();
+        for (pkey, path) in &closest_answered {
+          let request = Request {
+            id: rand::random(),
+            from: own.clone(),
+            query: Query::Store(paths.clone()),
+          };
+          self
+            .outgoing
+            .push((*pkey, path.clone(), Outgoing::Request(lookup_id, request)));
+          let stored_with = Peer {
+            pkey: *pkey,
+            paths: vec![path.clone()],
+            last_heard: now,
+          };
+          lookup.found.insert(*pkey, stored_with);
+        }
+      }
+    }
+    for (pkey, path) in to_ask {
+      let request = Request {
+        id: rand::random(),
+        from: own.clone(),
+        query: query.clone(),
+      };
+      self.waiting.insert(request.id, (lookup_id, pkey));
+      self.outgoing.push((pkey, path, Outgoing::Request(lookup_id, request)));
+    }
+    for pkey in timed_out {
+      self.forget(&pkey);
+    }
+  }
+
+  /// Marks a node that couldn't be sent to as failed in the lookup that was asking it.
+  fn send_failed(&mut self, lookup_id: LookupId, request_id: u64, pkey: &encrypt::PublicKey) {
+    self.waiting.remove(&request_id);
+    if let Some(c) = self
+      .lookups
+      .get_mut(&lookup_id)
+      .and_then(|l| l.candidates.iter_mut().find(|c| c.pkey == *pkey))
+    {
+      c.asked = Asked::Failed;
+    }
+    self.forget(pkey);
+  }
+}
+
+/// One mesher's place in the DHT: its routing table, the records it's storing for others, and its lookups.
+///
+/// Creating one registers handlers on the mesher for the chunks it uses, so there should only be one per mesher.
+/// Records are forgotten an hour after they were last stored, by default, so nodes should [publish](#method.publish)
+/// more often than that; the same goes for nodes in the routing table, which are only replaced by new ones once they've gone
+/// that long without being heard from.
+/// Requests that aren't answered in 5 seconds, by default, are given up on, and the node that didn't answer is dropped
+/// from the routing table.
+pub struct Dht {
+  request_timeout: Duration,
+  state: Arc<Mutex<State>>,
+}
+
+impl Dht {
+  /// Joins the DHT through `mesher`, which other nodes reach on `path`.
+  ///
+  /// The path should be one the mesher's listening on, and that other nodes can reach.
+  /// The mesher's [newest key](../struct.Mesher.html#method.newest_pkey) is the one it's known by in the DHT, so this
+  /// fails with [`NoKeys`](../fail/enum.MesherFail.html#variant.NoKeys) if it doesn't have one.
+  pub fn new(mesher: &mut Mesher, path: Path) -> fail::Result<Dht> {
+    let own = mesher.newest_pkey().ok_or(fail::MesherFail::NoKeys)?;
+    let state = Arc::new(Mutex::new(State {
+      own: (own, path),
+      expire_after: Duration::from_secs(3600),
+      buckets: vec![vec![]; 64],
+      records: HashMap::new(),
+      stores: LruCache::new(NonZeroUsize::new(MAX_RECORDS).expect("MAX_RECORDS isn't 0")),
+      lookups: HashMap::new(),
+      waiting: HashMap::new(),
+      outgoing: vec![],
+    }));
+    let request_state = state.clone();
+    mesher.on_chunk(move |request: Request| {
+      request_state.lock().expect("poisoned lock?").handle_request(request);
+    });
+    let response_state = state.clone();
+    mesher.on_chunk(move |response: Response| {
+      response_state.lock().expect("poisoned lock?").handle_response(response);
+    });
+    Ok(Dht {
+      request_timeout: Duration::from_secs(5),
+      state,
+    })
+  }
+
+  /// Forgets stored records after `after`, and lets nodes in the routing table be replaced once they haven't been heard
+  /// from in that long.
+  pub fn expire_after(self, after: Duration) -> Dht {
+    self.state.lock().expect("poisoned lock?").expire_after = after;
+    self
+  }
+
+  /// Gives up on requests that haven't been answered after `timeout`.
+  pub fn request_timeout(mut self, timeout: Duration) -> Dht {
+    self.request_timeout = timeout;
+    self
+  }
+
+  fn state(&self) -> MutexGuard<'_, State> {
+    let mut state = self.state.lock().expect("poisoned lock?");
+    let expire_after = state.expire_after;
+    state.records.retain(|_, r| r.last_heard.elapsed() < expire_after);
+    state
+  }
+
+  /// Adds a node to the routing table by hand, e.g. to bootstrap from.
+  pub fn add_contact(&self, pkey: encrypt::PublicKey, path: Path) {
+    // even a full bucket makes room for a node added by hand
+    self.state().heard_from(pkey, path, Duration::ZERO);
+  }
+
+  /// Every node in the routing table, with the path it's reached on.
+  pub fn contacts(&self) -> Vec<(encrypt::PublicKey, Path)> {
+    self
+      .state()
+      .buckets
+      .iter()
+      .flatten()
+      .map(|c| (c.pkey, c.path.clone()))
+      .collect()
+  }
+
+  /// The records this node's storing, for itself and others.
+  pub fn records(&self) -> Vec<Peer> {
+    self.state().records.values().cloned().collect()
+  }
+
+  fn start(&self, mesher: &mut Mesher, target: u64, goal: Goal) -> fail::Result<LookupId> {
+    let lookup_id = LookupId(rand::random());
+    {
+      let mut state = self.state();
+      let own = state.own.0;
+      let mut lookup = Lookup {
+        target,
+        goal,
+        candidates: vec![],
+        found: HashMap::new(),
+        done: false,
+      };
+      for (pkey, path) in state.closest(target, &own) {
+        lookup.add_candidate(&own, pkey, path);
+      }
+      if let Goal::Value = lookup.goal {
+        for (pkey, paths) in state.records_for(target) {
+          let record = Peer {
+            pkey,
+            paths,
+            last_heard: Instant::now(),
+          };
+          lookup.found.insert(pkey, record);
+        }
+      }
+      state.lookups.insert(lookup_id, lookup);
+    }
+    self.poll(mesher)?;
+    Ok(lookup_id)
+  }
+
+  /// Starts filling the routing table, by looking up the nodes closest to this one, which tells them about it too.
+  ///
+  /// At least one node should be [added](#method.add_contact) first.
+  /// The lookup's [result](#method.result) is the closest nodes it found.
+  pub fn bootstrap(&self, mesher: &mut Mesher) -> fail::Result<LookupId> {
+    let own = id(&self.state().own.0);
+    self.start(mesher, own, Goal::Nodes)
+  }
+
+  /// Starts looking up the paths of the node whose key has the given fingerprint.
+  ///
+  /// The lookup's [result](#method.result) is every record found for it, which is empty if there weren't any; more
+  /// than one means several keys have the fingerprint, and only one's likely to be the one that was wanted.
+  pub fn find(&self, mesher: &mut Mesher, fingerprint: Fingerprint) -> fail::Result<LookupId> {
+    let target = u64::from_be_bytes(fingerprint.0);
+    self.start(mesher, target, Goal::Value)
+  }
+
+  /// Starts publishing this node's record, saying it can be reached on `paths`.
+  ///
+  /// The record's stored here, and with the closest nodes to this one that the lookup finds, which are its
+  /// [result](#method.result).
+  pub fn publish(&self, mesher: &mut Mesher, paths: Vec<Path>) -> fail::Result<LookupId> {
+    let own = {
+      let mut state = self.state();
+      let own = state.own.0;
+      let record = Peer {
+        pkey: own,
+        paths: paths.clone(),
+        last_heard: Instant::now(),
+      };
+      state.records.insert(own, record);
+      id(&own)
+    };
+    self.start(mesher, own, Goal::Store(paths))
+  }
+
+  /// Sends everything waiting to be sent: answers to requests, and the next requests of every lookup that's still
+  /// going.
+  ///
+  /// This should be called after every [`receive`](../struct.Mesher.html#method.receive), since that's what collects
+  /// the requests and responses.
+  /// Nodes that can't be sent to are given up on, and the first failure is returned once the rest have been sent.
+  pub fn poll(&self, mesher: &mut Mesher) -> fail::Result<()> {
+    let own_pkey = mesher.newest_pkey().ok_or(fail::MesherFail::NoKeys)?;
+    let mut result = Ok(());
+    // sending can fail, which lets lookups move on again, so this goes until there's nothing left to do
+    loop {
+      let outgoing = {
+        let mut state = self.state();
+        let ids: Vec<_> = state.lookups.keys().copied().collect();
+        for lookup_id in ids {
+          state.advance(lookup_id, self.request_timeout);
+        }
+        std::mem::take(&mut state.outgoing)
+      };
+      if outgoing.is_empty() {
+        return result;
+      }
+      for (pkey, path, message) in outgoing {
+        let mut packet = mesher.new_packet(None);
+        packet.add_hop(path.to_string(), &own_pkey);
+        let failed_request = match &message {
+          Outgoing::Request(lookup_id, request) => {
+            packet.add_custom(request, &pkey);
+            Some((*lookup_id, request.id))
+          }
+          Outgoing::Response(response) => {
+            packet.add_custom(response, &pkey);
+            None
+          }
+        };
+        if let Err(e) = mesher.launch_with_priority(packet, Priority::Control) {
+          if let Some((lookup_id, request_id)) = failed_request {
+            self.state().send_failed(lookup_id, request_id, &pkey);
+          }
+          result = result.and(Err(e));
+        }
+      }
+    }
+  }
+
+  /// Takes the result of a lookup once it's done, or returns `None` if it's still going.
+  ///
+  /// Each result can only be taken once; after that, the lookup's forgotten, and this returns `None` for it too.
+  pub fn result(&self, lookup: LookupId) -> Option<Vec<Peer>> {
+    let mut state = self.state();
+    if !state.lookups.get(&lookup)?.done {
+      return None;
+    }
+    let lookup = state.lookups.remove(&lookup).expect("Was just there");
+    let target = lookup.target;
+    let mut found: Vec<_> = lookup.found.into_values().collect();
+    found.sort_by_key(|p| id(&p.pkey) ^ target);
+    Some(found)
+  }
+
+  /// Keeps [receiving](../struct.Mesher.html#method.receive) and [polling](#method.poll) until the lookup's done, or
+  /// `timeout` has passed, then takes its [result](#method.result).
+  ///
+  /// Returns the messages received in the meantime too, so they aren't lost.
+  /// Fails with [`TimedOut`](../fail/enum.MesherFail.html#variant.TimedOut) if the lookup isn't done in time.
+  pub fn wait(
+    &self,
+    mesher: &mut Mesher,
+    lookup: LookupId,
+    timeout: Duration,
+  ) -> fail::Result<(Vec<Peer>, Vec<Message>)> {
+    let deadline = Instant::now() + timeout;
+    let mut received = vec![];
+    loop {
+      received.extend(mesher.receive()?);
+      if let Err(e) = self.poll(mesher) {
+        mesher.report_failure(e);
+      }
+      if let Some(found) = self.result(lookup) {
+        return Ok((found, received));
+      }
+      if Instant::now() >= deadline {
+        return Err(fail::MesherFail::TimedOut(format!("DHT lookup {:x}", lookup.0)));
+      }
+      sleep(POLL_INTERVAL);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn chunks_roundtrip() {
+    let (pk1, _) = encrypt::gen_keypair();
+    let (pk2, _) = encrypt::gen_keypair();
+    let path = Path::parse("inmem:one").unwrap();
+    for query in [
+      Query::FindNode(7),
+      Query::FindValue(u64::MAX),
+      Query::Store(vec![path.clone(), Path::parse("tcp:[::1]:1").unwrap()]),
+    ] {
+      let request = Request {
+        id: 42,
+        from: (pk1, path.clone()),
+        query,
+      };
+      assert_eq!(Some(request.clone()), Request::decode(&request.encode()));
+    }
+    let response = Response {
+      id: 42,
+      from: (pk1, path.clone()),
+      nodes: vec![(pk2, vec![path.clone()])],
+      records: vec![(pk1, vec![path.clone()]), (pk2, vec![])],
+    };
+    let bytes = response.encode();
+    assert_eq!(Some(response), Response::decode(&bytes));
+    assert_eq!(None, Response::decode(&bytes[..bytes.len() - 1]));
+  }
+
+  #[test]
+  fn only_own_records_stored() {
+    let (pk1, _) = encrypt::gen_keypair();
+    let (pk2, _) = encrypt::gen_keypair();
+    let path = Path::parse("inmem:one").unwrap();
+    let mut bytes = Request {
+      id: 1,
+      from: (pk1, path.clone()),
+      query: Query::FindNode(0),
+    }
+    .encode();
+    bytes.truncate(8 + 32 + 2 + path.as_str().len());
+    bytes.push(STORE);
+    bytes.extend_from_slice(&encode_announcement(std::iter::once((&pk2, &[path][..]))));
+    assert_eq!(None, Request::decode(&bytes));
+  }
+
+  fn state() -> State {
+    let (own, _) = encrypt::gen_keypair();
+    State {
+      own: (own, Path::parse("inmem:own").unwrap()),
+      expire_after: Duration::from_secs(60),
+      buckets: vec![vec![]; 64],
+      records: HashMap::new(),
+      stores: LruCache::new(NonZeroUsize::new(MAX_RECORDS).unwrap()),
+      lookups: HashMap::new(),
+      waiting: HashMap::new(),
+      outgoing: vec![],
+    }
+  }
+
+  #[test]
+  fn full_buckets_keep_old_nodes() {
+    let mut state = state();
+    // about half of all keys land in the furthest bucket
+    let mut far = vec![];
+    while far.len() < K + 1 {
+      let (pk, _) = encrypt::gen_keypair();
+      if state.bucket(&pk) == Some(63) {
+        far.push(pk);
+      }
+    }
+    for pk in &far {
+      state.heard_from(*pk, Path::parse("inmem:x").unwrap(), Duration::from_secs(60));
+    }
+    assert_eq!(K, state.buckets[63].len());
+    assert!(!state.buckets[63].iter().any(|c| c.pkey == far[K]));
+    // unless they've gone quiet
+    state.heard_from(far[K], Path::parse("inmem:x").unwrap(), Duration::ZERO);
+    assert!(state.buckets[63].iter().any(|c| c.pkey == far[K]));
+    assert!(!state.buckets[63].iter().any(|c| c.pkey == far[0]));
+  }
+
+  #[test]
+  fn stores_keep_existing_paths() {
+    let mut state = state();
+    let (pk, _) = encrypt::gen_keypair();
+    let path = |name: &str| Path::parse(&format!("inmem:{}", name)).unwrap();
+    state.store(pk, vec![path("real")]);
+    state.store(pk, vec![path("fake"), path("real")]);
+    assert_eq!(&[path("real"), path("fake")][..], state.records[&pk].paths());
+
+    // nobody gets to add to this node's own record
+    let own = state.own.0;
+    state.records.insert(
+      own,
+      Peer {
+        pkey: own,
+        paths: vec![path("own")],
+        last_heard: Instant::now(),
+      },
+    );
+    state.store(own, vec![path("fake")]);
+    assert_eq!(&[path("own")][..], state.records[&own].paths());
+  }
+
+  #[test]
+  fn stores_are_rate_limited() {
+    let mut state = state();
+    let (pk, _) = encrypt::gen_keypair();
+    for i in 0..STORE_BURST + 1 {
+      state.store(pk, vec![Path::parse(&format!("inmem:{}", i)).unwrap()]);
+    }
+    assert_eq!(STORE_BURST as usize, state.records[&pk].paths().len());
+    // but only per node
+    let (other, _) = encrypt::gen_keypair();
+    state.store(other, vec![Path::parse("inmem:other").unwrap()]);
+    assert!(state.records.contains_key(&other));
+  }
+
+  #[test]
+  fn lookups_keep_the_closest_candidates() {
+    let mut state = state();
+    let own = state.own.0;
+    let (responder, _) = encrypt::gen_keypair();
+    let path = Path::parse("inmem:x").unwrap();
+    let mut lookup = Lookup {
+      target: 0,
+      goal: Goal::Nodes,
+      candidates: vec![],
+      found: HashMap::new(),
+      done: false,
+    };
+    lookup.add_candidate(&own, responder, path.clone());
+    state.lookups.insert(LookupId(1), lookup);
+    let nodes = |n: usize| -> Vec<_> { (0..n).map(|_| (encrypt::gen_keypair().0, vec![path.clone()])).collect() };
+    let response = |id: u64, nodes| Response {
+      id,
+      from: (responder, path.clone()),
+      nodes,
+      records: vec![],
+    };
+
+    // too many nodes, so the whole response is ignored, and the responder's given up on
+    state.waiting.insert(1, (LookupId(1), responder));
+    state.handle_response(response(1, nodes(K + 1)));
+    let lookup = &state.lookups[&LookupId(1)];
+    assert_eq!(1, lookup.candidates.len());
+    assert_eq!(Asked::Failed, lookup.candidates[0].asked);
+
+    // however many answer, only the closest few are kept
+    let mut all = vec![responder];
+    for id in 2..(2 + 2 * MAX_CANDIDATES as u64 / K as u64) {
+      let nodes = nodes(K);
+      all.extend(nodes.iter().map(|(pk, _)| *pk));
+      state.waiting.insert(id, (LookupId(1), responder));
+      state.handle_response(response(id, nodes));
+    }
+    all.sort_by_key(id);
+    all.truncate(MAX_CANDIDATES);
+    let kept: Vec<_> = state.lookups[&LookupId(1)].candidates.iter().map(|c| c.pkey).collect();
+    assert_eq!(all, kept);
+  }
+
+  #[test]
+  fn full_records_evict_the_oldest() {
+    let mut state = state();
+    let keys: Vec<_> = (0..=MAX_RECORDS as u32)
+      .map(|i| {
+        let mut bytes = [0; 32];
+        bytes[..4].copy_from_slice(&i.to_be_bytes());
+        encrypt::PublicKey::from_slice(&bytes).unwrap()
+      })
+      .collect();
+    for pk in &keys[..MAX_RECORDS] {
+      state.store(*pk, vec![Path::parse("inmem:x").unwrap()]);
+    }
+    state.records.get_mut(&keys[1]).unwrap().last_heard -= Duration::from_secs(10);
+    state.store(keys[MAX_RECORDS], vec![Path::parse("inmem:x").unwrap()]);
+    assert_eq!(MAX_RECORDS, state.records.len());
+    assert!(state.records.contains_key(&keys[MAX_RECORDS]));
+    assert!(!state.records.contains_key(&keys[1]));
+  }
+}
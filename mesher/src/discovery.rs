@@ -0,0 +1,143 @@
+//! Contains the optional peer discovery layer, where meshers tell each other how to reach them.
+
+use crate::{packet::take, prelude::*};
+
+use std::time::{Duration, Instant};
+
+/// The most peers passed along in a single announcement, besides the announcer itself.
+pub(crate) const GOSSIP_PEERS: usize = 16;
+
+/// The most peers a mesher will keep track of; announcements about new peers past this are ignored.
+pub(crate) const MAX_PEERS: usize = 1024;
+
+/// A node a mesher knows about, and the paths it can be reached on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+  pub(crate) pkey: encrypt::PublicKey,
+  pub(crate) paths: Vec<Path>,
+  pub(crate) last_heard: Instant,
+}
+
+impl Peer {
+  /// The peer's public key, which packets for it should be encrypted with.
+  pub fn pkey(&self) -> &encrypt::PublicKey {
+    &self.pkey
+  }
+
+  /// The paths the peer can be reached on, in the order it prefers.
+  pub fn paths(&self) -> &[Path] {
+    &self.paths
+  }
+
+  /// When the mesher last heard about this peer, whether from the peer itself or through gossip.
+  pub fn last_heard(&self) -> Instant {
+    self.last_heard
+  }
+}
+
+/// How a mesher should announce itself to its peers, passed to [`Mesher::set_discovery`](../struct.Mesher.html#method.set_discovery).
+///
+/// Announcements carry the mesher's newest public key, the paths it's advertising, and a handful of the peers it knows
+/// about, so peers learn about each other as well.
+/// They're encrypted for each peer individually and sent along the first of its paths that the mesher has a transport for.
+///
+/// Note that announcements aren't proof of anything: any node can announce any key, and gossip about other peers is
+/// passed along as-is.
+/// If that matters, sign them with [`signed_with`](#method.signed_with) and only accept signed packets.
+pub struct Discovery {
+  pub(crate) paths: Vec<Path>,
+  pub(crate) interval: Duration,
+  pub(crate) signing_key: Option<sign::SecretKey>,
+}
+
+impl Clone for Discovery {
+  fn clone(&self) -> Discovery {
+    Discovery {
+      paths: self.paths.clone(),
+      interval: self.interval,
+      signing_key: self.signing_key.as_ref().map(sign::SecretKey::clone_secret),
+    }
+  }
+}
+
+impl Discovery {
+  /// Announces the given paths, every `interval`.
+  ///
+  /// The paths should be ones other nodes can actually reach, which aren't always the ones being listened on,
+  /// e.g. `tcp:0.0.0.0:18540` might be advertised as `tcp:203.0.113.7:18540`.
+  pub fn new(paths: Vec<Path>, interval: Duration) -> Discovery {
+    Discovery {
+      paths,
+      interval,
+      signing_key: None,
+    }
+  }
+
+  /// Signs announcements with the given key, so signed meshers will accept them.
+  ///
+  /// If this isn't called, they're signed with the mesher's [own signing key](../struct.Mesher.html#method.set_signing_key), if it has one.
+  pub fn signed_with(mut self, skey: sign::SecretKey) -> Discovery {
+    self.signing_key = Some(skey);
+    self
+  }
+}
+
+/// Encodes an announcement, as a count of entries, then each entry's key followed by its paths.
+/// Counts are single bytes and path lengths are big-endian `u16`s.
+pub(crate) fn encode_announcement<'a>(entries: impl Iterator<Item = (&'a encrypt::PublicKey, &'a [Path])>) -> Vec<u8> {
+  let entries: Vec<_> = entries.take(u8::MAX as usize).collect();
+  let mut b = vec![entries.len() as u8];
+  for (pkey, paths) in entries {
+    b.extend_from_slice(pkey.as_bytes());
+    let paths: Vec<_> = paths
+      .iter()
+      .map(Path::as_str)
+      .filter(|p| p.len() <= u16::MAX as usize)
+      .take(u8::MAX as usize)
+      .collect();
+    b.push(paths.len() as u8);
+    for path in paths {
+      b.extend_from_slice(&(path.len() as u16).to_be_bytes());
+      b.extend_from_slice(path.as_bytes());
+    }
+  }
+  b
+}
+
+/// Decodes an announcement written by [`encode_announcement`](fn.encode_announcement.html), if it's well-formed.
+pub(crate) fn decode_announcement(mut from: &[u8]) -> Option<Vec<(encrypt::PublicKey, Vec<Path>)>> {
+  let mut entries = vec![];
+  for _ in 0..take(&mut from, 1)?[0] {
+    let pkey = encrypt::PublicKey::from_slice(take(&mut from, 32)?)?;
+    let mut paths = vec![];
+    for _ in 0..take(&mut from, 1)?[0] {
+      let len = take(&mut from, 2)?;
+      let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+      let path = std::str::from_utf8(take(&mut from, len)?).ok()?;
+      paths.push(Path::parse(path).ok()?);
+    }
+    entries.push((pkey, paths));
+  }
+  if from.is_empty() {
+    Some(entries)
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn announcement_roundtrip() {
+    let (pk1, _) = encrypt::gen_keypair();
+    let (pk2, _) = encrypt::gen_keypair();
+    let paths1 = vec![Path::parse("inmem:one").unwrap(), Path::parse("tcp:[::1]:1").unwrap()];
+    let paths2 = vec![];
+    let bytes = encode_announcement(vec![(&pk1, &paths1[..]), (&pk2, &paths2[..])].into_iter());
+    assert_eq!(Some(vec![(pk1, paths1), (pk2, paths2)]), decode_announcement(&bytes));
+    assert_eq!(None, decode_announcement(&bytes[..bytes.len() - 1]));
+    assert_eq!(None, decode_announcement(&[]));
+  }
+}
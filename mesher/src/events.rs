@@ -0,0 +1,33 @@
+//! Contains the callbacks for things a mesher does that don't otherwise surface to the application.
+
+use crate::{prelude::*, ListenStatus};
+
+/// Gets told about things a [`Mesher`](struct.Mesher.html) does while handling packets, which wouldn't otherwise be
+/// visible to the application, e.g. packets it forwards as a relay.
+///
+/// Set it with [`Mesher::set_event_handler`](struct.Mesher.html#method.set_event_handler).
+/// Every method does nothing by default, so implementations only need to handle the events they care about.
+/// They're called while the packet is being handled, before [`receive`](struct.Mesher.html#method.receive) returns.
+///
+/// Handlers have to be `Send`, so the mesher they're set on can be moved to another thread.
+pub trait MesherEvents: Send {
+  /// A packet is being forwarded along the given path.
+  ///
+  /// If the mesher's [mixing](struct.MixPolicy.html), this is called when the packet goes into the pool, not when it's
+  /// actually sent.
+  fn on_forwarded(&mut self, _path: &str) {}
+
+  /// A packet was received, but none of its chunks could be decrypted, i.e. there was nothing in it for this mesher.
+  fn on_undecryptable_packet(&mut self) {}
+
+  /// The transport for the given scheme failed to send or receive.
+  ///
+  /// The failure is still reported as usual, i.e. returned or passed to the [failure handler](struct.Mesher.html#method.on_failure).
+  fn on_transport_error(&mut self, _scheme: &str, _err: &fail::MesherFail) {}
+
+  /// The [status](struct.Mesher.html#method.health) of the listener on the given path changed, e.g. it went down and the
+  /// transport's trying to bring it back up.
+  ///
+  /// It's checked every time the mesher receives, so short outages between receives might not be seen.
+  fn on_listen_status(&mut self, _path: &Path, _status: &ListenStatus) {}
+}
@@ -2,6 +2,8 @@
 
 //! Contains the error-reporting enum for mesher.
 
+use crate::alloc_prelude::*;
+
 /// Every possible way a [`Mesher`](../struct.Mesher.html) can fail to do something.
 ///
 /// Generally split into two categories, mesher and transport errors.
@@ -16,6 +18,8 @@ pub enum MesherFail {
   /// This is only triggerd when keys are actually used, *not* during initialization.
   /// Meshers can generally be safely created without keys, then have them added, so long as it doesn't try to receive messages or send signed ones.
   NoKeys,
+  /// A signed mesher was created without any sender keys, so it could never accept any packets.
+  NoSenderKeys,
 
   /// A mesher received a packet in a format that couldn't be parsed into a packet.
   ///
@@ -23,32 +27,107 @@ pub enum MesherFail {
   /// They will be no-ops.
   /// This error means that the packet itself had an invalid structure.
   InvalidPacket,
+  /// A packet was bigger than the mesher's [maximum size](../struct.Mesher.html#method.set_max_packet_size), so it
+  /// wasn't sent or wasn't read.
+  /// Contains the packet's size, then the maximum, both in bytes.
+  PacketTooLarge(usize, usize),
+  /// A mesher received a packet in a format version it doesn't support, probably because it was sent by a newer version of mesher.
+  UnsupportedVersion(u8),
+  /// A chunk in a packet was encrypted for this mesher, but its signature didn't check out against any sender key.
+  /// That means it was either changed in transit or forged.
+  ///
+  /// Only signed meshers can tell: a tampered chunk fails to decrypt, which looks the same as a chunk meant for someone else.
+  /// What happens to the rest of the packet depends on the mesher's [`TamperPolicy`](../enum.TamperPolicy.html).
+  Tampered,
+  /// An [endorsement](../identity/struct.Endorsement.html) wasn't signed by a trust root, or its signature didn't check
+  /// out.
+  BadEndorsement,
+  /// A [revocation](../identity/struct.Revocation.html) wasn't signed by a trust root or the revoked key itself, or its
+  /// signature didn't check out.
+  BadRevocation,
   /// You tried to reply to a message that doesn't have a reply block attached.
   NoReplyBlock,
+  /// A [public key](../crypto/encrypt/struct.PublicKey.html) couldn't be parsed from text.
+  /// Contains the text.
+  InvalidKey(String),
+
+  /// There's no known way to get a packet to the given node, e.g. because it's not in the peer table.
+  NoRoute(String),
+  /// A received packet asked to be forwarded along the given path, but the mesher's
+  /// [`ForwardPolicy`](../struct.ForwardPolicy.html) doesn't allow it, or it was meant for a
+  /// [`Mailbox`](../struct.Mailbox.html) that isn't registered or is full.
+  ForwardDenied(String),
 
   /// The URL passed as the path to transport a packet along is invalid.
   InvalidURL(String),
   /// The URL's scheme hasn't been registered with the mesher, so it can't know what transport to use to move the packet.
   UnregisteredScheme(String),
+  /// A transport was added for a scheme which already has one.
+  /// To swap it out, use [`Mesher::replace_transport`](../struct.Mesher.html#method.replace_transport).
+  AlreadyRegistered(String),
 
   /// The transport being asked to listen on a path wasn't able to.
   SetupFailure(String),
 
   /// The transport being asked to send data along a path wasn't able to.
   ///
-  /// This can trigger during calls to [`Mesher::receive`](../struct.Mesher.html#method.receive), since it will send packets as requested while parsing them.
+  /// This can happen while [`Mesher::receive`](../struct.Mesher.html#method.receive) is processing packets, since it will send packets as requested while parsing them.
+  /// In that case, it's passed to the handler set with [`Mesher::on_failure`](../struct.Mesher.html#method.on_failure) rather than returned.
   SendFailure(String),
 
   /// The transport being asked to listen along a path wasn't able to.
   ListenFailure(String),
 
+  /// Sending would have gone over the [`Quota`](../struct.Quota.html) for the transport with the given scheme, so it
+  /// wasn't sent.
+  QuotaExceeded(String),
+
   /// The transport being asked to fetch all received messages wasn't able to.
   ReceiveFailure(String),
+  /// Something a transport was doing in the background, e.g. a listener thread, died, and the transport couldn't
+  /// restart it, so packets probably aren't being received.
+  /// Contains a description of what died, including the path it was for.
+  ///
+  /// Transports which report it keep trying to restart it, so it may come back by itself.
+  TransportDead(String),
+
+  /// The [`OutboundQueue`](../struct.OutboundQueue.html) couldn't read or write its files.
+  QueueFailure(String),
+  /// A [transfer](../transfer/index.html) couldn't read or write its stream.
+  TransferFailure(String),
+  /// A [keystore](../keystore/index.html) couldn't load or save a key, e.g. because there's no key with that name or
+  /// the passphrase was wrong.
+  KeystoreFailure(String),
+  /// An [address book](../contacts/index.html) couldn't be read or saved, or a contact in it isn't valid.
+  ContactsFailure(String),
+
+  /// An [RPC](../rpc/index.html) request couldn't be answered, e.g. because the responder has no handler for its method.
+  RpcFailure(String),
+  /// A [session](../session/index.html) couldn't be started or used.
+  SessionFailure(String),
+  /// An exit node couldn't make a request for the mesh, e.g. because what it was asked to reach isn't allowed, or didn't
+  /// answer properly.
+  /// Contains the exit's description of what went wrong.
+  ExitFailure(String),
+  /// Waiting for something, e.g. an [RPC](../rpc/index.html) response, took too long.
+  /// Contains a description of what was being waited for.
+  TimedOut(String),
+
+  /// A [config file](../config/index.html) couldn't be read, or doesn't describe a valid mesher.
+  /// Contains a description of what's wrong with it.
+  InvalidConfig(String),
+
+  /// The mesher was asked to stop listening on a path it wasn't listening on.
+  NotListening(String),
+
+  /// The transport doesn't support what it was asked to do.
+  /// Contains a description of the operation.
+  Unsupported(String),
 
   /// Some other error happened.
   /// Ideally, this would never be returned, but it's left as an option just in case, or for debugging.
-  Other(Box<dyn std::error::Error>),
+  Other(Box<dyn core::error::Error + Send + Sync>),
 }
 
 /// A `Result` alias with [`MesherFail`](enum.MesherFail.html) as the Err type to make some code a little less repetitive.
-pub type Result<TOk> = std::result::Result<TOk, MesherFail>;
+pub type Result<TOk> = core::result::Result<TOk, MesherFail>;
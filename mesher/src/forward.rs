@@ -0,0 +1,179 @@
+//! Contains the rules for which packets a mesher will forward for other nodes.
+
+use crate::{addr, prelude::*, RateLimit, Source};
+
+/// Which paths a [`Mesher`](struct.Mesher.html) will forward packets it receives along, and how often.
+///
+/// Without a policy, a mesher forwards packets wherever they ask to go, which lets anyone who can reach a public relay
+/// use it to send data to addresses only the relay can reach, e.g. `tcp:10.0.0.1:22`.
+/// The policy is only checked for packets the mesher receives; ones it [launches](struct.Mesher.html#method.launch)
+/// itself go wherever they ask.
+///
+/// A path is forwarded along only if it passes every rule that's been set:
+///
+/// - [`only_schemes`](#method.only_schemes) limits which transports can be used.
+/// - [`deny`](#method.deny) rejects paths matching a pattern, where `*` matches any run of characters, e.g. `tcp:10.*`.
+///   Patterns are matched against the scheme and location, i.e. the path without any options.
+/// - [`deny_local_addresses`](#method.deny_local_addresses) rejects anything that resolves to an address that isn't on
+///   the public internet.
+/// - [`deny_from`](#method.deny_from) rejects packets received from senders matching a pattern, wherever they're going.
+/// - [`rate_limit`](#method.rate_limit) limits how many packets are forwarded overall.
+///
+/// Packets that aren't forwarded are reported to the [failure handler](struct.Mesher.html#method.on_failure) as
+/// [`MesherFail::ForwardDenied`](fail/enum.MesherFail.html#variant.ForwardDenied).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ForwardPolicy {
+  schemes: Option<Vec<String>>,
+  denied: Vec<String>,
+  denied_sources: Vec<String>,
+  deny_local: bool,
+  pub(crate) rate_limit: Option<RateLimit>,
+}
+
+impl ForwardPolicy {
+  /// Creates a policy that allows everything, to be narrowed down with the other methods.
+  pub fn new() -> ForwardPolicy {
+    ForwardPolicy::default()
+  }
+
+  /// Only forwards along paths with one of the given schemes.
+  pub fn only_schemes<'a>(mut self, schemes: impl IntoIterator<Item = &'a str>) -> ForwardPolicy {
+    self.schemes = Some(schemes.into_iter().map(str::to_owned).collect());
+    self
+  }
+
+  /// Doesn't forward along paths matching the given pattern, where `*` matches any run of characters.
+  pub fn deny(mut self, pattern: &str) -> ForwardPolicy {
+    self.denied.push(pattern.to_owned());
+    self
+  }
+
+  /// Doesn't forward to anywhere that resolves to an address that isn't [public](addr/fn.is_public.html), e.g. loopback,
+  /// private, link-local, or multicast ones, with or without a port.
+  ///
+  /// Names are resolved, and rejected if any of their addresses aren't public, so every check can mean a DNS lookup.
+  /// The transport resolves the name again when it connects, so a name whose records change in between, on purpose or
+  /// not, can still get through.
+  pub fn deny_local_addresses(mut self) -> ForwardPolicy {
+    self.deny_local = true;
+    self
+  }
+
+  /// Doesn't forward packets received from a sender whose [remote address](struct.Source.html#method.remote) matches the
+  /// given pattern, where `*` matches any run of characters, e.g. `203.0.113.*`.
+  ///
+  /// Packets whose transport doesn't know who sent them are still forwarded.
+  pub fn deny_from(mut self, pattern: &str) -> ForwardPolicy {
+    self.denied_sources.push(pattern.to_owned());
+    self
+  }
+
+  /// Forwards at most `per_second` packets per second on average, with bursts of up to `burst`, across all paths.
+  pub fn rate_limit(mut self, per_second: u32, burst: u32) -> ForwardPolicy {
+    self.rate_limit = Some(RateLimit::new(per_second, burst));
+    self
+  }
+
+  /// Whether the path passes every rule except the rate limit, which the mesher keeps track of itself.
+  pub(crate) fn permits(&self, path: &Path) -> bool {
+    if let Some(schemes) = &self.schemes {
+      if !schemes.iter().any(|s| s == path.scheme()) {
+        return false;
+      }
+    }
+    let target = format!("{}:{}", path.scheme(), path.location());
+    if self.denied.iter().any(|p| glob_match(p, &target)) {
+      return false;
+    }
+    !(self.deny_local
+      && addr::resolve(path.location())
+        .into_iter()
+        .any(|ip| !addr::is_public(ip)))
+  }
+
+  /// Whether packets received from the given source may be forwarded at all.
+  pub(crate) fn accepts(&self, source: &Source) -> bool {
+    match source.remote() {
+      Some(remote) => !self.denied_sources.iter().any(|p| glob_match(p, remote)),
+      None => true,
+    }
+  }
+}
+
+/// Whether `text` matches `pattern`, where `*` in the pattern matches any run of characters, including none.
+fn glob_match(pattern: &str, text: &str) -> bool {
+  let mut pieces = pattern.split('*');
+  // there's always at least one piece, even in an empty pattern
+  let first = pieces.next().unwrap_or_default();
+  let mut rest = match text.strip_prefix(first) {
+    Some(rest) => rest,
+    None => return false,
+  };
+  let pieces: Vec<_> = pieces.collect();
+  let (last, middle) = match pieces.split_last() {
+    Some(split) => split,
+    None => return rest.is_empty(),
+  };
+  for piece in middle {
+    match rest.find(piece) {
+      Some(at) => rest = &rest[at + piece.len()..],
+      None => return false,
+    }
+  }
+  rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn permits(policy: &ForwardPolicy, path: &str) -> bool {
+    policy.permits(&Path::parse(path).expect("Failed to parse"))
+  }
+
+  #[test]
+  fn globs_match() {
+    assert!(glob_match("tcp:10.*", "tcp:10.0.0.1:22"));
+    assert!(glob_match("*:22", "tcp:10.0.0.1:22"));
+    assert!(glob_match("tcp:*.0.*", "tcp:10.0.0.1:22"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("a*a", "aa"));
+    assert!(!glob_match("a*a", "a"));
+    assert!(!glob_match("tcp:10.*", "udp:10.0.0.1:22"));
+    assert!(!glob_match("tcp:10.0.0.1", "tcp:10.0.0.1:22"));
+  }
+
+  #[test]
+  fn rules_followed() {
+    let policy = ForwardPolicy::new()
+      .only_schemes(vec!["tcp", "udp"])
+      .deny("*:22")
+      .deny_local_addresses();
+    assert!(permits(&policy, "tcp:1.1.1.1:18540?retries=3"));
+    assert!(permits(&policy, "udp:[2606:4700::1111]:18540"));
+    assert!(!permits(&policy, "inmem:anything"));
+    assert!(!permits(&policy, "tcp:1.1.1.1:22"));
+    assert!(!permits(&policy, "tcp:10.0.0.1:18540"));
+    assert!(!permits(&policy, "tcp:127.0.0.1:18540"));
+    assert!(!permits(&policy, "tcp:[::1]:18540"));
+    assert!(!permits(&policy, "tcp:[fd00::1]:18540"));
+    assert!(!permits(&policy, "tcp:[::ffff:192.168.1.1]:18540"));
+    assert!(!permits(&policy, "udp:localhost:18540"));
+    // other ways of writing loopback addresses, which the OS would still connect to
+    assert!(!permits(&policy, "tcp:127.1:18540"));
+    assert!(!permits(&policy, "tcp:2130706433:18540"));
+    assert!(!permits(&policy, "tcp:0x7f.1:18540"));
+    assert!(!permits(&policy, "tcp:100.64.0.1:18540"));
+    assert!(!permits(&policy, "udp:224.0.0.1:18540"));
+    assert!(permits(&ForwardPolicy::new(), "tcp:127.0.0.1:22"));
+  }
+
+  #[test]
+  fn sources_checked() {
+    let policy = ForwardPolicy::new().deny_from("203.0.113.*");
+    let listen = Path::parse("tcp:[::]:18540").expect("Failed to parse");
+    assert!(!policy.accepts(&Source::listening_on(listen.clone()).from_remote("203.0.113.7:41000")));
+    assert!(policy.accepts(&Source::listening_on(listen).from_remote("198.51.100.1:41000")));
+    assert!(policy.accepts(&Source::unknown()));
+  }
+}
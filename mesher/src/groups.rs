@@ -0,0 +1,169 @@
+use crate::{prelude::*, Priority, Route};
+
+/// Keeps track of a group's members and its current key, for the node that manages it.
+///
+/// Members read what's sent to the group with [`Packet::add_group_message`](struct.Packet.html#method.add_group_message)
+/// once they have the key, which [`distribute`](#method.distribute) sends each of them, wrapped for their own key.
+/// Removing a member re-keys the group, so that once the new key's distributed, the removed member can't read anything
+/// sent with it.
+///
+/// Nothing stops a member from passing the key along, so this only keeps out nodes nobody in the group wants in.
+///
+/// Members don't join a group just because they were sent its key: it's held as a [`GroupInvite`](struct.GroupInvite.html)
+/// until the app [accepts it](struct.Mesher.html#method.accept_group_invite).
+/// After that, new keys for the group are taken automatically, but only if they're from the same sender, so groups
+/// should be [signed](#method.signed).
+///
+/// ```
+/// # use mesher::{prelude::*, Group, Route};
+/// let (owner_pk, owner_sk) = encrypt::gen_keypair();
+/// let (member_pk, member_sk) = encrypt::gen_keypair();
+/// let mut owner = Mesher::unsigned(vec![owner_sk]);
+/// owner.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+/// let mut member = Mesher::unsigned(vec![member_sk]);
+/// member.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+/// member.listen_on("inmem:group-doc-member").expect("Failed to listen");
+/// let route = Route::new()
+///   .then(Path::parse("inmem:group-doc-owner").unwrap(), owner_pk)
+///   .then(Path::parse("inmem:group-doc-member").unwrap(), member_pk);
+///
+/// let (_, owner_ssk) = sign::gen_keypair();
+/// let mut group = Group::signed(owner_ssk);
+/// group.add_member(member_pk);
+/// group.distribute(&mut owner, |_| Some(route.clone())).expect("Failed to distribute");
+/// member.receive().expect("Failed to receive");
+/// for invite in member.take_group_invites() {
+///   // e.g. only accept invites from contacts
+///   assert!(invite.from().is_some());
+///   member.accept_group_invite(invite);
+/// }
+/// assert!(member.group_key(group.id()).is_some());
+/// ```
+pub struct Group {
+  key: group::GroupKey,
+  members: Vec<encrypt::PublicKey>,
+  signer: Option<sign::SecretKey>,
+}
+
+impl Default for Group {
+  fn default() -> Group {
+    Group::new()
+  }
+}
+
+impl Group {
+  /// Creates a new group, with a new key and no members.
+  ///
+  /// Its keys aren't signed, so members have to accept every new one by hand.
+  pub fn new() -> Group {
+    Group {
+      key: group::GroupKey::generate(),
+      members: vec![],
+      signer: None,
+    }
+  }
+
+  /// Creates a new group, like [`new`](#method.new), whose keys are sent with a
+  /// [claim](struct.Packet.html#method.claim_sender) that they're from the owner of `skey`.
+  pub fn signed(skey: sign::SecretKey) -> Group {
+    Group {
+      signer: Some(skey),
+      ..Group::new()
+    }
+  }
+
+  /// The group's ID.
+  pub fn id(&self) -> group::GroupId {
+    self.key.id()
+  }
+
+  /// The group's current key, to send messages to it with.
+  pub fn key(&self) -> &group::GroupKey {
+    &self.key
+  }
+
+  /// Everyone in the group, in the order they were added.
+  pub fn members(&self) -> &[encrypt::PublicKey] {
+    &self.members
+  }
+
+  /// Adds a member to the group, returning whether they're new.
+  ///
+  /// They only get the key at the next [`distribute`](#method.distribute).
+  pub fn add_member(&mut self, pkey: encrypt::PublicKey) -> bool {
+    if self.members.contains(&pkey) {
+      return false;
+    }
+    self.members.push(pkey);
+    true
+  }
+
+  /// Removes a member from the group, returning whether they were in it, and re-keys the group if they were.
+  ///
+  /// Until the new key's [distributed](#method.distribute), the rest of the members can't read messages sent with it.
+  pub fn remove_member(&mut self, pkey: &encrypt::PublicKey) -> bool {
+    let before = self.members.len();
+    self.members.retain(|m| m != pkey);
+    if self.members.len() == before {
+      return false;
+    }
+    self.key = self.key.next();
+    true
+  }
+
+  /// Sends the current key to every member `route_to` gives a route to, returning how many it was sent to, and has the
+  /// mesher [join](struct.Mesher.html#method.join_group) the group too, so it can read the group's messages.
+  ///
+  /// Routes should start at this mesher and end at the member, like the ones passed to
+  /// [`Packet::via_route`](struct.Packet.html#method.via_route).
+  /// Members without a route are skipped, as are ones the key fails to send to, and the first failure is returned once
+  /// the rest have been tried.
+  pub fn distribute(
+    &self,
+    mesher: &mut Mesher,
+    mut route_to: impl FnMut(&encrypt::PublicKey) -> Option<Route>,
+  ) -> fail::Result<usize> {
+    mesher.join_group(self.key.clone());
+    let mut sent = 0;
+    let mut result = Ok(());
+    for member in &self.members {
+      let route = match route_to(member) {
+        Some(route) => route,
+        None => continue,
+      };
+      let mut packet = mesher.new_packet(None);
+      if let Some(signer) = &self.signer {
+        packet.claim_sender(signer);
+      }
+      packet.via_route(&route);
+      packet.add_group_key(&self.key, member);
+      match mesher.launch_with_priority(packet, Priority::Control) {
+        Ok(()) => sent += 1,
+        Err(e) => result = result.and(Err(e)),
+      }
+    }
+    result.map(|_| sent)
+  }
+}
+
+/// A group key sent to a [`Mesher`](struct.Mesher.html) that it didn't take on its own, because the group's new to it
+/// or the key isn't from whoever it accepted the group's keys from before.
+///
+/// Invites are collected with [`Mesher::take_group_invites`](struct.Mesher.html#method.take_group_invites).
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupInvite {
+  pub(crate) key: group::GroupKey,
+  pub(crate) from: Option<sign::PublicKey>,
+}
+
+impl GroupInvite {
+  /// The key the mesher was sent.
+  pub fn key(&self) -> &group::GroupKey {
+    &self.key
+  }
+
+  /// Who [claimed](struct.Packet.html#method.claim_sender) to send the key, if anyone did and the claim checks out.
+  pub fn from(&self) -> Option<&sign::PublicKey> {
+    self.from.as_ref()
+  }
+}
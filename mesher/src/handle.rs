@@ -0,0 +1,59 @@
+//! Contains the handle for launching packets through a mesher from other threads.
+
+use crate::{mesher::Launch, prelude::*, Priority};
+
+use std::sync::mpsc::Sender;
+
+/// A cheap, cloneable way to launch packets through a [`Mesher`](struct.Mesher.html) from any thread.
+///
+/// Get one with [`Mesher::handle`](struct.Mesher.html#method.handle).
+/// The mesher itself keeps its transports, and has to be driven by calling [`receive`](struct.Mesher.html#method.receive)
+/// from wherever it lives, e.g. a worker thread it's been moved to.
+/// Packets launched through a handle are sent the next time it does, and failures sending them go to the
+/// [failure handler](struct.Mesher.html#method.on_failure), since they can't be returned here.
+///
+/// ```
+/// # use mesher::prelude::*;
+/// let (pk, sk) = encrypt::gen_keypair();
+/// let mut mesher = Mesher::unsigned(vec![sk]);
+/// mesher.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+/// mesher.listen_on("inmem:handle-doc").expect("Failed to listen");
+/// let handle = mesher.handle();
+///
+/// std::thread::spawn(move || {
+///   let mut packet = Packet::unsigned();
+///   packet.add_hop("inmem:handle-doc".to_owned(), &pk);
+///   packet.add_message(b"hi", &pk);
+///   handle.launch(packet).expect("Mesher was dropped");
+/// })
+/// .join()
+/// .unwrap();
+///
+/// // the first receive sends the packet, and the next one gets it back
+/// mesher.receive().expect("Failed to receive");
+/// assert_eq!(b"hi", mesher.receive().expect("Failed to receive")[0].contents());
+/// ```
+#[derive(Clone)]
+pub struct MesherHandle {
+  pub(crate) launches: Sender<Launch>,
+}
+
+impl MesherHandle {
+  /// Queues a packet for the mesher to [`launch`](struct.Mesher.html#method.launch) the next time it receives.
+  ///
+  /// Fails with [`MesherFail::SendFailure`](fail/enum.MesherFail.html#variant.SendFailure) if the mesher's been dropped.
+  pub fn launch(&self, packet: Packet) -> fail::Result<()> {
+    self.launch_with_priority(packet, Priority::Normal)
+  }
+
+  /// Queues a packet for the mesher to [`launch_with_priority`](struct.Mesher.html#method.launch_with_priority) the next
+  /// time it receives.
+  ///
+  /// Packets queued by every handle since the mesher last received are launched most urgent first.
+  pub fn launch_with_priority(&self, packet: Packet, priority: Priority) -> fail::Result<()> {
+    self
+      .launches
+      .send((packet, priority))
+      .map_err(|_| fail::MesherFail::SendFailure("the mesher was dropped".to_owned()))
+  }
+}
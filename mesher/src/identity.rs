@@ -0,0 +1,314 @@
+//! Contains endorsements, which let a signed mesher trust keys it's never seen, so long as a key it does trust vouches
+//! for them, and revocations, which take that trust back.
+//!
+//! Rather than every node needing every sender's key, a signed mesher can be given a few trust roots, e.g. with
+//! [`Mesher::endorsed`](../struct.Mesher.html#method.endorsed), and then accepts packets signed by any key one of
+//! them has [endorsed](struct.Endorsement.html).
+//! Senders either hand their endorsements out ahead of time, to be added with
+//! [`Mesher::add_endorsement`](../struct.Mesher.html#method.add_endorsement), or put them in the packets themselves with
+//! [`Packet::add_endorsement`](../struct.Packet.html#method.add_endorsement); either way, the mesher remembers them.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::identity::Endorsement;
+//!
+//! let (root_pk, root_sk) = sign::gen_keypair();
+//! let (node_pk, _) = sign::gen_keypair();
+//! let endorsement = Endorsement::new(&node_pk, &root_sk);
+//! assert!(endorsement.verify());
+//!
+//! let (_, sk) = encrypt::gen_keypair();
+//! let mut mesher = Mesher::endorsed(vec![sk], vec![root_pk]).expect("Failed to create mesher");
+//! mesher.add_endorsement(endorsement).expect("Endorsement wasn't accepted");
+//! ```
+//!
+//! If a key's lost or stolen, its owner or one of the trust roots can [revoke](struct.Revocation.html) it, and
+//! [publish](../struct.Mesher.html#method.publish_revocation) the revocation, which every mesher that accepts it passes
+//! on to its own peers.
+//! Meshers keep the revocations they've accepted in a [`RevocationStore`](struct.RevocationStore.html), and won't accept
+//! packets signed by a revoked key again, even if it's a sender key or has been endorsed.
+
+use crate::{alloc_prelude::*, crypto::Crypto, prelude::*};
+
+/// Signed along with the endorsed key, so endorsements can't be mistaken for any other signature.
+const ENDORSEMENT_CONTEXT: &[u8] = b"mesher endorsement v1";
+
+/// Signed along with the revoked key, so revocations can't be mistaken for any other signature.
+const REVOCATION_CONTEXT: &[u8] = b"mesher revocation v1";
+
+/// How many bytes an encoded endorsement or revocation takes up.
+const STATEMENT_BYTES: usize = 32 + 32 + 64;
+
+/// Signs `context` and `subject` with `signer`, returning the signer's public key and the signature.
+fn sign_statement(
+  context: &[u8],
+  subject: &sign::PublicKey,
+  signer: &sign::SecretKey,
+  crypto: &dyn Crypto,
+) -> (sign::PublicKey, [u8; 64]) {
+  let signed = crypto.sign(&[context, subject.as_bytes()].concat(), signer);
+  let mut signature = [0; 64];
+  signature.copy_from_slice(&signed[..64]);
+  (crypto.sign_public_key(signer), signature)
+}
+
+/// Whether `signature` is `signer`'s signature of `context` and `subject`.
+fn verify_statement(
+  context: &[u8],
+  subject: &sign::PublicKey,
+  signer: &sign::PublicKey,
+  signature: &[u8; 64],
+  crypto: &dyn Crypto,
+) -> bool {
+  let message = [context, subject.as_bytes()].concat();
+  let signed = [&signature[..], &message].concat();
+  crypto.verify(&signed, signer).as_deref() == Some(&message[..])
+}
+
+/// Splits an encoded statement into the subject, the signer, and the signature, if it's the right length.
+fn decode_statement(bytes: &[u8]) -> Option<(sign::PublicKey, sign::PublicKey, [u8; 64])> {
+  if bytes.len() != STATEMENT_BYTES {
+    return None;
+  }
+  let mut signature = [0; 64];
+  signature.copy_from_slice(&bytes[64..]);
+  Some((
+    sign::PublicKey::from_slice(&bytes[..32])?,
+    sign::PublicKey::from_slice(&bytes[32..64])?,
+    signature,
+  ))
+}
+
+/// A signature by one key (the endorser) vouching for another (the subject).
+///
+/// Endorsements don't expire; to stop trusting the keys a root's endorsed, stop trusting the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endorsement {
+  subject: sign::PublicKey,
+  endorser: sign::PublicKey,
+  signature: [u8; 64],
+}
+
+impl Endorsement {
+  /// Endorses `subject` with `endorser`, using the default backend.
+  pub fn new(subject: &sign::PublicKey, endorser: &sign::SecretKey) -> Endorsement {
+    Endorsement::new_using(subject, endorser, crate::crypto::default_backend().as_ref())
+  }
+
+  /// Endorses `subject` with `endorser`, using the given crypto backend.
+  pub fn new_using(subject: &sign::PublicKey, endorser: &sign::SecretKey, crypto: &dyn Crypto) -> Endorsement {
+    let (endorser, signature) = sign_statement(ENDORSEMENT_CONTEXT, subject, endorser, crypto);
+    Endorsement {
+      subject: *subject,
+      endorser,
+      signature,
+    }
+  }
+
+  /// The key being vouched for.
+  pub fn subject(&self) -> &sign::PublicKey {
+    &self.subject
+  }
+
+  /// The key vouching for it.
+  pub fn endorser(&self) -> &sign::PublicKey {
+    &self.endorser
+  }
+
+  /// Whether the signature checks out, using the default backend.
+  ///
+  /// This says nothing about whether the endorser should be trusted.
+  pub fn verify(&self) -> bool {
+    self.verify_using(crate::crypto::default_backend().as_ref())
+  }
+
+  /// Whether the signature checks out, using the given crypto backend.
+  pub fn verify_using(&self, crypto: &dyn Crypto) -> bool {
+    verify_statement(
+      ENDORSEMENT_CONTEXT,
+      &self.subject,
+      &self.endorser,
+      &self.signature,
+      crypto,
+    )
+  }
+
+  /// Encodes the endorsement as the subject, then the endorser, then the signature.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    [self.subject.as_bytes(), self.endorser.as_bytes(), &self.signature].concat()
+  }
+
+  /// Decodes an endorsement from [`to_bytes`](#method.to_bytes), if it's the right length.
+  ///
+  /// The signature isn't checked; use [`verify`](#method.verify) for that.
+  pub fn from_bytes(bytes: &[u8]) -> Option<Endorsement> {
+    let (subject, endorser, signature) = decode_statement(bytes)?;
+    Some(Endorsement {
+      subject,
+      endorser,
+      signature,
+    })
+  }
+}
+
+/// A signature saying a key (the revoked key) shouldn't be trusted any more.
+///
+/// Meshers only accept revocations signed by one of their trust roots, or by the revoked key itself, which is how a
+/// node's owner revokes their own key.
+/// Like endorsements, revocations don't expire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revocation {
+  revoked: sign::PublicKey,
+  revoker: sign::PublicKey,
+  signature: [u8; 64],
+}
+
+impl Revocation {
+  /// Revokes `revoked`, signed by `revoker`, using the default backend.
+  pub fn new(revoked: &sign::PublicKey, revoker: &sign::SecretKey) -> Revocation {
+    Revocation::new_using(revoked, revoker, crate::crypto::default_backend().as_ref())
+  }
+
+  /// Revokes `revoked`, signed by `revoker`, using the given crypto backend.
+  pub fn new_using(revoked: &sign::PublicKey, revoker: &sign::SecretKey, crypto: &dyn Crypto) -> Revocation {
+    let (revoker, signature) = sign_statement(REVOCATION_CONTEXT, revoked, revoker, crypto);
+    Revocation {
+      revoked: *revoked,
+      revoker,
+      signature,
+    }
+  }
+
+  /// The key that shouldn't be trusted any more.
+  pub fn revoked(&self) -> &sign::PublicKey {
+    &self.revoked
+  }
+
+  /// The key that signed the revocation.
+  pub fn revoker(&self) -> &sign::PublicKey {
+    &self.revoker
+  }
+
+  /// Whether the signature checks out, using the default backend.
+  ///
+  /// This says nothing about whether the revoker should be listened to.
+  pub fn verify(&self) -> bool {
+    self.verify_using(crate::crypto::default_backend().as_ref())
+  }
+
+  /// Whether the signature checks out, using the given crypto backend.
+  pub fn verify_using(&self, crypto: &dyn Crypto) -> bool {
+    verify_statement(
+      REVOCATION_CONTEXT,
+      &self.revoked,
+      &self.revoker,
+      &self.signature,
+      crypto,
+    )
+  }
+
+  /// Encodes the revocation as the revoked key, then the revoker, then the signature.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    [self.revoked.as_bytes(), self.revoker.as_bytes(), &self.signature].concat()
+  }
+
+  /// Decodes a revocation from [`to_bytes`](#method.to_bytes), if it's the right length.
+  ///
+  /// The signature isn't checked; use [`verify`](#method.verify) for that.
+  pub fn from_bytes(bytes: &[u8]) -> Option<Revocation> {
+    let (revoked, revoker, signature) = decode_statement(bytes)?;
+    Some(Revocation {
+      revoked,
+      revoker,
+      signature,
+    })
+  }
+}
+
+/// The revocations a mesher has accepted, which it checks before accepting any signed packet.
+///
+/// Get a mesher's with [`Mesher::revocations`](../struct.Mesher.html#method.revocations); revocations are added to it
+/// with [`Mesher::add_revocation`](../struct.Mesher.html#method.add_revocation), which checks they come from someone
+/// allowed to make them.
+#[derive(Debug, Clone, Default)]
+pub struct RevocationStore {
+  revocations: Vec<Revocation>,
+}
+
+impl RevocationStore {
+  /// Creates an empty store.
+  pub fn new() -> RevocationStore {
+    RevocationStore::default()
+  }
+
+  /// Adds a revocation, returning whether its key wasn't already revoked.
+  #[cfg(feature = "std")]
+  pub(crate) fn insert(&mut self, revocation: Revocation) -> bool {
+    if self.is_revoked(revocation.revoked()) {
+      return false;
+    }
+    self.revocations.push(revocation);
+    true
+  }
+
+  /// Whether the key has been revoked.
+  pub fn is_revoked(&self, pkey: &sign::PublicKey) -> bool {
+    self.revocations.iter().any(|r| r.revoked() == pkey)
+  }
+
+  /// Every revocation in the store, in the order they were accepted, e.g. to pass on to a new peer.
+  pub fn iter(&self) -> impl Iterator<Item = &Revocation> {
+    self.revocations.iter()
+  }
+
+  /// How many keys have been revoked.
+  pub fn len(&self) -> usize {
+    self.revocations.len()
+  }
+
+  /// Whether no keys have been revoked.
+  pub fn is_empty(&self) -> bool {
+    self.revocations.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn endorsements_only_verify_unchanged() {
+    let (_, root_sk) = sign::gen_keypair();
+    let (node_pk, _) = sign::gen_keypair();
+    let (other_pk, _) = sign::gen_keypair();
+    let endorsement = Endorsement::new(&node_pk, &root_sk);
+    assert!(endorsement.verify());
+    assert_eq!(
+      Some(&endorsement),
+      Endorsement::from_bytes(&endorsement.to_bytes()).as_ref()
+    );
+
+    let mut bytes = endorsement.to_bytes();
+    bytes[..32].copy_from_slice(other_pk.as_bytes());
+    assert!(!Endorsement::from_bytes(&bytes).unwrap().verify());
+    assert_eq!(None, Endorsement::from_bytes(&bytes[1..]));
+  }
+
+  #[test]
+  fn revocations_dont_pass_for_endorsements() {
+    let (node_pk, node_sk) = sign::gen_keypair();
+    let revocation = Revocation::new(&node_pk, &node_sk);
+    assert!(revocation.verify());
+    assert_eq!(
+      Some(&revocation),
+      Revocation::from_bytes(&revocation.to_bytes()).as_ref()
+    );
+    assert!(!Endorsement::from_bytes(&revocation.to_bytes()).unwrap().verify());
+
+    let mut store = RevocationStore::new();
+    assert!(store.insert(revocation.clone()));
+    assert!(!store.insert(revocation));
+    assert!(store.is_revoked(&node_pk));
+    assert_eq!(1, store.len());
+  }
+}
@@ -0,0 +1,114 @@
+//! Contains the limits on how many received packets a mesher holds before processing them.
+
+use std::collections::VecDeque;
+
+/// What a mesher's [inbound queue](struct.InboundQueue.html) does when a transport hands over a packet while its
+/// queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Overflow {
+  /// Drops the oldest packet in the queue to make room, so the newest traffic gets through.
+  DropOldest,
+  /// Drops the packet that just arrived, so the queue's handled in the order it arrived.
+  #[default]
+  DropNewest,
+  /// Doesn't take any more packets from the transport until there's room, leaving them to its own
+  /// [buffer](../struct.Mesher.html#method.set_max_buffered).
+  Block,
+}
+
+/// How many packets received from each transport a [`Mesher`](struct.Mesher.html) holds waiting to be processed, e.g.
+/// because of its [receive limit](struct.Mesher.html#method.set_receive_limit), and what happens when there are more.
+///
+/// Each transport has its own queue, so a flood on one doesn't push out packets from the others.
+/// Packets dropped because a queue was full are counted by
+/// [`Mesher::inbound_dropped`](struct.Mesher.html#method.inbound_dropped), and reported to the mesher's
+/// [metrics](metrics/trait.Metrics.html) as [`QueueFull`](metrics/enum.DropReason.html#variant.QueueFull).
+///
+/// Blocking can only hold packets back in transports which can hand over
+/// [only some of them](trait.Transport.html#method.receive_up_to); the rest hand over everything, and since nothing's
+/// dropped when blocking, their queues can end up over capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboundQueue {
+  capacity: usize,
+  overflow: Overflow,
+}
+
+impl InboundQueue {
+  /// Holds up to `capacity` packets from each transport, dropping the newest when there are more.
+  pub fn new(capacity: usize) -> InboundQueue {
+    InboundQueue {
+      capacity,
+      overflow: Overflow::default(),
+    }
+  }
+
+  /// Changes what happens when a transport's queue is full.
+  pub fn overflow(mut self, overflow: Overflow) -> InboundQueue {
+    self.overflow = overflow;
+    self
+  }
+
+  /// How many packets the queue for each transport can hold.
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// How many more packets should be taken from a transport whose queue has `held` in it, or `None` if there's no need
+  /// to hold back.
+  pub(crate) fn room(&self, held: usize) -> Option<usize> {
+    match self.overflow {
+      Overflow::Block => Some(self.capacity.saturating_sub(held)),
+      _ => None,
+    }
+  }
+
+  /// Adds a packet to a transport's queue, returning whether one had to be dropped to fit it in.
+  pub(crate) fn push<T>(&self, queue: &mut VecDeque<T>, item: T) -> bool {
+    if queue.len() < self.capacity {
+      queue.push_back(item);
+      return false;
+    }
+    match self.overflow {
+      Overflow::DropOldest => {
+        queue.pop_front();
+        queue.push_back(item);
+        // with no capacity at all, even the newest doesn't fit
+        if queue.len() > self.capacity {
+          queue.pop_back();
+        }
+      }
+      Overflow::DropNewest => (),
+      Overflow::Block => {
+        queue.push_back(item);
+        return false;
+      }
+    }
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn overflow_followed() {
+    let mut queue = VecDeque::new();
+    let newest = InboundQueue::new(2);
+    assert!(!newest.push(&mut queue, 1));
+    assert!(!newest.push(&mut queue, 2));
+    assert!(newest.push(&mut queue, 3));
+    assert_eq!(vec![1, 2], Vec::from(queue.clone()));
+    assert_eq!(None, newest.room(2));
+
+    let oldest = InboundQueue::new(2).overflow(Overflow::DropOldest);
+    assert!(oldest.push(&mut queue, 3));
+    assert_eq!(vec![2, 3], Vec::from(queue.clone()));
+
+    let block = InboundQueue::new(2).overflow(Overflow::Block);
+    assert_eq!(Some(0), block.room(queue.len()));
+    assert_eq!(Some(2), block.room(0));
+    assert!(!block.push(&mut queue, 4));
+    assert_eq!(vec![2, 3, 4], Vec::from(queue));
+  }
+}
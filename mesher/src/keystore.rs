@@ -0,0 +1,418 @@
+//! Contains keystores, which keep secret keys somewhere safer than a plain file and hand them out by name.
+//!
+//! There are two built in:
+//!
+//! - [`OsKeychain`](struct.OsKeychain.html) uses the OS's credential store: the Keychain on macOS, the Secret Service
+//!   (e.g. GNOME Keyring or KWallet) on Linux and the BSDs, and the Credential Manager on Windows.
+//!   It needs the `os-keychain` feature.
+//! - [`PassphraseFile`](struct.PassphraseFile.html) keeps every key in one file, encrypted with a key derived from a
+//!   passphrase, for anywhere without an OS store, e.g. servers and containers.
+//!   It uses libsodium directly, so it needs the `crypto-sodium` feature.
+//!
+//! Anything else, e.g. a cloud secrets manager, can be used by implementing [`trait KeyStore`](trait.KeyStore.html).
+//! Keys can be loaded from a store directly, or by name when a mesher's built, with
+//! [`MesherBuilder::keystore`](../struct.MesherBuilder.html#method.keystore) or the `[keystore]` section of a
+//! [config file](../config/index.html).
+//!
+//! ```
+//! # #[cfg(feature = "crypto-sodium")] {
+//! # use mesher::prelude::*;
+//! use mesher::keystore::{KeyStore, PassphraseFile};
+//! use std::sync::Arc;
+//!
+//! # let path = std::env::temp_dir().join(format!("mesher-keystore-doc-{}", std::process::id()));
+//! let store = PassphraseFile::new(&path, "correct horse battery staple");
+//! let (pk, sk) = encrypt::gen_keypair();
+//! store.save("node", sk.as_bytes()).expect("Failed to save key");
+//!
+//! let mesher = Mesher::builder()
+//!   .keystore(Arc::new(store))
+//!   .own_key_named("node")
+//!   .build()
+//!   .expect("Failed to build mesher");
+//! # let _ = (pk, mesher);
+//! # std::fs::remove_file(&path).unwrap();
+//! # }
+//! ```
+//!
+//! Keys are named with up to 64 ASCII letters, digits, `.`, `_`, and `-`, so they can be passed to other programs
+//! without any quoting.
+
+use crate::{crypto::wipe, prelude::*};
+
+#[cfg(feature = "crypto-sodium")]
+use sodiumoxide::crypto::{pwhash::argon2id13, secretbox};
+
+#[cfg(feature = "crypto-sodium")]
+use std::{
+  fs,
+  io::{self, Write},
+  path::PathBuf,
+};
+
+/// Somewhere secret keys can be stored, and loaded from, by name.
+///
+/// Implementations should wipe any copies of keys they make along the way, as [`crypto`](../crypto/index.html)'s keys
+/// are wiped when they're dropped.
+pub trait KeyStore: Send + Sync {
+  /// Loads the secret stored under `name`.
+  ///
+  /// Fails with [`MesherFail::KeystoreFailure`](../fail/enum.MesherFail.html#variant.KeystoreFailure) if nothing's
+  /// stored under it, or the store can't be read.
+  fn load(&self, name: &str) -> fail::Result<Vec<u8>>;
+
+  /// Stores `secret` under `name`, replacing whatever was there.
+  fn save(&self, name: &str, secret: &[u8]) -> fail::Result<()>;
+
+  /// Loads the encryption key stored under `name`, failing if what's there isn't one.
+  fn encrypt_key(&self, name: &str) -> fail::Result<encrypt::SecretKey> {
+    let mut secret = self.load(name)?;
+    let key = encrypt::SecretKey::from_slice(&secret);
+    wipe(&mut secret);
+    key.ok_or_else(|| keystore_fail(format!("{:?} isn't an encryption key", name)))
+  }
+
+  /// Loads the signing key stored under `name`, failing if what's there isn't one.
+  fn sign_key(&self, name: &str) -> fail::Result<sign::SecretKey> {
+    let mut secret = self.load(name)?;
+    let key = sign::SecretKey::from_slice(&secret);
+    wipe(&mut secret);
+    key.ok_or_else(|| keystore_fail(format!("{:?} isn't a signing key", name)))
+  }
+}
+
+fn keystore_fail(msg: String) -> fail::MesherFail {
+  fail::MesherFail::KeystoreFailure(msg)
+}
+
+/// Checks a key's name can be passed around without quoting.
+#[cfg(any(feature = "crypto-sodium", feature = "os-keychain"))]
+fn check_name(name: &str) -> fail::Result<()> {
+  let ok = |c: char| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-';
+  if name.is_empty() || name.len() > 64 || !name.chars().all(ok) {
+    return Err(keystore_fail(format!(
+      "{:?} isn't a valid key name; names are up to 64 letters, digits, '.', '_', or '-'",
+      name
+    )));
+  }
+  Ok(())
+}
+
+/// Keeps keys in the OS's credential store, under a service name, so different applications' keys don't collide.
+///
+/// The stores are reached through the [`keyring`](https://crates.io/crates/keyring) crate, which calls their APIs
+/// directly, so keys never pass through another program:
+///
+/// - On macOS, the login keychain, with keys stored as generic passwords.
+/// - On other Unixes, whichever Secret Service is running, e.g. GNOME Keyring or KWallet, over D-Bus.
+///   libdbus is built in, so it doesn't need to be installed.
+/// - On Windows, the Credential Manager, with keys stored as generic credentials for the current user.
+///
+/// Elsewhere, e.g. on WebAssembly, every load and save fails.
+///
+/// Only available with the `os-keychain` feature.
+#[cfg(feature = "os-keychain")]
+#[derive(Debug, Clone)]
+pub struct OsKeychain {
+  service: String,
+}
+
+#[cfg(feature = "os-keychain")]
+impl OsKeychain {
+  /// Uses keys stored under the given service name, e.g. the application's name.
+  ///
+  /// The service name follows the same rules as key names.
+  pub fn new(service: &str) -> OsKeychain {
+    OsKeychain {
+      service: service.to_owned(),
+    }
+  }
+
+  /// The store's entry for `name`.
+  fn entry(&self, name: &str) -> fail::Result<keyring::Entry> {
+    check_name(&self.service)?;
+    check_name(name)?;
+    keyring::Entry::new(&self.service, name).map_err(|e| keychain_fail(name, e))
+  }
+}
+
+#[cfg(feature = "os-keychain")]
+fn keychain_fail(name: &str, e: keyring::Error) -> fail::MesherFail {
+  match e {
+    keyring::Error::NoEntry => keystore_fail(format!("the OS keychain has no key named {:?}", name)),
+    keyring::Error::NoStorageAccess(_) | keyring::Error::PlatformFailure(_) => {
+      keystore_fail(format!("couldn't reach the OS keychain for {:?}: {}", name, e))
+    }
+    e => keystore_fail(format!("the OS keychain refused {:?}: {}", name, e)),
+  }
+}
+
+#[cfg(feature = "os-keychain")]
+impl KeyStore for OsKeychain {
+  fn load(&self, name: &str) -> fail::Result<Vec<u8>> {
+    self.entry(name)?.get_secret().map_err(|e| keychain_fail(name, e))
+  }
+
+  fn save(&self, name: &str, secret: &[u8]) -> fail::Result<()> {
+    self.entry(name)?.set_secret(secret).map_err(|e| keychain_fail(name, e))
+  }
+}
+
+/// Starts every passphrase-encrypted key file, including the format version.
+#[cfg(feature = "crypto-sodium")]
+const MAGIC: &[u8] = b"mesherks\x01";
+
+/// Keeps keys in a single file, encrypted with a key derived from a passphrase.
+///
+/// The key is derived with Argon2id, with libsodium's interactive limits, so opening the file takes a noticeable
+/// fraction of a second, on purpose; load every key needed when starting up, rather than as they're needed.
+/// The file is the format version, the salt, and a libsodium
+/// [secret box](https://libsodium.gitbook.io/doc/secret-key_cryptography/secretbox) holding every name and key.
+/// Saving rewrites the whole file, with a fresh salt, under another name first, so a crash partway through doesn't
+/// lose any keys.
+///
+/// Nothing stops two processes saving to the same file at once, in which case one's key will be lost.
+///
+/// Only available with the `crypto-sodium` feature.
+#[cfg(feature = "crypto-sodium")]
+pub struct PassphraseFile {
+  path: PathBuf,
+  passphrase: Vec<u8>,
+}
+
+#[cfg(feature = "crypto-sodium")]
+impl PassphraseFile {
+  /// Uses the key file at `path`, which doesn't have to exist until a key's saved to it.
+  pub fn new(path: impl Into<PathBuf>, passphrase: &str) -> PassphraseFile {
+    PassphraseFile {
+      path: path.into(),
+      passphrase: passphrase.as_bytes().to_vec(),
+    }
+  }
+
+  /// Derives the file's key from the passphrase and the salt.
+  fn derive(&self, salt: &argon2id13::Salt) -> fail::Result<secretbox::Key> {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    argon2id13::derive_key(
+      &mut key.0,
+      &self.passphrase,
+      salt,
+      argon2id13::OPSLIMIT_INTERACTIVE,
+      argon2id13::MEMLIMIT_INTERACTIVE,
+    )
+    .map_err(|_| keystore_fail("couldn't derive a key from the passphrase".to_owned()))?;
+    Ok(key)
+  }
+
+  /// Reads and decrypts every entry in the file, or none if it doesn't exist yet.
+  fn read_entries(&self) -> fail::Result<Entries> {
+    let file = match fs::read(&self.path) {
+      Ok(file) => file,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Entries(vec![])),
+      Err(e) => return Err(keystore_fail(format!("couldn't read {}: {}", self.path.display(), e))),
+    };
+    let corrupt = || keystore_fail(format!("{} isn't a mesher key file", self.path.display()));
+    let rest = file.strip_prefix(MAGIC).ok_or_else(corrupt)?;
+    if rest.len() < argon2id13::SALTBYTES + secretbox::NONCEBYTES {
+      return Err(corrupt());
+    }
+    let (salt, rest) = rest.split_at(argon2id13::SALTBYTES);
+    let (nonce, sealed) = rest.split_at(secretbox::NONCEBYTES);
+    let key = self.derive(&argon2id13::Salt::from_slice(salt).ok_or_else(corrupt)?)?;
+    let mut plain =
+      secretbox::open(sealed, &secretbox::Nonce::from_slice(nonce).ok_or_else(corrupt)?, &key).map_err(|_| {
+        keystore_fail(format!(
+          "wrong passphrase for {}, or it's been corrupted",
+          self.path.display()
+        ))
+      })?;
+    let entries = Entries::decode(&plain).ok_or_else(corrupt);
+    wipe(&mut plain);
+    entries
+  }
+
+  /// Encrypts and writes every entry, replacing the file.
+  fn write_entries(&self, entries: &Entries) -> fail::Result<()> {
+    let salt = argon2id13::gen_salt();
+    let nonce = secretbox::gen_nonce();
+    let key = self.derive(&salt)?;
+    let mut plain = entries.encode();
+    let sealed = secretbox::seal(&plain, &nonce, &key);
+    wipe(&mut plain);
+
+    let write_fail = |e: io::Error| keystore_fail(format!("couldn't write {}: {}", self.path.display(), e));
+    let mut tmp = self.path.clone().into_os_string();
+    tmp.push(".tmp");
+    // a file left over from a crash could have been made with any permissions, so start afresh
+    match fs::remove_file(&tmp) {
+      Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(write_fail(e)),
+      _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp).map_err(write_fail)?;
+    file
+      .write_all(&[MAGIC, &salt.0, &nonce.0, &sealed].concat())
+      .and_then(|_| file.sync_all())
+      .map_err(write_fail)?;
+    fs::rename(&tmp, &self.path).map_err(write_fail)
+  }
+}
+
+#[cfg(feature = "crypto-sodium")]
+impl KeyStore for PassphraseFile {
+  fn load(&self, name: &str) -> fail::Result<Vec<u8>> {
+    check_name(name)?;
+    self
+      .read_entries()?
+      .0
+      .iter()
+      .find(|(n, _)| n == name)
+      .map(|(_, secret)| secret.clone())
+      .ok_or_else(|| keystore_fail(format!("{} has no key named {:?}", self.path.display(), name)))
+  }
+
+  fn save(&self, name: &str, secret: &[u8]) -> fail::Result<()> {
+    check_name(name)?;
+    if secret.len() > u16::MAX as usize {
+      return Err(keystore_fail(format!("the secret for {:?} is too big to store", name)));
+    }
+    let mut entries = self.read_entries()?;
+    entries.0.retain(|(n, _)| n != name);
+    entries.0.push((name.to_owned(), secret.to_vec()));
+    self.write_entries(&entries)
+  }
+}
+
+#[cfg(feature = "crypto-sodium")]
+impl Drop for PassphraseFile {
+  fn drop(&mut self) {
+    wipe(&mut self.passphrase);
+  }
+}
+
+#[cfg(feature = "crypto-sodium")]
+impl core::fmt::Debug for PassphraseFile {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    f.debug_struct("PassphraseFile")
+      .field("path", &self.path)
+      .finish_non_exhaustive()
+  }
+}
+
+/// The names and keys in a key file, which are wiped when they're dropped.
+#[cfg(feature = "crypto-sodium")]
+struct Entries(Vec<(String, Vec<u8>)>);
+
+#[cfg(feature = "crypto-sodium")]
+impl Entries {
+  /// Encodes each entry as the name's length (one byte), the name, the key's length (two bytes, big-endian), and the
+  /// key.
+  fn encode(&self) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (name, secret) in &self.0 {
+      bytes.push(name.len() as u8);
+      bytes.extend_from_slice(name.as_bytes());
+      bytes.extend_from_slice(&(secret.len() as u16).to_be_bytes());
+      bytes.extend_from_slice(secret);
+    }
+    bytes
+  }
+
+  fn decode(mut bytes: &[u8]) -> Option<Entries> {
+    let mut entries = Entries(vec![]);
+    while let Some((&name_len, rest)) = bytes.split_first() {
+      let name = rest.get(..name_len as usize)?;
+      let rest = &rest[name_len as usize..];
+      let secret_len = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize;
+      let secret = rest.get(2..2 + secret_len)?;
+      entries
+        .0
+        .push((String::from_utf8(name.to_vec()).ok()?, secret.to_vec()));
+      bytes = &rest[2 + secret_len..];
+    }
+    Some(entries)
+  }
+}
+
+#[cfg(feature = "crypto-sodium")]
+impl Drop for Entries {
+  fn drop(&mut self) {
+    for (_, secret) in &mut self.0 {
+      wipe(secret);
+    }
+  }
+}
+
+#[cfg(all(test, feature = "crypto-sodium"))]
+mod tests {
+  use super::*;
+  use rand::prelude::*;
+
+  fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mesher-keystore-{}-{:x}", name, thread_rng().gen::<u32>()))
+  }
+
+  #[test]
+  fn passphrase_files_round_trip() {
+    let path = temp_file("round-trip");
+    let store = PassphraseFile::new(&path, "hunter2");
+    let (pk, sk) = encrypt::gen_keypair();
+    let (spk, ssk) = sign::gen_keypair();
+    store.save("node", sk.as_bytes()).expect("Failed to save");
+    store.save("node.sign", ssk.as_bytes()).expect("Failed to save");
+    let file = fs::read(&path).expect("Failed to read file");
+    assert!(!file.windows(32).any(|w| w == sk.as_bytes()));
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = fs::metadata(&path).expect("Failed to stat file").permissions().mode();
+      assert_eq!(0o600, mode & 0o777);
+    }
+
+    let reopened = PassphraseFile::new(&path, "hunter2");
+    assert_eq!(pk, reopened.encrypt_key("node").expect("Failed to load").public_key());
+    assert_eq!(
+      spk,
+      reopened.sign_key("node.sign").expect("Failed to load").public_key()
+    );
+    assert!(matches!(
+      reopened.sign_key("node"),
+      Err(fail::MesherFail::KeystoreFailure(_))
+    ));
+    assert!(matches!(
+      reopened.load("nobody"),
+      Err(fail::MesherFail::KeystoreFailure(_))
+    ));
+
+    let (pk2, sk2) = encrypt::gen_keypair();
+    reopened.save("node", sk2.as_bytes()).expect("Failed to save");
+    assert_eq!(pk2, store.encrypt_key("node").expect("Failed to load").public_key());
+    assert_eq!(spk, store.sign_key("node.sign").expect("Failed to load").public_key());
+
+    let wrong = PassphraseFile::new(&path, "hunter3");
+    assert!(matches!(wrong.load("node"), Err(fail::MesherFail::KeystoreFailure(_))));
+    assert!(matches!(
+      wrong.save("other", &[1]),
+      Err(fail::MesherFail::KeystoreFailure(_))
+    ));
+    fs::remove_file(&path).expect("Failed to clean up");
+  }
+
+  #[test]
+  fn names_are_checked() {
+    for name in ["", "has space", "semi;colon", "ünïcode", &"x".repeat(65)] {
+      assert!(check_name(name).is_err(), "{:?} was accepted", name);
+    }
+    for name in ["node", "node.sign", "a_b-C9", &"x".repeat(64)] {
+      assert!(check_name(name).is_ok(), "{:?} was rejected", name);
+    }
+    let store = PassphraseFile::new(temp_file("names"), "hunter2");
+    assert!(matches!(
+      store.save("../node", &[1]),
+      Err(fail::MesherFail::KeystoreFailure(_))
+    ));
+  }
+}
@@ -1,3 +1,4 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![warn(clippy::all)]
 #![doc(test(attr(deny(warnings))))]
 
@@ -8,35 +9,195 @@
 //! The mesher API is fairly simple, and based around three pieces, which reflect the concepts described in the README:
 //!
 //! - [`struct Mesher`](struct.Mesher.html) coordinates the rest of the objects, e.g. managing Transports, automatically handling bounces, etc.
+//!   [`struct MesherBuilder`](struct.MesherBuilder.html) can configure one all in one go.
+//!   Meshers can be moved between threads, and [`struct MesherHandle`](struct.MesherHandle.html) can launch packets through one from any thread.
 //! - [`trait Transport`](trait.Transport.html) defines the interface that `Mesher` uses to control Transports.
 //!   If you need them, e.g. for testing, there are debug transports available in [`mesher::debug_transports`](debug_transports/index.html).
+//!   To test several meshers together on an unreliable network, or one on its own with a transport that fails on cue, use [`mesher::testing`](testing/index.html).
+//!   To connect meshes that use different transports, e.g. a LoRa gateway onto TCP, use [`mesher::bridge`](bridge/index.html).
 //! - [`struct Packet`](struct.Packet.html) makes building signed and unsigned packets easier.
+//!   Applications can define their own kinds of chunks to put in them with [`trait CustomChunk`](trait.CustomChunk.html).
+//!   To check another implementation agrees on the packet format, use the vectors in [`mesher::testvectors`](testvectors/index.html).
+//!
+//! With the `config` feature (on by default), meshers can also be set up from a TOML or JSON file, with
+//! [`Mesher::from_config`](struct.Mesher.html#method.from_config) or [`mesher::config`](config/index.html).
+//!
+//! [`struct Path`](struct.Path.html) is the parsed form of the `scheme:location` paths that transports send along and listen on.
+//! [`struct Route`](struct.Route.html) chains nodes' paths and keys together, so packets can be sent through all of them in one call.
 //!
 //! Also worth mentioning are the types in [`mesher::crypto`](crypto/index.html), which encapsulate the manipulation of crypto primitives.
 //! You'll use them to pass keys into `Mesher` and `Packet`.
-//! They do offer secure keygen, but this crate **will not** handle storing keys for you, if you need that.
+//! If you need to swap out the library doing the actual crypto, implement [`trait Crypto`](crypto/trait.Crypto.html), and to keep keys in hardware, see [`mesher::crypto::hardware`](crypto/hardware/index.html).
+//! They do offer secure keygen, and to keep keys in the OS's credential store or a passphrase-encrypted file, rather
+//! than lying around in plain files, see [`mesher::keystore`](keystore/index.html).
 //!
 //! [`struct Message`](struct.Message.html) represents a message received.
+//! Things the mesher does that don't end up in a message, like forwarding packets, can be watched with [`trait MesherEvents`](trait.MesherEvents.html).
+//!
+//! To keep an eye on what a mesher's doing, give it something implementing [`trait Metrics`](metrics/trait.Metrics.html), e.g. [`metrics::Counters`](metrics/struct.Counters.html).
+//!
+//! With the `serde` feature on (which `config` turns on), messages, public keys, paths, routes, and configs can be
+//! serialized and deserialized with [`serde`](https://serde.rs).
+//! Secret keys can be too, but only with the `serde_secret_keys` feature, so they aren't written anywhere by accident.
+//!
+//! With the `tracing` feature on, meshers also emit [`tracing`](https://docs.rs/tracing) spans and events as they handle packets.
+//!
+//! With the `qr` feature on, public keys and routes can be shown and read as QR codes, to exchange them out of band, with [`mesher::qr`](qr/index.html).
+//!
+//! Rather than a fixed list of sender keys, signed meshers can trust any key endorsed by a trust root, and stop trusting revoked keys, with [`mesher::identity`](identity/index.html).
+//!
+//! Meshers can optionally tell each other how to reach them, and keep a table of peers, using [`mesher::discovery`](discovery/index.html).
+//!
+//! To send a file, or any other stream too big for one packet, use [`mesher::transfer`](transfer/index.html).
+//! For requests that expect a response, use [`mesher::rpc`](rpc/index.html).
+//! To check whether a route works, and how far along it packets get, use [`mesher::ping`](ping/index.html).
+//! For long-lived conversations with forward secrecy, e.g. chat, use [`mesher::session`](session/index.html).
+//! To publish to whoever's subscribed to a topic, use [`mesher::pubsub`](pubsub/index.html).
+//! To send one message that every member of a group can read, share a [group key](crypto/group/index.html) with
+//! [`struct Group`](struct.Group.html).
 //!
 //! There is, of course, a [`fail`](fail/index.html) module, with the expected [`enum MesherFail`](fail/enum.MesherFail.html) and [`type Result`](fail/type.Result.html) for this crate's error handling.
+//!
+//! Everything but the packet format and the crypto needs the `std` feature (on by default).
+//! Without it, the crate is `no_std`, though it still needs an allocator, so embedded devices can build and read packets
+//! with [`Packet`](struct.Packet.html), [`Route`](struct.Route.html), and the keys, and move them around however they like.
+//! Packets in the old bincode format (version 1) can only be read with `std`.
+//!
+//! The default crypto backend, which uses libsodium, is behind the `crypto-sodium` feature (also on by default), so
+//! builds for targets libsodium doesn't support can leave it out and bring their own
+//! [backend](crypto/trait.Crypto.html) instead.
+//! [Sessions](session/index.html) and the [passphrase keystore](keystore/struct.PassphraseFile.html) use libsodium
+//! directly, so they're only there with it.
+
+extern crate alloc;
 
 // for transport::debug::InMemory
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate lazy_static;
 
+/// Emits a `tracing` debug event, if the `tracing` feature is on, and does nothing otherwise.
+#[cfg(feature = "std")]
+macro_rules! debug_event {
+  ($($arg:tt)*) => {
+    #[cfg(feature = "tracing")]
+    tracing::debug!($($arg)*);
+  };
+}
+
+/// The parts of the std prelude that come from `alloc`, for the modules which have to build without `std`.
+mod alloc_prelude {
+  pub(crate) use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+  };
+}
+
+#[cfg(feature = "std")]
+pub mod addr;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+#[cfg(feature = "std")]
+pub mod bridge;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod contacts;
 pub mod crypto;
 
+#[cfg(feature = "std")]
 pub mod debug_transports;
+#[cfg(feature = "std")]
+pub mod dht;
+#[cfg(feature = "std")]
+pub mod discovery;
 pub mod fail;
+pub mod identity;
+#[cfg(feature = "std")]
+pub mod keystore;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod ping;
+#[cfg(feature = "std")]
+pub mod pubsub;
+#[cfg(feature = "qr")]
+pub mod qr;
+#[cfg(feature = "std")]
+pub mod rpc;
+#[cfg(all(feature = "std", feature = "crypto-sodium"))]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod testing;
+pub mod testvectors;
+#[cfg(feature = "std")]
+pub mod transfer;
 
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+mod events;
+#[cfg(feature = "std")]
+mod forward;
+#[cfg(feature = "std")]
+mod groups;
+#[cfg(feature = "std")]
+mod handle;
+#[cfg(feature = "std")]
+mod inbound;
+mod mailbox;
+#[cfg(feature = "std")]
 mod mesher;
+#[cfg(feature = "std")]
+mod mix;
+mod onion;
 mod packet;
+mod path;
+#[cfg(feature = "std")]
+mod queue;
+#[cfg(feature = "std")]
+mod quota;
+#[cfg(feature = "std")]
+mod ratelimit;
+mod reply_block;
+mod route;
+#[cfg(feature = "std")]
+mod schedule;
+#[cfg(feature = "serde")]
+mod serde_impls;
+#[cfg(feature = "std")]
 mod transport;
 
 pub use crate::{
-  mesher::{Mesher, Message},
-  packet::Packet,
-  transport::Transport,
+  onion::OnionBuilder,
+  packet::{
+    ChunkDump, CustomChunk, MessageId, Packet, PacketDump, ParseError, ParsedPacket, ReceiptId, ReplyPathHandle,
+  },
+  path::{Path, PathOptions},
+  reply_block::{ReplyBlock, ReplyBlockId},
+  route::Route,
+};
+
+#[cfg(feature = "std")]
+pub use crate::{
+  builder::MesherBuilder,
+  events::MesherEvents,
+  forward::ForwardPolicy,
+  groups::{Group, GroupInvite},
+  handle::MesherHandle,
+  inbound::{InboundQueue, Overflow},
+  mailbox::Mailbox,
+  mesher::{DeliveryStatus, ListenHandle, Mesher, Message, TamperPolicy},
+  mix::MixPolicy,
+  queue::OutboundQueue,
+  quota::{Quota, Usage},
+  ratelimit::RateLimit,
+  transport::{ListenStatus, Priority, Source, Transport},
 };
 
 pub mod prelude {
@@ -48,5 +209,7 @@ pub mod prelude {
   //! use mesher::prelude::*;
   //! ```
 
-  pub use crate::{crypto::*, fail, Mesher, Message, Packet, Transport};
+  pub use crate::{crypto::*, fail, Packet, Path};
+  #[cfg(feature = "std")]
+  pub use crate::{Mesher, Message, Source, Transport};
 }
@@ -0,0 +1,295 @@
+//! Contains the mailbox relays can keep for recipients who aren't online, and the messages used to collect from it.
+
+use crate::{alloc_prelude::*, packet::take, prelude::*};
+
+#[cfg(feature = "std")]
+use rand::prelude::*;
+
+#[cfg(feature = "std")]
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
+
+/// The scheme of the paths that deposit packets in a relay's mailbox.
+#[cfg(feature = "std")]
+pub(crate) const MAILBOX_SCHEME: &str = "mailbox";
+
+/// How long a relay waits for a recipient to answer a challenge before forgetting about it.
+#[cfg(feature = "std")]
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The most challenges a relay will wait on answers to at once; requests past this are ignored.
+#[cfg(feature = "std")]
+const MAX_CHALLENGES: usize = 64;
+
+/// Packets a relay is holding on to for recipients who can't receive them yet.
+///
+/// Senders deposit a packet by adding a hop for the relay along the recipient's [`path`](#method.path), e.g.:
+///
+/// ```
+/// # use mesher::{prelude::*, Mailbox};
+/// # let (relay_pk, _) = encrypt::gen_keypair();
+/// # let (recipient_pk, _) = encrypt::gen_keypair();
+/// # let (recipient_sign_pk, _) = sign::gen_keypair();
+/// let mut packet = Packet::unsigned();
+/// packet.add_hop(Mailbox::path(&recipient_sign_pk), &relay_pk);
+/// packet.add_message(b"hello", &recipient_pk);
+/// ```
+///
+/// The relay keeps the whole packet, and the recipient collects it later with
+/// [`Mesher::request_mail`](struct.Mesher.html#method.request_mail), proving it owns the signing key by signing a
+/// random challenge from the relay.
+/// Collected packets are sent to the path the recipient asked for, and processed by its mesher like any other.
+///
+/// Only recipients that have been [registered](#method.register) get a mailbox, so strangers can't use the relay as free
+/// storage, and each one holds at most [`max_packets`](#method.max_packets) packets.
+/// Mail is only kept in memory, so it's lost if the relay restarts.
+#[cfg(feature = "std")]
+pub struct Mailbox {
+  recipients: HashMap<sign::PublicKey, Vec<Vec<u8>>>,
+  max_packets: usize,
+  challenges: HashMap<u64, Challenge>,
+}
+
+/// A challenge a relay has sent, waiting to be answered.
+#[cfg(feature = "std")]
+struct Challenge {
+  recipient: sign::PublicKey,
+  challenge: [u8; 32],
+  return_path: String,
+  sent: Instant,
+}
+
+#[cfg(feature = "std")]
+impl Default for Mailbox {
+  fn default() -> Mailbox {
+    Mailbox {
+      recipients: HashMap::new(),
+      max_packets: 256,
+      challenges: HashMap::new(),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl Mailbox {
+  /// Creates a mailbox with no recipients registered, which holds up to 256 packets per recipient.
+  pub fn new() -> Mailbox {
+    Mailbox::default()
+  }
+
+  /// Holds packets for the recipient with the given signing key.
+  pub fn register(mut self, recipient: sign::PublicKey) -> Mailbox {
+    self.recipients.entry(recipient).or_default();
+    self
+  }
+
+  /// Holds up to `max` packets per recipient; packets deposited past that are rejected.
+  pub fn max_packets(mut self, max: usize) -> Mailbox {
+    self.max_packets = max;
+    self
+  }
+
+  /// How many packets are waiting for the given recipient.
+  pub fn held(&self, recipient: &sign::PublicKey) -> usize {
+    self.recipients.get(recipient).map_or(0, Vec::len)
+  }
+
+  /// The path to deposit packets for the given recipient along, from the relay holding its mail.
+  pub fn path(recipient: &sign::PublicKey) -> String {
+    format!("{}:{}", MAILBOX_SCHEME, recipient)
+  }
+
+  /// Holds a packet for the recipient in the given mailbox path, if it's registered and has room.
+  pub(crate) fn deposit(&mut self, path: &str, packet: Vec<u8>) -> bool {
+    let recipient = match path_recipient(path) {
+      Some(r) => r,
+      None => return false,
+    };
+    match self.recipients.get_mut(&recipient) {
+      Some(held) if held.len() < self.max_packets => {
+        held.push(packet);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  /// Starts a challenge for a request, if the recipient's registered, returning the challenge to send it.
+  pub(crate) fn challenge(&mut self, id: u64, recipient: sign::PublicKey, return_path: String) -> Option<[u8; 32]> {
+    if !self.recipients.contains_key(&recipient) {
+      return None;
+    }
+    self.challenges.retain(|_, c| c.sent.elapsed() < CHALLENGE_TIMEOUT);
+    if self.challenges.len() >= MAX_CHALLENGES {
+      return None;
+    }
+    let mut challenge = [0; 32];
+    thread_rng().fill(&mut challenge);
+    self.challenges.insert(
+      id,
+      Challenge {
+        recipient,
+        challenge,
+        return_path,
+        sent: Instant::now(),
+      },
+    );
+    Some(challenge)
+  }
+
+  /// Checks an answer to a challenge, returning the recipient's mail and where to send it if it's right.
+  ///
+  /// Either way, the challenge is used up.
+  pub(crate) fn claim(
+    &mut self,
+    id: u64,
+    recipient: &sign::PublicKey,
+    signed: &[u8],
+    crypto: &dyn Crypto,
+  ) -> Option<(String, Vec<Vec<u8>>)> {
+    let challenge = self.challenges.remove(&id)?;
+    if &challenge.recipient != recipient || challenge.sent.elapsed() >= CHALLENGE_TIMEOUT {
+      return None;
+    }
+    if !crate::crypto::ct_eq(&crypto.verify(signed, recipient)?, &challenge.challenge) {
+      return None;
+    }
+    let mail = std::mem::take(self.recipients.get_mut(recipient)?);
+    Some((challenge.return_path, mail))
+  }
+}
+
+/// Gets the recipient out of a mailbox path, if it's well-formed.
+#[cfg(feature = "std")]
+fn path_recipient(path: &str) -> Option<sign::PublicKey> {
+  path.strip_prefix(MAILBOX_SCHEME)?.strip_prefix(':')?.parse().ok()
+}
+
+/// One step of collecting mail from a relay.
+#[derive(Debug, PartialEq)]
+pub(crate) enum MailMessage {
+  /// A recipient asking for its mail, with the request ID, the recipient's keys, and where to send the challenge and mail.
+  Request(u64, sign::PublicKey, encrypt::PublicKey, String),
+  /// A relay's challenge for a request.
+  Challenge(u64, [u8; 32]),
+  /// A recipient's answer to a challenge, i.e. the challenge signed with its key.
+  Claim(u64, sign::PublicKey, Vec<u8>),
+}
+
+impl MailMessage {
+  /// Encodes the message as a kind byte, the request ID, then the rest of its fields, with the return path last.
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  pub(crate) fn encode(&self) -> Vec<u8> {
+    let mut b = vec![];
+    match self {
+      MailMessage::Request(id, sign_pk, encrypt_pk, path) => {
+        b.push(0);
+        b.extend_from_slice(&id.to_be_bytes());
+        b.extend_from_slice(sign_pk.as_bytes());
+        b.extend_from_slice(encrypt_pk.as_bytes());
+        b.extend_from_slice(path.as_bytes());
+      }
+      MailMessage::Challenge(id, challenge) => {
+        b.push(1);
+        b.extend_from_slice(&id.to_be_bytes());
+        b.extend_from_slice(challenge);
+      }
+      MailMessage::Claim(id, sign_pk, signed) => {
+        b.push(2);
+        b.extend_from_slice(&id.to_be_bytes());
+        b.extend_from_slice(sign_pk.as_bytes());
+        b.extend_from_slice(signed);
+      }
+    }
+    b
+  }
+
+  /// Decodes a message written by [`encode`](#method.encode), if it's well-formed.
+  pub(crate) fn decode(mut from: &[u8]) -> Option<MailMessage> {
+    let kind = take(&mut from, 1)?[0];
+    let mut id = [0; 8];
+    id.copy_from_slice(take(&mut from, 8)?);
+    let id = u64::from_be_bytes(id);
+    match kind {
+      0 => {
+        let sign_pk = sign::PublicKey::from_slice(take(&mut from, 32)?)?;
+        let encrypt_pk = encrypt::PublicKey::from_slice(take(&mut from, 32)?)?;
+        let path = String::from_utf8(from.to_vec()).ok()?;
+        Some(MailMessage::Request(id, sign_pk, encrypt_pk, path))
+      }
+      1 => {
+        let mut challenge = [0; 32];
+        challenge.copy_from_slice(take(&mut from, 32)?);
+        if !from.is_empty() {
+          return None;
+        }
+        Some(MailMessage::Challenge(id, challenge))
+      }
+      2 => {
+        let sign_pk = sign::PublicKey::from_slice(take(&mut from, 32)?)?;
+        Some(MailMessage::Claim(id, sign_pk, from.to_vec()))
+      }
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn messages_roundtrip() {
+    let (sign_pk, _) = sign::gen_keypair();
+    let (encrypt_pk, _) = encrypt::gen_keypair();
+    let messages = [
+      MailMessage::Request(1, sign_pk, encrypt_pk, "inmem:home".to_owned()),
+      MailMessage::Challenge(u64::MAX, [7; 32]),
+      MailMessage::Claim(3, sign_pk, vec![1, 2, 3]),
+    ];
+    for m in &messages {
+      assert_eq!(Some(m), MailMessage::decode(&m.encode()).as_ref());
+    }
+    assert_eq!(None, MailMessage::decode(&[1, 0, 0]));
+    assert_eq!(None, MailMessage::decode(&[9, 0, 0, 0, 0, 0, 0, 0, 0]));
+  }
+
+  #[test]
+  fn only_registered_with_room() {
+    let (registered, _) = sign::gen_keypair();
+    let (stranger, _) = sign::gen_keypair();
+    let mut mailbox = Mailbox::new().register(registered).max_packets(1);
+    assert!(mailbox.deposit(&Mailbox::path(&registered), vec![1]));
+    assert!(!mailbox.deposit(&Mailbox::path(&registered), vec![2]));
+    assert!(!mailbox.deposit(&Mailbox::path(&stranger), vec![3]));
+    assert!(!mailbox.deposit("mailbox:nonsense", vec![4]));
+    assert_eq!(1, mailbox.held(&registered));
+    assert_eq!(None, mailbox.challenge(1, stranger, "inmem:home".to_owned()));
+  }
+
+  #[test]
+  fn claims_checked() {
+    let crypto = crate::crypto::default_backend();
+    let (pk, sk) = sign::gen_keypair();
+    let (_, other_sk) = sign::gen_keypair();
+    let mut mailbox = Mailbox::new().register(pk);
+    assert!(mailbox.deposit(&Mailbox::path(&pk), vec![1]));
+
+    let challenge = mailbox.challenge(1, pk, "inmem:home".to_owned()).expect("No challenge");
+    let forged = crypto.sign(&challenge, &other_sk);
+    assert_eq!(None, mailbox.claim(1, &pk, &forged, crypto.as_ref()));
+    // the challenge is used up, even by a wrong answer
+    let signed = crypto.sign(&challenge, &sk);
+    assert_eq!(None, mailbox.claim(1, &pk, &signed, crypto.as_ref()));
+
+    let challenge = mailbox.challenge(2, pk, "inmem:home".to_owned()).expect("No challenge");
+    let signed = crypto.sign(&challenge, &sk);
+    assert_eq!(
+      Some(("inmem:home".to_owned(), vec![vec![1]])),
+      mailbox.claim(2, &pk, &signed, crypto.as_ref())
+    );
+    assert_eq!(0, mailbox.held(&pk));
+  }
+}
@@ -1,13 +1,57 @@
 //! Contains all the relevant bits and pieces for meshers themselves.
 
-use crate::prelude::*;
-use std::{collections::HashMap, sync::Arc};
+use crate::{
+  crypto::Crypto,
+  discovery::{self, Discovery, Peer},
+  identity::{Endorsement, Revocation, RevocationStore},
+  mailbox::{MailMessage, MAILBOX_SCHEME},
+  metrics::{DropReason, Metrics},
+  mix::MixPool,
+  prelude::*,
+  quota::Meter,
+  ratelimit::{Buckets, TokenBucket},
+  reply_block::{self, ReplyStep},
+  schedule::{Schedule, Scheduled},
+  transport::check_supported,
+  CustomChunk, ForwardPolicy, GroupInvite, InboundQueue, ListenStatus, Mailbox, MesherBuilder, MesherEvents,
+  MesherHandle, MessageId, MixPolicy, OutboundQueue, Priority, Quota, RateLimit, ReceiptId, ReplyBlock, ReplyBlockId,
+  Route, Usage,
+};
+use std::{
+  collections::{hash_map::RandomState, BTreeMap, HashMap, VecDeque},
+  hash::BuildHasher,
+  sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc,
+  },
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+/// How long [`Mesher::receive_wait`](struct.Mesher.html#method.receive_wait) waits between receives.
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Decodes a custom chunk's bytes and passes the result on to the application's handler.
+type ChunkHandler = Box<dyn FnMut(&[u8]) + Send>;
 
 /// Represents a single message received by a mesher.
+///
+/// With the `serde` feature, messages can be serialized, e.g. to be stored or handed to another process, and
+/// deserialized later with their reply paths intact, so they can still be [replied to](struct.Packet.html#method.reply_to).
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
   contents: Vec<u8>,
-  pub(crate) reply_path: Option<Arc<Vec<Vec<u8>>>>,
+  pub(crate) reply_path: Option<crate::packet::ReplyPath>,
+  id: Option<MessageId>,
+  #[cfg_attr(feature = "serde", serde(default))]
+  source: Option<Source>,
+  #[cfg_attr(feature = "serde", serde(default))]
+  group: Option<group::GroupId>,
+  #[cfg_attr(feature = "serde", serde(default))]
+  sender: Option<sign::PublicKey>,
+  #[cfg_attr(feature = "serde", serde(default))]
+  reply_block: Option<ReplyBlockId>,
 }
 
 impl Message {
@@ -21,10 +65,90 @@ impl Message {
     self.contents
   }
 
+  /// The message's ID, if the sender included one. Only very old versions of mesher don't.
+  pub fn id(&self) -> Option<MessageId> {
+    self.id
+  }
+
   /// Whether or not this message was sent with a reply path for it to follow.
   pub fn has_reply_path(&self) -> bool {
     self.reply_path.is_some()
   }
+
+  /// Where the packet carrying this message came from, or `None` if this mesher launched it itself, rather than
+  /// receiving it from a transport.
+  pub fn source(&self) -> Option<&Source> {
+    self.source.as_ref()
+  }
+
+  /// The group this message was [sent to](struct.Packet.html#method.add_group_message), or `None` if it was sent
+  /// just to this mesher.
+  pub fn group(&self) -> Option<group::GroupId> {
+    self.group
+  }
+
+  /// The key of whoever [claimed to send](struct.Packet.html#method.claim_sender) this message, if they did, and the
+  /// claim's signature checks out.
+  ///
+  /// That only says the key's owner sent this message to this mesher; whether to trust them is up to the application.
+  pub fn verified_sender(&self) -> Option<sign::PublicKey> {
+    self.sender
+  }
+
+  /// The [reply block](struct.ReplyBlock.html) this message came back through, if it's a reply sent with
+  /// [`Mesher::send_reply`](struct.Mesher.html#method.send_reply).
+  pub fn reply_block(&self) -> Option<ReplyBlockId> {
+    self.reply_block
+  }
+}
+
+/// What a [`Mesher`](struct.Mesher.html) does with a packet containing chunks that were [tampered with](fail/enum.MesherFail.html#variant.Tampered).
+///
+/// Either way, each tampered chunk is reported to the [failure handler](struct.Mesher.html#method.on_failure) and otherwise ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TamperPolicy {
+  /// Handle the rest of the packet as normal.
+  #[default]
+  Skip,
+  /// Drop the whole packet: don't forward it or return any of its messages.
+  DropPacket,
+}
+
+/// Whether a packet has been delivered, as far as its sender can tell from [receipts](struct.ReplyPathHandle.html#method.use_for_receipt).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+  /// No receipt has come back yet.
+  ///
+  /// Mesher doesn't retry anything, so this is also the status of receipts that will never come back, and of ones the
+  /// mesher isn't keeping track of, e.g. because they've been forgotten or it didn't launch the packet asking for them.
+  Pending,
+  /// The receipt has come back.
+  Delivered,
+  /// A relay couldn't forward the packet, and sent back a [failure notice](struct.ReplyPathHandle.html#method.use_for_failure_notice)
+  /// saying so.
+  Failed,
+}
+
+/// Refers to a single path a [`Mesher`](struct.Mesher.html) is listening on, so it can be stopped later.
+///
+/// Dropping the handle does **not** stop listening; the mesher will keep listening until it's explicitly told to stop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenHandle {
+  path: Path,
+}
+
+impl ListenHandle {
+  /// The path being listened on.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Has the mesher stop listening on this path.
+  ///
+  /// This should be the same mesher whose [`listen_on`](struct.Mesher.html#method.listen_on) returned the handle.
+  pub fn stop(self, mesher: &mut Mesher) -> fail::Result<()> {
+    mesher.get_transport_for_path(&self.path)?.unlisten(&self.path)
+  }
 }
 
 /// The control interface for a single mesher.
@@ -32,29 +156,154 @@ impl Message {
 /// One important thing to note is that the Mesher struct **only** stores keys during runtime.
 /// It does not manage them in any other way, e.g. keeping them securely on-disk, transmitting them securely to the computer, etc.
 /// (However, you could well use messages passed through mesher to handle some of it.)
+///
+/// Meshers are `Send`, so they can be moved to a worker thread, but not `Sync`.
+/// To launch packets through one from other threads, use a [`MesherHandle`](struct.MesherHandle.html).
 pub struct Mesher {
-  transports: HashMap<String, Box<dyn Transport>>,
+  /// Sorted by scheme, so they're always received from in the same order.
+  transports: BTreeMap<String, Box<dyn Transport>>,
+  /// The other schemes transports were [added for](#method.add_transport_for_schemes), and the first one, which
+  /// they're kept under.
+  aliases: BTreeMap<String, String>,
+  /// Packets received by each transport but held back by the receive limit, by scheme.
+  backlog: BTreeMap<String, VecDeque<(Source, Vec<u8>)>>,
+  receive_limit: Option<usize>,
+  max_buffered: Option<usize>,
+  inbound_queue: Option<InboundQueue>,
+  inbound_dropped: HashMap<String, u64>,
   own_skeys: Vec<encrypt::SecretKey>,
+  retiring: Vec<(encrypt::PublicKey, Instant)>,
+  /// The newest key of each group this mesher is in.
+  groups: Vec<group::GroupKey>,
+  /// Who new keys for each group are taken from without asking, i.e. who sent the invite that was accepted.
+  group_managers: HashMap<group::GroupId, sign::PublicKey>,
+  /// Group keys waiting for the app to accept them, oldest first.
+  group_invites: VecDeque<GroupInvite>,
+  #[cfg(feature = "pq-hybrid")]
+  hybrid_skeys: Vec<hybrid::SecretKey>,
+  signed: bool,
   sender_pkeys: Vec<sign::PublicKey>,
+  trust_roots: Vec<sign::PublicKey>,
+  /// Endorsements by the trust roots, whose subjects are accepted as sender keys.
+  endorsements: Vec<Endorsement>,
+  revocations: RevocationStore,
+  failure_handler: Option<Box<dyn FnMut(fail::MesherFail) + Send>>,
+  crypto: Arc<dyn Crypto>,
+  tamper_policy: TamperPolicy,
+  chunk_handlers: HashMap<u16, ChunkHandler>,
+  /// The receipts asked for by packets the mesher's launched, and when it launched them.
+  delivered: HashMap<ReceiptId, (DeliveryStatus, Instant)>,
+  dedup_window: Option<Duration>,
+  seen: HashMap<MessageId, Instant>,
+  discovery: Option<Discovery>,
+  last_announced: Option<Instant>,
+  peers: HashMap<encrypt::PublicKey, Peer>,
+  signing_key: Option<sign::SecretKey>,
+  mix_pool: Option<MixPool>,
+  /// Packets launched for later, and forwarded packets being held until their senders asked.
+  schedule: Schedule,
+  max_hold: Option<Duration>,
+  clock_skew: Duration,
+  rate_limit: Option<RateLimit>,
+  buckets: Buckets,
+  rate_limited: HashMap<String, u64>,
+  /// Usage and quotas, by scheme.
+  meters: HashMap<String, Meter>,
+  metrics: Option<Arc<dyn Metrics>>,
+  event_handler: Option<Box<dyn MesherEvents>>,
+  /// Each listened-on path's status as of the last receive, to tell the event handler when they change.
+  listen_statuses: HashMap<String, ListenStatus>,
+  forward_policy: Option<ForwardPolicy>,
+  forward_bucket: Option<TokenBucket>,
+  loop_window: Option<Duration>,
+  /// Hashes of the packets recently forwarded and where they went, keyed with `loop_hasher` so they can't be predicted.
+  forwarded: HashMap<u64, Instant>,
+  loop_hasher: RandomState,
+  queue: Option<OutboundQueue>,
+  mailbox: Option<Mailbox>,
+  /// The relays this mesher has asked for its mail, by request ID, so it knows where to send the answers to their
+  /// challenges and what to sign them with.
+  mail_requests: HashMap<u64, (encrypt::PublicKey, String, sign::SecretKey)>,
+  /// The keys replies through this mesher's unused reply blocks are encrypted for.
+  reply_keys: HashMap<ReplyBlockId, encrypt::SecretKey>,
+  max_packet_size: Option<usize>,
+  key_hints: bool,
+  /// Packets launched through [handles](struct.MesherHandle.html), waiting for the next `receive`.
+  launches: (Sender<Launch>, Receiver<Launch>),
+}
+
+/// A packet launched through a [handle](struct.MesherHandle.html), with the priority to launch it with.
+pub(crate) type Launch = (Packet, Priority);
+
+/// Groups packets by the path they're going along, keeping the paths in the order they first appear.
+fn by_path<T>(packets: impl IntoIterator<Item = (String, T)>) -> Vec<(String, Vec<T>)> {
+  let mut groups: Vec<(String, Vec<T>)> = vec![];
+  for (path, packet) in packets {
+    match groups.iter_mut().find(|(p, _)| *p == path) {
+      Some((_, group)) => group.push(packet),
+      None => groups.push((path, vec![packet])),
+    }
+  }
+  groups
 }
 
 impl Mesher {
+  /// The largest packet, in bytes, meshers send or accept unless they're told otherwise with
+  /// [`set_max_packet_size`](#method.set_max_packet_size): 16 MiB.
+  pub const DEFAULT_MAX_PACKET_SIZE: usize = 16 * 1024 * 1024;
+
+  /// How far meshers allow for their clocks being off from senders' when checking whether packets have
+  /// [expired](struct.Packet.html#method.add_expiry), unless they're told otherwise with
+  /// [`set_clock_skew`](#method.set_clock_skew): 5 minutes.
+  pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+  /// The most [group invites](#method.take_group_invites) held at once; the oldest are dropped to make room.
+  pub const MAX_GROUP_INVITES: usize = 64;
+
+  /// The most [receipts](struct.ReplyPathHandle.html#method.use_for_receipt) kept track of at once; the oldest are
+  /// forgotten to make room.
+  pub const MAX_RECEIPTS: usize = 4096;
+
+  /// How long receipts are kept track of after the packets asking for them are launched, whether or not they've come
+  /// back: an hour.
+  pub const RECEIPT_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
   /// Creates a mesher which expects incoming messages to be signed with one of the given keys.
   ///
   /// Note that there are no (explicit) markers to differentiate between signed and unsigned meshers' packets.
   /// Signed meshers will expect their incoming packets to have signatures; unsigned meshers won't.
   /// If a signing mesher receives an unsigned packet or vice versa, it'll be a no-op.
-  pub fn signed(own_skeys: Vec<encrypt::SecretKey>, sender_pkeys: Vec<sign::PublicKey>) -> Mesher {
-    assert!(
-      !sender_pkeys.is_empty(),
-      "Provide sender keys. If you don't want any, use Mesher::unsigned instead."
-    );
+  ///
+  /// Fails with [`MesherFail::NoSenderKeys`](fail/enum.MesherFail.html#variant.NoSenderKeys) if `sender_pkeys` is empty.
+  /// If you don't want any, use [`Mesher::unsigned`](#method.unsigned) instead.
+  pub fn signed(own_skeys: Vec<encrypt::SecretKey>, sender_pkeys: Vec<sign::PublicKey>) -> fail::Result<Mesher> {
+    if sender_pkeys.is_empty() {
+      return Err(fail::MesherFail::NoSenderKeys);
+    }
 
-    Mesher {
-      transports: HashMap::new(),
-      own_skeys,
+    Ok(Mesher {
+      signed: true,
       sender_pkeys,
+      ..Mesher::unsigned(own_skeys)
+    })
+  }
+
+  /// Creates a mesher which accepts packets signed by any key one of the trust roots has
+  /// [endorsed](identity/struct.Endorsement.html), rather than a fixed list of sender keys.
+  ///
+  /// Fails with [`MesherFail::NoSenderKeys`](fail/enum.MesherFail.html#variant.NoSenderKeys) if `trust_roots` is empty.
+  /// Packets signed by the roots themselves aren't accepted unless they're also added as
+  /// [sender keys](#method.add_sender_key).
+  pub fn endorsed(own_skeys: Vec<encrypt::SecretKey>, trust_roots: Vec<sign::PublicKey>) -> fail::Result<Mesher> {
+    if trust_roots.is_empty() {
+      return Err(fail::MesherFail::NoSenderKeys);
     }
+
+    Ok(Mesher {
+      signed: true,
+      trust_roots,
+      ..Mesher::unsigned(own_skeys)
+    })
   }
 
   /// Creates a mesher which doesn't sign its outgoing messages.
@@ -65,25 +314,403 @@ impl Mesher {
   /// If a signing mesher receives an unsigned packet or vice versa, it'll be a no-op.
   pub fn unsigned(own_skeys: Vec<encrypt::SecretKey>) -> Mesher {
     Mesher {
-      transports: HashMap::new(),
+      transports: BTreeMap::new(),
+      aliases: BTreeMap::new(),
+      backlog: BTreeMap::new(),
+      receive_limit: None,
+      max_buffered: None,
+      inbound_queue: None,
+      inbound_dropped: HashMap::new(),
       own_skeys,
+      retiring: vec![],
+      groups: vec![],
+      group_managers: HashMap::new(),
+      group_invites: VecDeque::new(),
+      #[cfg(feature = "pq-hybrid")]
+      hybrid_skeys: vec![],
+      signed: false,
       sender_pkeys: vec![],
+      trust_roots: vec![],
+      endorsements: vec![],
+      revocations: RevocationStore::new(),
+      failure_handler: None,
+      crypto: crate::crypto::default_backend(),
+      tamper_policy: TamperPolicy::default(),
+      chunk_handlers: HashMap::new(),
+      delivered: HashMap::new(),
+      dedup_window: None,
+      seen: HashMap::new(),
+      discovery: None,
+      last_announced: None,
+      peers: HashMap::new(),
+      signing_key: None,
+      mix_pool: None,
+      schedule: Schedule::default(),
+      max_hold: None,
+      clock_skew: Mesher::DEFAULT_CLOCK_SKEW,
+      rate_limit: None,
+      buckets: Buckets::new(),
+      rate_limited: HashMap::new(),
+      meters: HashMap::new(),
+      metrics: None,
+      event_handler: None,
+      listen_statuses: HashMap::new(),
+      forward_policy: None,
+      forward_bucket: None,
+      loop_window: None,
+      forwarded: HashMap::new(),
+      loop_hasher: RandomState::new(),
+      queue: None,
+      mailbox: None,
+      mail_requests: HashMap::new(),
+      reply_keys: HashMap::new(),
+      max_packet_size: Some(Mesher::DEFAULT_MAX_PACKET_SIZE),
+      key_hints: false,
+      launches: channel(),
+    }
+  }
+
+  /// Adds a secret key to decrypt incoming packets with, alongside the ones the mesher already has.
+  ///
+  /// Adding a key that the mesher already has does nothing.
+  /// If the key was being retired, it's no longer going to be.
+  pub fn add_own_key(&mut self, skey: encrypt::SecretKey) {
+    let pkey = self.crypto.encrypt_public_key(&skey);
+    self.retiring.retain(|(k, _)| k != &pkey);
+    if !self.has_own_key(&pkey) {
+      self.own_skeys.push(skey);
+    }
+  }
+
+  /// Adds a [hybrid](crypto/hybrid/index.html) secret key, to decrypt chunks sent with
+  /// [`Packet::add_hybrid_message`](struct.Packet.html#method.add_hybrid_message).
+  ///
+  /// Adding a key that the mesher already has does nothing.
+  #[cfg(feature = "pq-hybrid")]
+  pub fn add_hybrid_key(&mut self, skey: hybrid::SecretKey) {
+    if !self.hybrid_skeys.iter().any(|k| k.public_key() == skey.public_key()) {
+      self.hybrid_skeys.push(skey);
+    }
+  }
+
+  /// All of the keys this mesher opens chunks with.
+  fn own_keys(&self) -> crate::packet::OwnKeys<'_> {
+    let keys = crate::packet::OwnKeys::new(&self.own_skeys, &self.groups);
+    #[cfg(feature = "pq-hybrid")]
+    let keys = keys.hybrid(&self.hybrid_skeys);
+    keys
+  }
+
+  /// Schedules one of the mesher's own secret keys, identified by its public key, to be removed after a grace period.
+  /// Returns whether the mesher had that key.
+  ///
+  /// Until the grace period is up, packets encrypted for the key will still be decrypted as normal, so senders have time to switch to the new key.
+  /// Retiring a key that's already being retired resets the grace period.
+  pub fn retire_own_key(&mut self, pkey: &encrypt::PublicKey, after: Duration) -> bool {
+    if !self.has_own_key(pkey) {
+      return false;
+    }
+    self.retiring.retain(|(k, _)| k != pkey);
+    self.retiring.push((*pkey, Instant::now() + after));
+    true
+  }
+
+  /// Removes any keys whose retirement grace period is up.
+  fn drop_retired_keys(&mut self) {
+    let now = Instant::now();
+    let (expired, retiring) = self.retiring.drain(..).partition(|(_, at)| *at <= now);
+    self.retiring = retiring;
+    if expired.is_empty() {
+      return;
+    }
+    let expired: Vec<_> = expired.into_iter().map(|(k, _)| k).collect();
+    let crypto = &self.crypto;
+    self
+      .own_skeys
+      .retain(|k| !expired.contains(&crypto.encrypt_public_key(k)));
+  }
+
+  /// Whether one of the mesher's own secret keys matches `pkey`.
+  fn has_own_key(&self, pkey: &encrypt::PublicKey) -> bool {
+    self
+      .own_skeys
+      .iter()
+      .any(|k| &self.crypto.encrypt_public_key(k) == pkey)
+  }
+
+  /// Sets the crypto backend used to decrypt and verify incoming packets.
+  ///
+  /// Note that this doesn't affect packets passed to [`launch`](#method.launch); they use whatever backend they were created with.
+  pub fn set_crypto(&mut self, crypto: Arc<dyn Crypto>) {
+    self.crypto = crypto;
+  }
+
+  /// Sets what happens to packets with [tampered](fail/enum.MesherFail.html#variant.Tampered) chunks in them.
+  ///
+  /// Defaults to [`TamperPolicy::Skip`](enum.TamperPolicy.html#variant.Skip).
+  pub fn set_tamper_policy(&mut self, policy: TamperPolicy) {
+    self.tamper_policy = policy;
+  }
+
+  /// Adds a key which incoming packets can be signed with, so packets from that sender are accepted from now on.
+  ///
+  /// Adding a sender key to an unsigned mesher makes it a signed one, and it'll stop accepting unsigned packets.
+  /// Adding a key that's already trusted does nothing.
+  pub fn add_sender_key(&mut self, pkey: sign::PublicKey) {
+    self.signed = true;
+    if !self.sender_pkeys.contains(&pkey) {
+      self.sender_pkeys.push(pkey);
+    }
+  }
+
+  /// Stops trusting a sender key, so packets signed only by it are ignored from now on.
+  /// Returns whether the key was trusted before.
+  ///
+  /// A signed mesher stays signed even if every sender key is removed; it just won't accept any packets until more are added.
+  pub fn remove_sender_key(&mut self, pkey: &sign::PublicKey) -> bool {
+    let before = self.sender_pkeys.len();
+    self.sender_pkeys.retain(|k| k != pkey);
+    self.sender_pkeys.len() != before
+  }
+
+  /// The keys which incoming packets are currently allowed to be signed with.
+  ///
+  /// This is always empty for unsigned meshers.
+  /// Keys accepted because they were [endorsed](#method.add_endorsement) aren't included.
+  pub fn sender_keys(&self) -> &[sign::PublicKey] {
+    &self.sender_pkeys
+  }
+
+  /// Adds a trust root, so packets signed by any key it's [endorsed](identity/struct.Endorsement.html) are accepted
+  /// from now on.
+  ///
+  /// Like [`add_sender_key`](#method.add_sender_key), this makes an unsigned mesher a signed one.
+  /// Adding a root that's already trusted does nothing.
+  pub fn add_trust_root(&mut self, pkey: sign::PublicKey) {
+    self.signed = true;
+    if !self.trust_roots.contains(&pkey) {
+      self.trust_roots.push(pkey);
+    }
+  }
+
+  /// Stops trusting a trust root, and every key it's endorsed. Returns whether the root was trusted before.
+  pub fn remove_trust_root(&mut self, pkey: &sign::PublicKey) -> bool {
+    self.endorsements.retain(|e| e.endorser() != pkey);
+    let before = self.trust_roots.len();
+    self.trust_roots.retain(|k| k != pkey);
+    self.trust_roots.len() != before
+  }
+
+  /// The keys whose endorsements are currently trusted.
+  pub fn trust_roots(&self) -> &[sign::PublicKey] {
+    &self.trust_roots
+  }
+
+  /// Accepts packets signed by the endorsement's subject from now on, if it's validly signed by one of the
+  /// [trust roots](#method.add_trust_root).
+  ///
+  /// Endorsements [sent in packets](struct.Packet.html#method.add_endorsement) are added automatically.
+  /// Fails with [`MesherFail::BadEndorsement`](fail/enum.MesherFail.html#variant.BadEndorsement) if the endorser isn't
+  /// a trust root or the signature doesn't check out.
+  pub fn add_endorsement(&mut self, endorsement: Endorsement) -> fail::Result<()> {
+    if !self.trust_roots.contains(endorsement.endorser()) || !endorsement.verify_using(self.crypto.as_ref()) {
+      return Err(fail::MesherFail::BadEndorsement);
+    }
+    if !self.endorsements.contains(&endorsement) {
+      self.endorsements.push(endorsement);
+    }
+    Ok(())
+  }
+
+  /// Stops accepting packets signed by the revoked key, including as a trust root, if the revocation was signed by
+  /// one of the [trust roots](#method.add_trust_root) or the revoked key itself.
+  ///
+  /// Revocations [sent in packets](struct.Packet.html#method.add_revocation) are added automatically.
+  /// Revoked keys stay revoked, even if they're added as sender keys or endorsed again later.
+  /// A key can only revoke itself if the mesher currently accepts it, so a stranger can't fill the mesher's
+  /// [store](identity/struct.RevocationStore.html) with revocations of keys it's never heard of; to revoke those, have
+  /// a trust root sign the revocation.
+  ///
+  /// Fails with [`MesherFail::BadRevocation`](fail/enum.MesherFail.html#variant.BadRevocation) if the revocation isn't
+  /// signed by someone allowed to make it, or the signature doesn't check out.
+  pub fn add_revocation(&mut self, revocation: Revocation) -> fail::Result<()> {
+    self.accept_revocation(revocation).map(|_| ())
+  }
+
+  /// Adds a revocation, like [`add_revocation`](#method.add_revocation), then sends it to every known peer, each of
+  /// which passes it on to its own peers if it accepts it.
+  ///
+  /// Signed peers only accept it if they accept the mesher's [signing key](#method.set_signing_key).
+  /// Failures sending to individual peers go to the [failure handler](#method.on_failure), so one unreachable peer
+  /// doesn't stop the rest.
+  pub fn publish_revocation(&mut self, revocation: Revocation) -> fail::Result<()> {
+    self.accept_revocation(revocation.clone())?;
+    let mut failures = vec![];
+    self.spread_revocation(&revocation, &mut failures);
+    for failure in failures {
+      self.report_failure(failure);
+    }
+    Ok(())
+  }
+
+  /// The revocations the mesher has accepted.
+  pub fn revocations(&self) -> &RevocationStore {
+    &self.revocations
+  }
+
+  /// Checks and stores a revocation, returning whether its key wasn't already revoked.
+  fn accept_revocation(&mut self, revocation: Revocation) -> fail::Result<bool> {
+    let revoker = revocation.revoker();
+    let trusted = if revoker == revocation.revoked() {
+      self.sender_pkeys.contains(revoker)
+        || self.trust_roots.contains(revoker)
+        || self.endorsements.iter().any(|e| e.subject() == revoker)
+        || self.revocations.is_revoked(revoker)
+    } else {
+      self.trust_roots.contains(revoker) && !self.revocations.is_revoked(revoker)
+    };
+    if !trusted || !revocation.verify_using(self.crypto.as_ref()) {
+      return Err(fail::MesherFail::BadRevocation);
+    }
+    let revoked = *revocation.revoked();
+    self
+      .endorsements
+      .retain(|e| e.subject() != &revoked && e.endorser() != &revoked);
+    Ok(self.revocations.insert(revocation))
+  }
+
+  /// Sends a revocation straight to every known peer the mesher can reach.
+  fn spread_revocation(&mut self, revocation: &Revocation, failures: &mut Vec<fail::MesherFail>) {
+    let targets: Vec<_> = self
+      .peers
+      .values()
+      .filter_map(|p| {
+        let path = p.paths.iter().find(|path| self.has_transport(path.scheme()))?;
+        Some((p.pkey, path.clone()))
+      })
+      .collect();
+    for (pkey, path) in targets {
+      let mut packet = self.new_packet(None);
+      packet.add_revocation(revocation, &pkey);
+      let sent = packet
+        .serialize()
+        .and_then(|p| self.send_data(&p, path.as_str(), Priority::Control));
+      if let Err(e) = sent {
+        failures.push(e);
+      }
+    }
+  }
+
+  /// Starts reading [group messages](struct.Packet.html#method.add_group_message) sent with this key.
+  /// Returns whether the key was kept.
+  ///
+  /// The mesher only keeps the newest key for each group, so this replaces a key for the same group from an earlier
+  /// epoch, and does nothing if it already has one from the same epoch or a later one.
+  /// Keys [sent to the mesher](struct.Packet.html#method.add_group_key) are only joined with automatically if they're
+  /// new keys for a group whose [invite](#method.accept_group_invite) was accepted, from the same sender; the rest are
+  /// held as invites.
+  pub fn join_group(&mut self, key: group::GroupKey) -> bool {
+    match self.groups.iter_mut().find(|k| k.id() == key.id()) {
+      Some(old) if old.epoch() >= key.epoch() => false,
+      Some(old) => {
+        *old = key;
+        true
+      }
+      None => {
+        self.groups.push(key);
+        true
+      }
+    }
+  }
+
+  /// Takes every [group invite](struct.GroupInvite.html) the mesher's holding, oldest first.
+  ///
+  /// Only the newest [`MAX_GROUP_INVITES`](#associatedconstant.MAX_GROUP_INVITES) are held, so check regularly.
+  pub fn take_group_invites(&mut self) -> Vec<GroupInvite> {
+    self.group_invites.drain(..).collect()
+  }
+
+  /// Joins the group an invite's for, as [`join_group`](#method.join_group) does, and takes its new keys from the
+  /// invite's sender from then on. Returns whether the key was kept.
+  ///
+  /// If nobody claimed to send the invite, every new key for the group will be an invite too.
+  pub fn accept_group_invite(&mut self, invite: GroupInvite) -> bool {
+    let id = invite.key.id();
+    if !self.join_group(invite.key) {
+      return false;
+    }
+    match invite.from {
+      Some(from) => self.group_managers.insert(id, from),
+      None => self.group_managers.remove(&id),
+    };
+    true
+  }
+
+  /// Handles a group key sent to the mesher, joining with it if it's a new key from the group's manager, and holding
+  /// it as an invite otherwise.
+  fn offered_group_key(&mut self, key: group::GroupKey, from: Option<sign::PublicKey>) {
+    let id = key.id();
+    if from.is_some() && self.group_managers.get(&id) == from.as_ref() && self.group_key(id).is_some() {
+      self.join_group(key);
+      return;
+    }
+    debug_event!(group = ?id, epoch = key.epoch(), "holding group key as an invite");
+    let invite = GroupInvite { key, from };
+    if self.group_invites.contains(&invite) {
+      return;
+    }
+    if self.group_invites.len() >= Mesher::MAX_GROUP_INVITES {
+      self.group_invites.pop_front();
     }
+    self.group_invites.push_back(invite);
+  }
+
+  /// Forgets a group's key, so its messages aren't read anymore. Returns whether the mesher was in the group.
+  pub fn leave_group(&mut self, id: group::GroupId) -> bool {
+    self.group_managers.remove(&id);
+    let before = self.groups.len();
+    self.groups.retain(|k| k.id() != id);
+    self.groups.len() != before
+  }
+
+  /// The key the mesher has for a group, if it's in it.
+  pub fn group_key(&self, id: group::GroupId) -> Option<&group::GroupKey> {
+    self.groups.iter().find(|k| k.id() == id)
+  }
+
+  /// Creates a mesher as described by the [config file](config/index.html) at the given path.
+  ///
+  /// Only the built-in transport kinds can be used; to add others, use [`Config`](config/struct.Config.html) directly.
+  #[cfg(feature = "config")]
+  pub fn from_config(path: impl AsRef<std::path::Path>) -> fail::Result<Mesher> {
+    crate::config::Config::load(path)?.build()
+  }
+
+  /// Starts building a mesher with [`MesherBuilder`](struct.MesherBuilder.html).
+  pub fn builder() -> MesherBuilder {
+    MesherBuilder::new()
   }
 
   /// Does the massaging necessary to get the transport based on the scheme in the path.
   /// Will return the appropriate errors if any of it fails.
   #[allow(clippy::borrowed_box)] // because we can't easily massage &mut Box<T> into &mut T, apparently
-  fn get_transport_for_path(&mut self, path: &str) -> fail::Result<&mut Box<dyn Transport>> {
-    let scheme = path
-      .splitn(2, ':')
-      .next()
-      .ok_or_else(|| fail::MesherFail::InvalidURL("no colon-delimited scheme segment".to_string()))?
-      .to_owned();
+  fn get_transport_for_path(&mut self, path: &Path) -> fail::Result<&mut Box<dyn Transport>> {
+    let scheme = self.aliases.get(path.scheme()).map_or(path.scheme(), String::as_str);
     self
       .transports
-      .get_mut(&scheme)
-      .ok_or(fail::MesherFail::UnregisteredScheme(scheme))
+      .get_mut(scheme)
+      .ok_or_else(|| fail::MesherFail::UnregisteredScheme(path.scheme().to_owned()))
+  }
+
+  /// The scheme the transport for the given one was added under, which is what its counters and limits are kept by, so
+  /// that schemes [added together](#method.add_transport_for_schemes) share them.
+  fn primary_scheme<'a>(&'a self, scheme: &'a str) -> &'a str {
+    self.aliases.get(scheme).map_or(scheme, String::as_str)
+  }
+
+  /// Whether there's a transport for the given scheme.
+  fn has_transport(&self, scheme: &str) -> bool {
+    self.transports.contains_key(self.primary_scheme(scheme))
   }
 
   /// Does everything you'd expect when mesher receives a packet:
@@ -93,88 +720,2795 @@ impl Mesher {
   /// - Returns any messages contained in it
   ///
   /// It will try to use _all_ of the secret keys associated with the mesher to decrypt the packet.
-  fn process_packet(&mut self, pkt: Vec<u8>) -> fail::Result<Vec<Message>> {
-    let dis = if self.sender_pkeys.is_empty() {
-      Packet::deserialize(&pkt, &self.own_skeys)?
+  /// Anything that goes wrong is added to `failures`, but doesn't stop the rest of the packet from being handled, e.g.
+  /// one failed forward won't stop the others.
+  /// `from` is where the packet was received from, or `None` if this mesher launched it, in which case the forward policy
+  /// doesn't apply.
+  /// Whatever it's forwarded along is sent with the given priority.
+  fn process_packet(
+    &mut self,
+    mut pkt: Vec<u8>,
+    from: Option<&Source>,
+    priority: Priority,
+    failures: &mut Vec<fail::MesherFail>,
+  ) -> Vec<Message> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("packet", bytes = pkt.len()).entered();
+    self.drop_retired_keys();
+    let before = failures.len();
+    let dis = if self.signed {
+      let sender_keys: Vec<_> = self
+        .sender_pkeys
+        .iter()
+        .chain(self.endorsements.iter().map(|e| e.subject()))
+        .filter(|k| !self.revocations.is_revoked(k))
+        .copied()
+        .collect();
+      let trust_roots: Vec<_> = self
+        .trust_roots
+        .iter()
+        .filter(|k| !self.revocations.is_revoked(k))
+        .copied()
+        .collect();
+      Packet::deserialize_signed(
+        &pkt,
+        &self.own_keys(),
+        &sender_keys,
+        &trust_roots,
+        self.crypto.as_ref(),
+        failures,
+      )
     } else {
-      Packet::deserialize_signed(&pkt, &self.own_skeys, &self.sender_pkeys)?
+      Packet::deserialize_with(&pkt, &self.own_keys(), self.crypto.as_ref())
+    };
+    let dis = match dis {
+      Ok(dis) => dis,
+      Err(e) => {
+        debug_event!(error = ?e, "dropped malformed packet");
+        self.metric(|m| m.dropped(DropReason::Malformed));
+        failures.push(e);
+        return vec![];
+      }
     };
+    if self.tamper_policy == TamperPolicy::DropPacket && failures.len() > before {
+      debug_event!("dropped tampered packet");
+      self.metric(|m| m.dropped(DropReason::Tampered));
+      return vec![];
+    }
+    debug_event!(chunks = dis.len(), "decrypted chunks");
+    if dis
+      .iter()
+      .any(|c| matches!(c, crate::packet::Chunk::Expiry(at) if self.is_expired(*at)))
+    {
+      debug_event!("dropped expired packet");
+      self.metric(|m| m.dropped(DropReason::Expired));
+      return vec![];
+    }
+    if dis.is_empty() {
+      self.metric(|m| m.undecryptable());
+      self.event(|h| h.on_undecryptable_packet());
+    }
+    // reply headers need the packet too, so it's only ever given to the last hop if there aren't any
+    let mut hops_left = dis
+      .iter()
+      .filter(|c| {
+        matches!(
+          c,
+          crate::packet::Chunk::Transport(_) | crate::packet::Chunk::ReplyHeader(_)
+        )
+      })
+      .count();
+    let hold_until = dis.iter().find_map(|c| match c {
+      crate::packet::Chunk::HoldUntil(until) => self.hold_until(*until),
+      _ => None,
+    });
+    // sent back at most once, however many of the packet's hops fail
+    let mut failure_notices: Vec<_> = dis
+      .iter()
+      .filter_map(|c| match c {
+        crate::packet::Chunk::FailureRequest(path) => Some(path.clone()),
+        _ => None,
+      })
+      .collect();
+    let (claims, dis): (Vec<_>, Vec<_>) = dis
+      .into_iter()
+      .partition(|c| matches!(c, crate::packet::Chunk::SenderClaim(_)));
+    let claims: HashMap<_, _> = claims
+      .into_iter()
+      .filter_map(|c| match c {
+        crate::packet::Chunk::SenderClaim(claim) => Some((claim.message, claim)),
+        _ => None,
+      })
+      .collect();
     let mut messages = vec![];
+    let mut new_revocations = vec![];
     for piece in dis {
       match piece {
-        crate::packet::Chunk::Message(m, r) => messages.push(Message {
+        crate::packet::Chunk::Message(m, r, id, seq) => {
+          if id.is_some_and(|id| self.is_duplicate(id)) {
+            debug_event!(id = ?id, "dropped duplicate message");
+            continue;
+          }
+          let sender = match (seq.and_then(|seq| claims.get(&seq)), id) {
+            (Some(claim), Some(id)) => self.check_claim(claim, id, &m),
+            _ => None,
+          };
+          debug_event!(id = ?id, bytes = m.len(), claimed = sender.is_some(), "received message");
+          messages.push(Message {
+            contents: m,
+            reply_path: r,
+            id,
+            source: from.cloned(),
+            group: None,
+            sender,
+            reply_block: None,
+          })
+        }
+        crate::packet::Chunk::Transport(to) => {
+          hops_left -= 1;
+          // nothing needs the packet after its last hop, so that one can have it rather than a copy
+          let pkt = if hops_left == 0 {
+            std::mem::take(&mut pkt)
+          } else {
+            pkt.clone()
+          };
+          if let Some(at) = hold_until {
+            debug_event!(path = %to, "holding packet");
+            self
+              .schedule
+              .hold(at, Scheduled::Forward(pkt, to, from.cloned(), priority));
+            continue;
+          }
+          let before = failures.len();
+          self.forward(pkt, to, from, priority, failures);
+          if failures.len() > before {
+            for path in std::mem::take(&mut failure_notices) {
+              debug_event!("sending failure notice");
+              messages.append(&mut self.send_back(&path, from, failures));
+            }
+          }
+        }
+        crate::packet::Chunk::Custom(kind, data) => {
+          if let Some(handler) = self.chunk_handlers.get_mut(&kind) {
+            handler(&data);
+          }
+        }
+        crate::packet::Chunk::ReceiptRequest(path) => messages.append(&mut self.send_back(&path, from, failures)),
+        crate::packet::Chunk::Receipt(id) => {
+          // anything else is either forgotten or made up
+          if let Some((status, _)) = self.delivered.get_mut(&ReceiptId(id)) {
+            *status = DeliveryStatus::Delivered;
+          }
+        }
+        // handled along with the hops
+        crate::packet::Chunk::FailureRequest(_)
+        | crate::packet::Chunk::HoldUntil(_)
+        | crate::packet::Chunk::Expiry(_)
+        | crate::packet::Chunk::SenderClaim(_) => (),
+        crate::packet::Chunk::FailureNotice(id) => {
+          // a receipt that's already come back means the packet got there some other way, and anything not being kept
+          // track of is either forgotten or made up
+          if let Some((status @ DeliveryStatus::Pending, _)) = self.delivered.get_mut(&ReceiptId(id)) {
+            *status = DeliveryStatus::Failed;
+          }
+        }
+        crate::packet::Chunk::Onion(layer) => {
+          for (id, data) in layer.messages {
+            if self.is_duplicate(id) {
+              debug_event!(id = ?id, "dropped duplicate message");
+              continue;
+            }
+            debug_event!(id = ?id, bytes = data.len(), "received message");
+            messages.push(Message {
+              contents: data,
+              reply_path: None,
+              id: Some(id),
+              source: from.cloned(),
+              group: None,
+              sender: None,
+              reply_block: None,
+            });
+          }
+          // the rest of the onion is its own packet, not the one that was received
+          if let Some((path, rest)) = layer.forward {
+            self.forward(rest, path, from, priority, failures);
+          }
+        }
+        crate::packet::Chunk::ReplyHeader(ReplyStep::Forward(path, header)) => {
+          if let Some(reply) = reply_block::pass_on(Some(header), &pkt) {
+            self.forward(reply, path, from, priority, failures);
+          }
+        }
+        crate::packet::Chunk::ReplyHeader(ReplyStep::Final(block)) => {
+          messages.append(&mut self.open_reply(block, &pkt, from, failures));
+        }
+        crate::packet::Chunk::Announce(announcement) => {
+          if let Some(entries) = discovery::decode_announcement(&announcement) {
+            for (pkey, paths) in entries {
+              self.heard_about(pkey, paths);
+            }
+          }
+        }
+        crate::packet::Chunk::Mail(mail) => self.handle_mail(mail, from, failures),
+        crate::packet::Chunk::GroupMessage(group, m, id) => {
+          if self.is_duplicate(id) {
+            debug_event!(id = ?id, "dropped duplicate message");
+            continue;
+          }
+          debug_event!(id = ?id, group = ?group, bytes = m.len(), "received group message");
+          messages.push(Message {
+            contents: m,
+            reply_path: None,
+            id: Some(id),
+            source: from.cloned(),
+            group: Some(group),
+            sender: None,
+            reply_block: None,
+          });
+        }
+        crate::packet::Chunk::GroupKey(key, claim) => {
+          let from = claim.and_then(|claim| {
+            self.own_skeys.iter().find_map(|skey| {
+              let pkey = self.crypto.encrypt_public_key(skey);
+              claim.verify_group_key(&pkey, &key, self.crypto.as_ref())
+            })
+          });
+          self.offered_group_key(key, from);
+        }
+        crate::packet::Chunk::Endorsement(endorsement) => {
+          // only ever read by signed meshers, which already checked it's from a trust root
+          let _ = self.add_endorsement(endorsement);
+        }
+        crate::packet::Chunk::Revocation(revocation) => {
+          if let Ok(true) = self.accept_revocation(revocation.clone()) {
+            new_revocations.push(revocation);
+          }
+        }
+      }
+    }
+    // only passing on new revocations means they spread through the whole mesh, but each only once
+    for revocation in new_revocations {
+      self.spread_revocation(&revocation, failures);
+    }
+    messages
+  }
+
+  /// Checks a claim of who sent a message, returning their key if the claim's for this mesher and checks out.
+  fn check_claim(&self, claim: &crate::packet::SenderClaim, id: MessageId, contents: &[u8]) -> Option<sign::PublicKey> {
+    let pkeys = self.own_skeys.iter().map(|skey| self.crypto.encrypt_public_key(skey));
+    #[cfg(feature = "pq-hybrid")]
+    let pkeys = pkeys.chain(self.hybrid_skeys.iter().map(|skey| *skey.public_key().x25519()));
+    // hybrid keys' claims are made out to their X25519 halves
+    pkeys
+      .filter_map(|pkey| claim.verify(&pkey, id, contents, self.crypto.as_ref()))
+      .next()
+  }
+
+  /// Opens a reply that came back through one of this mesher's reply blocks, returning the messages in it.
+  ///
+  /// The block's key is forgotten, so any later replies through it are dropped.
+  fn open_reply(
+    &mut self,
+    block: ReplyBlockId,
+    pkt: &[u8],
+    from: Option<&Source>,
+    failures: &mut Vec<fail::MesherFail>,
+  ) -> Vec<Message> {
+    let key = match self.reply_keys.remove(&block) {
+      Some(key) => key,
+      None => {
+        debug_event!(block = ?block, "dropped reply through unknown or used reply block");
+        return vec![];
+      }
+    };
+    let chunks = reply_block::pass_on(None, pkt)
+      .ok_or(fail::MesherFail::InvalidPacket)
+      .and_then(|reply| Packet::deserialize(&reply, &[key], &[], self.crypto.as_ref()));
+    let chunks = match chunks {
+      Ok(chunks) => chunks,
+      Err(e) => {
+        failures.push(e);
+        return vec![];
+      }
+    };
+    let mut messages = vec![];
+    for chunk in chunks {
+      // anything else in a reply is ignored, since nothing else could get through a reply block
+      if let crate::packet::Chunk::Message(m, _, id, _) = chunk {
+        if id.is_some_and(|id| self.is_duplicate(id)) {
+          debug_event!(id = ?id, "dropped duplicate message");
+          continue;
+        }
+        debug_event!(id = ?id, block = ?block, bytes = m.len(), "received reply");
+        messages.push(Message {
           contents: m,
-          reply_path: r,
-        }),
-        crate::packet::Chunk::Transport(to) => self.send_data(&pkt, &to)?,
+          reply_path: None,
+          id,
+          source: from.cloned(),
+          group: None,
+          sender: None,
+          reply_block: Some(block),
+        });
       }
     }
-    Ok(messages)
+    messages
   }
 
-  // Sends the given bytes along the given path, getting the appropriate transport.
-  fn send_data(&mut self, packet: &[u8], path: &str) -> fail::Result<()> {
-    self
-      .get_transport_for_path(path)?
-      .send(path.to_owned(), packet.to_vec())
+  /// Sends a reply path from a received packet back, e.g. as a delivery receipt, returning any messages in it for this
+  /// mesher.
+  fn send_back(
+    &mut self,
+    path: &crate::packet::ReplyPath,
+    from: Option<&Source>,
+    failures: &mut Vec<fail::MesherFail>,
+  ) -> Vec<Message> {
+    // what's being sent back is already in the reply path, so it just needs to be sent, like any other packet, though
+    // the sender's waiting on it
+    let mut packet = Packet::unsigned_using(self.crypto.clone());
+    packet.main_path = path.as_ref().clone();
+    match packet.serialize() {
+      Ok(packet) => self.process_packet(packet, from, Priority::Control, failures),
+      Err(e) => {
+        failures.push(e);
+        vec![]
+      }
+    }
   }
 
-  /// Adds a transport to the mesher, for it to send and receive data through.
-  /// The scheme is passed to the transport exactly as-is.
-  /// If an initialization error occurs in the transport, nothing is added to the internal scheme mapping.
-  pub fn add_transport<T: Transport + 'static>(&mut self, scheme: &str) -> fail::Result<()> {
-    self.transports.insert(scheme.to_owned(), Box::new(T::new(scheme)?));
-    Ok(())
+  /// Sets a function to be called with every custom chunk of type `C` that [`receive`](#method.receive) decrypts.
+  ///
+  /// The handler is called while the packet is being processed, before `receive` returns.
+  /// Chunks which fail to [decode](trait.CustomChunk.html#tymethod.decode) are ignored.
+  /// Setting a new handler for the same kind of chunk replaces the old one.
+  pub fn on_chunk<C: CustomChunk + 'static>(&mut self, mut handler: impl FnMut(C) + Send + 'static) {
+    let handler = move |bytes: &[u8]| {
+      if let Some(chunk) = C::decode(bytes) {
+        handler(chunk);
+      }
+    };
+    self.chunk_handlers.insert(C::KIND, Box::new(handler));
   }
 
-  /// Has the mesher listen on the given path for messages.
-  /// This determines the transport to connect to based on the scheme, then just tells it to listen.
-  /// The exact behavior depends on the transport, but will generally involve either setting up some listener, or adding it to a list of internal paths to poll.
-  pub fn listen_on(&mut self, path: &str) -> fail::Result<()> {
-    self.get_transport_for_path(path)?.listen(path.to_owned())
+  /// Sets how long the mesher remembers the IDs of messages it's received, so it can drop duplicates, e.g. from a sender retrying.
+  ///
+  /// A message whose ID was first seen less than `window` ago is silently dropped.
+  /// `None`, the default, turns deduplication off, so every copy of a message is delivered.
+  pub fn set_dedup_window(&mut self, window: Option<Duration>) {
+    self.dedup_window = window;
+    if window.is_none() {
+      self.seen.clear();
+    }
   }
 
-  /// Sends a packet out.
+  /// Checks whether a message with the given ID was already received within the dedup window, and remembers it if not.
+  fn is_duplicate(&mut self, id: MessageId) -> bool {
+    let window = match self.dedup_window {
+      Some(window) => window,
+      None => return false,
+    };
+    let now = Instant::now();
+    self.seen.retain(|_, at| now.duration_since(*at) < window);
+    if self.seen.contains_key(&id) {
+      return true;
+    }
+    self.seen.insert(id, now);
+    false
+  }
+
+  /// Sets how long the mesher remembers the packets it's forwarded, so it can stop them going around in loops, e.g. when
+  /// a bad route sends a packet back and forth between two relays.
   ///
-  /// Note that while the outgoing packet is processed like any incoming one, any messages destined for this mesher are ignored.
-  pub fn launch(&mut self, packet: Packet) -> fail::Result<()> {
-    self.process_packet(packet.serialize()?).map(|_| ())
+  /// A received packet that asks to be forwarded along the same path it was already forwarded along less than `window`
+  /// ago is silently dropped.
+  /// This is separate from [deduplicating messages](#method.set_dedup_window): it stops relays from repeating
+  /// themselves, whether or not they can read anything in the packet.
+  /// `None`, the default, turns loop detection off.
+  pub fn set_loop_window(&mut self, window: Option<Duration>) {
+    self.loop_window = window;
+    if window.is_none() {
+      self.forwarded.clear();
+    }
   }
 
-  /// Gets pending messages from all of the transports along all of the paths they've been told to use.
-  pub fn receive(&mut self) -> fail::Result<Vec<Message>> {
-    if self.own_skeys.is_empty() {
-      return Err(fail::MesherFail::NoKeys);
+  /// Checks whether the packet was already forwarded along the path within the loop window, and remembers it if not.
+  fn is_looping(&mut self, packet: &[u8], path: &str) -> bool {
+    let window = match self.loop_window {
+      Some(window) => window,
+      None => return false,
+    };
+    let now = Instant::now();
+    self.forwarded.retain(|_, at| now.duration_since(*at) < window);
+    let hash = self.loop_hasher.hash_one((packet, path));
+    if self.forwarded.contains_key(&hash) {
+      return true;
     }
-    let mut packets = vec![];
-    for (_, transport) in self.transports.iter_mut() {
-      packets.append(&mut transport.receive()?);
+    self.forwarded.insert(hash, now);
+    false
+  }
+
+  /// Sets up (or, with `None`, turns off) periodic announcements to known peers.
+  ///
+  /// Announcements are sent during [`receive`](#method.receive), whenever at least the [`Discovery`](discovery/struct.Discovery.html)'s
+  /// interval has passed since the last one, so a mesher that's never received from won't announce itself.
+  /// Announcements from other peers are always accepted, whether or not this is set up.
+  pub fn set_discovery(&mut self, discovery: Option<Discovery>) {
+    self.discovery = discovery;
+    self.last_announced = None;
+  }
+
+  /// Adds a peer to the table by hand, e.g. to bootstrap discovery, or replaces the paths of one that's already known.
+  pub fn add_peer(&mut self, pkey: encrypt::PublicKey, paths: Vec<Path>) {
+    self.peers.insert(
+      pkey,
+      Peer {
+        pkey,
+        paths,
+        last_heard: Instant::now(),
+      },
+    );
+  }
+
+  /// Forgets about a peer. Returns whether it was known.
+  ///
+  /// Note that it'll be added back if another peer announces it.
+  pub fn remove_peer(&mut self, pkey: &encrypt::PublicKey) -> bool {
+    self.peers.remove(pkey).is_some()
+  }
+
+  /// Every peer the mesher knows about, whether it was added by hand or learned through announcements.
+  pub fn known_peers(&self) -> Vec<&Peer> {
+    self.peers.values().collect()
+  }
+
+  /// Records something an announcement said about a peer, ignoring anything about this mesher itself.
+  fn heard_about(&mut self, pkey: encrypt::PublicKey, paths: Vec<Path>) {
+    if paths.is_empty() || self.has_own_key(&pkey) {
+      return;
     }
-    let mut messages = vec![];
-    for p in packets {
-      messages.append(&mut self.process_packet(p)?);
+    if !self.peers.contains_key(&pkey) && self.peers.len() >= discovery::MAX_PEERS {
+      return;
     }
-    Ok(messages)
+    self.add_peer(pkey, paths);
   }
-}
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+  /// Sends an announcement to every known peer right away, rather than waiting for the next interval.
+  ///
+  /// Does nothing if discovery isn't set up, and fails with [`MesherFail::NoKeys`](fail/enum.MesherFail.html#variant.NoKeys)
+  /// if the mesher has no keys to announce.
+  /// Failures sending to individual peers go to the [failure handler](#method.on_failure), so one unreachable peer doesn't stop the rest.
+  pub fn announce(&mut self) -> fail::Result<()> {
+    let discovery = match &self.discovery {
+      Some(d) => d.clone(),
+      None => return Ok(()),
+    };
+    let own = self.newest_pkey().ok_or(fail::MesherFail::NoKeys)?;
+    self.last_announced = Some(Instant::now());
 
-  #[test]
-  fn empty_mesher_fails_receive() {
-    let mut empty = Mesher::unsigned(vec![]);
+    let gossip = self
+      .peers
+      .values()
+      .take(discovery::GOSSIP_PEERS)
+      .map(|p| (&p.pkey, &p.paths[..]));
+    let announcement = discovery::encode_announcement(std::iter::once((&own, &discovery.paths[..])).chain(gossip));
+    let targets: Vec<_> = self
+      .peers
+      .values()
+      .filter_map(|p| {
+        let path = p.paths.iter().find(|path| self.has_transport(path.scheme()))?;
+        Some((p.pkey, path.clone()))
+      })
+      .collect();
+    for (pkey, path) in targets {
+      let mut packet = self.new_packet(discovery.signing_key.as_ref());
+      packet.add_announcement(announcement.clone(), &pkey);
+      let sent = packet
+        .serialize()
+        .and_then(|p| self.send_data(&p, path.as_str(), Priority::Control));
+      if let Err(e) = sent {
+        self.report_failure(e);
+      }
+    }
+    Ok(())
+  }
 
-    match empty.receive() {
-      Err(fail::MesherFail::NoKeys) => (),
-      _ => unreachable!(),
+  /// The public key of the newest of the mesher's own keys that isn't being retired, which is the one peers should use.
+  pub(crate) fn newest_pkey(&self) -> Option<encrypt::PublicKey> {
+    self
+      .own_skeys
+      .iter()
+      .rev()
+      .map(|k| self.crypto.encrypt_public_key(k))
+      .find(|pk| !self.retiring.iter().any(|(k, _)| k == pk))
+  }
+
+  /// Creates a packet for the mesher to send itself, signed with the given key or else the mesher's own signing key, if it has one.
+  pub(crate) fn new_packet(&self, skey: Option<&sign::SecretKey>) -> Packet {
+    let mut packet = match skey.or(self.signing_key.as_ref()) {
+      Some(sk) => Packet::signed_using(sk.clone_secret(), self.crypto.clone()),
+      None => Packet::unsigned_using(self.crypto.clone()),
+    };
+    packet.use_key_hints(self.key_hints);
+    packet
+  }
+
+  /// Sets up (or, with `None`, removes) a [mailbox](struct.Mailbox.html) to hold packets for recipients who aren't online.
+  ///
+  /// Removing the mailbox drops any mail still in it.
+  pub fn set_mailbox(&mut self, mailbox: Option<Mailbox>) {
+    self.mailbox = mailbox;
+  }
+
+  /// The mesher's mailbox, if it has one, e.g. to check how much mail is waiting.
+  pub fn mailbox(&self) -> Option<&Mailbox> {
+    self.mailbox.as_ref()
+  }
+
+  /// Asks the relay with the given key, reachable along `relay_path`, for the mail it's holding for the recipient
+  /// `identity` is the signing key of.
+  ///
+  /// The relay answers with a challenge, which the mesher signs with `identity` during a later
+  /// [`receive`](#method.receive) to prove it's the recipient, and then sends the mail along `return_path`, where it's
+  /// received like any other packets.
+  /// `return_path` must be one this mesher is listening on.
+  ///
+  /// The identity is separate from the mesher's own [signing key](#method.set_signing_key), which is still used to sign
+  /// the packets themselves, if it's set.
+  ///
+  /// Fails with [`MesherFail::NoKeys`](fail/enum.MesherFail.html#variant.NoKeys) if the mesher has no key for the relay
+  /// to encrypt the challenge for.
+  pub fn request_mail(
+    &mut self,
+    relay_pkey: &encrypt::PublicKey,
+    relay_path: &str,
+    return_path: &str,
+    identity: &sign::SecretKey,
+  ) -> fail::Result<()> {
+    let sign_pkey = self.crypto.sign_public_key(identity);
+    let own = self.newest_pkey().ok_or(fail::MesherFail::NoKeys)?;
+    let id = rand::random();
+    let mut packet = self.new_packet(None);
+    packet.add_mail(
+      &MailMessage::Request(id, sign_pkey, own, return_path.to_owned()),
+      relay_pkey,
+    );
+    self.send_data(&packet.serialize()?, relay_path, Priority::Control)?;
+    self
+      .mail_requests
+      .insert(id, (*relay_pkey, relay_path.to_owned(), identity.clone_secret()));
+    Ok(())
+  }
+
+  /// Handles one step of collecting mail, whether this mesher is the relay or the recipient.
+  fn handle_mail(&mut self, mail: MailMessage, from: Option<&Source>, failures: &mut Vec<fail::MesherFail>) {
+    match mail {
+      MailMessage::Request(id, sign_pkey, encrypt_pkey, return_path) => {
+        let challenge = match &mut self.mailbox {
+          Some(mailbox) => mailbox.challenge(id, sign_pkey, return_path.clone()),
+          None => None,
+        };
+        let challenge = match challenge {
+          Some(c) => c,
+          None => return,
+        };
+        // the return path comes from the network, so it's treated like any other forward
+        if !self.may_forward(&return_path, from) {
+          failures.push(fail::MesherFail::ForwardDenied(return_path));
+          return;
+        }
+        let mut packet = self.new_packet(None);
+        packet.add_mail(&MailMessage::Challenge(id, challenge), &encrypt_pkey);
+        if let Err(e) = packet
+          .serialize()
+          .and_then(|p| self.send_data(&p, &return_path, Priority::Control))
+        {
+          failures.push(e);
+        }
+      }
+      MailMessage::Challenge(id, challenge) => {
+        let (relay_pkey, relay_path, identity) = match self.mail_requests.remove(&id) {
+          Some(r) => r,
+          None => return,
+        };
+        let claim = MailMessage::Claim(
+          id,
+          self.crypto.sign_public_key(&identity),
+          self.crypto.sign(&challenge, &identity),
+        );
+        let mut packet = self.new_packet(None);
+        packet.add_mail(&claim, &relay_pkey);
+        if let Err(e) = packet
+          .serialize()
+          .and_then(|p| self.send_data(&p, &relay_path, Priority::Control))
+        {
+          failures.push(e);
+        }
+      }
+      MailMessage::Claim(id, sign_pkey, signed) => {
+        let crypto = self.crypto.clone();
+        let claimed = match &mut self.mailbox {
+          Some(mailbox) => mailbox.claim(id, &sign_pkey, &signed, crypto.as_ref()),
+          None => None,
+        };
+        if let Some((return_path, mail)) = claimed {
+          debug_event!(path = %return_path, packets = mail.len(), "delivering mail");
+          for packet in mail {
+            self.send_or_queue(packet, return_path.clone(), Priority::Normal, failures);
+          }
+        }
+      }
     }
   }
 
-  #[test]
-  #[should_panic(expected = "Provide sender keys. If you don't want any, use Mesher::unsigned instead.")]
+  /// Sets the key used to sign packets the mesher builds itself, e.g. in [`send_to`](#method.send_to) and announcements.
+  ///
+  /// Without one, they're unsigned, so signed meshers will ignore them.
+  pub fn set_signing_key(&mut self, skey: Option<sign::SecretKey>) {
+    self.signing_key = skey;
+  }
+
+  /// Sets whether packets the mesher builds itself get [key hints](struct.Packet.html#method.use_key_hints), which is
+  /// off by default.
+  ///
+  /// Meshers always read hinted chunks, whether or not this is on.
+  pub fn set_key_hints(&mut self, on: bool) {
+    self.key_hints = on;
+  }
+
+  /// Sends a message to a known peer, picking the route automatically.
+  ///
+  /// If the mesher has a transport for one of the peer's paths, the packet is sent there directly.
+  /// Otherwise, it's sent through the most recently heard-from peer which the mesher *can* reach, on the assumption
+  /// that that peer can reach the destination.
+  /// Fails with [`MesherFail::NoRoute`](fail/enum.MesherFail.html#variant.NoRoute) if the peer isn't known, or there's no way to reach it or any relay.
+  ///
+  /// For full control over the route, build a [`Packet`](struct.Packet.html) and [`launch`](#method.launch) it instead.
+  pub fn send_to(&mut self, pkey: &encrypt::PublicKey, data: &[u8]) -> fail::Result<MessageId> {
+    let no_route = || fail::MesherFail::NoRoute(format!("{:?}", pkey));
+    let own = self.newest_pkey().ok_or(fail::MesherFail::NoKeys)?;
+    let dest = self.peers.get(pkey).ok_or_else(no_route)?;
+    let reachable = |p: &Peer| p.paths.iter().find(|path| self.has_transport(path.scheme())).cloned();
+
+    let mut packet = self.new_packet(None);
+    match reachable(dest) {
+      Some(path) => packet.add_hop(path.to_string(), &own),
+      None => {
+        let dest_path = dest.paths.first().ok_or_else(no_route)?;
+        let (relay, relay_path) = self
+          .peers
+          .values()
+          .filter(|p| &p.pkey != pkey)
+          .filter_map(|p| Some((p, reachable(p)?)))
+          .max_by_key(|(p, _)| p.last_heard)
+          .ok_or_else(no_route)?;
+        packet.add_hop(relay_path.to_string(), &own);
+        packet.add_hop(dest_path.to_string(), &relay.pkey);
+      }
+    }
+    let id = packet.add_message(data, pkey);
+    self.launch(packet)?;
+    Ok(id)
+  }
+
+  /// Whether the receipt with the given ID has come back to this mesher yet, or a
+  /// [failure notice](struct.ReplyPathHandle.html#method.use_for_failure_notice) about it has instead.
+  ///
+  /// Receipts are only kept track of for [`RECEIPT_LIFETIME`](#associatedconstant.RECEIPT_LIFETIME) after their packets
+  /// are launched, and only the newest [`MAX_RECEIPTS`](#associatedconstant.MAX_RECEIPTS) of them, so check before then.
+  pub fn delivery_status(&self, id: ReceiptId) -> DeliveryStatus {
+    match self.delivered.get(&id) {
+      Some((status, at)) if at.elapsed() < Mesher::RECEIPT_LIFETIME => *status,
+      _ => DeliveryStatus::Pending,
+    }
+  }
+
+  /// Starts keeping track of the receipts a packet being launched asks for, forgetting the oldest ones if there are too
+  /// many.
+  fn expect_receipts(&mut self, ids: &[ReceiptId]) {
+    if ids.is_empty() {
+      return;
+    }
+    let now = Instant::now();
+    self
+      .delivered
+      .retain(|_, (_, at)| now.duration_since(*at) < Mesher::RECEIPT_LIFETIME);
+    for &id in ids {
+      if self.delivered.len() >= Mesher::MAX_RECEIPTS {
+        let oldest = self.delivered.iter().min_by_key(|(_, (_, at))| *at).map(|(id, _)| *id);
+        if let Some(oldest) = oldest {
+          self.delivered.remove(&oldest);
+        }
+      }
+      self.delivered.insert(id, (DeliveryStatus::Pending, now));
+    }
+  }
+
+  /// Stops keeping track of a receipt, e.g. once the application has seen that it's been delivered.
+  ///
+  /// Its status goes back to [`Pending`](enum.DeliveryStatus.html#variant.Pending).
+  pub fn forget_receipt(&mut self, id: ReceiptId) {
+    self.delivered.remove(&id);
+  }
+
+  /// Passes a failure to the failure handler, if there is one.
+  pub(crate) fn report_failure(&mut self, failure: fail::MesherFail) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(failure = ?failure, "failure while receiving");
+    if let Some(handler) = &mut self.failure_handler {
+      handler(failure);
+    }
+  }
+
+  /// Sets a function to be called with every failure that happens while [`receive`](#method.receive) is processing packets.
+  ///
+  /// Failures processing one packet don't stop the rest from being processed, so they can't be returned from `receive` directly.
+  /// Without a handler, they're silently ignored.
+  /// Setting a new handler replaces the old one.
+  pub fn on_failure(&mut self, handler: impl FnMut(fail::MesherFail) + Send + 'static) {
+    self.failure_handler = Some(Box::new(handler));
+  }
+
+  /// Forwards a packet along a path, right away or, if the mesher's mixing, once the pool lets it go.
+  /// If it was received `from` somewhere, it's only forwarded if the forward policy allows it.
+  fn forward(
+    &mut self,
+    packet: Vec<u8>,
+    path: String,
+    from: Option<&Source>,
+    priority: Priority,
+    failures: &mut Vec<fail::MesherFail>,
+  ) {
+    let inbound = from.is_some();
+    if let Some(mailbox) = &mut self.mailbox {
+      if path.split(':').next() == Some(MAILBOX_SCHEME) {
+        if !mailbox.deposit(&path, packet) {
+          failures.push(fail::MesherFail::ForwardDenied(path));
+        }
+        return;
+      }
+    }
+    if inbound && !self.may_forward(&path, from) {
+      debug_event!(path = %path, "forward denied");
+      self.metric(|m| m.dropped(DropReason::ForwardDenied));
+      failures.push(fail::MesherFail::ForwardDenied(path));
+      return;
+    }
+    if inbound && self.is_looping(&packet, &path) {
+      debug_event!(path = %path, "dropped looping packet");
+      self.metric(|m| m.dropped(DropReason::Looped));
+      return;
+    }
+    self.metric(|m| m.forwarded(path.split(':').next().unwrap_or_default()));
+    debug_event!(path = %path, mixing = self.mix_pool.is_some(), "forwarding packet");
+    self.event(|h| h.on_forwarded(&path));
+    match &mut self.mix_pool {
+      Some(pool) => pool.hold(packet, path, priority),
+      None => self.send_or_queue(packet, path, priority, failures),
+    }
+  }
+
+  /// Sends a forwarded packet, putting it in the outbound queue if the transport fails and there is one.
+  fn send_or_queue(&mut self, packet: Vec<u8>, path: String, priority: Priority, failures: &mut Vec<fail::MesherFail>) {
+    match self.send_data(&packet, &path, priority) {
+      Err(fail::MesherFail::SendFailure(_) | fail::MesherFail::QuotaExceeded(_)) if self.queue.is_some() => {
+        debug_event!(path = %path, "queued packet to retry");
+        if let Err(e) = self.queue.as_mut().map_or(Ok(()), |q| q.push(packet, path, priority)) {
+          failures.push(e);
+        }
+      }
+      Err(e) => failures.push(e),
+      Ok(()) => (),
+    }
+  }
+
+  /// Sends forwarded packets going along the same path as one batch, queueing them all if the transport fails and there's
+  /// a queue.
+  fn send_batch_or_queue(
+    &mut self,
+    packets: Vec<(Vec<u8>, Priority)>,
+    path: String,
+    failures: &mut Vec<fail::MesherFail>,
+  ) {
+    if let [_] = packets.as_slice() {
+      let (packet, priority) = packets.into_iter().next().expect("Just checked there's one");
+      return self.send_or_queue(packet, path, priority, failures);
+    }
+    let blobs: Vec<&[u8]> = packets.iter().map(|(packet, _)| packet.as_slice()).collect();
+    match self.send_batch_data(&blobs, &path) {
+      Err(fail::MesherFail::SendFailure(_) | fail::MesherFail::QuotaExceeded(_)) if self.queue.is_some() => {
+        debug_event!(path = %path, packets = packets.len(), "queued batch to retry");
+        let queue = self.queue.as_mut().expect("Just checked there's a queue");
+        for (packet, priority) in packets {
+          if let Err(e) = queue.push(packet, path.clone(), priority) {
+            failures.push(e);
+          }
+        }
+      }
+      Err(e) => failures.push(e),
+      Ok(()) => (),
+    }
+  }
+
+  /// Retries whichever queued packets are due, reporting the ones that have been given up on.
+  ///
+  /// Packets going along the same path are retried as one batch, and the paths with the most urgent packets go first.
+  fn retry_queue(&mut self, failures: &mut Vec<fail::MesherFail>) {
+    let due = match &mut self.queue {
+      Some(queue) => queue.due(),
+      None => return,
+    };
+    for (path, batch) in by_path(due.into_iter().map(|q| (q.path.clone(), q))) {
+      let result = match batch.as_slice() {
+        [one] => self.send_data(&one.packet, &path, one.priority),
+        _ => {
+          let blobs: Vec<&[u8]> = batch.iter().map(|q| q.packet.as_slice()).collect();
+          self.send_batch_data(&blobs, &path)
+        }
+      };
+      let mut result = result.map_err(Some);
+      let queue = self.queue.as_mut().expect("Queue was just used");
+      for queued in batch {
+        let done = match &mut result {
+          Ok(()) => queue.sent(queued),
+          Err(e) => match queue.failed(queued) {
+            // failures can't be cloned, so only the first packet given up on gets the real reason
+            Ok(false) => Err(
+              e.take()
+                .unwrap_or_else(|| fail::MesherFail::SendFailure(format!("Gave up retrying packet along {}", path))),
+            ),
+            other => other.map(|_| ()),
+          },
+        };
+        if let Err(e) = done {
+          failures.push(e);
+        }
+      }
+    }
+  }
+
+  /// Sets (or, with `None`, removes) the [queue](struct.OutboundQueue.html) for forwarded packets that fail to send.
+  ///
+  /// Removing a queue doesn't delete the packets in it; they're retried the next time a queue is opened in the same directory.
+  pub fn set_outbound_queue(&mut self, queue: Option<OutboundQueue>) {
+    self.queue = queue;
+  }
+
+  /// How many packets are waiting in the outbound queue to be retried.
+  pub fn queued_packets(&self) -> usize {
+    self.queue.as_ref().map_or(0, OutboundQueue::len)
+  }
+
+  /// Whether the forward policy, if there is one, allows forwarding a packet received `from` somewhere along the given
+  /// path right now.
+  fn may_forward(&mut self, path: &str, from: Option<&Source>) -> bool {
+    let policy = match &self.forward_policy {
+      Some(policy) => policy,
+      None => return true,
+    };
+    if from.is_some_and(|from| !policy.accepts(from)) {
+      return false;
+    }
+    // unparseable paths can't be sent anyway, and fail with a better error when they're tried
+    if Path::parse(path).is_ok_and(|path| !policy.permits(&path)) {
+      return false;
+    }
+    match (&policy.rate_limit, &mut self.forward_bucket) {
+      (Some(limit), Some(bucket)) => bucket.take(limit),
+      _ => true,
+    }
+  }
+
+  /// Sets (or, with `None`, removes) the [policy](struct.ForwardPolicy.html) for which received packets get forwarded.
+  pub fn set_forward_policy(&mut self, policy: Option<ForwardPolicy>) {
+    self.forward_bucket = policy
+      .as_ref()
+      .and_then(|p| p.rate_limit.as_ref())
+      .map(TokenBucket::new);
+    self.forward_policy = policy;
+  }
+
+  /// Sends whichever held packets the mix pool says are due, or all of them if `all` is set.
+  fn flush_mix_pool(&mut self, all: bool, failures: &mut Vec<fail::MesherFail>) {
+    let due = match &mut self.mix_pool {
+      Some(pool) if all => pool.drain(),
+      Some(pool) => pool.due(),
+      None => return,
+    };
+    for (path, packets) in by_path(
+      due
+        .into_iter()
+        .map(|(packet, path, priority)| (path, (packet, priority))),
+    ) {
+      self.send_batch_or_queue(packets, path, failures);
+    }
+  }
+
+  /// Sets (or, with `None`, removes) the limit on how big packets can be, in bytes.
+  /// It starts out at [`DEFAULT_MAX_PACKET_SIZE`](#associatedconstant.DEFAULT_MAX_PACKET_SIZE).
+  ///
+  /// Bigger packets that are received are dropped before they're parsed, and bigger packets the mesher tries to send
+  /// fail with [`MesherFail::PacketTooLarge`](fail/enum.MesherFail.html#variant.PacketTooLarge).
+  /// Every transport is told about the limit too, so the ones that can stop reading oversized packets early.
+  pub fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.max_packet_size = max;
+    for transport in self.transports.values_mut() {
+      transport.set_max_packet_size(max);
+    }
+  }
+
+  /// Checks a packet against the [maximum size](#method.set_max_packet_size).
+  fn check_size(&self, len: usize) -> fail::Result<()> {
+    match self.max_packet_size {
+      Some(max) if len > max => Err(fail::MesherFail::PacketTooLarge(len, max)),
+      _ => Ok(()),
+    }
+  }
+
+  /// Sets (or, with `None`, removes) the [limit](struct.RateLimit.html) on how many packets are processed from each
+  /// sender on each transport.
+  ///
+  /// Every sender starts with a full bucket whenever the limit changes.
+  pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+    self.rate_limit = limit;
+    self.buckets.clear();
+  }
+
+  /// Sets (or, with `None`, removes) the most packets each transport gets processed in one [`receive`](#method.receive).
+  ///
+  /// Without a limit, a transport that's received a flood of packets has them all processed before the next
+  /// `receive` returns, which can hold up everything else.
+  /// With one, the packets over the limit are held for later calls, which process the held packets before newer ones.
+  /// Either way, transports take turns: each has its oldest packet processed, then each its next one, and so on, in
+  /// order of their schemes.
+  ///
+  /// Unlike the [rate limit](#method.set_rate_limit), nothing is dropped, so a transport that keeps receiving more
+  /// than the limit will build up a backlog.
+  pub fn set_receive_limit(&mut self, limit: Option<usize>) {
+    self.receive_limit = limit;
+  }
+
+  /// Sets (or, with `None`, removes) the limit on how many packets each transport holds on to between receives.
+  ///
+  /// Transports which [support it](trait.Transport.html#method.set_max_buffered) drop packets that arrive while
+  /// they're full, so a flood of packets between receives can't use up all of the memory.
+  /// There's no limit until one's set.
+  pub fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.max_buffered = max;
+    for transport in self.transports.values_mut() {
+      transport.set_max_buffered(max);
+    }
+  }
+
+  /// Sets (or, with `None`, removes) the [limit](struct.InboundQueue.html) on how many received packets are held for
+  /// later receives, and what happens to the rest.
+  ///
+  /// Without one, the mesher holds as many as it's handed.
+  /// Packets already held aren't dropped when the limit changes, but no more are added until there's room.
+  pub fn set_inbound_queue(&mut self, queue: Option<InboundQueue>) {
+    self.inbound_queue = queue;
+  }
+
+  /// How many packets received by the transport for the given scheme have been dropped because its
+  /// [inbound queue](struct.InboundQueue.html) was full.
+  pub fn inbound_dropped(&self, scheme: &str) -> u64 {
+    self
+      .inbound_dropped
+      .get(self.primary_scheme(scheme))
+      .copied()
+      .unwrap_or(0)
+  }
+
+  /// How many packets received by the transport for the given scheme are being held for a later
+  /// [`receive`](#method.receive) by the [receive limit](#method.set_receive_limit).
+  pub fn backlog(&self, scheme: &str) -> usize {
+    self.backlog.get(self.primary_scheme(scheme)).map_or(0, VecDeque::len)
+  }
+
+  /// How many packets received by the transport for the given scheme have been dropped because of the rate limit.
+  pub fn rate_limited(&self, scheme: &str) -> u64 {
+    self.rate_limited.get(self.primary_scheme(scheme)).copied().unwrap_or(0)
+  }
+
+  /// Sets (or, with `None`, removes) the [quota](struct.Quota.html) on how much the transport for the given scheme can
+  /// send.
+  ///
+  /// Setting a quota starts a new period, even if it's the same as the old one.
+  pub fn set_quota(&mut self, scheme: &str, quota: Option<Quota>) {
+    let scheme = self.primary_scheme(scheme).to_owned();
+    self.meters.entry(scheme).or_default().set_quota(quota);
+  }
+
+  /// How much has been sent and received through the transport for the given scheme.
+  pub fn usage(&self, scheme: &str) -> Usage {
+    self
+      .meters
+      .get(self.primary_scheme(scheme))
+      .map(|m| m.usage)
+      .unwrap_or_default()
+  }
+
+  /// How many more bytes the transport for the given scheme can send in its quota's current period, or `None` if it
+  /// doesn't have a quota.
+  pub fn quota_remaining(&self, scheme: &str) -> Option<u64> {
+    self.meters.get(self.primary_scheme(scheme)).and_then(Meter::remaining)
+  }
+
+  /// Fails with [`MesherFail::QuotaExceeded`](fail/enum.MesherFail.html#variant.QuotaExceeded) if sending `bytes` more
+  /// bytes through the transport for `scheme` would go over its quota.
+  fn check_quota(&self, scheme: &str, bytes: usize) -> fail::Result<()> {
+    match self.meters.get(self.primary_scheme(scheme)) {
+      Some(meter) if !meter.allows(bytes as u64) => Err(fail::MesherFail::QuotaExceeded(scheme.to_owned())),
+      _ => Ok(()),
+    }
+  }
+
+  /// Sets (or, with `None`, removes) the longest this mesher will hold a packet it's relaying when the sender
+  /// [asks it to](struct.Packet.html#method.add_hold_until), e.g. for a dead drop.
+  ///
+  /// Relays don't hold packets unless this is set, since every held packet takes up memory until it's sent.
+  /// Packets asking to be held longer are only held this long.
+  /// Held packets are sent during a later [`receive`](#method.receive) once they're due, and go through the
+  /// [forward policy](#method.set_forward_policy), [mixing](#method.set_mix_policy), and so on then, as if they'd just
+  /// arrived, except that they don't get [failure notices](struct.ReplyPathHandle.html#method.use_for_failure_notice).
+  pub fn set_max_hold(&mut self, max: Option<Duration>) {
+    self.max_hold = max;
+  }
+
+  /// Sets how far this mesher allows for its clock being off from senders' when checking whether packets have
+  /// [expired](struct.Packet.html#method.add_expiry).
+  /// It starts out at [`DEFAULT_CLOCK_SKEW`](#associatedconstant.DEFAULT_CLOCK_SKEW).
+  ///
+  /// Packets are only dropped once it's been this long since they expired, by this mesher's clock.
+  pub fn set_clock_skew(&mut self, skew: Duration) {
+    self.clock_skew = skew;
+  }
+
+  /// Whether a packet that expires at the given Unix time, in milliseconds, has, allowing for clock skew.
+  fn is_expired(&self, at: u64) -> bool {
+    let at = std::time::UNIX_EPOCH + Duration::from_millis(at) + self.clock_skew;
+    at < std::time::SystemTime::now()
+  }
+
+  /// Works out when to send a packet whose sender asked for it to be held until the given Unix time, in milliseconds,
+  /// or `None` to send it right away.
+  fn hold_until(&self, until: u64) -> Option<Instant> {
+    let max = self.max_hold?;
+    let until = std::time::UNIX_EPOCH + Duration::from_millis(until);
+    let delay = until.duration_since(std::time::SystemTime::now()).ok()?.min(max);
+    Some(Instant::now() + delay)
+  }
+
+  /// Sets up (or, with `None`, turns off) [mixing](struct.MixPolicy.html) for forwarded packets.
+  ///
+  /// Any packets held under the old policy are sent right away; failures sending them go to the [failure handler](#method.on_failure).
+  pub fn set_mix_policy(&mut self, policy: Option<MixPolicy>) {
+    let mut failures = vec![];
+    self.flush_mix_pool(true, &mut failures);
+    for f in failures {
+      self.report_failure(f);
+    }
+    self.mix_pool = policy.map(MixPool::new);
+  }
+
+  // Sends the given bytes along the given path, getting the appropriate transport.
+  fn send_data(&mut self, packet: &[u8], path: &str, priority: Priority) -> fail::Result<()> {
+    let path = Path::parse(path)?;
+    self.check_size(packet.len())?;
+    self.check_quota(path.scheme(), packet.len())?;
+    if let Err(e) = self
+      .get_transport_for_path(&path)?
+      .send_with_priority(&path, packet, priority)
+    {
+      self.event(|h| h.on_transport_error(path.scheme(), &e));
+      return Err(e);
+    }
+    debug_event!(path = %path, bytes = packet.len(), "sent packet");
+    self.metric(|m| m.sent(path.scheme(), packet.len()));
+    let scheme = self.primary_scheme(path.scheme()).to_owned();
+    self.meters.entry(scheme).or_default().sent(packet.len());
+    Ok(())
+  }
+
+  // Sends several packets along the same path as one batch, failing without sending any if one is too big.
+  fn send_batch_data(&mut self, packets: &[&[u8]], path: &str) -> fail::Result<()> {
+    let path = Path::parse(path)?;
+    for packet in packets {
+      self.check_size(packet.len())?;
+    }
+    self.check_quota(path.scheme(), packets.iter().map(|p| p.len()).sum())?;
+    if let Err(e) = self.get_transport_for_path(&path)?.send_batch(&path, packets) {
+      self.event(|h| h.on_transport_error(path.scheme(), &e));
+      return Err(e);
+    }
+    debug_event!(path = %path, packets = packets.len(), "sent batch");
+    let scheme = self.primary_scheme(path.scheme()).to_owned();
+    for packet in packets {
+      self.metric(|m| m.sent(path.scheme(), packet.len()));
+      self.meters.entry(scheme.clone()).or_default().sent(packet.len());
+    }
+    Ok(())
+  }
+
+  /// Tells the event handler, if there is one, about every path whose listener's status changed since the last check.
+  ///
+  /// Paths seen for the first time only count as changed if they're not listening.
+  fn check_health(&mut self) {
+    if self.event_handler.is_none() {
+      return;
+    }
+    let health = self.health();
+    for (path, status) in &health {
+      let changed = match self.listen_statuses.get(path.as_str()) {
+        Some(was) => was != status,
+        None => *status != ListenStatus::Listening,
+      };
+      if changed {
+        debug_event!(path = %path, status = ?status, "listener status changed");
+        self.event(|h| h.on_listen_status(path, status));
+      }
+    }
+    self.listen_statuses = health.into_iter().map(|(p, s)| (p.as_str().to_owned(), s)).collect();
+  }
+
+  /// Tells the metrics, if there are any, about something.
+  fn metric(&self, f: impl FnOnce(&dyn Metrics)) {
+    if let Some(m) = &self.metrics {
+      f(m.as_ref());
+    }
+  }
+
+  /// Tells the event handler, if there is one, about something.
+  fn event(&mut self, f: impl FnOnce(&mut dyn MesherEvents)) {
+    if let Some(h) = &mut self.event_handler {
+      f(h.as_mut());
+    }
+  }
+
+  /// Sets the [handler](trait.MesherEvents.html) to tell about things the mesher does that don't otherwise surface, like forwarding packets.
+  ///
+  /// Setting a new handler replaces the old one.
+  pub fn set_event_handler(&mut self, handler: impl MesherEvents + 'static) {
+    self.event_handler = Some(Box::new(handler));
+  }
+
+  /// Sets (or, with `None`, removes) the [`Metrics`](metrics/trait.Metrics.html) to tell about everything the mesher does with packets.
+  pub fn set_metrics(&mut self, metrics: Option<Arc<dyn Metrics>>) {
+    self.metrics = metrics;
+  }
+
+  /// Adds a transport to the mesher, for it to send and receive data through.
+  /// The scheme is passed to the transport exactly as-is.
+  /// If an initialization error occurs in the transport, nothing is added to the internal scheme mapping.
+  ///
+  /// Fails with [`MesherFail::AlreadyRegistered`](fail/enum.MesherFail.html#variant.AlreadyRegistered) if the scheme already has a transport.
+  /// To swap it out, use [`replace_transport`](#method.replace_transport).
+  /// Fails with [`MesherFail::Unsupported`](fail/enum.MesherFail.html#variant.Unsupported) if the transport
+  /// [doesn't support](trait.Transport.html#method.supported_schemes) the scheme.
+  pub fn add_transport<T: Transport>(&mut self, scheme: &str) -> fail::Result<()> {
+    self.add_transport_for_schemes::<T>(&[scheme])
+  }
+
+  /// Adds one transport to the mesher for several schemes, e.g. `tcp` and `tcp6`, or `http` and `https`, so paths with
+  /// any of them are sent and listened on through it.
+  ///
+  /// The transport's created with the first scheme, and the schemes share its counters and limits, e.g. its
+  /// [quota](#method.set_quota) and [backlog](#method.backlog), which can be looked up by any of them.
+  /// Otherwise, it fails like [`add_transport`](#method.add_transport), if any of the schemes already has a transport or
+  /// isn't supported, in which case none of them are added.
+  ///
+  /// Panics if there are no schemes.
+  pub fn add_transport_for_schemes<T: Transport>(&mut self, schemes: &[&str]) -> fail::Result<()> {
+    let (first, others) = schemes.split_first().expect("a transport needs at least one scheme");
+    for scheme in schemes {
+      if self.has_transport(scheme) {
+        return Err(fail::MesherFail::AlreadyRegistered((*scheme).to_owned()));
+      }
+      check_supported::<T>(scheme)?;
+    }
+    let mut transport = T::new(first)?;
+    transport.set_max_packet_size(self.max_packet_size);
+    transport.set_max_buffered(self.max_buffered);
+    debug_event!(scheme = *first, transport = transport.name(), "added transport");
+    self.transports.insert((*first).to_owned(), Box::new(transport));
+    for scheme in others.iter().filter(|s| *s != first) {
+      self.aliases.insert((*scheme).to_owned(), (*first).to_owned());
+    }
+    Ok(())
+  }
+
+  /// Drops the transport for the given scheme, and forgets every scheme it was added for, returning the one it was kept
+  /// under, if there was one.
+  fn drop_transport(&mut self, scheme: &str) -> Option<String> {
+    let primary = self.primary_scheme(scheme).to_owned();
+    self.transports.remove(&primary)?;
+    self.aliases.retain(|_, p| *p != primary);
+    Some(primary)
+  }
+
+  /// Replaces the transport for a scheme with a new one, or adds it if there wasn't one.
+  ///
+  /// The old transport is shut down (i.e. dropped) *before* the new one is created, so that e.g. ports it was using are freed up.
+  /// That means if the new one fails to initialize, the scheme is left with no transport at all.
+  /// It also means that any packets the old transport had received but not handed over are lost, and the new one won't be listening on any of the old one's paths.
+  ///
+  /// If the old transport was [added for several schemes](#method.add_transport_for_schemes), the new one only
+  /// replaces it for this one, and the others are left without a transport.
+  pub fn replace_transport<T: Transport>(&mut self, scheme: &str) -> fail::Result<()> {
+    self.drop_transport(scheme);
+    self.add_transport::<T>(scheme)
+  }
+
+  /// Removes the transport registered for the given scheme, returning whether there was one.
+  ///
+  /// Paths with that scheme can't be sent along or listened on until another transport is added for it.
+  /// The transport is dropped immediately, so any packets it had received but not yet handed over are lost.
+  /// If it was [added for several schemes](#method.add_transport_for_schemes), it's removed for all of them.
+  pub fn remove_transport(&mut self, scheme: &str) -> bool {
+    match self.drop_transport(scheme) {
+      Some(primary) => {
+        self.backlog.remove(&primary);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Lists the schemes which currently have transports registered, sorted, including every scheme a transport was
+  /// [added for](#method.add_transport_for_schemes).
+  pub fn transports(&self) -> Vec<&str> {
+    let mut schemes: Vec<_> = self
+      .transports
+      .keys()
+      .chain(self.aliases.keys())
+      .map(String::as_str)
+      .collect();
+    schemes.sort_unstable();
+    schemes
+  }
+
+  /// Has the mesher listen on the given path for messages.
+  /// This determines the transport to connect to based on the scheme, then just tells it to listen.
+  /// The exact behavior depends on the transport, but will generally involve either setting up some listener, or adding it to a list of internal paths to poll.
+  ///
+  /// The returned handle can be used to stop listening on just this path later, or you can use [`stop_listening`](#method.stop_listening).
+  pub fn listen_on(&mut self, path: &str) -> fail::Result<ListenHandle> {
+    let path = Path::parse(path)?;
+    self.get_transport_for_path(&path)?.listen(&path)?;
+    Ok(ListenHandle { path })
+  }
+
+  /// Has the mesher stop listening on the given path, which was previously passed to [`listen_on`](#method.listen_on).
+  ///
+  /// Not every transport can stop listening; those that can't will fail with [`MesherFail::Unsupported`](fail/enum.MesherFail.html#variant.Unsupported).
+  pub fn stop_listening(&mut self, path: &str) -> fail::Result<()> {
+    let path = Path::parse(path)?;
+    self.get_transport_for_path(&path)?.unlisten(&path)
+  }
+
+  /// How the listener on every path the mesher's listening on is doing, according to each transport's
+  /// [`status`](trait.Transport.html#method.status), sorted by path.
+  ///
+  /// Paths whose transports can't tell how they're doing aren't included.
+  pub fn health(&self) -> Vec<(Path, ListenStatus)> {
+    let mut health: Vec<_> = self.transports.values().flat_map(|t| t.status()).collect();
+    health.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    health
+  }
+
+  /// Sends a packet out.
+  ///
+  /// Note that while the outgoing packet is processed like any incoming one, any messages destined for this mesher are ignored.
+  ///
+  /// If sending along any of the packet's paths fails, it'll still be sent along the rest, and the first failure is returned.
+  ///
+  /// The packet's sent with [`Priority::Normal`](enum.Priority.html#variant.Normal); use
+  /// [`launch_with_priority`](#method.launch_with_priority) to pick another.
+  pub fn launch(&mut self, packet: Packet) -> fail::Result<()> {
+    self.launch_with_priority(packet, Priority::Normal)
+  }
+
+  /// Sends a packet out, like [`launch`](#method.launch), with the given priority.
+  ///
+  /// The priority decides which packets go first when several are waiting, e.g. in the [mix pool](struct.MixPolicy.html),
+  /// the [outbound queue](struct.OutboundQueue.html), or a transport that's still connecting, so that e.g. control
+  /// messages aren't stuck behind a big file transfer.
+  pub fn launch_with_priority(&mut self, packet: Packet, priority: Priority) -> fail::Result<()> {
+    self.expect_receipts(&packet.receipts);
+    let mut failures = vec![];
+    self.process_packet(packet.serialize()?, None, priority, &mut failures);
+    match failures.into_iter().next() {
+      Some(e) => Err(e),
+      None => Ok(()),
+    }
+  }
+
+  /// Sends a packet out, like [`launch`](#method.launch), but not until `at`.
+  ///
+  /// The packet's serialized right away, so that can fail here, but it's sent during the first
+  /// [`receive`](#method.receive) after `at`, and failures sending it go to the [failure handler](#method.on_failure).
+  /// [`next_scheduled`](#method.next_scheduled) says when that is, for event loops that sleep between receives.
+  pub fn launch_at(&mut self, packet: Packet, at: Instant) -> fail::Result<()> {
+    self.expect_receipts(&packet.receipts);
+    self
+      .schedule
+      .hold(at, Scheduled::Launch(packet.serialize()?, Priority::Normal));
+    Ok(())
+  }
+
+  /// Sends a packet out, like [`launch_at`](#method.launch_at), once `delay` has passed.
+  pub fn launch_after(&mut self, packet: Packet, delay: Duration) -> fail::Result<()> {
+    self.launch_at(packet, Instant::now() + delay)
+  }
+
+  /// Makes a single-use [reply block](struct.ReplyBlock.html) along `route`, for someone to reply to this mesher through
+  /// without learning the route.
+  ///
+  /// The route starts at the first relay the reply is sent to, and ends at this mesher, at a path it's listening on.
+  /// Each relay's header is signed with the mesher's [signing key](#method.set_signing_key), if it has one, so signed
+  /// relays accept it; the reply itself is never signed.
+  ///
+  /// Fails with [`MesherFail::NoRoute`](fail/enum.MesherFail.html#variant.NoRoute) if the route is empty or doesn't
+  /// end at one of this mesher's keys.
+  pub fn reply_block(&mut self, route: &Route) -> fail::Result<ReplyBlock> {
+    let ends_here = route.nodes().last().is_some_and(|(_, pkey)| {
+      self
+        .own_skeys
+        .iter()
+        .any(|sk| &self.crypto.encrypt_public_key(sk) == pkey)
+    });
+    if !ends_here {
+      return Err(fail::MesherFail::NoRoute(
+        "a reply block's route has to end at the mesher making it".to_owned(),
+      ));
+    }
+    let (pkey, skey) = self.crypto.gen_encrypt_keypair();
+    let block = ReplyBlock::build(route, ReplyBlockId(rand::random()), pkey, || self.new_packet(None))?;
+    self.reply_keys.insert(block.id, skey);
+    Ok(block)
+  }
+
+  /// Sends `data` back through a [reply block](struct.ReplyBlock.html), returning the reply's ID.
+  ///
+  /// Only the block's first relay is known to this mesher, so it's sent there like a launched packet, even if the
+  /// mesher's own paths would be a shorter way to the block's maker.
+  /// Fails like [`launch`](#method.launch).
+  pub fn send_reply(&mut self, block: &ReplyBlock, data: &[u8]) -> fail::Result<MessageId> {
+    let mut reply = Packet::unsigned_using(self.crypto.clone());
+    let id = reply.add_message(data, &block.key);
+    let mut failures = vec![];
+    self.forward(
+      block.wrap(reply.main_path),
+      block.first_hop.clone(),
+      None,
+      Priority::Normal,
+      &mut failures,
+    );
+    match failures.into_iter().next() {
+      Some(e) => Err(e),
+      None => Ok(id),
+    }
+  }
+
+  /// When the next packet [launched for later](#method.launch_at), or being held for its sender, is due to be sent, if
+  /// there are any.
+  pub fn next_scheduled(&self) -> Option<Instant> {
+    self.schedule.next_due()
+  }
+
+  /// Sends the scheduled packets which are due, or all of them if `all` is set, earliest first.
+  fn send_scheduled(&mut self, all: bool, failures: &mut Vec<fail::MesherFail>) {
+    let due = if all {
+      self.schedule.drain()
+    } else {
+      self.schedule.due(Instant::now())
+    };
+    for scheduled in due {
+      match scheduled {
+        Scheduled::Launch(packet, priority) => {
+          self.process_packet(packet, None, priority, failures);
+        }
+        Scheduled::Forward(packet, path, from, priority) => {
+          self.forward(packet, path, from.as_ref(), priority, failures)
+        }
+      }
+    }
+  }
+
+  /// Sends the packets launched through handles since this was last called, most urgent first.
+  fn send_launched(&mut self, failures: &mut Vec<fail::MesherFail>) {
+    let mut launched: Vec<_> = self.launches.1.try_iter().collect();
+    launched.sort_by_key(|(_, priority)| *priority);
+    for (packet, priority) in launched {
+      if let Err(e) = self.launch_with_priority(packet, priority) {
+        failures.push(e);
+      }
+    }
+  }
+
+  /// Shuts the mesher down cleanly: sends what it's holding, then closes every transport, waiting up to `timeout`
+  /// altogether for them to finish.
+  ///
+  /// Packets [launched](struct.MesherHandle.html#method.launch) through handles but not sent yet are sent, as are the
+  /// ones [scheduled](#method.launch_at) or held for their senders, even if they aren't due yet, the ones held for
+  /// [mixing](#method.set_mix_policy), and the ones due to be retried from the
+  /// [outbound queue](#method.set_outbound_queue).
+  /// The outbound queue's on disk, so whatever's still in it is retried by the next mesher using the same directory.
+  /// Then each transport is [closed](trait.Transport.html#method.close), e.g. stopping its listener threads, which frees
+  /// up the addresses they were listening on.
+  ///
+  /// Everything's done even if some of it fails, and the first failure is returned.
+  /// Just dropping a mesher also stops its transports' listeners, but doesn't wait for them, and loses whatever it was
+  /// holding.
+  pub fn close(mut self, timeout: Duration) -> fail::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut failures = vec![];
+    self.send_launched(&mut failures);
+    self.send_scheduled(true, &mut failures);
+    self.flush_mix_pool(true, &mut failures);
+    self.retry_queue(&mut failures);
+    for (_scheme, transport) in self.transports.iter_mut() {
+      if let Err(e) = transport.close(deadline.saturating_duration_since(Instant::now())) {
+        debug_event!(scheme = %_scheme, transport = transport.name(), "transport failed to close");
+        failures.push(e);
+      }
+    }
+    match failures.into_iter().next() {
+      Some(e) => Err(e),
+      None => Ok(()),
+    }
+  }
+
+  /// Creates a [handle](struct.MesherHandle.html) which can launch packets through this mesher from other threads.
+  ///
+  /// Packets launched through it are sent at the start of the next [`receive`](#method.receive).
+  pub fn handle(&self) -> MesherHandle {
+    MesherHandle {
+      launches: self.launches.0.clone(),
+    }
+  }
+
+  /// Gets pending messages from all of the transports along all of the paths they've been told to use.
+  ///
+  /// First, though, packets [launched](struct.MesherHandle.html#method.launch) through handles since the last call are
+  /// sent, most urgent first, and failures sending them go to the failure handler.
+  ///
+  /// Every transport is received from, in order of their schemes, even if some of them fail, and every packet received
+  /// is processed, even if some of them fail, e.g. because they're malformed or can't be forwarded.
+  /// Transports take turns having their packets processed, and a [receive limit](#method.set_receive_limit) can hold
+  /// some back for later calls.
+  /// Those failures are passed to the handler set by [`on_failure`](#method.on_failure) instead of being returned, so
+  /// one bad packet doesn't cost you the rest of the batch.
+  pub fn receive(&mut self) -> fail::Result<Vec<Message>> {
+    self.receive_at_most(None).map(|(messages, _)| messages)
+  }
+
+  /// Receives like [`receive`](#method.receive), but processes at most `max_packets` packets, and takes no more than
+  /// that from any transport, so a flood doesn't all end up in memory at once.
+  ///
+  /// Also returns whether there might be more packets waiting, either held by the mesher or still in a transport, in
+  /// which case it should be called again soon.
+  /// Transports which can't hand over only some of their packets (see
+  /// [`Transport::receive_up_to`](trait.Transport.html#method.receive_up_to)) hand over everything, and the mesher
+  /// holds what it doesn't process for later calls, as with the [receive limit](#method.set_receive_limit).
+  pub fn receive_limited(&mut self, max_packets: usize) -> fail::Result<(Vec<Message>, bool)> {
+    self.receive_at_most(Some(max_packets))
+  }
+
+  /// Keeps [receiving](#method.receive) until there's at least one message, or `timeout` has passed, for callers that
+  /// would otherwise sleep between receives themselves.
+  ///
+  /// Receives at least once, even with a timeout of 0, and returns as soon as any messages come in; an empty result
+  /// means the timeout passed without any.
+  /// Packets are still forwarded, and scheduled ones sent, while it's waiting, so long as it's waiting at all.
+  pub fn receive_wait(&mut self, timeout: Duration) -> fail::Result<Vec<Message>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+      let messages = self.receive()?;
+      let left = deadline.saturating_duration_since(Instant::now());
+      if !messages.is_empty() || left.is_zero() {
+        return Ok(messages);
+      }
+      sleep(left.min(RECEIVE_POLL_INTERVAL));
+    }
+  }
+
+  /// The old name for [`receive`](#method.receive).
+  #[deprecated(since = "0.8.0", note = "use `receive` instead")]
+  pub fn recv(&mut self) -> fail::Result<Vec<Message>> {
+    self.receive()
+  }
+
+  /// The old name for [`receive`](#method.receive), which never blocked anyway.
+  #[deprecated(since = "0.8.0", note = "use `receive`, which never blocks, instead")]
+  pub fn try_recv(&mut self) -> fail::Result<Vec<Message>> {
+    self.receive()
+  }
+
+  /// The old name for [`receive_limited`](#method.receive_limited).
+  #[deprecated(since = "0.8.0", note = "use `receive_limited` instead")]
+  pub fn recv_limit(&mut self, max_packets: usize) -> fail::Result<(Vec<Message>, bool)> {
+    self.receive_limited(max_packets)
+  }
+
+  /// The old name for [`receive_wait`](#method.receive_wait).
+  #[deprecated(since = "0.8.0", note = "use `receive_wait` instead")]
+  pub fn recv_wait(&mut self, timeout: Duration) -> fail::Result<Vec<Message>> {
+    self.receive_wait(timeout)
+  }
+
+  /// Does the work for [`receive`](#method.receive) and [`receive_limited`](#method.receive_limited).
+  fn receive_at_most(&mut self, max: Option<usize>) -> fail::Result<(Vec<Message>, bool)> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("receive").entered();
+    self.drop_retired_keys();
+    if self.own_skeys.is_empty() {
+      return Err(fail::MesherFail::NoKeys);
+    }
+    let mut failures = vec![];
+    let announce_due = match (&self.discovery, self.last_announced) {
+      (Some(d), Some(at)) => at.elapsed() >= d.interval,
+      (Some(_), None) => true,
+      (None, _) => false,
+    };
+    if announce_due {
+      if let Err(e) = self.announce() {
+        failures.push(e);
+      }
+    }
+    self.send_launched(&mut failures);
+    self.send_scheduled(false, &mut failures);
+    let mut more = false;
+    for (scheme, transport) in self.transports.iter_mut() {
+      let held = self.backlog.get(scheme).map_or(0, VecDeque::len);
+      let room = match (max, self.inbound_queue.and_then(|q| q.room(held))) {
+        (Some(max), Some(queue)) => Some(max.saturating_sub(held).min(queue)),
+        (Some(max), None) => Some(max.saturating_sub(held)),
+        (None, queue) => queue,
+      };
+      if room == Some(0) {
+        more = true;
+        continue;
+      }
+      let received = match room {
+        Some(room) => transport.receive_up_to(room),
+        None => transport.receive(),
+      };
+      let received = match received {
+        Ok(p) => {
+          more |= room.is_some_and(|room| p.len() >= room);
+          p
+        }
+        Err(e) => {
+          debug_event!(scheme = %scheme, transport = transport.name(), "transport failed to receive");
+          if let Some(h) = &mut self.event_handler {
+            h.on_transport_error(scheme, &e);
+          }
+          failures.push(e);
+          continue;
+        }
+      };
+      if !received.is_empty() {
+        debug_event!(scheme = %scheme, transport = transport.name(), packets = received.len(), "received packets");
+      }
+      let meter = self.meters.entry(scheme.clone()).or_default();
+      for (_, p) in &received {
+        meter.received(p.len());
+        if let Some(m) = &self.metrics {
+          m.received(scheme, p.len());
+        }
+      }
+      let max = self.max_packet_size.unwrap_or(usize::MAX);
+      let (received, oversized): (Vec<_>, Vec<_>) = received.into_iter().partition(|(_, p)| p.len() <= max);
+      for (_source, p) in oversized {
+        debug_event!(scheme = %scheme, remote = ?_source.remote(), bytes = p.len(), "dropped oversized packet");
+        if let Some(m) = &self.metrics {
+          m.dropped(DropReason::Oversized);
+        }
+        failures.push(fail::MesherFail::PacketTooLarge(p.len(), max));
+      }
+      let received: Vec<_> = match &self.rate_limit {
+        Some(limit) => {
+          let buckets = &mut self.buckets;
+          let (allowed, limited): (Vec<_>, Vec<_>) = received
+            .into_iter()
+            .partition(|(source, _)| buckets.take(scheme, source, limit));
+          for (_source, _) in limited {
+            debug_event!(scheme = %scheme, remote = ?_source.remote(), "dropped packet over rate limit");
+            *self.rate_limited.entry(scheme.clone()).or_insert(0) += 1;
+            if let Some(m) = &self.metrics {
+              m.dropped(DropReason::RateLimited);
+            }
+          }
+          allowed
+        }
+        None => received,
+      };
+      let backlog = self.backlog.entry(scheme.clone()).or_default();
+      match &self.inbound_queue {
+        Some(queue) => {
+          for packet in received {
+            if queue.push(backlog, packet) {
+              debug_event!(scheme = %scheme, "dropped packet, since the inbound queue was full");
+              *self.inbound_dropped.entry(scheme.clone()).or_insert(0) += 1;
+              if let Some(m) = &self.metrics {
+                m.dropped(DropReason::QueueFull);
+              }
+            }
+          }
+        }
+        None => backlog.extend(received),
+      }
+    }
+    self.check_health();
+    let total = max.unwrap_or(usize::MAX);
+    let mut packets = vec![];
+    'rounds: for _ in 0..self.receive_limit.unwrap_or(usize::MAX) {
+      let before = packets.len();
+      for backlog in self.backlog.values_mut() {
+        if packets.len() >= total {
+          break 'rounds;
+        }
+        packets.extend(backlog.pop_front());
+      }
+      if packets.len() == before {
+        break;
+      }
+    }
+    more |= self.backlog.values().any(|b| !b.is_empty());
+    let mut messages = vec![];
+    for (source, p) in packets {
+      messages.append(&mut self.process_packet(p, Some(&source), Priority::Normal, &mut failures));
+    }
+    self.flush_mix_pool(false, &mut failures);
+    self.retry_queue(&mut failures);
+    for f in failures {
+      self.report_failure(f);
+    }
+    Ok((messages, more))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn empty_mesher_fails_receive() {
+    let mut empty = Mesher::unsigned(vec![]);
+
+    match empty.receive() {
+      Err(fail::MesherFail::NoKeys) => (),
+      _ => unreachable!(),
+    }
+  }
+
+  fn received(m: &mut Mesher) -> Vec<Vec<u8>> {
+    m.receive()
+      .expect("Failed to receive")
+      .into_iter()
+      .map(Message::into_contents)
+      .collect()
+  }
+
+  fn deliver(path: &str, packet: Packet) {
+    let mut t = crate::debug_transports::InMemory::new("inmem").expect("Failed to create transport");
+    let path = Path::parse(path).expect("Failed to parse path");
+    t.send(&path, &packet.serialize().expect("Failed to serialize"))
+      .expect("Failed to send");
+  }
+
+  fn signed_message(skey: &sign::SecretKey, pkey: &encrypt::PublicKey, data: &[u8]) -> Packet {
+    let mut packet = Packet::signed(skey.clone_secret());
+    packet.add_message(data, pkey);
+    packet
+  }
+
+  #[test]
+  fn sender_keys_added_and_removed() {
+    let (spk1, ssk1) = sign::gen_keypair();
+    let (spk2, ssk2) = sign::gen_keypair();
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::signed(vec![sk], vec![spk1]).expect("Failed to create mesher");
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:sender-keys").expect("Failed to listen");
+
+    deliver("inmem:sender-keys", signed_message(&ssk2, &pk, &[1]));
+    assert!(received(&mut m).is_empty());
+
+    m.add_sender_key(spk2);
+    assert_eq!(&[spk1, spk2], m.sender_keys());
+    deliver("inmem:sender-keys", signed_message(&ssk2, &pk, &[2]));
+    assert_eq!(vec![vec![2]], received(&mut m));
+
+    assert!(m.remove_sender_key(&spk1));
+    assert!(!m.remove_sender_key(&spk1));
+    deliver("inmem:sender-keys", signed_message(&ssk1, &pk, &[3]));
+    assert!(received(&mut m).is_empty());
+
+    // removing every key doesn't make the mesher accept unsigned packets
+    assert!(m.remove_sender_key(&spk2));
+    let mut unsigned = Packet::unsigned();
+    unsigned.add_message(&[4], &pk);
+    deliver("inmem:sender-keys", unsigned);
+    assert!(received(&mut m).is_empty());
+  }
+
+  #[test]
+  fn own_keys_rotated() {
+    let (old_pk, old_sk) = encrypt::gen_keypair();
+    let (new_pk, new_sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![old_sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:own-keys").expect("Failed to listen");
+
+    let message = |pk, data: &[u8]| {
+      let mut packet = Packet::unsigned();
+      packet.add_message(data, pk);
+      packet
+    };
+
+    m.add_own_key(new_sk);
+    assert!(m.retire_own_key(&old_pk, Duration::from_millis(200)));
+    assert!(!m.retire_own_key(&encrypt::gen_keypair().0, Duration::from_millis(200)));
+
+    // during the grace period, both keys work
+    deliver("inmem:own-keys", message(&old_pk, &[1]));
+    deliver("inmem:own-keys", message(&new_pk, &[2]));
+    assert_eq!(vec![vec![1], vec![2]], received(&mut m));
+
+    std::thread::sleep(Duration::from_millis(250));
+
+    // after it, only the new one does
+    deliver("inmem:own-keys", message(&old_pk, &[3]));
+    deliver("inmem:own-keys", message(&new_pk, &[4]));
+    assert_eq!(vec![vec![4]], received(&mut m));
+  }
+
+  #[test]
+  fn retiring_last_key_fails_receive() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.retire_own_key(&pk, Duration::from_secs(0));
+
+    match m.receive() {
+      Err(fail::MesherFail::NoKeys) => (),
+      _ => unreachable!(),
+    }
+  }
+
+  #[test]
+  fn bad_packet_doesnt_stop_batch() {
+    use std::sync::Mutex;
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:bad-packet").expect("Failed to listen");
+    let failures = Arc::new(Mutex::new(vec![]));
+    let handler_failures = failures.clone();
+    m.on_failure(move |f| handler_failures.lock().unwrap().push(f));
+
+    let mut t = crate::debug_transports::InMemory::new("inmem").expect("Failed to create transport");
+    let path = Path::parse("inmem:bad-packet").expect("Failed to parse path");
+    let mut good = Packet::unsigned();
+    good.add_message(&[1], &pk);
+    let mut unforwardable = Packet::unsigned();
+    unforwardable.add_hop("nope:nowhere".to_owned(), &pk);
+    unforwardable.add_message(&[2], &pk);
+    t.send(&path, &[1, 2, 3]).expect("Failed to send");
+    t.send(&path, &unforwardable.serialize().expect("Failed to serialize"))
+      .expect("Failed to send");
+    t.send(&path, &good.serialize().expect("Failed to serialize"))
+      .expect("Failed to send");
+
+    let mut got = received(&mut m);
+    got.sort();
+    assert_eq!(vec![vec![1], vec![2]], got);
+    let failures = failures.lock().unwrap();
+    assert_eq!(2, failures.len());
+    assert!(matches!(failures[0], fail::MesherFail::InvalidPacket));
+    assert!(matches!(failures[1], fail::MesherFail::UnregisteredScheme(_)));
+  }
+
+  #[test]
+  fn oversized_packets_dropped() {
+    use std::sync::Mutex;
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:oversized").expect("Failed to listen");
+    m.set_max_packet_size(Some(512));
+    let failures = Arc::new(Mutex::new(vec![]));
+    let handler_failures = failures.clone();
+    m.on_failure(move |f| handler_failures.lock().unwrap().push(f));
+
+    let mut t = crate::debug_transports::InMemory::new("inmem").expect("Failed to create transport");
+    let path = Path::parse("inmem:oversized").expect("Failed to parse path");
+    let mut big = Packet::unsigned();
+    big.add_message(&[0; 1024], &pk);
+    let mut small = Packet::unsigned();
+    small.add_message(&[1], &pk);
+    t.send(&path, &big.serialize().expect("Failed to serialize"))
+      .expect("Failed to send");
+    t.send(&path, &small.serialize().expect("Failed to serialize"))
+      .expect("Failed to send");
+
+    assert_eq!(vec![vec![1]], received(&mut m));
+    let failures = failures.lock().unwrap();
+    assert_eq!(1, failures.len());
+    assert!(matches!(failures[0], fail::MesherFail::PacketTooLarge(_, 512)));
+  }
+
+  struct Broken(String);
+
+  impl Transport for Broken {
+    fn new(scheme: &str) -> fail::Result<Self> {
+      Ok(Broken(scheme.to_owned()))
+    }
+
+    fn scheme(&self) -> &str {
+      &self.0
+    }
+
+    fn send(&mut self, _path: &Path, _blob: &[u8]) -> fail::Result<()> {
+      Err(fail::MesherFail::SendFailure("broken".to_owned()))
+    }
+
+    fn listen(&mut self, _path: &Path) -> fail::Result<()> {
+      Ok(())
+    }
+
+    fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+      Err(fail::MesherFail::ReceiveFailure("broken".to_owned()))
+    }
+  }
+
+  #[test]
+  fn transports_named_after_type_by_default() {
+    let t = Broken::new("broken").expect("Failed to create");
+    assert_eq!("broken", t.scheme());
+    assert!(t.name().ends_with("::Broken"));
+  }
+
+  #[test]
+  fn broken_transport_doesnt_stop_others() {
+    use std::sync::Mutex;
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<Broken>("broken").expect("Failed to add transport");
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:broken-transport").expect("Failed to listen");
+    let failures = Arc::new(Mutex::new(vec![]));
+    let handler_failures = failures.clone();
+    m.on_failure(move |f| handler_failures.lock().unwrap().push(f));
+
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[1], &pk);
+    deliver("inmem:broken-transport", packet);
+
+    assert_eq!(vec![vec![1]], received(&mut m));
+    let failures = failures.lock().unwrap();
+    assert_eq!(1, failures.len());
+    assert!(matches!(failures[0], fail::MesherFail::ReceiveFailure(_)));
+  }
+
+  #[test]
+  fn transports_listed_and_removed() {
+    let (_, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.add_transport::<Broken>("broken").expect("Failed to add transport");
+
+    let mut schemes = m.transports();
+    schemes.sort();
+    assert_eq!(vec!["broken", "inmem"], schemes);
+
+    assert!(m.remove_transport("broken"));
+    assert!(!m.remove_transport("broken"));
+    assert_eq!(vec!["inmem"], m.transports());
+    match m.listen_on("broken:foo") {
+      Err(fail::MesherFail::UnregisteredScheme(_)) => (),
+      _ => panic!("Removed transport should be unregistered"),
+    }
+  }
+
+  #[test]
+  fn duplicate_transport_rejected_unless_replaced() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<Broken>("dup").expect("Failed to add transport");
+    match m.add_transport::<crate::debug_transports::InMemory>("dup") {
+      Err(fail::MesherFail::AlreadyRegistered(s)) => assert_eq!("dup", s),
+      _ => panic!("Duplicate transport should be rejected"),
+    }
+
+    // the Broken transport is still there
+    let mut packet = Packet::unsigned();
+    packet.add_hop("dup:replaced".to_owned(), &pk);
+    assert!(m.launch(packet.clone()).is_err());
+
+    m.replace_transport::<crate::debug_transports::InMemory>("dup")
+      .expect("Failed to replace transport");
+    m.launch(packet).expect("Replaced transport should be used");
+  }
+
+  #[test]
+  fn transports_serve_several_schemes() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport_for_schemes::<crate::debug_transports::InMemory>(&["multi-a", "multi-b"])
+      .expect("Failed to add transport");
+    assert_eq!(vec!["multi-a", "multi-b"], m.transports());
+    match m.add_transport::<crate::debug_transports::InMemory>("multi-b") {
+      Err(fail::MesherFail::AlreadyRegistered(s)) => assert_eq!("multi-b", s),
+      _ => panic!("Aliased scheme should already be registered"),
+    }
+
+    m.listen_on("multi-b:aliased").expect("Failed to listen");
+    let mut packet = Packet::unsigned();
+    packet.add_hop("multi-b:aliased".to_owned(), &pk);
+    packet.add_message(&[1], &pk);
+    m.launch(packet).expect("Failed to launch");
+    assert_eq!(vec![vec![1]], received(&mut m));
+
+    // the schemes share one transport's limits
+    m.set_quota("multi-b", Some(Quota::new(1000, Duration::from_secs(60))));
+    assert_eq!(Some(1000), m.quota_remaining("multi-a"));
+
+    assert!(m.remove_transport("multi-b"));
+    assert!(m.transports().is_empty());
+  }
+
+  struct WebOnly(String);
+
+  impl Transport for WebOnly {
+    fn new(scheme: &str) -> fail::Result<Self> {
+      Ok(WebOnly(scheme.to_owned()))
+    }
+
+    fn scheme(&self) -> &str {
+      &self.0
+    }
+
+    fn supported_schemes() -> Option<&'static [&'static str]> {
+      Some(&["http", "https"])
+    }
+
+    fn send(&mut self, _path: &Path, _blob: &[u8]) -> fail::Result<()> {
+      Ok(())
+    }
+
+    fn listen(&mut self, _path: &Path) -> fail::Result<()> {
+      Ok(())
+    }
+
+    fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+      Ok(vec![])
+    }
+  }
+
+  #[test]
+  fn unsupported_schemes_rejected() {
+    let mut m = Mesher::unsigned(vec![]);
+    match m.add_transport_for_schemes::<WebOnly>(&["http", "gopher"]) {
+      Err(fail::MesherFail::Unsupported(msg)) => assert!(msg.contains("\"gopher\" scheme, only http, https")),
+      _ => panic!("Unsupported scheme should be rejected"),
+    }
+    // neither scheme was added
+    assert!(m.transports().is_empty());
+
+    m.add_transport_for_schemes::<WebOnly>(&["http", "https"])
+      .expect("Failed to add transport");
+    assert_eq!(vec!["http", "https"], m.transports());
+  }
+
+  #[test]
+  fn stop_listening_stops_receiving() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.add_transport::<Broken>("broken").expect("Failed to add transport");
+    m.listen_on("inmem:stop-listening").expect("Failed to listen");
+    m.stop_listening("inmem:stop-listening")
+      .expect("Failed to stop listening");
+
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[1], &pk);
+    deliver("inmem:stop-listening", packet);
+    assert!(received(&mut m).is_empty());
+
+    match m.stop_listening("broken:foo") {
+      Err(fail::MesherFail::Unsupported(_)) => (),
+      _ => panic!("Broken transport shouldn't support unlistening"),
+    }
+  }
+
+  #[test]
+  fn listen_handle_stops_one_path() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    let stopped = m.listen_on("inmem:handle-stopped").expect("Failed to listen");
+    m.listen_on("inmem:handle-kept").expect("Failed to listen");
+    assert_eq!("inmem:handle-stopped", stopped.path().as_str());
+    stopped.stop(&mut m).expect("Failed to stop listening");
+
+    for (path, data) in &[("inmem:handle-stopped", 1), ("inmem:handle-kept", 2)] {
+      let mut packet = Packet::unsigned();
+      packet.add_message(&[*data], &pk);
+      deliver(path, packet);
+    }
+    assert_eq!(vec![vec![2]], received(&mut m));
+  }
+
+  #[test]
+  fn health_covers_listening_paths() {
+    let mut m = Mesher::unsigned(vec![]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.add_transport::<Broken>("broken").expect("Failed to add transport");
+    m.listen_on("inmem:health-b").expect("Failed to listen");
+    m.listen_on("inmem:health-a").expect("Failed to listen");
+    // transports which can't report their status are just left out
+    m.listen_on("broken:health").expect("Failed to listen");
+
+    let paths = |m: &Mesher| {
+      m.health()
+        .into_iter()
+        .map(|(p, s)| (p.to_string(), s))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      vec![
+        ("inmem:health-a".to_owned(), ListenStatus::Listening),
+        ("inmem:health-b".to_owned(), ListenStatus::Listening),
+      ],
+      paths(&m)
+    );
+    m.stop_listening("inmem:health-a").expect("Failed to stop listening");
+    assert_eq!(vec![("inmem:health-b".to_owned(), ListenStatus::Listening)], paths(&m));
+  }
+
+  #[test]
+  fn listen_status_changes_reported() {
+    use std::sync::Mutex;
+
+    static STATUS: Mutex<ListenStatus> = Mutex::new(ListenStatus::Listening);
+    struct Flapping(String);
+    impl Transport for Flapping {
+      fn new(scheme: &str) -> fail::Result<Self> {
+        Ok(Flapping(scheme.to_owned()))
+      }
+      fn scheme(&self) -> &str {
+        &self.0
+      }
+      fn send(&mut self, _path: &Path, _blob: &[u8]) -> fail::Result<()> {
+        Ok(())
+      }
+      fn listen(&mut self, _path: &Path) -> fail::Result<()> {
+        Ok(())
+      }
+      fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+        Ok(vec![])
+      }
+      fn status(&self) -> Vec<(Path, ListenStatus)> {
+        vec![(Path::parse("flap:x").unwrap(), STATUS.lock().unwrap().clone())]
+      }
+    }
+    struct Log(Arc<Mutex<Vec<ListenStatus>>>);
+    impl MesherEvents for Log {
+      fn on_listen_status(&mut self, _path: &Path, status: &ListenStatus) {
+        self.0.lock().unwrap().push(status.clone());
+      }
+    }
+
+    let (_, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<Flapping>("flap").expect("Failed to add transport");
+    let events = Arc::new(Mutex::new(vec![]));
+    m.set_event_handler(Log(events.clone()));
+    let mut receive_as = |status| {
+      *STATUS.lock().unwrap() = status;
+      m.receive().expect("Failed to receive");
+    };
+    // a path that's fine from the start isn't news, and neither is one that hasn't changed
+    receive_as(ListenStatus::Listening);
+    receive_as(ListenStatus::Reconnecting);
+    receive_as(ListenStatus::Reconnecting);
+    receive_as(ListenStatus::Listening);
+    assert_eq!(
+      vec![ListenStatus::Reconnecting, ListenStatus::Listening],
+      *events.lock().unwrap()
+    );
+  }
+
+  #[test]
   fn signed_mesher_empty_keys_fails() {
-    let _empty = Mesher::signed(vec![], vec![]);
+    match Mesher::signed(vec![], vec![]) {
+      Err(fail::MesherFail::NoSenderKeys) => (),
+      _ => unreachable!(),
+    }
+  }
+
+  #[test]
+  fn tamper_policy_followed() {
+    use std::sync::Mutex;
+
+    let (spk, ssk) = sign::gen_keypair();
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::signed(vec![sk], vec![spk]).expect("Failed to create mesher");
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:tamper-policy").expect("Failed to listen");
+    let tampered = Arc::new(Mutex::new(0));
+    let counter = tampered.clone();
+    m.on_failure(move |f| {
+      if let fail::MesherFail::Tampered = f {
+        *counter.lock().unwrap() += 1;
+      }
+    });
+    let tampered_packet = || {
+      let mut packet = signed_message(&ssk, &pk, &[1]);
+      packet.add_message(&[2], &pk);
+      packet.main_path[0][0] ^= 1;
+      packet
+    };
+
+    deliver("inmem:tamper-policy", tampered_packet());
+    assert_eq!(vec![vec![2]], received(&mut m));
+    assert_eq!(1, *tampered.lock().unwrap());
+
+    m.set_tamper_policy(TamperPolicy::DropPacket);
+    deliver("inmem:tamper-policy", tampered_packet());
+    assert!(received(&mut m).is_empty());
+    assert_eq!(2, *tampered.lock().unwrap());
+  }
+
+  #[derive(Debug, PartialEq)]
+  struct Ping(u32);
+
+  impl CustomChunk for Ping {
+    const KIND: u16 = 7;
+
+    fn encode(&self) -> Vec<u8> {
+      self.0.to_be_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Ping> {
+      let mut num = [0; 4];
+      if bytes.len() != num.len() {
+        return None;
+      }
+      num.copy_from_slice(bytes);
+      Some(Ping(u32::from_be_bytes(num)))
+    }
+  }
+
+  #[test]
+  fn custom_chunks_dispatched() {
+    use std::sync::Mutex;
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:custom-chunks").expect("Failed to listen");
+    let pings = Arc::new(Mutex::new(vec![]));
+    let handler_pings = pings.clone();
+    m.on_chunk(move |p: Ping| handler_pings.lock().unwrap().push(p));
+
+    let mut packet = Packet::unsigned();
+    packet.add_custom(&Ping(1234), &pk);
+    packet.add_message(&[1], &pk);
+    deliver("inmem:custom-chunks", packet);
+
+    // custom chunks go to their handler, not to the messages
+    assert_eq!(vec![vec![1]], received(&mut m));
+    assert_eq!(vec![Ping(1234)], *pings.lock().unwrap());
+  }
+
+  #[test]
+  fn duplicates_dropped_in_window() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:dedup").expect("Failed to listen");
+    let resend = |id| {
+      let mut packet = Packet::unsigned();
+      packet.add_message_with_id(&[1], &pk, id);
+      deliver("inmem:dedup", packet);
+    };
+
+    // without a window, every copy is delivered
+    resend(MessageId(1));
+    resend(MessageId(1));
+    assert_eq!(2, received(&mut m).len());
+
+    m.set_dedup_window(Some(Duration::from_secs(60)));
+    resend(MessageId(1));
+    resend(MessageId(1));
+    resend(MessageId(2));
+    let messages = m.receive().expect("Failed to receive");
+    let mut ids: Vec<_> = messages.iter().map(|m| m.id().expect("Message had no ID").0).collect();
+    ids.sort_unstable();
+    assert_eq!(vec![1, 2], ids);
+  }
+
+  #[test]
+  fn forwarding_loops_broken() {
+    let counters = Arc::new(crate::metrics::Counters::new());
+    let make = |name: &str| {
+      let (pk, sk) = encrypt::gen_keypair();
+      let mut m = Mesher::unsigned(vec![sk]);
+      m.add_transport::<crate::debug_transports::InMemory>("inmem")
+        .expect("Failed to add transport");
+      m.listen_on(&format!("inmem:{}", name)).expect("Failed to listen");
+      m.set_loop_window(Some(Duration::from_secs(60)));
+      m.set_metrics(Some(counters.clone()));
+      (m, pk)
+    };
+    let (mut a, a_pk) = make("loop-a");
+    let (mut b, b_pk) = make("loop-b");
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("inmem:loop-b".to_owned(), &a_pk);
+    packet.add_hop("inmem:loop-a".to_owned(), &b_pk);
+    deliver("inmem:loop-a", packet);
+    for _ in 0..3 {
+      a.receive().expect("Failed to receive");
+      b.receive().expect("Failed to receive");
+    }
+
+    assert_eq!(3, counters.packets_received());
+    assert_eq!(2, counters.packets_sent());
+    assert_eq!(1, counters.packets_dropped(crate::metrics::DropReason::Looped));
+  }
+
+  #[test]
+  fn failed_forwards_queued() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<Broken>("flaky").expect("Failed to add transport");
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:queue-relay").expect("Failed to listen");
+    let dir = std::env::temp_dir().join(format!("mesher-queue-relay-{:x}", rand::random::<u32>()));
+    let queue = OutboundQueue::open(&dir)
+      .expect("Failed to open queue")
+      .retry_every(Duration::from_millis(0));
+    m.set_outbound_queue(Some(queue));
+    // the broken transport fails every receive, but nothing else should fail
+    m.on_failure(|f| {
+      assert!(
+        matches!(f, fail::MesherFail::ReceiveFailure(_)),
+        "Unexpected failure: {:?}",
+        f
+      )
+    });
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("flaky:queue-next".to_owned(), &pk);
+    deliver("inmem:queue-relay", packet);
+    received(&mut m);
+    assert_eq!(1, m.queued_packets());
+
+    // the transport's back up, so the retry goes through
+    m.replace_transport::<crate::debug_transports::InMemory>("flaky")
+      .expect("Failed to replace transport");
+    let mut next = crate::debug_transports::InMemory::new("flaky").expect("Failed to create transport");
+    next
+      .listen(&Path::parse("flaky:queue-next").unwrap())
+      .expect("Failed to listen");
+    received(&mut m);
+    assert_eq!(0, m.queued_packets());
+    assert_eq!(1, next.receive().expect("Failed to receive").len());
+    std::fs::remove_dir_all(&dir).expect("Failed to clean up");
+  }
+
+  #[test]
+  fn mixing_holds_forwards() {
+    let (relay_pk, relay_sk) = encrypt::gen_keypair();
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut relay = Mesher::unsigned(vec![relay_sk]);
+    relay
+      .add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    relay.listen_on("inmem:mix-relay").expect("Failed to listen");
+    relay.set_mix_policy(Some(MixPolicy::new(2, Duration::from_secs(3600))));
+    let mut dest = Mesher::unsigned(vec![sk]);
+    dest
+      .add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    dest.listen_on("inmem:mix-dest").expect("Failed to listen");
+    let through_relay = |data| {
+      let mut packet = Packet::unsigned();
+      packet.add_hop("inmem:mix-dest".to_owned(), &relay_pk);
+      packet.add_message(data, &pk);
+      deliver("inmem:mix-relay", packet);
+    };
+
+    through_relay(&[1]);
+    relay.receive().expect("Failed to relay");
+    assert!(received(&mut dest).is_empty());
+
+    // the second packet fills the batch, so both go out
+    through_relay(&[2]);
+    relay.receive().expect("Failed to relay");
+    let mut got = received(&mut dest);
+    got.sort();
+    assert_eq!(vec![vec![1], vec![2]], got);
+
+    // turning mixing off sends anything still held
+    through_relay(&[3]);
+    relay.receive().expect("Failed to relay");
+    relay.set_mix_policy(None);
+    assert_eq!(vec![vec![3]], received(&mut dest));
+  }
+
+  #[test]
+  fn closing_sends_whats_held() {
+    let (relay_pk, relay_sk) = encrypt::gen_keypair();
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut relay = Mesher::unsigned(vec![relay_sk]);
+    relay
+      .add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    relay.listen_on("inmem:close-relay").expect("Failed to listen");
+    relay.set_mix_policy(Some(MixPolicy::new(10, Duration::from_secs(3600))));
+    let mut dest = Mesher::unsigned(vec![sk]);
+    dest
+      .add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    dest.listen_on("inmem:close-dest").expect("Failed to listen");
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("inmem:close-dest".to_owned(), &relay_pk);
+    packet.add_message(&[1], &pk);
+    deliver("inmem:close-relay", packet);
+    relay.receive().expect("Failed to relay");
+    let mut packet = Packet::unsigned();
+    packet.add_hop("inmem:close-dest".to_owned(), &relay_pk);
+    packet.add_message(&[2], &pk);
+    relay.handle().launch(packet).expect("Failed to launch");
+    assert!(received(&mut dest).is_empty());
+
+    relay.close(Duration::from_secs(1)).expect("Failed to close");
+    let mut got = received(&mut dest);
+    got.sort();
+    assert_eq!(vec![vec![1], vec![2]], got);
+  }
+
+  #[test]
+  fn message_sources_recorded() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:sources").expect("Failed to listen");
+
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[1], &pk);
+    deliver("inmem:sources", packet);
+    let messages = m.receive().expect("Failed to receive");
+    let source = messages[0].source().expect("Received message should have a source");
+    assert_eq!(Some("inmem:sources"), source.listen_path().map(Path::as_str));
+    assert_eq!(None, source.remote());
+
+    // packets the mesher launches to itself never went through a transport
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[2], &pk);
+    let messages = m.process_packet(packet.serialize().unwrap(), None, Priority::Normal, &mut vec![]);
+    assert_eq!(None, messages[0].source());
+  }
+
+  #[test]
+  fn mixed_forwards_batched_by_path() {
+    use std::sync::Mutex;
+
+    // what each batch sent, as the path and how many packets were in it
+    static BATCHES: Mutex<Vec<(String, usize)>> = Mutex::new(vec![]);
+    struct Batching(String);
+    impl Transport for Batching {
+      fn new(scheme: &str) -> fail::Result<Self> {
+        Ok(Batching(scheme.to_owned()))
+      }
+      fn scheme(&self) -> &str {
+        &self.0
+      }
+      fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+        self.send_batch(path, &[blob])
+      }
+      fn send_batch(&mut self, path: &Path, blobs: &[&[u8]]) -> fail::Result<()> {
+        BATCHES.lock().unwrap().push((path.to_string(), blobs.len()));
+        Ok(())
+      }
+      fn listen(&mut self, _path: &Path) -> fail::Result<()> {
+        Ok(())
+      }
+      fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+        Ok(vec![])
+      }
+    }
+
+    let (relay_pk, relay_sk) = encrypt::gen_keypair();
+    let mut relay = Mesher::unsigned(vec![relay_sk]);
+    relay
+      .add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    relay
+      .add_transport::<Batching>("batchy")
+      .expect("Failed to add transport");
+    relay.listen_on("inmem:batch-relay").expect("Failed to listen");
+    relay.set_mix_policy(Some(MixPolicy::new(3, Duration::from_secs(3600))));
+    for next in &["batchy:a", "batchy:b", "batchy:a"] {
+      let mut packet = Packet::unsigned();
+      packet.add_hop(next.to_string(), &relay_pk);
+      deliver("inmem:batch-relay", packet);
+    }
+    relay.receive().expect("Failed to relay");
+
+    let mut batches = BATCHES.lock().unwrap().clone();
+    batches.sort();
+    assert_eq!(vec![("batchy:a".to_owned(), 2), ("batchy:b".to_owned(), 1)], batches);
+  }
+
+  #[test]
+  fn rate_limit_drops_floods() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:rate-limit").expect("Failed to listen");
+    // refills too slowly to matter during the test
+    m.set_rate_limit(Some(RateLimit::new(1, 3)));
+    for i in 0..5 {
+      let mut packet = Packet::unsigned();
+      packet.add_message(&[i], &pk);
+      deliver("inmem:rate-limit", packet);
+    }
+
+    assert_eq!(3, received(&mut m).len());
+    assert_eq!(2, m.rate_limited("inmem"));
+    assert_eq!(0, m.rate_limited("other"));
+  }
+
+  #[test]
+  fn receive_limit_takes_turns() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    for scheme in &["fair-b", "fair-a"] {
+      m.add_transport::<crate::debug_transports::InMemory>(scheme)
+        .expect("Failed to add transport");
+      m.listen_on(&format!("{}:receive-limit-{}", scheme, scheme))
+        .expect("Failed to listen");
+    }
+    assert_eq!(vec!["fair-a", "fair-b"], m.transports());
+    m.set_receive_limit(Some(2));
+    for i in 0..4 {
+      let mut packet = Packet::unsigned();
+      packet.add_message(&[i], &pk);
+      deliver("fair-a:receive-limit-fair-a", packet);
+    }
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[10], &pk);
+    deliver("fair-b:receive-limit-fair-b", packet);
+
+    assert_eq!(vec![vec![0], vec![10], vec![1]], received(&mut m));
+    assert_eq!(2, m.backlog("fair-a"));
+    assert_eq!(0, m.backlog("fair-b"));
+    assert_eq!(vec![vec![2], vec![3]], received(&mut m));
+    assert_eq!(0, m.backlog("fair-a"));
+  }
+
+  #[test]
+  fn receive_wait_returns_once_something_arrives() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:receive-wait").expect("Failed to listen");
+
+    let started = Instant::now();
+    assert!(m
+      .receive_wait(Duration::from_millis(20))
+      .expect("Failed to receive")
+      .is_empty());
+    assert!(started.elapsed() >= Duration::from_millis(20));
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("inmem:receive-wait".to_owned(), &pk);
+    packet.add_message(&[1], &pk);
+    m.launch_after(packet, Duration::from_millis(20))
+      .expect("Failed to schedule packet");
+    let messages = m.receive_wait(Duration::from_secs(5)).expect("Failed to receive");
+    assert_eq!(
+      vec![vec![1]],
+      messages.into_iter().map(Message::into_contents).collect::<Vec<_>>()
+    );
+  }
+
+  #[test]
+  #[allow(deprecated)]
+  fn old_receive_names_still_work() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:old-receive-names").expect("Failed to listen");
+    for i in 0..4 {
+      let mut packet = Packet::unsigned();
+      packet.add_message(&[i], &pk);
+      deliver("inmem:old-receive-names", packet);
+    }
+    assert_eq!(1, m.recv_limit(1).expect("Failed to receive").0.len());
+    assert_eq!(3, m.recv().expect("Failed to receive").len());
+    assert!(m.try_recv().expect("Failed to receive").is_empty());
+    assert!(m.recv_wait(Duration::ZERO).expect("Failed to receive").is_empty());
+  }
+
+  #[test]
+  fn receive_limited_says_when_more_is_waiting() {
+    use crate::testing::MockTransport;
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<MockTransport>("limited-mock")
+      .expect("Failed to add transport");
+    let mock = MockTransport::control("limited-mock");
+    for i in 0..5 {
+      let mut packet = Packet::unsigned();
+      packet.add_message(&[i], &pk);
+      mock.inject(packet.serialize().expect("Failed to serialize"));
+    }
+
+    let (messages, more) = m.receive_limited(2).expect("Failed to receive");
+    assert_eq!(2, messages.len());
+    assert!(more);
+    // the rest are still in the transport, rather than held by the mesher
+    assert_eq!(0, m.backlog("limited-mock"));
+    let (messages, more) = m.receive_limited(3).expect("Failed to receive");
+    assert_eq!(
+      vec![vec![2], vec![3], vec![4]],
+      messages.into_iter().map(Message::into_contents).collect::<Vec<_>>()
+    );
+    assert!(more);
+    let (messages, more) = m.receive_limited(3).expect("Failed to receive");
+    assert!(messages.is_empty());
+    assert!(!more);
+  }
+
+  #[test]
+  fn inbound_queue_overflows() {
+    use crate::{metrics::Counters, testing::MockTransport, Overflow};
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let counters = Arc::new(Counters::new());
+    let mut m = MesherBuilder::new()
+      .own_key(sk)
+      .transport::<MockTransport>("inbound-mock")
+      .receive_limit(1)
+      .inbound_queue(InboundQueue::new(2).overflow(Overflow::DropOldest))
+      .metrics(counters.clone())
+      .build()
+      .expect("Failed to build mesher");
+    let mock = MockTransport::control("inbound-mock");
+    let inject = |count: u8| {
+      for i in 0..count {
+        let mut packet = Packet::unsigned();
+        packet.add_message(&[i], &pk);
+        mock.inject(packet.serialize().expect("Failed to serialize"));
+      }
+    };
+
+    inject(5);
+    assert_eq!(vec![vec![3]], received(&mut m));
+    assert_eq!(1, m.backlog("inbound-mock"));
+    assert_eq!(3, m.inbound_dropped("inbound-mock"));
+    assert_eq!(3, counters.packets_dropped(DropReason::QueueFull));
+    assert_eq!(vec![vec![4]], received(&mut m));
+
+    m.set_inbound_queue(Some(InboundQueue::new(2).overflow(Overflow::Block)));
+    inject(5);
+    assert_eq!(vec![vec![0]], received(&mut m));
+    assert_eq!(vec![vec![1]], received(&mut m));
+    // the queue only took what it had room for, leaving the rest in the transport
+    assert_eq!(1, m.backlog("inbound-mock"));
+    assert_eq!(3, m.inbound_dropped("inbound-mock"));
+    m.set_receive_limit(None);
+    assert_eq!(vec![vec![2], vec![3]], received(&mut m));
+    assert_eq!(vec![vec![4]], received(&mut m));
+  }
+
+  #[test]
+  fn forward_policy_followed() {
+    use std::sync::Mutex;
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:forward-policy").expect("Failed to listen");
+    m.set_forward_policy(Some(ForwardPolicy::new().deny("inmem:internal-*")));
+    let failures = Arc::new(Mutex::new(vec![]));
+    let handler_failures = failures.clone();
+    m.on_failure(move |f| handler_failures.lock().unwrap().push(f));
+    let mut internal = crate::debug_transports::InMemory::new("inmem").expect("Failed to create transport");
+    internal
+      .listen(&Path::parse("inmem:internal-ssh").unwrap())
+      .expect("Failed to listen");
+    let mut public = crate::debug_transports::InMemory::new("inmem").expect("Failed to create transport");
+    public
+      .listen(&Path::parse("inmem:public-relay").unwrap())
+      .expect("Failed to listen");
+
+    for to in &["inmem:internal-ssh", "inmem:public-relay"] {
+      let mut packet = Packet::unsigned();
+      packet.add_hop(to.to_string(), &pk);
+      deliver("inmem:forward-policy", packet);
+    }
+    received(&mut m);
+    // the mesher's own packets aren't subject to the policy
+    let mut packet = Packet::unsigned();
+    packet.add_hop("inmem:internal-ssh".to_owned(), &pk);
+    m.launch(packet).expect("Failed to launch");
+
+    assert_eq!(1, public.receive().expect("Failed to receive").len());
+    assert_eq!(1, internal.receive().expect("Failed to receive").len());
+    let failures = failures.lock().unwrap();
+    assert_eq!(1, failures.len());
+    assert!(matches!(&failures[0], fail::MesherFail::ForwardDenied(p) if p == "inmem:internal-ssh"));
+  }
+
+  #[test]
+  fn events_reported() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Log(Arc<Mutex<Vec<String>>>);
+    impl MesherEvents for Log {
+      fn on_forwarded(&mut self, path: &str) {
+        self.0.lock().unwrap().push(format!("forwarded {}", path));
+      }
+      fn on_undecryptable_packet(&mut self) {
+        self.0.lock().unwrap().push("undecryptable".to_owned());
+      }
+      fn on_transport_error(&mut self, scheme: &str, _err: &fail::MesherFail) {
+        self.0.lock().unwrap().push(format!("error {}", scheme));
+      }
+    }
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let (other_pk, _) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<Broken>("broken").expect("Failed to add transport");
+    m.add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    m.listen_on("inmem:events").expect("Failed to listen");
+    let log = Log::default();
+    let events = log.0.clone();
+    m.set_event_handler(log);
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("broken:next".to_owned(), &pk);
+    deliver("inmem:events", packet);
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[1], &other_pk);
+    deliver("inmem:events", packet);
+    received(&mut m);
+
+    let mut events = events.lock().unwrap().clone();
+    events.sort();
+    assert_eq!(
+      vec!["error broken", "error broken", "forwarded broken:next", "undecryptable"],
+      events
+    );
+  }
+
+  #[test]
+  fn quota_stops_sending() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let (sender_pk, sender_sk) = encrypt::gen_keypair();
+    let mut receiver = Mesher::unsigned(vec![sk]);
+    receiver
+      .add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    receiver.listen_on("inmem:quota-dest").expect("Failed to listen");
+
+    let packet = || {
+      let mut packet = Packet::unsigned();
+      packet.add_message(&[1, 2, 3], &pk);
+      packet.add_hop("inmem:quota-dest".to_owned(), &sender_pk);
+      packet
+    };
+    let size = packet().serialize().expect("Failed to serialize").len();
+    let mut sender = Mesher::unsigned(vec![sender_sk]);
+    sender
+      .add_transport::<crate::debug_transports::InMemory>("inmem")
+      .expect("Failed to add transport");
+    sender.set_quota("inmem", Some(Quota::new(size as u64, Duration::from_secs(3600))));
+    assert_eq!(Some(size as u64), sender.quota_remaining("inmem"));
+
+    sender.launch(packet()).expect("Failed to launch");
+    assert_eq!(Some(0), sender.quota_remaining("inmem"));
+    match sender.launch(packet()) {
+      Err(fail::MesherFail::QuotaExceeded(scheme)) => assert_eq!("inmem", scheme),
+      other => panic!("Expected QuotaExceeded, got {:?}", other),
+    }
+    let usage = sender.usage("inmem");
+    assert_eq!((1, size as u64), (usage.packets_sent(), usage.bytes_sent()));
+
+    assert_eq!(vec![vec![1, 2, 3]], received(&mut receiver));
+    let usage = receiver.usage("inmem");
+    assert_eq!((1, size as u64), (usage.packets_received(), usage.bytes_received()));
+    assert_eq!(None, receiver.quota_remaining("inmem"));
+  }
+
+  #[test]
+  fn launches_sent_by_priority() {
+    use std::sync::Mutex;
+
+    // the priority of every packet sent, in order
+    static SENT: Mutex<Vec<Priority>> = Mutex::new(vec![]);
+    struct Recording(String);
+    impl Transport for Recording {
+      fn new(scheme: &str) -> fail::Result<Self> {
+        Ok(Recording(scheme.to_owned()))
+      }
+      fn scheme(&self) -> &str {
+        &self.0
+      }
+      fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+        self.send_with_priority(path, blob, Priority::Normal)
+      }
+      fn send_with_priority(&mut self, _path: &Path, _blob: &[u8], priority: Priority) -> fail::Result<()> {
+        SENT.lock().unwrap().push(priority);
+        Ok(())
+      }
+      fn listen(&mut self, _path: &Path) -> fail::Result<()> {
+        Ok(())
+      }
+      fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+        Ok(vec![])
+      }
+    }
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<Recording>("rec").expect("Failed to add transport");
+    let handle = m.handle();
+    for priority in &[Priority::Bulk, Priority::Normal, Priority::Control, Priority::Bulk] {
+      let mut packet = Packet::unsigned();
+      packet.add_hop("rec:out".to_owned(), &pk);
+      handle
+        .launch_with_priority(packet, *priority)
+        .expect("Failed to launch");
+    }
+    m.receive().expect("Failed to receive");
+    assert_eq!(
+      vec![Priority::Control, Priority::Normal, Priority::Bulk, Priority::Bulk],
+      *SENT.lock().unwrap()
+    );
+  }
+
+  #[test]
+  fn receipts_capped() {
+    let mut m = Mesher::unsigned(vec![]);
+    let ids: Vec<_> = (0..=Mesher::MAX_RECEIPTS as u64).map(ReceiptId).collect();
+    m.expect_receipts(&ids[..1]);
+    m.expect_receipts(&ids[1..]);
+    assert_eq!(Mesher::MAX_RECEIPTS, m.delivered.len());
+    // the oldest is the one forgotten
+    assert!(!m.delivered.contains_key(&ids[0]));
+    assert!(m.delivered.contains_key(&ids[Mesher::MAX_RECEIPTS]));
   }
 }
@@ -0,0 +1,167 @@
+//! Contains the hooks for keeping track of what a mesher's doing, and a simple set of counters that uses them.
+
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+  },
+};
+
+/// Why a mesher dropped a packet without fully handling it.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+  /// The packet couldn't be parsed, or was in a format version the mesher doesn't support.
+  Malformed,
+  /// The packet was bigger than the mesher's [maximum size](../struct.Mesher.html#method.set_max_packet_size).
+  Oversized,
+  /// The packet arrived when its transport's [rate limit](../struct.RateLimit.html) was used up.
+  RateLimited,
+  /// The packet arrived when its transport's [inbound queue](../struct.InboundQueue.html) was full, and either it or
+  /// the oldest packet in the queue was dropped.
+  QueueFull,
+  /// The packet had tampered chunks in it, and the mesher's [`TamperPolicy`](../enum.TamperPolicy.html) said to drop it.
+  Tampered,
+  /// The packet asked to be forwarded somewhere the mesher's [`ForwardPolicy`](../struct.ForwardPolicy.html) doesn't allow.
+  ForwardDenied,
+  /// The packet asked to be forwarded along a path it was already forwarded along recently, so it's probably
+  /// [going around in a loop](../struct.Mesher.html#method.set_loop_window).
+  Looped,
+  /// The packet got to the mesher after its [expiry](../struct.Packet.html#method.add_expiry), even allowing for
+  /// [clock skew](../struct.Mesher.html#method.set_clock_skew).
+  Expired,
+}
+
+/// Gets told about everything a [`Mesher`](../struct.Mesher.html) does with packets, e.g. to export metrics.
+///
+/// Every method does nothing by default, so implementations only need to handle the ones they care about.
+/// Methods are called synchronously while the mesher's working, so they should be quick.
+/// They take `&self` so the same instance can be shared with whatever reports the metrics, e.g. in an `Arc`.
+pub trait Metrics: Send + Sync {
+  /// A packet of `bytes` bytes came in on the transport for `scheme`.
+  fn received(&self, _scheme: &str, _bytes: usize) {}
+
+  /// A packet of `bytes` bytes was sent through the transport for `scheme`.
+  fn sent(&self, _scheme: &str, _bytes: usize) {}
+
+  /// A packet was forwarded along a path with the given scheme, or held to be forwarded later.
+  fn forwarded(&self, _scheme: &str) {}
+
+  /// A packet was dropped without being handled.
+  fn dropped(&self, _reason: DropReason) {}
+
+  /// A packet was parsed, but none of its chunks could be decrypted, i.e. there was nothing in it for this mesher.
+  fn undecryptable(&self) {}
+}
+
+/// A ready-made [`Metrics`](trait.Metrics.html) that just counts everything.
+#[derive(Debug, Default)]
+pub struct Counters {
+  received: AtomicU64,
+  sent: AtomicU64,
+  forwarded: AtomicU64,
+  undecryptable: AtomicU64,
+  dropped: Mutex<HashMap<DropReason, u64>>,
+  /// Bytes received and sent, per scheme.
+  bytes: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl Counters {
+  /// Creates a set of counters, all at 0.
+  pub fn new() -> Counters {
+    Counters::default()
+  }
+
+  /// How many packets have been received.
+  pub fn packets_received(&self) -> u64 {
+    self.received.load(Ordering::Relaxed)
+  }
+
+  /// How many packets have been sent, including forwarded ones.
+  pub fn packets_sent(&self) -> u64 {
+    self.sent.load(Ordering::Relaxed)
+  }
+
+  /// How many packets have been forwarded.
+  pub fn packets_forwarded(&self) -> u64 {
+    self.forwarded.load(Ordering::Relaxed)
+  }
+
+  /// How many packets had nothing in them for this mesher.
+  pub fn packets_undecryptable(&self) -> u64 {
+    self.undecryptable.load(Ordering::Relaxed)
+  }
+
+  /// How many packets have been dropped for the given reason.
+  pub fn packets_dropped(&self, reason: DropReason) -> u64 {
+    self
+      .dropped
+      .lock()
+      .expect("Counters poisoned")
+      .get(&reason)
+      .copied()
+      .unwrap_or(0)
+  }
+
+  /// How many bytes have been received through the transport for the given scheme.
+  pub fn bytes_received(&self, scheme: &str) -> u64 {
+    self
+      .bytes
+      .lock()
+      .expect("Counters poisoned")
+      .get(scheme)
+      .map_or(0, |b| b.0)
+  }
+
+  /// How many bytes have been sent through the transport for the given scheme.
+  pub fn bytes_sent(&self, scheme: &str) -> u64 {
+    self
+      .bytes
+      .lock()
+      .expect("Counters poisoned")
+      .get(scheme)
+      .map_or(0, |b| b.1)
+  }
+}
+
+impl Metrics for Counters {
+  fn received(&self, scheme: &str, bytes: usize) {
+    self.received.fetch_add(1, Ordering::Relaxed);
+    self
+      .bytes
+      .lock()
+      .expect("Counters poisoned")
+      .entry(scheme.to_owned())
+      .or_default()
+      .0 += bytes as u64;
+  }
+
+  fn sent(&self, scheme: &str, bytes: usize) {
+    self.sent.fetch_add(1, Ordering::Relaxed);
+    self
+      .bytes
+      .lock()
+      .expect("Counters poisoned")
+      .entry(scheme.to_owned())
+      .or_default()
+      .1 += bytes as u64;
+  }
+
+  fn forwarded(&self, _scheme: &str) {
+    self.forwarded.fetch_add(1, Ordering::Relaxed);
+  }
+
+  fn dropped(&self, reason: DropReason) {
+    *self
+      .dropped
+      .lock()
+      .expect("Counters poisoned")
+      .entry(reason)
+      .or_default() += 1;
+  }
+
+  fn undecryptable(&self) {
+    self.undecryptable.fetch_add(1, Ordering::Relaxed);
+  }
+}
@@ -0,0 +1,127 @@
+//! Contains the pool forwarded packets wait in when a mesher is mixing.
+
+use crate::Priority;
+
+use rand::prelude::*;
+
+use std::time::{Duration, Instant};
+
+/// How a [`Mesher`](struct.Mesher.html) should hold packets it forwards, so that someone watching its traffic can't
+/// just match up packets coming in with packets going out by timing.
+///
+/// Each forwarded packet is held for a random delay, up to `max_delay`, and sent during a later
+/// [`receive`](struct.Mesher.html#method.receive) once that's up.
+/// Whenever `batch_size` packets are waiting, they're all sent at once, whether or not their delays are up.
+/// Either way, packets that go out together go out in a random order, except that more urgent
+/// [priorities](enum.Priority.html) go first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MixPolicy {
+  batch_size: usize,
+  max_delay: Duration,
+}
+
+impl MixPolicy {
+  /// Holds up to `batch_size` packets at once, each for a random delay up to `max_delay`.
+  ///
+  /// A `batch_size` of 0 or 1 sends every packet as soon as it's forwarded, so it's no different from not mixing.
+  pub fn new(batch_size: usize, max_delay: Duration) -> MixPolicy {
+    MixPolicy { batch_size, max_delay }
+  }
+}
+
+/// The packets a mesher is holding on to, along with where they're going and when they can go.
+pub(crate) struct MixPool {
+  policy: MixPolicy,
+  held: Vec<(Vec<u8>, String, Priority, Instant)>,
+}
+
+impl MixPool {
+  pub(crate) fn new(policy: MixPolicy) -> MixPool {
+    MixPool { policy, held: vec![] }
+  }
+
+  /// Holds on to a packet for a random delay.
+  pub(crate) fn hold(&mut self, packet: Vec<u8>, path: String, priority: Priority) {
+    let max = self.policy.max_delay.as_millis() as u64;
+    let delay = Duration::from_millis(thread_rng().gen_range(0, max + 1));
+    self.held.push((packet, path, priority, Instant::now() + delay));
+  }
+
+  /// Takes out the packets which should be sent now, in a random order within each priority.
+  pub(crate) fn due(&mut self) -> Vec<(Vec<u8>, String, Priority)> {
+    let due: Vec<_> = if self.held.len() >= self.policy.batch_size {
+      self.held.drain(..).collect()
+    } else {
+      let now = Instant::now();
+      let (due, held) = self.held.drain(..).partition(|(_, _, _, at)| *at <= now);
+      self.held = held;
+      due
+    };
+    shuffled(
+      due
+        .into_iter()
+        .map(|(packet, path, priority, _)| (packet, path, priority))
+        .collect(),
+    )
+  }
+
+  /// Takes out every packet, due or not, in a random order within each priority.
+  pub(crate) fn drain(&mut self) -> Vec<(Vec<u8>, String, Priority)> {
+    shuffled(
+      self
+        .held
+        .drain(..)
+        .map(|(packet, path, priority, _)| (packet, path, priority))
+        .collect(),
+    )
+  }
+}
+
+/// Shuffles packets, then puts the most urgent first, keeping the shuffled order within each priority.
+fn shuffled(mut packets: Vec<(Vec<u8>, String, Priority)>) -> Vec<(Vec<u8>, String, Priority)> {
+  packets.shuffle(&mut thread_rng());
+  packets.sort_by_key(|(_, _, priority)| *priority);
+  packets
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn held_until_batch_full() {
+    let mut pool = MixPool::new(MixPolicy::new(3, Duration::from_secs(3600)));
+    pool.hold(vec![1], "a:1".to_owned(), Priority::Normal);
+    pool.hold(vec![2], "a:2".to_owned(), Priority::Normal);
+    assert!(pool.due().is_empty());
+    pool.hold(vec![3], "a:3".to_owned(), Priority::Normal);
+    let mut due = pool.due();
+    due.sort();
+    assert_eq!(
+      vec![
+        (vec![1], "a:1".to_owned(), Priority::Normal),
+        (vec![2], "a:2".to_owned(), Priority::Normal),
+        (vec![3], "a:3".to_owned(), Priority::Normal)
+      ],
+      due
+    );
+    assert!(pool.due().is_empty());
+  }
+
+  #[test]
+  fn released_after_delay() {
+    let mut pool = MixPool::new(MixPolicy::new(100, Duration::from_millis(0)));
+    pool.hold(vec![1], "a:1".to_owned(), Priority::Normal);
+    assert_eq!(vec![(vec![1], "a:1".to_owned(), Priority::Normal)], pool.due());
+  }
+
+  #[test]
+  fn urgent_packets_first() {
+    let mut pool = MixPool::new(MixPolicy::new(3, Duration::from_secs(3600)));
+    pool.hold(vec![1], "a:1".to_owned(), Priority::Bulk);
+    pool.hold(vec![2], "a:2".to_owned(), Priority::Normal);
+    pool.hold(vec![3], "a:3".to_owned(), Priority::Control);
+    let order: Vec<_> = pool.due().into_iter().map(|(packet, _, _)| packet).collect();
+    assert_eq!(vec![vec![3], vec![2], vec![1]], order);
+  }
+}
@@ -0,0 +1,235 @@
+//! Contains the builder for onion-layered packets.
+
+use crate::{
+  alloc_prelude::*,
+  crypto::Crypto,
+  packet::{take, take_u32},
+  prelude::*,
+  MessageId,
+};
+
+use alloc::sync::Arc;
+
+/// The instructions for one node in an onion, before encryption.
+struct Layer {
+  pkey: encrypt::PublicKey,
+  messages: Vec<(MessageId, Vec<u8>)>,
+  forward: Option<String>,
+}
+
+/// What a node gets when it decrypts its layer of an onion.
+#[derive(Debug, PartialEq)]
+pub(crate) struct OpenedLayer {
+  pub(crate) messages: Vec<(MessageId, Vec<u8>)>,
+  /// The path to send the rest of the onion along, and the rest of the onion, already serialized as a packet.
+  pub(crate) forward: Option<(String, Vec<u8>)>,
+}
+
+impl OpenedLayer {
+  /// Encodes the layer as: a `u32` message count, each message's 8-byte ID, `u32` length, and bytes, then a `u32` path
+  /// length and the path, then the rest of the onion. A path length of 0 means there's nothing to forward.
+  fn serialize(self) -> Vec<u8> {
+    let mut b = vec![];
+    b.extend_from_slice(&(self.messages.len() as u32).to_be_bytes());
+    for (id, data) in self.messages {
+      b.extend_from_slice(&id.0.to_be_bytes());
+      b.extend_from_slice(&(data.len() as u32).to_be_bytes());
+      b.extend_from_slice(&data);
+    }
+    match self.forward {
+      Some((path, rest)) => {
+        b.extend_from_slice(&(path.len() as u32).to_be_bytes());
+        b.extend_from_slice(path.as_bytes());
+        b.extend_from_slice(&rest);
+      }
+      None => b.extend_from_slice(&0u32.to_be_bytes()),
+    }
+    b
+  }
+
+  /// Decodes a layer written by [`serialize`](#method.serialize), if it's well-formed.
+  pub(crate) fn deserialize(mut from: &[u8]) -> Option<OpenedLayer> {
+    let mut messages = vec![];
+    for _ in 0..take_u32(&mut from)? {
+      let mut id = [0; 8];
+      id.copy_from_slice(take(&mut from, 8)?);
+      let len = take_u32(&mut from)?;
+      messages.push((MessageId(u64::from_be_bytes(id)), take(&mut from, len)?.to_vec()));
+    }
+    let forward = match take_u32(&mut from)? {
+      0 if from.is_empty() => None,
+      0 => return None,
+      len => {
+        let path = String::from_utf8(take(&mut from, len)?.to_vec()).ok()?;
+        Some((path, from.to_vec()))
+      }
+    };
+    Some(OpenedLayer { messages, forward })
+  }
+}
+
+/// Builds a packet where each node's instructions wrap the rest of the packet, like an onion, so nodes only learn about
+/// their own instructions and the next hop.
+///
+/// Normal packets keep every chunk side by side, so every node along the way can see how many chunks there are, even if
+/// it can't read them.
+/// In an onion, each node decrypts a single layer, handles the messages in it, and forwards the (re-serialized) layer
+/// inside it along the path it was given, so the next node sees a packet with only one chunk in it again.
+///
+/// Layers are added from the outside in, i.e. in the order the nodes will get the packet.
+/// Consecutive calls for the same node add to the same layer, until it's given a [`hop`](#method.hop).
+///
+/// ```
+/// # use mesher::prelude::*;
+/// let (sender_pk, _) = encrypt::gen_keypair();
+/// let (relay_pk, _) = encrypt::gen_keypair();
+/// let (receiver_pk, _) = encrypt::gen_keypair();
+/// let _packet = Packet::onion()
+///   .hop(&sender_pk, "tcp:[::1]:18540")
+///   .hop(&relay_pk, "tcp:[::1]:18541")
+///   .message(&receiver_pk, b"hello")
+///   .build()
+///   .expect("Failed to build onion");
+/// ```
+///
+/// Note that layers aren't padded, so the packet does shrink as it's unwrapped, which hints at how far along it is.
+/// Onions also can't carry reply paths; use a normal [`Packet`](struct.Packet.html) for those.
+pub struct OnionBuilder {
+  layers: Vec<Layer>,
+  signing_key: Option<sign::SecretKey>,
+  crypto: Arc<dyn Crypto>,
+}
+
+impl OnionBuilder {
+  pub(crate) fn new() -> OnionBuilder {
+    OnionBuilder {
+      layers: vec![],
+      signing_key: None,
+      crypto: crate::crypto::default_backend(),
+    }
+  }
+
+  /// Signs every layer with the given key, for signed meshers to accept.
+  pub fn signed(mut self, skey: sign::SecretKey) -> OnionBuilder {
+    self.signing_key = Some(skey);
+    self
+  }
+
+  /// Encrypts and signs the layers with the given crypto backend, rather than the default one.
+  pub fn using(mut self, crypto: Arc<dyn Crypto>) -> OnionBuilder {
+    self.crypto = crypto;
+    self
+  }
+
+  /// Gets the layer to add to for the given node, starting a new one if the last was for another node or already has a hop.
+  fn layer_for(&mut self, pkey: &encrypt::PublicKey) -> &mut Layer {
+    let reuse = matches!(self.layers.last(), Some(l) if &l.pkey == pkey && l.forward.is_none());
+    if !reuse {
+      self.layers.push(Layer {
+        pkey: *pkey,
+        messages: vec![],
+        forward: None,
+      });
+    }
+    self.layers.last_mut().expect("Just made sure there's a layer")
+  }
+
+  /// Adds a message for the node with the right skey to read when it unwraps its layer.
+  pub fn message(mut self, node_pkey: &encrypt::PublicKey, data: &[u8]) -> OnionBuilder {
+    self
+      .layer_for(node_pkey)
+      .messages
+      .push((MessageId::random(), data.to_vec()));
+    self
+  }
+
+  /// Has the node with the right skey forward the rest of the onion along the given path, ending its layer.
+  pub fn hop(mut self, node_pkey: &encrypt::PublicKey, path: &str) -> OnionBuilder {
+    self.layer_for(node_pkey).forward = Some(path.to_owned());
+    self
+  }
+
+  /// Wraps up all the layers into a packet, ready to [launch](struct.Mesher.html#method.launch).
+  ///
+  /// Fails with [`MesherFail::InvalidURL`](fail/enum.MesherFail.html#variant.InvalidURL) if the innermost layer has a
+  /// hop, since there'd be nothing to forward, if any other layer doesn't, since the rest couldn't be forwarded, or if any
+  /// hop's path is empty, and with
+  /// [`MesherFail::InvalidPacket`](fail/enum.MesherFail.html#variant.InvalidPacket) if there are no layers at all.
+  pub fn build(self) -> fail::Result<Packet> {
+    let mut rest: Option<Vec<u8>> = None;
+    let mut layers = self.layers.into_iter().rev().peekable();
+    while let Some(layer) = layers.next() {
+      let forward = match (layer.forward, rest.take()) {
+        (Some(path), _) if path.is_empty() => return Err(fail::MesherFail::InvalidURL("empty onion hop".to_owned())),
+        (Some(path), Some(rest)) => Some((path, rest)),
+        (Some(path), None) => {
+          return Err(fail::MesherFail::InvalidURL(format!(
+            "nothing to forward along {}",
+            path
+          )))
+        }
+        (None, Some(_)) => return Err(fail::MesherFail::InvalidURL("no hop between onion layers".to_owned())),
+        (None, None) => None,
+      };
+      let opened = OpenedLayer {
+        messages: layer.messages,
+        forward,
+      };
+      let mut packet = match &self.signing_key {
+        Some(sk) => Packet::signed_using(sk.clone_secret(), self.crypto.clone()),
+        None => Packet::unsigned_using(self.crypto.clone()),
+      };
+      packet.add_onion_layer(opened.serialize(), &layer.pkey);
+      if layers.peek().is_none() {
+        return Ok(packet);
+      }
+      rest = Some(packet.serialize()?);
+    }
+    Err(fail::MesherFail::InvalidPacket)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn layer_roundtrip() {
+    let layer = OpenedLayer {
+      messages: vec![(MessageId(1), vec![1, 2]), (MessageId(2), vec![])],
+      forward: Some(("inmem:next".to_owned(), vec![9, 9, 9])),
+    };
+    let bytes = OpenedLayer {
+      messages: layer.messages.clone(),
+      forward: layer.forward.clone(),
+    }
+    .serialize();
+    assert_eq!(Some(layer), OpenedLayer::deserialize(&bytes));
+
+    let last = OpenedLayer {
+      messages: vec![(MessageId(3), vec![3])],
+      forward: None,
+    };
+    let mut bytes = OpenedLayer {
+      messages: last.messages.clone(),
+      forward: None,
+    }
+    .serialize();
+    assert_eq!(Some(last), OpenedLayer::deserialize(&bytes));
+    bytes.push(0);
+    assert_eq!(None, OpenedLayer::deserialize(&bytes));
+  }
+
+  #[test]
+  fn hop_at_the_end_fails() {
+    let (pk, _) = encrypt::gen_keypair();
+    assert!(Packet::onion().hop(&pk, "inmem:nowhere").build().is_err());
+    assert!(Packet::onion().build().is_err());
+    let (other_pk, _) = encrypt::gen_keypair();
+    assert!(Packet::onion()
+      .message(&pk, &[1])
+      .message(&other_pk, &[2])
+      .build()
+      .is_err());
+  }
+}
@@ -1,16 +1,501 @@
-use crate::prelude::*;
+use crate::{
+  alloc_prelude::*,
+  crypto::Crypto,
+  identity::{Endorsement, Revocation},
+  mailbox::MailMessage,
+  onion::{OnionBuilder, OpenedLayer},
+  prelude::*,
+  reply_block::ReplyStep,
+  Route,
+};
 
-use std::sync::Arc;
+use alloc::sync::Arc;
+use core::{cell::OnceCell, fmt};
 
+#[cfg(feature = "std")]
+use bincode::Options;
 use rand::prelude::*;
 
+/// The first byte of every encrypted chunk says how the rest of it was encrypted.
+///
+/// Nodes skip chunks in suites they don't know, exactly as if they couldn't decrypt them, so new suites (e.g. the
+/// [hybrid](constant.SUITE_HYBRID.html) one) can be rolled out without breaking nodes that only know the classical one.
+const SUITE_X25519: u8 = 0;
+
+/// A chunk sealed for a [hybrid X25519 + ML-KEM-768 key](crypto/hybrid/index.html), which is only read by nodes built
+/// with the `pq-hybrid` feature.
+#[cfg(feature = "pq-hybrid")]
+const SUITE_HYBRID: u8 = 1;
+
+/// The same as [`SUITE_X25519`](constant.SUITE_X25519.html), but with a [key hint](struct.Packet.html#method.use_key_hints)
+/// between the suite byte and the sealed box.
+const SUITE_X25519_HINTED: u8 = 2;
+
+/// A chunk sealed with a [group key](crypto/group/struct.GroupKey.html) rather than for one node, so every member of
+/// the group can open it.
+///
+/// The suite byte is followed by the group's ID and the key's epoch, as big-endian integers, so nodes can tell which
+/// key to open it with, or that they don't have it, without trying any, then the secret box.
+/// That means **anyone can tell which chunks are for the same group**, though not who's in it.
+const SUITE_GROUP: u8 = 3;
+
+/// How many bytes of the key hint are actually put in chunks.
+const HINT_BYTES: usize = 2;
+
+/// How many bytes at the start of a sealed box are its ephemeral public key, which hints use as their nonce.
+const NONCE_BYTES: usize = 32;
+
+/// The version of the packet format this mesher writes, which is the first byte of every serialized packet.
+///
+/// Any change to the format, e.g. new chunk types or new crypto, should bump this, so older nodes reject packets they'd misread.
+///
+/// - Version 1 was the paths serialized with bincode's default config, which no longer gets written but can still be read.
+/// - Version 2 is the explicit layout described in the README, written by [`encode_paths`](fn.encode_paths.html).
+pub(crate) const PACKET_VERSION: u8 = 2;
+
+/// The packet format versions this mesher can read.
+///
+/// Version 1 needs bincode, so it can only be read with the `std` feature.
+#[cfg(feature = "std")]
+const SUPPORTED_VERSIONS: &[u8] = &[1, 2];
+#[cfg(not(feature = "std"))]
+const SUPPORTED_VERSIONS: &[u8] = &[2];
+
+/// The most paths a packet can have, counting the main path.
+///
+/// Messages refer to their reply paths with a single byte, where 0 means none, so more than this could never be used.
+const MAX_PATHS: usize = 256;
+
+/// The most chunks any one path in a packet can have.
+const MAX_CHUNKS: usize = 4096;
+
+/// The RNG for message IDs and shuffling chunks: the thread's with `std`, and the OS's without it.
+#[cfg(feature = "std")]
+fn rng() -> impl RngCore {
+  thread_rng()
+}
+#[cfg(not(feature = "std"))]
+fn rng() -> impl RngCore {
+  rand::rngs::OsRng
+}
+
+/// Splits `len` bytes off the front of `from`, if there are that many.
+pub(crate) fn take<'a>(from: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+  if from.len() < len {
+    return None;
+  }
+  let (taken, rest) = from.split_at(len);
+  *from = rest;
+  Some(taken)
+}
+
+/// Splits a big-endian `u32` off the front of `from`, if there's one there.
+pub(crate) fn take_u32(from: &mut &[u8]) -> Option<usize> {
+  let mut bytes = [0; 4];
+  bytes.copy_from_slice(take(from, 4)?);
+  Some(u32::from_be_bytes(bytes) as usize)
+}
+
+/// Writes the main path and reply paths in the version 2 layout, not including the version byte.
+///
+/// Every count and length is a big-endian `u32`:
+/// the number of paths, then for each path, the number of chunks, then for each chunk, its length followed by its bytes.
+/// The main path always comes first.
+pub(crate) fn encode_paths(paths: &[Vec<Vec<u8>>], into: &mut Vec<u8>) {
+  into.extend_from_slice(&(paths.len() as u32).to_be_bytes());
+  for path in paths {
+    into.extend_from_slice(&(path.len() as u32).to_be_bytes());
+    for chunk in path {
+      into.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+      into.extend_from_slice(chunk);
+    }
+  }
+}
+
+/// Reads paths written by [`encode_paths`](fn.encode_paths.html), borrowing the chunks from the input.
+///
+/// The input has to be used up exactly; trailing bytes make the whole thing malformed.
+fn decode_paths(mut from: &[u8]) -> Result<Vec<Vec<&[u8]>>, ParseError> {
+  // counts aren't trusted for preallocation, since they come straight off the wire
+  let mut paths = vec![];
+  let path_count = take_u32(&mut from).ok_or(ParseError::Truncated)?;
+  if path_count > MAX_PATHS {
+    return Err(ParseError::TooManyPaths(path_count));
+  }
+  for _ in 0..path_count {
+    let mut path = vec![];
+    let chunk_count = take_u32(&mut from).ok_or(ParseError::Truncated)?;
+    if chunk_count > MAX_CHUNKS {
+      return Err(ParseError::TooManyChunks(chunk_count));
+    }
+    for _ in 0..chunk_count {
+      let len = take_u32(&mut from).ok_or(ParseError::Truncated)?;
+      path.push(take(&mut from, len).ok_or(ParseError::Truncated)?);
+    }
+    paths.push(path);
+  }
+  if from.is_empty() {
+    Ok(paths)
+  } else {
+    Err(ParseError::TrailingBytes)
+  }
+}
+
+/// Why [`Packet::parse_untrusted`](struct.Packet.html#method.parse_untrusted) couldn't parse a packet.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+  /// There were no bytes at all.
+  Empty,
+  /// The packet is in a format version this mesher can't read.
+  UnsupportedVersion(u8),
+  /// A count or length said there was more data than there actually was.
+  Truncated,
+  /// There was more data after the end of the packet.
+  TrailingBytes,
+  /// The packet claimed to have more paths than any packet can, including the main path.
+  TooManyPaths(usize),
+  /// One of the packet's paths claimed to have more chunks than any path can.
+  TooManyChunks(usize),
+  /// The packet didn't have a main path, only reply paths or nothing at all.
+  NoMainPath,
+  /// The packet is in the old bincode-based format, and bincode couldn't read it.
+  Malformed,
+}
+
+impl From<ParseError> for fail::MesherFail {
+  fn from(e: ParseError) -> fail::MesherFail {
+    match e {
+      ParseError::UnsupportedVersion(v) => fail::MesherFail::UnsupportedVersion(v),
+      _ => fail::MesherFail::InvalidPacket,
+    }
+  }
+}
+
+/// The layout of a packet, as read by [`Packet::parse_untrusted`](struct.Packet.html#method.parse_untrusted).
+///
+/// The chunks are borrowed from the bytes parsed, still encrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedPacket<'a> {
+  /// The packet's format version.
+  pub version: u8,
+  /// The chunks in the main path, in order.
+  pub chunks: Vec<&'a [u8]>,
+  /// The chunks in each reply path, in order.
+  pub reply_paths: Vec<Vec<&'a [u8]>>,
+}
+
+/// How many bytes of a signed chunk are the signature, which comes before the signed data.
+const SIGNATURE_BYTES: usize = 64;
+
+/// The secret keys chunks are opened with, along with their public keys, which are only worked out once a hinted chunk
+/// needs them, and the keys of the groups this node is in.
+pub(crate) struct OwnKeys<'a> {
+  skeys: &'a [encrypt::SecretKey],
+  pkeys: OnceCell<Vec<encrypt::PublicKey>>,
+  groups: &'a [group::GroupKey],
+  #[cfg(feature = "pq-hybrid")]
+  hybrid: &'a [hybrid::SecretKey],
+}
+
+impl<'a> OwnKeys<'a> {
+  pub(crate) fn new(skeys: &'a [encrypt::SecretKey], groups: &'a [group::GroupKey]) -> OwnKeys<'a> {
+    OwnKeys {
+      skeys,
+      pkeys: OnceCell::new(),
+      groups,
+      #[cfg(feature = "pq-hybrid")]
+      hybrid: &[],
+    }
+  }
+
+  /// Opens hybrid chunks with `keys` too.
+  #[cfg(feature = "pq-hybrid")]
+  pub(crate) fn hybrid(self, keys: &'a [hybrid::SecretKey]) -> OwnKeys<'a> {
+    OwnKeys { hybrid: keys, ..self }
+  }
+
+  fn pkeys(&self, crypto: &dyn Crypto) -> &[encrypt::PublicKey] {
+    self
+      .pkeys
+      .get_or_init(|| self.skeys.iter().map(|k| crypto.encrypt_public_key(k)).collect())
+  }
+}
+
+/// Decrypts a chunk with the first key that works, if its suite is one this node knows.
+///
+/// Hinted chunks are only tried with the keys their hint matches, which is what makes them cheaper to skip.
+/// Group messages are only accepted from group chunks, and only for the group whose key opened them, so nobody can
+/// pass off a message sealed just for this node as one sent to a whole group.
+fn open_chunk(chunk: &[u8], keys: &OwnKeys, crypto: &dyn Crypto) -> Option<Vec<u8>> {
+  let opened = match chunk.split_first() {
+    Some((&SUITE_X25519, sealed)) => keys.skeys.iter().find_map(|k| crypto.open(sealed, k)),
+    Some((&SUITE_X25519_HINTED, rest)) if rest.len() >= HINT_BYTES + NONCE_BYTES => {
+      let (hint, sealed) = rest.split_at(HINT_BYTES);
+      keys
+        .skeys
+        .iter()
+        .zip(keys.pkeys(crypto))
+        .filter(|(_, pk)| crypto.key_hint(&sealed[..NONCE_BYTES], pk)[..HINT_BYTES] == *hint)
+        .find_map(|(sk, _)| crypto.open(sealed, sk))
+    }
+    #[cfg(feature = "pq-hybrid")]
+    Some((&SUITE_HYBRID, sealed)) => keys.hybrid.iter().find_map(|k| hybrid::open(sealed, k, crypto)),
+    Some((&SUITE_GROUP, rest)) if rest.len() >= 12 => {
+      let (header, sealed) = rest.split_at(12);
+      let key = keys
+        .groups
+        .iter()
+        .find(|k| header[..8] == k.id.0.to_be_bytes() && header[8..] == k.epoch.to_be_bytes())?;
+      return crypto
+        .group_open(sealed, key)
+        .filter(|opened| opened.first() == Some(&GROUP_MESSAGE) && opened.get(1..9) == Some(&header[..8]));
+    }
+    _ => None,
+  }?;
+  if opened.first() == Some(&GROUP_MESSAGE) {
+    return None;
+  }
+  Some(opened)
+}
+
+/// The type byte of a message from an older node, which has no ID.
+const UNNUMBERED_MESSAGE: u8 = 0;
+
+/// The type byte of a transport chunk, holding the path to forward the packet along.
+const TRANSPORT: u8 = 1;
+
+/// The type byte of an application-defined chunk.
+const CUSTOM: u8 = 2;
+
+/// The type byte of a request to send a reply path back as a delivery receipt.
+const RECEIPT_REQUEST: u8 = 3;
+
+/// The type byte of a delivery receipt.
+const RECEIPT: u8 = 4;
+
+/// The type byte of a message from an older node, which has an ID but no sequence number.
+const NUMBERED_MESSAGE: u8 = 5;
+
+/// The type byte of a discovery announcement.
+const ANNOUNCE: u8 = 6;
+
+/// The type byte of an onion layer.
+const ONION: u8 = 7;
+
+/// The type byte of a step of collecting mail from a relay.
+const MAIL: u8 = 8;
+
+/// The type byte of a group message, which is only valid inside a [group chunk](constant.SUITE_GROUP.html).
+const GROUP_MESSAGE: u8 = 9;
+
+/// The type byte of a group key, for a member to join the group with.
+const GROUP_KEY: u8 = 10;
+
+/// The type byte of an endorsement, which signed meshers look for before checking the rest of the packet's signatures.
+const ENDORSEMENT: u8 = 11;
+
+/// The type byte of a revocation.
+const REVOCATION: u8 = 12;
+
+/// The type byte of a request to send a reply path back as a failure notice if the packet can't be forwarded.
+const FAILURE_REQUEST: u8 = 13;
+
+/// The type byte of a failure notice.
+const FAILURE_NOTICE: u8 = 14;
+
+/// The type byte of a request to hold the packet for a while before forwarding it.
+const HOLD_UNTIL: u8 = 15;
+
+/// The type byte of a packet's expiry time.
+const EXPIRY: u8 = 16;
+
+/// The type byte of a message with a sequence number, so messages come out in the order they were added.
+const SEQUENCED_MESSAGE: u8 = 17;
+
+/// The type byte of a claim of who sent one of the packet's messages.
+const SENDER_CLAIM: u8 = 18;
+
+/// The type byte of a relay's header in a [reply block](struct.ReplyBlock.html).
+const REPLY_HEADER: u8 = 19;
+
+/// Signed along with the message a [sender claim](struct.Packet.html#method.claim_sender) is for, so it can't be
+/// mistaken for any other signature.
+const CLAIM_CONTEXT: &[u8] = b"mesher sender claim v1";
+
+/// Signed along with a [group key](struct.Packet.html#method.add_group_key) a sender claim is for, so it can't be
+/// mistaken for a claim about a message.
+const GROUP_KEY_CONTEXT: &[u8] = b"mesher group key claim v1";
+
+/// A [claim](struct.Packet.html#method.claim_sender) of who sent one of a packet's messages, or a group key, before
+/// it's checked.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SenderClaim {
+  /// The sequence number of the message it's for, or 0 for a group key.
+  pub(crate) message: u32,
+  signer: sign::PublicKey,
+  signature: [u8; 64],
+}
+
+impl SenderClaim {
+  /// What's signed: the context, then who the message is for, its ID, and its contents.
+  fn statement(recipient: &encrypt::PublicKey, id: MessageId, contents: &[u8]) -> Vec<u8> {
+    [CLAIM_CONTEXT, recipient.as_bytes(), &id.0.to_be_bytes(), contents].concat()
+  }
+
+  /// What's signed for a group key: the context, then who the key is for, then the key as it's encoded.
+  fn group_key_statement(recipient: &encrypt::PublicKey, key: &group::GroupKey) -> Vec<u8> {
+    [GROUP_KEY_CONTEXT, recipient.as_bytes(), &key.encode()].concat()
+  }
+
+  fn new(
+    message: u32,
+    recipient: &encrypt::PublicKey,
+    id: MessageId,
+    contents: &[u8],
+    signer: &sign::SecretKey,
+    crypto: &dyn Crypto,
+  ) -> SenderClaim {
+    SenderClaim::signing(
+      message,
+      &SenderClaim::statement(recipient, id, contents),
+      signer,
+      crypto,
+    )
+  }
+
+  fn for_group_key(
+    recipient: &encrypt::PublicKey,
+    key: &group::GroupKey,
+    signer: &sign::SecretKey,
+    crypto: &dyn Crypto,
+  ) -> SenderClaim {
+    SenderClaim::signing(0, &SenderClaim::group_key_statement(recipient, key), signer, crypto)
+  }
+
+  fn signing(message: u32, statement: &[u8], signer: &sign::SecretKey, crypto: &dyn Crypto) -> SenderClaim {
+    let signed = crypto.sign(statement, signer);
+    let mut signature = [0; 64];
+    signature.copy_from_slice(&signed[..64]);
+    SenderClaim {
+      message,
+      signer: crypto.sign_public_key(signer),
+      signature,
+    }
+  }
+
+  /// The key that signed the claim, if it's a valid signature of the given message to the given recipient.
+  #[cfg(feature = "std")]
+  pub(crate) fn verify(
+    &self,
+    recipient: &encrypt::PublicKey,
+    id: MessageId,
+    contents: &[u8],
+    crypto: &dyn Crypto,
+  ) -> Option<sign::PublicKey> {
+    self.check(&SenderClaim::statement(recipient, id, contents), crypto)
+  }
+
+  /// The key that signed the claim, if it's a valid signature of the given group key to the given recipient.
+  #[cfg(feature = "std")]
+  pub(crate) fn verify_group_key(
+    &self,
+    recipient: &encrypt::PublicKey,
+    key: &group::GroupKey,
+    crypto: &dyn Crypto,
+  ) -> Option<sign::PublicKey> {
+    self.check(&SenderClaim::group_key_statement(recipient, key), crypto)
+  }
+
+  #[cfg(feature = "std")]
+  fn check(&self, statement: &[u8], crypto: &dyn Crypto) -> Option<sign::PublicKey> {
+    let signed = [&self.signature[..], statement].concat();
+    Some(self.signer).filter(|_| crypto.verify(&signed, &self.signer).as_deref() == Some(statement))
+  }
+
+  fn encode(&self) -> Vec<u8> {
+    [&self.message.to_be_bytes()[..], self.signer.as_bytes(), &self.signature].concat()
+  }
+
+  fn decode(bytes: &[u8]) -> Option<SenderClaim> {
+    if bytes.len() != 4 + 32 + 64 {
+      return None;
+    }
+    let mut message = [0; 4];
+    message.copy_from_slice(&bytes[..4]);
+    let mut signature = [0; 64];
+    signature.copy_from_slice(&bytes[36..]);
+    Some(SenderClaim {
+      message: u32::from_be_bytes(message),
+      signer: sign::PublicKey::from_slice(&bytes[4..36])?,
+      signature,
+    })
+  }
+}
+
+/// An application-defined kind of chunk, for things that aren't just messages, e.g. control messages or routing gossip.
+///
+/// Custom chunks are encrypted and targeted like any other chunk, with [`Packet::add_custom`](struct.Packet.html#method.add_custom).
+/// When a mesher decrypts one, it decodes it and passes it to the handler registered for its kind with
+/// [`Mesher::on_chunk`](struct.Mesher.html#method.on_chunk).
+/// Chunks of kinds without a handler are ignored, as are ones that fail to decode.
+pub trait CustomChunk: Sized {
+  /// Identifies this kind of chunk on the wire, so every kind a mesher handles needs a different one.
+  ///
+  /// Kinds from `0xFF00` up are reserved for mesher's own layers, like [`pubsub`](pubsub/index.html).
+  const KIND: u16;
+
+  /// Turns the chunk into bytes to be sent.
+  fn encode(&self) -> Vec<u8>;
+
+  /// Turns the bytes from [`encode`](#tymethod.encode) back into a chunk, or returns `None` if they're malformed.
+  fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
 /// A chunk being added into a packet
 #[derive(Debug, PartialEq)]
 enum InputChunk {
-  /// A message to pass back to the [`Mesher`](../struct.Mesher.html)
-  Message(Vec<u8>, Option<u8>),
+  /// A message to pass back to the [`Mesher`](../struct.Mesher.html), and where it was added among the packet's messages
+  Message(Vec<u8>, Option<u8>, MessageId, u32),
   /// A path to send this packet along
   Transport(String),
+  /// An application-defined chunk, with its kind and encoded contents
+  Custom(u16, Vec<u8>),
+  /// A request for the receiving node to send the given reply path back as a delivery receipt
+  ReceiptRequest(u8),
+  /// A delivery receipt for the original sender to read
+  Receipt(u64),
+  /// A request for the receiving node to send the given reply path back if it can't forward the packet
+  FailureRequest(u8),
+  /// A failure notice for the original sender to read, with the ID of the receipt it's about
+  FailureNotice(u64),
+  /// A request for the receiving node to hold the packet until the given Unix time, in milliseconds, before forwarding it
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  HoldUntil(u64),
+  /// The Unix time, in milliseconds, after which the receiving node should drop the packet
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  Expiry(u64),
+  /// A claim of who sent one of the packet's messages
+  SenderClaim(SenderClaim),
+  /// An encoded discovery announcement
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  Announce(Vec<u8>),
+  /// An encoded onion layer
+  Onion(Vec<u8>),
+  /// An encoded step of a reply block
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  ReplyHeader(Vec<u8>),
+  /// An encoded step of collecting mail from a relay
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  Mail(Vec<u8>),
+  /// A message for every member of a group, which has to be sealed with its key
+  GroupMessage(group::GroupId, Vec<u8>, MessageId),
+  /// An encoded group key, for a member to join the group with, and who claims to have sent it
+  GroupKey(Vec<u8>, Option<SenderClaim>),
+  /// An encoded endorsement of the key signing the packet
+  Endorsement(Vec<u8>),
+  /// An encoded revocation of some key
+  Revocation(Vec<u8>),
 }
 
 impl InputChunk {
@@ -18,69 +503,397 @@ impl InputChunk {
   /// Best considered a black box, so it can change freely.
   fn serialize(self) -> Vec<u8> {
     match self {
-      InputChunk::Message(mut m, reply_to) => {
-        let mut b = vec![0];
+      InputChunk::Message(mut m, reply_to, id, seq) => {
+        let mut b = vec![SEQUENCED_MESSAGE];
         let reply_to = match reply_to {
           None => 0,
           Some(idx) => idx + 1,
         };
         b.push(reply_to);
+        b.extend_from_slice(&id.0.to_be_bytes());
+        b.extend_from_slice(&seq.to_be_bytes());
         b.append(&mut m);
         b
       }
       InputChunk::Transport(t) => {
-        let mut b = vec![1];
+        let mut b = vec![TRANSPORT];
         b.append(&mut t.into_bytes());
         b
       }
+      InputChunk::Custom(kind, mut data) => {
+        let mut b = vec![CUSTOM];
+        b.extend_from_slice(&kind.to_be_bytes());
+        b.append(&mut data);
+        b
+      }
+      InputChunk::ReceiptRequest(reply_to) => vec![RECEIPT_REQUEST, reply_to],
+      InputChunk::Receipt(id) => {
+        let mut b = vec![RECEIPT];
+        b.extend_from_slice(&id.to_be_bytes());
+        b
+      }
+      InputChunk::FailureRequest(reply_to) => vec![FAILURE_REQUEST, reply_to],
+      InputChunk::FailureNotice(id) => {
+        let mut b = vec![FAILURE_NOTICE];
+        b.extend_from_slice(&id.to_be_bytes());
+        b
+      }
+      InputChunk::HoldUntil(until) => {
+        let mut b = vec![HOLD_UNTIL];
+        b.extend_from_slice(&until.to_be_bytes());
+        b
+      }
+      InputChunk::Expiry(at) => {
+        let mut b = vec![EXPIRY];
+        b.extend_from_slice(&at.to_be_bytes());
+        b
+      }
+      InputChunk::SenderClaim(claim) => {
+        let mut b = vec![SENDER_CLAIM];
+        b.append(&mut claim.encode());
+        b
+      }
+      InputChunk::Announce(mut announcement) => {
+        let mut b = vec![ANNOUNCE];
+        b.append(&mut announcement);
+        b
+      }
+      InputChunk::Onion(mut layer) => {
+        let mut b = vec![ONION];
+        b.append(&mut layer);
+        b
+      }
+      InputChunk::ReplyHeader(mut step) => {
+        let mut b = vec![REPLY_HEADER];
+        b.append(&mut step);
+        b
+      }
+      InputChunk::Mail(mut mail) => {
+        let mut b = vec![MAIL];
+        b.append(&mut mail);
+        b
+      }
+      InputChunk::GroupMessage(group, mut m, id) => {
+        let mut b = vec![GROUP_MESSAGE];
+        b.extend_from_slice(&group.0.to_be_bytes());
+        b.extend_from_slice(&id.0.to_be_bytes());
+        b.append(&mut m);
+        b
+      }
+      InputChunk::GroupKey(mut key, claim) => {
+        let mut b = vec![GROUP_KEY];
+        b.append(&mut key);
+        if let Some(claim) = claim {
+          b.append(&mut claim.encode());
+        }
+        b
+      }
+      InputChunk::Endorsement(mut endorsement) => {
+        let mut b = vec![ENDORSEMENT];
+        b.append(&mut endorsement);
+        b
+      }
+      InputChunk::Revocation(mut revocation) => {
+        let mut b = vec![REVOCATION];
+        b.append(&mut revocation);
+        b
+      }
     }
   }
 }
 
+/// A reply path pulled out of a received packet, shared by all the messages which can be replied to with it.
+pub(crate) type ReplyPath = Arc<Vec<Vec<u8>>>;
+
+/// A chunk from [`Packet::open_unchecked`](struct.Packet.html#method.open_unchecked): the chunk itself, whether it was
+/// signed, and what's in it, if it could be opened.
+pub(crate) type OpenedChunk<'a> = (&'a [u8], bool, Option<Vec<u8>>);
+
 /// One piece of a packet being parsed on receipt.
 #[derive(Debug, PartialEq)]
 pub(crate) enum Chunk {
-  /// A message to pass back to the [`Mesher`](../struct.Mesher.html)
-  Message(Vec<u8>, Option<Arc<Vec<Vec<u8>>>>),
+  /// A message to pass back to the [`Mesher`](../struct.Mesher.html), its ID, if it was sent with one, and where it was
+  /// added among the packet's messages, if it was sent with that
+  Message(Vec<u8>, Option<ReplyPath>, Option<MessageId>, Option<u32>),
   /// A path to send this packet along
   Transport(String),
+  /// An application-defined chunk, with its kind and encoded contents
+  Custom(u16, Vec<u8>),
+  /// A request to send the reply path back as a delivery receipt
+  ReceiptRequest(ReplyPath),
+  /// A delivery receipt for a packet this node sent
+  Receipt(u64),
+  /// A request to send the reply path back as a failure notice if the packet can't be forwarded
+  FailureRequest(ReplyPath),
+  /// A failure notice for a packet this node sent, with the ID of the receipt it's about
+  FailureNotice(u64),
+  /// A request to hold the packet until the given Unix time, in milliseconds, before forwarding it
+  HoldUntil(u64),
+  /// The Unix time, in milliseconds, after which to drop the packet
+  Expiry(u64),
+  /// A claim of who sent one of the packet's messages, which still has to be checked
+  SenderClaim(SenderClaim),
+  /// An encoded discovery announcement from a peer
+  Announce(Vec<u8>),
+  /// This node's layer of an onion
+  Onion(OpenedLayer),
+  /// This node's header in a reply block
+  ReplyHeader(ReplyStep),
+  /// A step of collecting mail from a relay
+  Mail(MailMessage),
+  /// A message sent to a group this node is in, and its ID
+  GroupMessage(group::GroupId, Vec<u8>, MessageId),
+  /// The key for a group this node's been added to, and who claims to have sent it
+  GroupKey(group::GroupKey, Option<SenderClaim>),
+  /// An endorsement of a key that might sign packets
+  Endorsement(Endorsement),
+  /// A revocation of a key that shouldn't be trusted any more
+  Revocation(Revocation),
 }
 
 impl Chunk {
+  /// Where the chunk was added among its packet's messages, to put them back in that order, or `None` if it isn't a
+  /// message or was sent without one; those go first, in the order they're in.
+  #[cfg(feature = "std")]
+  fn sequence(&self) -> Option<u32> {
+    match self {
+      Chunk::Message(.., seq) => *seq,
+      _ => None,
+    }
+  }
+
+  /// Describes the chunk for people, e.g. `a hop to tcp:[::1]:18540`.
+  fn describe(&self) -> String {
+    match self {
+      Chunk::Message(data, reply, id, seq) => format!(
+        "a message of {} bytes{}{}{}",
+        data.len(),
+        id.map(|id| format!(", ID {:016x}", id.0)).unwrap_or_default(),
+        seq
+          .map(|seq| format!(", number {} in the packet", seq))
+          .unwrap_or_default(),
+        if reply.is_some() { ", with a reply path" } else { "" },
+      ),
+      Chunk::Transport(path) => format!("a hop to {}", path),
+      Chunk::Custom(kind, data) => format!("a custom chunk of kind {}, {} bytes", kind, data.len()),
+      Chunk::ReceiptRequest(_) => "a delivery receipt request".to_owned(),
+      Chunk::Receipt(id) => format!("a delivery receipt for {:016x}", id),
+      Chunk::FailureRequest(_) => "a failure notice request".to_owned(),
+      Chunk::FailureNotice(id) => format!("a failure notice for {:016x}", id),
+      Chunk::HoldUntil(until) => format!("a request to hold the packet until {} ms past the Unix epoch", until),
+      Chunk::Expiry(at) => format!("an expiry at {} ms past the Unix epoch", at),
+      Chunk::SenderClaim(claim) => format!("a claim of who sent message number {}", claim.message),
+      Chunk::Announce(_) => "a discovery announcement".to_owned(),
+      Chunk::Onion(layer) => format!(
+        "an onion layer with {} messages{}",
+        layer.messages.len(),
+        match &layer.forward {
+          Some((path, rest)) => format!(", forwarding {} bytes to {}", rest.len(), path),
+          None => String::new(),
+        }
+      ),
+      Chunk::ReplyHeader(ReplyStep::Forward(path, _)) => format!("a reply block header, forwarding to {}", path),
+      Chunk::ReplyHeader(ReplyStep::Final(id)) => format!("the end of reply block {:016x}", id.0),
+      Chunk::Mail(MailMessage::Request(..)) => "a mail request".to_owned(),
+      Chunk::Mail(MailMessage::Challenge(..)) => "a mail challenge".to_owned(),
+      Chunk::Mail(MailMessage::Claim(..)) => "a mail claim".to_owned(),
+      Chunk::GroupMessage(group, data, id) => format!(
+        "a message of {} bytes to group {:016x}, ID {:016x}",
+        data.len(),
+        group.0,
+        id.0
+      ),
+      Chunk::GroupKey(key, _) => format!("the key for group {:016x}, epoch {}", key.id().0, key.epoch()),
+      Chunk::Endorsement(_) => "an endorsement".to_owned(),
+      Chunk::Revocation(_) => "a revocation".to_owned(),
+    }
+  }
+
   /// Converts a series of bytes from [`Chunk::serialize`](#method.serialize) back to a Chunk, if possible.
   /// Best considered a black box, so it can change freely.
-  fn deserialize(mut from: Vec<u8>, replies: &[Arc<Vec<Vec<u8>>>]) -> Result<Chunk, ()> {
-    match from.get(0) {
-      Some(0) => {
+  pub(crate) fn deserialize(mut from: Vec<u8>, replies: &[ReplyPath]) -> Result<Chunk, ()> {
+    match from.first() {
+      // messages from older nodes, which don't have IDs
+      Some(&UNNUMBERED_MESSAGE) if from.len() >= 2 => {
+        let reply = match from[1] {
+          0 => None,
+          i => Some(replies.get(i as usize - 1).ok_or(())?.clone()),
+        };
+        Ok(Chunk::Message(from.drain(2..).collect(), reply, None, None))
+      }
+      Some(&NUMBERED_MESSAGE) if from.len() >= 10 => {
+        let reply = match from[1] {
+          0 => None,
+          i => Some(replies.get(i as usize - 1).ok_or(())?.clone()),
+        };
+        let mut id = [0; 8];
+        id.copy_from_slice(&from[2..10]);
+        Ok(Chunk::Message(
+          from.drain(10..).collect(),
+          reply,
+          Some(MessageId(u64::from_be_bytes(id))),
+          None,
+        ))
+      }
+      Some(&SEQUENCED_MESSAGE) if from.len() >= 14 => {
         let reply = match from[1] {
           0 => None,
-          i => Some(replies.get(i as usize - 1).expect("Should be valid index").clone()),
+          i => Some(replies.get(i as usize - 1).ok_or(())?.clone()),
         };
-        Ok(Chunk::Message(from.drain(2..).collect(), reply))
+        let mut id = [0; 8];
+        id.copy_from_slice(&from[2..10]);
+        let mut seq = [0; 4];
+        seq.copy_from_slice(&from[10..14]);
+        Ok(Chunk::Message(
+          from.drain(14..).collect(),
+          reply,
+          Some(MessageId(u64::from_be_bytes(id))),
+          Some(u32::from_be_bytes(seq)),
+        ))
       }
-      Some(1) => Ok(Chunk::Transport(
+      Some(&TRANSPORT) => Ok(Chunk::Transport(
         String::from_utf8(from.drain(1..).collect()).map_err(|_| ())?,
       )),
+      Some(&CUSTOM) if from.len() >= 3 => Ok(Chunk::Custom(
+        u16::from_be_bytes([from[1], from[2]]),
+        from.drain(3..).collect(),
+      )),
+      Some(&RECEIPT_REQUEST) if from.len() == 2 => {
+        Ok(Chunk::ReceiptRequest(replies.get(from[1] as usize).ok_or(())?.clone()))
+      }
+      Some(&RECEIPT) if from.len() == 9 => {
+        let mut id = [0; 8];
+        id.copy_from_slice(&from[1..]);
+        Ok(Chunk::Receipt(u64::from_be_bytes(id)))
+      }
+      Some(&FAILURE_REQUEST) if from.len() == 2 => {
+        Ok(Chunk::FailureRequest(replies.get(from[1] as usize).ok_or(())?.clone()))
+      }
+      Some(&FAILURE_NOTICE) if from.len() == 9 => {
+        let mut id = [0; 8];
+        id.copy_from_slice(&from[1..]);
+        Ok(Chunk::FailureNotice(u64::from_be_bytes(id)))
+      }
+      Some(&HOLD_UNTIL) if from.len() == 9 => {
+        let mut until = [0; 8];
+        until.copy_from_slice(&from[1..]);
+        Ok(Chunk::HoldUntil(u64::from_be_bytes(until)))
+      }
+      Some(&EXPIRY) if from.len() == 9 => {
+        let mut at = [0; 8];
+        at.copy_from_slice(&from[1..]);
+        Ok(Chunk::Expiry(u64::from_be_bytes(at)))
+      }
+      Some(&SENDER_CLAIM) => SenderClaim::decode(&from[1..]).map(Chunk::SenderClaim).ok_or(()),
+      Some(&ANNOUNCE) => Ok(Chunk::Announce(from.drain(1..).collect())),
+      Some(&ONION) => OpenedLayer::deserialize(&from[1..]).map(Chunk::Onion).ok_or(()),
+      Some(&REPLY_HEADER) => ReplyStep::decode(&from[1..]).map(Chunk::ReplyHeader).ok_or(()),
+      Some(&MAIL) => MailMessage::decode(&from[1..]).map(Chunk::Mail).ok_or(()),
+      Some(&GROUP_MESSAGE) if from.len() >= 17 => {
+        let mut group = [0; 8];
+        group.copy_from_slice(&from[1..9]);
+        let mut id = [0; 8];
+        id.copy_from_slice(&from[9..17]);
+        Ok(Chunk::GroupMessage(
+          group::GroupId(u64::from_be_bytes(group)),
+          from.drain(17..).collect(),
+          MessageId(u64::from_be_bytes(id)),
+        ))
+      }
+      Some(&GROUP_KEY) if from.len() >= 45 => {
+        let key = group::GroupKey::decode(&from[1..45]).ok_or(())?;
+        let claim = match &from[45..] {
+          [] => None,
+          claim => Some(SenderClaim::decode(claim).ok_or(())?),
+        };
+        Ok(Chunk::GroupKey(key, claim))
+      }
+      Some(&ENDORSEMENT) => Endorsement::from_bytes(&from[1..]).map(Chunk::Endorsement).ok_or(()),
+      Some(&REVOCATION) => Revocation::from_bytes(&from[1..]).map(Chunk::Revocation).ok_or(()),
       _ => Err(()),
     }
   }
 }
 
+/// What [`Packet::debug_decode`](struct.Packet.html#method.debug_decode) could tell about a packet.
+///
+/// Its `Display` impl lays it all out, one chunk per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketDump {
+  /// The packet's format version.
+  pub version: u8,
+  /// Every chunk in the main body of the packet, in the order they're in.
+  pub chunks: Vec<ChunkDump>,
+  /// How many chunks are in each reply path.
+  pub reply_paths: Vec<usize>,
+}
+
+/// What could be told about a single chunk in a [`PacketDump`](struct.PacketDump.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkDump {
+  /// How many bytes the chunk takes up, encrypted.
+  pub len: usize,
+  /// Whether the chunk was signed, if it could be opened; the signature isn't checked.
+  pub signed: bool,
+  /// A description of what's in the chunk, if it could be opened with any of the keys.
+  pub contents: Option<String>,
+}
+
+impl fmt::Display for PacketDump {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "version {} packet with {} chunks", self.version, self.chunks.len())?;
+    for (i, chunk) in self.chunks.iter().enumerate() {
+      write!(f, "  {}: {} bytes, ", i, chunk.len)?;
+      match &chunk.contents {
+        Some(c) => writeln!(f, "{}{}", if chunk.signed { "signed, " } else { "" }, c)?,
+        None => writeln!(f, "can't be opened with these keys")?,
+      }
+    }
+    for (i, len) in self.reply_paths.iter().enumerate() {
+      writeln!(f, "reply path {}: {} chunks", i, len)?;
+    }
+    Ok(())
+  }
+}
+
+/// Identifies a message, so that receivers can tell when they've gotten the same one twice.
+///
+/// Every message gets one, generated randomly unless it's given explicitly, e.g. with
+/// [`Packet::add_message_with_id`](struct.Packet.html#method.add_message_with_id) when resending a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageId(pub u64);
+
+impl MessageId {
+  /// Generates a new, random message ID.
+  pub fn random() -> MessageId {
+    MessageId(rng().gen())
+  }
+}
+
+/// Identifies a delivery receipt requested with [`ReplyPathHandle::use_for_receipt`](struct.ReplyPathHandle.html#method.use_for_receipt).
+///
+/// Pass it to [`Mesher::delivery_status`](struct.Mesher.html#method.delivery_status) to find out whether the receipt has come back yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReceiptId(pub(crate) u64);
+
+/// Adds chunks to one of a packet's reply paths, returned by [`Packet::add_reply_path`](struct.Packet.html#method.add_reply_path).
 pub struct ReplyPathHandle<'packet>(u8, &'packet mut Packet);
 
 impl<'packet> ReplyPathHandle<'packet> {
-  /// Adds a message to the packet, for the node with the right skey to read.
+  /// Adds a message to the packet, for the node with the right skey to read, and returns its randomly generated ID.
   pub fn add_message<'handle>(
     &'handle mut self,
     data: &[u8],
     node_pkey: &encrypt::PublicKey,
     reply: Option<ReplyPathHandle<'handle>>,
-  ) {
-    self.1.add_instruction(
-      Some(self.0),
-      InputChunk::Message(data.to_vec(), reply.map(|h| h.0)),
-      node_pkey,
-    )
+  ) -> MessageId {
+    let id = MessageId::random();
+    self
+      .1
+      .add_numbered_message(Some(self.0), data, reply.map(|h| h.0), id, node_pkey);
+    id
   }
 
   /// Adds a hop to the packet, so that when it reaches the node with the right skey, it'll get forwarded along the given path.
@@ -90,11 +903,69 @@ impl<'packet> ReplyPathHandle<'packet> {
       .add_instruction(Some(self.0), InputChunk::Transport(path), node_pkey)
   }
 
+  /// Adds a hop to the reply path for every step along the route, like [`Packet::via_route`](struct.Packet.html#method.via_route).
+  ///
+  /// The route should start at the node that will send the reply, so it's usually the packet's route, [reversed](struct.Route.html#method.reversed).
+  pub fn via_route(&mut self, route: &Route) {
+    for (path, pkey) in route.hops() {
+      self.add_hop(path, pkey);
+    }
+  }
+
   /// Adds a message to the packet, for the node with the right skey to read, and to reply along the given path.
-  pub fn use_for_message(&mut self, data: &[u8], node_pkey: &encrypt::PublicKey) {
+  /// Returns the message's randomly generated ID.
+  pub fn use_for_message(&mut self, data: &[u8], node_pkey: &encrypt::PublicKey) -> MessageId {
+    let id = MessageId::random();
+    self.1.add_numbered_message(None, data, Some(self.0), id, node_pkey);
+    id
+  }
+
+  /// Asks the node with the right skey to send a delivery receipt back along this reply path when it gets the packet.
+  ///
+  /// The receipt itself is added to the reply path ahead of time, encrypted for `own_pkey` (and signed, if this packet is), so the
+  /// receiving mesher sends it back automatically without needing any keys.
+  /// When it arrives, the mesher holding the matching secret key will mark it [delivered](enum.DeliveryStatus.html#variant.Delivered),
+  /// as long as it's the one that [launched](struct.Mesher.html#method.launch) the packet; receipts it isn't expecting
+  /// are ignored, so anyone who can send it packets can't fill it up with them.
+  ///
+  /// Note that any node which both sees the packet and knows the route back could send the receipt early.
+  /// Receipts say that the packet got somewhere along the reply path's route, not that it was read.
+  pub fn use_for_receipt(&mut self, node_pkey: &encrypt::PublicKey, own_pkey: &encrypt::PublicKey) -> ReceiptId {
+    let id = rng().gen();
+    self.1.add_instruction(Some(self.0), InputChunk::Receipt(id), own_pkey);
     self
       .1
-      .add_instruction(None, InputChunk::Message(data.to_vec(), Some(self.0)), node_pkey)
+      .add_instruction(None, InputChunk::ReceiptRequest(self.0), node_pkey);
+    self.1.receipts.push(ReceiptId(id));
+    ReceiptId(id)
+  }
+
+  /// Asks the node with the right skey to send a failure notice back along this reply path if it can't forward the
+  /// packet, e.g. because the next hop is unreachable, so the sender finds out the route's broken rather than waiting
+  /// forever for a receipt.
+  ///
+  /// Like a [receipt](#method.use_for_receipt), the notice is added to the reply path ahead of time, encrypted for
+  /// `own_pkey` (and signed, if this packet is).
+  /// It's about the given receipt, usually the one the packet's final recipient was asked for, whose
+  /// [status](enum.DeliveryStatus.html) becomes `Failed` when the notice arrives, if it's still pending; notices about
+  /// receipts the mesher isn't expecting are ignored, like the receipts themselves.
+  /// Each relay that might fail needs its own reply path back, and its own request.
+  ///
+  /// Notices are only sent when forwarding fails right away; packets a relay holds on to, e.g. for
+  /// [mixing](struct.MixPolicy.html) or in an [outbound queue](struct.OutboundQueue.html), don't get one if they fail
+  /// later.
+  pub fn use_for_failure_notice(
+    &mut self,
+    node_pkey: &encrypt::PublicKey,
+    own_pkey: &encrypt::PublicKey,
+    receipt: ReceiptId,
+  ) {
+    self
+      .1
+      .add_instruction(Some(self.0), InputChunk::FailureNotice(receipt.0), own_pkey);
+    self
+      .1
+      .add_instruction(None, InputChunk::FailureRequest(self.0), node_pkey);
   }
 }
 
@@ -103,28 +974,73 @@ impl<'packet> ReplyPathHandle<'packet> {
 /// Note that each piece of the packet is associated with a key.
 /// The keys don't have to be unique -- more than one piece can be associated with a single key.
 /// For example, if a node is meant to both receive a message and transport the packet further, those two might be encrypted with the same key.
-#[derive(Clone)]
+///
+/// Chunks are shuffled when the packet's sent, but each message is numbered, under the encryption, so a node gets the
+/// messages for it in the order they were added.
 pub struct Packet {
   pub(crate) main_path: Vec<Vec<u8>>,
   pub(crate) reply_paths: Vec<Vec<Vec<u8>>>,
   pub(crate) signing_key: Option<sign::SecretKey>,
+  crypto: Arc<dyn Crypto>,
+  key_hints: bool,
+  /// How many messages have been added so far, to number the next one.
+  messages: u32,
+  /// Who to claim sent the messages added, if anyone.
+  sender: Option<sign::SecretKey>,
+  /// The receipts asked for, so the mesher launching the packet knows to expect them.
+  pub(crate) receipts: Vec<ReceiptId>,
+}
+
+impl Clone for Packet {
+  fn clone(&self) -> Packet {
+    Packet {
+      main_path: self.main_path.clone(),
+      reply_paths: self.reply_paths.clone(),
+      signing_key: self.signing_key.as_ref().map(sign::SecretKey::clone_secret),
+      crypto: self.crypto.clone(),
+      key_hints: self.key_hints,
+      messages: self.messages,
+      sender: self.sender.as_ref().map(sign::SecretKey::clone_secret),
+      receipts: self.receipts.clone(),
+    }
+  }
 }
 
 impl Packet {
   /// Creates a packet whose chunks won't be signed.
   pub fn unsigned() -> Packet {
+    Packet::unsigned_using(crate::crypto::default_backend())
+  }
+
+  /// Creates a packet whose chunks will be signed by the given key.
+  pub fn signed(skey: sign::SecretKey) -> Packet {
+    Packet::signed_using(skey, crate::crypto::default_backend())
+  }
+
+  /// Starts building an [onion](struct.OnionBuilder.html), where each node's instructions wrap the rest of the packet.
+  pub fn onion() -> OnionBuilder {
+    OnionBuilder::new()
+  }
+
+  /// Creates a packet whose chunks won't be signed, and which are encrypted with the given crypto backend.
+  pub fn unsigned_using(crypto: Arc<dyn Crypto>) -> Packet {
     Packet {
       main_path: vec![],
       reply_paths: vec![],
       signing_key: None,
+      crypto,
+      key_hints: false,
+      messages: 0,
+      sender: None,
+      receipts: vec![],
     }
   }
 
-  /// Creates a packet whose chunks will be signed by the given key.
-  pub fn signed(skey: sign::SecretKey) -> Packet {
+  /// Creates a packet whose chunks will be signed by the given key, and which are encrypted and signed with the given crypto backend.
+  pub fn signed_using(skey: sign::SecretKey, crypto: Arc<dyn Crypto>) -> Packet {
     Packet {
       signing_key: Some(skey),
-      ..Packet::unsigned()
+      ..Packet::unsigned_using(crypto)
     }
   }
 
@@ -134,6 +1050,7 @@ impl Packet {
   ///
   /// Note that the reply block is pre-encrypted and, if applicable, pre-signed by the original sender.
   /// The contents will **not** be signed, even if this packet is a signed one.
+  #[cfg(feature = "std")]
   pub fn reply_to(&mut self, msg: &Message) -> fail::Result<()> {
     match &msg.reply_path {
       None => Err(fail::MesherFail::NoReplyBlock),
@@ -145,12 +1062,61 @@ impl Packet {
   }
 
   fn add_instruction(&mut self, block: Option<u8>, instruct: InputChunk, target_pkey: &encrypt::PublicKey) {
-    let bytes = instruct.serialize();
-    let bytes = encrypt::seal(&bytes, &target_pkey);
-    let bytes = match &self.signing_key {
-      Some(key) => sign::sign(&bytes, key),
-      None => bytes,
+    let sealed = self.crypto.seal(&instruct.serialize(), target_pkey);
+    let mut bytes = if self.key_hints {
+      let mut bytes = vec![SUITE_X25519_HINTED];
+      bytes.extend_from_slice(&self.crypto.key_hint(&sealed[..NONCE_BYTES], target_pkey)[..HINT_BYTES]);
+      bytes
+    } else {
+      vec![SUITE_X25519]
     };
+    bytes.extend(sealed);
+    self.push_chunk(block, bytes);
+  }
+
+  /// Adds an instruction to the main path, sealed for a hybrid key.
+  #[cfg(feature = "pq-hybrid")]
+  fn add_hybrid_instruction(&mut self, instruct: InputChunk, target_pkey: &hybrid::PublicKey) {
+    let mut bytes = vec![SUITE_HYBRID];
+    bytes.extend(hybrid::seal(&instruct.serialize(), target_pkey, self.crypto.as_ref()));
+    self.push_chunk(None, bytes);
+  }
+
+  /// Adds a message to the main path or the given reply path, numbered so the messages for each node can be put back
+  /// in order after being shuffled, and with a claim of who sent it, if there's one to make.
+  fn add_numbered_message(
+    &mut self,
+    block: Option<u8>,
+    data: &[u8],
+    reply: Option<u8>,
+    id: MessageId,
+    node_pkey: &encrypt::PublicKey,
+  ) {
+    self.messages += 1;
+    let seq = self.messages;
+    self.add_instruction(block, InputChunk::Message(data.to_vec(), reply, id, seq), node_pkey);
+    if let Some(sender) = &self.sender {
+      let claim = SenderClaim::new(seq, node_pkey, id, data, sender, self.crypto.as_ref());
+      self.add_instruction(block, InputChunk::SenderClaim(claim), node_pkey);
+    }
+  }
+
+  /// Claims that every message added to the packet from now on was sent by the owner of `skey`, so whoever each message
+  /// is for can [tell who it's from](struct.Message.html#method.verified_sender).
+  ///
+  /// Unlike a [signed packet](#method.signed), whose signatures every node along the way checks against the keys it
+  /// trusts, the claim is encrypted along with the message, so only its recipient sees it, and it's signed along with
+  /// the message and who it's for, so it can't be passed off as being about another message, or sent to anyone else.
+  /// Claims don't stop anyone from receiving the message; it's up to the recipient what to do with who sent it.
+  pub fn claim_sender(&mut self, skey: &sign::SecretKey) {
+    self.sender = Some(skey.clone_secret());
+  }
+
+  /// Signs an encrypted chunk, if this is a signed packet, and adds it to the main path or the given reply path.
+  fn push_chunk(&mut self, block: Option<u8>, mut bytes: Vec<u8>) {
+    if let Some(key) = &self.signing_key {
+      bytes = self.crypto.sign(&bytes, key);
+    }
     match block {
       None => &mut self.main_path,
       Some(idx) => &mut self.reply_paths[idx as usize],
@@ -158,9 +1124,83 @@ impl Packet {
     .push(bytes);
   }
 
-  /// Adds a message to the packet, for the node with the right skey to read.
-  pub fn add_message(&mut self, data: &[u8], node_pkey: &encrypt::PublicKey) {
-    self.add_instruction(None, InputChunk::Message(data.to_vec(), None), node_pkey)
+  /// Sets whether chunks added to the packet from now on get a key hint, which is off by default.
+  ///
+  /// A key hint is a couple of bytes, worked out from the chunk's random nonce and the public key it's for, which let
+  /// nodes skip trying to decrypt chunks that aren't for them.
+  /// That makes a big difference to relays, which otherwise try every chunk of every packet with every key they have.
+  /// Hints from different chunks can't be linked to each other or to a key by someone who doesn't know the key, but
+  /// **anyone who knows a public key can tell which chunks are for it**, so leave hints off when that matters.
+  ///
+  /// Nodes running versions of mesher from before hints were added can't read hinted chunks at all.
+  pub fn use_key_hints(&mut self, on: bool) {
+    self.key_hints = on;
+  }
+
+  /// Adds a message to the packet, for the node with the right skey to read, and returns its randomly generated ID.
+  pub fn add_message(&mut self, data: &[u8], node_pkey: &encrypt::PublicKey) -> MessageId {
+    let id = MessageId::random();
+    self.add_message_with_id(data, node_pkey, id);
+    id
+  }
+
+  /// Adds a message to the packet with a specific ID, e.g. to resend a message without it being delivered twice.
+  ///
+  /// See [`Mesher::set_dedup_window`](struct.Mesher.html#method.set_dedup_window) for how receivers use the ID.
+  pub fn add_message_with_id(&mut self, data: &[u8], node_pkey: &encrypt::PublicKey, id: MessageId) {
+    self.add_numbered_message(None, data, None, id, node_pkey)
+  }
+
+  /// Adds a message to the packet for the holder of a [hybrid key](crypto/hybrid/index.html), and returns its randomly
+  /// generated ID.
+  ///
+  /// It can only be read by nodes built with the `pq-hybrid` feature, which have been given the secret key with
+  /// [`Mesher::add_hybrid_key`](struct.Mesher.html#method.add_hybrid_key).
+  /// Hybrid chunks never get [key hints](#method.use_key_hints).
+  #[cfg(feature = "pq-hybrid")]
+  pub fn add_hybrid_message(&mut self, data: &[u8], node_pkey: &hybrid::PublicKey) -> MessageId {
+    let id = MessageId::random();
+    self.messages += 1;
+    let seq = self.messages;
+    self.add_hybrid_instruction(InputChunk::Message(data.to_vec(), None, id, seq), node_pkey);
+    if let Some(sender) = &self.sender {
+      let claim = SenderClaim::new(seq, node_pkey.x25519(), id, data, sender, self.crypto.as_ref());
+      self.add_hybrid_instruction(InputChunk::SenderClaim(claim), node_pkey);
+    }
+    id
+  }
+
+  /// Adds a message to the packet that every member of the group can read, and returns its randomly generated ID.
+  ///
+  /// Members need the group's key, in the same epoch as `group`, to read it; see
+  /// [`Mesher::join_group`](struct.Mesher.html#method.join_group).
+  /// One chunk is enough for the whole group, but the packet still has to be routed to each member to reach them.
+  pub fn add_group_message(&mut self, group: &group::GroupKey, data: &[u8]) -> MessageId {
+    let id = MessageId::random();
+    let sealed = self.crypto.group_seal(
+      &InputChunk::GroupMessage(group.id(), data.to_vec(), id).serialize(),
+      group,
+    );
+    let mut bytes = vec![SUITE_GROUP];
+    bytes.extend_from_slice(&group.id().0.to_be_bytes());
+    bytes.extend_from_slice(&group.epoch().to_be_bytes());
+    bytes.extend(sealed);
+    self.push_chunk(None, bytes);
+    id
+  }
+
+  /// Adds a group's key to the packet, wrapped for the node with the right skey, so it can read the group's messages.
+  ///
+  /// Anyone who receives this can read everything sent to the group in the key's epoch, so only send it to members.
+  /// If the packet [claims a sender](#method.claim_sender), the claim covers the key too, which is what lets members
+  /// take new keys for a group they've joined without asking; see
+  /// [`Mesher::take_group_invites`](struct.Mesher.html#method.take_group_invites).
+  pub fn add_group_key(&mut self, key: &group::GroupKey, member_pkey: &encrypt::PublicKey) {
+    let claim = self
+      .sender
+      .as_ref()
+      .map(|sender| SenderClaim::for_group_key(member_pkey, key, sender, self.crypto.as_ref()));
+    self.add_instruction(None, InputChunk::GroupKey(key.encode(), claim), member_pkey)
   }
 
   /// Adds a hop to the packet, so that when it reaches the node with the right skey, it'll get forwarded along the given path.
@@ -168,18 +1208,109 @@ impl Packet {
     self.add_instruction(None, InputChunk::Transport(path), node_pkey)
   }
 
+  /// Adds an [endorsement](identity/struct.Endorsement.html) of the key signing this packet, for the node with the
+  /// right skey, so it accepts the packet if it trusts the endorser, even if it's never seen the signing key.
+  ///
+  /// Only makes sense in signed packets; the receiver remembers the endorsement, so it only needs to be sent once.
+  pub fn add_endorsement(&mut self, endorsement: &Endorsement, node_pkey: &encrypt::PublicKey) {
+    self.add_instruction(None, InputChunk::Endorsement(endorsement.to_bytes()), node_pkey)
+  }
+
+  /// Adds a [revocation](identity/struct.Revocation.html), for the node with the right skey to stop trusting the
+  /// revoked key and pass the revocation on, as with
+  /// [`Mesher::publish_revocation`](struct.Mesher.html#method.publish_revocation).
+  pub fn add_revocation(&mut self, revocation: &Revocation, node_pkey: &encrypt::PublicKey) {
+    self.add_instruction(None, InputChunk::Revocation(revocation.to_bytes()), node_pkey)
+  }
+
+  /// Adds a hop for every step along the route, so the packet gets passed from each node in it to the next.
+  pub fn via_route(&mut self, route: &Route) {
+    for (path, pkey) in route.hops() {
+      self.add_hop(path, pkey);
+    }
+  }
+
+  /// Asks the node with the right skey to hold the packet until `until` before forwarding it along its hops, e.g. to
+  /// leave it in a dead drop, or so the packet leaving can't be matched up with it arriving by timing.
+  ///
+  /// Only relays which have [opted in](struct.Mesher.html#method.set_max_hold) hold packets, and only for as long as
+  /// they're willing to; the rest forward them right away, as usual.
+  /// The time is sent as it is, so it's only as accurate as the relay's clock.
+  #[cfg(feature = "std")]
+  pub fn add_hold_until(&mut self, until: std::time::SystemTime, node_pkey: &encrypt::PublicKey) {
+    let until = until
+      .duration_since(std::time::UNIX_EPOCH)
+      .map_or(0, |d| d.as_millis() as u64);
+    self.add_instruction(None, InputChunk::HoldUntil(until), node_pkey)
+  }
+
+  /// Tells the node with the right skey to drop the packet if it gets there after `at`, rather than forwarding it or
+  /// reading the messages in it, e.g. so a packet stuck in a [mailbox](struct.Mailbox.html) or an
+  /// [outbound queue](struct.OutboundQueue.html) for days isn't delivered long after it stopped mattering.
+  ///
+  /// Each node only sees the expiry if it's given one, so add it for every node along the way that should check.
+  /// Nodes allow for their clocks being a little off from the sender's; see
+  /// [`Mesher::set_clock_skew`](struct.Mesher.html#method.set_clock_skew).
+  #[cfg(feature = "std")]
+  pub fn add_expiry(&mut self, at: std::time::SystemTime, node_pkey: &encrypt::PublicKey) {
+    let at = at
+      .duration_since(std::time::UNIX_EPOCH)
+      .map_or(0, |d| d.as_millis() as u64);
+    self.add_instruction(None, InputChunk::Expiry(at), node_pkey)
+  }
+
+  /// Adds an encoded discovery announcement to the packet, for the peer with the right skey to read.
+  #[cfg(feature = "std")]
+  pub(crate) fn add_announcement(&mut self, announcement: Vec<u8>, node_pkey: &encrypt::PublicKey) {
+    self.add_instruction(None, InputChunk::Announce(announcement), node_pkey)
+  }
+
+  /// Adds an encoded onion layer to the packet, for the node with the right skey to unwrap.
+  pub(crate) fn add_onion_layer(&mut self, layer: Vec<u8>, node_pkey: &encrypt::PublicKey) {
+    self.add_instruction(None, InputChunk::Onion(layer), node_pkey)
+  }
+
+  /// Adds a step of a reply block to the packet, for the node with the right skey to follow.
+  #[cfg(feature = "std")]
+  pub(crate) fn add_reply_step(&mut self, step: &ReplyStep, node_pkey: &encrypt::PublicKey) {
+    self.add_instruction(None, InputChunk::ReplyHeader(step.encode()), node_pkey)
+  }
+
+  /// Adds a step of collecting mail to the packet, for the node with the right skey to handle.
+  #[cfg(feature = "std")]
+  pub(crate) fn add_mail(&mut self, mail: &MailMessage, node_pkey: &encrypt::PublicKey) {
+    self.add_instruction(None, InputChunk::Mail(mail.encode()), node_pkey)
+  }
+
+  /// Adds an application-defined chunk to the packet, for the node with the right skey to handle.
+  pub fn add_custom<C: CustomChunk>(&mut self, chunk: &C, node_pkey: &encrypt::PublicKey) {
+    self.add_instruction(None, InputChunk::Custom(C::KIND, chunk.encode()), node_pkey)
+  }
+
   /// Starts creating a reply path.
-  pub fn add_reply_path(&mut self) -> Option<ReplyPathHandle> {
-    if self.reply_paths.len() == u8::max_value() as usize {
+  pub fn add_reply_path(&mut self) -> Option<ReplyPathHandle<'_>> {
+    if self.reply_paths.len() == u8::MAX as usize {
       return None;
     }
     self.reply_paths.push(vec![]);
     Some(ReplyPathHandle(self.reply_paths.len() as u8 - 1, self))
   }
 
+  /// How many chunks have been added to the packet's main path so far.
+  ///
+  /// Once it's serialized, [`Packet::parse_untrusted`](#method.parse_untrusted) finds the same number.
+  pub fn chunk_count(&self) -> usize {
+    self.main_path.len()
+  }
+
+  /// How many chunks have been added to each of the packet's reply paths so far, in the order they were added.
+  pub fn reply_path_lens(&self) -> Vec<usize> {
+    self.reply_paths.iter().map(Vec::len).collect()
+  }
+
   /// Serializes the packet into a sendable format.
   pub(crate) fn serialize(mut self) -> fail::Result<Vec<u8>> {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     let mut paths = Vec::with_capacity(self.reply_paths.len() + 1);
     self.main_path.shuffle(&mut rng);
     paths.push(self.main_path);
@@ -187,7 +1318,69 @@ impl Packet {
       path.shuffle(&mut rng);
       paths.push(path);
     }
-    bincode::serialize(&paths).map_err(|e| fail::MesherFail::Other(Box::new(e)))
+    let mut packet = vec![PACKET_VERSION];
+    encode_paths(&paths, &mut packet);
+    Ok(packet)
+  }
+
+  /// Parses the layout of a packet, without decrypting anything, so it needs no keys.
+  ///
+  /// This is the first thing a mesher does with every packet it receives, so it's built to take anything thrown at it:
+  /// it never panics, every count and length is checked against the input before it's used, and nothing is copied,
+  /// so it never allocates much more than a few pointers per chunk.
+  /// That makes it the entry point to use for fuzzing.
+  ///
+  /// ```
+  /// # use mesher::{prelude::*, ParseError};
+  /// assert_eq!(Err(ParseError::UnsupportedVersion(0xFF)), Packet::parse_untrusted(&[0xFF]).map(|_| ()));
+  /// ```
+  pub fn parse_untrusted(packet: &[u8]) -> Result<ParsedPacket<'_>, ParseError> {
+    let (&version, packet) = packet.split_first().ok_or(ParseError::Empty)?;
+    if !SUPPORTED_VERSIONS.contains(&version) {
+      return Err(ParseError::UnsupportedVersion(version));
+    }
+    let mut paths = match version {
+      // the same options as bincode::deserialize, which wrote these, plus a limit so length prefixes can't make it
+      // allocate past the size of the input
+      #[cfg(feature = "std")]
+      1 => bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(packet.len() as u64)
+        .deserialize::<Vec<Vec<&[u8]>>>(packet)
+        .map_err(|_| ParseError::Malformed)?,
+      _ => decode_paths(packet)?,
+    };
+    if paths.is_empty() {
+      return Err(ParseError::NoMainPath);
+    }
+    if paths.len() > MAX_PATHS {
+      return Err(ParseError::TooManyPaths(paths.len()));
+    }
+    if let Some(path) = paths.iter().find(|p| p.len() > MAX_CHUNKS) {
+      return Err(ParseError::TooManyChunks(path.len()));
+    }
+    let reply_paths = paths.split_off(1);
+    let chunks = paths.pop().expect("Already validated length before");
+    Ok(ParsedPacket {
+      version,
+      chunks,
+      reply_paths,
+    })
+  }
+
+  /// Checks the version of a serialized packet, then splits it into the main path and the reply paths.
+  ///
+  /// The main path's chunks are borrowed from the packet, since they're only decrypted; only the reply paths, which can
+  /// outlive it in messages, are copied.
+  fn split_paths(packet: &[u8]) -> fail::Result<(Vec<&[u8]>, Vec<ReplyPath>)> {
+    let parsed = Packet::parse_untrusted(packet)?;
+    let reply_blocks = parsed
+      .reply_paths
+      .into_iter()
+      .map(|p| Arc::new(p.into_iter().map(<[u8]>::to_vec).collect()))
+      .collect();
+    Ok((parsed.chunks, reply_blocks))
   }
 
   /// Given a packet and all of our secret keys, decrypt as many chunks as possible.
@@ -196,41 +1389,148 @@ impl Packet {
   /// composed of [`Chunk::Encrypted`](enum.Chunk.html#variant.Encrypted).
   ///
   /// See [`Chunk::decrypt`](enum.Chunk.html#method.decrypt) for more information.
-  pub(crate) fn deserialize(packet: &[u8], keys: &[encrypt::SecretKey]) -> fail::Result<Vec<Chunk>> {
-    let blocks = bincode::deserialize::<Vec<Vec<Vec<u8>>>>(packet);
-    let mut blocks = match blocks {
-      Ok(blocks) if !blocks.is_empty() => blocks,
-      _ => return Err(fail::MesherFail::InvalidPacket),
-    };
-    let reply_blocks: Vec<_> = blocks.split_off(1).into_iter().map(Arc::new).collect();
-    let main = blocks.pop().expect("Already validated length before");
-    let main = main
+  #[cfg(feature = "std")]
+  pub(crate) fn deserialize(
+    packet: &[u8],
+    keys: &[encrypt::SecretKey],
+    groups: &[group::GroupKey],
+    crypto: &dyn Crypto,
+  ) -> fail::Result<Vec<Chunk>> {
+    Packet::deserialize_with(packet, &OwnKeys::new(keys, groups), crypto)
+  }
+
+  /// Same as [`Packet::deserialize`](#method.deserialize), but with all of a mesher's keys.
+  #[cfg(feature = "std")]
+  pub(crate) fn deserialize_with(packet: &[u8], keys: &OwnKeys, crypto: &dyn Crypto) -> fail::Result<Vec<Chunk>> {
+    let (main, reply_blocks) = Packet::split_paths(packet)?;
+    let mut main: Vec<_> = main
       .into_iter()
-      .filter_map(|b| keys.iter().find_map(|k| encrypt::open(&b, k).ok()))
+      .filter_map(|b| open_chunk(b, keys, crypto))
       .filter_map(|c| Chunk::deserialize(c, &reply_blocks).ok())
       .collect();
+    // chunks are shuffled when they're sent, so messages have to be put back in order
+    main.sort_by_key(Chunk::sequence);
     Ok(main)
   }
 
-  /// Same as [`Packet::from_bytes`](#method.from_bytes) but only decrypts chunks signed with one of the valid keys.
+  /// Describes everything that can be seen in a packet with the given keys, for debugging, e.g. why a route isn't working.
+  ///
+  /// Unlike actually receiving a packet, signatures aren't checked, and chunks that can be opened but not parsed are
+  /// still listed.
+  /// Fails only if the packet itself can't be parsed, with the same errors a mesher would report.
+  ///
+  /// ```
+  /// # use mesher::prelude::*;
+  /// # let (pk, sk) = encrypt::gen_keypair();
+  /// # let (other_pk, _) = encrypt::gen_keypair();
+  /// # let mut packet = Packet::unsigned();
+  /// # packet.add_hop("tcp:[::1]:18540".to_owned(), &pk);
+  /// # packet.add_message(b"hi", &other_pk);
+  /// # let bytes = packet.into_bytes().unwrap();
+  /// let dump = Packet::debug_decode(&bytes, &[sk]).expect("Packet is malformed");
+  /// println!("{}", dump);
+  /// ```
+  pub fn debug_decode(packet: &[u8], keys: &[encrypt::SecretKey]) -> fail::Result<PacketDump> {
+    let (opened, reply_blocks) = Packet::open_unchecked(packet, keys)?;
+    let chunks = opened
+      .into_iter()
+      .map(|(chunk, signed, opened)| ChunkDump {
+        len: chunk.len(),
+        contents: opened.map(|c| match Chunk::deserialize(c, &reply_blocks) {
+          Ok(chunk) => chunk.describe(),
+          Err(()) => "a chunk that couldn't be parsed".to_owned(),
+        }),
+        signed,
+      })
+      .collect();
+    Ok(PacketDump {
+      version: packet[0],
+      chunks,
+      reply_paths: reply_blocks.iter().map(|b| b.len()).collect(),
+    })
+  }
+
+  /// Opens every chunk in the main path that any of the keys can, without checking signatures.
+  ///
+  /// Returns every chunk in the order they're in, along with the reply paths.
+  pub(crate) fn open_unchecked<'a>(
+    packet: &'a [u8],
+    keys: &[encrypt::SecretKey],
+  ) -> fail::Result<(Vec<OpenedChunk<'a>>, Vec<ReplyPath>)> {
+    let crypto = crate::crypto::default_backend();
+    let (main, reply_blocks) = Packet::split_paths(packet)?;
+    let keys = OwnKeys::new(keys, &[]);
+    let opened = main
+      .iter()
+      .map(|b| match open_chunk(b, &keys, crypto.as_ref()) {
+        Some(opened) => (*b, false, Some(opened)),
+        None => match open_chunk(b.get(SIGNATURE_BYTES..).unwrap_or_default(), &keys, crypto.as_ref()) {
+          Some(opened) => (*b, true, Some(opened)),
+          None => (*b, false, None),
+        },
+      })
+      .collect();
+    Ok((opened, reply_blocks))
+  }
+
+  /// Serializes the packet into the bytes that would be sent, e.g. to save it for
+  /// [`debug_decode`](#method.debug_decode) later.
+  pub fn into_bytes(self) -> fail::Result<Vec<u8>> {
+    self.serialize()
+  }
+
+  /// Same as [`Packet::deserialize`](#method.deserialize) but only decrypts chunks signed with one of the valid keys.
+  ///
+  /// Keys endorsed by one of `trust_roots` are valid too, so long as the endorsement is in the packet, signed by the
+  /// key it endorses.
+  /// Chunks which decrypt with one of the keys but aren't properly signed are left out, and a
+  /// [`MesherFail::Tampered`](../fail/enum.MesherFail.html#variant.Tampered) is added to `failures` for each.
+  #[cfg(feature = "std")]
   pub(crate) fn deserialize_signed(
     packet: &[u8],
-    keys: &[encrypt::SecretKey],
+    keys: &OwnKeys,
     sender_keys: &[sign::PublicKey],
+    trust_roots: &[sign::PublicKey],
+    crypto: &dyn Crypto,
+    failures: &mut Vec<fail::MesherFail>,
   ) -> fail::Result<Vec<Chunk>> {
-    let blocks = bincode::deserialize::<Vec<Vec<Vec<u8>>>>(packet);
-    let mut blocks = match blocks {
-      Ok(blocks) if !blocks.is_empty() => blocks,
-      _ => return Err(fail::MesherFail::InvalidPacket),
-    };
-    let reply_blocks: Vec<_> = blocks.split_off(1).into_iter().map(Arc::new).collect();
-    let main = blocks.pop().expect("Already validated length before");
-    let main = main
+    let (main, reply_blocks) = Packet::split_paths(packet)?;
+    let mut opened = vec![];
+    // chunks for this node that no sender key signed, which might still be signed by a key endorsed in this packet
+    let mut unverified = vec![];
+    for b in main {
+      match sender_keys.iter().find_map(|k| crypto.verify(b, k)) {
+        Some(verified) => opened.extend(open_chunk(&verified, keys, crypto)),
+        None => {
+          if let Some(chunk) = open_chunk(b.get(SIGNATURE_BYTES..).unwrap_or_default(), keys, crypto) {
+            unverified.push((b, chunk));
+          }
+        }
+      }
+    }
+    if !unverified.is_empty() && !trust_roots.is_empty() {
+      // an endorsement only counts if it's signed by the key it endorses, so it can't be lifted from another packet
+      let endorsed: Vec<_> = unverified
+        .iter()
+        .filter(|(_, chunk)| chunk.first() == Some(&ENDORSEMENT))
+        .filter_map(|(b, chunk)| Some((b, Endorsement::from_bytes(&chunk[1..])?)))
+        .filter(|(b, e)| {
+          trust_roots.contains(e.endorser()) && e.verify_using(crypto) && crypto.verify(b, e.subject()).is_some()
+        })
+        .map(|(_, e)| *e.subject())
+        .collect();
+      let (now_verified, tampered): (Vec<_>, _) = unverified
+        .into_iter()
+        .partition(|(b, _)| endorsed.iter().any(|k| crypto.verify(b, k).is_some()));
+      opened.extend(now_verified.into_iter().map(|(_, chunk)| chunk));
+      unverified = tampered;
+    }
+    failures.extend(unverified.iter().map(|_| fail::MesherFail::Tampered));
+    let mut main: Vec<_> = opened
       .into_iter()
-      .filter_map(|b| sender_keys.iter().find_map(|k| sign::verify(&b, k).ok()))
-      .filter_map(|b| keys.iter().find_map(|k| encrypt::open(&b, k).ok()))
       .filter_map(|c| Chunk::deserialize(c, &reply_blocks).ok())
       .collect();
+    main.sort_by_key(Chunk::sequence);
     Ok(main)
   }
 }
@@ -238,6 +1538,81 @@ impl Packet {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::crypto::Sodium;
+  use proptest::prelude::{any, prop, prop_assert, prop_assert_eq, prop_assume, prop_oneof, proptest, Strategy};
+
+  fn contents(chunks: &[Chunk]) -> Vec<Vec<u8>> {
+    chunks
+      .iter()
+      .filter_map(|c| match c {
+        Chunk::Message(data, _, _, _) => Some(data.clone()),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Counts how many times chunks are trial-decrypted, and otherwise does exactly what [`Sodium`] does.
+  #[derive(Default)]
+  struct CountingOpens(std::sync::atomic::AtomicUsize);
+
+  impl Crypto for CountingOpens {
+    fn gen_encrypt_keypair(&self) -> (encrypt::PublicKey, encrypt::SecretKey) {
+      Sodium.gen_encrypt_keypair()
+    }
+    fn encrypt_public_key(&self, skey: &encrypt::SecretKey) -> encrypt::PublicKey {
+      Sodium.encrypt_public_key(skey)
+    }
+    fn seal(&self, data: &[u8], pkey: &encrypt::PublicKey) -> Vec<u8> {
+      Sodium.seal(data, pkey)
+    }
+    fn open(&self, data: &[u8], skey: &encrypt::SecretKey) -> Option<Vec<u8>> {
+      self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+      Sodium.open(data, skey)
+    }
+    fn gen_sign_keypair(&self) -> (sign::PublicKey, sign::SecretKey) {
+      Sodium.gen_sign_keypair()
+    }
+    fn sign_public_key(&self, skey: &sign::SecretKey) -> sign::PublicKey {
+      Sodium.sign_public_key(skey)
+    }
+    fn sign(&self, data: &[u8], skey: &sign::SecretKey) -> Vec<u8> {
+      Sodium.sign(data, skey)
+    }
+    fn verify(&self, signed: &[u8], pkey: &sign::PublicKey) -> Option<Vec<u8>> {
+      Sodium.verify(signed, pkey)
+    }
+  }
+
+  #[test]
+  fn key_hints_skip_other_chunks() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let others: Vec<_> = (0..16).map(|_| encrypt::gen_keypair().0).collect();
+    let mut hinted = Packet::unsigned();
+    hinted.use_key_hints(true);
+    for (i, other) in others.iter().enumerate() {
+      hinted.add_message(&[i as u8], other);
+    }
+    hinted.add_message(&[0xFF], &pk);
+    let hinted = hinted.serialize().expect("Failed to serialize");
+    // after the version byte, path count, chunk count, and the first chunk's length
+    assert_eq!(SUITE_X25519_HINTED, hinted[13]);
+
+    let counter = CountingOpens::default();
+    let chunks = Packet::deserialize(&hinted, std::slice::from_ref(&sk), &[], &counter).expect("Failed to deserialize");
+    assert_eq!(vec![vec![0xFF]], contents(&chunks));
+    // with 2-byte hints, a false match among the other 16 chunks is very unlikely
+    assert!(counter.0.into_inner() <= 2);
+
+    // and unhinted chunks are still all tried
+    let mut plain = Packet::unsigned();
+    for other in &others {
+      plain.add_message(&[0], other);
+    }
+    let plain = plain.serialize().expect("Failed to serialize");
+    let counter = CountingOpens::default();
+    Packet::deserialize(&plain, &[sk], &[], &counter).expect("Failed to deserialize");
+    assert_eq!(others.len(), counter.0.into_inner());
+  }
 
   #[test]
   fn unsigned_serialized_deserializable() {
@@ -249,11 +1624,11 @@ mod tests {
     packet.add_message(&[1, 2, 3], &pk2);
     let packet = packet.serialize().expect("Failed to serialize packet");
 
-    let dec1 = Packet::deserialize(&packet, &[sk1]).expect("Failed to deserialize packets");
+    let dec1 = Packet::deserialize(&packet, &[sk1], &[], &Sodium).expect("Failed to deserialize packets");
     assert!(dec1.contains(&Chunk::Transport("hello".to_owned())));
 
-    let dec2 = Packet::deserialize(&packet, &[sk2]).expect("Failed to deserialize packets");
-    assert!(dec2.contains(&Chunk::Message(vec![1, 2, 3], None)));
+    let dec2 = Packet::deserialize(&packet, &[sk2], &[], &Sodium).expect("Failed to deserialize packets");
+    assert_eq!(vec![vec![1, 2, 3]], contents(&dec2));
   }
 
   #[test]
@@ -267,11 +1642,13 @@ mod tests {
     packet.add_message(&[1, 2, 3], &pk2);
     let packet = packet.serialize().expect("Failed to serialize packet");
 
-    let dec1 = Packet::deserialize_signed(&packet, &[sk1], &[pks]).expect("Failed to deserialize packets");
+    let dec1 = Packet::deserialize_signed(&packet, &OwnKeys::new(&[sk1], &[]), &[pks], &[], &Sodium, &mut vec![])
+      .expect("Failed to deserialize packets");
     assert!(dec1.contains(&Chunk::Transport("hello".to_owned())));
 
-    let dec2 = Packet::deserialize_signed(&packet, &[sk2], &[pks]).expect("Failed to deserialize packets");
-    assert!(dec2.contains(&Chunk::Message(vec![1, 2, 3], None)));
+    let dec2 = Packet::deserialize_signed(&packet, &OwnKeys::new(&[sk2], &[]), &[pks], &[], &Sodium, &mut vec![])
+      .expect("Failed to deserialize packets");
+    assert_eq!(vec![vec![1, 2, 3]], contents(&dec2));
   }
 
   #[test]
@@ -318,10 +1695,10 @@ mod tests {
       packet.serialize().expect("Failed to serialize packet")
     };
 
-    let deser = Packet::deserialize(&bytes, &[sk]).expect("Failed to deserialize");
+    let deser = Packet::deserialize(&bytes, &[sk], &[], &Sodium).expect("Failed to deserialize");
     let mut messages = HashMap::new();
     for chunk in deser {
-      if let Chunk::Message(data, rep) = chunk {
+      if let Chunk::Message(data, rep, _, _) = chunk {
         messages.insert(data[0], rep);
       }
     }
@@ -366,10 +1743,11 @@ mod tests {
       packet.serialize().expect("Failed to serialize packet")
     };
 
-    let deser = Packet::deserialize_signed(&bytes, &[rsk], &[spk]).expect("Failed to deserialize");
+    let deser = Packet::deserialize_signed(&bytes, &OwnKeys::new(&[rsk], &[]), &[spk], &[], &Sodium, &mut vec![])
+      .expect("Failed to deserialize");
     let mut messages = HashMap::new();
     for chunk in deser {
-      if let Chunk::Message(data, rep) = chunk {
+      if let Chunk::Message(data, rep, _, _) = chunk {
         messages.insert(data[0], rep);
       }
     }
@@ -387,4 +1765,487 @@ mod tests {
     // and 2/3 and 4/5 should be different
     assert_ne!(messages[&3], messages[&4]);
   }
+
+  #[test]
+  fn unknown_suite_skipped() {
+    let (pk, sk) = encrypt::gen_keypair();
+
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[1], &pk);
+    packet.add_message(&[2], &pk);
+    // pretend the second chunk was encrypted by a newer node, in a suite this one doesn't know
+    packet.main_path[1][0] = 255;
+    let packet = packet.serialize().expect("Failed to serialize packet");
+
+    let dec = Packet::deserialize(&packet, &[sk], &[], &Sodium).expect("Failed to deserialize packet");
+    assert_eq!(vec![vec![1]], contents(&dec));
+  }
+
+  #[test]
+  fn tampered_chunks_reported() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let (other_pk, _) = encrypt::gen_keypair();
+    let (spk, ssk) = sign::gen_keypair();
+
+    let mut packet = Packet::signed(ssk);
+    packet.add_message(&[1], &pk);
+    packet.add_message(&[2], &pk);
+    packet.add_message(&[3], &other_pk);
+    // break the signatures on one chunk for us and one for someone else
+    packet.main_path[0][0] ^= 1;
+    packet.main_path[2][0] ^= 1;
+    let packet = packet.serialize().expect("Failed to serialize packet");
+
+    let mut failures = vec![];
+    let dec = Packet::deserialize_signed(&packet, &OwnKeys::new(&[sk], &[]), &[spk], &[], &Sodium, &mut failures)
+      .expect("Failed to deserialize");
+    assert_eq!(vec![vec![2]], contents(&dec));
+    // the chunk for someone else can't be told apart from any other chunk for someone else
+    assert_eq!(1, failures.len());
+    assert!(matches!(failures[0], fail::MesherFail::Tampered));
+  }
+
+  #[test]
+  fn future_version_rejected() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[1], &pk);
+    let mut packet = packet.serialize().expect("Failed to serialize packet");
+    assert_eq!(PACKET_VERSION, packet[0]);
+
+    packet[0] = PACKET_VERSION + 1;
+    match Packet::deserialize(&packet, &[sk], &[], &Sodium) {
+      Err(fail::MesherFail::UnsupportedVersion(v)) => assert_eq!(PACKET_VERSION + 1, v),
+      other => panic!("expected UnsupportedVersion, got {:?}", other),
+    }
+    match Packet::deserialize(&[], &[], &[], &Sodium) {
+      Err(fail::MesherFail::InvalidPacket) => (),
+      other => panic!("expected InvalidPacket, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn paths_encoded_exactly() {
+    let paths = vec![vec![vec![0xAA], vec![]], vec![vec![0xBB, 0xCC]]];
+    let mut bytes = vec![];
+    encode_paths(&paths, &mut bytes);
+    #[rustfmt::skip]
+    let golden = vec![
+      0, 0, 0, 2, // two paths
+      0, 0, 0, 2, // main path has two chunks
+      0, 0, 0, 1, 0xAA,
+      0, 0, 0, 0,
+      0, 0, 0, 1, // reply path has one chunk
+      0, 0, 0, 2, 0xBB, 0xCC,
+    ];
+    assert_eq!(golden, bytes);
+    let borrowed: Vec<Vec<&[u8]>> = vec![vec![&[0xAA], &[]], vec![&[0xBB, 0xCC]]];
+    assert_eq!(Ok(borrowed), decode_paths(&golden));
+  }
+
+  #[test]
+  fn malformed_paths_rejected() {
+    // truncated in the middle of a chunk
+    assert_eq!(
+      Err(ParseError::Truncated),
+      decode_paths(&[0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 2, 0xAA])
+    );
+    // trailing garbage
+    assert_eq!(Err(ParseError::TrailingBytes), decode_paths(&[0, 0, 0, 0, 0xFF]));
+    // a huge count with nothing behind it
+    assert_eq!(
+      Err(ParseError::TooManyPaths(0xFFFFFFFF)),
+      decode_paths(&[0xFF, 0xFF, 0xFF, 0xFF])
+    );
+    assert_eq!(
+      Err(ParseError::TooManyChunks(0xFFFFFFFF)),
+      decode_paths(&[0, 0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF])
+    );
+  }
+
+  #[test]
+  fn untrusted_input_never_panics() {
+    assert_eq!(Err(ParseError::Empty), Packet::parse_untrusted(&[]).map(|_| ()));
+    assert_eq!(
+      Err(ParseError::NoMainPath),
+      Packet::parse_untrusted(&[2, 0, 0, 0, 0]).map(|_| ())
+    );
+    // a version 1 packet claiming a vec of 2^64 - 1 paths
+    let huge = [1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+    assert_eq!(Err(ParseError::Malformed), Packet::parse_untrusted(&huge).map(|_| ()));
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(331);
+    for _ in 0..2000 {
+      let len = rng.gen_range(0, 64);
+      let mut bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+      if let Some(version) = bytes.first_mut() {
+        *version = rng.gen_range(1, 3);
+      }
+      let _ = Packet::parse_untrusted(&bytes);
+      let _ = Packet::debug_decode(&bytes, &[]);
+    }
+  }
+
+  #[test]
+  fn group_messages_need_the_group_key() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let key = group::GroupKey::generate();
+    let mut packet = Packet::unsigned();
+    let id = packet.add_group_message(&key, &[1]);
+    // a group message sealed for one node directly doesn't count as being sent to the group
+    packet.add_instruction(None, InputChunk::GroupMessage(key.id(), vec![2], id), &pk);
+    let packet = packet.serialize().expect("Failed to serialize");
+
+    let dec = Packet::deserialize(&packet, std::slice::from_ref(&sk), std::slice::from_ref(&key), &Sodium)
+      .expect("Failed to deserialize");
+    assert_eq!(vec![Chunk::GroupMessage(key.id(), vec![1], id)], dec);
+    for other in [group::GroupKey::generate(), key.next()] {
+      let dec =
+        Packet::deserialize(&packet, std::slice::from_ref(&sk), &[other], &Sodium).expect("Failed to deserialize");
+      assert!(dec.is_empty());
+    }
+  }
+
+  #[test]
+  #[cfg(feature = "pq-hybrid")]
+  fn hybrid_messages_need_the_hybrid_key() {
+    let (pk, sk) = hybrid::gen_keypair_using(&Sodium);
+    let (_, other) = hybrid::gen_keypair_using(&Sodium);
+    let (spk, ssk) = sign::gen_keypair();
+
+    let mut packet = Packet::unsigned_using(Arc::new(Sodium));
+    packet.claim_sender(&ssk);
+    let id = packet.add_hybrid_message(b"quantum", &pk);
+    let packet = packet.serialize().expect("Failed to serialize packet");
+
+    let dec = Packet::deserialize_with(
+      &packet,
+      &OwnKeys::new(&[], &[]).hybrid(std::slice::from_ref(&sk)),
+      &Sodium,
+    )
+    .expect("Failed to deserialize");
+    assert_eq!(vec![b"quantum".to_vec()], contents(&dec));
+    assert!(dec
+      .iter()
+      .any(|c| matches!(c, Chunk::Message(_, _, Some(i), _) if *i == id)));
+    let claim = dec.iter().find_map(|c| match c {
+      Chunk::SenderClaim(claim) => Some(claim),
+      _ => None,
+    });
+    assert_eq!(
+      Some(spk),
+      claim.and_then(|c| c.verify(pk.x25519(), id, b"quantum", &Sodium))
+    );
+
+    // nodes with only the X25519 half, or someone else's key, can't read it
+    let x25519 = sk.to_bytes()[..32].to_vec();
+    let x25519 = encrypt::SecretKey::from_slice(&x25519).unwrap();
+    let dec = Packet::deserialize(&packet, &[x25519], &[], &Sodium).expect("Failed to deserialize");
+    assert!(dec.is_empty());
+    let dec = Packet::deserialize_with(&packet, &OwnKeys::new(&[], &[]).hybrid(&[other]), &Sodium)
+      .expect("Failed to deserialize");
+    assert!(dec.is_empty());
+  }
+
+  #[test]
+  fn chunk_types_distinct() {
+    let types = [
+      UNNUMBERED_MESSAGE,
+      TRANSPORT,
+      CUSTOM,
+      RECEIPT_REQUEST,
+      RECEIPT,
+      NUMBERED_MESSAGE,
+      ANNOUNCE,
+      ONION,
+      MAIL,
+      GROUP_MESSAGE,
+      GROUP_KEY,
+      ENDORSEMENT,
+      REVOCATION,
+      FAILURE_REQUEST,
+      FAILURE_NOTICE,
+      HOLD_UNTIL,
+      EXPIRY,
+      SEQUENCED_MESSAGE,
+      SENDER_CLAIM,
+      REPLY_HEADER,
+    ];
+    for (i, kind) in types.iter().enumerate() {
+      assert!(!types[i + 1..].contains(kind), "type {} is used twice", kind);
+    }
+  }
+
+  #[test]
+  fn short_legacy_message_chunk_rejected() {
+    assert!(Chunk::deserialize(vec![0], &[]).is_err());
+    assert!(Chunk::deserialize(vec![0, 3, 1], &[]).is_err());
+  }
+
+  #[test]
+  fn version_1_still_read() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut packet = Packet::unsigned();
+    packet.add_message(&[1], &pk);
+    let mut v1 = vec![1];
+    bincode::serialize_into(&mut v1, &vec![packet.main_path]).expect("Failed to serialize");
+
+    let dec = Packet::deserialize(&v1, &[sk], &[], &Sodium).expect("Failed to deserialize");
+    assert_eq!(vec![vec![1]], contents(&dec));
+  }
+
+  #[test]
+  fn debug_decode_describes_openable_chunks() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let (other_pk, _) = encrypt::gen_keypair();
+    let (_, ssk) = sign::gen_keypair();
+    let mut packet = Packet::signed(ssk);
+    packet.add_hop("inmem:dump".to_owned(), &pk);
+    packet.add_message(&[1, 2, 3], &other_pk);
+    packet.add_message(&[4, 5], &pk);
+    let bytes = packet.into_bytes().expect("Failed to serialize");
+
+    let dump = Packet::debug_decode(&bytes, &[sk]).expect("Failed to decode");
+    assert_eq!(3, dump.chunks.len());
+    assert!(dump.chunks.iter().filter(|c| c.contents.is_some()).all(|c| c.signed));
+    let mut contents: Vec<_> = dump.chunks.iter().filter_map(|c| c.contents.clone()).collect();
+    contents.sort();
+    assert_eq!(2, contents.len());
+    assert_eq!("a hop to inmem:dump", contents[0]);
+    assert!(contents[1].starts_with("a message of 2 bytes"));
+
+    let blind = Packet::debug_decode(&bytes, &[]).expect("Failed to decode");
+    assert!(blind.chunks.iter().all(|c| c.contents.is_none()));
+    assert!(Packet::debug_decode(&[], &[]).is_err());
+  }
+
+  #[test]
+  fn message_ids_carried() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut packet = Packet::unsigned();
+    let generated = packet.add_message(&[1], &pk);
+    packet.add_message_with_id(&[2], &pk, MessageId(1234));
+    let packet = packet.serialize().expect("Failed to serialize packet");
+
+    let mut ids: Vec<_> = Packet::deserialize(&packet, &[sk], &[], &Sodium)
+      .expect("Failed to deserialize")
+      .into_iter()
+      .filter_map(|c| match c {
+        Chunk::Message(data, _, id, _) => Some((data, id)),
+        _ => None,
+      })
+      .collect();
+    ids.sort_by_key(|(data, _)| data.clone());
+    assert_eq!(vec![(vec![1], Some(generated)), (vec![2], Some(MessageId(1234)))], ids);
+  }
+
+  #[test]
+  fn messages_kept_in_order() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let (other_pk, _) = encrypt::gen_keypair();
+    let mut packet = Packet::unsigned();
+    for i in 0..50 {
+      packet.add_hop(format!("inmem:{}", i), &pk);
+      packet.add_message(&[i], &pk);
+      packet.add_message(&[i], &other_pk);
+    }
+    let packet = packet.serialize().expect("Failed to serialize packet");
+    let chunks = Packet::deserialize(&packet, &[sk], &[], &Sodium).expect("Failed to deserialize");
+    assert_eq!((0..50).map(|i| vec![i]).collect::<Vec<_>>(), contents(&chunks));
+  }
+
+  #[test]
+  fn sender_claims_bound_to_message() {
+    let (pk, _) = encrypt::gen_keypair();
+    let (other_pk, _) = encrypt::gen_keypair();
+    let (signer_pk, signer_sk) = sign::gen_keypair();
+    let id = MessageId(1);
+    let claim = SenderClaim::new(1, &pk, id, &[1, 2, 3], &signer_sk, &Sodium);
+    let claim = SenderClaim::decode(&claim.encode()).expect("Failed to decode claim");
+    assert_eq!(Some(signer_pk), claim.verify(&pk, id, &[1, 2, 3], &Sodium));
+    assert_eq!(None, claim.verify(&other_pk, id, &[1, 2, 3], &Sodium));
+    assert_eq!(None, claim.verify(&pk, MessageId(2), &[1, 2, 3], &Sodium));
+    assert_eq!(None, claim.verify(&pk, id, &[1, 2, 4], &Sodium));
+  }
+
+  /// What a round-tripped chunk should come out as: its type, its contents, and how long its reply path is, if it has
+  /// one.
+  type Expected = (u8, Vec<u8>, Option<usize>);
+
+  fn expected(chunks: Vec<Chunk>) -> Vec<Expected> {
+    let mut expected: Vec<_> = chunks
+      .into_iter()
+      .map(|c| match c {
+        Chunk::Transport(path) => (TRANSPORT, path.into_bytes(), None),
+        Chunk::Message(data, reply, _, _) => (SEQUENCED_MESSAGE, data, reply.map(|r| r.len())),
+        other => panic!("Unexpected chunk {:?}", other),
+      })
+      .collect();
+    expected.sort();
+    expected
+  }
+
+  /// One thing to add to a random packet, for one of its keys, picked by index.
+  #[derive(Debug, Clone)]
+  enum Add {
+    Hop(prop::sample::Index, String),
+    Message(prop::sample::Index, Vec<u8>),
+    /// A message with a reply path through the keys at each of the other indexes.
+    Reply(prop::sample::Index, Vec<u8>, Vec<prop::sample::Index>),
+  }
+
+  fn add() -> impl Strategy<Value = Add> {
+    let data = || prop::collection::vec(any::<u8>(), 0..300);
+    prop_oneof![
+      (
+        any::<prop::sample::Index>(),
+        prop::collection::vec(any::<char>(), 0..40)
+      )
+        .prop_map(|(to, path)| Add::Hop(to, path.into_iter().collect())),
+      (any::<prop::sample::Index>(), data()).prop_map(|(to, data)| Add::Message(to, data)),
+      (
+        any::<prop::sample::Index>(),
+        data(),
+        prop::collection::vec(any::<prop::sample::Index>(), 1..4)
+      )
+        .prop_map(|(to, data, hops)| Add::Reply(to, data, hops)),
+    ]
+  }
+
+  /// Distinct encryption keypairs, made from the seeds proptest picks, so failures can be reproduced.
+  fn keys(count: std::ops::Range<usize>) -> impl Strategy<Value = Vec<(encrypt::PublicKey, encrypt::SecretKey)>> {
+    prop::collection::hash_set(any::<u64>(), count).prop_map(|seeds| {
+      seeds
+        .into_iter()
+        .map(|seed| {
+          // clear of the bits X25519 clamps, so different seeds always make different keys
+          let mut bytes = [0; 32];
+          bytes[8..16].copy_from_slice(&seed.to_be_bytes());
+          let sk = encrypt::SecretKey::from_slice(&bytes).unwrap();
+          (sk.public_key(), sk)
+        })
+        .collect()
+    })
+  }
+
+  /// A signing keypair made from a seed.
+  fn sign_keys(seed: [u8; 32]) -> (sign::PublicKey, sign::SecretKey) {
+    let (pk, sk) = sodiumoxide::crypto::sign::keypair_from_seed(&sodiumoxide::crypto::sign::Seed(seed));
+    (
+      sign::PublicKey::from_slice(&pk.0).unwrap(),
+      sign::SecretKey::from_slice(&sk.0).unwrap(),
+    )
+  }
+
+  /// Builds a packet out of `adds`, for keys out of `pkeys`, returning it along with what each key should be able to
+  /// read.
+  fn build_packet(
+    adds: &[Add],
+    pkeys: &[encrypt::PublicKey],
+    signer: Option<&sign::SecretKey>,
+    key_hints: bool,
+  ) -> (Packet, Vec<Vec<Expected>>) {
+    let mut packet = match signer {
+      Some(skey) => Packet::signed(skey.clone_secret()),
+      None => Packet::unsigned(),
+    };
+    packet.use_key_hints(key_hints);
+    let mut expected = vec![vec![]; pkeys.len()];
+    for add in adds {
+      match add {
+        Add::Hop(to, path) => {
+          packet.add_hop(path.clone(), to.get(pkeys));
+          expected[to.index(pkeys.len())].push((TRANSPORT, path.clone().into_bytes(), None));
+        }
+        Add::Message(to, data) => {
+          packet.add_message(data, to.get(pkeys));
+          expected[to.index(pkeys.len())].push((SEQUENCED_MESSAGE, data.clone(), None));
+        }
+        Add::Reply(to, data, hops) => {
+          let mut reply = match packet.add_reply_path() {
+            Some(reply) => reply,
+            None => continue,
+          };
+          for hop in hops {
+            reply.add_hop("tcp:[::1]:18540".to_owned(), hop.get(pkeys));
+          }
+          reply.use_for_message(data, to.get(pkeys));
+          expected[to.index(pkeys.len())].push((SEQUENCED_MESSAGE, data.clone(), Some(hops.len())));
+        }
+      }
+    }
+    for e in &mut expected {
+      e.sort();
+    }
+    (packet, expected)
+  }
+
+  proptest! {
+    // each case builds and opens a whole packet, for several keys
+    #![proptest_config(proptest::test_runner::Config::with_cases(64))]
+
+    #[test]
+    fn random_packets_round_trip(
+      keys in keys(2..5),
+      adds in prop::collection::vec(add(), 1..12),
+      key_hints in any::<bool>(),
+    ) {
+      // the first key isn't sent anything, to check nothing can be read with the wrong key
+      let (wrong, keys) = keys.split_first().unwrap();
+      let pkeys: Vec<_> = keys.iter().map(|(pk, _)| *pk).collect();
+      let (packet, expected) = build_packet(&adds, &pkeys, None, key_hints);
+      let (chunks, reply_lens) = (packet.chunk_count(), packet.reply_path_lens());
+      let bytes = packet.into_bytes().expect("Failed to serialize");
+
+      let parsed = Packet::parse_untrusted(&bytes).expect("Failed to parse");
+      prop_assert_eq!(chunks, parsed.chunks.len());
+      prop_assert_eq!(reply_lens, parsed.reply_paths.iter().map(Vec::len).collect::<Vec<_>>());
+      for ((_, sk), expected) in keys.iter().zip(expected) {
+        let opened =
+          Packet::deserialize(&bytes, std::slice::from_ref(sk), &[], &Sodium).expect("Failed to deserialize");
+        prop_assert_eq!(expected, self::expected(opened));
+      }
+      prop_assert!(Packet::deserialize(&bytes, std::slice::from_ref(&wrong.1), &[], &Sodium)
+        .expect("Failed to deserialize")
+        .is_empty());
+    }
+
+    #[test]
+    fn random_signed_packets_round_trip(
+      keys in keys(1..4),
+      adds in prop::collection::vec(add(), 1..12),
+      key_hints in any::<bool>(),
+      signer in any::<[u8; 32]>(),
+      wrong_signer in any::<[u8; 32]>(),
+    ) {
+      prop_assume!(signer != wrong_signer);
+      let (spk, ssk) = sign_keys(signer);
+      let (wrong_spk, _) = sign_keys(wrong_signer);
+      let pkeys: Vec<_> = keys.iter().map(|(pk, _)| *pk).collect();
+      let (packet, expected) = build_packet(&adds, &pkeys, Some(&ssk), key_hints);
+      let bytes = packet.into_bytes().expect("Failed to serialize");
+
+      for ((_, sk), expected) in keys.iter().zip(expected) {
+        let sk = std::slice::from_ref(sk);
+        let mut failures = vec![];
+        let opened = Packet::deserialize_signed(&bytes, &OwnKeys::new(sk, &[]), &[spk], &[], &Sodium, &mut failures)
+          .expect("Failed to deserialize");
+        prop_assert!(failures.is_empty());
+        prop_assert_eq!(&expected, &self::expected(opened));
+
+        // with the wrong sender key, every chunk this key can open is tampered with, as far as it can tell
+        let opened = Packet::deserialize_signed(
+          &bytes,
+          &OwnKeys::new(sk, &[]),
+          &[wrong_spk],
+          &[],
+          &Sodium,
+          &mut failures,
+        )
+        .expect("Failed to deserialize");
+        prop_assert!(opened.is_empty());
+        prop_assert_eq!(expected.len(), failures.len());
+      }
+    }
+  }
 }
@@ -0,0 +1,268 @@
+//! Contains the parsed representation of the paths packets are sent along.
+
+use crate::{alloc_prelude::*, prelude::*};
+
+use core::{fmt, str::FromStr, time::Duration};
+
+/// The options attached to a path through its query string, e.g. `retries=3&timeout=2000` in `tcp:[::1]:18540?retries=3&timeout=2000`.
+///
+/// Each `&`-separated piece is one option, split into key and value at the first `=`.
+/// An option without an `=`, like `?nodelay`, has the empty string as its value.
+/// If an option is given more than once, the last one wins.
+///
+/// Mesher itself doesn't interpret any options; what they mean is entirely up to the transport handling the path.
+/// Transports should ignore options they don't recognize.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PathOptions {
+  pairs: Vec<(String, String)>,
+}
+
+impl PathOptions {
+  fn parse(query: &str) -> PathOptions {
+    let pairs = query
+      .split('&')
+      .filter(|p| !p.is_empty())
+      .map(|p| match p.find('=') {
+        Some(eq) => (p[..eq].to_owned(), p[eq + 1..].to_owned()),
+        None => (p.to_owned(), String::new()),
+      })
+      .collect();
+    PathOptions { pairs }
+  }
+
+  /// Gets the raw value of an option, if it was given.
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.pairs.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+  }
+
+  /// Whether the option was given at all, with or without a value.
+  pub fn has(&self, key: &str) -> bool {
+    self.get(key).is_some()
+  }
+
+  /// Parses the value of an option into some type.
+  ///
+  /// Returns `Ok(None)` if the option wasn't given, and [`MesherFail::InvalidURL`](fail/enum.MesherFail.html#variant.InvalidURL) if it was but couldn't be parsed.
+  pub fn parse_value<T: FromStr>(&self, key: &str) -> fail::Result<Option<T>> {
+    match self.get(key) {
+      None => Ok(None),
+      Some(v) => v
+        .parse()
+        .map(Some)
+        .map_err(|_| fail::MesherFail::InvalidURL(format!("invalid value for option {}: {}", key, v))),
+    }
+  }
+
+  /// Parses the value of an option as a duration.
+  ///
+  /// Durations are a whole number followed by a unit, one of `ms`, `s`, or `m`.
+  /// If there's no unit, the number is taken to be milliseconds, so `timeout=2000` and `timeout=2s` mean the same thing.
+  pub fn duration(&self, key: &str) -> fail::Result<Option<Duration>> {
+    let v = match self.get(key) {
+      None => return Ok(None),
+      Some(v) => v,
+    };
+    let split = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let (num, unit) = v.split_at(split);
+    let invalid = || fail::MesherFail::InvalidURL(format!("invalid duration for option {}: {}", key, v));
+    let num: u64 = num.parse().map_err(|_| invalid())?;
+    match unit {
+      "" | "ms" => Ok(Some(Duration::from_millis(num))),
+      "s" => Ok(Some(Duration::from_secs(num))),
+      "m" => Ok(Some(Duration::from_secs(num.checked_mul(60).ok_or_else(invalid)?))),
+      _ => Err(fail::MesherFail::InvalidURL(format!(
+        "invalid duration unit for option {}: {}",
+        key, v
+      ))),
+    }
+  }
+
+  /// Iterates over every option, in the order they were given.
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+    self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+  }
+
+  /// Whether there are no options at all.
+  pub fn is_empty(&self) -> bool {
+    self.pairs.is_empty()
+  }
+}
+
+/// A parsed transport path, like `tcp:[::1]:18540` or `inmem:foo?bar=baz`.
+///
+/// Paths are made up of three parts:
+///
+/// - The scheme, everything before the first `:`, which determines which transport handles the path.
+/// - The location, everything after that colon and before the first `?`, which the transport interprets however it likes.
+/// - The query, everything after the first `?`, if there is one, which is parsed into [`PathOptions`](struct.PathOptions.html).
+///
+/// Transports are handed already-parsed paths, so they don't have to do their own string splitting.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Path {
+  full: String,
+  colon: usize,
+  question: Option<usize>,
+  options: PathOptions,
+}
+
+impl Path {
+  /// Parses a path out of a string.
+  ///
+  /// Fails with [`MesherFail::InvalidURL`](fail/enum.MesherFail.html#variant.InvalidURL) if there's no scheme.
+  pub fn parse(from: &str) -> fail::Result<Path> {
+    let colon = from
+      .find(':')
+      .ok_or_else(|| fail::MesherFail::InvalidURL("no colon-delimited scheme segment".to_string()))?;
+    if colon == 0 {
+      return Err(fail::MesherFail::InvalidURL("empty scheme".to_string()));
+    }
+    let question = from[colon..].find('?').map(|i| i + colon);
+    let options = match question {
+      Some(q) => PathOptions::parse(&from[q + 1..]),
+      None => PathOptions::default(),
+    };
+    Ok(Path {
+      full: from.to_owned(),
+      colon,
+      question,
+      options,
+    })
+  }
+
+  /// The scheme, used to pick the transport.
+  pub fn scheme(&self) -> &str {
+    &self.full[..self.colon]
+  }
+
+  /// The transport-specific part of the path, e.g. `[::1]:18540` in `tcp:[::1]:18540?timeout=100`.
+  pub fn location(&self) -> &str {
+    let end = self.question.unwrap_or(self.full.len());
+    &self.full[self.colon + 1..end]
+  }
+
+  /// The query string, without the leading `?`, if there is one.
+  pub fn query(&self) -> Option<&str> {
+    self.question.map(|q| &self.full[q + 1..])
+  }
+
+  /// The options given in the query string.
+  pub fn options(&self) -> &PathOptions {
+    &self.options
+  }
+
+  /// The whole path, exactly as it was parsed.
+  pub fn as_str(&self) -> &str {
+    &self.full
+  }
+}
+
+impl FromStr for Path {
+  type Err = fail::MesherFail;
+
+  fn from_str(s: &str) -> fail::Result<Path> {
+    Path::parse(s)
+  }
+}
+
+impl fmt::Display for Path {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.full)
+  }
+}
+
+impl AsRef<str> for Path {
+  fn as_ref(&self) -> &str {
+    &self.full
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn splits_pieces() {
+    let path = Path::parse("tcp:[::1]:18540?timeout=5s").expect("Failed to parse");
+    assert_eq!("tcp", path.scheme());
+    assert_eq!("[::1]:18540", path.location());
+    assert_eq!(Some("timeout=5s"), path.query());
+    assert_eq!("tcp:[::1]:18540?timeout=5s", path.as_str());
+  }
+
+  #[test]
+  fn no_query() {
+    let path = Path::parse("inmem:foo").expect("Failed to parse");
+    assert_eq!("inmem", path.scheme());
+    assert_eq!("foo", path.location());
+    assert_eq!(None, path.query());
+  }
+
+  #[test]
+  fn question_in_scheme_is_not_query() {
+    let path = Path::parse("we?ird:foo").expect("Failed to parse");
+    assert_eq!("we?ird", path.scheme());
+    assert_eq!(None, path.query());
+  }
+
+  #[test]
+  fn options_parsed() {
+    let path = Path::parse("tcp:[::1]:18540?retries=3&timeout=2000&nodelay&retries=4").expect("Failed to parse");
+    let opts = path.options();
+    assert_eq!(Some("4"), opts.get("retries"));
+    assert_eq!(
+      Some(4),
+      opts.parse_value::<u32>("retries").expect("Failed to parse retries")
+    );
+    assert_eq!(
+      Some(Duration::from_millis(2000)),
+      opts.duration("timeout").expect("Failed to parse timeout")
+    );
+    assert!(opts.has("nodelay"));
+    assert!(!opts.has("missing"));
+    assert_eq!(
+      None,
+      opts.parse_value::<u32>("missing").expect("Missing option errored")
+    );
+  }
+
+  #[test]
+  fn durations_with_units() {
+    let path = Path::parse("x:y?a=10ms&b=3s&c=2m&d=5h&e=s").expect("Failed to parse");
+    let opts = path.options();
+    assert_eq!(Some(Duration::from_millis(10)), opts.duration("a").unwrap());
+    assert_eq!(Some(Duration::from_secs(3)), opts.duration("b").unwrap());
+    assert_eq!(Some(Duration::from_secs(120)), opts.duration("c").unwrap());
+    assert!(opts.duration("d").is_err());
+    assert!(opts.duration("e").is_err());
+  }
+
+  #[test]
+  fn huge_durations_fail() {
+    let path = Path::parse("x:y?timeout=999999999999999999m").expect("Failed to parse");
+    match path.options().duration("timeout") {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("expected InvalidURL, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn bad_value_fails() {
+    let path = Path::parse("x:y?retries=lots").expect("Failed to parse");
+    match path.options().parse_value::<u32>("retries") {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("expected InvalidURL, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn missing_scheme_fails() {
+    match Path::parse("foo") {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("expected InvalidURL, got {:?}", other),
+    }
+    match Path::parse(":foo") {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("expected InvalidURL, got {:?}", other),
+    }
+  }
+}
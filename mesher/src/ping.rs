@@ -0,0 +1,196 @@
+//! Contains probes for checking whether a route works, and how long the round trip along it takes.
+//!
+//! A [`Probe`](struct.Probe.html) is an empty packet sent along a [`Route`](../struct.Route.html) which asks for a
+//! [delivery receipt](../struct.ReplyPathHandle.html#method.use_for_receipt) to be sent back along the same route.
+//! [Pinging](struct.Probe.html#method.ping) asks only the last node for one, to tell whether the whole route works;
+//! [tracing](struct.Probe.html#method.trace) asks every node after the first, so when a route's broken, the hops that
+//! answered show how far the packet got.
+//! Nodes don't need to do anything special to answer, since every mesher sends the receipts it's asked for.
+//! Every relay is also asked for a [failure notice](../struct.ReplyPathHandle.html#method.use_for_failure_notice) if it
+//! can't pass the probe on, so a broken route is noticed as soon as the relay before the break finds out, rather than
+//! only once the probe times out.
+//!
+//! The route has to start with the mesher sending the probe, at a path it's listening on, since that's where the
+//! receipts come back to.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::ping::Probe;
+//! use std::time::Duration;
+//!
+//! let (own_pk, own_sk) = encrypt::gen_keypair();
+//! let (far_pk, far_sk) = encrypt::gen_keypair();
+//! let mut own = Mesher::unsigned(vec![own_sk]);
+//! own.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! own.listen_on("inmem:ping-doc-own").expect("Failed to listen");
+//! let mut far = Mesher::unsigned(vec![far_sk]);
+//! far.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! far.listen_on("inmem:ping-doc-far").expect("Failed to listen");
+//!
+//! let route = mesher::Route::new()
+//!   .then(Path::parse("inmem:ping-doc-own").unwrap(), own_pk)
+//!   .then(Path::parse("inmem:ping-doc-far").unwrap(), far_pk);
+//! let mut probe = Probe::ping(&mut own, &route).expect("Failed to send probe");
+//! far.receive().expect("Failed to receive");
+//! probe.wait(&mut own, Duration::from_secs(5)).expect("Failed to receive");
+//! assert!(probe.round_trip().is_some());
+//! ```
+
+use crate::{prelude::*, DeliveryStatus, ReceiptId, Route};
+
+use std::{
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+/// How long [`Probe::wait`](struct.Probe.html#method.wait) waits between receives.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// One of the nodes a [`Probe`](struct.Probe.html) asked to answer.
+#[derive(Debug, Clone)]
+pub struct Hop {
+  path: Path,
+  receipt: ReceiptId,
+  round_trip: Option<Duration>,
+  failed: bool,
+}
+
+impl Hop {
+  /// The path the node was sent the probe along.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// How long it took for the node's answer to come back, as of the last [check](struct.Probe.html#method.check), or
+  /// `None` if it hasn't yet.
+  pub fn round_trip(&self) -> Option<Duration> {
+    self.round_trip
+  }
+
+  /// Whether a relay before the node said it couldn't pass the probe on, as of the last
+  /// [check](struct.Probe.html#method.check), in which case the node will never answer.
+  pub fn failed(&self) -> bool {
+    self.failed
+  }
+}
+
+/// A probe sent along a route, waiting to hear back from the nodes on it.
+///
+/// Answers are only noticed once the mesher's [received](../struct.Mesher.html#method.receive) them and the probe's
+/// been [checked](#method.check), so round trips are only as precise as how often that happens; to do both in a loop
+/// until every node's answered, use [`wait`](#method.wait).
+#[derive(Debug, Clone)]
+pub struct Probe {
+  sent: Instant,
+  hops: Vec<Hop>,
+}
+
+impl Probe {
+  /// Sends a probe along the route, which the last node answers.
+  ///
+  /// Fails with [`MesherFail::NoRoute`](../fail/enum.MesherFail.html#variant.NoRoute) if the route has fewer than two
+  /// nodes, and otherwise like [`Mesher::launch`](../struct.Mesher.html#method.launch).
+  pub fn ping(mesher: &mut Mesher, route: &Route) -> fail::Result<Probe> {
+    Probe::send(mesher, route, route.len().saturating_sub(1)..route.len())
+  }
+
+  /// Sends a probe along the route, which every node after the first answers.
+  ///
+  /// Fails like [`ping`](#method.ping), or if the route's too long for the packet to hold two ways back from every node,
+  /// i.e. more than 128 nodes.
+  pub fn trace(mesher: &mut Mesher, route: &Route) -> fail::Result<Probe> {
+    Probe::send(mesher, route, 1..route.len())
+  }
+
+  /// Sends a probe along the route, asking the nodes with the given indices to answer.
+  fn send(mesher: &mut Mesher, route: &Route, answering: core::ops::Range<usize>) -> fail::Result<Probe> {
+    if route.len() < 2 {
+      return Err(fail::MesherFail::NoRoute(
+        "a probe's route needs at least the sender and one other node".to_owned(),
+      ));
+    }
+    let nodes: Vec<_> = route.nodes().collect();
+    let own_pkey = *nodes[0].1;
+    let mut packet = mesher.new_packet(None);
+    packet.via_route(route);
+    // each node answers back along the part of the route that reached it
+    let back = |i: usize| {
+      nodes[..=i]
+        .iter()
+        .rev()
+        .fold(Route::new(), |back, (path, pkey)| back.then((*path).clone(), **pkey))
+    };
+    let too_long = || fail::MesherFail::NoRoute("too many nodes to probe in one packet".to_owned());
+    let mut hops = Vec::with_capacity(answering.len());
+    for i in answering {
+      let mut reply = packet.add_reply_path().ok_or_else(too_long)?;
+      reply.via_route(&back(i));
+      hops.push(Hop {
+        path: nodes[i].0.clone(),
+        receipt: reply.use_for_receipt(nodes[i].1, &own_pkey),
+        round_trip: None,
+        failed: false,
+      });
+    }
+    // each relay says if it can't reach the next node that should answer
+    let first_answering = nodes.len() - hops.len();
+    for relay in 1..nodes.len() - 1 {
+      let next = hops[(relay + 1).max(first_answering) - first_answering].receipt;
+      let mut reply = packet.add_reply_path().ok_or_else(too_long)?;
+      reply.via_route(&back(relay));
+      reply.use_for_failure_notice(nodes[relay].1, &own_pkey, next);
+    }
+    let sent = Instant::now();
+    mesher.launch(packet)?;
+    Ok(Probe { sent, hops })
+  }
+
+  /// Notes the answers and failure notices that have come back to the mesher since the last check, returning whether
+  /// there's nothing left to wait for, i.e. every node's either answered or is past a relay that couldn't reach it.
+  pub fn check(&mut self, mesher: &mut Mesher) -> bool {
+    let now = Instant::now();
+    for hop in self.hops.iter_mut().filter(|h| h.round_trip.is_none() && !h.failed) {
+      match mesher.delivery_status(hop.receipt) {
+        DeliveryStatus::Delivered => hop.round_trip = Some(now - self.sent),
+        DeliveryStatus::Failed => hop.failed = true,
+        DeliveryStatus::Pending => continue,
+      }
+      mesher.forget_receipt(hop.receipt);
+    }
+    for hop in &self.hops {
+      if hop.failed {
+        return true;
+      }
+      if hop.round_trip.is_none() {
+        return false;
+      }
+    }
+    true
+  }
+
+  /// Keeps [receiving](../struct.Mesher.html#method.receive) and [checking](#method.check) until there's nothing left to
+  /// wait for, or `timeout` has passed since the probe was sent.
+  ///
+  /// Returns the messages received in the meantime, so they aren't lost.
+  pub fn wait(&mut self, mesher: &mut Mesher, timeout: Duration) -> fail::Result<Vec<Message>> {
+    let mut received = vec![];
+    loop {
+      received.extend(mesher.receive()?);
+      if self.check(mesher) || self.sent.elapsed() >= timeout {
+        return Ok(received);
+      }
+      sleep(POLL_INTERVAL);
+    }
+  }
+
+  /// The nodes asked to answer, in the order they are along the route.
+  pub fn hops(&self) -> &[Hop] {
+    &self.hops
+  }
+
+  /// How long it took for the last node's answer to come back, i.e. the round trip along the whole route, or `None` if
+  /// it hasn't yet.
+  pub fn round_trip(&self) -> Option<Duration> {
+    self.hops.last().and_then(Hop::round_trip)
+  }
+}
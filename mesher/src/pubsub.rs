@@ -0,0 +1,331 @@
+//! Contains a topic-based publish/subscribe layer, where nodes subscribe to topics and messages published to a topic
+//! reach every node subscribed to it.
+//!
+//! Each mesher using it has a [`PubSub`](struct.PubSub.html), which keeps a table of who's subscribed to what.
+//! Nodes tell their [peers](../struct.Mesher.html#method.add_peer) about their own subscriptions, and the ones they've
+//! heard about, every time they [gossip](struct.PubSub.html#method.gossip), so tables fill up across the mesh.
+//! [Publishing](struct.PubSub.html#method.publish) sends a copy to every subscriber in the table, either directly or,
+//! if the publisher has no transport for the subscriber's path, through the peer that told it about the subscriber.
+//! Nodes only pass along subscribers they can reach directly themselves, so that always works.
+//!
+//! Subscriptions and publications travel as [custom chunks](../trait.CustomChunk.html) of kinds `0xFF00` and
+//! `0xFF01`, which the `PubSub` handles itself, so they never show up as messages.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::pubsub::PubSub;
+//!
+//! let (pub_pk, pub_sk) = encrypt::gen_keypair();
+//! let (_, sub_sk) = encrypt::gen_keypair();
+//! let mut publisher = Mesher::unsigned(vec![pub_sk]);
+//! publisher.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! publisher.listen_on("inmem:pubsub-doc-pub").expect("Failed to listen");
+//! let mut subscriber = Mesher::unsigned(vec![sub_sk]);
+//! subscriber.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! subscriber.listen_on("inmem:pubsub-doc-sub").expect("Failed to listen");
+//! subscriber.add_peer(pub_pk, vec![Path::parse("inmem:pubsub-doc-pub").unwrap()]);
+//!
+//! let pub_side = PubSub::new(&mut publisher, Path::parse("inmem:pubsub-doc-pub").unwrap());
+//! let sub_side = PubSub::new(&mut subscriber, Path::parse("inmem:pubsub-doc-sub").unwrap());
+//! sub_side.subscribe("weather");
+//! sub_side.gossip(&mut subscriber).expect("Failed to gossip");
+//! publisher.receive().expect("Failed to receive");
+//!
+//! assert_eq!(1, pub_side.publish(&mut publisher, "weather", b"rain").expect("Failed to publish"));
+//! subscriber.receive().expect("Failed to receive");
+//! assert_eq!(vec![("weather".to_owned(), b"rain".to_vec())], sub_side.take_published());
+//! ```
+
+use crate::{packet::take, prelude::*, CustomChunk, Priority};
+
+use std::{
+  collections::{HashMap, HashSet},
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+/// The most subscriptions a node keeps track of, besides its own; gossip about new ones past this is ignored.
+const MAX_SUBSCRIPTIONS: usize = 4096;
+/// The most subscriptions passed along in a single gossip chunk.
+const GOSSIP_SUBSCRIPTIONS: usize = 256;
+
+/// A subscription some other node has, and how to reach it.
+struct Subscriber {
+  path: Path,
+  /// The peer this node heard about the subscriber from, if it wasn't the subscriber itself.
+  via: Option<(encrypt::PublicKey, Path)>,
+  last_heard: Instant,
+}
+
+/// Everything shared between a `PubSub` and the chunk handlers it registers on its mesher.
+#[derive(Default)]
+struct State {
+  own: HashSet<String>,
+  table: HashMap<(String, encrypt::PublicKey), Subscriber>,
+  published: Vec<(String, Vec<u8>)>,
+}
+
+/// Gossip about who's subscribed to what, sent to peers.
+struct Subscriptions {
+  /// The node gossiping, and the path it can be reached on.
+  from: (encrypt::PublicKey, Path),
+  /// Each subscription's topic, and the subscriber's key and path.
+  entries: Vec<(String, encrypt::PublicKey, Path)>,
+}
+
+fn put_str(b: &mut Vec<u8>, s: &str) {
+  b.extend_from_slice(&(s.len() as u16).to_be_bytes());
+  b.extend_from_slice(s.as_bytes());
+}
+
+fn take_str<'a>(from: &mut &'a [u8]) -> Option<&'a str> {
+  let len = take(from, 2)?;
+  let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+  std::str::from_utf8(take(from, len)?).ok()
+}
+
+impl CustomChunk for Subscriptions {
+  const KIND: u16 = 0xFF00;
+
+  /// Encodes the gossiper's key and path, then a big-endian `u16` count of entries, then each entry's topic, key, and
+  /// path, with strings prefixed by their big-endian `u16` length.
+  fn encode(&self) -> Vec<u8> {
+    let mut b = self.from.0.as_bytes().to_vec();
+    put_str(&mut b, self.from.1.as_str());
+    b.extend_from_slice(&(self.entries.len() as u16).to_be_bytes());
+    for (topic, pkey, path) in &self.entries {
+      put_str(&mut b, topic);
+      b.extend_from_slice(pkey.as_bytes());
+      put_str(&mut b, path.as_str());
+    }
+    b
+  }
+
+  fn decode(mut from: &[u8]) -> Option<Subscriptions> {
+    let pkey = encrypt::PublicKey::from_slice(take(&mut from, 32)?)?;
+    let path = Path::parse(take_str(&mut from)?).ok()?;
+    let count = take(&mut from, 2)?;
+    let mut entries = vec![];
+    for _ in 0..u16::from_be_bytes([count[0], count[1]]) {
+      let topic = take_str(&mut from)?.to_owned();
+      let pkey = encrypt::PublicKey::from_slice(take(&mut from, 32)?)?;
+      let path = Path::parse(take_str(&mut from)?).ok()?;
+      entries.push((topic, pkey, path));
+    }
+    if !from.is_empty() {
+      return None;
+    }
+    Some(Subscriptions {
+      from: (pkey, path),
+      entries,
+    })
+  }
+}
+
+/// A message published to a topic.
+struct Publication {
+  topic: String,
+  data: Vec<u8>,
+}
+
+impl CustomChunk for Publication {
+  const KIND: u16 = 0xFF01;
+
+  /// Encodes the topic, prefixed by its big-endian `u16` length, then the data.
+  fn encode(&self) -> Vec<u8> {
+    let mut b = Vec::with_capacity(2 + self.topic.len() + self.data.len());
+    put_str(&mut b, &self.topic);
+    b.extend_from_slice(&self.data);
+    b
+  }
+
+  fn decode(mut from: &[u8]) -> Option<Publication> {
+    let topic = take_str(&mut from)?.to_owned();
+    Some(Publication {
+      topic,
+      data: from.to_vec(),
+    })
+  }
+}
+
+/// One mesher's subscriptions, and everything it knows about other nodes' subscriptions.
+///
+/// Creating one registers handlers on the mesher for the chunks it uses, so there should only be one per mesher.
+/// Subscriptions learned through gossip are forgotten once they haven't been heard about in a while, 10 minutes by
+/// default, so nodes should gossip more often than that.
+pub struct PubSub {
+  path: Path,
+  expire_after: Duration,
+  state: Arc<Mutex<State>>,
+}
+
+impl PubSub {
+  /// Sets up publishing and subscribing through `mesher`, which receives publications on `path`.
+  ///
+  /// The path is what other nodes are told to send publications to, so it should be one the mesher's listening on,
+  /// and that they can reach.
+  pub fn new(mesher: &mut Mesher, path: Path) -> PubSub {
+    let state = Arc::new(Mutex::new(State::default()));
+    let gossip_state = state.clone();
+    mesher.on_chunk(move |gossip: Subscriptions| {
+      let mut state = gossip_state.lock().expect("poisoned lock?");
+      let now = Instant::now();
+      let (from_pkey, from_path) = gossip.from;
+      for (topic, pkey, path) in gossip.entries {
+        let key = (topic, pkey);
+        if !state.table.contains_key(&key) && state.table.len() >= MAX_SUBSCRIPTIONS {
+          continue;
+        }
+        let via = if pkey == from_pkey {
+          None
+        } else {
+          Some((from_pkey, from_path.clone()))
+        };
+        state.table.insert(
+          key,
+          Subscriber {
+            path,
+            via,
+            last_heard: now,
+          },
+        );
+      }
+    });
+    let publish_state = state.clone();
+    mesher.on_chunk(move |publication: Publication| {
+      let mut state = publish_state.lock().expect("poisoned lock?");
+      if state.own.contains(&publication.topic) {
+        state.published.push((publication.topic, publication.data));
+      }
+    });
+    PubSub {
+      path,
+      expire_after: Duration::from_secs(600),
+      state,
+    }
+  }
+
+  /// Forgets subscriptions learned through gossip after `after` without hearing about them again.
+  pub fn expire_after(mut self, after: Duration) -> PubSub {
+    self.expire_after = after;
+    self
+  }
+
+  fn state(&self) -> std::sync::MutexGuard<'_, State> {
+    let mut state = self.state.lock().expect("poisoned lock?");
+    let expire_after = self.expire_after;
+    state.table.retain(|_, s| s.last_heard.elapsed() < expire_after);
+    state
+  }
+
+  /// Starts receiving what's published to `topic`.
+  ///
+  /// Other nodes only find out at the next [`gossip`](#method.gossip).
+  pub fn subscribe(&self, topic: &str) {
+    self.state().own.insert(topic.to_owned());
+  }
+
+  /// Stops receiving what's published to `topic`, returning whether this node was subscribed to it.
+  ///
+  /// Other nodes find out when they [stop hearing about it](#method.expire_after); until then, they keep sending
+  /// publications, which are dropped.
+  pub fn unsubscribe(&self, topic: &str) -> bool {
+    self.state().own.remove(topic)
+  }
+
+  /// The keys of every other node this one knows is subscribed to `topic`.
+  pub fn subscribers(&self, topic: &str) -> Vec<encrypt::PublicKey> {
+    self
+      .state()
+      .table
+      .keys()
+      .filter(|(t, _)| t == topic)
+      .map(|(_, pkey)| *pkey)
+      .collect()
+  }
+
+  /// Tells every peer the mesher [knows about](../struct.Mesher.html#method.known_peers), and has a transport for,
+  /// about this node's subscriptions and the ones it's heard about that it can reach directly.
+  ///
+  /// Peers that can't be sent to are skipped, and the first failure is returned once the rest have been tried.
+  pub fn gossip(&self, mesher: &mut Mesher) -> fail::Result<()> {
+    let own_pkey = mesher.newest_pkey().ok_or(fail::MesherFail::NoKeys)?;
+    let schemes = mesher.transports().into_iter().map(str::to_owned).collect::<Vec<_>>();
+    let reachable = |path: &Path| schemes.iter().any(|s| s == path.scheme());
+    let gossip = {
+      let state = self.state();
+      let own = state
+        .own
+        .iter()
+        .map(|topic| (topic.clone(), own_pkey, self.path.clone()));
+      let heard = state
+        .table
+        .iter()
+        .filter(|(_, s)| reachable(&s.path))
+        .map(|((topic, pkey), s)| (topic.clone(), *pkey, s.path.clone()));
+      Subscriptions {
+        from: (own_pkey, self.path.clone()),
+        entries: own.chain(heard).take(GOSSIP_SUBSCRIPTIONS).collect(),
+      }
+    };
+    let targets: Vec<_> = mesher
+      .known_peers()
+      .into_iter()
+      .filter_map(|p| Some((*p.pkey(), p.paths().iter().find(|path| reachable(path))?.clone())))
+      .collect();
+    let mut result = Ok(());
+    for (pkey, path) in targets {
+      let mut packet = mesher.new_packet(None);
+      packet.add_hop(path.to_string(), &own_pkey);
+      packet.add_custom(&gossip, &pkey);
+      if let Err(e) = mesher.launch_with_priority(packet, Priority::Control) {
+        result = result.and(Err(e));
+      }
+    }
+    result
+  }
+
+  /// Sends `data` to every other node this one knows is subscribed to `topic`, returning how many it was sent to.
+  ///
+  /// Subscribers the mesher can't reach directly are sent to through the peer that told it about them.
+  /// Ones that can't be sent to at all are skipped, and the first failure is returned once the rest have been tried.
+  pub fn publish(&self, mesher: &mut Mesher, topic: &str, data: &[u8]) -> fail::Result<usize> {
+    let own_pkey = mesher.newest_pkey().ok_or(fail::MesherFail::NoKeys)?;
+    let schemes = mesher.transports().into_iter().map(str::to_owned).collect::<Vec<_>>();
+    let reachable = |path: &Path| schemes.iter().any(|s| s == path.scheme());
+    let targets: Vec<_> = self
+      .state()
+      .table
+      .iter()
+      .filter(|((t, _), _)| t == topic)
+      .map(|((_, pkey), s)| (*pkey, s.path.clone(), s.via.clone()))
+      .collect();
+    let publication = Publication {
+      topic: topic.to_owned(),
+      data: data.to_vec(),
+    };
+    let mut sent = 0;
+    let mut result = Ok(());
+    for (pkey, path, via) in targets {
+      let mut packet = mesher.new_packet(None);
+      match via {
+        Some((relay_pkey, relay_path)) if !reachable(&path) => {
+          packet.add_hop(relay_path.to_string(), &own_pkey);
+          packet.add_hop(path.to_string(), &relay_pkey);
+        }
+        _ => packet.add_hop(path.to_string(), &own_pkey),
+      }
+      packet.add_custom(&publication, &pkey);
+      match mesher.launch(packet) {
+        Ok(()) => sent += 1,
+        Err(e) => result = result.and(Err(e)),
+      }
+    }
+    result.map(|_| sent)
+  }
+
+  /// Takes everything published to this node's topics that it's received since the last call, with the topics.
+  pub fn take_published(&self) -> Vec<(String, Vec<u8>)> {
+    std::mem::take(&mut self.state().published)
+  }
+}
@@ -0,0 +1,222 @@
+//! Contains QR codes for exchanging keys and routes out of band, e.g. by pointing one device's camera at another's
+//! screen.
+//!
+//! Only available with the `qr` feature.
+//!
+//! What's exchanged is an [`Exchange`](enum.Exchange.html): a public key or a route, written as text with a `mesher:`
+//! prefix so scanners and people can tell what it is.
+//! It can be [drawn as a QR code](enum.Exchange.html#method.to_qr) for the terminal or as a PNG, and whatever text a
+//! scanner reads back out of the code can be [parsed](enum.Exchange.html#impl-FromStr-for-Exchange) again:
+//!
+//! ```
+//! use mesher::{prelude::*, qr::Exchange};
+//!
+//! let (pk, _) = encrypt::gen_keypair();
+//! let shown = Exchange::Key(pk);
+//! let qr = shown.to_qr().expect("Keys always fit");
+//! println!("{}", qr.to_terminal());
+//!
+//! // ... and on the other end, with whatever the scanner read
+//! let scanned = shown.to_string();
+//! assert_eq!(shown, scanned.parse().unwrap());
+//! ```
+//!
+//! The codes are made with the [`qrcode`](https://crates.io/crates/qrcode) crate, at the medium (M) error correction
+//! level, which recovers from about 15% of the code being unreadable, e.g. from glare on a screen, and PNGs are written
+//! with [`image`](https://crates.io/crates/image).
+
+use crate::{alloc_prelude::*, prelude::*, Route};
+
+use core::{fmt, str::FromStr};
+
+use image::{ImageFormat, Luma};
+use qrcode::{
+  render::{unicode::Dense1x2, Pixel, Renderer},
+  Color, EcLevel,
+};
+
+/// What every exchanged key or route starts with.
+const PREFIX: &str = "mesher:";
+
+/// A public key or route, given to another node out of band.
+///
+/// Written as `mesher:key:<hex>` for an encryption key, `mesher:sign-key:<hex>` for a signing key, or `mesher:route:`
+/// followed by the route's [text form](../struct.Route.html).
+/// When parsing, a key or route without the prefix is also accepted, and read as an encryption key or route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exchange {
+  /// A node's encryption key, for sending it packets.
+  Key(encrypt::PublicKey),
+  /// A signing key, e.g. to [trust as a sender](../struct.MesherBuilder.html#method.sender_key).
+  SignKey(sign::PublicKey),
+  /// A route to a node, through any relays in front of it.
+  Route(Route),
+}
+
+impl Exchange {
+  /// Draws the exchange as a QR code, or returns `None` if it's too long to fit in one, which only happens for routes
+  /// with dozens of nodes.
+  pub fn to_qr(&self) -> Option<QrCode> {
+    QrCode::encode(self.to_string().as_bytes())
+  }
+}
+
+impl fmt::Display for Exchange {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Exchange::Key(pkey) => write!(f, "{}key:{}", PREFIX, pkey),
+      Exchange::SignKey(pkey) => write!(f, "{}sign-key:{}", PREFIX, pkey),
+      // no trailing newline, since every byte makes the code denser
+      Exchange::Route(route) => write!(f, "{}route:{}", PREFIX, route.to_string().trim_end()),
+    }
+  }
+}
+
+impl FromStr for Exchange {
+  type Err = fail::MesherFail;
+
+  /// Reads an exchange written by `Display`, ignoring surrounding whitespace, which some scanners add.
+  ///
+  /// Fails with [`MesherFail::InvalidKey`](../fail/enum.MesherFail.html#variant.InvalidKey) if it isn't a key or
+  /// route, or the route's error if it looks like one but can't be parsed.
+  fn from_str(s: &str) -> fail::Result<Exchange> {
+    let text = s.trim();
+    match text.strip_prefix(PREFIX) {
+      Some(rest) => {
+        if let Some(key) = rest.strip_prefix("key:") {
+          key.parse().map(Exchange::Key)
+        } else if let Some(key) = rest.strip_prefix("sign-key:") {
+          key.parse().map(Exchange::SignKey)
+        } else if let Some(route) = rest.strip_prefix("route:") {
+          route.parse().map(Exchange::Route)
+        } else {
+          Err(fail::MesherFail::InvalidKey(s.to_owned()))
+        }
+      }
+      None if text.contains('@') => text.parse().map(Exchange::Route),
+      None => text.parse().map(Exchange::Key),
+    }
+  }
+}
+
+/// How wide the blank border around a code is, in modules, as the QR standard asks for.
+const QUIET_ZONE: u32 = 4;
+
+/// A QR code, as a square grid of dark and light modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+  size: usize,
+  modules: Vec<Color>,
+}
+
+impl QrCode {
+  /// Encodes some bytes in the smallest QR code they fit in, or returns `None` if they're too long for even the
+  /// biggest, i.e. more than 2331 bytes.
+  pub fn encode(data: &[u8]) -> Option<QrCode> {
+    let code = qrcode::QrCode::with_error_correction_level(data, EcLevel::M).ok()?;
+    Some(QrCode {
+      size: code.width(),
+      modules: code.into_colors(),
+    })
+  }
+
+  /// How many modules wide (and tall) the code is, not counting the blank border.
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  /// Whether the module in column `x`, row `y` is dark, counting from the top left.
+  /// Anything outside the code is light, like the border around it.
+  pub fn is_dark(&self, x: usize, y: usize) -> bool {
+    x < self.size && y < self.size && self.modules[y * self.size + x] == Color::Dark
+  }
+
+  fn renderer<P: Pixel>(&self) -> Renderer<'_, P> {
+    Renderer::new(&self.modules, self.size, QUIET_ZONE)
+  }
+
+  /// Draws the code with Unicode block characters, two rows of modules to a line, with the blank border around it.
+  ///
+  /// Light modules are the ones drawn in, so it comes out right on the usual light-on-dark terminal.
+  pub fn to_terminal(&self) -> String {
+    let mut out = self
+      .renderer::<Dense1x2>()
+      .dark_color(Dense1x2::Light)
+      .light_color(Dense1x2::Dark)
+      .build();
+    out.push('\n');
+    out
+  }
+
+  /// Draws the code as a greyscale PNG image, with each module `scale` pixels wide, and the blank border around it.
+  pub fn to_png(&self, scale: usize) -> Vec<u8> {
+    let scale = scale.clamp(1, u32::MAX as usize) as u32;
+    let image = self.renderer::<Luma<u8>>().module_dimensions(scale, scale).build();
+    let mut png = vec![];
+    image
+      .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+      .expect("writing a PNG to memory can't fail");
+    png
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn capacities() {
+    assert!(QrCode::encode(&[0; 2331]).is_some());
+    assert!(QrCode::encode(&[0; 2332]).is_none());
+  }
+
+  #[test]
+  fn finders_in_corners() {
+    let code = QrCode::encode(b"mesher").unwrap();
+    assert_eq!(21, code.size());
+    for &(x, y) in &[(0, 0), (14, 0), (0, 14)] {
+      assert!(code.is_dark(x, y) && code.is_dark(x + 6, y + 6) && code.is_dark(x + 3, y + 3));
+      assert!(!code.is_dark(x + 1, y + 1));
+    }
+    assert!(!code.is_dark(21, 0));
+  }
+
+  #[test]
+  fn terminal_and_png() {
+    let code = QrCode::encode(b"mesher").unwrap();
+    let text = code.to_terminal();
+    assert_eq!(15, text.lines().count());
+    assert!(text.lines().all(|l| l.chars().count() == 29));
+    // the border is light, so it's drawn in
+    assert!(text.lines().next().unwrap().chars().all(|c| c == '█'));
+
+    let png = code.to_png(3);
+    assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+    assert_eq!(&(87u32).to_be_bytes(), &png[16..20]);
+  }
+
+  #[test]
+  fn exchanges_as_text() {
+    let (pk, _) = encrypt::gen_keypair();
+    let (spk, _) = sign::gen_keypair();
+    let route = Route::new()
+      .then(Path::parse("tcp:[::1]:18540").unwrap(), pk)
+      .then(Path::parse("udp:[::1]:18541").unwrap(), pk);
+    for exchange in [
+      Exchange::Key(pk),
+      Exchange::SignKey(spk),
+      Exchange::Route(route.clone()),
+    ] {
+      let text = exchange.to_string();
+      assert!(text.starts_with("mesher:"));
+      assert_eq!(exchange, format!("{}\r\n", text).parse().unwrap());
+      assert!(exchange.to_qr().is_some());
+    }
+    assert_eq!(Exchange::Key(pk), pk.to_string().parse().unwrap());
+    assert_eq!(Exchange::Route(route.clone()), route.to_string().parse().unwrap());
+    assert!(matches!(
+      "mesher:group:abcd".parse::<Exchange>(),
+      Err(fail::MesherFail::InvalidKey(_))
+    ));
+  }
+}
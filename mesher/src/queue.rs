@@ -0,0 +1,273 @@
+//! Contains the disk-backed queue for packets that couldn't be forwarded yet.
+
+use crate::{prelude::*, Priority};
+
+use rand::prelude::*;
+
+use std::{
+  fs,
+  io::ErrorKind,
+  path::{Path as FsPath, PathBuf},
+  time::{Duration, Instant},
+};
+
+/// Where a [`Mesher`](struct.Mesher.html) keeps packets it couldn't send, so they can be retried later, even if the
+/// process restarts in between.
+///
+/// Whenever a transport fails to send a packet the mesher is forwarding, the packet is written to a file in the queue's
+/// directory instead of being dropped, and retried during [`receive`](struct.Mesher.html#method.receive) every
+/// [`retry_every`](#method.retry_every) until it's sent or it's been tried [`max_attempts`](#method.max_attempts) times.
+/// Packets are only reported to the [failure handler](struct.Mesher.html#method.on_failure) once they've been given up on.
+/// Packets due at the same time are retried most [urgent](enum.Priority.html) first.
+///
+/// When a queue is opened, every packet left in its directory is loaded and retried at the next `receive`.
+/// Each queue should have its own directory, and only one mesher should use a directory at a time.
+pub struct OutboundQueue {
+  dir: PathBuf,
+  retry_interval: Duration,
+  max_attempts: u32,
+  entries: Vec<Queued>,
+}
+
+/// A single packet waiting to be retried.
+pub(crate) struct Queued {
+  file: PathBuf,
+  pub(crate) path: String,
+  pub(crate) packet: Vec<u8>,
+  pub(crate) priority: Priority,
+  attempts: u32,
+  next_try: Instant,
+}
+
+fn queue_fail(what: &str, e: std::io::Error) -> fail::MesherFail {
+  fail::MesherFail::QueueFailure(format!("Failed to {}: {:?}", what, e))
+}
+
+/// Encodes a queued packet as a big-endian `u32` with the priority in its top byte and the path length in the rest, the
+/// path, then the packet.
+///
+/// `Normal` is 0, so packets queued before priorities were stored are read back as `Normal`.
+fn encode(path: &str, packet: &[u8], priority: Priority) -> Vec<u8> {
+  let priority: u32 = match priority {
+    Priority::Normal => 0,
+    Priority::Control => 1,
+    Priority::Bulk => 2,
+  };
+  let mut b = Vec::with_capacity(4 + path.len() + packet.len());
+  b.extend_from_slice(&(priority << 24 | path.len() as u32).to_be_bytes());
+  b.extend_from_slice(path.as_bytes());
+  b.extend_from_slice(packet);
+  b
+}
+
+/// Decodes a file written by [`encode`](fn.encode.html), if it's well-formed.
+fn decode(mut from: &[u8]) -> Option<(String, Vec<u8>, Priority)> {
+  let header = crate::packet::take_u32(&mut from)?;
+  let priority = match header >> 24 {
+    0 => Priority::Normal,
+    1 => Priority::Control,
+    2 => Priority::Bulk,
+    _ => return None,
+  };
+  let path = String::from_utf8(crate::packet::take(&mut from, header & 0xFF_FFFF)?.to_vec()).ok()?;
+  Some((path, from.to_vec(), priority))
+}
+
+impl OutboundQueue {
+  /// Opens the queue in the given directory, creating it if it doesn't exist yet, and loads any packets left in it.
+  ///
+  /// By default, packets are retried every 30 seconds, up to 10 times.
+  /// Files in the directory that aren't valid queued packets are ignored.
+  pub fn open(dir: impl AsRef<FsPath>) -> fail::Result<OutboundQueue> {
+    let dir = dir.as_ref().to_owned();
+    fs::create_dir_all(&dir).map_err(|e| queue_fail("create queue directory", e))?;
+    let now = Instant::now();
+    let mut entries = vec![];
+    for file in fs::read_dir(&dir).map_err(|e| queue_fail("read queue directory", e))? {
+      let file = file.map_err(|e| queue_fail("read queue directory", e))?.path();
+      match file.extension().and_then(|e| e.to_str()) {
+        Some("pkt") => (),
+        // left over from a write that was interrupted, so it was never queued
+        Some("tmp") => {
+          let _ = fs::remove_file(&file);
+          continue;
+        }
+        _ => continue,
+      }
+      let bytes = fs::read(&file).map_err(|e| queue_fail("read queued packet", e))?;
+      if let Some((path, packet, priority)) = decode(&bytes) {
+        entries.push(Queued {
+          file,
+          path,
+          packet,
+          priority,
+          attempts: 0,
+          next_try: now,
+        });
+      }
+    }
+    Ok(OutboundQueue {
+      dir,
+      retry_interval: Duration::from_secs(30),
+      max_attempts: 10,
+      entries,
+    })
+  }
+
+  /// Retries packets every `interval`.
+  pub fn retry_every(mut self, interval: Duration) -> OutboundQueue {
+    self.retry_interval = interval;
+    self
+  }
+
+  /// Gives up on packets once they've been retried `attempts` times.
+  pub fn max_attempts(mut self, attempts: u32) -> OutboundQueue {
+    self.max_attempts = attempts;
+    self
+  }
+
+  /// How many packets are waiting to be retried.
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  /// Whether there are no packets waiting to be retried.
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// Writes a packet to disk and queues it to be retried.
+  pub(crate) fn push(&mut self, packet: Vec<u8>, path: String, priority: Priority) -> fail::Result<()> {
+    if path.len() > 0xFF_FFFF {
+      return Err(fail::MesherFail::QueueFailure(format!(
+        "Path too long to queue: {} bytes",
+        path.len()
+      )));
+    }
+    let name = format!("{:016x}", thread_rng().gen::<u64>());
+    let tmp = self.dir.join(format!("{}.tmp", name));
+    let file = self.dir.join(format!("{}.pkt", name));
+    // written under another name first, so a crash partway through doesn't leave a truncated packet behind
+    fs::write(&tmp, encode(&path, &packet, priority)).map_err(|e| queue_fail("write queued packet", e))?;
+    fs::rename(&tmp, &file).map_err(|e| queue_fail("write queued packet", e))?;
+    self.entries.push(Queued {
+      file,
+      path,
+      packet,
+      priority,
+      attempts: 0,
+      next_try: Instant::now() + self.retry_interval,
+    });
+    Ok(())
+  }
+
+  /// Takes out the packets which are due to be retried, most urgent first.
+  pub(crate) fn due(&mut self) -> Vec<Queued> {
+    let now = Instant::now();
+    let (mut due, waiting): (Vec<_>, _) = self.entries.drain(..).partition(|q| q.next_try <= now);
+    self.entries = waiting;
+    due.sort_by_key(|q| q.priority);
+    due
+  }
+
+  /// Forgets a packet that's been sent, deleting its file.
+  pub(crate) fn sent(&mut self, queued: Queued) -> fail::Result<()> {
+    match fs::remove_file(&queued.file) {
+      Err(e) if e.kind() != ErrorKind::NotFound => Err(queue_fail("remove sent packet", e)),
+      _ => Ok(()),
+    }
+  }
+
+  /// Puts back a packet that failed to send again, unless it's out of attempts, in which case it's deleted.
+  ///
+  /// Returns whether it was put back.
+  pub(crate) fn failed(&mut self, mut queued: Queued) -> fail::Result<bool> {
+    queued.attempts += 1;
+    if queued.attempts >= self.max_attempts {
+      self.sent(queued)?;
+      return Ok(false);
+    }
+    queued.next_try = Instant::now() + self.retry_interval;
+    self.entries.push(queued);
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mesher-queue-{}-{:x}", name, thread_rng().gen::<u32>()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+  }
+
+  #[test]
+  fn survives_reopening() {
+    let dir = temp_dir("reopen");
+    let mut queue = OutboundQueue::open(&dir).expect("Failed to open queue");
+    queue
+      .push(vec![1, 2, 3], "inmem:a".to_owned(), Priority::Normal)
+      .expect("Failed to push");
+    queue
+      .push(vec![4], "inmem:b".to_owned(), Priority::Control)
+      .expect("Failed to push");
+    assert!(queue.due().is_empty());
+    drop(queue);
+    fs::write(dir.join("garbage.tmp"), [1]).expect("Failed to write");
+
+    let mut queue = OutboundQueue::open(&dir).expect("Failed to reopen queue");
+    assert_eq!(2, queue.len());
+    let due: Vec<_> = queue
+      .due()
+      .into_iter()
+      .map(|q| (q.path.clone(), q.packet.clone(), q.priority))
+      .collect();
+    assert_eq!(
+      vec![
+        ("inmem:b".to_owned(), vec![4], Priority::Control),
+        ("inmem:a".to_owned(), vec![1, 2, 3], Priority::Normal)
+      ],
+      due
+    );
+    assert!(!dir.join("garbage.tmp").exists());
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+  }
+
+  #[test]
+  fn gives_up_eventually() {
+    let dir = temp_dir("give-up");
+    let mut queue = OutboundQueue::open(&dir)
+      .expect("Failed to open queue")
+      .retry_every(Duration::from_millis(0))
+      .max_attempts(2);
+    queue
+      .push(vec![1], "inmem:a".to_owned(), Priority::Normal)
+      .expect("Failed to push");
+    let first = queue.due().pop().expect("Packet wasn't due");
+    assert!(queue.failed(first).expect("Failed to requeue"));
+    let second = queue.due().pop().expect("Packet wasn't due");
+    assert!(!queue.failed(second).expect("Failed to requeue"));
+    assert!(queue.is_empty());
+    assert_eq!(0, fs::read_dir(&dir).expect("Failed to read dir").count());
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+  }
+
+  #[test]
+  fn reads_files_from_before_priorities() {
+    let dir = temp_dir("old-format");
+    fs::create_dir_all(&dir).expect("Failed to create dir");
+    let mut old = 7u32.to_be_bytes().to_vec();
+    old.extend_from_slice(b"inmem:a");
+    old.extend_from_slice(&[1, 2]);
+    fs::write(dir.join("0000000000000000.pkt"), old).expect("Failed to write");
+    let mut queue = OutboundQueue::open(&dir).expect("Failed to open queue");
+    let queued = queue.due().pop().expect("Packet wasn't loaded");
+    assert_eq!(
+      ("inmem:a", &[1, 2][..], Priority::Normal),
+      (&queued.path[..], &queued.packet[..], queued.priority)
+    );
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+  }
+}
@@ -0,0 +1,128 @@
+//! Contains the byte quotas and usage counters a mesher keeps for each of its transports.
+
+use std::time::{Duration, Instant};
+
+/// How many bytes a [`Mesher`](struct.Mesher.html) may send through one transport in a given period, e.g. to cap a
+/// metered cellular link or a slow radio one.
+///
+/// The period starts with the first packet sent after the quota's set, and starts over once it's passed, rather than
+/// sliding.
+/// Packets which would go over the quota aren't sent, and fail with
+/// [`MesherFail::QuotaExceeded`](fail/enum.MesherFail.html#variant.QuotaExceeded); forwarded ones go in the
+/// [outbound queue](struct.OutboundQueue.html), if there is one, to be retried later.
+/// Only sent bytes count, since there's no stopping other nodes from sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+  bytes: u64,
+  per: Duration,
+}
+
+impl Quota {
+  /// Allows sending up to `bytes` bytes every `per`.
+  pub fn new(bytes: u64, per: Duration) -> Quota {
+    Quota { bytes, per }
+  }
+}
+
+/// How much a [`Mesher`](struct.Mesher.html) has sent and received through the transport for one scheme, ever since the
+/// mesher was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+  bytes_sent: u64,
+  bytes_received: u64,
+  packets_sent: u64,
+  packets_received: u64,
+}
+
+impl Usage {
+  /// How many bytes have been sent, including forwarded packets.
+  pub fn bytes_sent(&self) -> u64 {
+    self.bytes_sent
+  }
+
+  /// How many bytes have been received, including packets which were dropped.
+  pub fn bytes_received(&self) -> u64 {
+    self.bytes_received
+  }
+
+  /// How many packets have been sent, including forwarded ones.
+  pub fn packets_sent(&self) -> u64 {
+    self.packets_sent
+  }
+
+  /// How many packets have been received, including ones which were dropped.
+  pub fn packets_received(&self) -> u64 {
+    self.packets_received
+  }
+}
+
+/// Keeps track of one transport's usage, and of how much of its quota, if it has one, is left.
+#[derive(Default)]
+pub(crate) struct Meter {
+  pub(crate) usage: Usage,
+  quota: Option<Quota>,
+  /// When the current period started, and how many bytes have been sent in it.
+  period: Option<(Instant, u64)>,
+}
+
+impl Meter {
+  /// Sets a new quota, starting a new period.
+  pub(crate) fn set_quota(&mut self, quota: Option<Quota>) {
+    self.quota = quota;
+    self.period = None;
+  }
+
+  /// How many more bytes can be sent in the current period, or `None` if there's no quota.
+  pub(crate) fn remaining(&self) -> Option<u64> {
+    let quota = self.quota?;
+    let used = match self.period {
+      Some((start, used)) if start.elapsed() < quota.per => used,
+      _ => 0,
+    };
+    Some(quota.bytes.saturating_sub(used))
+  }
+
+  /// Whether `bytes` more bytes can be sent without going over the quota.
+  pub(crate) fn allows(&self, bytes: u64) -> bool {
+    self.remaining().is_none_or(|left| bytes <= left)
+  }
+
+  pub(crate) fn sent(&mut self, bytes: usize) {
+    self.usage.bytes_sent += bytes as u64;
+    self.usage.packets_sent += 1;
+    if let Some(quota) = self.quota {
+      let now = Instant::now();
+      match &mut self.period {
+        Some((start, used)) if now.duration_since(*start) < quota.per => *used += bytes as u64,
+        period => *period = Some((now, bytes as u64)),
+      }
+    }
+  }
+
+  pub(crate) fn received(&mut self, bytes: usize) {
+    self.usage.bytes_received += bytes as u64;
+    self.usage.packets_received += 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn quota_used_up_and_renewed() {
+    let mut meter = Meter::default();
+    assert!(meter.allows(u64::MAX));
+    meter.set_quota(Some(Quota::new(100, Duration::from_millis(20))));
+    assert!(meter.allows(100));
+    meter.sent(60);
+    assert_eq!(Some(40), meter.remaining());
+    assert!(!meter.allows(41));
+    meter.sent(40);
+    assert!(!meter.allows(1));
+    std::thread::sleep(Duration::from_millis(25));
+    assert_eq!(Some(100), meter.remaining());
+    assert_eq!(2, meter.usage.packets_sent());
+    assert_eq!(100, meter.usage.bytes_sent());
+  }
+}
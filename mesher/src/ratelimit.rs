@@ -0,0 +1,145 @@
+//! Contains the token buckets used to limit how many packets a mesher bothers processing.
+
+use crate::Source;
+
+use lru::LruCache;
+use std::{net::SocketAddr, num::NonZeroUsize, time::Instant};
+
+/// How many inbound packets a [`Mesher`](struct.Mesher.html) will process from each sender on each transport.
+///
+/// Each sender gets its own token bucket on each transport, which holds up to `burst` tokens and refills at
+/// `per_second` tokens per second. Every packet received takes a token, and packets that arrive when the bucket is
+/// empty are dropped before the mesher tries to decrypt them, so a flood from one sender can't eat all the CPU time or
+/// crowd out everyone else on the same listener.
+///
+/// Senders are told apart by their [`Source::remote`](struct.Source.html#method.remote), ignoring the port for IP
+/// addresses; packets from transports that don't say who sent them share one bucket per transport.
+/// Only the most recently heard-from senders' buckets are kept, so a sender that's forgotten starts again with a full
+/// one, and since a sender's only as trustworthy as its transport, e.g. spoofed UDP addresses, this is a limit on
+/// honest floods more than on determined attackers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+  per_second: f64,
+  burst: f64,
+}
+
+impl RateLimit {
+  /// Allows `per_second` packets per second on average, with bursts of up to `burst` packets at once.
+  pub fn new(per_second: u32, burst: u32) -> RateLimit {
+    RateLimit {
+      per_second: per_second as f64,
+      burst: burst as f64,
+    }
+  }
+}
+
+/// A single token bucket, following some [`RateLimit`](struct.RateLimit.html).
+pub(crate) struct TokenBucket {
+  tokens: f64,
+  last: Instant,
+}
+
+impl TokenBucket {
+  /// Creates a full bucket.
+  pub(crate) fn new(limit: &RateLimit) -> TokenBucket {
+    TokenBucket {
+      tokens: limit.burst,
+      last: Instant::now(),
+    }
+  }
+
+  /// Takes a token out of the bucket, if there are any left after refilling it.
+  pub(crate) fn take(&mut self, limit: &RateLimit) -> bool {
+    let now = Instant::now();
+    let refill = now.duration_since(self.last).as_secs_f64() * limit.per_second;
+    self.tokens = (self.tokens + refill).min(limit.burst);
+    self.last = now;
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      true
+    } else {
+      false
+    }
+  }
+}
+
+/// The most senders [`Buckets`](struct.Buckets.html) keeps buckets for.
+const MAX_SOURCES: usize = 4096;
+
+/// The token buckets for each sender on each transport, forgetting the least recently used once there are
+/// [`MAX_SOURCES`](constant.MAX_SOURCES.html) of them.
+pub(crate) struct Buckets {
+  buckets: LruCache<(String, Option<String>), TokenBucket>,
+}
+
+impl Buckets {
+  pub(crate) fn new() -> Buckets {
+    Buckets {
+      buckets: LruCache::new(NonZeroUsize::new(MAX_SOURCES).expect("MAX_SOURCES isn't 0")),
+    }
+  }
+
+  /// Takes a token out of the bucket for the packet's sender on the given scheme.
+  pub(crate) fn take(&mut self, scheme: &str, source: &Source, limit: &RateLimit) -> bool {
+    // the port changes with every connection, so IP senders are limited by address
+    let sender = source.remote().map(|r| match r.parse::<SocketAddr>() {
+      Ok(addr) => addr.ip().to_string(),
+      Err(_) => r.to_owned(),
+    });
+    self
+      .buckets
+      .get_or_insert_mut((scheme.to_owned(), sender), || TokenBucket::new(limit))
+      .take(limit)
+  }
+
+  /// Forgets every bucket, so every sender starts again with a full one.
+  pub(crate) fn clear(&mut self) {
+    self.buckets.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bucket_empties_and_refills() {
+    let limit = RateLimit::new(1000, 3);
+    let mut bucket = TokenBucket::new(&limit);
+    assert!(bucket.take(&limit));
+    assert!(bucket.take(&limit));
+    assert!(bucket.take(&limit));
+    assert!(!bucket.take(&limit));
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    assert!(bucket.take(&limit));
+  }
+
+  #[test]
+  fn senders_limited_separately() {
+    let limit = RateLimit::new(1, 2);
+    let mut buckets = Buckets::new();
+    let alice = Source::unknown().from_remote("203.0.113.7:41000");
+    // a new connection from the same address doesn't get a new bucket
+    let alice_again = Source::unknown().from_remote("203.0.113.7:41001");
+    let bob = Source::unknown().from_remote("198.51.100.2:5000");
+    assert!(buckets.take("tcp", &alice, &limit));
+    assert!(buckets.take("tcp", &alice_again, &limit));
+    assert!(!buckets.take("tcp", &alice, &limit));
+    assert!(buckets.take("tcp", &bob, &limit));
+    assert!(buckets.take("udp", &alice, &limit));
+    assert!(buckets.take("tcp", &Source::unknown(), &limit));
+  }
+
+  #[test]
+  fn least_recently_used_forgotten() {
+    let limit = RateLimit::new(1, 1);
+    let mut buckets = Buckets::new();
+    let first = Source::unknown().from_remote("first");
+    assert!(buckets.take("tcp", &first, &limit));
+    assert!(!buckets.take("tcp", &first, &limit));
+    for i in 0..MAX_SOURCES {
+      buckets.take("tcp", &Source::unknown().from_remote(i.to_string()), &limit);
+    }
+    assert!(buckets.take("tcp", &first, &limit));
+  }
+}
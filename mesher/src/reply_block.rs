@@ -0,0 +1,209 @@
+//! Contains single-use reply blocks, which let a node reply along a route it can't see.
+
+use crate::{
+  alloc_prelude::*,
+  packet::{take, take_u32},
+  prelude::*,
+};
+#[cfg(feature = "std")]
+use crate::{
+  packet::{encode_paths, PACKET_VERSION},
+  Route,
+};
+
+/// Identifies a [`ReplyBlock`](struct.ReplyBlock.html), so the mesher that made it can tell which replies came back
+/// through it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReplyBlockId(pub u64);
+
+/// What a node gets when it opens its header of a reply block.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ReplyStep {
+  /// Send the next node's header along the path, with the reply carried behind this one.
+  Forward(String, Vec<u8>),
+  /// The reply has reached the mesher that made the block.
+  Final(ReplyBlockId),
+}
+
+impl ReplyStep {
+  /// Encodes the step as a 0 byte, a `u32` path length, the path, and the next node's header, or a 1 byte and the
+  /// block's ID.
+  #[cfg(feature = "std")]
+  pub(crate) fn encode(&self) -> Vec<u8> {
+    match self {
+      ReplyStep::Forward(path, header) => {
+        let mut b = vec![0];
+        b.extend_from_slice(&(path.len() as u32).to_be_bytes());
+        b.extend_from_slice(path.as_bytes());
+        b.extend_from_slice(header);
+        b
+      }
+      ReplyStep::Final(id) => {
+        let mut b = vec![1];
+        b.extend_from_slice(&id.0.to_be_bytes());
+        b
+      }
+    }
+  }
+
+  /// Decodes a step written by [`encode`](#method.encode), if it's well-formed.
+  pub(crate) fn decode(mut from: &[u8]) -> Option<ReplyStep> {
+    match take(&mut from, 1)?[0] {
+      0 => {
+        let len = take_u32(&mut from)?;
+        let path = String::from_utf8(take(&mut from, len)?.to_vec()).ok()?;
+        Some(ReplyStep::Forward(path, from.to_vec()))
+      }
+      1 if from.len() == 8 => {
+        let mut id = [0; 8];
+        id.copy_from_slice(from);
+        Some(ReplyStep::Final(ReplyBlockId(u64::from_be_bytes(id))))
+      }
+      _ => None,
+    }
+  }
+}
+
+/// A way back to the mesher that made it, which can be used for one reply without learning the route it takes.
+///
+/// A mesher [makes a block](struct.Mesher.html#method.reply_block) along a route of relays ending at itself, and gives
+/// the [bytes](#method.to_bytes) to whoever it wants to hear back from, e.g. in a message.
+/// They [send a reply](struct.Mesher.html#method.send_reply) through it, which only needs the first relay's path: each
+/// relay's header is encrypted for it, and only says where to send the next one, so nobody but the block's maker knows
+/// the whole route, and the replier doesn't learn where it ends.
+/// The reply itself is encrypted with a key made just for the block, so relays can't read it either.
+///
+/// Blocks only work once: the maker forgets the block's key when the first reply through it arrives, and drops any
+/// others.
+/// Like [onions](struct.OnionBuilder.html), replies aren't padded or re-encrypted at each hop, so relays that compare
+/// notes can tell it's the same reply passing through them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyBlock {
+  pub(crate) id: ReplyBlockId,
+  /// The path to the first relay, which the reply is sent along.
+  pub(crate) first_hop: String,
+  /// The first relay's header, already encrypted and, if the maker signs its packets, signed.
+  header: Vec<u8>,
+  /// The key made for the block, which the reply is encrypted for.
+  pub(crate) key: encrypt::PublicKey,
+}
+
+impl ReplyBlock {
+  /// Builds the headers for a block along `route` from the inside out, encrypting each one in a packet from
+  /// `new_packet`, so it's signed like the maker's other packets.
+  #[cfg(feature = "std")]
+  pub(crate) fn build(
+    route: &Route,
+    id: ReplyBlockId,
+    key: encrypt::PublicKey,
+    mut new_packet: impl FnMut() -> Packet,
+  ) -> fail::Result<ReplyBlock> {
+    let nodes: Vec<_> = route.nodes().collect();
+    let (_, last_pkey) = nodes
+      .last()
+      .ok_or_else(|| fail::MesherFail::NoRoute("a reply block's route needs at least one node".to_owned()))?;
+    let mut seal = |step: ReplyStep, pkey: &encrypt::PublicKey| {
+      let mut packet = new_packet();
+      packet.add_reply_step(&step, pkey);
+      packet.main_path.pop().expect("Just added a chunk")
+    };
+    let mut header = seal(ReplyStep::Final(id), last_pkey);
+    for pair in nodes.windows(2).rev() {
+      header = seal(ReplyStep::Forward(pair[1].0.to_string(), header), pair[0].1);
+    }
+    Ok(ReplyBlock {
+      id,
+      first_hop: nodes[0].0.to_string(),
+      header,
+      key,
+    })
+  }
+
+  /// The block's ID, which replies through it are [tagged with](struct.Message.html#method.reply_block).
+  pub fn id(&self) -> ReplyBlockId {
+    self.id
+  }
+
+  /// Puts the block's first header in front of the encrypted chunks of a reply, making the packet to send.
+  #[cfg(feature = "std")]
+  pub(crate) fn wrap(&self, reply: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut main = Vec::with_capacity(reply.len() + 1);
+    main.push(self.header.clone());
+    main.extend(reply);
+    let mut packet = vec![PACKET_VERSION];
+    encode_paths(&[main], &mut packet);
+    packet
+  }
+
+  /// Encodes the block, to give to whoever should reply: its ID, its key, a `u32` length and the first relay's path,
+  /// then the first relay's header.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut b = vec![];
+    b.extend_from_slice(&self.id.0.to_be_bytes());
+    b.extend_from_slice(self.key.as_bytes());
+    b.extend_from_slice(&(self.first_hop.len() as u32).to_be_bytes());
+    b.extend_from_slice(self.first_hop.as_bytes());
+    b.extend_from_slice(&self.header);
+    b
+  }
+
+  /// Decodes a block encoded with [`to_bytes`](#method.to_bytes), if it's well-formed.
+  pub fn from_bytes(mut bytes: &[u8]) -> Option<ReplyBlock> {
+    let mut id = [0; 8];
+    id.copy_from_slice(take(&mut bytes, 8)?);
+    let key = encrypt::PublicKey::from_slice(take(&mut bytes, 32)?)?;
+    let len = take_u32(&mut bytes)?;
+    let first_hop = String::from_utf8(take(&mut bytes, len)?.to_vec()).ok()?;
+    if bytes.is_empty() {
+      return None;
+    }
+    Some(ReplyBlock {
+      id: ReplyBlockId(u64::from_be_bytes(id)),
+      first_hop,
+      header: bytes.to_vec(),
+      key,
+    })
+  }
+}
+
+/// Takes the reply carried behind the header in a received packet, and puts it behind the next node's header or, with
+/// `None`, alone, for the block's maker to open.
+///
+/// Returns `None` if the packet can't be parsed.
+#[cfg(feature = "std")]
+pub(crate) fn pass_on(next: Option<Vec<u8>>, received: &[u8]) -> Option<Vec<u8>> {
+  let parsed = Packet::parse_untrusted(received).ok()?;
+  let mut main: Vec<_> = next.into_iter().collect();
+  main.extend(parsed.chunks.iter().skip(1).map(|c| c.to_vec()));
+  let mut packet = vec![PACKET_VERSION];
+  encode_paths(&[main], &mut packet);
+  Some(packet)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn steps_round_trip() {
+    for step in [
+      ReplyStep::Forward("tcp:[::1]:18540".to_owned(), vec![1, 2, 3]),
+      ReplyStep::Forward("inmem:x".to_owned(), vec![]),
+      ReplyStep::Final(ReplyBlockId(0x0123_4567_89ab_cdef)),
+    ] {
+      assert_eq!(Some(&step), ReplyStep::decode(&step.encode()).as_ref());
+    }
+    assert_eq!(None, ReplyStep::decode(&[1, 0, 0]));
+    assert_eq!(None, ReplyStep::decode(&[2]));
+  }
+
+  #[test]
+  fn blocks_round_trip() {
+    let (pk, _) = encrypt::gen_keypair();
+    let route = Route::new().then(Path::parse("inmem:reply-block-unit").unwrap(), pk);
+    let block = ReplyBlock::build(&route, ReplyBlockId(7), pk, Packet::unsigned).unwrap();
+    assert_eq!(Some(&block), ReplyBlock::from_bytes(&block.to_bytes()).as_ref());
+    assert_eq!(None, ReplyBlock::from_bytes(&block.to_bytes()[..50]));
+  }
+}
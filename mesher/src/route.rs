@@ -0,0 +1,135 @@
+//! Contains routes, the chains of nodes packets are sent through.
+
+use crate::{alloc_prelude::*, prelude::*};
+
+use core::{fmt, str::FromStr};
+
+/// An ordered chain of nodes for a packet to pass through, each given by the path it listens on and its public key.
+///
+/// Applying a route to a packet with [`Packet::via_route`](struct.Packet.html#method.via_route) adds a hop for every
+/// node but the last, telling it to forward the packet along the next node's path.
+/// The first node is normally the sender itself, since [`Mesher::launch`](struct.Mesher.html#method.launch) handles
+/// packets like it just received them.
+///
+/// Routes can be written out and read back in as text, one `key@path` node per line, where the key is the hex of the
+/// public key's bytes, e.g.:
+///
+/// ```text
+/// 6c1ffa...@tcp:[::1]:18540
+/// 0d4e21...@tcp:[::1]:18541
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Route {
+  nodes: Vec<(Path, encrypt::PublicKey)>,
+}
+
+impl Route {
+  /// Creates an empty route.
+  pub fn new() -> Route {
+    Route::default()
+  }
+
+  /// Adds a node to the end of the route.
+  pub fn then(mut self, path: Path, pkey: encrypt::PublicKey) -> Route {
+    self.nodes.push((path, pkey));
+    self
+  }
+
+  /// The nodes in the route, in order.
+  pub fn nodes(&self) -> impl Iterator<Item = (&Path, &encrypt::PublicKey)> {
+    self.nodes.iter().map(|(p, k)| (p, k))
+  }
+
+  /// The number of nodes in the route.
+  pub fn len(&self) -> usize {
+    self.nodes.len()
+  }
+
+  /// Whether there are no nodes in the route.
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  /// The same route, but backwards, e.g. for the reply path of a packet sent along this one.
+  pub fn reversed(&self) -> Route {
+    Route {
+      nodes: self.nodes.iter().rev().cloned().collect(),
+    }
+  }
+
+  /// Each hop in the route, as the path to send along and the key of the node that should send along it.
+  pub(crate) fn hops(&self) -> impl Iterator<Item = (String, &encrypt::PublicKey)> {
+    self.nodes.windows(2).map(|w| (w[1].0.to_string(), &w[0].1))
+  }
+}
+
+impl fmt::Display for Route {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (path, pkey) in &self.nodes {
+      writeln!(f, "{}@{}", pkey, path)?;
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for Route {
+  type Err = fail::MesherFail;
+
+  /// Reads a route in the format written by `Display`; blank lines are ignored.
+  fn from_str(s: &str) -> fail::Result<Route> {
+    let bad = |line: &str| fail::MesherFail::InvalidURL(format!("invalid route node: {}", line));
+    let mut route = Route::new();
+    for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+      let at = line.find('@').ok_or_else(|| bad(line))?;
+      let pkey: encrypt::PublicKey = line[..at].parse().map_err(|_| bad(line))?;
+      route = route.then(Path::parse(&line[at + 1..])?, pkey);
+    }
+    Ok(route)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn node(path: &str) -> (Path, encrypt::PublicKey) {
+    (
+      Path::parse(path).expect("Failed to parse path"),
+      encrypt::gen_keypair().0,
+    )
+  }
+
+  #[test]
+  fn hops_between_nodes() {
+    let (p1, k1) = node("inmem:one");
+    let (p2, k2) = node("inmem:two");
+    let (p3, k3) = node("inmem:three");
+    let route = Route::new().then(p1, k1).then(p2, k2).then(p3, k3);
+
+    let hops: Vec<_> = route.hops().collect();
+    assert_eq!(
+      vec![("inmem:two".to_owned(), &k1), ("inmem:three".to_owned(), &k2)],
+      hops
+    );
+    let hops: Vec<_> = route.reversed().hops().map(|(p, k)| (p, *k)).collect();
+    assert_eq!(vec![("inmem:two".to_owned(), k3), ("inmem:one".to_owned(), k2)], hops);
+  }
+
+  #[test]
+  fn text_roundtrip() {
+    let (p1, k1) = node("tcp:[::1]:18540?retries=2");
+    let (p2, k2) = node("inmem:a@b");
+    let route = Route::new().then(p1, k1).then(p2, k2);
+    let text = route.to_string();
+    assert_eq!(2, text.lines().count());
+    assert_eq!(route, text.parse().expect("Failed to parse route"));
+  }
+
+  #[test]
+  fn bad_text_fails() {
+    assert!("nope".parse::<Route>().is_err());
+    assert!("abcd@inmem:foo".parse::<Route>().is_err());
+    assert!(format!("{}@inmem:foo", "zz".repeat(32)).parse::<Route>().is_err());
+    assert_eq!(Route::new(), "\n\n".parse().expect("Failed to parse empty route"));
+  }
+}
@@ -0,0 +1,319 @@
+//! Contains a request/response layer on top of messages, so applications don't have to match replies up with requests
+//! themselves.
+//!
+//! An [`Rpc`](struct.Rpc.html) sends requests for a named method along a [`Route`](../struct.Route.html), each with an
+//! ID and a reply path back along the same route, and answers requests for the methods it has handlers
+//! [registered](struct.Rpc.html#method.register) for.
+//! Every message the mesher receives should be passed to [`Rpc::handle`](struct.Rpc.html#method.handle), which answers
+//! requests and holds on to responses until they're [collected](struct.Rpc.html#method.response).
+//! Applications that would rather wait for the response can use [`call`](struct.Rpc.html#method.call) instead, which
+//! keeps receiving until it comes or the request times out.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::rpc::Rpc;
+//! use std::time::Duration;
+//!
+//! let (client_pk, client_sk) = encrypt::gen_keypair();
+//! let (server_pk, server_sk) = encrypt::gen_keypair();
+//! let mut client = Mesher::unsigned(vec![client_sk]);
+//! client.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! client.listen_on("inmem:rpc-doc-client").expect("Failed to listen");
+//! let mut server = Mesher::unsigned(vec![server_sk]);
+//! server.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! server.listen_on("inmem:rpc-doc-server").expect("Failed to listen");
+//!
+//! let mut server_rpc = Rpc::new();
+//! server_rpc.register("echo", |body| body.to_vec());
+//! let mut client_rpc = Rpc::new();
+//! let route = mesher::Route::new()
+//!   .then(Path::parse("inmem:rpc-doc-client").unwrap(), client_pk)
+//!   .then(Path::parse("inmem:rpc-doc-server").unwrap(), server_pk);
+//! let id = client_rpc
+//!   .request(&mut client, &route, "echo", b"hello", Duration::from_secs(5))
+//!   .expect("Failed to send request");
+//!
+//! for message in server.receive().expect("Failed to receive") {
+//!   server_rpc.handle(&mut server, &message).expect("Failed to answer");
+//! }
+//! for message in client.receive().expect("Failed to receive") {
+//!   client_rpc.handle(&mut client, &message).expect("Failed to handle response");
+//! }
+//! assert_eq!(b"hello".to_vec(), client_rpc.response(id).unwrap().unwrap());
+//! ```
+
+use crate::{packet::take, prelude::*, Route};
+
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
+
+/// Marks a message as part of an RPC, rather than something else the application sent.
+const MAGIC: &[u8; 4] = b"MRPC";
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+/// The response's status: the handler answered.
+const ANSWERED: u8 = 0;
+/// The response's status: the responder has no handler for the method.
+const NO_HANDLER: u8 = 1;
+/// How long [`Rpc::call`](struct.Rpc.html#method.call) waits between receives.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Identifies one request, so its response can be matched up with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RequestId(pub u64);
+
+/// Answers requests for one method, turning the request's body into the response's.
+type Handler = Box<dyn FnMut(&[u8]) -> Vec<u8> + Send>;
+
+/// A request that's been sent and is waiting for a response.
+struct Pending {
+  method: String,
+  deadline: Instant,
+}
+
+/// A message that's part of an RPC.
+enum Decoded<'a> {
+  Request {
+    id: RequestId,
+    reply_pkey: encrypt::PublicKey,
+    method: &'a str,
+    body: &'a [u8],
+  },
+  Response {
+    id: RequestId,
+    status: u8,
+    body: &'a [u8],
+  },
+}
+
+/// Encodes a request: the magic, the kind, the big-endian ID, the key to encrypt the response for, the method's length
+/// and name, then the body.
+fn encode_request(id: RequestId, reply_pkey: &encrypt::PublicKey, method: &str, body: &[u8]) -> Vec<u8> {
+  let mut b = Vec::with_capacity(4 + 1 + 8 + 32 + 1 + method.len() + body.len());
+  b.extend_from_slice(MAGIC);
+  b.push(REQUEST);
+  b.extend_from_slice(&id.0.to_be_bytes());
+  b.extend_from_slice(reply_pkey.as_bytes());
+  b.push(method.len() as u8);
+  b.extend_from_slice(method.as_bytes());
+  b.extend_from_slice(body);
+  b
+}
+
+/// Encodes a response: the magic, the kind, the big-endian ID, the status, then the body.
+fn encode_response(id: RequestId, status: u8, body: &[u8]) -> Vec<u8> {
+  let mut b = Vec::with_capacity(4 + 1 + 8 + 1 + body.len());
+  b.extend_from_slice(MAGIC);
+  b.push(RESPONSE);
+  b.extend_from_slice(&id.0.to_be_bytes());
+  b.push(status);
+  b.extend_from_slice(body);
+  b
+}
+
+/// Decodes a message written by [`encode_request`](fn.encode_request.html) or
+/// [`encode_response`](fn.encode_response.html), or returns `None` if it isn't one.
+fn decode(message: &[u8]) -> Option<Decoded<'_>> {
+  let mut rest = message.strip_prefix(&MAGIC[..])?;
+  let kind = take(&mut rest, 1)?[0];
+  let mut id = [0; 8];
+  id.copy_from_slice(take(&mut rest, 8)?);
+  let id = RequestId(u64::from_be_bytes(id));
+  match kind {
+    REQUEST => {
+      let reply_pkey = encrypt::PublicKey::from_slice(take(&mut rest, 32)?)?;
+      let len = take(&mut rest, 1)?[0];
+      let method = core::str::from_utf8(take(&mut rest, len.into())?).ok()?;
+      Some(Decoded::Request {
+        id,
+        reply_pkey,
+        method,
+        body: rest,
+      })
+    }
+    RESPONSE => {
+      let status = take(&mut rest, 1)?[0];
+      Some(Decoded::Response { id, status, body: rest })
+    }
+    _ => None,
+  }
+}
+
+/// Sends requests and answers them, on top of a [`Mesher`](../struct.Mesher.html).
+///
+/// One `Rpc` can do both, for as many meshers as it's used with, though usually there's one per mesher.
+/// Responses are only kept until they're [collected](#method.response), and ones that arrive after their request has
+/// timed out are dropped.
+#[derive(Default)]
+pub struct Rpc {
+  handlers: HashMap<String, Handler>,
+  pending: HashMap<RequestId, Pending>,
+  responses: HashMap<RequestId, fail::Result<Vec<u8>>>,
+  unhandled: Vec<Message>,
+}
+
+impl Rpc {
+  /// Creates an `Rpc` with no handlers and no requests waiting.
+  pub fn new() -> Rpc {
+    Rpc::default()
+  }
+
+  /// Answers requests for `method` with whatever `handler` returns, given the request's body.
+  ///
+  /// Registering a method again replaces its old handler.
+  pub fn register(&mut self, method: &str, handler: impl FnMut(&[u8]) -> Vec<u8> + Send + 'static) {
+    self.handlers.insert(method.to_owned(), Box::new(handler));
+  }
+
+  /// Stops answering requests for `method`, returning whether there was a handler for it.
+  ///
+  /// Requests for methods without handlers fail on the requester's side with
+  /// [`MesherFail::RpcFailure`](../fail/enum.MesherFail.html#variant.RpcFailure).
+  pub fn unregister(&mut self, method: &str) -> bool {
+    self.handlers.remove(method).is_some()
+  }
+
+  /// Sends a request for `method` along `route` to its last node, and returns its ID, to collect the
+  /// [response](#method.response) with.
+  ///
+  /// The route has to start with a path the requesting mesher is listening on, since the response comes back along
+  /// it, reversed.
+  /// If no response has come back after `timeout`, the request fails with
+  /// [`MesherFail::TimedOut`](../fail/enum.MesherFail.html#variant.TimedOut).
+  pub fn request(
+    &mut self,
+    mesher: &mut Mesher,
+    route: &Route,
+    method: &str,
+    body: &[u8],
+    timeout: Duration,
+  ) -> fail::Result<RequestId> {
+    let dest = match route.nodes().last() {
+      Some((_, pkey)) => *pkey,
+      None => return Err(fail::MesherFail::NoRoute("the route is empty".to_owned())),
+    };
+    if method.len() > u8::MAX as usize {
+      return Err(fail::MesherFail::RpcFailure(format!(
+        "method names can be at most 255 bytes, not {}",
+        method.len()
+      )));
+    }
+    let own = mesher.newest_pkey().ok_or(fail::MesherFail::NoKeys)?;
+    let id = RequestId(rand::random());
+    let mut packet = mesher.new_packet(None);
+    packet.via_route(route);
+    let mut reply = packet
+      .add_reply_path()
+      .ok_or_else(|| fail::MesherFail::RpcFailure("the packet has no room for a reply path".to_owned()))?;
+    reply.via_route(&route.reversed());
+    reply.use_for_message(&encode_request(id, &own, method, body), &dest);
+    mesher.launch(packet)?;
+    self.pending.insert(
+      id,
+      Pending {
+        method: method.to_owned(),
+        deadline: Instant::now() + timeout,
+      },
+    );
+    Ok(id)
+  }
+
+  /// Handles a received message, returning whether it was part of an RPC, and so shouldn't be handled as anything
+  /// else.
+  ///
+  /// Requests are answered right away, through the mesher, and responses are held until they're
+  /// [collected](#method.response).
+  /// Fails if a request can't be answered, e.g. because it came without a way to reply.
+  pub fn handle(&mut self, mesher: &mut Mesher, message: &Message) -> fail::Result<bool> {
+    match decode(message.contents()) {
+      None => Ok(false),
+      Some(Decoded::Request {
+        id,
+        reply_pkey,
+        method,
+        body,
+      }) => {
+        let response = match self.handlers.get_mut(method) {
+          Some(handler) => encode_response(id, ANSWERED, &handler(body)),
+          None => encode_response(id, NO_HANDLER, &[]),
+        };
+        let mut packet = mesher.new_packet(None);
+        packet.reply_to(message)?;
+        packet.add_message(&response, &reply_pkey);
+        mesher.launch(packet)?;
+        Ok(true)
+      }
+      Some(Decoded::Response { id, status, body }) => {
+        // anything not waiting has already timed out, or was never asked for
+        if let Some(pending) = self.pending.remove(&id) {
+          let result = match status {
+            ANSWERED => Ok(body.to_vec()),
+            _ => Err(fail::MesherFail::RpcFailure(format!(
+              "the responder has no handler for {}",
+              pending.method
+            ))),
+          };
+          self.responses.insert(id, result);
+        }
+        Ok(true)
+      }
+    }
+  }
+
+  /// Takes the response to a request, or the reason it failed, or returns `None` if it's still waiting.
+  ///
+  /// Each response can only be taken once; after that, its ID is forgotten, and this returns `None` for it too.
+  pub fn response(&mut self, id: RequestId) -> Option<fail::Result<Vec<u8>>> {
+    if let Some(result) = self.responses.remove(&id) {
+      return Some(result);
+    }
+    let timed_out = self.pending.get(&id)?.deadline <= Instant::now();
+    if !timed_out {
+      return None;
+    }
+    let pending = self.pending.remove(&id).expect("Was just there");
+    Some(Err(fail::MesherFail::TimedOut(format!(
+      "response to request {:x} for {}",
+      id.0, pending.method
+    ))))
+  }
+
+  /// Sends a request, like [`request`](#method.request), then keeps [receiving](../struct.Mesher.html#method.receive)
+  /// and [handling](#method.handle) messages until its response comes back or it times out.
+  ///
+  /// Other requests are answered in the meantime, and messages which aren't part of an RPC are kept, to be collected
+  /// with [`take_unhandled`](#method.take_unhandled).
+  /// Failures answering other requests go to the mesher's [failure handler](../struct.Mesher.html#method.on_failure),
+  /// rather than stopping the wait.
+  pub fn call(
+    &mut self,
+    mesher: &mut Mesher,
+    route: &Route,
+    method: &str,
+    body: &[u8],
+    timeout: Duration,
+  ) -> fail::Result<Vec<u8>> {
+    let id = self.request(mesher, route, method, body, timeout)?;
+    loop {
+      for message in mesher.receive()? {
+        match self.handle(mesher, &message) {
+          Ok(true) => (),
+          Ok(false) => self.unhandled.push(message),
+          Err(e) => mesher.report_failure(e),
+        }
+      }
+      if let Some(result) = self.response(id) {
+        return result;
+      }
+      std::thread::sleep(POLL_INTERVAL);
+    }
+  }
+
+  /// Takes the messages [`call`](#method.call) received while it was waiting that weren't part of an RPC.
+  pub fn take_unhandled(&mut self) -> Vec<Message> {
+    std::mem::take(&mut self.unhandled)
+  }
+}
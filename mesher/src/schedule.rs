@@ -0,0 +1,90 @@
+//! Contains the schedule of packets a mesher's been asked to send later.
+
+use crate::{Priority, Source};
+
+use std::time::Instant;
+
+/// Something the mesher's waiting to do with a packet.
+pub(crate) enum Scheduled {
+  /// A packet [launched](struct.Mesher.html#method.launch_at) for later, already serialized.
+  Launch(Vec<u8>, Priority),
+  /// A packet a sender asked this relay to [hold](struct.Packet.html#method.add_hold_until) before forwarding, where
+  /// it's going, and where it came from.
+  Forward(Vec<u8>, String, Option<Source>, Priority),
+}
+
+/// The packets waiting to be sent, and when they're due.
+#[derive(Default)]
+pub(crate) struct Schedule {
+  held: Vec<(Instant, Scheduled)>,
+}
+
+impl Schedule {
+  /// Holds on to something until `at`.
+  pub(crate) fn hold(&mut self, at: Instant, what: Scheduled) {
+    self.held.push((at, what));
+  }
+
+  /// Takes out everything that's due by `now`, earliest first.
+  pub(crate) fn due(&mut self, now: Instant) -> Vec<Scheduled> {
+    let (mut due, held): (Vec<_>, _) = self.held.drain(..).partition(|(at, _)| *at <= now);
+    self.held = held;
+    due.sort_by_key(|(at, _)| *at);
+    due.into_iter().map(|(_, what)| what).collect()
+  }
+
+  /// Takes out everything, due or not, earliest first.
+  pub(crate) fn drain(&mut self) -> Vec<Scheduled> {
+    let mut all: Vec<_> = self.held.drain(..).collect();
+    all.sort_by_key(|(at, _)| *at);
+    all.into_iter().map(|(_, what)| what).collect()
+  }
+
+  /// When the next thing's due, if anything's waiting.
+  pub(crate) fn next_due(&self) -> Option<Instant> {
+    self.held.iter().map(|(at, _)| *at).min()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::time::Duration;
+
+  fn launched(what: Vec<Scheduled>) -> Vec<Vec<u8>> {
+    what
+      .into_iter()
+      .map(|s| match s {
+        Scheduled::Launch(packet, _) => packet,
+        Scheduled::Forward(packet, ..) => packet,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn due_in_order() {
+    let now = Instant::now();
+    let mut schedule = Schedule::default();
+    schedule.hold(
+      now + Duration::from_secs(2),
+      Scheduled::Launch(vec![2], Priority::Normal),
+    );
+    schedule.hold(
+      now + Duration::from_secs(1),
+      Scheduled::Launch(vec![1], Priority::Normal),
+    );
+    schedule.hold(
+      now + Duration::from_secs(60),
+      Scheduled::Launch(vec![3], Priority::Normal),
+    );
+    assert_eq!(Some(now + Duration::from_secs(1)), schedule.next_due());
+    assert!(schedule.due(now).is_empty());
+    assert_eq!(
+      vec![vec![1], vec![2]],
+      launched(schedule.due(now + Duration::from_secs(2)))
+    );
+    assert_eq!(vec![vec![3]], launched(schedule.drain()));
+    assert_eq!(None, schedule.next_due());
+  }
+}
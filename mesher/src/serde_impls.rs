@@ -0,0 +1,230 @@
+//! Contains the `Serialize` and `Deserialize` impls which can't just be derived.
+//!
+//! Keys are written as hex in human-readable formats like JSON, and as raw bytes otherwise.
+//! Paths are written as strings, and routes as a list of nodes, each with a `path` and a `key`.
+
+use crate::{alloc_prelude::*, prelude::*, Route};
+
+use core::fmt;
+
+use serde::{
+  de::{self, SeqAccess, Visitor},
+  Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// Writes bytes as lowercase hex, without allocating.
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for b in self.0 {
+      write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+  }
+}
+
+fn serialize_key<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+  if s.is_human_readable() {
+    s.collect_str(&Hex(bytes))
+  } else {
+    s.serialize_bytes(bytes)
+  }
+}
+
+/// Reads an `N`-byte key, as hex, raw bytes, or a sequence of bytes.
+struct KeyVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for KeyVisitor<N> {
+  type Value = [u8; N];
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "a {}-byte key, as hex or bytes", N)
+  }
+
+  fn visit_str<E: de::Error>(self, hex: &str) -> Result<[u8; N], E> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+      return Err(E::invalid_length(hex.len() / 2, &self));
+    }
+    let mut key = [0; N];
+    for (i, b) in key.iter_mut().enumerate() {
+      *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+        .map_err(|_| E::invalid_value(de::Unexpected::Str(hex), &self))?;
+    }
+    Ok(key)
+  }
+
+  fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<[u8; N], E> {
+    let mut key = [0; N];
+    if bytes.len() != N {
+      return Err(E::invalid_length(bytes.len(), &self));
+    }
+    key.copy_from_slice(bytes);
+    Ok(key)
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[u8; N], A::Error> {
+    let mut key = [0; N];
+    for (i, b) in key.iter_mut().enumerate() {
+      *b = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(i, &self))?;
+    }
+    if seq.next_element::<u8>()?.is_some() {
+      return Err(de::Error::invalid_length(N + 1, &self));
+    }
+    Ok(key)
+  }
+}
+
+fn deserialize_key<'de, D: Deserializer<'de>, const N: usize>(d: D) -> Result<[u8; N], D::Error> {
+  if d.is_human_readable() {
+    d.deserialize_str(KeyVisitor::<N>)
+  } else {
+    d.deserialize_bytes(KeyVisitor::<N>)
+  }
+}
+
+macro_rules! key_serde {
+  ($($key:ty),*) => {$(
+    impl Serialize for $key {
+      fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serialize_key(&self.0, s)
+      }
+    }
+
+    impl<'de> Deserialize<'de> for $key {
+      fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        deserialize_key(d).map(Self)
+      }
+    }
+  )*};
+}
+
+key_serde!(encrypt::PublicKey, sign::PublicKey);
+
+/// Like `key_serde`, but refusing to serialize keys [held by a device](../crypto/hardware/index.html), which don't have
+/// any bytes to write.
+#[cfg(feature = "serde_secret_keys")]
+macro_rules! secret_key_serde {
+  ($($key:ty),*) => {$(
+    impl Serialize for $key {
+      fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        if self.1.is_some() {
+          return Err(serde::ser::Error::custom("keys held by a device can't be serialized"));
+        }
+        serialize_key(&self.0, s)
+      }
+    }
+
+    impl<'de> Deserialize<'de> for $key {
+      fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        deserialize_key(d).map(|key| Self(key, None))
+      }
+    }
+  )*};
+}
+
+#[cfg(feature = "serde_secret_keys")]
+secret_key_serde!(encrypt::SecretKey, sign::SecretKey);
+
+impl Serialize for Path {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(self.as_str())
+  }
+}
+
+/// Reads a path from a string, borrowed or not.
+struct PathVisitor;
+
+impl Visitor<'_> for PathVisitor {
+  type Value = Path;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a path, like scheme:location")
+  }
+
+  fn visit_str<E: de::Error>(self, path: &str) -> Result<Path, E> {
+    Path::parse(path).map_err(|_| E::invalid_value(de::Unexpected::Str(path), &self))
+  }
+}
+
+impl<'de> Deserialize<'de> for Path {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Path, D::Error> {
+    d.deserialize_str(PathVisitor)
+  }
+}
+
+/// One node of a route, as it's written out.
+#[derive(Serialize, Deserialize)]
+struct Node<P, K> {
+  path: P,
+  key: K,
+}
+
+impl Serialize for Route {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(self.nodes().map(|(path, key)| Node { path, key }))
+  }
+}
+
+impl<'de> Deserialize<'de> for Route {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Route, D::Error> {
+    let nodes = Vec::<Node<Path, encrypt::PublicKey>>::deserialize(d)?;
+    Ok(nodes.into_iter().fold(Route::new(), |r, n| r.then(n.path, n.key)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip<T: Serialize + for<'de> Deserialize<'de> + PartialEq + fmt::Debug>(value: &T) -> String {
+    let json = serde_json::to_string(value).expect("Failed to serialize JSON");
+    assert_eq!(
+      value,
+      &serde_json::from_str::<T>(&json).expect("Failed to deserialize JSON")
+    );
+    let bin = bincode::serialize(value).expect("Failed to serialize bincode");
+    assert_eq!(
+      value,
+      &bincode::deserialize::<T>(&bin).expect("Failed to deserialize bincode")
+    );
+    json
+  }
+
+  #[test]
+  fn keys_are_hex() {
+    let (pk, _) = encrypt::gen_keypair();
+    let (spk, _) = sign::gen_keypair();
+    assert_eq!(format!("\"{}\"", Hex(pk.as_bytes())), round_trip(&pk));
+    assert_eq!(format!("\"{}\"", Hex(spk.as_bytes())), round_trip(&spk));
+  }
+
+  #[cfg(feature = "serde_secret_keys")]
+  #[test]
+  fn secret_keys_round_trip() {
+    let (_, sk) = encrypt::gen_keypair();
+    let json = serde_json::to_string(&sk).expect("Failed to serialize");
+    assert!(sk == serde_json::from_str(&json).expect("Failed to deserialize"));
+  }
+
+  #[test]
+  fn bad_keys_rejected() {
+    assert!(serde_json::from_str::<encrypt::PublicKey>("\"abcd\"").is_err());
+    assert!(serde_json::from_str::<encrypt::PublicKey>(&format!("\"{}\"", "zz".repeat(32))).is_err());
+    assert!(bincode::deserialize::<sign::PublicKey>(&bincode::serialize(&[0u8; 31][..]).unwrap()).is_err());
+  }
+
+  #[test]
+  fn paths_and_routes_round_trip() {
+    let path = Path::parse("tcp:[::1]:18540?retries=3").unwrap();
+    assert_eq!("\"tcp:[::1]:18540?retries=3\"", round_trip(&path));
+    assert!(serde_json::from_str::<Path>("\"no-scheme\"").is_err());
+
+    let (pk1, _) = encrypt::gen_keypair();
+    let (pk2, _) = encrypt::gen_keypair();
+    let route = Route::new()
+      .then(Path::parse("inmem:a").unwrap(), pk1)
+      .then(Path::parse("inmem:b").unwrap(), pk2);
+    round_trip(&route);
+  }
+}
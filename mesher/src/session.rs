@@ -0,0 +1,645 @@
+//! Contains a session layer, for long-lived conversations between two nodes, using a double ratchet.
+//!
+//! Every message in a session is encrypted with its own key, derived from a chain that only moves forward, so a key
+//! leaking doesn't reveal anything sent before it (forward secrecy).
+//! Every time the conversation changes direction, both sides also mix a fresh Diffie-Hellman exchange into the chain,
+//! so once a compromised node's attacker stops watching, the session heals (post-compromise security).
+//! This is the same construction as Signal's, though the wire format isn't compatible with it.
+//! It uses libsodium directly, rather than the mesher's crypto backend, so it needs the `crypto-sodium` feature.
+//!
+//! Each node has one [`Sessions`](struct.Sessions.html), with an identity key, which should be the key its mesher's
+//! reached by.
+//! The first message to a peer starts a session, by sending a handshake along with it, and every message until the
+//! peer replies carries the handshake too, so it doesn't matter which arrives first.
+//! Handshakes are stamped with when they were started, by the sender's clock, and each one has to be newer than every
+//! other a node's had from that peer, so an old one replayed can't start a session again or replace the current one.
+//! Session messages travel as [custom chunks](../trait.CustomChunk.html) of kind `0xFF02`, which the `Sessions` handles
+//! itself, so they never show up as ordinary messages.
+//!
+//! ```
+//! # use mesher::{prelude::*, Route};
+//! use mesher::session::Sessions;
+//!
+//! let (alice_pk, alice_sk) = encrypt::gen_keypair();
+//! let (bob_pk, bob_sk) = encrypt::gen_keypair();
+//! let mut alice = Mesher::unsigned(vec![alice_sk.clone_secret()]);
+//! alice.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! alice.listen_on("inmem:session-doc-alice").expect("Failed to listen");
+//! let mut bob = Mesher::unsigned(vec![bob_sk.clone_secret()]);
+//! bob.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! bob.listen_on("inmem:session-doc-bob").expect("Failed to listen");
+//! let to_bob = Route::new()
+//!   .then(Path::parse("inmem:session-doc-alice").unwrap(), alice_pk)
+//!   .then(Path::parse("inmem:session-doc-bob").unwrap(), bob_pk);
+//!
+//! let alice_sessions = Sessions::new(&mut alice, alice_sk);
+//! let bob_sessions = Sessions::new(&mut bob, bob_sk);
+//! alice_sessions.send(&mut alice, &to_bob, b"hi bob").expect("Failed to send");
+//! bob.receive().expect("Failed to receive");
+//! assert_eq!(vec![(alice_pk, b"hi bob".to_vec())], bob_sessions.take_received());
+//! bob_sessions.send(&mut bob, &to_bob.reversed(), b"hi alice").expect("Failed to send");
+//! alice.receive().expect("Failed to receive");
+//! assert_eq!(vec![(bob_pk, b"hi alice".to_vec())], alice_sessions.take_received());
+//! ```
+
+use crate::{packet::take, prelude::*, CustomChunk, Route};
+
+use sodiumoxide::crypto::{aead::chacha20poly1305_ietf as aead, auth::hmacsha256, scalarmult::curve25519};
+
+use std::{
+  collections::{hash_map::Entry, HashMap, VecDeque},
+  sync::{Arc, Mutex},
+};
+
+/// The most message keys skipped over at once, e.g. for messages that were lost or are still on their way.
+const MAX_SKIP: u32 = 1000;
+/// The most skipped message keys a session keeps, for messages that arrive late; the oldest are dropped first.
+const MAX_SKIPPED_KEYS: usize = 2000;
+/// The length of a message header: the sender's ratchet key, then the length of its previous chain and the message's
+/// number in this one, as big-endian `u32`s.
+const HEADER_BYTES: usize = 40;
+/// The most handshake ephemeral keys remembered for each peer, to recognize repeats; the oldest are forgotten first.
+const MAX_SEEN_HANDSHAKES: usize = 64;
+
+type Key = [u8; 32];
+
+/// Derives a key from `key` and `input`, HKDF-style, with HMAC-SHA256, where `label` tells apart keys derived from the
+/// same things.
+fn kdf(key: &Key, input: &[u8], label: u8) -> Key {
+  let prk = hmacsha256::authenticate(input, &hmacsha256::Key(*key)).0;
+  hmacsha256::authenticate(&[label], &hmacsha256::Key(prk)).0
+}
+
+/// Steps the root chain with a Diffie-Hellman output, returning the new root key and the new sending or receiving
+/// chain key.
+fn kdf_root(root: &Key, dh: &Key) -> (Key, Key) {
+  (kdf(root, dh, 1), kdf(root, dh, 2))
+}
+
+/// Steps a sending or receiving chain, returning the next chain key and the key for one message.
+fn kdf_chain(chain: &Key) -> (Key, Key) {
+  (kdf(chain, &[], 1), kdf(chain, &[], 2))
+}
+
+/// Always fails for keys [held by a device](../crypto/hardware/index.html), which don't give out the raw key.
+fn dh(sk: &encrypt::SecretKey, pk: &encrypt::PublicKey) -> Option<Key> {
+  if sk.handle().is_some() {
+    return None;
+  }
+  curve25519::scalarmult(&curve25519::Scalar(sk.0), &curve25519::GroupElement(pk.0))
+    .ok()
+    .map(|g| g.0)
+}
+
+fn session_fail(what: impl Into<String>) -> fail::MesherFail {
+  fail::MesherFail::SessionFailure(what.into())
+}
+
+/// A message's header, which says which keys it was encrypted with.
+#[derive(Clone, Copy)]
+struct Header {
+  ratchet: encrypt::PublicKey,
+  previous: u32,
+  number: u32,
+}
+
+impl Header {
+  fn encode(&self) -> Vec<u8> {
+    let mut b = Vec::with_capacity(HEADER_BYTES);
+    b.extend_from_slice(self.ratchet.as_bytes());
+    b.extend_from_slice(&self.previous.to_be_bytes());
+    b.extend_from_slice(&self.number.to_be_bytes());
+    b
+  }
+}
+
+/// What starts a session: the initiator's ephemeral and first ratchet keys, and when it was started, in milliseconds
+/// since the Unix epoch.
+#[derive(Clone, Copy)]
+struct Handshake {
+  ephemeral: encrypt::PublicKey,
+  ratchet: encrypt::PublicKey,
+  started: u64,
+}
+
+impl Handshake {
+  fn encode(&self) -> Vec<u8> {
+    let mut b = self.ephemeral.as_bytes().to_vec();
+    b.extend_from_slice(self.ratchet.as_bytes());
+    b.extend_from_slice(&self.started.to_be_bytes());
+    b
+  }
+}
+
+/// One side of a session.
+///
+/// Its keys are wiped when it's dropped, like the mesher's own secret keys.
+struct Ratchet {
+  /// This side's current ratchet keypair.
+  own: (encrypt::PublicKey, encrypt::SecretKey),
+  /// The other side's current ratchet key, once it's known.
+  theirs: Option<encrypt::PublicKey>,
+  root: Key,
+  sending: Option<Key>,
+  receiving: Option<Key>,
+  sent: u32,
+  received: u32,
+  previous: u32,
+  skipped: VecDeque<((encrypt::PublicKey, u32), Key)>,
+  /// The handshake this side started the session with, to send along until the other side replies.
+  handshake: Option<Handshake>,
+  /// The ephemeral key from the handshake the other side started the session with, so repeats are recognized.
+  their_handshake: Option<encrypt::PublicKey>,
+}
+
+impl Clone for Ratchet {
+  fn clone(&self) -> Ratchet {
+    Ratchet {
+      own: (self.own.0, self.own.1.clone_secret()),
+      theirs: self.theirs,
+      root: self.root,
+      sending: self.sending,
+      receiving: self.receiving,
+      sent: self.sent,
+      received: self.received,
+      previous: self.previous,
+      skipped: self.skipped.clone(),
+      handshake: self.handshake,
+      their_handshake: self.their_handshake,
+    }
+  }
+}
+
+impl Drop for Ratchet {
+  fn drop(&mut self) {
+    let chains = self.sending.iter_mut().chain(self.receiving.iter_mut());
+    for key in core::iter::once(&mut self.root)
+      .chain(chains)
+      .chain(self.skipped.iter_mut().map(|(_, k)| k))
+    {
+      crate::crypto::wipe(key);
+    }
+  }
+}
+
+/// Works out the secret a session starts from, from an ephemeral key and both identity keys.
+///
+/// Both exchanges involve the responder's identity key, so only it can complete the handshake, and the second involves
+/// the initiator's, so only it could have started it.
+fn initial_secret(first: Key, second: Key) -> Key {
+  kdf(&[0; 32], &[first, second].concat(), 0)
+}
+
+impl Ratchet {
+  /// Starts a session with `peer`, as the side sending the handshake.
+  fn initiate(identity: &encrypt::SecretKey, peer: &encrypt::PublicKey) -> Option<Ratchet> {
+    let (ephemeral_pk, ephemeral_sk) = encrypt::gen_keypair();
+    let secret = initial_secret(dh(&ephemeral_sk, peer)?, dh(identity, peer)?);
+    let own = encrypt::gen_keypair();
+    let (root, sending) = kdf_root(&secret, &dh(&own.1, peer)?);
+    let started = std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map_or(0, |d| d.as_millis() as u64);
+    Some(Ratchet {
+      handshake: Some(Handshake {
+        ephemeral: ephemeral_pk,
+        ratchet: own.0,
+        started,
+      }),
+      own,
+      theirs: Some(*peer),
+      root,
+      sending: Some(sending),
+      receiving: None,
+      sent: 0,
+      received: 0,
+      previous: 0,
+      skipped: VecDeque::new(),
+      their_handshake: None,
+    })
+  }
+
+  /// Starts a session with `peer`, as the side receiving its handshake.
+  fn respond(identity: &encrypt::SecretKey, peer: &encrypt::PublicKey, handshake: &Handshake) -> Option<Ratchet> {
+    let secret = initial_secret(dh(identity, &handshake.ephemeral)?, dh(identity, peer)?);
+    let mut session = Ratchet {
+      own: (identity.public_key(), identity.clone_secret()),
+      theirs: None,
+      root: secret,
+      sending: None,
+      receiving: None,
+      sent: 0,
+      received: 0,
+      previous: 0,
+      skipped: VecDeque::new(),
+      handshake: None,
+      their_handshake: Some(handshake.ephemeral),
+    };
+    session.step(&handshake.ratchet)?;
+    Some(session)
+  }
+
+  /// Moves both chains on to a new ratchet key from the other side.
+  fn step(&mut self, theirs: &encrypt::PublicKey) -> Option<()> {
+    self.previous = self.sent;
+    self.sent = 0;
+    self.received = 0;
+    self.theirs = Some(*theirs);
+    let (root, receiving) = kdf_root(&self.root, &dh(&self.own.1, theirs)?);
+    self.own = encrypt::gen_keypair();
+    let (root, sending) = kdf_root(&root, &dh(&self.own.1, theirs)?);
+    self.root = root;
+    self.receiving = Some(receiving);
+    self.sending = Some(sending);
+    Some(())
+  }
+
+  /// Keeps the keys for the messages in the current receiving chain before `until`, so they can still be read later.
+  fn skip_to(&mut self, until: u32) -> Option<()> {
+    let mut chain = match self.receiving {
+      Some(chain) => chain,
+      None => return Some(()),
+    };
+    if until.checked_sub(self.received)? > MAX_SKIP {
+      return None;
+    }
+    let theirs = self.theirs?;
+    while self.received < until {
+      let (next, key) = kdf_chain(&chain);
+      chain = next;
+      self.skipped.push_back(((theirs, self.received), key));
+      self.received += 1;
+    }
+    while self.skipped.len() > MAX_SKIPPED_KEYS {
+      self.skipped.pop_front();
+    }
+    self.receiving = Some(chain);
+    Some(())
+  }
+
+  fn encrypt(&mut self, data: &[u8], ad: &[u8]) -> Option<(Header, Vec<u8>)> {
+    let (next, key) = kdf_chain(&self.sending?);
+    self.sending = Some(next);
+    let header = Header {
+      ratchet: self.own.0,
+      previous: self.previous,
+      number: self.sent,
+    };
+    self.sent += 1;
+    let ad = [ad, &header.encode()].concat();
+    // every message key is only ever used once, so the nonce doesn't need to change
+    let sealed = aead::seal(data, Some(&ad), &aead::Nonce([0; 12]), &aead::Key(key));
+    Some((header, sealed))
+  }
+
+  /// Decrypts a message, only changing the session's state if it's genuine.
+  fn decrypt(&mut self, header: &Header, sealed: &[u8], ad: &[u8]) -> Option<Vec<u8>> {
+    let mut next = self.clone();
+    let key = match next
+      .skipped
+      .iter()
+      .position(|((pk, n), _)| *pk == header.ratchet && *n == header.number)
+    {
+      Some(idx) => next.skipped.remove(idx)?.1,
+      None => {
+        if next.theirs != Some(header.ratchet) {
+          next.skip_to(header.previous)?;
+          next.step(&header.ratchet)?;
+        }
+        next.skip_to(header.number)?;
+        let (chain, key) = kdf_chain(&next.receiving?);
+        next.receiving = Some(chain);
+        next.received += 1;
+        key
+      }
+    };
+    let ad = [ad, &header.encode()].concat();
+    let data = aead::open(sealed, Some(&ad), &aead::Nonce([0; 12]), &aead::Key(key)).ok()?;
+    // anything that decrypts means the other side has the session, so the handshake's done
+    next.handshake = None;
+    *self = next;
+    Some(data)
+  }
+}
+
+/// A message in a session, with the handshake, if the sender's still waiting for a reply.
+struct SessionChunk {
+  from: encrypt::PublicKey,
+  handshake: Option<Handshake>,
+  header: Header,
+  sealed: Vec<u8>,
+}
+
+impl CustomChunk for SessionChunk {
+  const KIND: u16 = 0xFF02;
+
+  /// Encodes the sender's identity key, a byte saying whether there's a handshake, the handshake's keys and start
+  /// time (a big-endian `u64`) if there is, then the message header and the sealed message.
+  fn encode(&self) -> Vec<u8> {
+    let mut b = self.from.as_bytes().to_vec();
+    match &self.handshake {
+      Some(handshake) => {
+        b.push(1);
+        b.extend(handshake.encode());
+      }
+      None => b.push(0),
+    }
+    b.extend(self.header.encode());
+    b.extend_from_slice(&self.sealed);
+    b
+  }
+
+  fn decode(mut from: &[u8]) -> Option<SessionChunk> {
+    let key = |from: &mut &[u8]| encrypt::PublicKey::from_slice(take(from, 32)?);
+    let sender = key(&mut from)?;
+    let handshake = match take(&mut from, 1)?[0] {
+      0 => None,
+      1 => Some(Handshake {
+        ephemeral: key(&mut from)?,
+        ratchet: key(&mut from)?,
+        started: {
+          let mut started = [0; 8];
+          started.copy_from_slice(take(&mut from, 8)?);
+          u64::from_be_bytes(started)
+        },
+      }),
+      _ => return None,
+    };
+    let ratchet = key(&mut from)?;
+    let previous = crate::packet::take_u32(&mut from)? as u32;
+    let number = crate::packet::take_u32(&mut from)? as u32;
+    Some(SessionChunk {
+      from: sender,
+      handshake,
+      header: Header {
+        ratchet,
+        previous,
+        number,
+      },
+      sealed: from.to_vec(),
+    })
+  }
+}
+
+/// Everything shared between a `Sessions` and the chunk handler it registers on its mesher.
+#[derive(Default)]
+struct State {
+  sessions: HashMap<encrypt::PublicKey, Ratchet>,
+  /// Sessions peers started at the same time as this node, which lost out but whose messages are still read.
+  losing: HashMap<encrypt::PublicKey, Ratchet>,
+  /// The handshakes had from each peer, to reject repeats.
+  seen: HashMap<encrypt::PublicKey, Seen>,
+  received: Vec<(encrypt::PublicKey, Vec<u8>)>,
+}
+
+/// The handshakes had from one peer.
+#[derive(Default)]
+struct Seen {
+  /// Their ephemeral keys, newest last.
+  ephemerals: VecDeque<encrypt::PublicKey>,
+  /// The newest one's start time.
+  latest: u64,
+}
+
+impl Seen {
+  /// Whether a handshake hasn't been had before and is newer than every one that has.
+  fn is_new(&self, handshake: &Handshake) -> bool {
+    handshake.started > self.latest && !self.ephemerals.contains(&handshake.ephemeral)
+  }
+
+  fn record(&mut self, handshake: &Handshake) {
+    self.latest = handshake.started;
+    self.ephemerals.push_back(handshake.ephemeral);
+    while self.ephemerals.len() > MAX_SEEN_HANDSHAKES {
+      self.ephemerals.pop_front();
+    }
+  }
+}
+
+/// The additional data each message is bound to: who sent it, who it's for, then the handshake it carries, if any.
+fn associated(from: &encrypt::PublicKey, to: &encrypt::PublicKey, handshake: Option<&Handshake>) -> Vec<u8> {
+  let mut ad = [from.as_bytes(), to.as_bytes()].concat();
+  ad.extend(handshake.map(Handshake::encode).unwrap_or_default());
+  ad
+}
+
+/// Handles a session message from a peer, starting a new session if it carries a new handshake.
+fn accept(state: &mut State, identity: &encrypt::SecretKey, chunk: SessionChunk) -> Option<()> {
+  let own_pk = identity.public_key();
+  let ad = associated(&chunk.from, &own_pk, chunk.handshake.as_ref());
+  let ephemeral = chunk.handshake.map(|h| h.ephemeral);
+  // messages in a session that's already going, which only decrypt once each
+  let ongoing = match state.sessions.get_mut(&chunk.from) {
+    Some(session) if ephemeral.is_none() || session.their_handshake == ephemeral => Some(session),
+    _ => state
+      .losing
+      .get_mut(&chunk.from)
+      .filter(|s| ephemeral.is_some() && s.their_handshake == ephemeral),
+  };
+  let data = match ongoing {
+    Some(session) => session.decrypt(&chunk.header, &chunk.sealed, &ad)?,
+    None => {
+      let handshake = chunk.handshake?;
+      // a replayed handshake is never new, so it can't start its session over or replace a newer one
+      if !state.seen.get(&chunk.from).is_none_or(|s| s.is_new(&handshake)) {
+        return None;
+      }
+      // a handshake only counts once a message sent with it decrypts, so one can't be forged
+      let mut session = Ratchet::respond(identity, &chunk.from, &handshake)?;
+      let data = session.decrypt(&chunk.header, &chunk.sealed, &ad)?;
+      state.seen.entry(chunk.from).or_default().record(&handshake);
+      // when both sides start a session at once, the newer one wins, or the one with the lower key if they're as new,
+      // but the message is still read
+      let loses = match state.sessions.get(&chunk.from).and_then(|s| s.handshake) {
+        Some(own) => {
+          own.started > handshake.started
+            || (own.started == handshake.started && own_pk.as_bytes() < chunk.from.as_bytes())
+        }
+        None => false,
+      };
+      if loses {
+        state.losing.insert(chunk.from, session);
+      } else {
+        state.losing.remove(&chunk.from);
+        state.sessions.insert(chunk.from, session);
+      }
+      data
+    }
+  };
+  state.received.push((chunk.from, data));
+  Some(())
+}
+
+/// All of one node's sessions with its peers.
+///
+/// Creating one registers a handler on the mesher for session chunks, so there should only be one per mesher.
+/// Messages that can't be decrypted, e.g. because they're forged or their session was [closed](#method.close), are
+/// dropped.
+/// If two nodes start sessions with each other at once, only the one started later is kept, or if they were started at
+/// the same time, the one started by the node with the lower identity key, though the messages sent in the other are
+/// still read until its sender switches over.
+/// A handshake from a peer only replaces a session if it's newer than every one had from them before, so a peer whose
+/// clock has gone backwards can't start a new session until it catches up again.
+pub struct Sessions {
+  identity: encrypt::SecretKey,
+  state: Arc<Mutex<State>>,
+}
+
+impl Sessions {
+  /// Sets up sessions through `mesher`, identified by `identity`.
+  ///
+  /// Peers send session messages to the node that `identity` is the key for, so it should be one of the mesher's own.
+  pub fn new(mesher: &mut Mesher, identity: encrypt::SecretKey) -> Sessions {
+    let state = Arc::new(Mutex::new(State::default()));
+    let handler_state = state.clone();
+    let handler_identity = identity.clone_secret();
+    mesher.on_chunk(move |chunk: SessionChunk| {
+      let mut state = handler_state.lock().expect("poisoned lock?");
+      let _ = accept(&mut state, &handler_identity, chunk);
+    });
+    Sessions { identity, state }
+  }
+
+  /// Sends `data` along `route` to the session with its last node, starting one if there isn't one yet.
+  ///
+  /// The route should start at this mesher, like the ones passed to
+  /// [`Packet::via_route`](../struct.Packet.html#method.via_route), and end at the peer, whose key identifies it.
+  pub fn send(&self, mesher: &mut Mesher, route: &Route, data: &[u8]) -> fail::Result<()> {
+    let peer = *route
+      .nodes()
+      .last()
+      .ok_or_else(|| session_fail("Can't send along an empty route"))?
+      .1;
+    let chunk = {
+      let mut state = self.state.lock().expect("poisoned lock?");
+      let session = match state.sessions.entry(peer) {
+        Entry::Occupied(e) => e.into_mut(),
+        Entry::Vacant(e) => e.insert(
+          Ratchet::initiate(&self.identity, &peer)
+            .ok_or_else(|| session_fail("Can't start a session with an invalid key"))?,
+        ),
+      };
+      let from = self.identity.public_key();
+      let (header, sealed) = session
+        .encrypt(data, &associated(&from, &peer, session.handshake.as_ref()))
+        .ok_or_else(|| session_fail("Session has no sending chain"))?;
+      SessionChunk {
+        from,
+        handshake: session.handshake,
+        header,
+        sealed,
+      }
+    };
+    let mut packet = mesher.new_packet(None);
+    packet.via_route(route);
+    packet.add_custom(&chunk, &peer);
+    mesher.launch(packet)
+  }
+
+  /// Whether there's a session with `peer`.
+  pub fn has_session(&self, peer: &encrypt::PublicKey) -> bool {
+    self.state.lock().expect("poisoned lock?").sessions.contains_key(peer)
+  }
+
+  /// Forgets the session with `peer`, returning whether there was one.
+  ///
+  /// The next message sent to them starts a new session, e.g. to recover after a key's been leaked, though a fresh
+  /// handshake from them does that too.
+  pub fn close(&self, peer: &encrypt::PublicKey) -> bool {
+    let mut state = self.state.lock().expect("poisoned lock?");
+    state.losing.remove(peer);
+    state.sessions.remove(peer).is_some()
+  }
+
+  /// Takes every session message received since the last call, with the key of the peer who sent it.
+  pub fn take_received(&self) -> Vec<(encrypt::PublicKey, Vec<u8>)> {
+    std::mem::take(&mut self.state.lock().expect("poisoned lock?").received)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ratchets_handle_lost_and_reordered_messages() {
+    let (alice_pk, alice_sk) = encrypt::gen_keypair();
+    let (bob_pk, bob_sk) = encrypt::gen_keypair();
+    let to_bob = associated(&alice_pk, &bob_pk, None);
+    let to_alice = associated(&bob_pk, &alice_pk, None);
+    let mut alice = Ratchet::initiate(&alice_sk, &bob_pk).expect("Failed to initiate");
+    let handshake = alice.handshake.expect("No handshake");
+    let mut bob = Ratchet::respond(&bob_sk, &alice_pk, &handshake).expect("Failed to respond");
+
+    let first = alice.encrypt(b"one", &to_bob).unwrap();
+    let second = alice.encrypt(b"two", &to_bob).unwrap();
+    let third = alice.encrypt(b"three", &to_bob).unwrap();
+    assert_eq!(Some(b"three".to_vec()), bob.decrypt(&third.0, &third.1, &to_bob));
+    assert_eq!(Some(b"one".to_vec()), bob.decrypt(&first.0, &first.1, &to_bob));
+    // the key's used up, so a replay fails
+    assert_eq!(None, bob.decrypt(&first.0, &first.1, &to_bob));
+    // bound to who it was sent by and to
+    assert_eq!(None, bob.decrypt(&second.0, &second.1, &to_alice));
+
+    let reply = bob.encrypt(b"four", &to_alice).unwrap();
+    assert_eq!(Some(b"four".to_vec()), alice.decrypt(&reply.0, &reply.1, &to_alice));
+    assert!(alice.handshake.is_none());
+    let after = alice.encrypt(b"five", &to_bob).unwrap();
+    assert_ne!(first.0.ratchet, after.0.ratchet);
+    assert_eq!(Some(b"five".to_vec()), bob.decrypt(&after.0, &after.1, &to_bob));
+    // still readable from the skipped keys, after the ratchet's stepped
+    assert_eq!(Some(b"two".to_vec()), bob.decrypt(&second.0, &second.1, &to_bob));
+  }
+
+  /// Sends `data` from `from` to `to` in `session`, as a chunk.
+  fn chunk(session: &mut Ratchet, from: &encrypt::PublicKey, to: &encrypt::PublicKey, data: &[u8]) -> SessionChunk {
+    let (header, sealed) = session
+      .encrypt(data, &associated(from, to, session.handshake.as_ref()))
+      .unwrap();
+    SessionChunk {
+      from: *from,
+      handshake: session.handshake,
+      header,
+      sealed,
+    }
+  }
+
+  /// A copy of `chunk`, as an attacker who saw it would have.
+  fn replay(chunk: &SessionChunk) -> SessionChunk {
+    SessionChunk::decode(&chunk.encode()).expect("Failed to decode")
+  }
+
+  #[test]
+  fn replayed_handshakes_are_rejected() {
+    let (alice_pk, alice_sk) = encrypt::gen_keypair();
+    let (bob_pk, bob_sk) = encrypt::gen_keypair();
+    let mut bob = State::default();
+
+    let mut first = Ratchet::initiate(&alice_sk, &bob_pk).expect("Failed to initiate");
+    let hello = chunk(&mut first, &alice_pk, &bob_pk, b"hello");
+    let copy = replay(&hello);
+    assert_eq!(Some(()), accept(&mut bob, &bob_sk, hello));
+    assert_eq!(None, accept(&mut bob, &bob_sk, copy));
+
+    // alice starts over, e.g. after closing the session
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    let mut second = Ratchet::initiate(&alice_sk, &bob_pk).expect("Failed to initiate");
+    let again = chunk(&mut second, &alice_pk, &bob_pk, b"hello again");
+    let current = again.handshake.unwrap().ephemeral;
+    assert_eq!(Some(()), accept(&mut bob, &bob_sk, again));
+    assert_eq!(Some(current), bob.sessions[&alice_pk].their_handshake);
+
+    // the old handshake, even with a message that was never read, can't take the session back
+    let unread = chunk(&mut first, &alice_pk, &bob_pk, b"unread");
+    assert_eq!(None, accept(&mut bob, &bob_sk, unread));
+    assert_eq!(Some(current), bob.sessions[&alice_pk].their_handshake);
+
+    let later = chunk(&mut second, &alice_pk, &bob_pk, b"still here");
+    assert_eq!(Some(()), accept(&mut bob, &bob_sk, later));
+    let received: Vec<_> = bob.received.into_iter().map(|(_, data)| data).collect();
+    assert_eq!(
+      vec![b"hello".to_vec(), b"hello again".to_vec(), b"still here".to_vec()],
+      received
+    );
+  }
+}
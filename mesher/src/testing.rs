@@ -0,0 +1,984 @@
+//! Contains a harness for testing how meshers behave together, on a simulated network.
+//!
+//! A [`Simulation`](struct.Simulation.html) runs a handful of meshers in-process, connected by a simulated transport
+//! which can lose, delay, and reorder packets according to some [`Conditions`](struct.Conditions.html).
+//! Time only passes when [`step`](struct.Simulation.html#method.step) is called, so tests don't need to sleep.
+//!
+//! ```
+//! use mesher::testing::{Conditions, Simulation};
+//!
+//! let mut sim = Simulation::with_conditions(4, Conditions::new().latency(0, 2).reorder())
+//!   .expect("Failed to set up simulation");
+//! sim.send(&[0, 1, 2, 3], b"hello").expect("Failed to send");
+//! sim.run_until_quiet(100).expect("Failed to run");
+//! assert_eq!(vec![b"hello".to_vec()], sim.take_received(3));
+//! ```
+//!
+//! To unit test code that uses a single mesher instead, give it a [`MockTransport`](struct.MockTransport.html), which
+//! records what's sent, receives whatever the test injects, and fails whenever it's told to.
+//! To see how an application copes with a real network going bad, wrap its transports in a
+//! [`FaultyTransport`](struct.FaultyTransport.html), which delays, drops, duplicates, and reorders what they send.
+
+use crate::{prelude::*, ListenStatus, MessageId, Priority, Route};
+
+use rand::{prelude::*, rngs::StdRng};
+
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+lazy_static! {
+  /// The network of every running simulation, by the scheme its nodes use.
+  static ref NETWORKS: Mutex<HashMap<String, Arc<Mutex<Network>>>> = Mutex::new(HashMap::new());
+  /// What every mock transport has sent and been given to receive, by scheme.
+  static ref MOCKS: Mutex<HashMap<String, Arc<Mutex<MockState>>>> = Mutex::new(HashMap::new());
+  /// The faults every faulty transport injects, by scheme.
+  static ref FAULTS: Mutex<HashMap<String, Arc<Mutex<FaultState>>>> = Mutex::new(HashMap::new());
+}
+
+/// Used to give every simulation its own scheme, so they don't interfere when tests are run in parallel.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// How the simulated network treats packets sent over it.
+///
+/// By default, it's perfect: every packet arrives in the step after it's sent, in the order it was sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conditions {
+  loss: f64,
+  latency: (u32, u32),
+  reorder: bool,
+  seed: Option<u64>,
+}
+
+impl Default for Conditions {
+  fn default() -> Conditions {
+    Conditions {
+      loss: 0.0,
+      latency: (0, 0),
+      reorder: false,
+      seed: None,
+    }
+  }
+}
+
+impl Conditions {
+  /// Creates conditions for a perfect network.
+  pub fn new() -> Conditions {
+    Conditions::default()
+  }
+
+  /// Loses each packet sent with the given probability, from 0 (none) to 1 (all of them).
+  pub fn loss(mut self, probability: f64) -> Conditions {
+    self.loss = probability.clamp(0.0, 1.0);
+    self
+  }
+
+  /// Holds each packet for a random number of extra steps, between `min` and `max` inclusive, before it arrives.
+  ///
+  /// Packets held for different lengths of time can arrive out of order.
+  pub fn latency(mut self, min: u32, max: u32) -> Conditions {
+    self.latency = (min, max.max(min));
+    self
+  }
+
+  /// Shuffles the packets that arrive at a node in the same step, rather than handing them over in the order they were sent.
+  pub fn reorder(mut self) -> Conditions {
+    self.reorder = true;
+    self
+  }
+
+  /// Makes the packets lost, delayed, and reordered the same every run, for the same seed.
+  ///
+  /// Only the network is made repeatable; meshers still pick random IDs, keys, and so on.
+  pub fn seed(mut self, seed: u64) -> Conditions {
+    self.seed = Some(seed);
+    self
+  }
+}
+
+/// A packet on its way to a node.
+struct InFlight {
+  arrives: u64,
+  location: String,
+  blob: Vec<u8>,
+}
+
+/// Everything sent over a simulation's network, shared by all of its nodes' transports.
+struct Network {
+  conditions: Conditions,
+  rng: StdRng,
+  step: u64,
+  in_flight: Vec<InFlight>,
+  lost: usize,
+}
+
+/// The transport a simulation's nodes use to reach each other, which sends packets through its network.
+struct SimTransport {
+  scheme: String,
+  network: Arc<Mutex<Network>>,
+  listening: Vec<Path>,
+}
+
+impl Transport for SimTransport {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    let networks = NETWORKS.lock().expect("poisoned lock?");
+    match networks.get(scheme) {
+      Some(network) => Ok(SimTransport {
+        scheme: scheme.to_owned(),
+        network: network.clone(),
+        listening: vec![],
+      }),
+      None => Err(fail::MesherFail::SetupFailure(format!(
+        "no simulation uses the scheme {}",
+        scheme
+      ))),
+    }
+  }
+
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "Simulation"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    let mut network = self.network.lock().expect("poisoned lock?");
+    let network = &mut *network;
+    if network.rng.gen_bool(network.conditions.loss) {
+      network.lost += 1;
+      return Ok(());
+    }
+    let (min, max) = network.conditions.latency;
+    let delay = network.rng.gen_range(min, max + 1);
+    network.in_flight.push(InFlight {
+      arrives: network.step + 1 + delay as u64,
+      location: path.location().to_owned(),
+      blob: blob.to_vec(),
+    });
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    self.listening.push(path.clone());
+    Ok(())
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    let before = self.listening.len();
+    self.listening.retain(|p| p.location() != path.location());
+    if self.listening.len() == before {
+      return Err(fail::MesherFail::NotListening(path.to_string()));
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let mut network = self.network.lock().expect("poisoned lock?");
+    let network = &mut *network;
+    let step = network.step;
+    let listening = |location: &str| self.listening.iter().find(|l| l.location() == location);
+    let (mut arrived, in_flight): (Vec<_>, Vec<_>) = network
+      .in_flight
+      .drain(..)
+      .partition(|p| p.arrives <= step && listening(&p.location).is_some());
+    network.in_flight = in_flight;
+    // stable, so packets which arrive together stay in the order they were sent
+    arrived.sort_by_key(|p| p.arrives);
+    if network.conditions.reorder {
+      arrived.shuffle(&mut network.rng);
+    }
+    let source = |location: &str| {
+      listening(location)
+        .cloned()
+        .map(Source::listening_on)
+        .unwrap_or_default()
+    };
+    Ok(arrived.into_iter().map(|p| (source(&p.location), p.blob)).collect())
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self
+      .listening
+      .iter()
+      .map(|p| (p.clone(), ListenStatus::Listening))
+      .collect()
+  }
+}
+
+/// Everything shared by the mock transports for one scheme and their [`MockControl`](struct.MockControl.html)s.
+#[derive(Default)]
+struct MockState {
+  inbox: Vec<(Source, Vec<u8>)>,
+  sent: Vec<(Path, Vec<u8>)>,
+  listening: Vec<Path>,
+  failing_sends: usize,
+  failing_receives: usize,
+}
+
+fn mock_state(scheme: &str) -> Arc<Mutex<MockState>> {
+  MOCKS
+    .lock()
+    .expect("poisoned lock?")
+    .entry(scheme.to_owned())
+    .or_default()
+    .clone()
+}
+
+/// A transport that does nothing on its own, for unit testing code that uses a mesher without writing a fake
+/// transport.
+///
+/// Add it to a mesher like any other transport, then use the [`MockControl`](struct.MockControl.html) for its scheme to
+/// see what's been sent, hand it packets to receive, and make sending or receiving fail.
+/// Every mock transport for the same scheme shares a control, even across meshers and tests, so, as with
+/// [`InMemory`](../debug_transports/struct.InMemory.html), use a different scheme in each test.
+///
+/// ```
+/// # use mesher::prelude::*;
+/// use mesher::testing::MockTransport;
+///
+/// let (pk, sk) = encrypt::gen_keypair();
+/// let mut mesher = Mesher::unsigned(vec![sk]);
+/// mesher.add_transport::<MockTransport>("mock-doc").expect("Failed to add transport");
+/// let mock = MockTransport::control("mock-doc");
+///
+/// let mut packet = Packet::unsigned();
+/// packet.add_hop("mock-doc:relay".to_owned(), &pk);
+/// mesher.launch(packet).expect("Failed to launch");
+/// assert_eq!("mock-doc:relay", mock.sent()[0].0.to_string());
+///
+/// mock.fail_sends(1);
+/// let mut packet = Packet::unsigned();
+/// packet.add_hop("mock-doc:relay".to_owned(), &pk);
+/// assert!(matches!(mesher.launch(packet), Err(fail::MesherFail::SendFailure(_))));
+/// ```
+pub struct MockTransport {
+  scheme: String,
+  state: Arc<Mutex<MockState>>,
+}
+
+impl MockTransport {
+  /// Gets the control for the mock transports with the given scheme, whether or not any have been created yet.
+  pub fn control(scheme: &str) -> MockControl {
+    MockControl {
+      state: mock_state(scheme),
+    }
+  }
+}
+
+impl Transport for MockTransport {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(MockTransport {
+      scheme: scheme.to_owned(),
+      state: mock_state(scheme),
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  fn name(&self) -> &str {
+    "Mock"
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    let mut state = self.state.lock().expect("poisoned lock?");
+    if state.failing_sends > 0 {
+      state.failing_sends -= 1;
+      return Err(fail::MesherFail::SendFailure(format!(
+        "injected failure sending to {}",
+        path
+      )));
+    }
+    state.sent.push((path.clone(), blob.to_vec()));
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    self.state.lock().expect("poisoned lock?").listening.push(path.clone());
+    Ok(())
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    let mut state = self.state.lock().expect("poisoned lock?");
+    let before = state.listening.len();
+    state.listening.retain(|p| p != path);
+    if state.listening.len() == before {
+      return Err(fail::MesherFail::NotListening(path.to_string()));
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let mut state = self.state.lock().expect("poisoned lock?");
+    if state.failing_receives > 0 {
+      state.failing_receives -= 1;
+      return Err(fail::MesherFail::ReceiveFailure("injected failure".to_owned()));
+    }
+    Ok(state.inbox.drain(..).collect())
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let mut state = self.state.lock().expect("poisoned lock?");
+    if state.failing_receives > 0 {
+      state.failing_receives -= 1;
+      return Err(fail::MesherFail::ReceiveFailure("injected failure".to_owned()));
+    }
+    let count = max.min(state.inbox.len());
+    Ok(state.inbox.drain(..count).collect())
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    let state = self.state.lock().expect("poisoned lock?");
+    state
+      .listening
+      .iter()
+      .map(|p| (p.clone(), ListenStatus::Listening))
+      .collect()
+  }
+}
+
+/// Scripts the [`MockTransport`](struct.MockTransport.html)s for one scheme, from
+/// [`MockTransport::control`](struct.MockTransport.html#method.control).
+///
+/// Controls can be cloned, and moved to other threads, e.g. to inject packets while a mesher's running.
+#[derive(Clone)]
+pub struct MockControl {
+  state: Arc<Mutex<MockState>>,
+}
+
+impl MockControl {
+  fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+    self.state.lock().expect("poisoned lock?")
+  }
+
+  /// Hands a packet to the next [`receive`](../trait.Transport.html#tymethod.receive), as if it had arrived from
+  /// somewhere unknown.
+  ///
+  /// It's received whether or not the transport's listening on anything.
+  pub fn inject(&self, blob: Vec<u8>) {
+    self.inject_from(Source::unknown(), blob);
+  }
+
+  /// Hands a packet to the next `receive`, as if it had arrived from the given source, e.g. to test
+  /// [forward policies](../struct.ForwardPolicy.html#method.deny_from).
+  pub fn inject_from(&self, source: Source, blob: Vec<u8>) {
+    self.state().inbox.push((source, blob));
+  }
+
+  /// Every packet sent so far, and the path it was sent along, in the order they were sent.
+  ///
+  /// Sends that were made to fail aren't included.
+  pub fn sent(&self) -> Vec<(Path, Vec<u8>)> {
+    self.state().sent.clone()
+  }
+
+  /// Takes every packet sent so far, like [`sent`](#method.sent), so the next call only sees newer ones.
+  pub fn take_sent(&self) -> Vec<(Path, Vec<u8>)> {
+    self.state().sent.drain(..).collect()
+  }
+
+  /// The paths the transports are listening on.
+  pub fn listening(&self) -> Vec<Path> {
+    self.state().listening.clone()
+  }
+
+  /// Makes the next `count` sends fail with [`MesherFail::SendFailure`](../fail/enum.MesherFail.html#variant.SendFailure).
+  pub fn fail_sends(&self, count: usize) {
+    self.state().failing_sends = count;
+  }
+
+  /// Makes the next `count` receives fail with
+  /// [`MesherFail::ReceiveFailure`](../fail/enum.MesherFail.html#variant.ReceiveFailure).
+  pub fn fail_receives(&self, count: usize) {
+    self.state().failing_receives = count;
+  }
+}
+
+/// What a [`FaultyTransport`](struct.FaultyTransport.html) does to the packets sent through it.
+///
+/// By default, nothing: every packet's sent right away, once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Faults {
+  drop: f64,
+  duplicate: f64,
+  delay: (Duration, Duration),
+  reorder: bool,
+  seed: Option<u64>,
+}
+
+impl Default for Faults {
+  fn default() -> Faults {
+    Faults {
+      drop: 0.0,
+      duplicate: 0.0,
+      delay: (Duration::ZERO, Duration::ZERO),
+      reorder: false,
+      seed: None,
+    }
+  }
+}
+
+impl Faults {
+  /// Creates faults that leave packets alone.
+  pub fn new() -> Faults {
+    Faults::default()
+  }
+
+  /// Drops each packet with the given probability, from 0 (none) to 1 (all of them).
+  pub fn drop(mut self, probability: f64) -> Faults {
+    self.drop = probability.clamp(0.0, 1.0);
+    self
+  }
+
+  /// Sends each packet that isn't dropped twice with the given probability, from 0 to 1.
+  pub fn duplicate(mut self, probability: f64) -> Faults {
+    self.duplicate = probability.clamp(0.0, 1.0);
+    self
+  }
+
+  /// Holds each copy of a packet for a random time between `min` and `max` before it's sent.
+  ///
+  /// Packets held for different times can be sent out of order.
+  pub fn delay(mut self, min: Duration, max: Duration) -> Faults {
+    self.delay = (min, max.max(min));
+    self
+  }
+
+  /// Holds every packet until the transport's next [`receive`](../trait.Transport.html#tymethod.receive), which the
+  /// mesher calls on every [`Mesher::receive`](../struct.Mesher.html#method.receive), then sends them in a random
+  /// order.
+  pub fn reorder(mut self) -> Faults {
+    self.reorder = true;
+    self
+  }
+
+  /// Makes the packets dropped, duplicated, delayed, and reordered the same every run, for the same seed and the same
+  /// packets sent at the same times.
+  pub fn seed(mut self, seed: u64) -> Faults {
+    self.seed = Some(seed);
+    self
+  }
+}
+
+/// The faults for one scheme, and the randomness they're injected with.
+struct FaultState {
+  faults: Faults,
+  rng: StdRng,
+}
+
+impl FaultState {
+  fn new(faults: Faults) -> FaultState {
+    let rng = match faults.seed {
+      Some(seed) => StdRng::seed_from_u64(seed),
+      None => StdRng::from_entropy(),
+    };
+    FaultState { faults, rng }
+  }
+}
+
+fn fault_state(scheme: &str) -> Arc<Mutex<FaultState>> {
+  FAULTS
+    .lock()
+    .expect("poisoned lock?")
+    .entry(scheme.to_owned())
+    .or_insert_with(|| Arc::new(Mutex::new(FaultState::new(Faults::new()))))
+    .clone()
+}
+
+/// Sets the faults the [`FaultyTransport`](struct.FaultyTransport.html)s for a scheme inject.
+///
+/// It can be called before or after the transports are created, and changes take effect on the next packet sent, e.g.
+/// to have a link go bad partway through a test.
+pub fn set_faults(scheme: &str, faults: Faults) {
+  *fault_state(scheme).lock().expect("poisoned lock?") = FaultState::new(faults);
+}
+
+/// A packet a [`FaultyTransport`](struct.FaultyTransport.html) is holding back.
+struct Held {
+  due: Instant,
+  path: Path,
+  blob: Vec<u8>,
+  priority: Priority,
+}
+
+/// Wraps any transport, and delays, drops, duplicates, and reorders the packets sent through it, for chaos testing
+/// applications and relay policies.
+///
+/// Add it to a mesher in place of the transport it wraps, and set what it does with [`set_faults`](fn.set_faults.html):
+///
+/// ```
+/// # use mesher::prelude::*;
+/// use mesher::{
+///   debug_transports::InMemory,
+///   testing::{set_faults, Faults, FaultyTransport},
+/// };
+/// use std::time::Duration;
+///
+/// set_faults("flaky", Faults::new().drop(0.1).delay(Duration::from_millis(5), Duration::from_millis(50)));
+/// let (_, sk) = encrypt::gen_keypair();
+/// let mut mesher = Mesher::unsigned(vec![sk]);
+/// mesher.add_transport::<FaultyTransport<InMemory>>("flaky").expect("Failed to add transport");
+/// ```
+///
+/// Only sending is affected; to interfere with the packets a node receives, wrap the transports of the nodes sending
+/// them.
+/// Held packets are sent the next time the transport's used after they're due, whether to send or receive, and are
+/// lost if it's dropped first.
+/// Since the mesher's already been told they were sent, failures sending them are ignored, like a packet lost on the
+/// network.
+pub struct FaultyTransport<T: Transport> {
+  inner: T,
+  state: Arc<Mutex<FaultState>>,
+  held: Vec<Held>,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+  /// Sends the held packets that are due, or all of them, in random order if the faults say to reorder them.
+  fn flush(&mut self, all: bool) {
+    let now = Instant::now();
+    let (mut due, held) = self.held.drain(..).partition::<Vec<_>, _>(|h| all || h.due <= now);
+    self.held = held;
+    due.sort_by_key(|h| h.due);
+    let mut state = self.state.lock().expect("poisoned lock?");
+    if state.faults.reorder {
+      due.shuffle(&mut state.rng);
+    }
+    drop(state);
+    for h in due {
+      let _ = self.inner.send_with_priority(&h.path, &h.blob, h.priority);
+    }
+  }
+}
+
+impl<T: Transport> Transport for FaultyTransport<T> {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(FaultyTransport {
+      inner: T::new(scheme)?,
+      state: fault_state(scheme),
+      held: vec![],
+    })
+  }
+
+  fn scheme(&self) -> &str {
+    self.inner.scheme()
+  }
+
+  fn supported_schemes() -> Option<&'static [&'static str]> {
+    T::supported_schemes()
+  }
+
+  fn name(&self) -> &str {
+    self.inner.name()
+  }
+
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()> {
+    self.send_with_priority(path, blob, Priority::Normal)
+  }
+
+  fn send_with_priority(&mut self, path: &Path, blob: &[u8], priority: Priority) -> fail::Result<()> {
+    let reorder = {
+      let mut state = self.state.lock().expect("poisoned lock?");
+      let state = &mut *state;
+      if state.rng.gen_bool(state.faults.drop) {
+        return Ok(());
+      }
+      let copies = if state.rng.gen_bool(state.faults.duplicate) {
+        2
+      } else {
+        1
+      };
+      let (min, max) = state.faults.delay;
+      let now = Instant::now();
+      for _ in 0..copies {
+        let delay = if min == max {
+          min
+        } else {
+          Duration::from_secs_f64(state.rng.gen_range(min.as_secs_f64(), max.as_secs_f64()))
+        };
+        self.held.push(Held {
+          due: now + delay,
+          path: path.clone(),
+          blob: blob.to_vec(),
+          priority,
+        });
+      }
+      state.faults.reorder
+    };
+    if !reorder {
+      self.flush(false);
+    }
+    Ok(())
+  }
+
+  fn listen(&mut self, path: &Path) -> fail::Result<()> {
+    self.inner.listen(path)
+  }
+
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    self.inner.unlisten(path)
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let reorder = self.state.lock().expect("poisoned lock?").faults.reorder;
+    self.flush(reorder);
+    self.inner.receive()
+  }
+
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let reorder = self.state.lock().expect("poisoned lock?").faults.reorder;
+    self.flush(reorder);
+    self.inner.receive_up_to(max)
+  }
+
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    self.inner.status()
+  }
+
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    self.inner.set_max_packet_size(max)
+  }
+
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    self.inner.set_max_buffered(max)
+  }
+
+  /// Sends everything held, whether it's due or not, then closes the inner transport.
+  fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    self.flush(true);
+    self.inner.close(timeout)
+  }
+}
+
+/// One of the meshers in a simulation, along with what it's received so far.
+struct Node {
+  mesher: Mesher,
+  pkey: encrypt::PublicKey,
+  path: Path,
+  received: Vec<Message>,
+}
+
+/// A set of meshers in the same process, connected by a simulated network.
+///
+/// Each node is an unsigned mesher with its own keypair, listening on its own path.
+/// Nodes are referred to by their index, from 0 up to (but not including) [`len`](#method.len).
+/// Their meshers can be reached with [`mesher`](#method.mesher), e.g. to set policies on them.
+///
+/// Nothing happens on its own: each call to [`step`](#method.step) moves time forward by one step, delivers the packets
+/// due then, and has every node process what it's received, in order of their indices.
+/// Every hop a packet takes uses up at least one step, plus whatever latency the [`Conditions`](struct.Conditions.html)
+/// add.
+pub struct Simulation {
+  scheme: String,
+  network: Arc<Mutex<Network>>,
+  nodes: Vec<Node>,
+}
+
+impl Simulation {
+  /// Sets up `nodes` meshers on a perfect network.
+  pub fn new(nodes: usize) -> fail::Result<Simulation> {
+    Simulation::with_conditions(nodes, Conditions::new())
+  }
+
+  /// Sets up `nodes` meshers on a network with the given conditions.
+  pub fn with_conditions(nodes: usize, conditions: Conditions) -> fail::Result<Simulation> {
+    let scheme = format!("sim{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let rng = match conditions.seed {
+      Some(seed) => StdRng::seed_from_u64(seed),
+      None => StdRng::from_entropy(),
+    };
+    let network = Arc::new(Mutex::new(Network {
+      conditions,
+      rng,
+      step: 0,
+      in_flight: vec![],
+      lost: 0,
+    }));
+    NETWORKS
+      .lock()
+      .expect("poisoned lock?")
+      .insert(scheme.clone(), network.clone());
+
+    // made before the nodes, so the network is cleaned up even if one of them fails to build
+    let mut sim = Simulation {
+      scheme,
+      network,
+      nodes: vec![],
+    };
+    for i in 0..nodes {
+      let (pkey, skey) = encrypt::gen_keypair();
+      let path = format!("{}:{}", sim.scheme, i);
+      let mesher = Mesher::builder()
+        .own_key(skey)
+        .transport::<SimTransport>(&sim.scheme)
+        .listen_on(&path)
+        .build()?;
+      sim.nodes.push(Node {
+        mesher,
+        pkey,
+        path: Path::parse(&path)?,
+        received: vec![],
+      });
+    }
+    Ok(sim)
+  }
+
+  /// How many nodes are in the simulation.
+  pub fn len(&self) -> usize {
+    self.nodes.len()
+  }
+
+  /// Whether the simulation has no nodes at all.
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  /// The given node's mesher.
+  ///
+  /// Panics if there's no such node, like indexing into a `Vec`.
+  pub fn mesher(&mut self, node: usize) -> &mut Mesher {
+    &mut self.nodes[node].mesher
+  }
+
+  /// The given node's public key.
+  pub fn pkey(&self, node: usize) -> &encrypt::PublicKey {
+    &self.nodes[node].pkey
+  }
+
+  /// The path the given node listens on.
+  pub fn path(&self, node: usize) -> &Path {
+    &self.nodes[node].path
+  }
+
+  /// The route through the given nodes, in order.
+  pub fn route(&self, nodes: &[usize]) -> Route {
+    nodes.iter().fold(Route::new(), |route, &i| {
+      route.then(self.nodes[i].path.clone(), self.nodes[i].pkey)
+    })
+  }
+
+  /// Sends a message through the given nodes, in order, to the last one.
+  ///
+  /// The first node launches the packet, so it's sent along the rest of the route as soon as this is called.
+  /// Returns the ID of the message sent.
+  pub fn send(&mut self, nodes: &[usize], data: &[u8]) -> fail::Result<MessageId> {
+    let (first, last) = match (nodes.first(), nodes.last()) {
+      (Some(&first), Some(&last)) => (first, last),
+      _ => return Err(fail::MesherFail::NoRoute("no nodes to send through".to_owned())),
+    };
+    let mut packet = Packet::unsigned();
+    packet.via_route(&self.route(nodes));
+    let id = packet.add_message(data, &self.nodes[last].pkey);
+    self.nodes[first].mesher.launch(packet)?;
+    Ok(id)
+  }
+
+  /// Moves time forward one step, delivering every packet due and having every node process them.
+  ///
+  /// Messages received are kept until they're [taken](#method.take_received).
+  /// Fails with the first error any node's [`receive`](../struct.Mesher.html#method.receive) returns.
+  pub fn step(&mut self) -> fail::Result<()> {
+    self.network.lock().expect("poisoned lock?").step += 1;
+    for node in &mut self.nodes {
+      let messages = node.mesher.receive()?;
+      node.received.extend(messages);
+    }
+    Ok(())
+  }
+
+  /// Runs the given number of steps.
+  pub fn run(&mut self, steps: usize) -> fail::Result<()> {
+    for _ in 0..steps {
+      self.step()?;
+    }
+    Ok(())
+  }
+
+  /// Runs until there are no packets left in flight, or `max_steps` steps have gone by, whichever comes first.
+  ///
+  /// Returns whether the network went quiet.
+  /// Packets held by the meshers themselves, e.g. in a [mix pool](../struct.MixPolicy.html), don't count as in flight.
+  pub fn run_until_quiet(&mut self, max_steps: usize) -> fail::Result<bool> {
+    for _ in 0..max_steps {
+      if self.in_flight() == 0 {
+        return Ok(true);
+      }
+      self.step()?;
+    }
+    Ok(self.in_flight() == 0)
+  }
+
+  /// How many packets are on their way somewhere, including ones sent to paths nobody's listening on.
+  pub fn in_flight(&self) -> usize {
+    self.network.lock().expect("poisoned lock?").in_flight.len()
+  }
+
+  /// How many packets the network has lost so far.
+  pub fn lost(&self) -> usize {
+    self.network.lock().expect("poisoned lock?").lost
+  }
+
+  /// The messages the given node has received so far.
+  pub fn received(&self, node: usize) -> &[Message] {
+    &self.nodes[node].received
+  }
+
+  /// Takes the contents of the messages the given node has received so far, in the order they were received.
+  pub fn take_received(&mut self, node: usize) -> Vec<Vec<u8>> {
+    self.nodes[node]
+      .received
+      .drain(..)
+      .map(Message::into_contents)
+      .collect()
+  }
+}
+
+impl Drop for Simulation {
+  fn drop(&mut self) {
+    if let Ok(mut networks) = NETWORKS.lock() {
+      networks.remove(&self.scheme);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn perfect_network_delivers_in_one_step_per_hop() {
+    let mut sim = Simulation::new(3).expect("Failed to set up");
+    sim.send(&[0, 1, 2], &[1]).expect("Failed to send");
+    sim.step().expect("Failed to step");
+    assert!(sim.received(2).is_empty());
+    sim.step().expect("Failed to step");
+    assert_eq!(vec![vec![1]], sim.take_received(2));
+    assert_eq!(0, sim.in_flight());
+  }
+
+  #[test]
+  fn total_loss_delivers_nothing() {
+    let mut sim = Simulation::with_conditions(2, Conditions::new().loss(1.0)).expect("Failed to set up");
+    sim.send(&[0, 1], &[1]).expect("Failed to send");
+    assert!(sim.run_until_quiet(10).expect("Failed to run"));
+    assert!(sim.received(1).is_empty());
+    assert_eq!(1, sim.lost());
+  }
+
+  #[test]
+  fn latency_holds_packets() {
+    let mut sim = Simulation::with_conditions(2, Conditions::new().latency(3, 3)).expect("Failed to set up");
+    sim.send(&[0, 1], &[1]).expect("Failed to send");
+    sim.run(3).expect("Failed to run");
+    assert!(sim.received(1).is_empty());
+    sim.step().expect("Failed to step");
+    assert_eq!(vec![vec![1]], sim.take_received(1));
+  }
+
+  #[test]
+  fn seeded_conditions_repeat() {
+    let lost = |seed| {
+      let mut sim = Simulation::with_conditions(2, Conditions::new().loss(0.5).seed(seed)).expect("Failed to set up");
+      for i in 0..32 {
+        sim.send(&[0, 1], &[i]).expect("Failed to send");
+      }
+      sim.lost()
+    };
+    assert_eq!(lost(7), lost(7));
+  }
+
+  #[test]
+  fn mock_transports_are_scriptable() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut mesher = Mesher::unsigned(vec![sk]);
+    mesher
+      .add_transport::<MockTransport>("mock-script")
+      .expect("Failed to add transport");
+    mesher.listen_on("mock-script:here").expect("Failed to listen");
+    let failures = Arc::new(Mutex::new(vec![]));
+    let handler_failures = failures.clone();
+    mesher.on_failure(move |f| handler_failures.lock().unwrap().push(f));
+    let mock = MockTransport::control("mock-script");
+    assert_eq!(vec![Path::parse("mock-script:here").unwrap()], mock.listening());
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("mock-script:there".to_owned(), &pk);
+    packet.add_message(&[1], &pk);
+    mesher.launch(packet).expect("Failed to launch");
+    let sent = mock.take_sent();
+    assert_eq!(1, sent.len());
+    assert!(mock.sent().is_empty());
+
+    // the packet comes back, but the first receive fails
+    mock.inject(sent[0].1.clone());
+    mock.fail_receives(1);
+    assert!(mesher.receive().expect("Failed to receive").is_empty());
+    assert!(matches!(
+      failures.lock().unwrap()[..],
+      [fail::MesherFail::ReceiveFailure(_)]
+    ));
+    let received = mesher.receive().expect("Failed to receive");
+    assert_eq!(
+      vec![vec![1]],
+      received.into_iter().map(Message::into_contents).collect::<Vec<_>>()
+    );
+  }
+
+  #[test]
+  fn faulty_transports_inject_faults() {
+    let mut faulty = FaultyTransport::<MockTransport>::new("faulty-inject").expect("Failed to create");
+    let mock = MockTransport::control("faulty-inject");
+    let path = Path::parse("faulty-inject:there").unwrap();
+    let sent = || {
+      mock
+        .take_sent()
+        .into_iter()
+        .map(|(_, blob)| blob[0])
+        .collect::<Vec<_>>()
+    };
+
+    faulty.send(&path, &[0]).expect("Failed to send");
+    assert_eq!(vec![0], sent());
+
+    set_faults("faulty-inject", Faults::new().drop(1.0));
+    faulty.send(&path, &[1]).expect("Failed to send");
+    assert!(sent().is_empty());
+
+    set_faults("faulty-inject", Faults::new().duplicate(1.0));
+    faulty.send(&path, &[2]).expect("Failed to send");
+    assert_eq!(vec![2, 2], sent());
+
+    let delay = Duration::from_millis(20);
+    set_faults("faulty-inject", Faults::new().delay(delay, delay));
+    faulty.send(&path, &[3]).expect("Failed to send");
+    faulty.receive().expect("Failed to receive");
+    assert!(sent().is_empty());
+    std::thread::sleep(delay);
+    faulty.receive().expect("Failed to receive");
+    assert_eq!(vec![3], sent());
+
+    set_faults("faulty-inject", Faults::new().reorder().seed(367));
+    for i in 0..16 {
+      faulty.send(&path, &[i]).expect("Failed to send");
+    }
+    assert!(sent().is_empty());
+    faulty.receive().expect("Failed to receive");
+    let mut reordered = sent();
+    assert_ne!((0..16).collect::<Vec<_>>(), reordered);
+    reordered.sort_unstable();
+    assert_eq!((0..16).collect::<Vec<_>>(), reordered);
+  }
+
+  #[test]
+  fn empty_route_fails() {
+    let mut sim = Simulation::new(1).expect("Failed to set up");
+    assert!(matches!(sim.send(&[], &[1]), Err(fail::MesherFail::NoRoute(_))));
+  }
+}
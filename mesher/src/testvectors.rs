@@ -0,0 +1,359 @@
+//! Contains test vectors for the packet format: packets built with known keys, and what's in every chunk.
+//!
+//! They're for checking that two things agree on the wire format, e.g. another implementation of mesher and this one,
+//! or this version of the crate and an older one.
+//! [`vectors`](fn.vectors.html) returns the ones shipped with the crate, which its own tests check every version still
+//! reads the same way; another implementation should open each packet with the vector's keys and get exactly the
+//! chunks listed, in the same order.
+//!
+//! ```
+//! for vector in mesher::testvectors::vectors() {
+//!   vector.check().expect("Packet didn't decode as expected");
+//! }
+//! ```
+//!
+//! Sealed boxes are randomized, so packets can't be regenerated byte-for-byte, which is why the vectors are stored
+//! rather than built in the tests.
+//! When the format gains something new, make more with [`generate`](fn.generate.html) (or the `mesher-testvectors`
+//! binary in `mesher-node`) and add them to the file, keeping the old ones so old packets keep being checked.
+//!
+//! # File format
+//!
+//! The vectors are stored as text, one field per line, so they're easy to read in any language:
+//!
+//! ```text
+//! # comments and blank lines are ignored
+//! vector <name>
+//! key <hex X25519 secret key the receiving node has, repeated for each one>
+//! signer <hex Ed25519 public key signed chunks are signed with, if any are>
+//! packet <hex packet>
+//! chunk sealed              # a chunk none of the keys can open
+//! chunk open <hex>          # an unsigned chunk, and its plaintext, starting with the chunk type
+//! chunk signed <hex>        # a signed chunk, and its plaintext
+//! reply-path <chunk count>  # repeated for each reply path
+//! end
+//! ```
+//!
+//! Chunks are listed in the order they're in on the main path, and plaintext is what's left once the signature, the
+//! suite byte, any key hint, and the sealed box are taken off.
+
+use crate::{
+  alloc_prelude::*,
+  crypto::{parse_key, write_key},
+  identity::{Endorsement, Revocation},
+  packet::Chunk,
+  prelude::*,
+  CustomChunk, MessageId,
+};
+
+use core::fmt;
+
+/// The vectors shipped with the crate.
+const SHIPPED: &str = include_str!("../testvectors/packets.txt");
+
+/// What should be in one chunk of a [`TestVector`](struct.TestVector.html)'s packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedChunk {
+  /// Whether the chunk is signed.
+  pub signed: bool,
+  /// The chunk's plaintext, or `None` if none of the vector's keys can open it.
+  pub contents: Option<Vec<u8>>,
+}
+
+/// A packet, the keys to open it with, and what opening it should find.
+#[derive(Debug)]
+pub struct TestVector {
+  /// A short name for what the vector tests, e.g. `signed`.
+  pub name: String,
+  /// The secret keys the receiving node has.
+  pub keys: Vec<encrypt::SecretKey>,
+  /// The key signed chunks should be signed with, if there are any.
+  pub signer: Option<sign::PublicKey>,
+  /// The packet, as it would be sent.
+  pub packet: Vec<u8>,
+  /// Every chunk in the packet's main path, in order.
+  pub chunks: Vec<ExpectedChunk>,
+  /// How many chunks are in each reply path.
+  pub reply_paths: Vec<usize>,
+}
+
+impl TestVector {
+  fn new(name: &str) -> TestVector {
+    TestVector {
+      name: name.to_owned(),
+      keys: vec![],
+      signer: None,
+      packet: vec![],
+      chunks: vec![],
+      reply_paths: vec![],
+    }
+  }
+
+  /// Checks this crate reads the packet the way the vector says it should, returning a description of the first
+  /// difference if it doesn't.
+  ///
+  /// As well as every chunk having the expected plaintext, every opened chunk has to parse, and every signed chunk's
+  /// signature has to check out against the signer.
+  pub fn check(&self) -> Result<(), String> {
+    let fail = |msg: String| Err(format!("{}: {}", self.name, msg));
+    let crypto = crate::crypto::default_backend();
+    let (opened, reply_paths) = match Packet::open_unchecked(&self.packet, &self.keys) {
+      Ok(opened) => opened,
+      Err(e) => return fail(format!("couldn't parse the packet: {:?}", e)),
+    };
+    if opened.len() != self.chunks.len() {
+      return fail(format!("expected {} chunks, found {}", self.chunks.len(), opened.len()));
+    }
+    for (i, ((raw, signed, contents), expected)) in opened.into_iter().zip(&self.chunks).enumerate() {
+      let found = ExpectedChunk { signed, contents };
+      if found != *expected {
+        return fail(format!("chunk {} should be {}, but is {}", i, expected, found));
+      }
+      if let Some(contents) = found.contents {
+        if Chunk::deserialize(contents, &reply_paths).is_err() {
+          return fail(format!("chunk {} opened, but couldn't be parsed", i));
+        }
+      }
+      if signed && !self.signer.iter().any(|s| crypto.verify(raw, s).is_some()) {
+        return fail(format!("chunk {}'s signature doesn't check out", i));
+      }
+    }
+    let lens: Vec<_> = reply_paths.iter().map(|p| p.len()).collect();
+    if lens != self.reply_paths {
+      return fail(format!(
+        "expected reply paths of {:?} chunks, found {:?}",
+        self.reply_paths, lens
+      ));
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for ExpectedChunk {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match &self.contents {
+      None => write!(f, "sealed"),
+      Some(contents) => write!(
+        f,
+        "{} {}",
+        if self.signed { "signed" } else { "open" },
+        write_key(contents)
+      ),
+    }
+  }
+}
+
+/// Writes the vector in the [file format](index.html#file-format).
+impl fmt::Display for TestVector {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "vector {}", self.name)?;
+    for key in &self.keys {
+      writeln!(f, "key {}", write_key(key.as_bytes()))?;
+    }
+    if let Some(signer) = &self.signer {
+      writeln!(f, "signer {}", write_key(signer.as_bytes()))?;
+    }
+    writeln!(f, "packet {}", write_key(&self.packet))?;
+    for chunk in &self.chunks {
+      writeln!(f, "chunk {}", chunk)?;
+    }
+    for len in &self.reply_paths {
+      writeln!(f, "reply-path {}", len)?;
+    }
+    writeln!(f, "end")
+  }
+}
+
+/// The test vectors shipped with the crate.
+pub fn vectors() -> Vec<TestVector> {
+  from_file(SHIPPED).expect("Shipped test vectors are valid")
+}
+
+/// Reads test vectors in the [file format](index.html#file-format), failing with the line number of the first line
+/// that isn't valid.
+pub fn from_file(text: &str) -> Result<Vec<TestVector>, String> {
+  let mut vectors = vec![];
+  let mut current: Option<TestVector> = None;
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let bad = || format!("line {}: {:?} isn't a valid test vector line", i + 1, line);
+    let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+    match (field, current.as_mut()) {
+      ("vector", None) => current = Some(TestVector::new(value)),
+      ("key", Some(v)) => v.keys.push(
+        parse_key(value)
+          .and_then(|b| encrypt::SecretKey::from_slice(&b))
+          .ok_or_else(bad)?,
+      ),
+      ("signer", Some(v)) => {
+        v.signer = Some(
+          parse_key(value)
+            .and_then(|b| sign::PublicKey::from_slice(&b))
+            .ok_or_else(bad)?,
+        )
+      }
+      ("packet", Some(v)) => v.packet = parse_key(value).ok_or_else(bad)?,
+      ("chunk", Some(v)) => {
+        let chunk = match value.split_once(' ') {
+          None if value == "sealed" => ExpectedChunk {
+            signed: false,
+            contents: None,
+          },
+          Some((kind @ ("open" | "signed"), hex)) => ExpectedChunk {
+            signed: kind == "signed",
+            contents: Some(parse_key(hex).ok_or_else(bad)?),
+          },
+          _ => return Err(bad()),
+        };
+        v.chunks.push(chunk);
+      }
+      ("reply-path", Some(v)) => v.reply_paths.push(value.parse().map_err(|_| bad())?),
+      ("end", Some(_)) => vectors.extend(current.take()),
+      _ => return Err(bad()),
+    }
+  }
+  match current {
+    Some(v) => Err(format!("vector {} has no end line", v.name)),
+    None => Ok(vectors),
+  }
+}
+
+/// Writes test vectors in the [file format](index.html#file-format), with a comment at the top saying where they came
+/// from.
+pub fn to_file(vectors: &[TestVector]) -> String {
+  let mut file = "# mesher packet test vectors; see the docs of mesher::testvectors for the format\n".to_owned();
+  for vector in vectors {
+    file.push('\n');
+    file.push_str(&vector.to_string());
+  }
+  file
+}
+
+/// A custom chunk with a fixed kind, so the vectors cover custom chunks without depending on any real layer.
+struct Example(Vec<u8>);
+
+impl CustomChunk for Example {
+  const KIND: u16 = 0x1234;
+
+  fn encode(&self) -> Vec<u8> {
+    self.0.clone()
+  }
+
+  fn decode(bytes: &[u8]) -> Option<Example> {
+    Some(Example(bytes.to_vec()))
+  }
+}
+
+/// Builds a fresh set of test vectors, with new keys, covering every kind of chunk a node can be sent directly.
+///
+/// What's expected in each chunk is worked out by opening the packets with this version of the crate, so they're only
+/// as right as it is; the point is to catch anything reading them differently later.
+pub fn generate() -> Vec<TestVector> {
+  let (node_pk, node_sk) = encrypt::gen_keypair();
+  let (other_pk, _) = encrypt::gen_keypair();
+  let (signer_pk, signer_sk) = sign::gen_keypair();
+  let (_, root_sk) = sign::gen_keypair();
+  let (revoked_pk, _) = sign::gen_keypair();
+  let hop = "tcp:[::1]:18540".to_owned();
+
+  let mut message = Packet::unsigned();
+  message.add_hop(hop.clone(), &node_pk);
+  message.add_message_with_id(b"hello, mesher", &node_pk, MessageId(0x0123_4567_89ab_cdef));
+  message.add_message(b"not for you", &other_pk);
+
+  let mut signed = Packet::signed(signer_sk.clone_secret());
+  signed.add_hop(hop.clone(), &node_pk);
+  signed.add_message(b"hello, mesher", &node_pk);
+  signed.add_custom(&Example(vec![1, 2, 3]), &node_pk);
+  signed.add_message(b"not for you", &other_pk);
+
+  let mut hinted = Packet::unsigned();
+  hinted.use_key_hints(true);
+  hinted.add_hop(hop.clone(), &node_pk);
+  hinted.add_message(b"hello, mesher", &node_pk);
+  hinted.add_message(b"not for you", &other_pk);
+
+  let mut reply = Packet::unsigned();
+  reply.add_hop(hop, &node_pk);
+  let mut path = reply.add_reply_path().expect("Packet has no other reply paths");
+  path.add_hop("tcp:[::1]:18541".to_owned(), &other_pk);
+  path.use_for_message(b"reply to this", &node_pk);
+  path.use_for_receipt(&node_pk, &other_pk);
+
+  let mut ordered = Packet::unsigned();
+  for i in 1..=3 {
+    ordered.add_message_with_id(format!("message {}", i).as_bytes(), &node_pk, MessageId(i));
+  }
+
+  let mut identity = Packet::signed(signer_sk);
+  identity.add_endorsement(&Endorsement::new(&signer_pk, &root_sk), &node_pk);
+  identity.add_revocation(&Revocation::new(&revoked_pk, &root_sk), &node_pk);
+
+  let packets = vec![
+    ("message", message, false),
+    ("signed", signed, true),
+    ("key-hints", hinted, false),
+    ("reply-path", reply, false),
+    ("identity", identity, true),
+    ("ordered", ordered, false),
+  ];
+  packets
+    .into_iter()
+    .map(|(name, packet, is_signed)| {
+      let mut vector = TestVector::new(name);
+      vector.keys.push(node_sk.clone_secret());
+      vector.signer = Some(signer_pk).filter(|_| is_signed);
+      vector.packet = packet.into_bytes().expect("Test vector packets are small");
+      let (opened, reply_paths) = Packet::open_unchecked(&vector.packet, &vector.keys).expect("Packet was just built");
+      vector.chunks = opened
+        .into_iter()
+        .map(|(_, signed, contents)| ExpectedChunk { signed, contents })
+        .collect();
+      vector.reply_paths = reply_paths.iter().map(|p| p.len()).collect();
+      vector
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn shipped_vectors_pass() {
+    let vectors = vectors();
+    assert!(!vectors.is_empty());
+    for vector in vectors {
+      vector.check().expect("Shipped vector failed");
+    }
+  }
+
+  #[test]
+  fn generated_vectors_round_trip() {
+    let file = to_file(&generate());
+    let vectors = from_file(&file).expect("Generated file isn't valid");
+    assert_eq!(6, vectors.len());
+    for vector in &vectors {
+      vector.check().expect("Generated vector failed");
+    }
+    assert_eq!(file, to_file(&vectors));
+  }
+
+  #[test]
+  fn changes_are_caught() {
+    let mut vector = vectors().remove(0);
+    let opened = vector.chunks.iter_mut().find_map(|c| c.contents.as_mut()).unwrap();
+    opened[0] ^= 1;
+    assert!(vector.check().is_err());
+    vector.packet[0] = 0xFF;
+    assert!(vector.check().unwrap_err().contains("UnsupportedVersion"));
+    assert!(from_file("vector unfinished\n").is_err());
+    assert_eq!(
+      Err("line 2: \"chunk ajar\" isn't a valid test vector line".to_owned()),
+      from_file("vector bad\nchunk ajar\nend\n").map(|v| v.len())
+    );
+  }
+}
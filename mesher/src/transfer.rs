@@ -0,0 +1,468 @@
+//! Contains helpers for sending files, or anything else too big for one packet, across the mesh as a series of messages.
+//!
+//! The sending side wraps anything it can [`Read`](https://doc.rust-lang.org/std/io/trait.Read.html) in an
+//! [`Upload`](struct.Upload.html), which splits it into numbered chunks and launches each one as a message along a
+//! [`Route`](../struct.Route.html).
+//! The receiving side hands every message it receives to its [`Downloads`](struct.Downloads.html), which picks out the
+//! chunks, puts them back in order, and writes each transfer to its own sink, reporting progress as it goes.
+//! Other messages are left alone, so transfers can share a mesher with everything else.
+//!
+//! Chunks are ordinary messages, so they can be lost like any other packet.
+//! The receiver can tell which chunks it's [missing](struct.Downloads.html#method.missing), and uploads of anything
+//! seekable, like files, can be [resumed](struct.Upload.html#method.resume_from) from any chunk, even after either side
+//! restarts.
+//!
+//! ```
+//! # use mesher::prelude::*;
+//! use mesher::transfer::{Downloads, Upload};
+//!
+//! let (sender_pk, sender_sk) = encrypt::gen_keypair();
+//! let (receiver_pk, receiver_sk) = encrypt::gen_keypair();
+//! let mut sender = Mesher::unsigned(vec![sender_sk]);
+//! sender.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! let mut receiver = Mesher::unsigned(vec![receiver_sk]);
+//! receiver.add_transport::<mesher::debug_transports::InMemory>("inmem").expect("Failed to add transport");
+//! receiver.listen_on("inmem:transfer-doc").expect("Failed to listen");
+//!
+//! let file = vec![7; 100_000];
+//! let route = mesher::Route::new()
+//!   .then(Path::parse("inmem:transfer-doc-sender").unwrap(), sender_pk)
+//!   .then(Path::parse("inmem:transfer-doc").unwrap(), receiver_pk);
+//! let mut upload = Upload::new(route, &file[..]);
+//! let mut downloads = Downloads::new(|_id| Ok(Vec::new()));
+//! while !upload.progress().is_done() {
+//!   upload.send(&mut sender, 4).expect("Failed to send");
+//!   for message in receiver.receive().expect("Failed to receive") {
+//!     if !downloads.accept(&message).expect("Failed to write") {
+//!       // not part of a transfer, so it's handled like any other message
+//!     }
+//!   }
+//! }
+//! let (id, received) = downloads.take_finished().pop().expect("Transfer didn't finish");
+//! assert_eq!(upload.id(), id);
+//! assert_eq!(file, received);
+//! ```
+
+use crate::{prelude::*, Priority, Route};
+
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  io::{ErrorKind, Read, Seek, SeekFrom, Write},
+};
+
+/// Marks a message as a transfer chunk, rather than something else the application sent.
+const MAGIC: &[u8; 4] = b"MXFR";
+/// The magic, the transfer ID, the chunk's index, and its flags.
+const HEADER_LEN: usize = 4 + 8 + 4 + 1;
+/// Set in a chunk's flags if it's the last one in its transfer.
+const LAST: u8 = 1;
+/// How many bytes of the stream go in each chunk, unless the upload says otherwise.
+const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+/// The most chunks held for each transfer while waiting for the ones before them; any more are dropped.
+const MAX_OUT_OF_ORDER: usize = 256;
+
+fn transfer_fail(what: &str, e: std::io::Error) -> fail::MesherFail {
+  fail::MesherFail::TransferFailure(format!("Failed to {}: {:?}", what, e))
+}
+
+/// Identifies one transfer, so the chunks of several going on at once can be told apart.
+///
+/// To resume an upload after restarting, save its ID and give it to the new one with
+/// [`Upload::with_id`](struct.Upload.html#method.with_id).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransferId(pub u64);
+
+impl TransferId {
+  /// Generates a new, random transfer ID.
+  pub fn random() -> TransferId {
+    TransferId(rand::random())
+  }
+}
+
+/// How far along a transfer is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+  chunks: u32,
+  bytes: u64,
+  done: bool,
+}
+
+impl Progress {
+  /// How many chunks have been sent, or received in order.
+  pub fn chunks(&self) -> u32 {
+    self.chunks
+  }
+
+  /// How many bytes of the stream have been sent, or received in order.
+  pub fn bytes(&self) -> u64 {
+    self.bytes
+  }
+
+  /// Whether the whole stream has been sent, or received.
+  pub fn is_done(&self) -> bool {
+    self.done
+  }
+}
+
+/// Encodes one chunk as a message: the magic, the big-endian transfer ID and chunk index, the flags, then the data.
+fn encode(id: TransferId, index: u32, last: bool, data: &[u8]) -> Vec<u8> {
+  let mut b = Vec::with_capacity(HEADER_LEN + data.len());
+  b.extend_from_slice(MAGIC);
+  b.extend_from_slice(&id.0.to_be_bytes());
+  b.extend_from_slice(&index.to_be_bytes());
+  b.push(if last { LAST } else { 0 });
+  b.extend_from_slice(data);
+  b
+}
+
+/// Decodes a message written by [`encode`](fn.encode.html), or returns `None` if it isn't a chunk.
+fn decode(message: &[u8]) -> Option<(TransferId, u32, bool, &[u8])> {
+  if message.len() < HEADER_LEN || &message[..4] != MAGIC {
+    return None;
+  }
+  let mut id = [0; 8];
+  id.copy_from_slice(&message[4..12]);
+  let mut index = [0; 4];
+  index.copy_from_slice(&message[12..16]);
+  let last = message[16] & LAST != 0;
+  Some((
+    TransferId(u64::from_be_bytes(id)),
+    u32::from_be_bytes(index),
+    last,
+    &message[HEADER_LEN..],
+  ))
+}
+
+/// Sends everything read from a stream to the last node in a route, one chunk per message.
+///
+/// Nothing's sent until [`send`](#method.send) is called, which sends a few chunks at a time, so an application can
+/// keep receiving, and spread big transfers out, in between.
+/// Chunks are launched with [`Priority::Bulk`](../enum.Priority.html#variant.Bulk) by default, so they don't hold up
+/// the mesher's other traffic.
+pub struct Upload<R> {
+  id: TransferId,
+  route: Route,
+  reader: R,
+  chunk_size: usize,
+  priority: Priority,
+  progress: Progress,
+}
+
+impl<R: Read> Upload<R> {
+  /// Prepares to send everything `reader` reads along `route`, to its last node, in chunks of 16 KiB.
+  ///
+  /// Like any other packet [launched](../struct.Mesher.html#method.launch), the route should start with the sending
+  /// mesher itself.
+  pub fn new(route: Route, reader: R) -> Upload<R> {
+    Upload {
+      id: TransferId::random(),
+      route,
+      reader,
+      chunk_size: DEFAULT_CHUNK_SIZE,
+      priority: Priority::Bulk,
+      progress: Progress::default(),
+    }
+  }
+
+  /// Sends `bytes` bytes of the stream in each chunk, or 1 if it's 0.
+  ///
+  /// Each chunk's message is a few bytes bigger than this, and the packet carrying it bigger still, so it should be
+  /// well under the smallest [packet size limit](../struct.Mesher.html#method.set_max_packet_size) along the route.
+  /// Resuming an upload only works if the chunk size is the same as it was the first time.
+  pub fn chunk_size(mut self, bytes: usize) -> Upload<R> {
+    self.chunk_size = bytes.max(1);
+    self
+  }
+
+  /// Launches the chunks with the given priority, rather than `Bulk`.
+  pub fn priority(mut self, priority: Priority) -> Upload<R> {
+    self.priority = priority;
+    self
+  }
+
+  /// Uses the given ID rather than a random one, e.g. to carry on an upload that was interrupted.
+  pub fn with_id(mut self, id: TransferId) -> Upload<R> {
+    self.id = id;
+    self
+  }
+
+  /// The transfer's ID, which the receiver sees its chunks under.
+  pub fn id(&self) -> TransferId {
+    self.id
+  }
+
+  /// How many chunks, and bytes, have been launched so far.
+  pub fn progress(&self) -> Progress {
+    self.progress
+  }
+
+  /// Reads and launches up to `chunks` more chunks through the mesher, stopping early once the stream's run out.
+  ///
+  /// If reading or launching a chunk fails, the error's returned, and the chunk isn't counted in the progress.
+  /// Uploads of seekable streams can try it again with [`resume_from`](#method.resume_from) and the progress's
+  /// [chunk count](struct.Progress.html#method.chunks).
+  pub fn send(&mut self, mesher: &mut Mesher, chunks: usize) -> fail::Result<Progress> {
+    let dest = match self.route.nodes().last() {
+      Some((_, pkey)) => *pkey,
+      None => return Err(fail::MesherFail::NoRoute("the route is empty".to_owned())),
+    };
+    for _ in 0..chunks {
+      if self.progress.done {
+        break;
+      }
+      let data = self.read_chunk()?;
+      // a short chunk means the stream's run out; if it ran out right at the end of a full one, the last is empty
+      let last = data.len() < self.chunk_size;
+      let mut packet = mesher.new_packet(None);
+      packet.via_route(&self.route);
+      packet.add_message(&encode(self.id, self.progress.chunks, last, &data), &dest);
+      mesher.launch_with_priority(packet, self.priority)?;
+      self.progress.chunks += 1;
+      self.progress.bytes += data.len() as u64;
+      self.progress.done = last;
+    }
+    Ok(self.progress)
+  }
+
+  /// Reads up to a whole chunk's worth of the stream, only coming up short if it ends.
+  fn read_chunk(&mut self) -> fail::Result<Vec<u8>> {
+    let mut data = vec![0; self.chunk_size];
+    let mut filled = 0;
+    while filled < data.len() {
+      match self.reader.read(&mut data[filled..]) {
+        Ok(0) => break,
+        Ok(n) => filled += n,
+        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+        Err(e) => return Err(transfer_fail("read upload", e)),
+      }
+    }
+    data.truncate(filled);
+    Ok(data)
+  }
+}
+
+impl<R: Read + Seek> Upload<R> {
+  /// Carries on sending from the given chunk, e.g. the first one the receiver's
+  /// [missing](struct.Downloads.html#method.missing), or wherever it left off before restarting.
+  ///
+  /// The stream's expected to be the same one, from the start, as it was when those chunks were first sent.
+  pub fn resume_from(&mut self, chunk: u32) -> fail::Result<()> {
+    let offset = u64::from(chunk) * self.chunk_size as u64;
+    self
+      .reader
+      .seek(SeekFrom::Start(offset))
+      .map_err(|e| transfer_fail("seek upload", e))?;
+    self.progress = Progress {
+      chunks: chunk,
+      bytes: offset,
+      done: false,
+    };
+    Ok(())
+  }
+}
+
+/// A transfer that's still being received.
+struct Incoming<W> {
+  sink: W,
+  progress: Progress,
+  /// Chunks which arrived before the ones they follow, by index, along with whether they're the last.
+  early: BTreeMap<u32, (Vec<u8>, bool)>,
+}
+
+/// Reassembles the transfers a mesher receives, writing each one, in order, to its own sink.
+///
+/// Every message the mesher receives should be passed to [`accept`](#method.accept), which says whether it was part of
+/// a transfer.
+/// When the first chunk of a new transfer arrives, the function given to [`new`](#method.new) is called to open a sink
+/// for it, e.g. a file; once the whole transfer's been written, the sink's flushed and can be collected with
+/// [`take_finished`](#method.take_finished).
+///
+/// Chunks that arrive out of order are held until the ones before them arrive, up to 256 per transfer; any past that
+/// are dropped, and have to be sent again.
+pub struct Downloads<W> {
+  open: Box<dyn FnMut(TransferId) -> std::io::Result<W> + Send>,
+  progress_handler: Option<Box<dyn FnMut(TransferId, Progress) + Send>>,
+  active: HashMap<TransferId, Incoming<W>>,
+  /// Transfers which have finished, so late duplicates of their chunks don't start them over.
+  completed: HashSet<TransferId>,
+  finished: Vec<(TransferId, W)>,
+}
+
+impl<W: Write> Downloads<W> {
+  /// Reassembles transfers, opening a new sink for each one with `open`.
+  pub fn new(open: impl FnMut(TransferId) -> std::io::Result<W> + Send + 'static) -> Downloads<W> {
+    Downloads {
+      open: Box::new(open),
+      progress_handler: None,
+      active: HashMap::new(),
+      completed: HashSet::new(),
+      finished: vec![],
+    }
+  }
+
+  /// Calls `handler` whenever a transfer makes progress, i.e. whenever a chunk's written to its sink.
+  ///
+  /// Setting a new handler replaces the old one.
+  pub fn on_progress(&mut self, handler: impl FnMut(TransferId, Progress) + Send + 'static) {
+    self.progress_handler = Some(Box::new(handler));
+  }
+
+  /// Carries on receiving a transfer that was interrupted, e.g. by a restart, writing the rest to `sink`.
+  ///
+  /// `chunks` and `bytes` are how much had already been written, which the sender should
+  /// [resume from](struct.Upload.html#method.resume_from).
+  pub fn resume(&mut self, id: TransferId, sink: W, chunks: u32, bytes: u64) {
+    let incoming = Incoming {
+      sink,
+      progress: Progress {
+        chunks,
+        bytes,
+        done: false,
+      },
+      early: BTreeMap::new(),
+    };
+    self.completed.remove(&id);
+    self.active.insert(id, incoming);
+  }
+
+  /// Handles a received message, returning whether it was a transfer's chunk, and so shouldn't be handled as anything
+  /// else.
+  ///
+  /// Fails with [`MesherFail::TransferFailure`](../fail/enum.MesherFail.html#variant.TransferFailure) if the
+  /// transfer's sink couldn't be opened or written to.
+  pub fn accept(&mut self, message: &Message) -> fail::Result<bool> {
+    self.accept_chunk(message.contents())
+  }
+
+  fn accept_chunk(&mut self, message: &[u8]) -> fail::Result<bool> {
+    let (id, index, last, data) = match decode(message) {
+      Some(chunk) => chunk,
+      None => return Ok(false),
+    };
+    if self.completed.contains(&id) {
+      return Ok(true);
+    }
+    if !self.active.contains_key(&id) {
+      let sink = (self.open)(id).map_err(|e| transfer_fail("open download", e))?;
+      let incoming = Incoming {
+        sink,
+        progress: Progress::default(),
+        early: BTreeMap::new(),
+      };
+      self.active.insert(id, incoming);
+    }
+    let incoming = self.active.get_mut(&id).expect("Just inserted");
+    if index < incoming.progress.chunks {
+      return Ok(true);
+    }
+    if index > incoming.progress.chunks {
+      if incoming.early.len() < MAX_OUT_OF_ORDER || incoming.early.contains_key(&index) {
+        incoming.early.insert(index, (data.to_vec(), last));
+      }
+      return Ok(true);
+    }
+
+    let mut next = Some((data.to_vec(), last));
+    while let Some((data, last)) = next {
+      incoming
+        .sink
+        .write_all(&data)
+        .map_err(|e| transfer_fail("write download", e))?;
+      incoming.progress.chunks += 1;
+      incoming.progress.bytes += data.len() as u64;
+      if last {
+        incoming.sink.flush().map_err(|e| transfer_fail("write download", e))?;
+        incoming.progress.done = true;
+        break;
+      }
+      next = incoming.early.remove(&incoming.progress.chunks);
+    }
+    let progress = incoming.progress;
+    if progress.done {
+      let incoming = self.active.remove(&id).expect("Was just written to");
+      self.completed.insert(id);
+      self.finished.push((id, incoming.sink));
+    }
+    if let Some(handler) = &mut self.progress_handler {
+      handler(id, progress);
+    }
+    Ok(true)
+  }
+
+  /// How far along a transfer that's still being received is, or `None` if it isn't.
+  pub fn progress(&self, id: TransferId) -> Option<Progress> {
+    self.active.get(&id).map(|i| i.progress)
+  }
+
+  /// The transfers still being received.
+  pub fn active(&self) -> Vec<TransferId> {
+    self.active.keys().copied().collect()
+  }
+
+  /// The chunks of a transfer that still haven't arrived, though some after them have, in order.
+  ///
+  /// There's no telling whether chunks after the newest one to arrive are missing or just haven't been sent yet, so
+  /// they're not included.
+  pub fn missing(&self, id: TransferId) -> Vec<u32> {
+    let incoming = match self.active.get(&id) {
+      Some(i) => i,
+      None => return vec![],
+    };
+    let newest = match incoming.early.keys().next_back() {
+      Some(&n) => n,
+      None => return vec![],
+    };
+    (incoming.progress.chunks..newest)
+      .filter(|i| !incoming.early.contains_key(i))
+      .collect()
+  }
+
+  /// Takes the sinks of every transfer that's finished since the last call, along with their IDs.
+  pub fn take_finished(&mut self) -> Vec<(TransferId, W)> {
+    std::mem::take(&mut self.finished)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::{
+    io::Cursor,
+    sync::{Arc, Mutex},
+  };
+
+  #[test]
+  fn reassembled_in_order_and_resumed() {
+    let id = TransferId(7);
+    let seen = Arc::new(Mutex::new(vec![]));
+    let mut downloads = Downloads::new(|_| Ok(vec![]));
+    let progress = seen.clone();
+    downloads.on_progress(move |_, p| progress.lock().unwrap().push(p.chunks()));
+
+    assert!(!downloads.accept_chunk(b"not a chunk").unwrap());
+    assert!(downloads.accept_chunk(&encode(id, 2, false, b"cc")).unwrap());
+    assert!(downloads.accept_chunk(&encode(id, 4, true, b"e")).unwrap());
+    assert_eq!(vec![0, 1, 3], downloads.missing(id));
+    downloads.accept_chunk(&encode(id, 0, false, b"aa")).unwrap();
+    downloads.accept_chunk(&encode(id, 0, false, b"aa")).unwrap();
+    assert_eq!(Some(1), downloads.progress(id).map(|p| p.chunks()));
+    downloads.accept_chunk(&encode(id, 1, false, b"bb")).unwrap();
+    assert_eq!(vec![3], downloads.missing(id));
+    downloads.accept_chunk(&encode(id, 3, false, b"dd")).unwrap();
+    assert_eq!(vec![(id, b"aabbccdde".to_vec())], downloads.take_finished());
+    assert_eq!(vec![1, 3, 5], *seen.lock().unwrap());
+    // late duplicates don't start it over
+    downloads.accept_chunk(&encode(id, 0, false, b"aa")).unwrap();
+    assert!(downloads.active().is_empty());
+
+    // picking up from where an earlier upload left off, after a restart
+    let mut upload = Upload::new(Route::new(), Cursor::new(b"aabbccdde".to_vec()))
+      .chunk_size(2)
+      .with_id(id);
+    upload.resume_from(3).unwrap();
+    assert_eq!(b"dd".to_vec(), upload.read_chunk().unwrap());
+    assert_eq!(6, upload.progress().bytes());
+  }
+}
@@ -1,5 +1,81 @@
 use crate::prelude::*;
 
+use std::time::Duration;
+
+/// Where a received packet came from, as far as the transport that received it can tell.
+///
+/// Transports return one with every packet they [receive](trait.Transport.html#tymethod.receive), and the mesher
+/// attaches it to the [messages](struct.Message.html#method.source) in the packet.
+/// [Forward policies](struct.ForwardPolicy.html#method.deny_from) can use it to decide whether to relay the packet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Source {
+  listen: Option<Path>,
+  remote: Option<String>,
+}
+
+impl Source {
+  /// A source the transport knows nothing about.
+  pub fn unknown() -> Source {
+    Source::default()
+  }
+
+  /// A packet received on the given path, i.e. one the transport was told to [listen](trait.Transport.html#tymethod.listen) on.
+  pub fn listening_on(path: Path) -> Source {
+    Source {
+      listen: Some(path),
+      remote: None,
+    }
+  }
+
+  /// Records who sent the packet, in whatever form makes sense for the transport, e.g. `203.0.113.7:41000` for IP.
+  pub fn from_remote(mut self, remote: impl Into<String>) -> Source {
+    self.remote = Some(remote.into());
+    self
+  }
+
+  /// The path the packet was received on, if the transport knows it.
+  pub fn listen_path(&self) -> Option<&Path> {
+    self.listen.as_ref()
+  }
+
+  /// Who sent the packet, if the transport knows.
+  ///
+  /// It's only as trustworthy as the transport: e.g. UDP source addresses are easily spoofed.
+  pub fn remote(&self) -> Option<&str> {
+    self.remote.as_deref()
+  }
+}
+
+/// How a transport's listener on one path is doing, as reported by [`Transport::status`](trait.Transport.html#method.status).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenStatus {
+  /// Packets sent to the path are being received.
+  Listening,
+  /// The connection or listener went down, and the transport's trying to bring it back up.
+  Reconnecting,
+  /// Something's wrong with the listener, e.g. its thread died, so packets probably aren't being received.
+  Failed(String),
+}
+
+/// How urgently a packet should be sent, when there's a choice of which waiting packet goes first.
+///
+/// Priorities are ordered from most to least urgent, so sorting packets by them puts the ones to send first at the front.
+/// They only decide the order packets leave this mesher in, e.g. from its [outbound queue](struct.OutboundQueue.html)
+/// or from a transport waiting on a connection; they aren't part of the packet, so relays forward everything as
+/// `Normal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Priority {
+  /// Small packets other traffic is waiting on, like receipts and announcements.
+  Control,
+  /// Most packets.
+  #[default]
+  Normal,
+  /// Big transfers that can wait for everything else, like files.
+  Bulk,
+}
+
 /// Transport is the core of mesher's communication system.
 ///
 /// All the ways that mesher can communicate are defined through this interface.
@@ -7,27 +83,168 @@ use crate::prelude::*;
 /// This ensures that transports can be reused across multiple versions of mesher without changes.
 /// It also ensures that transports can be largely reused for other projects which want to communicate over those methods.
 /// And, of course, it ensures that mesher can operate identically over any communication channel.
-pub trait Transport {
+///
+/// Transports have to be `Send`, so the mesher they're added to can be moved to another thread, but not `Sync`: the
+/// mesher only ever uses them from one thread at a time.
+/// They also have to be `'static`, since the mesher owns them as trait objects.
+pub trait Transport: Send + 'static {
   /// Creates a new instance of this transport method, associated with the given scheme.
   /// This isn't meant to be called by the end user; it's used by mesher internally.
   /// It should perform as little error-prone work as possible, and what errors happen should be fixable (possibly just by waiting and retrying) to the greatest extent possible.
+  ///
+  /// A transport [added for several schemes](struct.Mesher.html#method.add_transport_for_schemes) is created with the
+  /// first, but is passed paths with any of them.
   fn new(scheme: &str) -> fail::Result<Self>
   where
     Self: Sized;
 
+  /// The scheme this transport was created for, i.e. the one passed to [`new`](#tymethod.new).
+  fn scheme(&self) -> &str;
+
+  /// The schemes this kind of transport can be used for, or `None`, by default, if it doesn't mind.
+  ///
+  /// The mesher won't [add it](struct.Mesher.html#method.add_transport) for any other scheme, so e.g. a transport which
+  /// only speaks HTTP can list `http` and `https`, and catch a typo'd scheme in a config before anything's sent.
+  fn supported_schemes() -> Option<&'static [&'static str]>
+  where
+    Self: Sized,
+  {
+    None
+  }
+
+  /// A short, human-readable name for the kind of transport this is, e.g. `TCP`, for logs and debugging.
+  ///
+  /// By default, it's the type's name, including the module path.
+  fn name(&self) -> &str {
+    core::any::type_name::<Self>()
+  }
+
   /// Sends some bytes through this transport method.
   /// The transport should *not* care about the bytes being sent, only (possibly) the quantity.
-  /// The path will include the `scheme:` prefix.
-  fn send(&mut self, path: String, blob: Vec<u8>) -> fail::Result<()>;
+  /// The path's scheme will always be one this transport was created for.
+  ///
+  /// The bytes are borrowed, so that the mesher doesn't have to copy every packet it sends; transports which need to
+  /// hold on to them should copy them themselves.
+  ///
+  /// The mesher waits for this to return, so transports which can block, e.g. on a hung destination, should give up
+  /// after a while, and let it be changed with a `timeout` [path option](struct.PathOptions.html).
+  fn send(&mut self, path: &Path, blob: &[u8]) -> fail::Result<()>;
+
+  /// Sends some bytes through this transport method, like [`send`](#tymethod.send), with the given priority.
+  ///
+  /// Transports which hold packets before sending them, e.g. until a connection opens, should implement this, and send
+  /// more urgent packets first; by default, the priority's ignored and the packet's sent with `send`.
+  fn send_with_priority(&mut self, path: &Path, blob: &[u8], priority: Priority) -> fail::Result<()> {
+    let _ = priority;
+    self.send(path, blob)
+  }
+
+  /// Sends several packets along the same path, e.g. a burst of packets a relay is forwarding.
+  ///
+  /// Transports which can send them more cheaply together than one by one, e.g. over a single connection, should
+  /// implement this; by default, each is sent with [`send`](#tymethod.send).
+  /// Every packet should still be tried if one fails, and the first failure returned.
+  /// The mesher treats a failed batch as entirely unsent, so if it's retried, some packets might be sent twice.
+  fn send_batch(&mut self, path: &Path, blobs: &[&[u8]]) -> fail::Result<()> {
+    let mut result = Ok(());
+    for blob in blobs {
+      if let Err(e) = self.send(path, blob) {
+        result = result.and(Err(e));
+      }
+    }
+    result
+  }
 
   /// Set up this transport to listen on the given path.
   /// This does not return any messages -- it just tells the transport to listen on/poll on this route to receive future messages.
-  /// The path will include the `scheme:` prefix.
-  fn listen(&mut self, path: String) -> fail::Result<()>;
+  /// The path's scheme will always be one this transport was created for.
+  fn listen(&mut self, path: &Path) -> fail::Result<()>;
+
+  /// Stop listening on the given path, which was previously passed to [`Transport::listen`](#tymethod.listen).
+  /// Packets which arrived on the path before this is called may still be returned by [`Transport::receive`](#tymethod.receive).
+  ///
+  /// Transports which can't stop listening don't need to implement it; by default, it fails with [`MesherFail::Unsupported`](fail/enum.MesherFail.html#variant.Unsupported).
+  fn unlisten(&mut self, path: &Path) -> fail::Result<()> {
+    let _ = path;
+    Err(fail::MesherFail::Unsupported("stopping listening".to_owned()))
+  }
 
   /// Actually receive the pending messages.
   /// In listen-based transports, this will simply pull the received messages from the listener.
   /// In poll-based ones, it will actually perform the poll.
   /// The paths to receive on are given through calls to [`Transport::listen`](/mesher/struct.Transport.html#tymethod.listen).
-  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>>;
+  ///
+  /// Each packet comes with its [`Source`](struct.Source.html): at least the path it was received on, and who sent it
+  /// if the transport can tell.
+  fn receive(&mut self) -> fail::Result<Vec<(Source, Vec<u8>)>>;
+
+  /// Receives at most `max` of the pending packets, like [`receive`](#tymethod.receive), leaving the rest for later
+  /// calls, oldest first.
+  ///
+  /// Transports which hold on to packets until they're received, e.g. from listener threads, should implement this, so
+  /// a flood of packets isn't all handed over at once; by default, everything's received with `receive`.
+  fn receive_up_to(&mut self, max: usize) -> fail::Result<Vec<(Source, Vec<u8>)>> {
+    let _ = max;
+    self.receive()
+  }
+
+  /// How the listener on each path this transport's [listening](#tymethod.listen) on is doing.
+  ///
+  /// Transports should report every path they're listening on, since one that's missing looks the same as one that was
+  /// never listened on.
+  /// Transports which can't tell don't need to implement it; by default, it reports nothing.
+  fn status(&self) -> Vec<(Path, ListenStatus)> {
+    vec![]
+  }
+
+  /// Tells the transport the largest packet, in bytes, it should send or accept, or that there's no limit, with `None`.
+  /// It's called whenever the transport is added to a mesher, and whenever the mesher's
+  /// [limit](../struct.Mesher.html#method.set_max_packet_size) changes.
+  ///
+  /// Transports should refuse to send bigger packets with [`MesherFail::PacketTooLarge`](fail/enum.MesherFail.html#variant.PacketTooLarge),
+  /// and stop reading incoming ones as soon as they can tell they're too big, rather than buffering them.
+  /// Transports which can't don't need to implement it; by default, it does nothing, and the mesher drops oversized
+  /// packets itself once they're received.
+  fn set_max_packet_size(&mut self, max: Option<usize>) {
+    let _ = max;
+  }
+
+  /// Tells the transport the most packets it should hold on to between receives, or that there's no limit, with
+  /// `None`.
+  /// It's called whenever the transport is added to a mesher, and whenever the mesher's
+  /// [limit](../struct.Mesher.html#method.set_max_buffered) changes.
+  ///
+  /// Once a transport's holding that many, it should drop the packets that arrive until some are received, so a flood
+  /// can't use up all of the memory.
+  /// Transports which don't hold on to packets, or can't limit how many, don't need to implement it; by default, it
+  /// does nothing.
+  fn set_max_buffered(&mut self, max: Option<usize>) {
+    let _ = max;
+  }
+
+  /// Stops listening on every path, then waits up to `timeout` for everything the transport's doing in the background,
+  /// e.g. listener threads, to finish, so it can be dropped without leaving anything behind.
+  /// It's called by [`Mesher::close`](../struct.Mesher.html#method.close).
+  ///
+  /// If anything's still running once the time's up, it should fail with
+  /// [`MesherFail::TimedOut`](fail/enum.MesherFail.html#variant.TimedOut).
+  /// Transports which don't do anything in the background don't need to implement it; by default, it does nothing.
+  fn close(&mut self, timeout: Duration) -> fail::Result<()> {
+    let _ = timeout;
+    Ok(())
+  }
+}
+
+/// Fails with [`MesherFail::Unsupported`](fail/enum.MesherFail.html#variant.Unsupported) if the given kind of transport
+/// [doesn't support](trait.Transport.html#method.supported_schemes) the scheme.
+pub(crate) fn check_supported<T: Transport>(scheme: &str) -> fail::Result<()> {
+  match T::supported_schemes() {
+    Some(supported) if !supported.contains(&scheme) => Err(fail::MesherFail::Unsupported(format!(
+      "{} doesn't support the {:?} scheme, only {}",
+      core::any::type_name::<T>(),
+      scheme,
+      supported.join(", ")
+    ))),
+    _ => Ok(()),
+  }
 }
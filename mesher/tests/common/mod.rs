@@ -4,7 +4,7 @@ use mesher::prelude::*;
 #[allow(dead_code)]
 pub fn make_signed(name: &str, sender_pkey: &sign::PublicKey) -> (Mesher, encrypt::PublicKey) {
   let (pk, sk) = encrypt::gen_keypair();
-  let mut m = Mesher::signed(vec![sk], vec![sender_pkey.clone()]);
+  let mut m = Mesher::signed(vec![sk], vec![*sender_pkey]).expect("failed to create mesher");
   m.add_transport::<InMemory>("inmem").expect("failed to add mock");
   m.listen_on(&format!("inmem:{}", name)).expect("failed to listen");
   (m, pk)
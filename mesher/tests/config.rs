@@ -0,0 +1,38 @@
+use mesher::prelude::*;
+
+use std::fs;
+
+#[test]
+fn mesher_from_config_file() {
+  let dir = std::env::temp_dir().join(format!("mesher-config-{:x}", rand::random::<u32>()));
+  fs::create_dir_all(&dir).expect("Failed to create dir");
+  let (pk, sk) = encrypt::gen_keypair();
+  // one key as raw bytes, the other in hex, both relative to the config file
+  fs::write(dir.join("node.key"), sk.as_bytes()).expect("Failed to write key");
+  let (_, ssk) = sign::gen_keypair();
+  let hex: String = ssk.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+  fs::write(dir.join("node.sign"), hex + "\n").expect("Failed to write key");
+  fs::write(
+    dir.join("node.toml"),
+    r#"
+      keys = ["node.key"]
+      signing_key = "node.sign"
+
+      [[transports]]
+      scheme = "inmem"
+      listen = ["inmem:config-file"]
+    "#,
+  )
+  .expect("Failed to write config");
+
+  let mut mesher = Mesher::from_config(dir.join("node.toml")).expect("Failed to load config");
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:config-file".to_owned(), &pk);
+  packet.add_message(&[1], &pk);
+  mesher.launch(packet).expect("Failed to launch");
+  let messages = mesher.receive().expect("Failed to receive");
+  assert_eq!(1, messages.len());
+
+  assert!(Mesher::from_config(dir.join("missing.toml")).is_err());
+  fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
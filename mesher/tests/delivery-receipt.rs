@@ -0,0 +1,131 @@
+use mesher::prelude::*;
+use mesher::DeliveryStatus;
+
+mod common;
+use common::make_signed as make_mesher;
+
+#[test]
+fn delivery_receipt() {
+  let (signing_pk, signing_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_mesher("receipt-sender", &signing_pk);
+  let (mut receiver, receiver_pk) = make_mesher("receipt-receiver", &signing_pk);
+
+  let mut packet = Packet::signed(signing_sk);
+  packet.add_hop("inmem:receipt-receiver".to_owned(), &sender_pk);
+  packet.add_message(&[1], &receiver_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:receipt-sender".to_owned(), &receiver_pk);
+  let receipt = rh.use_for_receipt(&receiver_pk, &sender_pk);
+
+  sender.launch(packet).expect("Failed to send message");
+  assert_eq!(DeliveryStatus::Pending, sender.delivery_status(receipt));
+
+  let messages = receiver.receive().expect("Failed to receive message");
+  assert_eq!(1, messages.len());
+  assert_eq!(&[1], messages[0].contents());
+
+  // the receipt doesn't show up as a message
+  assert!(sender.receive().expect("Failed to receive receipt").is_empty());
+  assert_eq!(DeliveryStatus::Delivered, sender.delivery_status(receipt));
+
+  sender.forget_receipt(receipt);
+  assert_eq!(DeliveryStatus::Pending, sender.delivery_status(receipt));
+}
+
+#[test]
+fn failure_notice() {
+  let (signing_pk, signing_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_mesher("failure-sender", &signing_pk);
+  let (mut relay, relay_pk) = make_mesher("failure-relay", &signing_pk);
+  let (receiver_pk, _) = encrypt::gen_keypair();
+
+  // the relay has no transport for the last hop, so it can't forward the packet
+  let mut packet = Packet::signed(signing_sk);
+  packet.add_hop("inmem:failure-relay".to_owned(), &sender_pk);
+  packet.add_hop("nowhere:failure-receiver".to_owned(), &relay_pk);
+  packet.add_message(&[1], &receiver_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:failure-sender".to_owned(), &receiver_pk);
+  let receipt = rh.use_for_receipt(&receiver_pk, &sender_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:failure-sender".to_owned(), &relay_pk);
+  rh.use_for_failure_notice(&relay_pk, &sender_pk, receipt);
+
+  sender.launch(packet).expect("Failed to send message");
+  let _ = relay.receive();
+  assert_eq!(DeliveryStatus::Pending, sender.delivery_status(receipt));
+
+  // the notice doesn't show up as a message either
+  assert!(sender.receive().expect("Failed to receive notice").is_empty());
+  assert_eq!(DeliveryStatus::Failed, sender.delivery_status(receipt));
+
+  sender.forget_receipt(receipt);
+  assert_eq!(DeliveryStatus::Pending, sender.delivery_status(receipt));
+}
+
+#[test]
+fn no_failure_notice_when_forwarded() {
+  let (signing_pk, signing_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_mesher("forwarded-sender", &signing_pk);
+  let (mut relay, relay_pk) = make_mesher("forwarded-relay", &signing_pk);
+  let (receiver_pk, _) = encrypt::gen_keypair();
+
+  let mut packet = Packet::signed(signing_sk);
+  packet.add_hop("inmem:forwarded-relay".to_owned(), &sender_pk);
+  packet.add_hop("inmem:forwarded-receiver".to_owned(), &relay_pk);
+  packet.add_message(&[1], &receiver_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:forwarded-sender".to_owned(), &receiver_pk);
+  let receipt = rh.use_for_receipt(&receiver_pk, &sender_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:forwarded-sender".to_owned(), &relay_pk);
+  rh.use_for_failure_notice(&relay_pk, &sender_pk, receipt);
+
+  sender.launch(packet).expect("Failed to send message");
+  relay.receive().expect("Failed to forward");
+  assert!(sender.receive().expect("Failed to receive").is_empty());
+  assert_eq!(DeliveryStatus::Pending, sender.delivery_status(receipt));
+}
+
+#[test]
+fn unexpected_receipts_ignored() {
+  let (signing_pk, signing_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_mesher("unexpected-sender", &signing_pk);
+  let (mut receiver, receiver_pk) = make_mesher("unexpected-receiver", &signing_pk);
+
+  // the receipt's for the sender, but the receiver's the one launching the packet, so the sender isn't expecting it
+  let mut packet = Packet::signed(signing_sk);
+  packet.add_hop("inmem:unexpected-receiver".to_owned(), &receiver_pk);
+  packet.add_message(&[1], &receiver_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:unexpected-sender".to_owned(), &receiver_pk);
+  let receipt = rh.use_for_receipt(&receiver_pk, &sender_pk);
+
+  receiver.launch(packet).expect("Failed to send message");
+  assert_eq!(1, receiver.receive().expect("Failed to receive message").len());
+  assert!(sender.receive().expect("Failed to receive receipt").is_empty());
+  assert_eq!(DeliveryStatus::Pending, sender.delivery_status(receipt));
+}
+
+#[test]
+fn unexpected_failure_notices_ignored() {
+  let (signing_pk, signing_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_mesher("unexpected-failure-sender", &signing_pk);
+  let (mut relay, relay_pk) = make_mesher("unexpected-failure-relay", &signing_pk);
+  let (receiver_pk, _) = encrypt::gen_keypair();
+
+  // like failure_notice, but the relay's the one launching the packet, so the sender isn't expecting anything
+  let mut packet = Packet::signed(signing_sk);
+  packet.add_hop("nowhere:unexpected-failure-receiver".to_owned(), &relay_pk);
+  packet.add_message(&[1], &receiver_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:unexpected-failure-sender".to_owned(), &receiver_pk);
+  let receipt = rh.use_for_receipt(&receiver_pk, &sender_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:unexpected-failure-sender".to_owned(), &relay_pk);
+  rh.use_for_failure_notice(&relay_pk, &sender_pk, receipt);
+
+  let _ = relay.launch(packet);
+  assert!(sender.receive().expect("Failed to receive notice").is_empty());
+  assert_eq!(DeliveryStatus::Pending, sender.delivery_status(receipt));
+}
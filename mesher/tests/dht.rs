@@ -0,0 +1,118 @@
+use mesher::{dht::Dht, prelude::*};
+
+use std::time::Duration;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+struct Node {
+  mesher: Mesher,
+  dht: Dht,
+  pkey: encrypt::PublicKey,
+  path: Path,
+}
+
+fn node(name: &str) -> Node {
+  let (mut mesher, pkey) = make_mesher(name);
+  let path = Path::parse(&format!("inmem:{}", name)).unwrap();
+  let dht = Dht::new(&mut mesher, path.clone())
+    .expect("Failed to join DHT")
+    .request_timeout(Duration::from_millis(200));
+  Node {
+    mesher,
+    dht,
+    pkey,
+    path,
+  }
+}
+
+/// Has every node receive and poll, enough times for any lookup to finish.
+fn settle(nodes: &mut [Node]) {
+  for _ in 0..20 {
+    for n in nodes.iter_mut() {
+      n.mesher.receive().expect("Failed to receive");
+      n.dht.poll(&mut n.mesher).expect("Failed to poll");
+    }
+  }
+}
+
+/// A network of `count` nodes, which all bootstrapped from the first.
+fn network(prefix: &str, count: usize) -> Vec<Node> {
+  let mut nodes: Vec<_> = (0..count).map(|i| node(&format!("{}-{}", prefix, i))).collect();
+  let (first_pk, first_path) = (nodes[0].pkey, nodes[0].path.clone());
+  for n in &mut nodes[1..] {
+    n.dht.add_contact(first_pk, first_path.clone());
+    n.dht.bootstrap(&mut n.mesher).expect("Failed to bootstrap");
+    settle(std::slice::from_mut(n));
+  }
+  settle(&mut nodes);
+  nodes
+}
+
+#[test]
+fn everyone_finds_everyone() {
+  let mut nodes = network("dht-all", 12);
+  let mut published = vec![];
+  for n in &mut nodes {
+    let path = n.path.clone();
+    published.push(n.dht.publish(&mut n.mesher, vec![path]).expect("Failed to publish"));
+  }
+  settle(&mut nodes);
+  for (n, id) in nodes.iter().zip(published) {
+    assert!(!n.dht.result(id).expect("Publishing should be done").is_empty());
+  }
+
+  let targets: Vec<_> = nodes.iter().map(|n| (n.pkey, n.path.clone())).collect();
+  for (i, (pkey, path)) in targets.into_iter().enumerate() {
+    let seeker = &mut nodes[(i + 5) % 12];
+    let id = seeker
+      .dht
+      .find(&mut seeker.mesher, pkey.fingerprint())
+      .expect("Failed to look up");
+    settle(&mut nodes);
+    let found = nodes[(i + 5) % 12].dht.result(id).expect("Lookup should be done");
+    assert_eq!(1, found.len());
+    assert_eq!((&pkey, &[path][..]), (found[0].pkey(), found[0].paths()));
+  }
+}
+
+#[test]
+fn bootstrapping_fills_routing_tables() {
+  let nodes = network("dht-boot", 6);
+  for n in &nodes {
+    assert!(n.dht.contacts().len() >= 2);
+    assert!(!n.dht.contacts().iter().any(|(pk, _)| *pk == n.pkey));
+  }
+}
+
+#[test]
+fn missing_records_find_nothing() {
+  let mut nodes = network("dht-missing", 4);
+  let (absent, _) = encrypt::gen_keypair();
+  let seeker = &mut nodes[1];
+  let id = seeker
+    .dht
+    .find(&mut seeker.mesher, absent.fingerprint())
+    .expect("Failed to look up");
+  settle(&mut nodes);
+  assert_eq!(Some(vec![]), nodes[1].dht.result(id));
+  // results can only be taken once
+  assert_eq!(None, nodes[1].dht.result(id));
+}
+
+#[test]
+fn silent_nodes_are_dropped() {
+  let mut lonely = node("dht-silent-lonely");
+  let (gone, _) = encrypt::gen_keypair();
+  lonely
+    .dht
+    .add_contact(gone, Path::parse("inmem:dht-silent-nobody").unwrap());
+  let id = lonely.dht.bootstrap(&mut lonely.mesher).expect("Failed to bootstrap");
+  let (found, received) = lonely
+    .dht
+    .wait(&mut lonely.mesher, id, Duration::from_secs(2))
+    .expect("Lookup should finish");
+  assert!(found.is_empty());
+  assert!(received.is_empty());
+  assert!(lonely.dht.contacts().is_empty());
+}
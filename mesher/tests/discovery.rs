@@ -0,0 +1,46 @@
+use mesher::discovery::Discovery;
+use mesher::prelude::*;
+use std::time::Duration;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn path(p: &str) -> Path {
+  Path::parse(p).expect("Failed to parse path")
+}
+
+#[test]
+fn peers_learned_through_gossip() {
+  let (mut a, a_pk) = make_mesher("discovery-a");
+  let (mut b, b_pk) = make_mesher("discovery-b");
+  let (mut c, c_pk) = make_mesher("discovery-c");
+  for (m, p) in [(&mut a, "a"), (&mut b, "b"), (&mut c, "c")] {
+    m.set_discovery(Some(Discovery::new(
+      vec![path(&format!("inmem:discovery-{}", p))],
+      Duration::from_secs(3600),
+    )));
+  }
+  // a knows about b, and c knows about a, but nobody knows everybody
+  a.add_peer(b_pk, vec![path("inmem:discovery-b")]);
+  c.add_peer(a_pk, vec![path("inmem:discovery-a")]);
+
+  // c announces itself to a on its first receive, and a hears about it on its own
+  c.receive().expect("c failed to receive");
+  a.receive().expect("a failed to receive");
+  assert_eq!(2, a.known_peers().len());
+  // then a passes c along to b
+  a.announce().expect("a failed to announce");
+  b.receive().expect("b failed to receive");
+
+  let mut b_peers: Vec<_> = b.known_peers().into_iter().map(|p| *p.pkey()).collect();
+  b_peers.sort_by_key(|k| k.as_bytes().to_vec());
+  let mut expected = vec![a_pk, c_pk];
+  expected.sort_by_key(|k| k.as_bytes().to_vec());
+  assert_eq!(expected, b_peers);
+  let from_gossip = b
+    .known_peers()
+    .into_iter()
+    .find(|p| p.pkey() == &c_pk)
+    .expect("b didn't hear about c");
+  assert_eq!(&[path("inmem:discovery-c")], from_gossip.paths());
+}
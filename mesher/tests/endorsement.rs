@@ -0,0 +1,84 @@
+use mesher::{debug_transports::InMemory, identity::Endorsement, prelude::*};
+
+mod common;
+use common::make_signed;
+
+fn make_endorsed(name: &str, root: &sign::PublicKey) -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::endorsed(vec![sk], vec![*root]).expect("Failed to create mesher");
+  m.add_transport::<InMemory>("inmem").expect("Failed to add transport");
+  m.listen_on(&format!("inmem:{}", name)).expect("Failed to listen");
+  (m, pk)
+}
+
+/// Sends `packet` from `sender` to `dest`, returning the contents of whatever `dest` accepted.
+fn deliver(sender: &mut Mesher, dest: &mut Mesher, packet: Packet) -> Vec<Vec<u8>> {
+  sender.launch(packet).expect("Failed to launch");
+  dest
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(Message::into_contents)
+    .collect()
+}
+
+#[test]
+fn endorsements_in_packets_are_remembered() {
+  let (root_pk, root_sk) = sign::gen_keypair();
+  let (node_pk, node_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_signed("endorse-inband-sender", &node_pk);
+  let (mut dest, dest_pk) = make_endorsed("endorse-inband-dest", &root_pk);
+  let packet = |endorse: bool, data: u8| {
+    let mut packet = Packet::signed(node_sk.clone_secret());
+    packet.add_hop("inmem:endorse-inband-dest".to_owned(), &sender_pk);
+    if endorse {
+      packet.add_endorsement(&Endorsement::new(&node_pk, &root_sk), &dest_pk);
+    }
+    packet.add_message(&[data], &dest_pk);
+    packet
+  };
+
+  assert!(deliver(&mut sender, &mut dest, packet(false, 1)).is_empty());
+  assert_eq!(vec![vec![2]], deliver(&mut sender, &mut dest, packet(true, 2)));
+  // remembered, so it doesn't need sending again
+  assert_eq!(vec![vec![3]], deliver(&mut sender, &mut dest, packet(false, 3)));
+
+  assert!(dest.remove_trust_root(&root_pk));
+  assert!(deliver(&mut sender, &mut dest, packet(false, 4)).is_empty());
+}
+
+#[test]
+fn only_roots_can_endorse() {
+  let (root_pk, _) = sign::gen_keypair();
+  let (_, impostor_sk) = sign::gen_keypair();
+  let (node_pk, node_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_signed("endorse-impostor-sender", &node_pk);
+  let (mut dest, dest_pk) = make_endorsed("endorse-impostor-dest", &root_pk);
+  let endorsement = Endorsement::new(&node_pk, &impostor_sk);
+  assert!(matches!(
+    dest.add_endorsement(endorsement.clone()),
+    Err(fail::MesherFail::BadEndorsement)
+  ));
+
+  let mut packet = Packet::signed(node_sk);
+  packet.add_hop("inmem:endorse-impostor-dest".to_owned(), &sender_pk);
+  packet.add_endorsement(&endorsement, &dest_pk);
+  packet.add_message(&[1], &dest_pk);
+  assert!(deliver(&mut sender, &mut dest, packet).is_empty());
+}
+
+#[test]
+fn endorsements_cant_be_lifted_into_other_packets() {
+  let (root_pk, root_sk) = sign::gen_keypair();
+  let (node_pk, _) = sign::gen_keypair();
+  let (attacker_pk, attacker_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_signed("endorse-lifted-sender", &attacker_pk);
+  let (mut dest, dest_pk) = make_endorsed("endorse-lifted-dest", &root_pk);
+
+  let mut packet = Packet::signed(attacker_sk);
+  packet.add_hop("inmem:endorse-lifted-dest".to_owned(), &sender_pk);
+  packet.add_endorsement(&Endorsement::new(&node_pk, &root_sk), &dest_pk);
+  packet.add_message(&[1], &dest_pk);
+  assert!(deliver(&mut sender, &mut dest, packet).is_empty());
+  assert!(dest.trust_roots().contains(&root_pk));
+}
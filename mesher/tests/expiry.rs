@@ -0,0 +1,65 @@
+use mesher::metrics::{Counters, DropReason};
+use mesher::prelude::*;
+
+use std::{
+  sync::Arc,
+  time::{Duration, SystemTime},
+};
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+/// Sends a message that expires at `expiry` straight to a receiver allowing for `skew`, and returns how many messages
+/// it got, and how many packets it dropped for being expired.
+fn receive_expiring(name: &str, expiry: SystemTime, skew: Duration) -> (usize, u64) {
+  let (mut sender, sender_pk) = make_mesher(&format!("{}-sender", name));
+  let (mut receiver, receiver_pk) = make_mesher(&format!("{}-receiver", name));
+  let counters = Arc::new(Counters::new());
+  receiver.set_metrics(Some(counters.clone()));
+  receiver.set_clock_skew(skew);
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop(format!("inmem:{}-receiver", name), &sender_pk);
+  packet.add_expiry(expiry, &receiver_pk);
+  packet.add_message(&[1], &receiver_pk);
+  sender.launch(packet).expect("Failed to send packet");
+
+  let messages = receiver.receive().expect("Failed to receive");
+  (messages.len(), counters.packets_dropped(DropReason::Expired))
+}
+
+#[test]
+fn unexpired_delivered() {
+  let expiry = SystemTime::now() + Duration::from_secs(60);
+  assert_eq!((1, 0), receive_expiring("unexpired", expiry, Duration::ZERO));
+}
+
+#[test]
+fn expired_dropped() {
+  let expiry = SystemTime::now() - Duration::from_secs(60);
+  assert_eq!((0, 1), receive_expiring("expired", expiry, Duration::ZERO));
+}
+
+#[test]
+fn expiry_allows_for_skew() {
+  let expiry = SystemTime::now() - Duration::from_secs(60);
+  assert_eq!((1, 0), receive_expiring("skewed", expiry, Duration::from_secs(120)));
+}
+
+#[test]
+fn expired_not_forwarded() {
+  let (mut sender, sender_pk) = make_mesher("expired-fwd-sender");
+  let (mut relay, relay_pk) = make_mesher("expired-fwd-relay");
+  let (mut receiver, receiver_pk) = make_mesher("expired-fwd-receiver");
+  relay.set_clock_skew(Duration::ZERO);
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:expired-fwd-relay".to_owned(), &sender_pk);
+  packet.add_hop("inmem:expired-fwd-receiver".to_owned(), &relay_pk);
+  packet.add_expiry(SystemTime::now() - Duration::from_secs(60), &relay_pk);
+  packet.add_message(&[1], &receiver_pk);
+  sender.launch(packet).expect("Failed to send packet");
+
+  relay.receive().expect("Failed to relay");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
@@ -0,0 +1,137 @@
+use mesher::{prelude::*, Group, Route};
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn route_to(owner: (&str, encrypt::PublicKey), member: (&str, encrypt::PublicKey)) -> Route {
+  Route::new()
+    .then(Path::parse(owner.0).unwrap(), owner.1)
+    .then(Path::parse(member.0).unwrap(), member.1)
+}
+
+/// Launches a packet from `owner` that passes through both members, with one group message in it.
+fn send_to_group(owner: &mut Mesher, owner_pk: &encrypt::PublicKey, group: &Group, data: &[u8]) {
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:groups-a".to_owned(), owner_pk);
+  packet.add_hop("inmem:groups-b".to_owned(), owner_pk);
+  packet.add_group_message(group.key(), data);
+  owner.launch(packet).expect("Failed to launch");
+}
+
+/// Accepts every group invite the mesher's been sent.
+fn accept_invites(mesher: &mut Mesher) {
+  for invite in mesher.take_group_invites() {
+    assert!(mesher.accept_group_invite(invite));
+  }
+}
+
+fn group_contents(mesher: &mut Mesher, group: &Group) -> Vec<Vec<u8>> {
+  mesher
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .inspect(|m| assert_eq!(Some(group.id()), m.group()))
+    .map(Message::into_contents)
+    .collect()
+}
+
+#[test]
+fn removed_members_cant_read_after_rekey() {
+  let (mut owner, owner_pk) = make_mesher("groups-owner");
+  let (mut a, a_pk) = make_mesher("groups-a");
+  let (mut b, b_pk) = make_mesher("groups-b");
+  let routes = |pk: &encrypt::PublicKey| {
+    if *pk == a_pk {
+      Some(route_to(("inmem:groups-owner", owner_pk), ("inmem:groups-a", a_pk)))
+    } else {
+      Some(route_to(("inmem:groups-owner", owner_pk), ("inmem:groups-b", b_pk)))
+    }
+  };
+
+  let (_, owner_ssk) = sign::gen_keypair();
+  let mut group = Group::signed(owner_ssk);
+  assert!(group.add_member(a_pk));
+  assert!(group.add_member(b_pk));
+  assert!(!group.add_member(b_pk));
+  assert_eq!(2, group.distribute(&mut owner, routes).expect("Failed to distribute"));
+  a.receive().expect("Failed to receive");
+  b.receive().expect("Failed to receive");
+  assert!(a.group_key(group.id()).is_none());
+  accept_invites(&mut a);
+  accept_invites(&mut b);
+  assert_eq!(Some(0), a.group_key(group.id()).map(|k| k.epoch()));
+
+  send_to_group(&mut owner, &owner_pk, &group, b"hello");
+  assert_eq!(vec![b"hello".to_vec()], group_contents(&mut a, &group));
+  assert_eq!(vec![b"hello".to_vec()], group_contents(&mut b, &group));
+
+  assert!(group.remove_member(&b_pk));
+  assert!(!group.remove_member(&b_pk));
+  assert_eq!(1, group.distribute(&mut owner, routes).expect("Failed to distribute"));
+  a.receive().expect("Failed to receive");
+  // new keys from whoever sent the accepted invite are taken without asking
+  assert!(a.take_group_invites().is_empty());
+  assert_eq!(Some(1), a.group_key(group.id()).map(|k| k.epoch()));
+  assert_eq!(Some(0), b.group_key(group.id()).map(|k| k.epoch()));
+
+  send_to_group(&mut owner, &owner_pk, &group, b"without b");
+  assert_eq!(vec![b"without b".to_vec()], group_contents(&mut a, &group));
+  assert!(group_contents(&mut b, &group).is_empty());
+}
+
+#[test]
+fn older_keys_dont_replace_newer_ones() {
+  let (mut m, _) = make_mesher("groups-epochs");
+  let first = mesher::crypto::group::GroupKey::generate();
+  let second = first.next();
+  assert!(m.join_group(second.clone()));
+  assert!(!m.join_group(first.clone()));
+  assert!(!m.join_group(second.clone()));
+  assert_eq!(Some(1), m.group_key(first.id()).map(|k| k.epoch()));
+  assert!(m.leave_group(first.id()));
+  assert!(!m.leave_group(first.id()));
+  assert!(m.group_key(first.id()).is_none());
+}
+
+#[test]
+fn strangers_keys_only_invites() {
+  let (mut owner, owner_pk) = make_mesher("groups-stranger-owner");
+  let (mut stranger, stranger_pk) = make_mesher("groups-stranger");
+  let (mut member, member_pk) = make_mesher("groups-stranger-member");
+  let route = |from: (&str, encrypt::PublicKey)| route_to(from, ("inmem:groups-stranger-member", member_pk));
+
+  let (owner_spk, owner_ssk) = sign::gen_keypair();
+  let mut group = Group::signed(owner_ssk);
+  group.add_member(member_pk);
+  group
+    .distribute(&mut owner, |_| Some(route(("inmem:groups-stranger-owner", owner_pk))))
+    .expect("Failed to distribute");
+  member.receive().expect("Failed to receive");
+  let invites = member.take_group_invites();
+  assert_eq!(1, invites.len());
+  assert_eq!(Some(&owner_spk), invites[0].from());
+  assert!(member.accept_group_invite(invites[0].clone()));
+
+  // someone else sending a newer key for the same group isn't enough to replace it
+  let (stranger_spk, stranger_ssk) = sign::gen_keypair();
+  let forged = group.key().next().next();
+  let mut packet = Packet::unsigned();
+  packet.claim_sender(&stranger_ssk);
+  packet.via_route(&route(("inmem:groups-stranger", stranger_pk)));
+  packet.add_group_key(&forged, &member_pk);
+  stranger.launch(packet).expect("Failed to launch");
+  // and neither is sending one without saying who it's from
+  let mut packet = Packet::unsigned();
+  packet.via_route(&route(("inmem:groups-stranger", stranger_pk)));
+  packet.add_group_key(&forged, &member_pk);
+  stranger.launch(packet).expect("Failed to launch");
+  member.receive().expect("Failed to receive");
+
+  assert_eq!(Some(0), member.group_key(group.id()).map(|k| k.epoch()));
+  let invites = member.take_group_invites();
+  assert_eq!(
+    vec![Some(&stranger_spk), None],
+    invites.iter().map(|i| i.from()).collect::<Vec<_>>()
+  );
+  assert!(invites.iter().all(|i| i.key() == &forged));
+}
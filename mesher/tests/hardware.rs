@@ -0,0 +1,99 @@
+use mesher::{
+  crypto::{
+    hardware::{Device, KeyHandle},
+    Crypto, Sodium,
+  },
+  prelude::*,
+};
+
+use std::sync::{
+  atomic::{AtomicUsize, Ordering},
+  Arc,
+};
+
+mod common;
+use common::{make_signed, make_unsigned};
+
+/// Pretends to be a hardware token, by keeping raw keys where nobody else can get at them.
+#[derive(Default)]
+struct SoftToken {
+  encrypt: Vec<encrypt::SecretKey>,
+  sign: Vec<sign::SecretKey>,
+  uses: AtomicUsize,
+}
+
+impl SoftToken {
+  fn new_encrypt_key(&mut self) -> (encrypt::PublicKey, KeyHandle) {
+    let (pk, sk) = encrypt::gen_keypair();
+    self.encrypt.push(sk);
+    (pk, KeyHandle(self.encrypt.len() as u64 - 1))
+  }
+
+  fn new_sign_key(&mut self) -> (sign::PublicKey, KeyHandle) {
+    let (pk, sk) = sign::gen_keypair();
+    self.sign.push(sk);
+    (pk, KeyHandle(self.sign.len() as u64 - 1))
+  }
+}
+
+impl Device for SoftToken {
+  fn open(&self, handle: KeyHandle, sealed: &[u8]) -> Option<Vec<u8>> {
+    self.uses.fetch_add(1, Ordering::SeqCst);
+    Sodium.open(sealed, self.encrypt.get(handle.0 as usize)?)
+  }
+
+  fn sign(&self, handle: KeyHandle, data: &[u8]) -> Option<[u8; 64]> {
+    self.uses.fetch_add(1, Ordering::SeqCst);
+    let signed = Sodium.sign(data, self.sign.get(handle.0 as usize)?);
+    let mut signature = [0; 64];
+    signature.copy_from_slice(&signed[..64]);
+    Some(signature)
+  }
+}
+
+#[test]
+fn held_keys_open_and_sign_on_the_device() {
+  let mut token = SoftToken::default();
+  let (dest_pk, dest_handle) = token.new_encrypt_key();
+  let (signer_pk, signer_handle) = token.new_sign_key();
+  let token = Arc::new(token);
+
+  let dest_sk = encrypt::SecretKey::held(token.clone(), dest_handle, dest_pk);
+  assert_eq!(dest_pk, dest_sk.public_key());
+  assert!(dest_sk.as_bytes().is_empty());
+  let mut dest = Mesher::unsigned(vec![dest_sk]);
+  dest
+    .add_transport::<mesher::debug_transports::InMemory>("inmem")
+    .expect("Failed to add transport");
+  dest.listen_on("inmem:hardware-dest").expect("Failed to listen");
+  let (mut sender, sender_pk) = make_unsigned("hardware-sender");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:hardware-dest".to_owned(), &sender_pk);
+  packet.add_message(b"sealed for the token", &dest_pk);
+  sender.launch(packet).expect("Failed to launch");
+  let received: Vec<_> = dest
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(Message::into_contents)
+    .collect();
+  assert_eq!(vec![b"sealed for the token".to_vec()], received);
+
+  let signer_sk = sign::SecretKey::held(token.clone(), signer_handle, signer_pk);
+  let (mut relay, relay_pk) = make_signed("hardware-relay", &signer_pk);
+  let (mut signed_dest, signed_dest_pk) = make_signed("hardware-signed-dest", &signer_pk);
+  let before = token.uses.load(Ordering::SeqCst);
+  let mut packet = Packet::signed(signer_sk);
+  packet.add_hop("inmem:hardware-signed-dest".to_owned(), &relay_pk);
+  packet.add_message(b"signed by the token", &signed_dest_pk);
+  relay.launch(packet).expect("Failed to launch");
+  assert_eq!(2, token.uses.load(Ordering::SeqCst) - before);
+  let received: Vec<_> = signed_dest
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(Message::into_contents)
+    .collect();
+  assert_eq!(vec![b"signed by the token".to_vec()], received);
+}
@@ -0,0 +1,75 @@
+use mesher::prelude::*;
+use mesher::Mailbox;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+#[test]
+fn mail_held_until_collected() {
+  let (mut sender, sender_pk) = make_mesher("mailbox-sender");
+  let (mut relay, relay_pk) = make_mesher("mailbox-relay");
+  let (recipient_sign_pk, recipient_sign_sk) = sign::gen_keypair();
+  relay.set_mailbox(Some(Mailbox::new().register(recipient_sign_pk)));
+
+  // the recipient doesn't exist yet, so the mail can only wait at the relay
+  let (recipient_pk, recipient_sk) = encrypt::gen_keypair();
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:mailbox-relay".to_owned(), &sender_pk);
+  packet.add_hop(Mailbox::path(&recipient_sign_pk), &relay_pk);
+  packet.add_message(b"while you were out", &recipient_pk);
+  sender.launch(packet).expect("Failed to send");
+  relay.receive().expect("Failed to receive");
+  assert_eq!(1, relay.mailbox().expect("No mailbox").held(&recipient_sign_pk));
+
+  let mut recipient = Mesher::unsigned(vec![recipient_sk]);
+  recipient
+    .add_transport::<mesher::debug_transports::InMemory>("inmem")
+    .expect("Failed to add transport");
+  recipient
+    .listen_on("inmem:mailbox-recipient")
+    .expect("Failed to listen");
+  recipient
+    .request_mail(
+      &relay_pk,
+      "inmem:mailbox-relay",
+      "inmem:mailbox-recipient",
+      &recipient_sign_sk,
+    )
+    .expect("Failed to request mail");
+  relay.receive().expect("Failed to send challenge");
+  assert!(recipient.receive().expect("Failed to answer challenge").is_empty());
+  relay.receive().expect("Failed to deliver mail");
+
+  let messages = recipient.receive().expect("Failed to receive mail");
+  assert_eq!(1, messages.len());
+  assert_eq!(b"while you were out", messages[0].contents());
+  assert_eq!(0, relay.mailbox().expect("No mailbox").held(&recipient_sign_pk));
+}
+
+#[test]
+fn mail_needs_the_right_key() {
+  let (mut relay, relay_pk) = make_mesher("mailbox-strict-relay");
+  let (mut impostor, _) = make_mesher("mailbox-impostor");
+  let (recipient_sign_pk, _) = sign::gen_keypair();
+  relay.set_mailbox(Some(Mailbox::new().register(recipient_sign_pk)));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop(Mailbox::path(&recipient_sign_pk), &relay_pk);
+  relay.launch(packet).expect("Failed to deposit");
+  assert_eq!(1, relay.mailbox().expect("No mailbox").held(&recipient_sign_pk));
+
+  // signed with some other key, so its requests don't match any registered recipient
+  let (_, impostor_sign_sk) = sign::gen_keypair();
+  impostor
+    .request_mail(
+      &relay_pk,
+      "inmem:mailbox-strict-relay",
+      "inmem:mailbox-impostor",
+      &impostor_sign_sk,
+    )
+    .expect("Failed to request mail");
+  relay.receive().expect("Failed to receive");
+  impostor.receive().expect("Failed to receive");
+  relay.receive().expect("Failed to receive");
+  assert_eq!(1, relay.mailbox().expect("No mailbox").held(&recipient_sign_pk));
+}
@@ -0,0 +1,35 @@
+use mesher::metrics::{Counters, DropReason};
+use mesher::prelude::*;
+use std::sync::Arc;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+#[test]
+fn relay_counted() {
+  let (mut sender, sender_pk) = make_mesher("metrics-sender");
+  let (mut relay, relay_pk) = make_mesher("metrics-relay");
+  let (_receiver, receiver_pk) = make_mesher("metrics-receiver");
+  let counters = Arc::new(Counters::new());
+  relay.set_metrics(Some(counters.clone()));
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:metrics-relay".to_owned(), &sender_pk);
+  packet.add_hop("inmem:metrics-receiver".to_owned(), &relay_pk);
+  packet.add_message(&[1], &receiver_pk);
+  sender.launch(packet).expect("Failed to send");
+  // and one packet with nothing for the relay in it
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:metrics-relay".to_owned(), &sender_pk);
+  packet.add_message(&[2], &receiver_pk);
+  sender.launch(packet).expect("Failed to send");
+  relay.receive().expect("Failed to relay");
+
+  assert_eq!(2, counters.packets_received());
+  assert_eq!(1, counters.packets_forwarded());
+  assert_eq!(1, counters.packets_sent());
+  assert_eq!(1, counters.packets_undecryptable());
+  assert_eq!(0, counters.packets_dropped(DropReason::Malformed));
+  assert!(counters.bytes_received("inmem") > counters.bytes_sent("inmem"));
+  assert!(counters.bytes_sent("inmem") > 0);
+}
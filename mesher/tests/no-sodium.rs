@@ -0,0 +1,110 @@
+//! Meshers built without the `crypto-sodium` feature only have the backend they're given, so nothing they do should fall
+//! back on the default one, which panics.
+//! These pass with it too, but they're really meant for
+//! `cargo test -p mesher --no-default-features --features std --test no-sodium`.
+
+use mesher::crypto::{group::GroupKey, Crypto};
+use mesher::debug_transports::InMemory;
+use mesher::prelude::*;
+
+use sha2::{Digest, Sha256};
+use std::{sync::Arc, time::Duration};
+
+/// A backend that only pretends to encrypt and sign, so these tests don't need libsodium.
+struct Toy;
+
+fn hash(parts: &[&[u8]]) -> [u8; 32] {
+  let mut hasher = Sha256::new();
+  for part in parts {
+    hasher.update(part);
+  }
+  hasher.finalize().into()
+}
+
+impl Crypto for Toy {
+  fn gen_encrypt_keypair(&self) -> (encrypt::PublicKey, encrypt::SecretKey) {
+    let skey = encrypt::SecretKey::from_slice(&rand::random::<[u8; 32]>()).unwrap();
+    (self.encrypt_public_key(&skey), skey)
+  }
+  fn encrypt_public_key(&self, skey: &encrypt::SecretKey) -> encrypt::PublicKey {
+    encrypt::PublicKey::from_slice(&hash(&[b"encrypt", skey.as_bytes()])).unwrap()
+  }
+  fn seal(&self, data: &[u8], pkey: &encrypt::PublicKey) -> Vec<u8> {
+    [pkey.as_bytes(), data].concat()
+  }
+  fn open(&self, data: &[u8], skey: &encrypt::SecretKey) -> Option<Vec<u8>> {
+    data
+      .strip_prefix(self.encrypt_public_key(skey).as_bytes())
+      .map(<[u8]>::to_vec)
+  }
+  fn gen_sign_keypair(&self) -> (sign::PublicKey, sign::SecretKey) {
+    let mut bytes = [0; 64];
+    bytes[..32].copy_from_slice(&rand::random::<[u8; 32]>());
+    let skey = sign::SecretKey::from_slice(&bytes).unwrap();
+    (self.sign_public_key(&skey), skey)
+  }
+  fn sign_public_key(&self, skey: &sign::SecretKey) -> sign::PublicKey {
+    sign::PublicKey::from_slice(&hash(&[b"sign", skey.as_bytes()])).unwrap()
+  }
+  fn sign(&self, data: &[u8], skey: &sign::SecretKey) -> Vec<u8> {
+    let pkey = self.sign_public_key(skey);
+    [&hash(&[pkey.as_bytes(), data])[..], &[0; 32], data].concat()
+  }
+  fn verify(&self, signed: &[u8], pkey: &sign::PublicKey) -> Option<Vec<u8>> {
+    if signed.len() < 64 || signed[..32] != hash(&[pkey.as_bytes(), &signed[64..]]) {
+      return None;
+    }
+    Some(signed[64..].to_vec())
+  }
+  fn key_hint(&self, nonce: &[u8], pkey: &encrypt::PublicKey) -> [u8; 32] {
+    hash(&[pkey.as_bytes(), nonce])
+  }
+  fn group_seal(&self, _: &[u8], _: &GroupKey) -> Vec<u8> {
+    unreachable!("groups aren't used here")
+  }
+  fn group_open(&self, _: &[u8], _: &GroupKey) -> Option<Vec<u8>> {
+    unreachable!("groups aren't used here")
+  }
+}
+
+fn make_mesher(name: &str, skeys: Vec<encrypt::SecretKey>) -> Mesher {
+  Mesher::builder()
+    .own_keys(skeys)
+    .crypto(Arc::new(Toy))
+    .transport::<InMemory>("inmem")
+    .listen_on(&format!("inmem:{}", name))
+    .build()
+    .expect("Failed to build mesher")
+}
+
+#[test]
+fn receives_with_a_given_backend() {
+  let (sender_pk, sender_sk) = Toy.gen_encrypt_keypair();
+  let (pk, sk) = Toy.gen_encrypt_keypair();
+  let mut sender = make_mesher("no-sodium-sender", vec![sender_sk]);
+  let mut receiver = make_mesher("no-sodium-receiver", vec![sk]);
+
+  let mut packet = Packet::unsigned_using(Arc::new(Toy));
+  packet.add_hop("inmem:no-sodium-receiver".to_owned(), &sender_pk);
+  packet.add_message(b"hello", &pk);
+  sender.launch(packet).expect("Failed to send packet");
+
+  let messages = receiver.receive().expect("Failed to receive");
+  assert_eq!(1, messages.len());
+  assert_eq!(b"hello", messages[0].contents());
+}
+
+#[test]
+fn manages_keys_with_a_given_backend() {
+  let (old_pk, old_sk) = Toy.gen_encrypt_keypair();
+  let (new_pk, new_sk) = Toy.gen_encrypt_keypair();
+  let mut mesher = make_mesher("no-sodium-keys", vec![old_sk]);
+  mesher.add_own_key(new_sk);
+  assert!(mesher.retire_own_key(&old_pk, Duration::ZERO));
+  assert!(!mesher.retire_own_key(&Toy.gen_encrypt_keypair().0, Duration::ZERO));
+
+  // receiving drops the retired key, so only the new one can be retired now
+  mesher.receive().expect("Failed to receive");
+  assert!(!mesher.retire_own_key(&old_pk, Duration::ZERO));
+  assert!(mesher.retire_own_key(&new_pk, Duration::ZERO));
+}
@@ -0,0 +1,39 @@
+use mesher::prelude::*;
+
+mod common;
+use common::make_signed as make_mesher;
+
+#[test]
+fn onion_unwrapped_hop_by_hop() {
+  let (signing_pk, signing_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_mesher("onion-sender", &signing_pk);
+  let (mut relay, relay_pk) = make_mesher("onion-relay", &signing_pk);
+  let (mut receiver, receiver_pk) = make_mesher("onion-receiver", &signing_pk);
+
+  let packet = Packet::onion()
+    .signed(signing_sk)
+    .hop(&sender_pk, "inmem:onion-relay")
+    .message(&relay_pk, &[1])
+    .hop(&relay_pk, "inmem:onion-receiver")
+    .message(&receiver_pk, &[2])
+    .message(&receiver_pk, &[3])
+    .build()
+    .expect("Failed to build onion");
+  sender.launch(packet).expect("Failed to send");
+
+  let relayed: Vec<_> = relay
+    .receive()
+    .expect("Failed to relay")
+    .into_iter()
+    .map(Message::into_contents)
+    .collect();
+  assert_eq!(vec![vec![1]], relayed);
+  let mut received: Vec<_> = receiver
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(Message::into_contents)
+    .collect();
+  received.sort();
+  assert_eq!(vec![vec![2], vec![3]], received);
+}
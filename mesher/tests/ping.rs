@@ -0,0 +1,101 @@
+use mesher::ping::Probe;
+use mesher::prelude::*;
+use mesher::Route;
+
+use std::time::Duration;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn path(p: &str) -> Path {
+  Path::parse(p).expect("Failed to parse path")
+}
+
+#[test]
+fn ping_through_relay() {
+  let (mut sender, sender_pk) = make_mesher("ping-sender");
+  let (mut relay, relay_pk) = make_mesher("ping-relay");
+  let (mut receiver, receiver_pk) = make_mesher("ping-receiver");
+  let route = Route::new()
+    .then(path("inmem:ping-sender"), sender_pk)
+    .then(path("inmem:ping-relay"), relay_pk)
+    .then(path("inmem:ping-receiver"), receiver_pk);
+
+  let mut probe = Probe::ping(&mut sender, &route).expect("Failed to send probe");
+  assert_eq!(1, probe.hops().len());
+  assert_eq!("inmem:ping-receiver", probe.hops()[0].path().as_str());
+
+  assert!(relay.receive().expect("Failed to relay").is_empty());
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  // the receipt comes back through the relay
+  assert!(!probe.check(&mut sender));
+  relay.receive().expect("Failed to relay receipt");
+  assert!(probe
+    .wait(&mut sender, Duration::from_secs(5))
+    .expect("Failed to receive receipt")
+    .is_empty());
+  assert!(probe.round_trip().is_some());
+}
+
+#[test]
+fn trace_shows_how_far_it_got() {
+  let (mut sender, sender_pk) = make_mesher("trace-sender");
+  let (mut relay, relay_pk) = make_mesher("trace-relay");
+  let (receiver_pk, _) = encrypt::gen_keypair();
+  // nothing's listening at the end, so the packet stops at the relay
+  let route = Route::new()
+    .then(path("inmem:trace-sender"), sender_pk)
+    .then(path("inmem:trace-relay"), relay_pk)
+    .then(path("inmem:trace-nowhere"), receiver_pk);
+
+  let mut probe = Probe::trace(&mut sender, &route).expect("Failed to send probe");
+  relay.receive().expect("Failed to relay");
+  probe
+    .wait(&mut sender, Duration::from_millis(100))
+    .expect("Failed to receive receipts");
+  let answered: Vec<_> = probe
+    .hops()
+    .iter()
+    .map(|h| (h.path().as_str(), h.round_trip().is_some()))
+    .collect();
+  assert_eq!(
+    vec![("inmem:trace-relay", true), ("inmem:trace-nowhere", false)],
+    answered
+  );
+  assert_eq!(None, probe.round_trip());
+}
+
+#[test]
+fn broken_route_reported_by_relay() {
+  let (mut sender, sender_pk) = make_mesher("broken-sender");
+  let (mut relay, relay_pk) = make_mesher("broken-relay");
+  let (receiver_pk, _) = encrypt::gen_keypair();
+  // the relay can't send over a scheme it has no transport for
+  let route = Route::new()
+    .then(path("inmem:broken-sender"), sender_pk)
+    .then(path("inmem:broken-relay"), relay_pk)
+    .then(path("nowhere:broken-receiver"), receiver_pk);
+
+  let mut probe = Probe::trace(&mut sender, &route).expect("Failed to send probe");
+  let _ = relay.receive();
+  // done as soon as the notice is in, without waiting out the timeout
+  probe
+    .wait(&mut sender, Duration::from_secs(60))
+    .expect("Failed to receive notice");
+  let hops: Vec<_> = probe
+    .hops()
+    .iter()
+    .map(|h| (h.round_trip().is_some(), h.failed()))
+    .collect();
+  assert_eq!(vec![(true, false), (false, true)], hops);
+}
+
+#[test]
+fn probe_needs_a_route() {
+  let (mut sender, sender_pk) = make_mesher("ping-alone");
+  let route = Route::new().then(path("inmem:ping-alone"), sender_pk);
+  match Probe::ping(&mut sender, &route) {
+    Err(fail::MesherFail::NoRoute(_)) => (),
+    _ => panic!("Probe with nowhere to go should fail"),
+  }
+}
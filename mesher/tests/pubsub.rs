@@ -0,0 +1,79 @@
+use mesher::{debug_transports::InMemory, prelude::*, pubsub::PubSub};
+
+use std::time::Duration;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn path(p: &str) -> Path {
+  Path::parse(p).unwrap()
+}
+
+#[test]
+fn publications_reach_subscribers_through_relays() {
+  let (mut publisher, publisher_pk) = make_mesher("pubsub-relay-pub");
+  let (mut relay, relay_pk) = make_mesher("pubsub-relay-relay");
+  relay.add_transport::<InMemory>("far").expect("Failed to add transport");
+  let (sub_pk, sub_sk) = encrypt::gen_keypair();
+  let mut subscriber = Mesher::unsigned(vec![sub_sk]);
+  subscriber
+    .add_transport::<InMemory>("far")
+    .expect("Failed to add transport");
+  subscriber.listen_on("far:pubsub-relay-sub").expect("Failed to listen");
+
+  subscriber.add_peer(relay_pk, vec![path("far:pubsub-relay-relay")]);
+  relay.add_peer(publisher_pk, vec![path("inmem:pubsub-relay-pub")]);
+
+  let pub_side = PubSub::new(&mut publisher, path("inmem:pubsub-relay-pub"));
+  let relay_side = PubSub::new(&mut relay, path("inmem:pubsub-relay-relay"));
+  let sub_side = PubSub::new(&mut subscriber, path("far:pubsub-relay-sub"));
+  sub_side.subscribe("news");
+  sub_side.subscribe("sports");
+  sub_side.gossip(&mut subscriber).expect("Failed to gossip");
+  relay.receive().expect("Failed to receive");
+  assert_eq!(vec![sub_pk], relay_side.subscribers("news"));
+  relay_side.gossip(&mut relay).expect("Failed to gossip");
+  publisher.receive().expect("Failed to receive");
+  assert_eq!(vec![sub_pk], pub_side.subscribers("news"));
+
+  assert_eq!(
+    1,
+    pub_side
+      .publish(&mut publisher, "news", b"extra")
+      .expect("Failed to publish")
+  );
+  assert_eq!(
+    0,
+    pub_side
+      .publish(&mut publisher, "weather", b"rain")
+      .expect("Failed to publish")
+  );
+  relay.receive().expect("Failed to receive");
+  subscriber.receive().expect("Failed to receive");
+  assert_eq!(vec![("news".to_owned(), b"extra".to_vec())], sub_side.take_published());
+
+  // the publisher still thinks it's subscribed, but what it sends is dropped
+  assert!(sub_side.unsubscribe("sports"));
+  assert!(!sub_side.unsubscribe("sports"));
+  pub_side
+    .publish(&mut publisher, "sports", b"score")
+    .expect("Failed to publish");
+  relay.receive().expect("Failed to receive");
+  subscriber.receive().expect("Failed to receive");
+  assert!(sub_side.take_published().is_empty());
+}
+
+#[test]
+fn subscriptions_expire_without_gossip() {
+  let (mut publisher, publisher_pk) = make_mesher("pubsub-expire-pub");
+  let (mut subscriber, _) = make_mesher("pubsub-expire-sub");
+  subscriber.add_peer(publisher_pk, vec![path("inmem:pubsub-expire-pub")]);
+  let pub_side = PubSub::new(&mut publisher, path("inmem:pubsub-expire-pub")).expire_after(Duration::from_millis(50));
+  let sub_side = PubSub::new(&mut subscriber, path("inmem:pubsub-expire-sub"));
+  sub_side.subscribe("news");
+  sub_side.gossip(&mut subscriber).expect("Failed to gossip");
+  publisher.receive().expect("Failed to receive");
+  assert_eq!(1, pub_side.subscribers("news").len());
+  std::thread::sleep(Duration::from_millis(100));
+  assert!(pub_side.subscribers("news").is_empty());
+}
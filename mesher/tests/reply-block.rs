@@ -0,0 +1,80 @@
+use mesher::{prelude::*, ReplyBlock, Route};
+
+mod common;
+use common::{make_signed, make_unsigned};
+
+/// A route from the relay to the block's maker.
+fn route_back(relay: &str, relay_pk: encrypt::PublicKey, owner: &str, owner_pk: encrypt::PublicKey) -> Route {
+  Route::new()
+    .then(Path::parse(&format!("inmem:{}", relay)).unwrap(), relay_pk)
+    .then(Path::parse(&format!("inmem:{}", owner)).unwrap(), owner_pk)
+}
+
+#[test]
+fn reply_through_relay() {
+  let (mut owner, owner_pk) = make_unsigned("block-owner");
+  let (mut relay, relay_pk) = make_unsigned("block-relay");
+  let (mut replier, _) = make_unsigned("block-replier");
+
+  let block = owner
+    .reply_block(&route_back("block-relay", relay_pk, "block-owner", owner_pk))
+    .expect("Failed to make reply block");
+  // the block would normally be sent along in a message
+  let given = ReplyBlock::from_bytes(&block.to_bytes()).expect("Failed to read reply block");
+  replier.send_reply(&given, &[1, 2, 3]).expect("Failed to send reply");
+
+  assert!(relay.receive().expect("Failed to relay").is_empty());
+  let messages = owner.receive().expect("Failed to receive");
+  assert_eq!(1, messages.len());
+  assert_eq!(&[1, 2, 3], messages[0].contents());
+  assert_eq!(Some(block.id()), messages[0].reply_block());
+}
+
+#[test]
+fn reply_block_single_use() {
+  let (mut owner, owner_pk) = make_unsigned("once-owner");
+  let (mut relay, relay_pk) = make_unsigned("once-relay");
+  let (mut replier, _) = make_unsigned("once-replier");
+
+  let block = owner
+    .reply_block(&route_back("once-relay", relay_pk, "once-owner", owner_pk))
+    .expect("Failed to make reply block");
+  replier.send_reply(&block, &[1]).expect("Failed to send reply");
+  replier.send_reply(&block, &[2]).expect("Failed to send reply");
+
+  relay.receive().expect("Failed to relay");
+  // the second reply is dropped by the maker, since the block's already been used
+  assert_eq!(1, owner.receive().expect("Failed to receive").len());
+}
+
+#[test]
+fn reply_through_signed_relay() {
+  let (signing_pk, signing_sk) = sign::gen_keypair();
+  let (mut owner, owner_pk) = make_signed("signed-block-owner", &signing_pk);
+  let (mut relay, relay_pk) = make_signed("signed-block-relay", &signing_pk);
+  let (mut replier, _) = make_unsigned("signed-block-replier");
+  owner.set_signing_key(Some(signing_sk));
+
+  let block = owner
+    .reply_block(&route_back(
+      "signed-block-relay",
+      relay_pk,
+      "signed-block-owner",
+      owner_pk,
+    ))
+    .expect("Failed to make reply block");
+  replier.send_reply(&block, &[1]).expect("Failed to send reply");
+
+  relay.receive().expect("Failed to relay");
+  let messages = owner.receive().expect("Failed to receive");
+  assert_eq!(1, messages.len());
+  assert_eq!(Some(block.id()), messages[0].reply_block());
+}
+
+#[test]
+fn reply_block_must_end_at_maker() {
+  let (mut owner, _) = make_unsigned("elsewhere-owner");
+  let (other_pk, _) = encrypt::gen_keypair();
+  let route = Route::new().then(Path::parse("inmem:elsewhere").unwrap(), other_pk);
+  assert!(matches!(owner.reply_block(&route), Err(fail::MesherFail::NoRoute(_))));
+}
@@ -0,0 +1,113 @@
+use mesher::{
+  identity::{Endorsement, Revocation},
+  prelude::*,
+};
+
+mod common;
+use common::make_signed;
+
+fn path(p: &str) -> Path {
+  Path::parse(p).unwrap()
+}
+
+/// Sends a message in a packet signed by `skey` from `sender` to `dest`, and returns what `dest` accepted.
+fn deliver(
+  skey: &sign::SecretKey,
+  sender: &mut Mesher,
+  sender_pk: &encrypt::PublicKey,
+  dest: &mut Mesher,
+  dest_path: &str,
+  dest_pk: &encrypt::PublicKey,
+) -> Vec<Vec<u8>> {
+  let mut packet = Packet::signed(skey.clone_secret());
+  packet.add_hop(dest_path.to_owned(), sender_pk);
+  packet.add_message(&[1], dest_pk);
+  sender.launch(packet).expect("Failed to launch");
+  dest
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(Message::into_contents)
+    .collect()
+}
+
+#[test]
+fn revocations_spread_through_the_mesh() {
+  let (node_pk, node_sk) = sign::gen_keypair();
+  let (publisher_sign_pk, publisher_sign_sk) = sign::gen_keypair();
+  let (relay_sign_pk, relay_sign_sk) = sign::gen_keypair();
+  let (mut publisher, _) = make_signed("revoke-spread-pub", &node_pk);
+  publisher.set_signing_key(Some(publisher_sign_sk));
+  let (mut relay, relay_pk) = make_signed("revoke-spread-relay", &node_pk);
+  relay.add_sender_key(publisher_sign_pk);
+  relay.set_signing_key(Some(relay_sign_sk));
+  let (mut far, far_pk) = make_signed("revoke-spread-far", &node_pk);
+  far.add_sender_key(relay_sign_pk);
+  let (mut sender, sender_pk) = make_signed("revoke-spread-sender", &node_pk);
+  publisher.add_peer(relay_pk, vec![path("inmem:revoke-spread-relay")]);
+  relay.add_peer(far_pk, vec![path("inmem:revoke-spread-far")]);
+
+  let far_path = "inmem:revoke-spread-far";
+  assert_eq!(
+    vec![vec![1]],
+    deliver(&node_sk, &mut sender, &sender_pk, &mut far, far_path, &far_pk)
+  );
+
+  publisher
+    .publish_revocation(Revocation::new(&node_pk, &node_sk))
+    .expect("Failed to publish");
+  assert!(publisher.revocations().is_revoked(&node_pk));
+  relay.receive().expect("Failed to receive");
+  assert!(relay.revocations().is_revoked(&node_pk));
+  far.receive().expect("Failed to receive");
+  assert!(far.revocations().is_revoked(&node_pk));
+
+  assert!(deliver(&node_sk, &mut sender, &sender_pk, &mut far, far_path, &far_pk).is_empty());
+}
+
+#[test]
+fn only_roots_and_owners_can_revoke() {
+  let (root_pk, root_sk) = sign::gen_keypair();
+  let (node_pk, node_sk) = sign::gen_keypair();
+  let (stranger_pk, stranger_sk) = sign::gen_keypair();
+  let (mut sender, sender_pk) = make_signed("revoke-roots-sender", &node_pk);
+  let (mut dest, dest_pk) = make_signed("revoke-roots-dest", &stranger_pk);
+  dest.add_trust_root(root_pk);
+  dest
+    .add_endorsement(Endorsement::new(&node_pk, &root_sk))
+    .expect("Endorsement wasn't accepted");
+  let dest_path = "inmem:revoke-roots-dest";
+  assert_eq!(
+    vec![vec![1]],
+    deliver(&node_sk, &mut sender, &sender_pk, &mut dest, dest_path, &dest_pk)
+  );
+
+  // strangers can't revoke other keys, or their own unless they're trusted
+  let (unknown_pk, unknown_sk) = sign::gen_keypair();
+  for revocation in [
+    Revocation::new(&node_pk, &stranger_sk),
+    Revocation::new(&unknown_pk, &unknown_sk),
+  ] {
+    assert!(matches!(
+      dest.add_revocation(revocation),
+      Err(fail::MesherFail::BadRevocation)
+    ));
+  }
+
+  dest
+    .add_revocation(Revocation::new(&node_pk, &root_sk))
+    .expect("Revocation wasn't accepted");
+  assert!(deliver(&node_sk, &mut sender, &sender_pk, &mut dest, dest_path, &dest_pk).is_empty());
+  dest.add_sender_key(node_pk);
+  assert!(deliver(&node_sk, &mut sender, &sender_pk, &mut dest, dest_path, &dest_pk).is_empty());
+
+  // once the root's revoked, it can't revoke anything else
+  dest
+    .add_revocation(Revocation::new(&root_pk, &root_sk))
+    .expect("Revocation wasn't accepted");
+  assert!(matches!(
+    dest.add_revocation(Revocation::new(&stranger_pk, &root_sk)),
+    Err(fail::MesherFail::BadRevocation)
+  ));
+  assert_eq!(2, dest.revocations().len());
+}
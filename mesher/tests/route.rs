@@ -0,0 +1,44 @@
+use mesher::prelude::*;
+use mesher::Route;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn path(p: &str) -> Path {
+  Path::parse(p).expect("Failed to parse path")
+}
+
+#[test]
+fn route_there_and_back() {
+  let (mut sender, sender_pk) = make_mesher("route-sender");
+  let (mut relay, relay_pk) = make_mesher("route-relay");
+  let (mut receiver, receiver_pk) = make_mesher("route-receiver");
+  let route = Route::new()
+    .then(path("inmem:route-sender"), sender_pk)
+    .then(path("inmem:route-relay"), relay_pk)
+    .then(path("inmem:route-receiver"), receiver_pk);
+  let route: Route = route.to_string().parse().expect("Failed to parse route");
+
+  let mut packet = Packet::unsigned();
+  packet.via_route(&route);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.via_route(&route.reversed());
+  rh.use_for_message(&[1], &receiver_pk);
+  sender.launch(packet).expect("Failed to send");
+
+  assert!(relay.receive().expect("Failed to relay").is_empty());
+  let messages = receiver.receive().expect("Failed to receive");
+  assert_eq!(1, messages.len());
+
+  let mut reply = Packet::unsigned();
+  reply.reply_to(&messages[0]).expect("Message had no reply path");
+  reply.add_message(&[2], &sender_pk);
+  receiver.launch(reply).expect("Failed to reply");
+
+  assert!(relay.receive().expect("Failed to relay reply").is_empty());
+  let replies = sender.receive().expect("Failed to receive reply");
+  assert_eq!(
+    vec![vec![2]],
+    replies.into_iter().map(Message::into_contents).collect::<Vec<_>>()
+  );
+}
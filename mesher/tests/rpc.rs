@@ -0,0 +1,78 @@
+use mesher::{prelude::*, rpc::Rpc, Route};
+
+use std::{sync::mpsc::channel, thread, time::Duration};
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn route(from: (&str, encrypt::PublicKey), to: (&str, encrypt::PublicKey)) -> Route {
+  Route::new()
+    .then(Path::parse(from.0).unwrap(), from.1)
+    .then(Path::parse(to.0).unwrap(), to.1)
+}
+
+#[test]
+fn call_answered_by_server_thread() {
+  let (mut client, client_pk) = make_mesher("rpc-call-client");
+  let (mut server, server_pk) = make_mesher("rpc-call-server");
+  let (stop_tx, stop_rx) = channel::<()>();
+
+  let worker = thread::spawn(move || {
+    let mut rpc = Rpc::new();
+    rpc.register("reverse", |body| body.iter().rev().copied().collect());
+    while stop_rx.try_recv().is_err() {
+      for message in server.receive().expect("Failed to receive") {
+        rpc.handle(&mut server, &message).expect("Failed to answer");
+      }
+      thread::sleep(Duration::from_millis(5));
+    }
+  });
+
+  let mut rpc = Rpc::new();
+  let route = route(
+    ("inmem:rpc-call-client", client_pk),
+    ("inmem:rpc-call-server", server_pk),
+  );
+  let timeout = Duration::from_secs(10);
+  let reversed = rpc
+    .call(&mut client, &route, "reverse", &[1, 2, 3], timeout)
+    .expect("Call failed");
+  assert_eq!(vec![3, 2, 1], reversed);
+  match rpc.call(&mut client, &route, "missing", &[], timeout) {
+    Err(fail::MesherFail::RpcFailure(msg)) => assert!(msg.contains("missing"), "{}", msg),
+    other => panic!("Expected RpcFailure, got {:?}", other),
+  }
+
+  stop_tx.send(()).unwrap();
+  worker.join().unwrap();
+}
+
+#[test]
+fn unanswered_requests_time_out() {
+  let (mut client, client_pk) = make_mesher("rpc-timeout-client");
+  let (mut server, server_pk) = make_mesher("rpc-timeout-server");
+  let mut rpc = Rpc::new();
+  let route = route(
+    ("inmem:rpc-timeout-client", client_pk),
+    ("inmem:rpc-timeout-server", server_pk),
+  );
+
+  match rpc.call(&mut client, &route, "anything", &[], Duration::from_millis(50)) {
+    Err(fail::MesherFail::TimedOut(_)) => (),
+    other => panic!("Expected TimedOut, got {:?}", other),
+  }
+  // the server only gets around to it now, so the late response is dropped, and the request's been forgotten
+  let mut server_rpc = Rpc::new();
+  for message in server.receive().expect("Failed to receive") {
+    assert!(server_rpc.handle(&mut server, &message).expect("Failed to answer"));
+  }
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:rpc-timeout-client".to_owned(), &client_pk);
+  packet.add_message(b"not rpc", &client_pk);
+  client.launch(packet).expect("Failed to launch");
+  for message in client.receive().expect("Failed to receive") {
+    if !rpc.handle(&mut client, &message).expect("Failed to handle") {
+      assert_eq!(b"not rpc", message.contents());
+    }
+  }
+}
@@ -0,0 +1,101 @@
+use mesher::prelude::*;
+
+use std::{
+  thread::sleep,
+  time::{Duration, Instant, SystemTime},
+};
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+#[test]
+fn launched_later() {
+  let (mut sender, sender_pk) = make_mesher("later-sender");
+  let (mut receiver, receiver_pk) = make_mesher("later-receiver");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:later-receiver".to_owned(), &sender_pk);
+  packet.add_message(&[1], &receiver_pk);
+  sender
+    .launch_after(packet, Duration::from_millis(50))
+    .expect("Failed to schedule packet");
+  assert!(sender.next_scheduled().is_some());
+
+  sender.receive().expect("Failed to receive");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+
+  sleep(Duration::from_millis(60));
+  sender.receive().expect("Failed to send scheduled packet");
+  assert_eq!(None, sender.next_scheduled());
+  let messages = receiver.receive().expect("Failed to receive");
+  assert_eq!(1, messages.len());
+  assert_eq!(&[1], messages[0].contents());
+}
+
+#[test]
+fn scheduled_sent_on_close() {
+  let (mut sender, sender_pk) = make_mesher("close-scheduled-sender");
+  let (mut receiver, receiver_pk) = make_mesher("close-scheduled-receiver");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:close-scheduled-receiver".to_owned(), &sender_pk);
+  packet.add_message(&[1], &receiver_pk);
+  sender
+    .launch_at(packet, Instant::now() + Duration::from_secs(3600))
+    .expect("Failed to schedule packet");
+  sender.close(Duration::from_secs(1)).expect("Failed to close");
+  assert_eq!(1, receiver.receive().expect("Failed to receive").len());
+}
+
+/// Sends a packet through a relay, asking it to hold the packet for `ask`, and returns how many messages the receiver
+/// got right away, and after waiting `wait`.
+fn held_by_relay(name: &str, max_hold: Option<Duration>, ask: Duration, wait: Duration) -> (usize, usize) {
+  let (mut sender, sender_pk) = make_mesher(&format!("{}-sender", name));
+  let (mut relay, relay_pk) = make_mesher(&format!("{}-relay", name));
+  let (mut receiver, receiver_pk) = make_mesher(&format!("{}-receiver", name));
+  relay.set_max_hold(max_hold);
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop(format!("inmem:{}-relay", name), &sender_pk);
+  packet.add_hop(format!("inmem:{}-receiver", name), &relay_pk);
+  packet.add_hold_until(SystemTime::now() + ask, &relay_pk);
+  packet.add_message(&[1], &receiver_pk);
+  sender.launch(packet).expect("Failed to send packet");
+
+  relay.receive().expect("Failed to relay");
+  let before = receiver.receive().expect("Failed to receive").len();
+  sleep(wait);
+  relay.receive().expect("Failed to relay");
+  let after = receiver.receive().expect("Failed to receive").len();
+  (before, after)
+}
+
+#[test]
+fn relay_holds_packet() {
+  let held = held_by_relay(
+    "hold",
+    Some(Duration::from_secs(60)),
+    Duration::from_millis(50),
+    Duration::from_millis(60),
+  );
+  assert_eq!((0, 1), held);
+}
+
+#[test]
+fn relay_holds_no_longer_than_max() {
+  let held = held_by_relay(
+    "hold-max",
+    Some(Duration::from_millis(50)),
+    Duration::from_secs(3600),
+    Duration::from_millis(60),
+  );
+  assert_eq!((0, 1), held);
+}
+
+#[test]
+fn relay_ignores_hold_unless_opted_in() {
+  assert_eq!(
+    (1, 0),
+    held_by_relay("no-hold", None, Duration::from_secs(3600), Duration::ZERO)
+  );
+}
@@ -0,0 +1,59 @@
+use mesher::debug_transports::InMemory;
+use mesher::prelude::*;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn path(p: &str) -> Path {
+  Path::parse(p).expect("Failed to parse path")
+}
+
+fn contents(m: &mut Mesher) -> Vec<Vec<u8>> {
+  m.receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(Message::into_contents)
+    .collect()
+}
+
+#[test]
+fn send_to_direct() {
+  let (mut sender, _) = make_mesher("send-to-direct-sender");
+  let (mut receiver, receiver_pk) = make_mesher("send-to-direct-receiver");
+  sender.add_peer(receiver_pk, vec![path("inmem:send-to-direct-receiver")]);
+
+  sender.send_to(&receiver_pk, &[1]).expect("Failed to send");
+  assert_eq!(vec![vec![1]], contents(&mut receiver));
+}
+
+#[test]
+fn send_to_through_relay() {
+  let (mut sender, _) = make_mesher("send-to-relay-sender");
+  let (mut relay, relay_pk) = make_mesher("send-to-relay-relay");
+  relay.add_transport::<InMemory>("far").expect("Failed to add transport");
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut receiver = Mesher::unsigned(vec![sk]);
+  receiver
+    .add_transport::<InMemory>("far")
+    .expect("Failed to add transport");
+  receiver
+    .listen_on("far:send-to-relay-receiver")
+    .expect("Failed to listen");
+
+  // the sender can't reach far: paths, but the relay can
+  sender.add_peer(pk, vec![path("far:send-to-relay-receiver")]);
+  sender.add_peer(relay_pk, vec![path("inmem:send-to-relay-relay")]);
+  sender.send_to(&pk, &[2]).expect("Failed to send");
+
+  assert!(contents(&mut relay).is_empty());
+  assert_eq!(vec![vec![2]], contents(&mut receiver));
+}
+
+#[test]
+fn send_to_unknown_fails() {
+  let (mut sender, _) = make_mesher("send-to-unknown-sender");
+  match sender.send_to(&encrypt::gen_keypair().0, &[3]) {
+    Err(fail::MesherFail::NoRoute(_)) => (),
+    other => panic!("expected NoRoute, got {:?}", other),
+  }
+}
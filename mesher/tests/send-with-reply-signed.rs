@@ -9,7 +9,7 @@ fn send_with_reply_signed() {
   let (mut sender, sender_pk) = make_mesher("sender", &signing_pk);
   let (mut receiver, receiver_pk) = make_mesher("receiver", &signing_pk);
 
-  let mut packet = Packet::signed(signing_sk.clone());
+  let mut packet = Packet::signed(signing_sk.clone_secret());
   packet.add_hop("inmem:receiver".to_owned(), &sender_pk);
   let mut rh = packet.add_reply_path().expect("Failed to add reply path");
   rh.add_hop("inmem:sender".to_owned(), &receiver_pk);
@@ -21,8 +21,8 @@ fn send_with_reply_signed() {
   let message = &messages[0];
   assert_eq!(&[1], message.contents());
 
-  let mut reply_packet = Packet::signed(signing_sk.clone());
-  reply_packet.reply_to(&message).expect("message had no reply path");
+  let mut reply_packet = Packet::signed(signing_sk.clone_secret());
+  reply_packet.reply_to(message).expect("message had no reply path");
   reply_packet.add_message(&[2], &sender_pk);
 
   receiver.launch(reply_packet).expect("failed to send reply");
@@ -21,7 +21,7 @@ fn send_with_reply() {
   assert_eq!(&[1], message.contents());
 
   let mut reply_packet = Packet::unsigned();
-  reply_packet.reply_to(&message).expect("message had no reply path");
+  reply_packet.reply_to(message).expect("message had no reply path");
   reply_packet.add_message(&[2], &sender_pk);
 
   receiver.launch(reply_packet).expect("failed to send reply");
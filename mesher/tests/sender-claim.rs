@@ -0,0 +1,50 @@
+use mesher::prelude::*;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+#[test]
+fn claimed_sender_verified() {
+  let (mut sender, sender_pk) = make_mesher("claim-sender");
+  let (mut receiver, receiver_pk) = make_mesher("claim-receiver");
+  let (identity_pk, identity_sk) = sign::gen_keypair();
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:claim-receiver".to_owned(), &sender_pk);
+  packet.add_message(&[1], &receiver_pk);
+  packet.claim_sender(&identity_sk);
+  packet.add_message(&[2], &receiver_pk);
+  sender.launch(packet).expect("Failed to send packet");
+
+  let messages = receiver.receive().expect("Failed to receive");
+  let senders: Vec<_> = messages
+    .iter()
+    .map(|m| (m.contents().to_vec(), m.verified_sender()))
+    .collect();
+  // only messages added after the claim are claimed
+  assert_eq!(vec![(vec![1], None), (vec![2], Some(identity_pk))], senders);
+}
+
+#[test]
+fn claim_only_seen_by_recipient() {
+  let (mut sender, sender_pk) = make_mesher("claim-only-sender");
+  let (mut relay, relay_pk) = make_mesher("claim-only-relay");
+  let (mut receiver, receiver_pk) = make_mesher("claim-only-receiver");
+  let (identity_pk, identity_sk) = sign::gen_keypair();
+
+  let mut packet = Packet::unsigned();
+  packet.claim_sender(&identity_sk);
+  packet.add_hop("inmem:claim-only-relay".to_owned(), &sender_pk);
+  packet.add_hop("inmem:claim-only-receiver".to_owned(), &relay_pk);
+  packet.add_message(&[1], &relay_pk);
+  packet.add_message(&[2], &receiver_pk);
+  sender.launch(packet).expect("Failed to send packet");
+
+  let relayed = relay.receive().expect("Failed to relay");
+  assert_eq!(1, relayed.len());
+  assert_eq!(Some(identity_pk), relayed[0].verified_sender());
+  let received = receiver.receive().expect("Failed to receive");
+  assert_eq!(1, received.len());
+  assert_eq!(&[2], received[0].contents());
+  assert_eq!(Some(identity_pk), received[0].verified_sender());
+}
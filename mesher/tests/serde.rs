@@ -0,0 +1,34 @@
+#![cfg(feature = "serde")]
+
+use mesher::prelude::*;
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+#[test]
+fn stored_message_replied_to() {
+  let (mut sender, sender_pk) = make_mesher("serde-sender");
+  let (mut receiver, receiver_pk) = make_mesher("serde-receiver");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("inmem:serde-receiver".to_owned(), &sender_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("inmem:serde-sender".to_owned(), &receiver_pk);
+  rh.use_for_message(&[1], &receiver_pk);
+  sender.launch(packet).expect("Failed to send message");
+
+  let messages = receiver.receive().expect("Failed to receive message");
+  let stored = serde_json::to_string(&messages[0]).expect("Failed to serialize message");
+  let message: Message = serde_json::from_str(&stored).expect("Failed to deserialize message");
+  assert_eq!(messages[0], message);
+
+  let mut reply_packet = Packet::unsigned();
+  reply_packet
+    .reply_to(&message)
+    .expect("Stored message lost its reply path");
+  reply_packet.add_message(&[2], &sender_pk);
+  receiver.launch(reply_packet).expect("Failed to send reply");
+
+  let replies = sender.receive().expect("Failed to receive reply");
+  assert_eq!(&[2], replies[0].contents());
+}
@@ -0,0 +1,95 @@
+use mesher::{prelude::*, session::Sessions, Route};
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn route(from: (&str, encrypt::PublicKey), to: (&str, encrypt::PublicKey)) -> Route {
+  Route::new()
+    .then(Path::parse(from.0).unwrap(), from.1)
+    .then(Path::parse(to.0).unwrap(), to.1)
+}
+
+#[test]
+fn simultaneous_starts_settle_on_one_session() {
+  let (alice_pk, alice_sk) = encrypt::gen_keypair();
+  let (bob_pk, bob_sk) = encrypt::gen_keypair();
+  let mut alice = Mesher::unsigned(vec![alice_sk.clone_secret()]);
+  alice
+    .add_transport::<mesher::debug_transports::InMemory>("inmem")
+    .expect("Failed to add transport");
+  alice.listen_on("inmem:session-both-alice").expect("Failed to listen");
+  let mut bob = Mesher::unsigned(vec![bob_sk.clone_secret()]);
+  bob
+    .add_transport::<mesher::debug_transports::InMemory>("inmem")
+    .expect("Failed to add transport");
+  bob.listen_on("inmem:session-both-bob").expect("Failed to listen");
+  let to_bob = route(
+    ("inmem:session-both-alice", alice_pk),
+    ("inmem:session-both-bob", bob_pk),
+  );
+  let to_alice = to_bob.reversed();
+  let alice_sessions = Sessions::new(&mut alice, alice_sk);
+  let bob_sessions = Sessions::new(&mut bob, bob_sk);
+
+  alice_sessions
+    .send(&mut alice, &to_bob, b"from alice")
+    .expect("Failed to send");
+  bob_sessions
+    .send(&mut bob, &to_alice, b"from bob")
+    .expect("Failed to send");
+  alice.receive().expect("Failed to receive");
+  bob.receive().expect("Failed to receive");
+  // only the lower key's handshake is kept, but both messages get through
+  assert_eq!(vec![(bob_pk, b"from bob".to_vec())], alice_sessions.take_received());
+  assert_eq!(vec![(alice_pk, b"from alice".to_vec())], bob_sessions.take_received());
+
+  // both sides are in the same session now, so everything gets through
+  for i in 0..3u8 {
+    alice_sessions.send(&mut alice, &to_bob, &[i]).expect("Failed to send");
+    bob_sessions.send(&mut bob, &to_alice, &[i]).expect("Failed to send");
+    alice.receive().expect("Failed to receive");
+    bob.receive().expect("Failed to receive");
+    assert_eq!(vec![(bob_pk, vec![i])], alice_sessions.take_received());
+    assert_eq!(vec![(alice_pk, vec![i])], bob_sessions.take_received());
+  }
+}
+
+#[test]
+fn closed_sessions_restart() {
+  let (mut alice, _) = make_mesher("session-restart-alice");
+  let (mut bob, _) = make_mesher("session-restart-bob");
+  // make_mesher doesn't hand out its secret keys, so give each mesher another for its identity
+  let (alice_id, alice_id_sk) = encrypt::gen_keypair();
+  let (bob_id, bob_id_sk) = encrypt::gen_keypair();
+  alice.add_own_key(alice_id_sk.clone_secret());
+  bob.add_own_key(bob_id_sk.clone_secret());
+  let to_bob = route(
+    ("inmem:session-restart-alice", alice_id),
+    ("inmem:session-restart-bob", bob_id),
+  );
+  let alice_sessions = Sessions::new(&mut alice, alice_id_sk);
+  let bob_sessions = Sessions::new(&mut bob, bob_id_sk);
+
+  alice_sessions
+    .send(&mut alice, &to_bob, b"one")
+    .expect("Failed to send");
+  bob.receive().expect("Failed to receive");
+  assert_eq!(vec![(alice_id, b"one".to_vec())], bob_sessions.take_received());
+
+  assert!(bob_sessions.close(&alice_id));
+  assert!(!bob_sessions.has_session(&alice_id));
+  // handshakes started in the same millisecond are settled by key, rather than which is newer
+  std::thread::sleep(std::time::Duration::from_millis(2));
+  // the old session's messages can't be read anymore...
+  bob_sessions
+    .send(&mut bob, &to_bob.reversed(), b"two")
+    .expect("Failed to send");
+  alice.receive().expect("Failed to receive");
+  // ...but a new handshake replaces it on the other side
+  assert_eq!(vec![(bob_id, b"two".to_vec())], alice_sessions.take_received());
+  alice_sessions
+    .send(&mut alice, &to_bob, b"three")
+    .expect("Failed to send");
+  bob.receive().expect("Failed to receive");
+  assert_eq!(vec![(alice_id, b"three".to_vec())], bob_sessions.take_received());
+}
@@ -0,0 +1,65 @@
+use mesher::{
+  testing::{Conditions, Simulation},
+  MixPolicy,
+};
+
+use std::time::Duration;
+
+#[test]
+fn multi_hop_forwarding() {
+  let mut sim = Simulation::new(6).expect("Failed to set up");
+  sim.send(&[0, 1, 2, 3, 4, 5], b"far").expect("Failed to send");
+  sim.send(&[5, 3, 1], b"back").expect("Failed to send");
+  assert!(sim.run_until_quiet(20).expect("Failed to run"));
+
+  assert_eq!(vec![b"far".to_vec()], sim.take_received(5));
+  assert_eq!(vec![b"back".to_vec()], sim.take_received(1));
+  for node in [0, 2, 3, 4] {
+    assert!(sim.received(node).is_empty(), "node {} got a message", node);
+  }
+}
+
+#[test]
+fn every_message_arrives_despite_delays_and_reordering() {
+  let conditions = Conditions::new().latency(0, 4).reorder().seed(330);
+  let mut sim = Simulation::with_conditions(4, conditions).expect("Failed to set up");
+  for i in 0..20 {
+    sim.send(&[0, 1, 2, 3], &[i]).expect("Failed to send");
+  }
+  assert!(sim.run_until_quiet(100).expect("Failed to run"));
+
+  let mut received = sim.take_received(3);
+  received.sort();
+  assert_eq!((0..20).map(|i| vec![i]).collect::<Vec<_>>(), received);
+}
+
+#[test]
+fn lossy_network_loses_some() {
+  let mut sim = Simulation::with_conditions(3, Conditions::new().loss(0.5).seed(33)).expect("Failed to set up");
+  for i in 0..40 {
+    sim.send(&[0, 1, 2], &[i]).expect("Failed to send");
+  }
+  assert!(sim.run_until_quiet(20).expect("Failed to run"));
+
+  let received = sim.received(2).len();
+  assert!(sim.lost() > 0);
+  assert!(received < 40);
+  // a packet can only be lost once, since it goes no further after that
+  assert_eq!(40, received + sim.lost());
+}
+
+#[test]
+fn mixing_relay_still_delivers() {
+  let mut sim = Simulation::new(3).expect("Failed to set up");
+  sim
+    .mesher(1)
+    .set_mix_policy(Some(MixPolicy::new(4, Duration::from_secs(60))));
+  for i in 0..4 {
+    sim.send(&[0, 1, 2], &[i]).expect("Failed to send");
+  }
+  sim.run(3).expect("Failed to run");
+
+  let mut received = sim.take_received(2);
+  received.sort();
+  assert_eq!(vec![vec![0], vec![1], vec![2], vec![3]], received);
+}
@@ -0,0 +1,68 @@
+use mesher::prelude::*;
+
+use std::{sync::mpsc::channel, thread};
+
+mod common;
+use common::make_unsigned as make_mesher;
+
+fn assert_send<T: Send>() {}
+fn assert_sync<T: Sync>() {}
+
+#[test]
+fn public_types_thread_safe() {
+  assert_send::<Mesher>();
+  assert_send::<Packet>();
+  assert_send::<Message>();
+  assert_send::<fail::MesherFail>();
+  assert_send::<mesher::MesherBuilder>();
+  assert_send::<mesher::MesherHandle>();
+  assert_sync::<mesher::MesherHandle>();
+  assert_sync::<Message>();
+  assert_sync::<fail::MesherFail>();
+}
+
+#[test]
+fn mesher_runs_on_worker_thread() {
+  let (mut sender, sender_pk) = make_mesher("threads-sender");
+  let (mut receiver, receiver_pk) = make_mesher("threads-receiver");
+  let handle = sender.handle();
+  let (stop_tx, stop_rx) = channel();
+
+  let worker = thread::spawn(move || loop {
+    // checked before receiving, so packets launched before the stop are still sent
+    let stop = stop_rx.try_recv().is_ok();
+    sender.receive().expect("Failed to receive");
+    if stop {
+      return;
+    }
+  });
+
+  let launchers: Vec<_> = (0..8u8)
+    .map(|i| {
+      let handle = handle.clone();
+      thread::spawn(move || {
+        let mut packet = Packet::unsigned();
+        packet.add_hop("inmem:threads-receiver".to_owned(), &sender_pk);
+        packet.add_message(&[i], &receiver_pk);
+        handle.launch(packet).expect("Mesher was dropped");
+      })
+    })
+    .collect();
+  for launcher in launchers {
+    launcher.join().expect("Launcher panicked");
+  }
+  stop_tx.send(()).expect("Worker hung up");
+  worker.join().expect("Worker panicked");
+
+  let mut contents: Vec<_> = receiver
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(Message::into_contents)
+    .collect();
+  contents.sort();
+  assert_eq!((0..8u8).map(|i| vec![i]).collect::<Vec<_>>(), contents);
+
+  // the worker's dropped the mesher, so there's nothing left to launch through
+  assert!(handle.launch(Packet::unsigned()).is_err());
+}